@@ -20,4 +20,5 @@ kill:
     dup              /* [i-1,i-1] */
     jumpzero quit    /* [i-1] */
     jump loop
-quit:
\ No newline at end of file
+quit:
+    pop              /* [] */
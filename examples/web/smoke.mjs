@@ -0,0 +1,44 @@
+// Loads the wasm build under node and runs Res for a few thousand events,
+// exiting with status 1 if any call fails:
+//
+//   node examples/web/smoke.mjs [substrate_engine.wasm]
+//
+// The module defaults to the release build in the target directory.
+import crypto from "crypto";
+import fs from "fs";
+
+const path =
+  process.argv[2] ||
+  new URL("../../target/wasm32-unknown-unknown/release/substrate_engine.wasm", import.meta.url);
+let memory;
+const { instance } = await WebAssembly.instantiate(fs.readFileSync(path), {
+  env: {
+    ew_random_fill: (ptr, len) => {
+      crypto.randomFillSync(new Uint8Array(memory.buffer, ptr, len));
+      return 0;
+    },
+  },
+});
+const ew = instance.exports;
+memory = ew.memory;
+
+function check(what, ok) {
+  if (!ok) {
+    console.error("failed:", what);
+    process.exit(1);
+  }
+}
+
+const s = ew.ew_new(32, 32, 1n);
+check("ew_new", s !== 0);
+const name = new TextEncoder().encode("Res");
+const p = ew.ew_alloc(name.length);
+new Uint8Array(memory.buffer, p, name.length).set(name);
+const res = ew.ew_load_standard(s, p, name.length);
+check("ew_load_standard", res > 0);
+check("ew_set", ew.ew_set(s, 16, 16, res) === 0);
+check("ew_step", ew.ew_step(s, 20000) >= 0);
+check("ew_event_count", ew.ew_event_count(s) === 20000n);
+check("ew_render", ew.ew_render(s) !== 0);
+ew.ew_free(s);
+console.log("ok");
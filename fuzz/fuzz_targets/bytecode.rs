@@ -0,0 +1,15 @@
+//! Arbitrary bytecode, loaded and run for one event in an arbitrary window.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use substrate_engine::fuzz::{self, FuzzInput};
+use substrate_engine::runtime::mfm::EventWindow;
+
+fuzz_target!(|data: &[u8]| {
+    let mut input = FuzzInput::new(data);
+    let mut window: EventWindow = input.value();
+    let seed = input.u64();
+    if let Ok((mut runtime, element)) = fuzz::load(input.rest()) {
+        let _ = fuzz::run_single_event(&mut runtime, element, &mut window, seed);
+    }
+});
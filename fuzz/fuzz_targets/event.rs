@@ -0,0 +1,35 @@
+//! One event of a standard element in an arbitrary window. The elements are
+//! compiled and loaded once per thread.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use std::cell::RefCell;
+use substrate_engine::fuzz::{self, FuzzInput};
+use substrate_engine::runtime::mfm::EventWindow;
+use substrate_engine::runtime::Runtime;
+use substrate_engine::stdlib;
+
+thread_local! {
+    static ELEMENTS: RefCell<Vec<(Runtime<'static>, u16)>> = RefCell::new(
+        stdlib::ELEMENTS
+            .iter()
+            .map(|(name, _)| {
+                let program = stdlib::compile(name, "fuzz").unwrap().unwrap();
+                fuzz::load(&program).unwrap()
+            })
+            .collect(),
+    );
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut input = FuzzInput::new(data);
+    let i = input.u8() as usize;
+    let window: EventWindow = input.value();
+    let seed = input.u64();
+    ELEMENTS.with(|elements| {
+        let mut elements = elements.borrow_mut();
+        let n = elements.len();
+        let (runtime, element) = &mut elements[i % n];
+        assert!(fuzz::is_deterministic(runtime, *element, &window, seed));
+    });
+});
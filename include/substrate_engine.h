@@ -0,0 +1,102 @@
+/* The C ABI of substrate-engine, exported with the `ffi` feature.
+ *
+ * Declares the functions of src/ffi.rs, and can be regenerated from it with
+ * cbindgen; see cbindgen.toml. Build the
+ * library with
+ *
+ *     cargo rustc --lib --release --features ffi --crate-type cdylib
+ *
+ * or `--crate-type staticlib`, and link against it. Calls returning int32_t
+ * give a negative number on failure and leave a message for ew_error. */
+
+#ifndef SUBSTRATE_ENGINE_H
+#define SUBSTRATE_ENGINE_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/* A runtime and the grid it runs on, owned by the host. */
+typedef struct EwSession EwSession;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/* Creates a session with an empty `width` by `height` grid and a generator
+ * seeded with `seed`. Gives null if the grid has no sites. */
+EwSession *ew_new(uint32_t width, uint32_t height, uint64_t seed);
+
+void ew_free(EwSession *s);
+
+/* Compiles and loads the EWAL source of `len` bytes at `ptr`, giving the
+ * element's type number. */
+int32_t ew_load_source(EwSession *s, const char *ptr, size_t len);
+
+/* Loads the `ewac` output of `len` bytes at `ptr`, giving the element's
+ * type number. */
+int32_t ew_load_bytecode(EwSession *s, const uint8_t *ptr, size_t len);
+
+/* Loads the standard element named by the `len` bytes at `ptr`, giving its
+ * type number. */
+int32_t ew_load_standard(EwSession *s, const char *ptr, size_t len);
+
+/* Gives the type number of the element `name`, of `len` bytes at `ptr`. */
+int32_t ew_type_of(EwSession *s, const char *ptr, size_t len);
+
+/* Places a new atom of type `t` at `(x, y)`, or empties the site if `t`
+ * is 0. */
+int32_t ew_set(EwSession *s, uint32_t x, uint32_t y, uint32_t t);
+
+/* Gives the type of the atom at `(x, y)`, or -1 outside the grid. */
+int32_t ew_get(const EwSession *s, uint32_t x, uint32_t y);
+
+/* Writes the atom at `(x, y)` with the bits `hi << 64 | lo`, as stored;
+ * its ECC bits are not recomputed. */
+int32_t ew_set_atom(EwSession *s, uint32_t x, uint32_t y, uint64_t lo, uint64_t hi);
+
+/* Reads the bits of the atom at `(x, y)` into `lo` and `hi` as for
+ * `ew_set_atom`. Gives -1 outside the grid. */
+int32_t ew_get_atom(EwSession *s, uint32_t x, uint32_t y, uint64_t *lo, uint64_t *hi);
+
+/* Writes the type of every site into the `len` values at `out`, in rows
+ * from the top. Fails if `len` is less than `width * height`. */
+int32_t ew_read_types(EwSession *s, uint16_t *out, size_t len);
+
+/* Counts the sites holding each type `t` below `len` into `out[t]`, empty
+ * sites as type 0, and gives one more than the highest type loaded or on
+ * the grid, the `len` needed to count them all. */
+int32_t ew_census(const EwSession *s, uint64_t *out, size_t len);
+
+/* Gives the name of type `t` as `len` bytes of UTF-8, not NUL-terminated,
+ * or null if no element of that type is loaded. */
+const char *ew_type_name(const EwSession *s, uint32_t t, size_t *len);
+
+/* Runs `events` events at random sites, stopping at the first which fails. */
+int32_t ew_step(EwSession *s, uint32_t events);
+
+/* The number of events which have completed. */
+uint64_t ew_event_count(const EwSession *s);
+
+uint32_t ew_width(const EwSession *s);
+
+uint32_t ew_height(const EwSession *s);
+
+/* Draws the grid, one pixel per site, as RGBA rows from the top, and gives
+ * a pointer to the `4 * width * height` bytes. They stay valid until the
+ * next call on the session. */
+const uint8_t *ew_render(EwSession *s);
+
+/* The message left by the last call which failed, NUL-terminated UTF-8
+ * of `ew_error_len` bytes. Empty if none has failed. */
+const char *ew_error(const EwSession *s);
+
+size_t ew_error_len(const EwSession *s);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* SUBSTRATE_ENGINE_H */
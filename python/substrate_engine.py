@@ -0,0 +1,249 @@
+"""Python bindings for substrate-engine.
+
+Wraps the C ABI of the library built with the `python` feature:
+
+    cargo rustc --lib --release --features python --crate-type cdylib
+
+The library is looked for at $SUBSTRATE_ENGINE_LIB, then in the target
+directory of the repository holding this file, then on the system library
+path. Sessions run elements on a grid:
+
+    from substrate_engine import Engine
+
+    with Engine(64, 64, seed=42) as engine:
+        engine.load_standard("DReg")
+        engine.load_standard("Res")
+        engine[32, 32] = "DReg"
+        engine.run(100_000)
+        print(engine.census())      # {'Empty': 4095, 'DReg': 1}
+        types = engine.to_numpy()   # a (64, 64) uint16 array of type numbers
+
+Grids export as buffers of type numbers which numpy reads without copying
+twice; numpy itself is only needed for `to_numpy`.
+"""
+
+import array
+import ctypes
+import ctypes.util
+import os
+import sys
+
+__all__ = ["Engine", "EngineError", "load_library"]
+
+
+class EngineError(Exception):
+    """A call into the engine failed."""
+
+
+def _library_names():
+    if sys.platform == "darwin":
+        return ["libsubstrate_engine.dylib"]
+    if sys.platform == "win32":
+        return ["substrate_engine.dll"]
+    return ["libsubstrate_engine.so"]
+
+
+def _find_library():
+    path = os.environ.get("SUBSTRATE_ENGINE_LIB")
+    if path:
+        return path
+    root = os.path.dirname(os.path.dirname(os.path.abspath(__file__)))
+    for profile in ["release", "debug"]:
+        for name in _library_names():
+            path = os.path.join(root, "target", profile, name)
+            if os.path.exists(path):
+                return path
+    path = ctypes.util.find_library("substrate_engine")
+    if path:
+        return path
+    raise EngineError(
+        "libsubstrate_engine not found; build it with `cargo rustc --lib --release "
+        "--features python --crate-type cdylib` or set SUBSTRATE_ENGINE_LIB"
+    )
+
+
+_lib = None
+
+
+def load_library(path=None):
+    """Loads the engine library from `path`, or finds it as described in the
+    module docs. Called by the first `Engine`; call it earlier to choose the
+    library."""
+    global _lib
+    if _lib is not None and path is None:
+        return _lib
+    lib = ctypes.CDLL(path or _find_library())
+    session = ctypes.c_void_p
+    i32, u16, u32, u64, size = (
+        ctypes.c_int32,
+        ctypes.c_uint16,
+        ctypes.c_uint32,
+        ctypes.c_uint64,
+        ctypes.c_size_t,
+    )
+    signatures = {
+        "ew_new": ([u32, u32, u64], session),
+        "ew_free": ([session], None),
+        "ew_load_source": ([session, ctypes.c_char_p, size], i32),
+        "ew_load_bytecode": ([session, ctypes.c_char_p, size], i32),
+        "ew_load_standard": ([session, ctypes.c_char_p, size], i32),
+        "ew_type_of": ([session, ctypes.c_char_p, size], i32),
+        "ew_set": ([session, u32, u32, u32], i32),
+        "ew_get": ([session, u32, u32], i32),
+        "ew_read_types": ([session, ctypes.POINTER(u16), size], i32),
+        "ew_census": ([session, ctypes.POINTER(u64), size], i32),
+        "ew_type_name": ([session, u32, ctypes.POINTER(size)], ctypes.c_void_p),
+        "ew_step": ([session, u32], i32),
+        "ew_event_count": ([session], u64),
+        "ew_width": ([session], u32),
+        "ew_height": ([session], u32),
+        "ew_render": ([session], ctypes.c_void_p),
+        "ew_error": ([session], ctypes.c_char_p),
+    }
+    for name, (args, result) in signatures.items():
+        f = getattr(lib, name)
+        f.argtypes = args
+        f.restype = result
+    _lib = lib
+    return lib
+
+
+class Engine:
+    """A grid and the elements which run on it."""
+
+    # ew_step takes a 32-bit count.
+    _MAX_STEP = 2**32 - 1
+
+    def __init__(self, width=64, height=64, seed=0):
+        self._lib = load_library()
+        self._s = self._lib.ew_new(width, height, seed)
+        if not self._s:
+            raise EngineError("bad grid size")
+
+    def close(self):
+        """Frees the session. The engine can't be used afterwards."""
+        if self._s:
+            self._lib.ew_free(self._s)
+            self._s = None
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, *exc):
+        self.close()
+
+    def __del__(self):
+        self.close()
+
+    def _check(self, result):
+        if result < 0:
+            raise EngineError(self._lib.ew_error(self._s).decode("utf-8", "replace"))
+        return result
+
+    @staticmethod
+    def _bytes(s):
+        return s.encode("utf-8") if isinstance(s, str) else bytes(s)
+
+    def load_source(self, source):
+        """Compiles and loads an element from EWAL source, giving its type."""
+        b = self._bytes(source)
+        return self._check(self._lib.ew_load_source(self._s, b, len(b)))
+
+    def load_bytecode(self, code):
+        """Loads an element compiled by `ewac`, giving its type."""
+        b = bytes(code)
+        return self._check(self._lib.ew_load_bytecode(self._s, b, len(b)))
+
+    def load_standard(self, name):
+        """Loads a standard element such as "DReg", giving its type."""
+        b = self._bytes(name)
+        return self._check(self._lib.ew_load_standard(self._s, b, len(b)))
+
+    def type_of(self, name):
+        b = self._bytes(name)
+        return self._check(self._lib.ew_type_of(self._s, b, len(b)))
+
+    def type_name(self, t):
+        """The name of type `t`, or None if no element of that type is
+        loaded."""
+        n = ctypes.c_size_t()
+        p = self._lib.ew_type_name(self._s, t, ctypes.byref(n))
+        if not p:
+            return None
+        return ctypes.string_at(p, n.value).decode("utf-8", "replace")
+
+    @property
+    def width(self):
+        return self._lib.ew_width(self._s)
+
+    @property
+    def height(self):
+        return self._lib.ew_height(self._s)
+
+    @property
+    def events(self):
+        """The number of events which have completed."""
+        return self._lib.ew_event_count(self._s)
+
+    @property
+    def aeps(self):
+        """Average events per site."""
+        return self.events / (self.width * self.height)
+
+    def __getitem__(self, site):
+        """The type of the atom at `(x, y)`."""
+        x, y = site
+        t = self._lib.ew_get(self._s, x, y)
+        if t < 0:
+            raise IndexError("site ({}, {}) is outside the grid".format(x, y))
+        return t
+
+    def __setitem__(self, site, element):
+        """Places a new atom of `element`, a type or a name, at `(x, y)`.
+        Type 0 empties the site."""
+        x, y = site
+        t = self.type_of(element) if isinstance(element, str) else element
+        self._check(self._lib.ew_set(self._s, x, y, t))
+
+    def run(self, events):
+        """Runs `events` events at random sites."""
+        while events > 0:
+            n = min(events, self._MAX_STEP)
+            self._check(self._lib.ew_step(self._s, n))
+            events -= n
+
+    def types(self):
+        """The type of every site as an `array.array` of unsigned 16-bit
+        values, in rows from the top."""
+        n = self.width * self.height
+        out = array.array("H", bytes(2 * n))
+        buf = (ctypes.c_uint16 * n).from_buffer(out)
+        self._check(self._lib.ew_read_types(self._s, buf, n))
+        del buf
+        return out
+
+    def to_numpy(self):
+        """The type of every site as a numpy array of shape (height, width)."""
+        import numpy
+
+        return numpy.frombuffer(self.types(), dtype=numpy.uint16).reshape(
+            self.height, self.width
+        )
+
+    def rgba(self):
+        """The grid drawn one pixel per site in the elements' colors, as
+        `4 * width * height` bytes of RGBA rows from the top."""
+        n = 4 * self.width * self.height
+        return ctypes.string_at(self._lib.ew_render(self._s), n)
+
+    def census(self):
+        """The number of sites holding each element, by name. Types with no
+        element loaded are named "type N"."""
+        n = self._lib.ew_census(self._s, None, 0)
+        counts = (ctypes.c_uint64 * n)()
+        self._lib.ew_census(self._s, counts, n)
+        result = {}
+        for t, count in enumerate(counts):
+            if count:
+                result[self.type_name(t) or "type {}".format(t)] = count
+        return result
@@ -0,0 +1,181 @@
+"""Tests of the Python bindings against the library built with the `python`
+feature (see substrate_engine.py for how it is found):
+
+    cargo rustc --lib --release --features python --crate-type cdylib
+    python3 -m unittest discover python
+"""
+
+import array
+import ctypes
+import os
+import re
+import unittest
+
+from substrate_engine import Engine, EngineError, load_library
+
+try:
+    import numpy
+except ImportError:
+    numpy = None
+
+
+class EngineTest(unittest.TestCase):
+    def setUp(self):
+        self.engine = Engine(16, 8, seed=42)
+        self.dreg = self.engine.load_standard("DReg")
+        self.res = self.engine.load_standard("Res")
+
+    def tearDown(self):
+        self.engine.close()
+
+    def test_types_and_names(self):
+        e = self.engine
+        self.assertEqual((e.width, e.height), (16, 8))
+        self.assertEqual(e.type_of("DReg"), self.dreg)
+        self.assertEqual(e.type_name(self.res), "Res")
+        self.assertIsNone(e.type_name(999))
+        with self.assertRaises(EngineError):
+            e.type_of("NoSuchElement")
+        with self.assertRaises(EngineError):
+            e.load_standard("NoSuchElement")
+
+    def test_sites(self):
+        e = self.engine
+        e[3, 2] = "Res"
+        e[4, 2] = self.dreg
+        self.assertEqual(e[3, 2], self.res)
+        self.assertEqual(e[4, 2], self.dreg)
+        e[3, 2] = 0
+        self.assertEqual(e[3, 2], 0)
+        with self.assertRaises(IndexError):
+            e[16, 0]
+        with self.assertRaises(EngineError):
+            e[16, 0] = "Res"
+
+    def test_types_buffer(self):
+        e = self.engine
+        e[5, 1] = "Res"
+        types = e.types()
+        self.assertIsInstance(types, array.array)
+        self.assertEqual(len(types), 16 * 8)
+        self.assertEqual(types[1 * 16 + 5], self.res)
+        self.assertEqual(sum(1 for t in types if t), 1)
+
+    def test_run_and_census(self):
+        e = self.engine
+        e[8, 4] = "DReg"
+        e.run(5000)
+        self.assertEqual(e.events, 5000)
+        self.assertEqual(e.aeps, 5000 / (16 * 8))
+        census = e.census()
+        self.assertEqual(sum(census.values()), 16 * 8)
+        self.assertIn("Empty", census)
+
+    def test_same_seed_same_grid(self):
+        grids = []
+        for seed in [7, 7, 8]:
+            with Engine(16, 8, seed=seed) as e:
+                e.load_standard("DReg")
+                e.load_standard("Res")
+                for i in range(0, 16 * 8, 3):
+                    e[i % 16, i // 16] = "Res"
+                e.run(500)
+                grids.append(e.types())
+        self.assertEqual(grids[0], grids[1])
+        self.assertNotEqual(grids[0], grids[2])
+
+    def test_rgba(self):
+        self.assertEqual(len(self.engine.rgba()), 4 * 16 * 8)
+
+    def test_load_source(self):
+        src = ".name Still\n.symbol s\n  nop\n"
+        t = self.engine.load_source(src)
+        self.assertEqual(self.engine.type_name(t), "Still")
+        with self.assertRaises(EngineError):
+            self.engine.load_source("not an instruction\n")
+
+    @unittest.skipIf(numpy is None, "numpy is not installed")
+    def test_to_numpy(self):
+        e = self.engine
+        e[5, 1] = "Res"
+        types = e.to_numpy()
+        self.assertEqual(types.shape, (8, 16))
+        self.assertEqual(types.dtype, numpy.uint16)
+        self.assertEqual(types[1, 5], self.res)
+        self.assertEqual(int(types.sum()), self.res)
+
+
+HEADER = os.path.join(os.path.dirname(__file__), "..", "include", "substrate_engine.h")
+
+# The ctypes types which pass each C type of the header the same way.
+C_TYPES = {
+    "EwSession *": (ctypes.c_void_p,),
+    "const EwSession *": (ctypes.c_void_p,),
+    "const char *": (ctypes.c_char_p, ctypes.c_void_p),
+    "const uint8_t *": (ctypes.c_char_p, ctypes.c_void_p),
+    "uint16_t *": (ctypes.POINTER(ctypes.c_uint16),),
+    "uint64_t *": (ctypes.POINTER(ctypes.c_uint64),),
+    "size_t *": (ctypes.POINTER(ctypes.c_size_t),),
+    "int32_t": (ctypes.c_int32,),
+    "uint32_t": (ctypes.c_uint32,),
+    "uint64_t": (ctypes.c_uint64,),
+    "size_t": (ctypes.c_size_t,),
+    "void": (None,),
+}
+
+
+def declarations():
+    """Each function the C header declares, by name, as its return type and
+    parameter types."""
+    with open(HEADER) as f:
+        text = f.read()
+    found = {}
+    for result, name, params in re.findall(
+        r"^([\w ]+?\s*\**)\s*(ew_\w+)\(([^)]*)\);", text, re.M
+    ):
+        params = [re.sub(r"\s*\w+$", "", p.strip()) for p in params.split(",")]
+        found[name] = (result.strip(), params)
+    return found
+
+
+class SignatureTest(unittest.TestCase):
+    def test_signatures_match_header(self):
+        lib = load_library()
+        declared = declarations()
+        wrapped = [n for n in declared if getattr(lib, n).argtypes is not None]
+        # Every entry point the wrapper calls, which is all but the atom
+        # accessors and ew_error_len.
+        self.assertEqual(len(wrapped), len(declared) - 3)
+        for name in wrapped:
+            result, params = declared[name]
+            f = getattr(lib, name)
+            self.assertEqual(len(f.argtypes), len(params), name)
+            for arg, param in zip(f.argtypes, params):
+                self.assertIn(arg, C_TYPES[param], "%s: %s" % (name, param))
+            self.assertIn(f.restype, C_TYPES[result], "%s returns %s" % (name, result))
+
+    def test_calls_through_declared_signatures(self):
+        # Calls the library directly, as the declarations say, rather than
+        # through Engine.
+        lib = load_library()
+        s = lib.ew_new(4, 2, 1)
+        try:
+            self.assertEqual((lib.ew_width(s), lib.ew_height(s)), (4, 2))
+            res = lib.ew_load_standard(s, b"Res", 3)
+            self.assertGreater(res, 0)
+            self.assertEqual(lib.ew_set(s, 1, 1, res), 0)
+            self.assertEqual(lib.ew_get(s, 1, 1), res)
+            self.assertLess(lib.ew_set(s, 4, 0, res), 0)
+            self.assertIn(b"outside", lib.ew_error(s))
+            types = (ctypes.c_uint16 * 8)()
+            self.assertEqual(lib.ew_read_types(s, types, 8), 0)
+            self.assertEqual(list(types).count(res), 1)
+            length = ctypes.c_size_t()
+            name = lib.ew_type_name(s, res, ctypes.byref(length))
+            self.assertEqual(ctypes.string_at(name, length.value), b"Res")
+        finally:
+            lib.ew_free(s)
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -1,4 +1,4 @@
-use crate::base::arith::Const;
+use crate::base::arith::{ArithMode, Const, DivZeroPolicy, Flags};
 use crate::base::{FieldSelector, Symmetries};
 
 #[derive(Copy, Clone, Debug)]
@@ -22,10 +22,83 @@ pub enum Metadata<'input> {
     Symmetries(Symmetries),
     Field(&'input str, FieldSelector),
     Parameter(&'input str, Const),
+    Arithmetic(ArithMode),
+    MfmType(u16),
+    Color(&'input str),
+    FieldWrites(ArithMode),
+    DivZero(DivZeroPolicy),
+    Bond(&'input str, FieldSelector),
 }
 
 impl Metadata<'_> {
-    pub const MAX: u8 = 10;
+    pub const MAX: u8 = 16;
+
+    /// Every metadata directive, in opcode order.
+    pub const DIRECTIVES: [&'static str; 17] = [
+        ".name",
+        ".symbol",
+        ".desc",
+        ".author",
+        ".license",
+        ".radius",
+        ".bgcolor",
+        ".fgcolor",
+        ".symmetries",
+        ".field",
+        ".parameter",
+        ".arithmetic",
+        ".mfmtype",
+        ".color",
+        ".fieldwrites",
+        ".divzero",
+        ".bond",
+    ];
+
+    /// The operands and purpose of each directive of `DIRECTIVES`, in the same
+    /// order, for the reference documentation.
+    pub const USAGE: [(&'static str, &'static str); 17] = [
+        ("NAME", "The name of the element."),
+        ("SYMBOL", "A symbol for the element."),
+        ("DESC", "A short description of the element. Repeatable."),
+        ("AUTHOR", "An author of the element, one per line. Repeatable."),
+        ("LICENSE", "An SPDX license name."),
+        ("RADIUS", "The radius of the element's event window, from 0 to 4."),
+        ("COLOR", "A background color for frontends to use."),
+        ("COLOR", "A foreground color for frontends to use."),
+        ("SYM[|...]", "The symmetries events run with by default."),
+        (
+            "NAME, POSITION, BIT-LENGTH",
+            "A named field of the element's atoms. Repeatable.",
+        ),
+        (
+            "NAME, DEFAULT-VALUE",
+            "A named constant which can be overridden when loading. Repeatable.",
+        ),
+        (
+            "MODE",
+            "How arithmetic overflows: `saturating` (default), `wrapping` or `checked`.",
+        ),
+        (
+            "TYPE",
+            "The element's type number in the reference MFM simulator.",
+        ),
+        (
+            "COLOR",
+            "The color the element is drawn with, as `#rgb`, `#rrggbb` or `#rrggbbaa`.",
+        ),
+        (
+            "MODE",
+            "What field writes do with values too wide for the field: `wrapping` (default), `saturating` or `checked`.",
+        ),
+        (
+            "POLICY",
+            "What `div` and `mod` do when dividing by zero: `arithmetic` (default), `trap`, `ones` or `flag`.",
+        ),
+        (
+            "NAME, POSITION",
+            "A 6-bit field of the element's atoms holding a bond to another atom. Repeatable.",
+        ),
+    ];
 
     pub fn as_u8(&self) -> u8 {
         match self {
@@ -40,6 +113,12 @@ impl Metadata<'_> {
             Self::Symmetries(_) => 8,
             Self::Field(_, _) => 9,
             Self::Parameter(_, _) => 10,
+            Self::Arithmetic(_) => 11,
+            Self::MfmType(_) => 12,
+            Self::Color(_) => 13,
+            Self::FieldWrites(_) => 14,
+            Self::DivZero(_) => 15,
+            Self::Bond(_, _) => 16,
         }
     }
 }
@@ -76,6 +155,86 @@ impl<T, U> Arg<T, U> {
     }
 }
 
+/// The destination of a jump or call, as written in the source. Numeric
+/// targets keep their text so errors can point at them.
+#[derive(Copy, Clone, Debug)]
+pub enum Target<'input> {
+    Label(&'input str),
+    /// An instruction index.
+    Absolute(&'input str),
+    /// A signed offset from the jumping instruction.
+    Relative(&'input str),
+}
+
+impl<'input> Target<'input> {
+    pub fn text(&self) -> &'input str {
+        match self {
+            Self::Label(x) | Self::Absolute(x) | Self::Relative(x) => x,
+        }
+    }
+}
+
+/// What a conditional jump tests in the flags. The `u` forms order unsigned
+/// numbers; the others order signed ones.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Condition {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    LessUnsigned,
+    LessEqualUnsigned,
+    GreaterUnsigned,
+    GreaterEqualUnsigned,
+}
+
+impl Condition {
+    pub const ALL: [Condition; 10] = [
+        Self::Equal,
+        Self::NotEqual,
+        Self::Less,
+        Self::LessEqual,
+        Self::Greater,
+        Self::GreaterEqual,
+        Self::LessUnsigned,
+        Self::LessEqualUnsigned,
+        Self::GreaterUnsigned,
+        Self::GreaterEqualUnsigned,
+    ];
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Self::Equal => "jeq",
+            Self::NotEqual => "jne",
+            Self::Less => "jlt",
+            Self::LessEqual => "jle",
+            Self::Greater => "jgt",
+            Self::GreaterEqual => "jge",
+            Self::LessUnsigned => "jltu",
+            Self::LessEqualUnsigned => "jleu",
+            Self::GreaterUnsigned => "jgtu",
+            Self::GreaterEqualUnsigned => "jgeu",
+        }
+    }
+
+    pub fn holds(&self, f: Flags) -> bool {
+        match self {
+            Self::Equal => f.zero,
+            Self::NotEqual => !f.zero,
+            Self::Less => f.negative,
+            Self::LessEqual => f.negative || f.zero,
+            Self::Greater => !f.negative && !f.zero,
+            Self::GreaterEqual => !f.negative,
+            Self::LessUnsigned => f.carry,
+            Self::LessEqualUnsigned => f.carry || f.zero,
+            Self::GreaterUnsigned => !f.carry && !f.zero,
+            Self::GreaterEqualUnsigned => !f.carry,
+        }
+    }
+}
+
 #[repr(u8)]
 #[derive(Copy, Clone, Debug)]
 pub enum Instruction<'input> {
@@ -141,7 +300,7 @@ pub enum Instruction<'input> {
     Over,
     Swap,
     Rot,
-    Call(Arg<&'input str, u16>),
+    Call(Arg<Target<'input>, u16>),
     Ret,
     Checksum,
     Add,
@@ -161,16 +320,61 @@ pub enum Instruction<'input> {
     BitScanReverse,
     LShift,
     RShift,
-    Jump(Arg<&'input str, u16>),
+    Jump(Arg<Target<'input>, u16>),
     JumpRelativeOffset,
-    JumpZero(Arg<&'input str, u16>),
-    JumpNonZero(Arg<&'input str, u16>),
+    JumpZero(Arg<Target<'input>, u16>),
+    JumpNonZero(Arg<Target<'input>, u16>),
     SetPaint,
     GetPaint,
+    MoveSite,
+    Diffuse,
+    CountOf,
+    FindFirst,
+    FindRandom,
+    Rand,
+    OddsOf,
+    RandSite,
+    Compare,
+    JumpIf(Condition, Arg<Target<'input>, u16>),
+    ZeroExtend(u8),
+    SignExtend(u8),
+    Truncate(u8),
+    GetRegister(u8),
+    SetRegister(u8),
+    Assert,
+    PopCount,
+    LeadingZeros,
+    TrailingZeros,
+    RotateLeft,
+    RotateRight,
+    Min,
+    Max,
+    Clamp,
+    Abs,
+    MulHigh,
+    GetLayer,
+    SetLayer,
+    DecayLayer,
+    GetHeat,
+    EmitHeat,
+    AbsorbHeat,
+    Hash,
+    NewId,
+    Bond(Arg<&'input str, FieldSelector>),
+    Unbond(Arg<&'input str, FieldSelector>),
+    FollowBond(Arg<&'input str, FieldSelector>),
+    Count(u8),
 }
 
 impl Instruction<'_> {
-    pub const MAX: u8 = 87;
+    pub const MAX: u8 = 134;
+
+    /// The number of registers, `R0` to `R14`. `getregister` takes this
+    /// number for `R?`, the random source.
+    pub const REGISTERS: u8 = 15;
+
+    /// The number of counters `count` adds to, numbered from 0.
+    pub const COUNTERS: u8 = 16;
 
     pub fn as_u8(&self) -> u8 {
         match self {
@@ -262,6 +466,790 @@ impl Instruction<'_> {
             Self::JumpNonZero(_) => 85,
             Self::SetPaint => 86,
             Self::GetPaint => 87,
+            Self::MoveSite => 88,
+            Self::Diffuse => 89,
+            Self::CountOf => 90,
+            Self::FindFirst => 91,
+            Self::FindRandom => 92,
+            Self::Rand => 93,
+            Self::OddsOf => 94,
+            Self::RandSite => 95,
+            Self::Compare => 96,
+            Self::JumpIf(c, _) => 97 + *c as u8,
+            Self::ZeroExtend(_) => 107,
+            Self::SignExtend(_) => 108,
+            Self::Truncate(_) => 109,
+            Self::GetRegister(_) => 110,
+            Self::SetRegister(_) => 111,
+            Self::Assert => 112,
+            Self::PopCount => 113,
+            Self::LeadingZeros => 114,
+            Self::TrailingZeros => 115,
+            Self::RotateLeft => 116,
+            Self::RotateRight => 117,
+            Self::Min => 118,
+            Self::Max => 119,
+            Self::Clamp => 120,
+            Self::Abs => 121,
+            Self::MulHigh => 122,
+            Self::GetLayer => 123,
+            Self::SetLayer => 124,
+            Self::DecayLayer => 125,
+            Self::GetHeat => 126,
+            Self::EmitHeat => 127,
+            Self::AbsorbHeat => 128,
+            Self::Hash => 129,
+            Self::NewId => 130,
+            Self::Bond(_) => 131,
+            Self::Unbond(_) => 132,
+            Self::FollowBond(_) => 133,
+            Self::Count(_) => 134,
+        }
+    }
+
+    /// Every assembly mnemonic, in opcode order.
+    pub const MNEMONICS: [&'static str; 135] = [
+        "nop",
+        "exit",
+        "swapsites",
+        "setsite",
+        "setfield",
+        "setsitefield",
+        "getsite",
+        "getfield",
+        "getsitefield",
+        "gettype",
+        "getparameter",
+        "scan",
+        "savesymmetries",
+        "usesymmetries",
+        "restoresymmetries",
+        "push0",
+        "push1",
+        "push2",
+        "push3",
+        "push4",
+        "push5",
+        "push6",
+        "push7",
+        "push8",
+        "push9",
+        "push10",
+        "push11",
+        "push12",
+        "push13",
+        "push14",
+        "push15",
+        "push16",
+        "push17",
+        "push18",
+        "push19",
+        "push20",
+        "push21",
+        "push22",
+        "push23",
+        "push24",
+        "push25",
+        "push26",
+        "push27",
+        "push28",
+        "push29",
+        "push30",
+        "push31",
+        "push32",
+        "push33",
+        "push34",
+        "push35",
+        "push36",
+        "push37",
+        "push38",
+        "push39",
+        "push40",
+        "push",
+        "pop",
+        "dup",
+        "over",
+        "swap",
+        "rot",
+        "call",
+        "ret",
+        "checksum",
+        "add",
+        "sub",
+        "neg",
+        "mod",
+        "mul",
+        "div",
+        "less",
+        "lessequal",
+        "or",
+        "and",
+        "xor",
+        "equal",
+        "bitcount",
+        "bitscanforward",
+        "bitscanreverse",
+        "lshift",
+        "rshift",
+        "jump",
+        "jumprelativeoffset",
+        "jumpzero",
+        "jumpnonzero",
+        "setpaint",
+        "getpaint",
+        "movesite",
+        "diffuse",
+        "countof",
+        "findfirst",
+        "findrandom",
+        "rand",
+        "oddsof",
+        "randsite",
+        "cmp",
+        "jeq",
+        "jne",
+        "jlt",
+        "jle",
+        "jgt",
+        "jge",
+        "jltu",
+        "jleu",
+        "jgtu",
+        "jgeu",
+        "zext",
+        "sext",
+        "trunc",
+        "getregister",
+        "setregister",
+        "assert",
+        "popcnt",
+        "clz",
+        "ctz",
+        "rotl",
+        "rotr",
+        "min",
+        "max",
+        "clamp",
+        "abs",
+        "mulh",
+        "getlayer",
+        "setlayer",
+        "decaylayer",
+        "getheat",
+        "emitheat",
+        "absorbheat",
+        "hash",
+        "newid",
+        "bond",
+        "unbond",
+        "followbond",
+        "count",
+    ];
+
+    /// The constant this instruction pushes, if it is a push.
+    pub fn pushed(&self) -> Option<Const> {
+        match self {
+            Self::Push(c) => Some(*c),
+            i if (Self::Push0.as_u8()..=Self::Push40.as_u8()).contains(&i.as_u8()) => {
+                Some(((i.as_u8() - Self::Push0.as_u8()) as i32).into())
+            }
+            _ => None,
+        }
+    }
+
+    /// How many values this instruction pops off the operand stack and how
+    /// many it pushes after. A `call` and a `ret` move nothing themselves.
+    pub fn stack_effect(&self) -> (usize, usize) {
+        match self {
+            Self::Nop
+            | Self::Exit
+            | Self::SaveSymmetries
+            | Self::UseSymmetries(_)
+            | Self::RestoreSymmetries
+            | Self::Call(_)
+            | Self::Ret
+            | Self::Jump(_)
+            | Self::JumpIf(..)
+            | Self::Count(_) => (0, 0),
+            Self::GetType(_)
+            | Self::GetParameter(_)
+            | Self::RandSite
+            | Self::GetRegister(_)
+            | Self::Push(_)
+            | Self::Push0
+            | Self::Push1
+            | Self::Push2
+            | Self::Push3
+            | Self::Push4
+            | Self::Push5
+            | Self::Push6
+            | Self::Push7
+            | Self::Push8
+            | Self::Push9
+            | Self::Push10
+            | Self::Push11
+            | Self::Push12
+            | Self::Push13
+            | Self::Push14
+            | Self::Push15
+            | Self::Push16
+            | Self::Push17
+            | Self::Push18
+            | Self::Push19
+            | Self::Push20
+            | Self::Push21
+            | Self::Push22
+            | Self::Push23
+            | Self::Push24
+            | Self::Push25
+            | Self::Push26
+            | Self::Push27
+            | Self::Push28
+            | Self::Push29
+            | Self::Push30
+            | Self::Push31
+            | Self::Push32
+            | Self::Push33
+            | Self::Push34
+            | Self::Push35
+            | Self::Push36
+            | Self::Push37
+            | Self::Push38
+            | Self::Push39
+            | Self::Push40
+            | Self::NewId => (0, 1),
+            Self::Pop
+            | Self::JumpRelativeOffset
+            | Self::JumpZero(_)
+            | Self::JumpNonZero(_)
+            | Self::Diffuse
+            | Self::SetRegister(_)
+            | Self::Assert
+            | Self::Unbond(_) => (1, 0),
+            Self::GetSite
+            | Self::GetField(_)
+            | Self::GetSiteField(_)
+            | Self::Scan
+            | Self::Checksum
+            | Self::Neg
+            | Self::BitCount
+            | Self::BitScanForward
+            | Self::BitScanReverse
+            | Self::GetPaint
+            | Self::FindFirst
+            | Self::FindRandom
+            | Self::Rand
+            | Self::ZeroExtend(_)
+            | Self::SignExtend(_)
+            | Self::Truncate(_)
+            | Self::PopCount
+            | Self::LeadingZeros
+            | Self::TrailingZeros
+            | Self::Abs
+            | Self::GetHeat
+            | Self::FollowBond(_) => (1, 1),
+            Self::Dup => (1, 2),
+            Self::SwapSites
+            | Self::SetSite
+            | Self::SetSiteField(_)
+            | Self::Compare
+            | Self::SetPaint
+            | Self::MoveSite
+            | Self::DecayLayer
+            | Self::EmitHeat
+            | Self::Bond(_) => (2, 0),
+            Self::Swap => (2, 2),
+            Self::Over => (2, 3),
+            Self::Rot => (3, 3),
+            Self::SetLayer => (3, 0),
+            Self::Clamp => (3, 1),
+            Self::SetField(_)
+            | Self::Add
+            | Self::Sub
+            | Self::Mod
+            | Self::Mul
+            | Self::Div
+            | Self::Less
+            | Self::LessEqual
+            | Self::Or
+            | Self::And
+            | Self::Xor
+            | Self::Equal
+            | Self::LShift
+            | Self::RShift
+            | Self::CountOf
+            | Self::OddsOf
+            | Self::RotateLeft
+            | Self::RotateRight
+            | Self::Min
+            | Self::Max
+            | Self::MulHigh
+            | Self::GetLayer
+            | Self::AbsorbHeat
+            | Self::Hash => (2, 1),
+        }
+    }
+
+    /// An instruction with opcode `op` and placeholder operands, to list the
+    /// instruction set by, or `None` if no instruction has that opcode.
+    pub fn template(op: u8) -> Option<Instruction<'static>> {
+        Some(match op {
+            0 => Instruction::Nop,
+            1 => Instruction::Exit,
+            2 => Instruction::SwapSites,
+            3 => Instruction::SetSite,
+            4 => Instruction::SetField(Arg::Runtime(FieldSelector::DATA)),
+            5 => Instruction::SetSiteField(Arg::Runtime(FieldSelector::DATA)),
+            6 => Instruction::GetSite,
+            7 => Instruction::GetField(Arg::Runtime(FieldSelector::DATA)),
+            8 => Instruction::GetSiteField(Arg::Runtime(FieldSelector::DATA)),
+            9 => Instruction::GetType(Arg::Runtime(0)),
+            10 => Instruction::GetParameter(Arg::Runtime(Const::from(0))),
+            11 => Instruction::Scan,
+            12 => Instruction::SaveSymmetries,
+            13 => Instruction::UseSymmetries(Symmetries::empty()),
+            14 => Instruction::RestoreSymmetries,
+            15 => Instruction::Push0,
+            16 => Instruction::Push1,
+            17 => Instruction::Push2,
+            18 => Instruction::Push3,
+            19 => Instruction::Push4,
+            20 => Instruction::Push5,
+            21 => Instruction::Push6,
+            22 => Instruction::Push7,
+            23 => Instruction::Push8,
+            24 => Instruction::Push9,
+            25 => Instruction::Push10,
+            26 => Instruction::Push11,
+            27 => Instruction::Push12,
+            28 => Instruction::Push13,
+            29 => Instruction::Push14,
+            30 => Instruction::Push15,
+            31 => Instruction::Push16,
+            32 => Instruction::Push17,
+            33 => Instruction::Push18,
+            34 => Instruction::Push19,
+            35 => Instruction::Push20,
+            36 => Instruction::Push21,
+            37 => Instruction::Push22,
+            38 => Instruction::Push23,
+            39 => Instruction::Push24,
+            40 => Instruction::Push25,
+            41 => Instruction::Push26,
+            42 => Instruction::Push27,
+            43 => Instruction::Push28,
+            44 => Instruction::Push29,
+            45 => Instruction::Push30,
+            46 => Instruction::Push31,
+            47 => Instruction::Push32,
+            48 => Instruction::Push33,
+            49 => Instruction::Push34,
+            50 => Instruction::Push35,
+            51 => Instruction::Push36,
+            52 => Instruction::Push37,
+            53 => Instruction::Push38,
+            54 => Instruction::Push39,
+            55 => Instruction::Push40,
+            56 => Instruction::Push(Const::from(0)),
+            57 => Instruction::Pop,
+            58 => Instruction::Dup,
+            59 => Instruction::Over,
+            60 => Instruction::Swap,
+            61 => Instruction::Rot,
+            62 => Instruction::Call(Arg::Runtime(0)),
+            63 => Instruction::Ret,
+            64 => Instruction::Checksum,
+            65 => Instruction::Add,
+            66 => Instruction::Sub,
+            67 => Instruction::Neg,
+            68 => Instruction::Mod,
+            69 => Instruction::Mul,
+            70 => Instruction::Div,
+            71 => Instruction::Less,
+            72 => Instruction::LessEqual,
+            73 => Instruction::Or,
+            74 => Instruction::And,
+            75 => Instruction::Xor,
+            76 => Instruction::Equal,
+            77 => Instruction::BitCount,
+            78 => Instruction::BitScanForward,
+            79 => Instruction::BitScanReverse,
+            80 => Instruction::LShift,
+            81 => Instruction::RShift,
+            82 => Instruction::Jump(Arg::Runtime(0)),
+            83 => Instruction::JumpRelativeOffset,
+            84 => Instruction::JumpZero(Arg::Runtime(0)),
+            85 => Instruction::JumpNonZero(Arg::Runtime(0)),
+            86 => Instruction::SetPaint,
+            87 => Instruction::GetPaint,
+            88 => Instruction::MoveSite,
+            89 => Instruction::Diffuse,
+            90 => Instruction::CountOf,
+            91 => Instruction::FindFirst,
+            92 => Instruction::FindRandom,
+            93 => Instruction::Rand,
+            94 => Instruction::OddsOf,
+            95 => Instruction::RandSite,
+            96 => Instruction::Compare,
+            107 => Instruction::ZeroExtend(1),
+            108 => Instruction::SignExtend(1),
+            109 => Instruction::Truncate(1),
+            110 => Instruction::GetRegister(0),
+            111 => Instruction::SetRegister(0),
+            112 => Instruction::Assert,
+            113 => Instruction::PopCount,
+            114 => Instruction::LeadingZeros,
+            115 => Instruction::TrailingZeros,
+            116 => Instruction::RotateLeft,
+            117 => Instruction::RotateRight,
+            118 => Instruction::Min,
+            119 => Instruction::Max,
+            120 => Instruction::Clamp,
+            121 => Instruction::Abs,
+            122 => Instruction::MulHigh,
+            123 => Instruction::GetLayer,
+            124 => Instruction::SetLayer,
+            125 => Instruction::DecayLayer,
+            126 => Instruction::GetHeat,
+            127 => Instruction::EmitHeat,
+            128 => Instruction::AbsorbHeat,
+            129 => Instruction::Hash,
+            130 => Instruction::NewId,
+            131 => Instruction::Bond(Arg::Runtime(FieldSelector::DATA)),
+            132 => Instruction::Unbond(Arg::Runtime(FieldSelector::DATA)),
+            133 => Instruction::FollowBond(Arg::Runtime(FieldSelector::DATA)),
+            134 => Instruction::Count(0),
+            97..=106 => Instruction::JumpIf(Condition::ALL[op as usize - 97], Arg::Runtime(0)),
+            _ => return None,
+        })
+    }
+
+    /// What this instruction does, in a sentence, for the reference
+    /// documentation. `[0]` is the value on top of the stack, `[1]` the one
+    /// below it and so on, and operands are named as `operand` names them.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Nop => "Do nothing.",
+            Self::Exit => "End the event.",
+            Self::SwapSites => "Swap the atoms at numbered sites `[0]` and `[1]`.",
+            Self::SetSite => "Set numbered site `[1]` to the atom `[0]`.",
+            Self::SetField(_) => "Push `[0]` with its field `[FIELD]` set to `[1]`.",
+            Self::SetSiteField(_) => "Set the field `[FIELD]` of the atom at numbered site `[0]` to `[1]`.",
+            Self::GetSite => "Push the atom at numbered site `[0]`.",
+            Self::GetField(_) => "Push the field `[FIELD]` of `[0]`.",
+            Self::GetSiteField(_) => "Push the field `[FIELD]` of the atom at numbered site `[0]`.",
+            Self::GetType(_) => "Push the number of the element type named `[TYPE]`; `\"Self\"` is the element's own.",
+            Self::GetParameter(_) => "Push the parameter `[PARAM]`, as overridden when loading.",
+            Self::Scan => "Push a bitmask of the sites holding atoms of type `[0]`, bit `n` for site `n`.",
+            Self::SaveSymmetries => "Save the current symmetries on the symmetries stack.",
+            Self::UseSymmetries(_) => "Use the symmetries `[SYM[|...]]`, picking one at random.",
+            Self::RestoreSymmetries => "Restore the symmetries last saved.",
+            Self::Push(_) => "Push the constant `[X]`.",
+            Self::Pop => "Discard `[0]`.",
+            Self::Dup => "Push `[0]` again.",
+            Self::Over => "Push `[1]` again, above `[0]`.",
+            Self::Swap => "Swap `[0]` and `[1]`.",
+            Self::Rot => "Move `[0]` below `[2]`, so that `[1]` ends on top.",
+            Self::Call(_) => "Call the routine at `[LABEL]`, keeping where to return to on the call stack.",
+            Self::Ret => "Return to the instruction after the most recent `call`.",
+            Self::Checksum => "Push 1 if the header checksum of the atom `[0]` is wrong, otherwise 0.",
+            Self::Add => "Push `[1] + [0]`.",
+            Self::Sub => "Push `[1] - [0]`.",
+            Self::Neg => "Push `-[0]`.",
+            Self::Mod => "Push the remainder of `[1] / [0]`.",
+            Self::Mul => "Push `[1] * [0]`.",
+            Self::Div => "Push `[1] / [0]`, rounded toward zero.",
+            Self::Less => "Push 1 if `[1] < [0]` by value, otherwise 0.",
+            Self::LessEqual => "Push 1 if `[1] <= [0]` by value, otherwise 0.",
+            Self::Or => "Push the bitwise or of `[1]` and `[0]`.",
+            Self::And => "Push the bitwise and of `[1]` and `[0]`.",
+            Self::Xor => "Push the bitwise exclusive or of `[1]` and `[0]`.",
+            Self::Equal => "Push 1 if `[1]` and `[0]` are equal by value, otherwise 0.",
+            Self::BitCount => "Push the number of set bits of `[0]`'s 96-bit pattern.",
+            Self::BitScanForward => "Push the index of the lowest set bit of `[0]`.",
+            Self::BitScanReverse => "Push the index of the highest set bit of `[0]`.",
+            Self::LShift => "Push `[1]` shifted left by `[0]`.",
+            Self::RShift => "Push `[1]` shifted right by `[0]`.",
+            Self::Jump(_) => "Jump to `[LABEL]`.",
+            Self::JumpRelativeOffset => "Jump `[0]` instructions forward, or backward if it is negative.",
+            Self::JumpZero(_) => "Jump to `[LABEL]` if `[0]` is zero.",
+            Self::JumpNonZero(_) => "Jump to `[LABEL]` if `[0]` is not zero.",
+            Self::SetPaint => "Set the paint of numbered site `[0]` to the color `[1]`, as `0xrrggbbaa`.",
+            Self::GetPaint => "Push the paint of numbered site `[0]`.",
+            Self::MoveSite => "Move the atom at numbered site `[1]` to site `[0]`, leaving site `[1]` empty.",
+            Self::Diffuse => "With a chance of `[0]` in 100, move this atom to an empty site from 1 to 4.",
+            Self::CountOf => "Push how many sites within distance `[0]`, other than this one, hold atoms of type `[1]`.",
+            Self::FindFirst => "Push the lowest site other than 0 holding an atom of type `[0]`, or 0 if none does.",
+            Self::FindRandom => "Push a random site other than 0 holding an atom of type `[0]`, or 0 if none does.",
+            Self::Rand => "Push a random number of `[0]` bits, at most 96.",
+            Self::OddsOf => "Push 1 with a chance of `[1]` in `[0]`, otherwise 0.",
+            Self::RandSite => "Push a random site number from 0 to 40.",
+            Self::Compare => "Set the flags from `[1] - [0]`, comparing the values' 96-bit patterns.",
+            Self::ZeroExtend(_) => "Push the low `[WIDTH]` bits of `[0]` as an unsigned value.",
+            Self::SignExtend(_) => "Push the low `[WIDTH]` bits of `[0]` as a signed value.",
+            Self::Truncate(_) => "Push `[0]` narrowed to `[WIDTH]` bits, keeping its type.",
+            Self::GetRegister(_) => "Push the value of register `[REG]`, `R0` to `R14`, or a random value for `R?`.",
+            Self::SetRegister(_) => "Store `[0]` in register `[REG]`, `R0` to `R14`.",
+            Self::Assert => "Fail the assertion if `[0]` is zero.",
+            Self::PopCount => "Push the number of set bits of `[0]` within its width.",
+            Self::LeadingZeros => "Push the number of zero bits above the highest set bit of `[0]`.",
+            Self::TrailingZeros => "Push the number of zero bits below the lowest set bit of `[0]`.",
+            Self::RotateLeft => "Push `[1]` with its bits rotated left by `[0]` within its width.",
+            Self::RotateRight => "Push `[1]` with its bits rotated right by `[0]` within its width.",
+            Self::Min => "Push the smaller of `[1]` and `[0]` by value.",
+            Self::Max => "Push the larger of `[1]` and `[0]` by value.",
+            Self::Clamp => "Push `[2]` if it is between `[1]` and `[0]`, or else the nearer of them.",
+            Self::Abs => "Push the magnitude of `[0]`, keeping its type.",
+            Self::MulHigh => "Push the bits of `[1] * [0]` above `[1]`'s width.",
+            Self::GetLayer => "Push the value of layer `[1]` of numbered site `[0]`.",
+            Self::SetLayer => "Set layer `[1]` of numbered site `[0]` to the low 32 bits of `[2]`.",
+            Self::DecayLayer => "Lower every value of layer `[1]` in the window by a `[0]`-bit shift of it, at least 1.",
+            Self::GetHeat => "Push the heat of numbered site `[0]`.",
+            Self::EmitHeat => "Add `[1]` heat to numbered site `[0]`.",
+            Self::AbsorbHeat => "Take up to `[1]` heat from numbered site `[0]` and push how much was taken.",
+            Self::Hash => "Push a 64-bit hash of `[1]` and the grid coordinates of numbered site `[0]`.",
+            Self::NewId => "Push an id no other `newid` of the run gives, counting up from 1.",
+            Self::Bond(_) => "Bond the atom at numbered site `[0]` to the one at `[1]` by its bond field `[FIELD]`.",
+            Self::Unbond(_) => "Clear the bond field `[FIELD]` of the atom at numbered site `[0]`.",
+            Self::FollowBond(_) => "Push the site the bond field `[FIELD]` of the atom at numbered site `[0]` leads to, or 41 if none in the window.",
+            Self::Count(_) => "Add 1 to counter `[COUNTER]` of the run, from 0 to 15.",
+            Self::Push0
+            | Self::Push1
+            | Self::Push2
+            | Self::Push3
+            | Self::Push4
+            | Self::Push5
+            | Self::Push6
+            | Self::Push7
+            | Self::Push8
+            | Self::Push9
+            | Self::Push10
+            | Self::Push11
+            | Self::Push12
+            | Self::Push13
+            | Self::Push14
+            | Self::Push15
+            | Self::Push16
+            | Self::Push17
+            | Self::Push18
+            | Self::Push19
+            | Self::Push20
+            | Self::Push21
+            | Self::Push22
+            | Self::Push23
+            | Self::Push24
+            | Self::Push25
+            | Self::Push26
+            | Self::Push27
+            | Self::Push28
+            | Self::Push29
+            | Self::Push30
+            | Self::Push31
+            | Self::Push32
+            | Self::Push33
+            | Self::Push34
+            | Self::Push35
+            | Self::Push36
+            | Self::Push37
+            | Self::Push38
+            | Self::Push39
+            | Self::Push40 => {
+                "Push the number in the mnemonic."
+            }
+            Self::JumpIf(c, _) => match c {
+                Condition::Equal => "Jump to `[LABEL]` if the flags compare equal.",
+                Condition::NotEqual => "Jump to `[LABEL]` if the flags compare not equal.",
+                Condition::Less => "Jump to `[LABEL]` if the flags compare less, as signed numbers.",
+                Condition::LessEqual => {
+                    "Jump to `[LABEL]` if the flags compare less or equal, as signed numbers."
+                }
+                Condition::Greater => {
+                    "Jump to `[LABEL]` if the flags compare greater, as signed numbers."
+                }
+                Condition::GreaterEqual => {
+                    "Jump to `[LABEL]` if the flags compare greater or equal, as signed numbers."
+                }
+                Condition::LessUnsigned => {
+                    "Jump to `[LABEL]` if the flags compare less, as unsigned numbers."
+                }
+                Condition::LessEqualUnsigned => {
+                    "Jump to `[LABEL]` if the flags compare less or equal, as unsigned numbers."
+                }
+                Condition::GreaterUnsigned => {
+                    "Jump to `[LABEL]` if the flags compare greater, as unsigned numbers."
+                }
+                Condition::GreaterEqualUnsigned => {
+                    "Jump to `[LABEL]` if the flags compare greater or equal, as unsigned numbers."
+                }
+            },
+        }
+    }
+
+    /// The operand written after this instruction's mnemonic, named as the
+    /// reference documentation names it, if it takes one.
+    pub fn operand(&self) -> Option<&'static str> {
+        match self {
+            Self::SetField(_)
+            | Self::SetSiteField(_)
+            | Self::GetField(_)
+            | Self::GetSiteField(_)
+            | Self::Bond(_)
+            | Self::Unbond(_)
+            | Self::FollowBond(_) => Some("FIELD"),
+            Self::GetType(_) => Some("TYPE"),
+            Self::GetParameter(_) => Some("PARAM"),
+            Self::UseSymmetries(_) => Some("SYM[|...]"),
+            Self::Push(_) => Some("X"),
+            Self::Call(_)
+            | Self::Jump(_)
+            | Self::JumpZero(_)
+            | Self::JumpNonZero(_)
+            | Self::JumpIf(..) => Some("LABEL"),
+            Self::ZeroExtend(_) | Self::SignExtend(_) | Self::Truncate(_) => Some("WIDTH"),
+            Self::GetRegister(_) | Self::SetRegister(_) => Some("REG"),
+            Self::Count(_) => Some("COUNTER"),
+            _ => None,
+        }
+    }
+
+    /// Whether this instruction sets the flags, all four of them, from its
+    /// result.
+    pub fn sets_flags(&self) -> bool {
+        matches!(
+            self,
+            Self::Add | Self::Sub | Self::Neg | Self::Mod | Self::Mul | Self::Div | Self::Compare
+        )
+    }
+
+    /// The assembly mnemonic of this instruction.
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Self::Nop => "nop",
+            Self::Exit => "exit",
+            Self::SwapSites => "swapsites",
+            Self::SetSite => "setsite",
+            Self::SetField(_) => "setfield",
+            Self::SetSiteField(_) => "setsitefield",
+            Self::GetSite => "getsite",
+            Self::GetField(_) => "getfield",
+            Self::GetSiteField(_) => "getsitefield",
+            Self::GetType(_) => "gettype",
+            Self::GetParameter(_) => "getparameter",
+            Self::Scan => "scan",
+            Self::SaveSymmetries => "savesymmetries",
+            Self::UseSymmetries(_) => "usesymmetries",
+            Self::RestoreSymmetries => "restoresymmetries",
+            Self::Push0 => "push0",
+            Self::Push1 => "push1",
+            Self::Push2 => "push2",
+            Self::Push3 => "push3",
+            Self::Push4 => "push4",
+            Self::Push5 => "push5",
+            Self::Push6 => "push6",
+            Self::Push7 => "push7",
+            Self::Push8 => "push8",
+            Self::Push9 => "push9",
+            Self::Push10 => "push10",
+            Self::Push11 => "push11",
+            Self::Push12 => "push12",
+            Self::Push13 => "push13",
+            Self::Push14 => "push14",
+            Self::Push15 => "push15",
+            Self::Push16 => "push16",
+            Self::Push17 => "push17",
+            Self::Push18 => "push18",
+            Self::Push19 => "push19",
+            Self::Push20 => "push20",
+            Self::Push21 => "push21",
+            Self::Push22 => "push22",
+            Self::Push23 => "push23",
+            Self::Push24 => "push24",
+            Self::Push25 => "push25",
+            Self::Push26 => "push26",
+            Self::Push27 => "push27",
+            Self::Push28 => "push28",
+            Self::Push29 => "push29",
+            Self::Push30 => "push30",
+            Self::Push31 => "push31",
+            Self::Push32 => "push32",
+            Self::Push33 => "push33",
+            Self::Push34 => "push34",
+            Self::Push35 => "push35",
+            Self::Push36 => "push36",
+            Self::Push37 => "push37",
+            Self::Push38 => "push38",
+            Self::Push39 => "push39",
+            Self::Push40 => "push40",
+            Self::Push(_) => "push",
+            Self::Pop => "pop",
+            Self::Dup => "dup",
+            Self::Over => "over",
+            Self::Swap => "swap",
+            Self::Rot => "rot",
+            Self::Call(_) => "call",
+            Self::Ret => "ret",
+            Self::Checksum => "checksum",
+            Self::Add => "add",
+            Self::Sub => "sub",
+            Self::Neg => "neg",
+            Self::Mod => "mod",
+            Self::Mul => "mul",
+            Self::Div => "div",
+            Self::Less => "less",
+            Self::LessEqual => "lessequal",
+            Self::Or => "or",
+            Self::And => "and",
+            Self::Xor => "xor",
+            Self::Equal => "equal",
+            Self::BitCount => "bitcount",
+            Self::BitScanForward => "bitscanforward",
+            Self::BitScanReverse => "bitscanreverse",
+            Self::LShift => "lshift",
+            Self::RShift => "rshift",
+            Self::Jump(_) => "jump",
+            Self::JumpRelativeOffset => "jumprelativeoffset",
+            Self::JumpZero(_) => "jumpzero",
+            Self::JumpNonZero(_) => "jumpnonzero",
+            Self::SetPaint => "setpaint",
+            Self::GetPaint => "getpaint",
+            Self::MoveSite => "movesite",
+            Self::Diffuse => "diffuse",
+            Self::CountOf => "countof",
+            Self::FindFirst => "findfirst",
+            Self::FindRandom => "findrandom",
+            Self::Rand => "rand",
+            Self::OddsOf => "oddsof",
+            Self::RandSite => "randsite",
+            Self::Compare => "cmp",
+            Self::JumpIf(c, _) => c.mnemonic(),
+            Self::ZeroExtend(_) => "zext",
+            Self::SignExtend(_) => "sext",
+            Self::Truncate(_) => "trunc",
+            Self::GetRegister(_) => "getregister",
+            Self::SetRegister(_) => "setregister",
+            Self::Assert => "assert",
+            Self::PopCount => "popcnt",
+            Self::LeadingZeros => "clz",
+            Self::TrailingZeros => "ctz",
+            Self::RotateLeft => "rotl",
+            Self::RotateRight => "rotr",
+            Self::Min => "min",
+            Self::Max => "max",
+            Self::Clamp => "clamp",
+            Self::Abs => "abs",
+            Self::MulHigh => "mulh",
+            Self::GetLayer => "getlayer",
+            Self::SetLayer => "setlayer",
+            Self::DecayLayer => "decaylayer",
+            Self::GetHeat => "getheat",
+            Self::EmitHeat => "emitheat",
+            Self::AbsorbHeat => "absorbheat",
+            Self::Hash => "hash",
+            Self::NewId => "newid",
+            Self::Bond(_) => "bond",
+            Self::Unbond(_) => "unbond",
+            Self::FollowBond(_) => "followbond",
+            Self::Count(_) => "count",
         }
     }
 }
@@ -270,4 +1258,6 @@ impl Instruction<'_> {
 pub struct File<'input> {
     pub header: Vec<Node<'input>>,
     pub body: Vec<Node<'input>>,
+    /// The byte offset in the source of each node of `body`.
+    pub offsets: Vec<usize>,
 }
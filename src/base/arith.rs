@@ -1,58 +1,1091 @@
 use crate::base::FieldSelector;
+use std::cmp::Ordering;
 use std::fmt;
 use std::num::ParseIntError;
 use std::ops::{Add, BitAnd, BitOr, BitXor, Div, Mul, Neg, Rem, Shl, Shr, Sub};
+use std::str::FromStr;
 
-#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Const {
     Unsigned(u128),
-    Signed(i128), // FIXME: signed doesn't support variable width
+    /// A two's complement value of the given bit width. `value` is always kept
+    /// sign extended from `bits`, so it is in range for that width.
+    Signed {
+        value: i128,
+        bits: u8,
+    },
+    /// A 96 bit two's complement fixed point number of `value` units of
+    /// 2^-`frac`, so that `Fixed { value: 384, frac: 8 }` is 1.5. Like
+    /// `Signed`, `value` is kept in range.
+    Fixed {
+        value: i128,
+        frac: u8,
+    },
 }
 
 impl Const {
+    /// Width of atoms and of constants which do not declare their own width.
+    pub const MAX_BITS: u8 = 96;
+
+    /// Most fraction bits of a fixed point constant, which keeps a sign bit.
+    pub const MAX_FRAC: u8 = Self::MAX_BITS - 1;
+
+    /// Builds a signed constant of width `bits`, wrapping `value` into range.
+    ///
+    /// Panics if `bits` is not in `1..=128`.
+    pub fn signed(value: i128, bits: u8) -> Self {
+        Self::Signed {
+            value: sign_extend(value as u128, bits),
+            bits,
+        }
+    }
+
+    /// Builds a fixed point constant of `value` units of 2^-`frac`, wrapping
+    /// `value` into range.
+    ///
+    /// Panics if `frac` is not in `1..=MAX_FRAC`.
+    pub fn fixed(value: i128, frac: u8) -> Self {
+        assert!(
+            (1..=Self::MAX_FRAC).contains(&frac),
+            "bad fraction bits: {} not in 1..={}",
+            frac,
+            Self::MAX_FRAC
+        );
+        Self::Fixed {
+            value: sign_extend(value as u128, Self::MAX_BITS),
+            frac,
+        }
+    }
+
     pub fn from_str_radix(s: &str, radix: u32) -> Result<Self, ParseIntError> {
         u128::from_str_radix(s, radix).map(|x| Self::Unsigned(x))
     }
 
     pub fn from_str_signed_radix(s: &str, radix: u32) -> Result<Self, ParseIntError> {
-        i128::from_str_radix(s, radix).map(|x| Self::Signed(x))
+        i128::from_str_radix(s, radix).map(|x| Self::signed(x, Self::MAX_BITS))
+    }
+
+    /// Smallest value representable in a signed field of width `bits`.
+    pub fn min_signed(bits: u8) -> i128 {
+        assert_width(bits);
+        i128::MIN >> (128 - bits as u32)
+    }
+
+    /// Largest value representable in a signed field of width `bits`.
+    pub fn max_signed(bits: u8) -> i128 {
+        assert_width(bits);
+        i128::MAX >> (128 - bits as u32)
+    }
+
+    pub fn bits(&self) -> u8 {
+        match self {
+            Self::Unsigned(_) | Self::Fixed { .. } => Self::MAX_BITS,
+            Self::Signed { bits, .. } => *bits,
+        }
+    }
+
+    /// Whether this is a signed integer or a fixed point number.
+    pub fn is_signed(&self) -> bool {
+        matches!(self, Self::Signed { .. } | Self::Fixed { .. })
     }
 
     pub fn is_zero(&self) -> bool {
         match self {
             Self::Unsigned(x) => *x == 0,
-            Self::Signed(x) => *x == 0,
+            Self::Signed { value, .. } | Self::Fixed { value, .. } => *value == 0,
         }
     }
 
+    /// The value, or the units of a fixed point number.
     pub fn as_u128(self) -> u128 {
         match self {
             Self::Unsigned(x) => x,
-            Self::Signed(x) => x as u128,
+            Self::Signed { value, .. } | Self::Fixed { value, .. } => value as u128,
         }
     }
 
+    /// The value, or the units of a fixed point number.
     pub fn as_i128(self) -> i128 {
         match self {
             Self::Unsigned(x) => x as i128,
-            Self::Signed(x) => x,
+            Self::Signed { value, .. } | Self::Fixed { value, .. } => value,
+        }
+    }
+
+    /// The constant as an EWAL literal, as in `255`, `0x10000`, `+5` or
+    /// `1.5q8`. Signed constants are written without their width.
+    pub fn to_literal(self) -> String {
+        match self {
+            Self::Unsigned(x) if x > 0xffff => format!("{:#x}", x),
+            Self::Unsigned(x) => format!("{}", x),
+            Self::Signed { value, .. } => format!("{:+}", value),
+            Self::Fixed { frac, .. } => format!("{}q{}", self.to_decimal(), frac),
+        }
+    }
+
+    /// The value in decimal, with the exact fraction of a fixed point number,
+    /// as in `-1.5`.
+    pub fn to_decimal(self) -> String {
+        let (value, frac) = match self {
+            Self::Unsigned(x) => return x.to_string(),
+            Self::Signed { value, .. } => return value.to_string(),
+            Self::Fixed { value, frac } => (value, frac),
+        };
+        let m = value.unsigned_abs();
+        let sign = if value < 0 { "-" } else { "" };
+        let mut s = format!("{}{}", sign, m >> frac);
+        // Each fraction bit adds at most a decimal digit, so this ends.
+        let mask = (1 << frac) - 1;
+        let mut part = m & mask;
+        if part != 0 {
+            s.push('.');
+        }
+        while part != 0 {
+            part *= 10;
+            s.push(char::from(b'0' + (part >> frac) as u8));
+            part &= mask;
+        }
+        s
+    }
+
+    /// The integer part of a fixed point number, rounded toward zero, as a
+    /// full width signed constant. Integers are returned as they are.
+    pub fn integer(self) -> Self {
+        match self {
+            Self::Fixed { value, frac } => Self::signed(value / (1 << frac), Self::MAX_BITS),
+            _ => self,
+        }
+    }
+
+    /// The 96 bit two's complement pattern of this value.
+    pub fn pattern(self) -> u128 {
+        self.as_u128() & Self::MAX_UNSIGNED
+    }
+
+    /// Compares the numbers represented, so that signed and unsigned values
+    /// of any width order as expected: `-1` is less than `0` of either kind.
+    pub fn cmp_value(self, other: Self) -> Ordering {
+        match (self, other) {
+            (Self::Fixed { value, frac }, _) => {
+                // Integer parts first, then the fractions in the finer units.
+                let (int, part) = (value >> frac, value & ((1 << frac) - 1));
+                let (other_int, other_part, other_frac) = match other {
+                    Self::Fixed { value, frac } => (value >> frac, value & ((1 << frac) - 1), frac),
+                    _ => (0, 0, 0),
+                };
+                let int_order = match other {
+                    Self::Fixed { .. } => int.cmp(&other_int),
+                    _ => Self::signed(int, Self::MAX_BITS).cmp_value(other),
+                };
+                let finest = frac.max(other_frac);
+                int_order
+                    .then((part << (finest - frac)).cmp(&(other_part << (finest - other_frac))))
+            }
+            (_, Self::Fixed { .. }) => other.cmp_value(self).reverse(),
+            (Self::Unsigned(x), Self::Unsigned(y)) => x.cmp(&y),
+            (Self::Signed { value: x, .. }, Self::Signed { value: y, .. }) => x.cmp(&y),
+            (Self::Signed { value, .. }, Self::Unsigned(_)) if value < 0 => Ordering::Less,
+            (Self::Signed { value, .. }, Self::Unsigned(y)) => (value as u128).cmp(&y),
+            (Self::Unsigned(_), Self::Signed { .. }) => other.cmp_value(self).reverse(),
+        }
+    }
+
+    /// Compares the 96 bit patterns as unsigned numbers, ignoring the types,
+    /// as the unsigned conditional jumps do after `cmp`.
+    pub fn cmp_unsigned(self, other: Self) -> Ordering {
+        self.pattern().cmp(&other.pattern())
+    }
+
+    /// Compares the 96 bit patterns as two's complement numbers, ignoring the
+    /// types, as the signed conditional jumps do after `cmp`.
+    pub fn cmp_signed(self, other: Self) -> Ordering {
+        let (x, y) = (self.pattern(), other.pattern());
+        sign_extend(x, Self::MAX_BITS).cmp(&sign_extend(y, Self::MAX_BITS))
+    }
+
+    /// Reinterprets the low `bits` of this value as a signed constant of that
+    /// width (sign extending from bit `bits - 1`).
+    pub fn to_signed(self, bits: u8) -> Self {
+        Self::signed(self.as_i128(), bits)
+    }
+
+    /// Returns the two's complement bit pattern of this value truncated to its
+    /// width, as an unsigned constant.
+    pub fn to_unsigned(self) -> Self {
+        match self {
+            Self::Unsigned(_) => self,
+            Self::Signed { value, bits } => Self::Unsigned(value as u128 & width_mask(bits)),
+            Self::Fixed { value, .. } => Self::Unsigned(value as u128 & Self::MAX_UNSIGNED),
+        }
+    }
+
+    /// The low `bits` of this value's 96 bit pattern, as an unsigned constant.
+    pub fn zero_extend(self, bits: u8) -> Self {
+        Self::Unsigned(self.pattern() & width_mask(bits))
+    }
+
+    /// The low `bits` of this value's 96 bit pattern read as two's
+    /// complement, widened to a full width signed constant.
+    pub fn sign_extend(self, bits: u8) -> Self {
+        Self::signed(sign_extend(self.as_u128(), bits), Self::MAX_BITS)
+    }
+
+    /// Changes the width of a signed constant, sign extending when widening and
+    /// wrapping when narrowing. Unsigned constants are truncated to `bits`, and
+    /// fixed point numbers become signed constants of their units.
+    pub fn with_bits(self, bits: u8) -> Self {
+        match self {
+            Self::Unsigned(x) => Self::Unsigned(x & width_mask(bits)),
+            Self::Signed { value, .. } | Self::Fixed { value, .. } => Self::signed(value, bits),
+        }
+    }
+
+    /// Builds a signed constant of width `bits`, clamping `value` into range.
+    fn saturating(value: i128, bits: u8) -> Self {
+        Self::Signed {
+            value: value.clamp(Self::min_signed(bits), Self::max_signed(bits)),
+            bits,
         }
     }
 
     pub fn apply(self, f: FieldSelector) -> Self {
-        (self >> f.offset) & ((1u128 << (f.length - 1)) - 1).into()
+        Self::Unsigned(f.extract(self.as_u128()))
+    }
+
+    /// The inverse of `apply`: returns this word with field `f` replaced by
+    /// `value`, truncated to the field width.
+    pub fn with_field(self, f: FieldSelector, value: Const) -> Self {
+        Self::Unsigned(f.insert(self.as_u128(), value.as_u128()))
+    }
+
+    /// The smaller of two values, compared as numbers whatever their types,
+    /// as `cmp_value` does. Equal values give `self`.
+    pub fn min(self, other: Self) -> Self {
+        if other.cmp_value(self).is_lt() {
+            other
+        } else {
+            self
+        }
+    }
+
+    /// The larger of two values, compared as `min` does.
+    pub fn max(self, other: Self) -> Self {
+        if other.cmp_value(self).is_gt() {
+            other
+        } else {
+            self
+        }
+    }
+
+    /// This value if it is between `lo` and `hi`, or else the nearer of them.
+    /// If `lo` is above `hi`, gives `hi`.
+    pub fn clamp(self, lo: Self, hi: Self) -> Self {
+        self.max(lo).min(hi)
+    }
+
+    /// Whether dividing this value by `rhs`, or taking the remainder if
+    /// `rem`, divides by zero. Integers take only the integer part of a fixed
+    /// point divisor, and fixed point remainders take the divisor in units of
+    /// the dividend, as in arithmetic.
+    pub fn divides_by_zero(self, rhs: Self, rem: bool) -> bool {
+        match (self, rhs) {
+            (
+                Self::Fixed { frac, .. },
+                Self::Fixed {
+                    value,
+                    frac: y_frac,
+                },
+            ) if rem => align_fixed(value, y_frac, frac) == (0, false),
+            (Self::Fixed { .. }, _) => rhs.is_zero(),
+            _ => rhs.integer().is_zero(),
+        }
+    }
+
+    /// Whether this value is signed and below zero.
+    pub fn is_negative(self) -> bool {
+        self.is_signed() && self.as_i128() < 0
+    }
+
+    /// This value's two's complement bits within its width.
+    fn width_bits(self) -> u128 {
+        self.as_u128() & width_mask(self.bits())
+    }
+
+    /// A constant of this one's type with the bits `x`.
+    fn with_width_bits(self, x: u128) -> Self {
+        match self {
+            Self::Unsigned(_) => Self::Unsigned(x),
+            Self::Signed { bits, .. } => Self::signed(x as i128, bits),
+            Self::Fixed { frac, .. } => Self::fixed(x as i128, frac),
+        }
+    }
+
+    /// The number of set bits within this value's width.
+    pub fn count_ones(self) -> u32 {
+        self.width_bits().count_ones()
+    }
+
+    /// The number of zero bits above the highest set bit within this value's
+    /// width.
+    pub fn leading_zeros(self) -> u32 {
+        self.width_bits().leading_zeros() - (128 - self.bits() as u32)
+    }
+
+    /// The number of zero bits below the lowest set bit, or the width if no
+    /// bit is set.
+    pub fn trailing_zeros(self) -> u32 {
+        self.width_bits().trailing_zeros().min(self.bits() as u32)
+    }
+
+    /// Rotates this value's bits left by `n` within its width, keeping its
+    /// type.
+    pub fn rotate_left(self, n: u32) -> Self {
+        let bits = self.bits() as u32;
+        let (x, n) = (self.width_bits(), n % bits);
+        if n == 0 {
+            return self;
+        }
+        self.with_width_bits(((x << n) | (x >> (bits - n))) & width_mask(bits as u8))
+    }
+
+    /// Rotates this value's bits right by `n` within its width, keeping its
+    /// type.
+    pub fn rotate_right(self, n: u32) -> Self {
+        let bits = self.bits() as u32;
+        self.rotate_left(bits - n % bits)
+    }
+
+    /// The exact product of two values, as its high and low halves of this
+    /// one's width, each of this one's type: the product is `hi` times two to
+    /// the width plus the bits of `lo`. Fixed point numbers multiply as their
+    /// units.
+    pub fn widening_mul(self, other: Self) -> (Self, Self) {
+        let magnitude = |c: Self| match c {
+            Self::Unsigned(x) => (x, false),
+            Self::Signed { value, .. } | Self::Fixed { value, .. } => {
+                (value.unsigned_abs(), value < 0)
+            }
+        };
+        let ((a, a_neg), (b, b_neg)) = (magnitude(self), magnitude(other));
+        let (mut hi, mut lo) = wide_mul(a, b);
+        if a_neg != b_neg {
+            // Two's complement of all 256 bits.
+            let (l, carry) = (!lo).overflowing_add(1);
+            hi = (!hi).wrapping_add(carry as u128);
+            lo = l;
+        }
+        let (bits, mask) = (self.bits() as u32, width_mask(self.bits()));
+        let high = (lo >> bits) | (hi << (128 - bits));
+        (
+            self.with_width_bits(high & mask),
+            self.with_width_bits(lo & mask),
+        )
+    }
+
+    /// The high half of `widening_mul`.
+    pub fn mul_high(self, other: Self) -> Self {
+        self.widening_mul(other).0
     }
 }
 
+/// Selects how arithmetic behaves when a result does not fit its width.
+///
+/// Division by zero is only reported by `Checked`; the other modes produce zero.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ArithMode {
+    #[default]
+    Saturating,
+    Wrapping,
+    Checked,
+}
+
+impl ArithMode {
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            Self::Saturating => 0,
+            Self::Wrapping => 1,
+            Self::Checked => 2,
+        }
+    }
+
+    pub fn from_u8(x: u8) -> Option<Self> {
+        match x {
+            0 => Some(Self::Saturating),
+            1 => Some(Self::Wrapping),
+            2 => Some(Self::Checked),
+            _ => None,
+        }
+    }
+
+    pub fn add(self, a: Const, b: Const) -> Option<Const> {
+        a.arith(b, Op::Add, self)
+    }
+
+    pub fn sub(self, a: Const, b: Const) -> Option<Const> {
+        a.arith(b, Op::Sub, self)
+    }
+
+    pub fn mul(self, a: Const, b: Const) -> Option<Const> {
+        a.arith(b, Op::Mul, self)
+    }
+
+    pub fn div(self, a: Const, b: Const) -> Option<Const> {
+        a.arith(b, Op::Div, self)
+    }
+
+    pub fn rem(self, a: Const, b: Const) -> Option<Const> {
+        a.arith(b, Op::Rem, self)
+    }
+
+    /// The magnitude of `a`, of the same type. Only the most negative value
+    /// of a width doesn't fit.
+    pub fn abs(self, a: Const) -> Option<Const> {
+        if !a.is_negative() {
+            return Some(a);
+        }
+        self.neg(a)
+    }
+
+    pub fn neg(self, a: Const) -> Option<Const> {
+        let zero = match a {
+            Const::Unsigned(_) => Const::signed(0, Const::MAX_BITS),
+            Const::Signed { bits, .. } => Const::signed(0, bits),
+            Const::Fixed { frac, .. } => Const::Fixed { value: 0, frac },
+        };
+        zero.arith(a, Op::Sub, self)
+    }
+}
+
+impl FromStr for ArithMode {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "saturating" => Ok(Self::Saturating),
+            "wrapping" => Ok(Self::Wrapping),
+            "checked" => Ok(Self::Checked),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for ArithMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Saturating => write!(f, "saturating"),
+            Self::Wrapping => write!(f, "wrapping"),
+            Self::Checked => write!(f, "checked"),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+serde_str!(ArithMode);
+
+/// Selects what `div` and `mod` do when dividing by zero.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DivZeroPolicy {
+    /// As the arithmetic mode says: `Checked` aborts the event and the other
+    /// modes give zero.
+    #[default]
+    Arithmetic,
+    /// Aborts the event whatever the arithmetic mode.
+    Trap,
+    /// Division gives all ones of the dividend's type, and the remainder is
+    /// the dividend.
+    Ones,
+    /// Gives zero, which the overflow flag marks, whatever the arithmetic
+    /// mode.
+    Flag,
+}
+
+impl DivZeroPolicy {
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            Self::Arithmetic => 0,
+            Self::Trap => 1,
+            Self::Ones => 2,
+            Self::Flag => 3,
+        }
+    }
+
+    pub fn from_u8(x: u8) -> Option<Self> {
+        match x {
+            0 => Some(Self::Arithmetic),
+            1 => Some(Self::Trap),
+            2 => Some(Self::Ones),
+            3 => Some(Self::Flag),
+            _ => None,
+        }
+    }
+
+    /// The result of dividing `a` by zero, or of its remainder if `rem`,
+    /// under arithmetic mode `mode`, or `None` if the event should abort.
+    pub fn divide_by_zero(self, a: Const, rem: bool, mode: ArithMode) -> Option<Const> {
+        let zero = a.with_width_bits(0);
+        match self {
+            Self::Arithmetic => div_by_zero(zero, mode),
+            Self::Trap => None,
+            Self::Ones if rem => Some(a),
+            Self::Ones => Some(a.with_width_bits(width_mask(a.bits()))),
+            Self::Flag => Some(zero),
+        }
+    }
+}
+
+impl FromStr for DivZeroPolicy {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "arithmetic" => Ok(Self::Arithmetic),
+            "trap" => Ok(Self::Trap),
+            "ones" => Ok(Self::Ones),
+            "flag" => Ok(Self::Flag),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for DivZeroPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Arithmetic => write!(f, "arithmetic"),
+            Self::Trap => write!(f, "trap"),
+            Self::Ones => write!(f, "ones"),
+            Self::Flag => write!(f, "flag"),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+serde_str!(DivZeroPolicy);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+macro_rules! arith_family {
+    ($($checked:ident, $wrapping:ident, $saturating:ident => $op:ident;)*) => {
+        impl Const {
+            $(
+                pub fn $checked(self, rhs: Self) -> Option<Self> {
+                    self.arith(rhs, Op::$op, ArithMode::Checked)
+                }
+
+                pub fn $wrapping(self, rhs: Self) -> Self {
+                    self.arith(rhs, Op::$op, ArithMode::Wrapping).unwrap()
+                }
+
+                pub fn $saturating(self, rhs: Self) -> Self {
+                    self.arith(rhs, Op::$op, ArithMode::Saturating).unwrap()
+                }
+            )*
+        }
+    };
+}
+
+arith_family! {
+    checked_add, wrapping_add, saturating_add => Add;
+    checked_sub, wrapping_sub, saturating_sub => Sub;
+    checked_mul, wrapping_mul, saturating_mul => Mul;
+    checked_div, wrapping_div, saturating_div => Div;
+    checked_rem, wrapping_rem, saturating_rem => Rem;
+}
+
+impl Const {
+    /// Largest unsigned value; unsigned constants are as wide as an atom.
+    pub const MAX_UNSIGNED: u128 = (1 << Self::MAX_BITS) - 1;
+
+    pub fn checked_neg(self) -> Option<Self> {
+        ArithMode::Checked.neg(self)
+    }
+
+    pub fn wrapping_neg(self) -> Self {
+        ArithMode::Wrapping.neg(self).unwrap()
+    }
+
+    pub fn saturating_neg(self) -> Self {
+        ArithMode::Saturating.neg(self).unwrap()
+    }
+
+    /// Applies `op` under `mode`. Returns `None` only in `Checked` mode, on
+    /// overflow or division by zero.
+    fn arith(self, rhs: Self, op: Op, mode: ArithMode) -> Option<Self> {
+        // Integers take only the integer part of a fixed point operand.
+        let rhs = match self {
+            Self::Fixed { .. } => rhs,
+            _ => rhs.integer(),
+        };
+        match self {
+            Self::Unsigned(x) => {
+                let y = rhs.as_u128();
+                let (r, overflow) = match op {
+                    Op::Add => x.overflowing_add(y),
+                    Op::Sub => x.overflowing_sub(y),
+                    Op::Mul => x.overflowing_mul(y),
+                    Op::Div | Op::Rem if y == 0 => return div_by_zero(Self::Unsigned(0), mode),
+                    Op::Div => (x / y, false),
+                    Op::Rem => (x % y, false),
+                };
+                if !overflow && r <= Self::MAX_UNSIGNED {
+                    return Some(Self::Unsigned(r));
+                }
+                match mode {
+                    ArithMode::Saturating if op == Op::Sub => Some(Self::Unsigned(0)),
+                    ArithMode::Saturating => Some(Self::Unsigned(Self::MAX_UNSIGNED)),
+                    ArithMode::Wrapping => Some(Self::Unsigned(r & Self::MAX_UNSIGNED)),
+                    ArithMode::Checked => None,
+                }
+            }
+            Self::Signed { value: x, bits } => {
+                let y = rhs.as_i128();
+                let (r, overflow) = match op {
+                    Op::Add => x.overflowing_add(y),
+                    Op::Sub => x.overflowing_sub(y),
+                    Op::Mul => x.overflowing_mul(y),
+                    Op::Div | Op::Rem if y == 0 => {
+                        return div_by_zero(Self::Signed { value: 0, bits }, mode)
+                    }
+                    Op::Div => x.overflowing_div(y),
+                    Op::Rem => x.overflowing_rem(y),
+                };
+                if !overflow && r >= Self::min_signed(bits) && r <= Self::max_signed(bits) {
+                    return Some(Self::Signed { value: r, bits });
+                }
+                match mode {
+                    ArithMode::Saturating => {
+                        let r = match op {
+                            Op::Add => x.saturating_add(y),
+                            Op::Sub => x.saturating_sub(y),
+                            Op::Mul => x.saturating_mul(y),
+                            Op::Div => x.saturating_div(y),
+                            Op::Rem => 0,
+                        };
+                        Some(Self::saturating(r, bits))
+                    }
+                    ArithMode::Wrapping => Some(Self::signed(r, bits)),
+                    ArithMode::Checked => None,
+                }
+            }
+            Self::Fixed { value: x, frac } => Self::fixed_arith(x, frac, rhs, op, mode),
+        }
+    }
+
+    /// Applies `op` to a fixed point number of `x` units of 2^-`frac`.
+    /// Products and quotients are rounded toward zero.
+    fn fixed_arith(x: i128, frac: u8, rhs: Self, op: Op, mode: ArithMode) -> Option<Self> {
+        let (y, y_frac) = match rhs {
+            Self::Fixed { value, frac } => (value, frac),
+            _ => (rhs.as_i128(), 0),
+        };
+        // `rhs` in units of 2^-`frac`, for sums and remainders, and whether
+        // it doesn't fit an i128 in them.
+        let aligned = align_fixed(y, y_frac, frac);
+        // A divisor of fewer units than the dividend's smallest is zero for
+        // remainders.
+        if y == 0 && op == Op::Div || aligned == (0, false) && op == Op::Rem {
+            return div_by_zero(Self::Fixed { value: 0, frac }, mode);
+        }
+        let (r, overflow) = match op {
+            Op::Add if aligned.1 => (x.wrapping_add(aligned.0), true),
+            Op::Add => x.overflowing_add(aligned.0),
+            Op::Sub if aligned.1 => (x.wrapping_sub(aligned.0), true),
+            Op::Sub => x.overflowing_sub(aligned.0),
+            // `x` is smaller than any `rhs` too big for an i128.
+            Op::Rem if aligned.1 => (x, false),
+            Op::Rem => x.overflowing_rem(aligned.0),
+            Op::Mul => mul_shr(x, y, y_frac),
+            Op::Div => div_shl(x, y, y_frac),
+        };
+        let (min, max) = (
+            Self::min_signed(Self::MAX_BITS),
+            Self::max_signed(Self::MAX_BITS),
+        );
+        if !overflow && r >= min && r <= max {
+            return Some(Self::Fixed { value: r, frac });
+        }
+        match mode {
+            ArithMode::Saturating => {
+                let negative = match op {
+                    _ if !overflow => r < 0,
+                    Op::Add | Op::Rem => y < 0,
+                    Op::Sub => y > 0,
+                    Op::Mul | Op::Div => (x < 0) != (y < 0),
+                };
+                let value = if negative { min } else { max };
+                Some(Self::Fixed { value, frac })
+            }
+            ArithMode::Wrapping => Some(Self::fixed(r, frac)),
+            ArithMode::Checked => None,
+        }
+    }
+}
+
+/// `y` units of 2^-`y_frac` in units of 2^-`frac`, rounded toward zero, and
+/// whether they don't fit an i128.
+fn align_fixed(y: i128, y_frac: u8, frac: u8) -> (i128, bool) {
+    if y_frac > frac {
+        (y >> (y_frac - frac), false)
+    } else {
+        y.overflowing_mul(1 << (frac - y_frac))
+    }
+}
+
+/// The product `x * y >> shift`, rounded toward zero, and whether it doesn't
+/// fit an i128, in which case its low 128 bits are given.
+fn mul_shr(x: i128, y: i128, shift: u8) -> (i128, bool) {
+    let (hi, lo) = wide_mul(x.unsigned_abs(), y.unsigned_abs());
+    let (hi, lo) = match shift {
+        0 => (hi, lo),
+        s => (hi >> s, (lo >> s) | (hi << (128 - s as u32))),
+    };
+    signed_magnitude(lo, hi != 0, (x < 0) != (y < 0))
+}
+
+/// The 256 bit product of `a` and `b`, as high and low halves.
+fn wide_mul(a: u128, b: u128) -> (u128, u128) {
+    let (a1, a0, b1, b0) = (a >> 64, a as u64 as u128, b >> 64, b as u64 as u128);
+    let (mid1, mid2) = (a0 * b1, a1 * b0);
+    let (lo, c1) = (a0 * b0).overflowing_add(mid1 << 64);
+    let (lo, c2) = lo.overflowing_add(mid2 << 64);
+    let hi = a1 * b1 + (mid1 >> 64) + (mid2 >> 64) + c1 as u128 + c2 as u128;
+    (hi, lo)
+}
+
+/// The quotient `(x << shift) / y`, rounded toward zero, and whether it
+/// doesn't fit an i128, in which case its low 128 bits are given. `y` must
+/// not be zero.
+fn div_shl(x: i128, y: i128, shift: u8) -> (i128, bool) {
+    let (a, b) = (x.unsigned_abs(), y.unsigned_abs());
+    let (mut q, mut r) = (a / b, a % b);
+    let mut overflow = false;
+    // Long division, one bit of the shifted dividend at a time. `r` is less
+    // than `b`, so doubling it can't overflow.
+    for _ in 0..shift {
+        overflow |= q >> 127 != 0;
+        r <<= 1;
+        q = (q << 1) | (r >= b) as u128;
+        if r >= b {
+            r -= b;
+        }
+    }
+    signed_magnitude(q, overflow, (x < 0) != (y < 0))
+}
+
+/// Applies a sign to a magnitude, saying whether the result doesn't fit an
+/// i128.
+fn signed_magnitude(m: u128, overflow: bool, negative: bool) -> (i128, bool) {
+    let limit = if negative {
+        1 << 127
+    } else {
+        i128::MAX as u128
+    };
+    let r = if negative {
+        (m as i128).wrapping_neg()
+    } else {
+        m as i128
+    };
+    (r, overflow || m > limit)
+}
+
+/// Condition flags, set by arithmetic instructions and `cmp` and tested by
+/// the conditional jumps.
+///
+/// `zero` and `negative` describe the exact result, before the arithmetic
+/// mode wraps or saturates it. `carry` is set when adding the operands' 96 bit
+/// patterns carries out, or subtracting them borrows. `overflow` is set when
+/// the result did not fit its type.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Flags {
+    pub zero: bool,
+    pub negative: bool,
+    pub carry: bool,
+    pub overflow: bool,
+}
+
+impl Flags {
+    fn exact(result: i128, carry: bool, overflow: bool) -> Self {
+        Self {
+            zero: result == 0,
+            negative: result < 0,
+            carry,
+            overflow,
+        }
+    }
+
+    /// The result of `op` on a fixed point `a`, saturated, which is zero or
+    /// negative when the exact result is. Operands are at most 96 bits wide,
+    /// so sums and quotients of other values are exact in an i128.
+    fn fixed_result(a: Const, b: Const, op: Op) -> Option<i128> {
+        match a {
+            Const::Fixed { .. } => a.arith(b, op, ArithMode::Saturating).map(Const::as_i128),
+            _ => None,
+        }
+    }
+
+    pub fn add(a: Const, b: Const) -> Self {
+        let carry = a.pattern() + b.pattern() > Const::MAX_UNSIGNED;
+        let overflow = a.checked_add(b).is_none();
+        let r = Self::fixed_result(a, b, Op::Add)
+            .unwrap_or_else(|| a.as_i128() + b.integer().as_i128());
+        Self::exact(r, carry, overflow)
+    }
+
+    pub fn sub(a: Const, b: Const) -> Self {
+        let carry = a.pattern() < b.pattern();
+        let overflow = a.checked_sub(b).is_none();
+        let r = Self::fixed_result(a, b, Op::Sub)
+            .unwrap_or_else(|| a.as_i128() - b.integer().as_i128());
+        Self::exact(r, carry, overflow)
+    }
+
+    pub fn mul(a: Const, b: Const) -> Self {
+        let overflow = a.checked_mul(b).is_none();
+        let b = if matches!(a, Const::Fixed { .. }) {
+            b
+        } else {
+            b.integer()
+        };
+        let sign = a.as_i128().signum() * b.as_i128().signum();
+        Self::exact(sign, overflow, overflow)
+    }
+
+    pub fn div(a: Const, b: Const) -> Self {
+        let overflow = a.checked_div(b).is_none();
+        let r = Self::fixed_result(a, b, Op::Div)
+            .unwrap_or_else(|| a.as_i128().checked_div(b.integer().as_i128()).unwrap_or(0));
+        Self::exact(r, false, overflow)
+    }
+
+    pub fn rem(a: Const, b: Const) -> Self {
+        let overflow = a.checked_rem(b).is_none();
+        let r = Self::fixed_result(a, b, Op::Rem)
+            .unwrap_or_else(|| a.as_i128().checked_rem(b.integer().as_i128()).unwrap_or(0));
+        Self::exact(r, false, overflow)
+    }
+
+    pub fn neg(a: Const) -> Self {
+        Self::exact(-a.as_i128(), !a.is_zero(), a.checked_neg().is_none())
+    }
+
+    /// The flags of subtracting the 96 bit patterns of `a` and `b`, ignoring
+    /// their types: `carry` orders them as unsigned numbers and `negative` as
+    /// two's complement ones.
+    pub fn compare(a: Const, b: Const) -> Self {
+        let (x, y) = (a.pattern(), b.pattern());
+        let d = sign_extend(x, Const::MAX_BITS) - sign_extend(y, Const::MAX_BITS);
+        let overflow =
+            d < Const::min_signed(Const::MAX_BITS) || d > Const::max_signed(Const::MAX_BITS);
+        Self::exact(d, x < y, overflow)
+    }
+}
+
+fn div_by_zero(zero: Const, mode: ArithMode) -> Option<Const> {
+    match mode {
+        ArithMode::Checked => None,
+        _ => Some(zero),
+    }
+}
+
+fn assert_width(bits: u8) {
+    assert!(
+        (1..=128).contains(&bits),
+        "bad signed width: {} not in 1..=128",
+        bits
+    );
+}
+
+fn width_mask(bits: u8) -> u128 {
+    assert_width(bits);
+    u128::MAX >> (128 - bits as u32)
+}
+
+/// Sign extends the low `bits` of `x` to a full i128.
+pub fn sign_extend(x: u128, bits: u8) -> i128 {
+    assert_width(bits);
+    let shift = 128 - bits as u32;
+    ((x << shift) as i128) >> shift
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ParseConstError {
+    #[error("missing digits")]
+    NoDigits,
+    #[error("bad type {0}: expected U96, I1 to I128 or Q1 to Q95")]
+    BadType(String),
+    #[error("bad fraction bits {0}: expected 1 to 95")]
+    BadFraction(String),
+    #[error("bad digit {0:?} in base {1} number")]
+    BadDigit(char, u32),
+    #[error("digit separators must come between digits")]
+    BadSeparator,
+    #[error("value out of range for {0}")]
+    OutOfRange(String),
+}
+
+/// The canonical form of a constant: its type, a colon and its value.
+/// Unsigned values are written in hex and signed and fixed point values in
+/// decimal, as in `U96:0xff`, `I96:-5` and `Q8:1.5`, where `Q8` has 8
+/// fraction bits; `FromStr` reads them back exactly.
 impl fmt::Display for Const {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Unsigned(x) => write!(f, "U96({})", x),
-            Self::Signed(x) => write!(f, "I96({})", x),
+            Self::Unsigned(x) => write!(f, "U96:{:#x}", x),
+            Self::Signed { value, bits } => write!(f, "I{}:{}", bits, value),
+            Self::Fixed { frac, .. } => write!(f, "Q{}:{}", frac, self.to_decimal()),
+        }
+    }
+}
+
+/// Parses a literal, as in `255`, `0xFF_FF`, `0b1010`, `0o777`, `-0x10` or
+/// `1.5q8`, or the canonical form written by `Display`, such as `I8:-0x80`.
+/// Digits may be separated by `_`. Literals with a sign are signed 96 bit
+/// values and those without are unsigned, unless they end with `q` and a
+/// number of fraction bits, which makes them fixed point numbers. Fixed
+/// point fractions are decimal and round to the nearest unit.
+impl FromStr for Const {
+    type Err = ParseConstError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ty, mut literal) = match s.find(':') {
+            Some(i) => (Some(&s[..i]), &s[i + 1..]),
+            None => (None, s),
+        };
+        let frac = match ty {
+            Some(ty) => ty.strip_prefix('Q'),
+            None => literal.find('q').map(|i| {
+                let frac = &literal[i + 1..];
+                literal = &literal[..i];
+                frac
+            }),
+        };
+        let (sign, digits) = match literal.as_bytes().first() {
+            Some(b'-') => (Some(true), &literal[1..]),
+            Some(b'+') => (Some(false), &literal[1..]),
+            _ => (None, literal),
+        };
+        if let Some(frac) = frac {
+            let bits = match frac.parse() {
+                Ok(bits) if (1..=Self::MAX_FRAC).contains(&bits) => bits,
+                _ => return Err(ParseConstError::BadFraction(frac.to_owned())),
+            };
+            return parse_fixed(digits, bits, sign == Some(true));
+        }
+        let magnitude = parse_digits(digits)?;
+        let bits = match ty {
+            Some("U96") => None,
+            Some(ty) => match ty.strip_prefix('I').map(str::parse::<u8>) {
+                Some(Ok(bits)) if (1..=128).contains(&bits) => Some(bits),
+                _ => return Err(ParseConstError::BadType(ty.to_owned())),
+            },
+            None if sign.is_some() => Some(Self::MAX_BITS),
+            None => None,
+        };
+        match bits {
+            None if (sign == Some(true) && magnitude > 0) || magnitude > Self::MAX_UNSIGNED => {
+                Err(ParseConstError::OutOfRange("U96".to_owned()))
+            }
+            None => Ok(Self::Unsigned(magnitude)),
+            Some(bits) => {
+                let out_of_range = ParseConstError::OutOfRange(format!("I{}", bits));
+                let value = match sign {
+                    Some(true) if magnitude <= 1 << 127 => (magnitude as i128).wrapping_neg(),
+                    Some(true) => return Err(out_of_range),
+                    _ if magnitude <= i128::MAX as u128 => magnitude as i128,
+                    _ => return Err(out_of_range),
+                };
+                if value < Self::min_signed(bits) || value > Self::max_signed(bits) {
+                    return Err(out_of_range);
+                }
+                Ok(Self::Signed { value, bits })
+            }
+        }
+    }
+}
+
+/// Parses unsigned digits with an optional `0x`, `0b` or `0o` prefix,
+/// separated by `_`.
+fn parse_digits(s: &str) -> Result<u128, ParseConstError> {
+    match s.get(..2) {
+        Some("0x") | Some("0X") => parse_radix(&s[2..], 16),
+        Some("0b") | Some("0B") => parse_radix(&s[2..], 2),
+        Some("0o") | Some("0O") => parse_radix(&s[2..], 8),
+        _ => parse_radix(s, 10),
+    }
+}
+
+/// Parses digits in base `radix`, separated by `_`.
+fn parse_radix(digits: &str, radix: u32) -> Result<u128, ParseConstError> {
+    if digits.is_empty() {
+        return Err(ParseConstError::NoDigits);
+    }
+    if digits.starts_with('_') || digits.ends_with('_') {
+        return Err(ParseConstError::BadSeparator);
+    }
+    let mut value: u128 = 0;
+    for c in digits.chars().filter(|c| *c != '_') {
+        let d = c
+            .to_digit(radix)
+            .ok_or(ParseConstError::BadDigit(c, radix))?;
+        value = value
+            .checked_mul(radix as u128)
+            .and_then(|v| v.checked_add(d as u128))
+            .ok_or_else(|| ParseConstError::OutOfRange("U96".to_owned()))?;
+    }
+    Ok(value)
+}
+
+/// Parses a decimal with an optional fraction, such as `1.5`, as a fixed
+/// point number of `frac` fraction bits.
+fn parse_fixed(digits: &str, frac: u8, negative: bool) -> Result<Const, ParseConstError> {
+    let (int, fraction) = match digits.find('.') {
+        Some(i) => (&digits[..i], &digits[i + 1..]),
+        None => (digits, "0"),
+    };
+    let int = parse_radix(int, 10)?;
+    if fraction.is_empty() {
+        return Err(ParseConstError::NoDigits);
+    }
+    if fraction.starts_with('_') || fraction.ends_with('_') {
+        return Err(ParseConstError::BadSeparator);
+    }
+    let mut decimals = Vec::new();
+    for c in fraction.chars().filter(|c| *c != '_') {
+        decimals.push(c.to_digit(10).ok_or(ParseConstError::BadDigit(c, 10))?);
+    }
+    // Doubling the decimal fraction carries its next binary digit out. One
+    // more than is kept is taken, to round with.
+    let mut units: u128 = 0;
+    for _ in 0..=frac {
+        let mut carry = 0;
+        for d in decimals.iter_mut().rev() {
+            let x = *d * 2 + carry;
+            *d = x % 10;
+            carry = x / 10;
         }
+        units = (units << 1) | carry as u128;
+    }
+    let units = (units >> 1) + (units & 1);
+    let out_of_range = || ParseConstError::OutOfRange(format!("Q{}", frac));
+    let m = int
+        .checked_mul(1 << frac)
+        .and_then(|m| m.checked_add(units))
+        .ok_or_else(out_of_range)?;
+    let limit = Const::min_signed(Const::MAX_BITS).unsigned_abs() - !negative as u128;
+    if m > limit {
+        return Err(out_of_range());
     }
+    let value = if negative { -(m as i128) } else { m as i128 };
+    Ok(Const::Fixed { value, frac })
 }
 
+#[cfg(feature = "serde")]
+serde_str!(Const);
+
 impl From<u8> for Const {
     fn from(x: u8) -> Self {
         Self::Unsigned(x as u128)
@@ -85,31 +1118,31 @@ impl From<u128> for Const {
 
 impl From<i8> for Const {
     fn from(x: i8) -> Self {
-        Self::Signed(x as i128)
+        Self::signed(x as i128, Self::MAX_BITS)
     }
 }
 
 impl From<i16> for Const {
     fn from(x: i16) -> Self {
-        Self::Signed(x as i128)
+        Self::signed(x as i128, Self::MAX_BITS)
     }
 }
 
 impl From<i32> for Const {
     fn from(x: i32) -> Self {
-        Self::Signed(x as i128)
+        Self::signed(x as i128, Self::MAX_BITS)
     }
 }
 
 impl From<i64> for Const {
     fn from(x: i64) -> Self {
-        Self::Signed(x as i128)
+        Self::signed(x as i128, Self::MAX_BITS)
     }
 }
 
 impl From<i128> for Const {
     fn from(x: i128) -> Self {
-        Self::Signed(x)
+        Self::signed(x, Self::MAX_BITS)
     }
 }
 
@@ -117,10 +1150,7 @@ impl Add for Const {
     type Output = Self;
 
     fn add(self, rhs: Self) -> Self {
-        match self {
-            Self::Unsigned(x) => Self::Unsigned(x.saturating_add(rhs.as_u128())),
-            Self::Signed(x) => Self::Signed(x.saturating_add(rhs.as_i128())),
-        }
+        self.saturating_add(rhs)
     }
 }
 
@@ -128,10 +1158,7 @@ impl Sub for Const {
     type Output = Self;
 
     fn sub(self, rhs: Self) -> Self {
-        match self {
-            Self::Unsigned(x) => Self::Unsigned(x.saturating_sub(rhs.as_u128())),
-            Self::Signed(x) => Self::Signed(x.saturating_sub(rhs.as_i128())),
-        }
+        self.saturating_sub(rhs)
     }
 }
 
@@ -139,9 +1166,17 @@ impl Mul for Const {
     type Output = Self;
 
     fn mul(self, rhs: Self) -> Self {
-        match self {
-            Self::Unsigned(x) => Self::Unsigned(x * rhs.as_u128()),
-            Self::Signed(x) => Self::Signed(x * rhs.as_i128()),
+        self.saturating_mul(rhs)
+    }
+}
+
+/// Orders the numbers represented, as `cmp_value` does. Equal numbers of
+/// different types, such as `1` and `I8:1`, are not equal and so not ordered.
+impl PartialOrd for Const {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match self.cmp_value(*other) {
+            Ordering::Equal if self != other => None,
+            o => Some(o),
         }
     }
 }
@@ -150,10 +1185,7 @@ impl Div for Const {
     type Output = Self;
 
     fn div(self, rhs: Self) -> Self {
-        match self {
-            Self::Unsigned(x) => Self::Unsigned(x / rhs.as_u128()),
-            Self::Signed(x) => Self::Signed(x / rhs.as_i128()),
-        }
+        self.saturating_div(rhs)
     }
 }
 
@@ -161,10 +1193,7 @@ impl Rem for Const {
     type Output = Self;
 
     fn rem(self, rhs: Self) -> Self {
-        match self {
-            Self::Unsigned(x) => Self::Unsigned(x % rhs.as_u128()),
-            Self::Signed(x) => Self::Signed(x % rhs.as_i128()),
-        }
+        self.saturating_rem(rhs)
     }
 }
 
@@ -172,10 +1201,7 @@ impl Neg for Const {
     type Output = Self;
 
     fn neg(self) -> Self {
-        match self {
-            Self::Unsigned(x) => Self::Signed(-(x as i128)),
-            Self::Signed(x) => Self::Signed(-x),
-        }
+        self.saturating_neg()
     }
 }
 
@@ -185,7 +1211,14 @@ impl Shr<u8> for Const {
     fn shr(self, rhs: u8) -> Self {
         match self {
             Self::Unsigned(x) => Self::Unsigned(x >> rhs),
-            Self::Signed(x) => Self::Signed(x >> rhs),
+            Self::Signed { value, bits } => Self::Signed {
+                value: value >> rhs,
+                bits,
+            },
+            Self::Fixed { value, frac } => Self::Fixed {
+                value: value >> rhs,
+                frac,
+            },
         }
     }
 }
@@ -196,7 +1229,8 @@ impl Shl<u8> for Const {
     fn shl(self, rhs: u8) -> Self {
         match self {
             Self::Unsigned(x) => Self::Unsigned(x << rhs),
-            Self::Signed(x) => Self::Signed(x << rhs),
+            Self::Signed { value, bits } => Self::signed(value << rhs, bits),
+            Self::Fixed { value, frac } => Self::fixed(value << rhs, frac),
         }
     }
 }
@@ -207,7 +1241,8 @@ impl BitAnd for Const {
     fn bitand(self, rhs: Self) -> Self {
         match self {
             Self::Unsigned(x) => Self::Unsigned(x & rhs.as_u128()),
-            Self::Signed(x) => Self::Signed(x & rhs.as_i128()),
+            Self::Signed { value, bits } => Self::signed(value & rhs.as_i128(), bits),
+            Self::Fixed { value, frac } => Self::fixed(value & rhs.as_i128(), frac),
         }
     }
 }
@@ -218,7 +1253,8 @@ impl BitOr for Const {
     fn bitor(self, rhs: Self) -> Self {
         match self {
             Self::Unsigned(x) => Self::Unsigned(x | rhs.as_u128()),
-            Self::Signed(x) => Self::Signed(x | rhs.as_i128()),
+            Self::Signed { value, bits } => Self::signed(value | rhs.as_i128(), bits),
+            Self::Fixed { value, frac } => Self::fixed(value | rhs.as_i128(), frac),
         }
     }
 }
@@ -229,7 +1265,326 @@ impl BitXor for Const {
     fn bitxor(self, rhs: Self) -> Self {
         match self {
             Self::Unsigned(x) => Self::Unsigned(x ^ rhs.as_u128()),
-            Self::Signed(x) => Self::Signed(x ^ rhs.as_i128()),
+            Self::Signed { value, bits } => Self::signed(value ^ rhs.as_i128(), bits),
+            Self::Fixed { value, frac } => Self::fixed(value ^ rhs.as_i128(), frac),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTHS: [u8; 6] = [1, 8, 63, 64, 95, 96];
+
+    #[test]
+    fn signed_range_and_wrapping() {
+        for &bits in WIDTHS.iter() {
+            let (min, max) = (Const::min_signed(bits), Const::max_signed(bits));
+            assert_eq!(min, -(1 << (bits - 1)), "bits {}", bits);
+            assert_eq!(max, (1 << (bits - 1)) - 1, "bits {}", bits);
+            assert_eq!(Const::signed(max + 1, bits), Const::signed(min, bits));
+            assert_eq!(Const::signed(min - 1, bits), Const::signed(max, bits));
+            assert_eq!(Const::signed(-1, bits).as_i128(), -1);
+        }
+    }
+
+    #[test]
+    fn sign_extension() {
+        for &bits in WIDTHS.iter() {
+            let (min, max) = (Const::min_signed(bits), Const::max_signed(bits));
+            let sign = 1u128 << (bits - 1);
+            assert_eq!(sign_extend(sign, bits), min, "bits {}", bits);
+            assert_eq!(sign_extend(sign - 1, bits), max, "bits {}", bits);
+            assert_eq!(sign_extend(u128::MAX, bits), -1, "bits {}", bits);
+            assert_eq!(
+                Const::Unsigned(sign).sign_extend(bits),
+                Const::signed(min, Const::MAX_BITS)
+            );
+            // Widening keeps the value and narrowing back gives it again.
+            let widened = Const::signed(min, bits).with_bits(Const::MAX_BITS);
+            assert_eq!(widened.as_i128(), min);
+            assert_eq!(widened.with_bits(bits), Const::signed(min, bits));
+        }
+    }
+
+    #[test]
+    fn negation() {
+        for &bits in WIDTHS.iter() {
+            let (min, max) = (Const::min_signed(bits), Const::max_signed(bits));
+            let (min_c, max_c) = (Const::signed(min, bits), Const::signed(max, bits));
+            assert_eq!(max_c.checked_neg(), Some(Const::signed(-max, bits)));
+            assert_eq!(min_c.checked_neg(), None, "bits {}", bits);
+            assert_eq!(min_c.wrapping_neg(), min_c, "bits {}", bits);
+            assert_eq!(min_c.saturating_neg(), max_c, "bits {}", bits);
+            assert_eq!(
+                Const::signed(0, bits).checked_neg(),
+                Some(Const::signed(0, bits))
+            );
+        }
+    }
+
+    #[test]
+    fn shifts() {
+        for &bits in WIDTHS.iter() {
+            let min = Const::signed(Const::min_signed(bits), bits);
+            // Shifting into the sign bit makes the value negative, and out of
+            // the width leaves zero.
+            assert_eq!(Const::signed(1, bits) << (bits - 1), min, "bits {}", bits);
+            assert_eq!(Const::signed(1, bits) << bits, Const::signed(0, bits));
+            // Right shifts are arithmetic.
+            assert_eq!(min >> (bits - 1), Const::signed(-1, bits), "bits {}", bits);
+            assert_eq!(Const::signed(-1, bits) >> bits, Const::signed(-1, bits));
+            if bits > 1 {
+                let max = Const::signed(Const::max_signed(bits), bits);
+                assert_eq!(max >> (bits - 2), Const::signed(1, bits), "bits {}", bits);
+            }
+        }
+    }
+
+    #[test]
+    fn fixed_rem_by_divisor_below_dividend_units() {
+        // 0.25q8 is zero units of 2^-1.
+        let (a, b) = (Const::fixed(2, 1), Const::fixed(64, 8));
+        assert!(a.divides_by_zero(b, true));
+        assert!(!a.divides_by_zero(b, false));
+        assert_eq!(a.checked_rem(b), None);
+        assert_eq!(a.wrapping_rem(b), Const::fixed(0, 1));
+        assert_eq!(a.saturating_rem(b), Const::fixed(0, 1));
+        assert_eq!(a.checked_div(b), Some(Const::fixed(8, 1)));
+        assert_eq!(
+            DivZeroPolicy::Ones.divide_by_zero(a, true, ArithMode::Checked),
+            Some(a)
+        );
+        let flags = Flags::rem(a, b);
+        assert!(flags.zero && flags.overflow);
+    }
+
+    #[test]
+    fn fixed_rem_by_aligned_divisor() {
+        // 1.5q8 % 0.5q2 is 0, and 1.75q8 % 0.5q2 is 0.25.
+        let b = Const::fixed(2, 2);
+        assert!(!Const::fixed(384, 8).divides_by_zero(b, true));
+        assert_eq!(
+            Const::fixed(384, 8).checked_rem(b),
+            Some(Const::fixed(0, 8))
+        );
+        assert_eq!(
+            Const::fixed(448, 8).checked_rem(b),
+            Some(Const::fixed(64, 8))
+        );
+    }
+
+    /// The zero, negative, carry and overflow flags, in that order.
+    fn bits(f: Flags) -> [bool; 4] {
+        [f.zero, f.negative, f.carry, f.overflow]
+    }
+
+    #[test]
+    fn unsigned_flags() {
+        let max = Const::Unsigned(Const::MAX_UNSIGNED);
+        let u = Const::Unsigned;
+        assert_eq!(bits(Flags::add(u(2), u(3))), [false, false, false, false]);
+        assert_eq!(bits(Flags::add(u(0), u(0))), [true, false, false, false]);
+        assert_eq!(bits(Flags::add(max, u(1))), [false, false, true, true]);
+        assert_eq!(bits(Flags::sub(u(3), u(3))), [true, false, false, false]);
+        assert_eq!(bits(Flags::sub(u(0), u(1))), [false, true, true, true]);
+        assert_eq!(bits(Flags::mul(u(0), max)), [true, false, false, false]);
+        assert_eq!(
+            bits(Flags::mul(u(1 << 48), u(1 << 48))),
+            [false, false, true, true]
+        );
+    }
+
+    #[test]
+    fn signed_flags() {
+        let i = |v| Const::signed(v, 8);
+        let (min, max) = (i(-128), i(127));
+        assert_eq!(bits(Flags::add(i(2), i(3))), [false, false, false, false]);
+        // -1 is all ones as a pattern, so adding 1 carries out of it.
+        assert_eq!(bits(Flags::add(i(-1), i(1))), [true, false, true, false]);
+        assert_eq!(bits(Flags::add(max, i(1))), [false, false, false, true]);
+        assert_eq!(bits(Flags::add(min, i(-1))), [false, true, true, true]);
+        assert_eq!(bits(Flags::sub(i(5), i(5))), [true, false, false, false]);
+        assert_eq!(bits(Flags::sub(i(0), i(1))), [false, true, true, false]);
+        assert_eq!(bits(Flags::sub(min, i(1))), [false, true, false, true]);
+        assert_eq!(bits(Flags::mul(i(-1), i(0))), [true, false, false, false]);
+        assert_eq!(bits(Flags::mul(i(-4), i(5))), [false, true, false, false]);
+        assert_eq!(bits(Flags::mul(i(64), i(2))), [false, false, true, true]);
+    }
+
+    #[test]
+    fn fixed_flags() {
+        // Units of 2^-8, so that 256 is 1.0.
+        let q = |v| Const::fixed(v, 8);
+        let (min, max) = (q(Const::min_signed(96)), q(Const::max_signed(96)));
+        assert_eq!(
+            bits(Flags::add(q(384), q(128))),
+            [false, false, false, false]
+        );
+        assert_eq!(
+            bits(Flags::add(q(256), q(-256))),
+            [true, false, true, false]
+        );
+        assert_eq!(bits(Flags::add(max, q(1))), [false, false, false, true]);
+        assert_eq!(
+            bits(Flags::sub(q(128), q(128))),
+            [true, false, false, false]
+        );
+        assert_eq!(bits(Flags::sub(q(128), q(384))), [false, true, true, false]);
+        assert_eq!(bits(Flags::sub(min, q(1))), [false, true, false, true]);
+        assert_eq!(
+            bits(Flags::mul(q(128), q(128))),
+            [false, false, false, false]
+        );
+        // 0.0625 * 0.0625 is below the smallest unit, but the flags follow
+        // the exact product.
+        assert_eq!(bits(Flags::mul(q(16), q(16))), [false, false, false, false]);
+        assert_eq!(bits(Flags::mul(q(0), q(-256))), [true, false, false, false]);
+        assert_eq!(bits(Flags::mul(max, q(512))), [false, false, true, true]);
+    }
+
+    #[test]
+    fn canonical_form_round_trip() {
+        let mut consts = vec![
+            Const::Unsigned(0),
+            Const::Unsigned(1),
+            Const::Unsigned(Const::MAX_UNSIGNED),
+        ];
+        for &bits in WIDTHS.iter().chain([128].iter()) {
+            for &v in [Const::min_signed(bits), -1, 0, Const::max_signed(bits)].iter() {
+                consts.push(Const::signed(v, bits));
+            }
+        }
+        for &frac in [1, 8, Const::MAX_FRAC].iter() {
+            for &v in [Const::min_signed(96), -1, 0, 1, 3, Const::max_signed(96)].iter() {
+                consts.push(Const::fixed(v, frac));
+            }
+        }
+        for c in consts {
+            let s = c.to_string();
+            assert_eq!(s.parse::<Const>(), Ok(c), "{}", s);
+            assert_eq!(s.parse::<Const>().unwrap().to_string(), s);
+        }
+        for &s in [
+            "U96:0xff",
+            "I1:-1",
+            "I8:-128",
+            "I8:127",
+            "I128:-170141183460469231731687303715884105728",
+            "Q1:-0.5",
+            "Q8:1.5",
+            "Q95:-1",
+        ]
+        .iter()
+        {
+            assert_eq!(s.parse::<Const>().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn canonical_form_out_of_range() {
+        for &s in [
+            "U96:0x1000000000000000000000000",
+            "I8:128",
+            "I8:-129",
+            "Q95:1",
+        ]
+        .iter()
+        {
+            assert!(
+                matches!(s.parse::<Const>(), Err(ParseConstError::OutOfRange(_))),
+                "{}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn literals() {
+        for &(literal, canonical) in [
+            ("255", "U96:0xff"),
+            ("0xFF_FF", "U96:0xffff"),
+            ("0b1010", "U96:0xa"),
+            ("0o777", "U96:0x1ff"),
+            (
+                "0xffff_ffff_ffff_ffff_ffff_ffff",
+                "U96:0xffffffffffffffffffffffff",
+            ),
+            ("-0x10", "I96:-16"),
+            ("+7", "I96:7"),
+            (
+                "-39614081257132168796771975168",
+                "I96:-39614081257132168796771975168",
+            ),
+            ("1.5q8", "Q8:1.5"),
+            ("-0.5q1", "Q1:-0.5"),
+        ]
+        .iter()
+        {
+            assert_eq!(literal.parse::<Const>().unwrap().to_string(), canonical);
+        }
+        for (literal, err) in [
+            ("0x", ParseConstError::NoDigits),
+            ("0b102", ParseConstError::BadDigit('2', 2)),
+            ("_1", ParseConstError::BadSeparator),
+            ("1_", ParseConstError::BadSeparator),
+            (
+                "0x1_0000_0000_0000_0000_0000_0000",
+                ParseConstError::OutOfRange("U96".into()),
+            ),
+        ]
+        .iter()
+        {
+            assert_eq!(literal.parse::<Const>().as_ref(), Err(err), "{}", literal);
+        }
+    }
+
+    #[test]
+    fn order_across_kinds() {
+        use std::cmp::Ordering::*;
+        let (u, i, q) = (Const::Unsigned, Const::signed, Const::fixed);
+        let max = u(Const::MAX_UNSIGNED);
+        // In increasing order of value.
+        let ordered = [
+            i(Const::min_signed(128), 128),
+            i(Const::min_signed(96), 96),
+            i(-128, 8),
+            q(-384, 8),
+            i(-1, 1),
+            q(-1, 95),
+            u(0),
+            q(1, 95),
+            q(128, 8),
+            i(1, 8),
+            q(3, 1),
+            u(2),
+            i(127, 8),
+            max,
+            i(Const::max_signed(128), 128),
+        ];
+        for (a, x) in ordered.iter().enumerate() {
+            for (b, y) in ordered.iter().enumerate() {
+                assert_eq!(x.cmp_value(*y), a.cmp(&b), "{} and {}", x, y);
+                assert_eq!(x.partial_cmp(y), Some(a.cmp(&b)), "{} and {}", x, y);
+            }
+        }
+        // Equal values of different kinds are unordered, as they aren't `==`.
+        for &(x, y) in [
+            (u(0), i(0, 8)),
+            (i(1, 8), i(1, 96)),
+            (q(256, 8), u(1)),
+            (q(2, 1), q(256, 8)),
+        ]
+        .iter()
+        {
+            assert_eq!(x.cmp_value(y), Equal, "{} and {}", x, y);
+            assert_eq!(x.partial_cmp(&y), None, "{} and {}", x, y);
         }
+        // The pattern comparisons ignore the kinds.
+        assert_eq!(i(-1, 8).cmp_unsigned(max), Equal);
+        assert_eq!(i(-1, 8).cmp_unsigned(u(0)), Greater);
+        assert_eq!(max.cmp_signed(u(0)), Less);
+        assert_eq!(q(-256, 8).cmp_signed(i(-1, 96)), Less);
     }
 }
@@ -0,0 +1,91 @@
+use crate::base::arith::Const;
+use crate::base::FieldSelector;
+use std::fmt;
+use thiserror;
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum AtomError {
+    #[error("write to field {0:?} would clobber the atom header")]
+    HeaderWrite(FieldSelector),
+}
+
+/// A 96-bit MFM atom: a 16-bit type, 9 ECC bits and 71 bits of user state.
+///
+/// Bits above 96 are always zero.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Atom(u128);
+
+impl Atom {
+    pub const BITS: u8 = 96;
+    const MASK: u128 = (1 << Self::BITS) - 1;
+
+    pub fn new(type_num: u16) -> Self {
+        Self(FieldSelector::TYPE.insert(0, type_num as u128))
+    }
+
+    pub fn from_bits(x: u128) -> Self {
+        Self(x & Self::MASK)
+    }
+
+    pub fn bits(&self) -> u128 {
+        self.0
+    }
+
+    pub fn get_type(&self) -> u16 {
+        FieldSelector::TYPE.extract(self.0) as u16
+    }
+
+    /// Changes the type of this atom, leaving the rest of the header as is.
+    pub fn set_type(&mut self, type_num: u16) {
+        self.0 = FieldSelector::TYPE.insert(self.0, type_num as u128);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.get_type() == 0
+    }
+
+    pub fn ecc(&self) -> u16 {
+        FieldSelector::ECC.extract(self.0) as u16
+    }
+
+    /// The user state bits of the atom.
+    pub fn payload(&self) -> u128 {
+        FieldSelector::DATA.extract(self.0)
+    }
+
+    pub fn get_field(&self, f: FieldSelector) -> Const {
+        Const::Unsigned(f.extract(self.0))
+    }
+
+    /// Writes a field of the user state. Fails if `f` reaches into the header.
+    pub fn set_field(&mut self, f: FieldSelector, v: Const) -> Result<(), AtomError> {
+        if f.mask() & FieldSelector::HEADER.mask() != 0 {
+            return Err(AtomError::HeaderWrite(f));
+        }
+        self.set_header_field(f, v);
+        Ok(())
+    }
+
+    /// Writes any field, including the header.
+    pub fn set_header_field(&mut self, f: FieldSelector, v: Const) {
+        self.0 = f.insert(self.0, v.as_u128()) & Self::MASK;
+    }
+}
+
+impl From<Const> for Atom {
+    fn from(x: Const) -> Self {
+        Self::from_bits(x.as_u128())
+    }
+}
+
+impl From<Atom> for Const {
+    fn from(x: Atom) -> Self {
+        Const::Unsigned(x.0)
+    }
+}
+
+impl fmt::Display for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "%{}({:#x})", self.get_type(), self.payload())
+    }
+}
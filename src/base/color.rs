@@ -1,3 +1,4 @@
+use std::fmt;
 use std::num::ParseIntError;
 use std::str::FromStr;
 use thiserror;
@@ -19,6 +20,26 @@ impl Color {
   pub fn bits(&self) -> u32 {
     return self.0;
   }
+
+  /// Writes `c` on a terminal background of this color. Fully transparent
+  /// colors leave the background alone.
+  pub fn write_background<W: fmt::Write>(&self, w: &mut W, c: char) -> fmt::Result {
+    if self.0 & 0xff == 0 {
+      return w.write_char(c);
+    }
+    let [r, g, b, _] = self.0.to_be_bytes();
+    write!(w, "\x1b[48;2;{};{};{}m{}\x1b[0m", r, g, b, c)
+  }
+
+  /// Writes `c` on a terminal in this color. Fully transparent colors leave
+  /// the foreground alone.
+  pub fn write_foreground<W: fmt::Write>(&self, w: &mut W, c: char) -> fmt::Result {
+    if self.0 & 0xff == 0 {
+      return w.write_char(c);
+    }
+    let [r, g, b, _] = self.0.to_be_bytes();
+    write!(w, "\x1b[38;2;{};{};{}m{}\x1b[0m", r, g, b, c)
+  }
 }
 
 #[derive(Debug, Clone, thiserror::Error)]
@@ -29,13 +50,16 @@ pub enum ParseColorError {
   BadLength(usize),
 }
 
+/// Parses `rrggbbaa`, `rrggbb` or `rgb` hex colors, optionally prefixed with
+/// `#`. Colors without alpha are opaque.
 impl FromStr for Color {
   type Err = ParseColorError;
 
   fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let s = s.strip_prefix('#').unwrap_or(s);
     match s.len() {
-      9 => Ok(u32::from_str_radix(s, 16)?.into()),
-      6 => Ok((u32::from_str_radix(s, 16)? << 8).into()),
+      8 => Ok(u32::from_str_radix(s, 16)?.into()),
+      6 => Ok((u32::from_str_radix(s, 16)? << 8 | 0xff).into()),
       3 => {
         let v = u32::from_str_radix(s, 16)?;
         // abc => aabbccff
@@ -45,3 +69,12 @@ impl FromStr for Color {
     }
   }
 }
+
+impl fmt::Display for Color {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "#{:08x}", self.0)
+  }
+}
+
+#[cfg(feature = "serde")]
+serde_str!(Color);
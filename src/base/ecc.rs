@@ -0,0 +1,149 @@
+//! Single error correcting, double error detecting (SECDED) Hamming code over
+//! the type and state bits of an atom.
+//!
+//! The 87 protected bits take the non-power-of-two positions of a Hamming
+//! codeword. The 7 check bits and an overall parity bit are stored in the low
+//! 8 bits of the ECC field; the remaining ECC bit is reserved.
+
+use crate::base::atom::Atom;
+use crate::base::FieldSelector;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Check {
+    Valid,
+    /// A single bit error was found; `atom` is the repaired atom and `bit` the
+    /// index of the flipped atom bit.
+    Corrected {
+        atom: Atom,
+        bit: u8,
+    },
+    Uncorrectable,
+}
+
+const CHECK_BITS: u32 = 7;
+const PARITY_BIT: u32 = 7;
+
+/// Atom bit index of each protected bit, in codeword order.
+fn protected_bits() -> impl Iterator<Item = u8> {
+    let f = FieldSelector::DATA;
+    let t = FieldSelector::TYPE;
+    (f.offset..f.offset + f.length).chain(t.offset..t.offset + t.length)
+}
+
+/// Codeword positions of the protected bits: the integers > 2 which are not
+/// powers of two.
+fn positions() -> impl Iterator<Item = u32> {
+    (3u32..).filter(|p| !p.is_power_of_two())
+}
+
+fn syndrome(x: u128) -> (u32, u32) {
+    let mut s = 0;
+    let mut parity = 0;
+    for (b, p) in protected_bits().zip(positions()) {
+        if x >> b & 1 == 1 {
+            s ^= p;
+            parity ^= 1;
+        }
+    }
+    (s, parity)
+}
+
+fn code(s: u32, parity: u32) -> u128 {
+    let parity = parity ^ (s.count_ones() & 1);
+    (s | parity << PARITY_BIT) as u128
+}
+
+/// Returns `atom` with its ECC bits computed from its type and state.
+pub fn encode(atom: Atom) -> Atom {
+    let (s, parity) = syndrome(atom.bits());
+    Atom::from_bits(FieldSelector::ECC.insert(atom.bits(), code(s, parity)))
+}
+
+pub fn check(atom: Atom) -> Check {
+    let stored = FieldSelector::ECC.extract(atom.bits()) as u32;
+    let stored_checks = stored & ((1 << CHECK_BITS) - 1);
+    let (s, parity) = syndrome(atom.bits());
+    let s = s ^ stored_checks;
+    let parity = parity ^ (stored_checks.count_ones() & 1) ^ (stored >> PARITY_BIT & 1);
+    match (s, parity) {
+        (0, 0) => Check::Valid,
+        (_, 0) => Check::Uncorrectable,
+        (s, _) => {
+            let bit = if s == 0 {
+                FieldSelector::ECC.offset as u32 + PARITY_BIT
+            } else if s.is_power_of_two() {
+                FieldSelector::ECC.offset as u32 + s.trailing_zeros()
+            } else {
+                match protected_bits().zip(positions()).find(|(_, p)| *p == s) {
+                    Some((b, _)) => b as u32,
+                    None => return Check::Uncorrectable,
+                }
+            };
+            Check::Corrected {
+                atom: Atom::from_bits(atom.bits() ^ 1 << bit),
+                bit: bit as u8,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Atoms with a few patterns of type and state bits.
+    fn atoms() -> Vec<Atom> {
+        let data = FieldSelector::DATA;
+        [0, 1, 0x5a5a, 0xffff]
+            .iter()
+            .zip([0, 1, 0x1234_5678_9abc, u128::MAX].iter())
+            .map(|(t, d)| {
+                let mut atom = Atom::new(*t);
+                atom.set_header_field(data, (*d).into());
+                encode(atom)
+            })
+            .collect()
+    }
+
+    /// Every bit an error may be corrected in: the protected bits and the
+    /// check and parity bits, but not the reserved ECC bit.
+    fn covered_bits() -> impl Iterator<Item = u8> {
+        let e = FieldSelector::ECC;
+        protected_bits().chain(e.offset..e.offset + PARITY_BIT as u8 + 1)
+    }
+
+    #[test]
+    fn encode_check_round_trip() {
+        for atom in atoms() {
+            assert_eq!(check(atom), Check::Valid);
+            assert_eq!(encode(atom), atom);
+        }
+    }
+
+    #[test]
+    fn correct_single_bit_flips() {
+        for atom in atoms() {
+            for bit in covered_bits() {
+                let flipped = Atom::from_bits(atom.bits() ^ 1 << bit);
+                assert_eq!(
+                    check(flipped),
+                    Check::Corrected { atom, bit },
+                    "bit {}",
+                    bit
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn detect_double_bit_flips() {
+        for atom in atoms() {
+            for a in covered_bits() {
+                for b in covered_bits().filter(|b| *b > a) {
+                    let flipped = Atom::from_bits(atom.bits() ^ 1 << a ^ 1 << b);
+                    assert_eq!(check(flipped), Check::Uncorrectable, "bits {}, {}", a, b);
+                }
+            }
+        }
+    }
+}
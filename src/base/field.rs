@@ -0,0 +1,82 @@
+use crate::base::FieldSelector;
+use std::collections::HashMap;
+use thiserror;
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum FieldError {
+    #[error("duplicate field: {0}")]
+    Duplicate(String),
+    #[error("field has zero length: {0}")]
+    Empty(String),
+    #[error("field {0} overlaps the atom header")]
+    OverlapsHeader(String),
+    #[error("field {0} overlaps field {1}")]
+    Overlap(String, String),
+}
+
+/// Named fields of an element, including the builtin `type`, `header` and
+/// `data` fields. User fields must lie inside `data` and may not overlap.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FieldMap {
+    fields: HashMap<String, FieldSelector>,
+}
+
+impl FieldMap {
+    const BUILTINS: [(&'static str, FieldSelector); 3] = [
+        ("type", FieldSelector::TYPE),
+        ("header", FieldSelector::HEADER),
+        ("data", FieldSelector::DATA),
+    ];
+
+    pub fn new() -> Self {
+        let mut fields = HashMap::new();
+        for (name, f) in Self::BUILTINS.iter() {
+            fields.insert(name.to_string(), *f);
+        }
+        Self { fields }
+    }
+
+    pub fn is_builtin(name: &str) -> bool {
+        Self::BUILTINS.iter().any(|(i, _)| *i == name)
+    }
+
+    pub fn insert(&mut self, name: &str, f: FieldSelector) -> Result<(), FieldError> {
+        if self.fields.contains_key(name) {
+            return Err(FieldError::Duplicate(name.to_owned()));
+        }
+        if f.is_empty() {
+            return Err(FieldError::Empty(name.to_owned()));
+        }
+        if f.mask() & !FieldSelector::DATA.mask() != 0
+            || f.offset as u16 + f.length as u16 > FieldSelector::DATA.length as u16
+        {
+            return Err(FieldError::OverlapsHeader(name.to_owned()));
+        }
+        for (i, g) in self.user_fields() {
+            if f.mask() & g.mask() != 0 {
+                return Err(FieldError::Overlap(name.to_owned(), i.to_owned()));
+            }
+        }
+        self.fields.insert(name.to_owned(), f);
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<FieldSelector> {
+        self.fields.get(name).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, FieldSelector)> {
+        self.fields.iter().map(|(i, f)| (i.as_str(), *f))
+    }
+
+    pub fn user_fields(&self) -> impl Iterator<Item = (&str, FieldSelector)> {
+        self.iter().filter(|(i, _)| !Self::is_builtin(i))
+    }
+}
+
+impl Default for FieldMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,13 +1,131 @@
+/// Implements `Serialize` and `Deserialize` for a type through its `Display`
+/// and `FromStr` forms.
+#[cfg(feature = "serde")]
+macro_rules! serde_str {
+    ($t:ty) => {
+        impl serde::Serialize for $t {
+            fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+                s.collect_str(self)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $t {
+            fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+                let s = String::deserialize(d)?;
+                s.parse()
+                    .map_err(|_| serde::de::Error::custom(format!("bad {}: {}", stringify!($t), s)))
+            }
+        }
+    };
+}
+
 pub mod arith;
+pub mod atom;
 pub mod color;
+pub mod ecc;
+pub mod field;
 
 use bitflags::bitflags;
+use lazy_static::lazy_static;
+use rand::Rng;
 use std::fmt;
 use std::str::FromStr;
 
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
 pub struct SiteNumber(pub u8);
 
+/// Offsets `(x, y)` of each site from the center of the event window, with `y`
+/// increasing southward.
+const SITE_COORDS: [(i8, i8); 41] = [
+    (0, 0),
+    (-1, 0),
+    (0, -1),
+    (0, 1),
+    (1, 0),
+    (-1, -1),
+    (-1, 1),
+    (1, -1),
+    (1, 1),
+    (-2, 0),
+    (0, -2),
+    (0, 2),
+    (2, 0),
+    (-2, -1),
+    (-2, 1),
+    (-1, -2),
+    (-1, 2),
+    (1, -2),
+    (1, 2),
+    (2, -1),
+    (2, 1),
+    (-3, 0),
+    (0, -3),
+    (0, 3),
+    (3, 0),
+    (-2, -2),
+    (-2, 2),
+    (2, -2),
+    (2, 2),
+    (-3, -1),
+    (-3, 1),
+    (-1, -3),
+    (-1, 3),
+    (1, -3),
+    (1, 3),
+    (3, -1),
+    (3, 1),
+    (-4, 0),
+    (0, -4),
+    (0, 4),
+    (4, 0),
+];
+
+lazy_static! {
+    /// Site numbers mapped through each of the 8 symmetries, indexed by the
+    /// symmetry's bit position.
+    static ref SYMMETRY_MAP: [[u8; 41]; 8] = {
+        let mut m = [[0u8; 41]; 8];
+        for (i, row) in m.iter_mut().enumerate() {
+            let s = Symmetries::from(1u8 << i);
+            for (j, x) in row.iter_mut().enumerate() {
+                let (dx, dy) = s.transform(SITE_COORDS[j]);
+                *x = SiteNumber::from_coord(dx, dy).unwrap().0;
+            }
+        }
+        m
+    };
+}
+
+impl SiteNumber {
+    /// The number of sites in an event window of radius 4.
+    pub const COUNT: usize = 41;
+
+    /// The bits a site number takes, as in a bond field.
+    pub const BITS: u8 = 6;
+
+    pub fn from_coord(x: i8, y: i8) -> Option<Self> {
+        SITE_COORDS
+            .iter()
+            .position(|c| *c == (x, y))
+            .map(|i| Self(i as u8))
+    }
+
+    pub fn coord(&self) -> Option<(i8, i8)> {
+        SITE_COORDS.get(self.0 as usize).copied()
+    }
+
+    /// The Manhattan distance of this site from the center.
+    pub fn radius(&self) -> Option<u8> {
+        self.coord().map(|(x, y)| (x.abs() + y.abs()) as u8)
+    }
+
+    /// Maps this site through the first symmetry in `s`.
+    pub fn map(&self, s: Symmetries) -> Option<Self> {
+        let i = s.bits().trailing_zeros().min(7) as usize;
+        SYMMETRY_MAP[i].get(self.0 as usize).map(|x| Self(*x))
+    }
+}
+
 impl fmt::Display for SiteNumber {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "#{}", self.0)
@@ -15,6 +133,7 @@ impl fmt::Display for SiteNumber {
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FieldSelector {
     pub offset: u8,
     pub length: u8,
@@ -25,6 +144,10 @@ impl FieldSelector {
         offset: 80,
         length: 16,
     };
+    pub const ECC: Self = Self {
+        offset: 71,
+        length: 9,
+    };
     pub const HEADER: Self = Self {
         offset: 71,
         length: 25,
@@ -37,6 +160,67 @@ impl FieldSelector {
     pub fn as_u16(&self) -> u16 {
         (self.offset as u16) | (self.length as u16) << 8
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Returns the bits covered by this field. Bits past 128 are dropped, so a
+    /// field may span the full width of the word.
+    pub fn mask(&self) -> u128 {
+        if self.is_empty() {
+            return 0;
+        }
+        let ones = u128::MAX >> (128 - self.length.min(128) as u32);
+        ones.checked_shl(self.offset as u32).unwrap_or(0)
+    }
+
+    /// The largest value the field holds.
+    pub fn max_value(&self) -> u128 {
+        if self.is_empty() {
+            return 0;
+        }
+        u128::MAX >> (128 - self.length.min(128) as u32)
+    }
+
+    /// The smallest value the field holds read as two's complement, as
+    /// `extract_signed` reads it.
+    pub fn min_signed(&self) -> i128 {
+        if self.is_empty() {
+            return 0;
+        }
+        i128::MIN >> (128 - self.length.min(128) as u32)
+    }
+
+    /// Whether `v` can be stored in the field without losing bits: up to
+    /// `max_value`, or down to `min_signed` if it is negative.
+    pub fn fits(&self, v: arith::Const) -> bool {
+        if v.is_negative() {
+            v.as_i128() >= self.min_signed()
+        } else {
+            v.as_u128() <= self.max_value()
+        }
+    }
+
+    pub fn extract(&self, x: u128) -> u128 {
+        (x & self.mask())
+            .checked_shr(self.offset as u32)
+            .unwrap_or(0)
+    }
+
+    /// Extracts the field as a two's complement value of `length` bits.
+    pub fn extract_signed(&self, x: u128) -> i128 {
+        if self.is_empty() {
+            return 0;
+        }
+        arith::sign_extend(self.extract(x), self.length.min(128))
+    }
+
+    /// Replaces the field in `x` with `v`, truncated to the field width.
+    pub fn insert(&self, x: u128, v: u128) -> u128 {
+        let v = v.checked_shl(self.offset as u32).unwrap_or(0);
+        (x & !self.mask()) | (v & self.mask())
+    }
 }
 
 impl From<u16> for FieldSelector {
@@ -55,15 +239,55 @@ bitflags! {
     const R180L = 0x4; // Flip_XY.
     const R270L = 0x8;
     const R000R = 0x10; // Flip_Y.
-    const R090R = 0x20; // Flip_X.
-    const R180R = 0x40;
+    const R090R = 0x20;
+    const R180R = 0x40; // Flip_X.
     const R270R = 0x80;
   }
 }
 
+impl Symmetries {
+    /// Transforms window coordinates by the first symmetry in this set. The
+    /// empty set is the identity.
+    pub fn transform(self, (x, y): (i8, i8)) -> (i8, i8) {
+        match self.bits().trailing_zeros() {
+            1 => (-y, x),
+            2 => (-x, -y),
+            3 => (y, -x),
+            4 => (x, -y),
+            5 => (y, x),
+            6 => (-x, y),
+            7 => (-y, -x),
+            _ => (x, y),
+        }
+    }
+
+    /// Iterates over the single symmetries in this set.
+    pub fn iter(self) -> impl Iterator<Item = Symmetries> {
+        (0..8)
+            .map(|i| Symmetries::from(1u8 << i))
+            .filter(move |s| self.contains(*s))
+    }
+
+    /// Picks one of the symmetries in this set uniformly at random, or
+    /// `R000L` if the set is empty.
+    pub fn choose<R: Rng + ?Sized>(self, rng: &mut R) -> Symmetries {
+        let n = self.bits().count_ones();
+        if n == 0 {
+            return Symmetries::R000L;
+        }
+        self.iter().nth(rng.gen_range(0..n) as usize).unwrap()
+    }
+}
+
+/// Parses a symmetry name, or several joined by `|` as they are displayed.
 impl FromStr for Symmetries {
     type Err = ();
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains('|') {
+            return s
+                .split('|')
+                .try_fold(Self::empty(), |acc, x| Ok(acc | x.trim().parse()?));
+        }
         match s {
             "NONE" => Ok(0.into()),
             "R000L" => Ok(Symmetries::R000L),
@@ -74,13 +298,105 @@ impl FromStr for Symmetries {
             "R090R" => Ok(Symmetries::R090R),
             "R180R" => Ok(Symmetries::R180R),
             "R270R" => Ok(Symmetries::R270R),
+            "R000" => Ok(Symmetries::R000L),
+            "R090" => Ok(Symmetries::R090L),
+            "R180" => Ok(Symmetries::R180L),
+            "R270" => Ok(Symmetries::R270L),
+            "FLIPX" => Ok(Symmetries::R180R),
+            "FLIPY" => Ok(Symmetries::R000R),
+            "FLIPXY" => Ok(Symmetries::R180L),
+            "ROTATIONS" => Ok(0xf.into()),
             "ALL" => Ok(0xff.into()),
             _ => Err(()),
         }
     }
 }
+impl fmt::Display for Symmetries {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const NAMES: [&str; 8] = [
+            "R000L", "R090L", "R180L", "R270L", "R000R", "R090R", "R180R", "R270R",
+        ];
+        if self.is_empty() {
+            return write!(f, "NONE");
+        }
+        if self.is_all() {
+            return write!(f, "ALL");
+        }
+        let names: Vec<&str> = self
+            .iter()
+            .map(|s| NAMES[s.bits().trailing_zeros() as usize])
+            .collect();
+        write!(f, "{}", names.join("|"))
+    }
+}
+
 impl From<u8> for Symmetries {
     fn from(x: u8) -> Self {
         Self { bits: x }
     }
 }
+
+#[cfg(feature = "serde")]
+serde_str!(Symmetries);
+
+#[cfg(test)]
+mod tests {
+    use super::arith::Const;
+    use super::*;
+
+    fn field(offset: u8, length: u8) -> FieldSelector {
+        FieldSelector { offset, length }
+    }
+
+    #[test]
+    fn mask_keeps_top_bit() {
+        // The mask once dropped the top bit of every field.
+        assert_eq!(field(0, 8).mask(), 0xff);
+        assert_eq!(FieldSelector::TYPE.mask(), 0xffff << 80);
+        assert_eq!(field(127, 1).mask(), 1 << 127);
+    }
+
+    #[test]
+    fn full_width_fields() {
+        let atom = field(0, 96);
+        assert_eq!(atom.mask(), (1 << 96) - 1);
+        assert_eq!(atom.max_value(), (1 << 96) - 1);
+        assert_eq!(atom.extract(u128::MAX), (1 << 96) - 1);
+        assert_eq!(atom.extract_signed(u128::MAX), -1);
+        assert_eq!(atom.insert(u128::MAX << 96, 5), u128::MAX << 96 | 5);
+
+        let word = field(0, 128);
+        assert_eq!(word.mask(), u128::MAX);
+        assert_eq!(word.max_value(), u128::MAX);
+        assert_eq!(word.min_signed(), i128::MIN);
+        assert_eq!(word.extract(u128::MAX - 1), u128::MAX - 1);
+        assert_eq!(word.extract_signed(u128::MAX), -1);
+        assert_eq!(word.insert(0, u128::MAX), u128::MAX);
+        assert_eq!(Const::Unsigned(u128::MAX).apply(word).as_u128(), u128::MAX);
+    }
+
+    #[test]
+    fn zero_length_fields() {
+        for &offset in [0, 40, 95, 127].iter() {
+            let empty = field(offset, 0);
+            assert!(empty.is_empty());
+            assert_eq!(empty.mask(), 0);
+            assert_eq!(empty.max_value(), 0);
+            assert_eq!(empty.extract(u128::MAX), 0);
+            assert_eq!(empty.extract_signed(u128::MAX), 0);
+            assert_eq!(empty.insert(0x1234, u128::MAX), 0x1234);
+        }
+        assert!(!field(0, 1).is_empty());
+    }
+
+    #[test]
+    fn signed_fields() {
+        let f = field(8, 8);
+        assert_eq!(f.extract_signed(0xff00), -1);
+        assert_eq!(f.extract_signed(0x7f00), 127);
+        assert_eq!(f.extract_signed(0x8000), -128);
+        assert_eq!(f.min_signed(), -128);
+        assert!(f.fits(Const::from(-128i32)) && !f.fits(Const::from(-129i32)));
+        assert!(f.fits(Const::from(255u32)) && !f.fits(Const::from(256u32)));
+    }
+}
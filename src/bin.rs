@@ -1,24 +1,37 @@
 extern crate lalrpop_util;
 extern crate lazy_static;
 
-mod ast;
-mod base;
-mod code;
+mod docs;
+mod lang;
+mod splat;
+mod ulam;
 
+use substrate_engine::{ast, base, code, diag, format, logging, preprocess};
+
+use crate::base::arith::Const;
 use crate::code::Compiler;
+use crate::logging::Logger;
+use crate::preprocess::Expanded;
 use atty::Stream;
+use log::LevelFilter;
+use std::collections::HashMap;
 use std::env;
 use std::fs;
-use std::fs::File;
 use std::io;
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
 struct Cli {
-    #[structopt(name = "INPUT", help = "Input EWAL source files.")]
+    #[structopt(subcommand)]
+    command: Option<Command>,
+
+    #[structopt(
+        name = "INPUT",
+        help = "Input EWAL source files, or SPLAT rule files ending in .splat."
+    )]
     input: Vec<String>,
 
     #[structopt(
@@ -28,6 +41,19 @@ struct Cli {
     )]
     output_dir: Option<String>,
 
+    #[structopt(
+        long = "log-level",
+        help = "Log the phases of compiling each element to stderr up to this level: off, error, warn, info, debug or trace.",
+        default_value = "warn"
+    )]
+    log_level: LevelFilter,
+
+    #[structopt(
+        long = "log-json",
+        help = "Log one JSON object per line instead of text."
+    )]
+    log_json: bool,
+
     #[structopt(
         long = "build-tag",
         short = "t",
@@ -35,14 +61,178 @@ struct Cli {
         default_value = "ephemeral"
     )]
     build_tag: String,
+
+    #[structopt(
+        long = "define",
+        short = "D",
+        number_of_values = 1,
+        parse(try_from_str = parse_define),
+        help = "Defines NAME for conditional assembly, as NAME or NAME=EXPR. Repeatable."
+    )]
+    defines: Vec<(String, Const)>,
+
+    #[structopt(
+        long = "import-ulam",
+        help = "Translate ulam element sources to EWAL source files instead of compiling, reporting what could not be translated."
+    )]
+    import_ulam: bool,
+
+    #[structopt(
+        long = "lang",
+        help = "Compile sources written in the structured element language instead of EWAL."
+    )]
+    lang: bool,
+
+    #[structopt(
+        short = "O",
+        long = "opt-level",
+        default_value = "0",
+        possible_values = &["0", "1", "2"],
+        help = "Optimize at this level: 0 for none, 1 to fold constants, clean up short sequences and thread jumps, 2 to also drop unreachable code and unread register stores."
+    )]
+    opt_level: u8,
+
+    #[structopt(
+        long = "dump-ir",
+        help = "Print each element's code to stderr before and after optimizing it."
+    )]
+    dump_ir: bool,
+
+    #[structopt(
+        long = "fmt",
+        help = "Reformat EWAL sources in place instead of compiling. Writes to the output directory instead if one is given."
+    )]
+    fmt: bool,
+
+    #[structopt(
+        long = "check",
+        requires = "fmt",
+        help = "With --fmt, list the sources which are not formatted instead of rewriting them, failing if there are any."
+    )]
+    check: bool,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    #[structopt(
+        about = "Compile several elements into one bundle sharing a constant pool, with the types they refer to resolved among them."
+    )]
+    Link(LinkArgs),
+    #[structopt(
+        about = "Print reference documentation of the instructions, metadata directives and builtins ewac compiles."
+    )]
+    Docs(DocsArgs),
+}
+
+#[derive(StructOpt)]
+struct LinkArgs {
+    #[structopt(
+        name = "INPUT",
+        required = true,
+        help = "Input EWAL source files, or SPLAT rule files ending in .splat. The first element is the bundle's first."
+    )]
+    input: Vec<String>,
+
+    #[structopt(
+        long = "output",
+        short = "o",
+        help = "Output bundle file. Stdout is -, the default when piped."
+    )]
+    output: Option<String>,
+
+    #[structopt(
+        long = "build-tag",
+        short = "t",
+        help = "Build tag compiled into the bundle.",
+        default_value = "ephemeral"
+    )]
+    build_tag: String,
+
+    #[structopt(
+        long = "define",
+        short = "D",
+        number_of_values = 1,
+        parse(try_from_str = parse_define),
+        help = "Defines NAME for conditional assembly, as NAME or NAME=EXPR. Repeatable."
+    )]
+    defines: Vec<(String, Const)>,
+
+    #[structopt(
+        long = "lang",
+        help = "Link sources written in the structured element language instead of EWAL."
+    )]
+    lang: bool,
+
+    #[structopt(
+        short = "O",
+        long = "opt-level",
+        default_value = "0",
+        possible_values = &["0", "1", "2"],
+        help = "Optimize at this level: 0 for none, 1 to fold constants, clean up short sequences and thread jumps, 2 to also drop unreachable code and unread register stores."
+    )]
+    opt_level: u8,
+
+    #[structopt(
+        long = "dump-ir",
+        help = "Print each element's code to stderr before and after optimizing it."
+    )]
+    dump_ir: bool,
+
+    #[structopt(
+        long = "extern",
+        number_of_values = 1,
+        help = "A type the elements may refer to without linking it, such as a standard element loaded beside the bundle. Repeatable."
+    )]
+    externs: Vec<String>,
+}
+
+#[derive(StructOpt)]
+struct DocsArgs {
+    #[structopt(
+        long = "format",
+        default_value = "markdown",
+        possible_values = &["markdown", "md", "html"],
+        help = "Write the documentation as Markdown or as an HTML page."
+    )]
+    format: docs::Format,
+
+    #[structopt(
+        long = "output",
+        short = "o",
+        help = "Output file. Stdout is -, the default."
+    )]
+    output: Option<String>,
+}
+
+fn parse_define(s: &str) -> Result<(String, Const), String> {
+    let (name, value) = match s.find('=') {
+        Some(i) => (&s[..i], preprocess::evaluate(&s[i + 1..], &HashMap::new())?),
+        None => (s, Const::from(1u8)),
+    };
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("bad name: {}", name));
+    }
+    Ok((name.to_owned(), value))
 }
 
 fn main() {
     let args = Cli::from_args();
-    ewac_main(&args);
+    Logger::init(args.log_level, args.log_json).expect("Failed to start logging");
+    if let Some(Command::Link(link)) = &args.command {
+        link_main(link);
+    } else if let Some(Command::Docs(docs)) = &args.command {
+        docs_main(docs);
+    } else if args.import_ulam {
+        import_main(&args);
+    } else if args.fmt {
+        fmt_main(&args);
+    } else {
+        ewac_main(&args);
+    }
 }
 
-fn ewac_main(args: &Cli) {
+/// Where output goes: stdout, for a pipe, or a directory.
+fn output(args: &Cli) -> (bool, PathBuf) {
     let is_explicit_stdout = args.output_dir == Some("-".to_string());
     let is_pipe = is_explicit_stdout || (args.output_dir.is_none() && !atty::is(Stream::Stdout));
     if is_pipe && args.input.len() != 1 {
@@ -61,26 +251,146 @@ fn ewac_main(args: &Cli) {
         if !is_explicit_stdout {
             fs::create_dir_all(d).expect("Failed to create target directory");
         }
-        d
+        d.to_owned()
+    } else {
+        curr_dir
+    };
+    (is_pipe, output_dir)
+}
+
+fn read_source(path: &str) -> String {
+    fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        exit(1)
+    })
+}
+
+/// Writes each element of the ulam sources given as `NAME.s`.
+fn import_main(args: &Cli) {
+    let (is_pipe, output_dir) = output(args);
+    for i in &args.input {
+        let src = read_source(i);
+        let (elements, skipped) = ulam::translate(&src, i).unwrap_or_else(|e| {
+            eprintln!("{}: {}", i, e);
+            exit(1)
+        });
+        if is_pipe && elements.len() != 1 {
+            eprintln!(
+                "{}: declares {} elements; pipes take one.",
+                i,
+                elements.len()
+            );
+            exit(1);
+        }
+        for u in &skipped {
+            eprintln!("{}: not translated: {}", i, u);
+        }
+        for e in &elements {
+            for u in &e.unsupported {
+                eprintln!("{}: {}: not translated: {}", i, e.name, u);
+            }
+            if is_pipe {
+                print!("{}", e.source);
+            } else {
+                let path = output_dir.join(format!("{}.s", e.name));
+                fs::write(path, &e.source).expect("Failed to write target")
+            }
+        }
+    }
+}
+
+/// Formats each source given, in place, into the output directory or to
+/// stdout.
+fn fmt_main(args: &Cli) {
+    if args.input.is_empty() {
+        eprintln!("No input files.");
+        exit(1);
+    }
+    let mut unformatted = false;
+    for i in &args.input {
+        let src = read_source(i);
+        let formatted = format::format(&src).unwrap_or_else(|e| {
+            eprintln!("{}: {}", i, e);
+            exit(1)
+        });
+        if args.check {
+            if formatted != src {
+                println!("{}", i);
+                unformatted = true;
+            }
+            continue;
+        }
+        match args.output_dir.as_deref() {
+            Some("-") => print!("{}", formatted),
+            Some(dir) => {
+                fs::create_dir_all(dir).expect("Failed to create target directory");
+                let name = Path::new(i).file_name().unwrap();
+                fs::write(Path::new(dir).join(name), formatted).expect("Failed to write target")
+            }
+            None if formatted != src => fs::write(i, formatted).expect("Failed to write target"),
+            None => {}
+        }
+    }
+    if unformatted {
+        exit(1);
+    }
+}
+
+/// The source of input `i`, translated and expanded for compiling.
+fn expand_input(i: &str, lang: bool, defines: &HashMap<String, Const>) -> Expanded {
+    let filename = Path::new(i);
+    let src = if lang {
+        let text = read_source(i);
+        let ewal = lang::translate(&text).unwrap_or_else(|e| {
+            eprint!("{}", e.diagnostic().render(i, &Expanded::plain(i, &text)));
+            exit(1)
+        });
+        preprocess::expand(i, &ewal, defines)
+    } else if filename.extension() == Some("splat".as_ref()) {
+        let text = splat::translate(&read_source(i)).unwrap_or_else(|e| {
+            eprintln!("{}: {}", i, e);
+            exit(1)
+        });
+        preprocess::expand(i, &text, defines)
     } else {
-        let path = curr_dir
-            .to_str()
-            .expect("Current directory is not valid UTF-8");
-        Path::new::<str>(path)
+        preprocess::expand_file(filename, defines)
     };
+    src.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1)
+    })
+}
 
-    let mut compiler = Compiler::new(args.build_tag.as_str());
+/// A compiler optimizing at `opt_level`, printing the code before and after
+/// if `dump_ir`.
+fn compiler(build_tag: &str, opt_level: u8, dump_ir: bool) -> Compiler {
+    let mut compiler = Compiler::new(build_tag);
+    compiler.set_opt_level(opt_level);
+    if dump_ir {
+        compiler.set_ir_hook(Box::new(move |name, before, after| {
+            eprint!(
+                "/* {} before optimizing */\n{}/* {} at -O{} */\n{}",
+                name, before, name, opt_level, after
+            )
+        }));
+    }
+    compiler
+}
+
+fn ewac_main(args: &Cli) {
+    let (is_pipe, output_dir) = output(args);
+
+    let mut compiler = compiler(&args.build_tag, args.opt_level, args.dump_ir);
+    let defines: HashMap<String, Const> = args.defines.iter().cloned().collect();
 
     for i in &args.input {
         let filename = Path::new::<String>(&i);
-        let mut file = File::open(filename).expect("Failed to open input file");
         let mut v = Vec::new();
-        let mut s = String::new();
-        file.read_to_string(&mut s)
-            .expect("Failed to read input file");
-        compiler
-            .compile_to_writer(&mut v, s.as_str())
-            .expect("Failed to compile input file");
+        let src = expand_input(i, args.lang, &defines);
+        if let Err(e) = compiler.compile_to_writer(&mut v, src.text.as_str()) {
+            eprint!("{}", e.diagnostic(&src.text).render(i, &src));
+            exit(1)
+        }
 
         if is_pipe {
             io::stdout()
@@ -92,3 +402,50 @@ fn ewac_main(args: &Cli) {
         }
     }
 }
+
+fn link_main(args: &LinkArgs) {
+    let is_pipe = match args.output.as_deref() {
+        Some("-") => true,
+        Some(_) => false,
+        None if !atty::is(Stream::Stdout) => true,
+        None => {
+            eprintln!("No output file.");
+            exit(1)
+        }
+    };
+
+    let compiler = compiler(&args.build_tag, args.opt_level, args.dump_ir);
+    let defines: HashMap<String, Const> = args.defines.iter().cloned().collect();
+    let srcs: Vec<Expanded> = args
+        .input
+        .iter()
+        .map(|i| expand_input(i, args.lang, &defines))
+        .collect();
+    let texts: Vec<&str> = srcs.iter().map(|s| s.text.as_str()).collect();
+    let externs: Vec<&str> = args.externs.iter().map(String::as_str).collect();
+
+    let mut v = Vec::new();
+    if let Err((n, e)) = compiler.link_to_writer(&mut v, &texts, &externs) {
+        eprint!(
+            "{}",
+            e.diagnostic(texts[n]).render(&args.input[n], &srcs[n])
+        );
+        exit(1)
+    }
+
+    if is_pipe {
+        io::stdout()
+            .write_all(v.as_slice())
+            .expect("Failed to write to stdout");
+    } else {
+        fs::write(args.output.as_ref().unwrap(), v).expect("Failed to write target")
+    }
+}
+
+fn docs_main(args: &DocsArgs) {
+    let text = docs::reference(args.format);
+    match args.output.as_deref() {
+        None | Some("-") => print!("{}", text),
+        Some(path) => fs::write(path, text).expect("Failed to write documentation"),
+    }
+}
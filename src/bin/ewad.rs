@@ -0,0 +1,58 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::process::exit;
+use structopt::StructOpt;
+use substrate_engine::disasm;
+use substrate_engine::runtime::Runtime;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "ewad",
+    about = "Disassemble compiled event window assembly (EWAL) back into source."
+)]
+struct Cli {
+    #[structopt(
+        name = "INPUT",
+        required = true,
+        help = "Compiled element files, or bundles linked by ewac link."
+    )]
+    input: Vec<String>,
+
+    #[structopt(
+        long,
+        help = "Follow each instruction with the depth of the operand stack before it."
+    )]
+    explain: bool,
+}
+
+fn main() {
+    let args = Cli::from_args();
+    ewad_main(&args);
+}
+
+fn ewad_main(args: &Cli) {
+    let mut first = true;
+    for i in args.input.iter() {
+        // Each file gets its own runtime, so the same element may be listed
+        // twice.
+        let mut runtime = Runtime::new();
+        let file = File::open(Path::new::<String>(i)).expect("Failed to open input file");
+        let atoms = runtime
+            .load_all_from_reader(&mut BufReader::new(file))
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to process input file {}: {}", i, e);
+                exit(1)
+            });
+        for atom in atoms {
+            if !first {
+                println!();
+            }
+            first = false;
+            print!(
+                "{}",
+                disasm::disassemble(&runtime, atom.get_type(), args.explain).unwrap()
+            );
+        }
+    }
+}
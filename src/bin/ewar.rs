@@ -1,18 +1,51 @@
-#[path = "../runtime/mod.rs"]
-mod runtime;
+#[path = "../debug.rs"]
+mod debug;
 
-#[path = "../base/mod.rs"]
-mod base;
+#[cfg(unix)]
+#[path = "../tui.rs"]
+mod tui;
 
-#[path = "../ast.rs"]
-mod ast;
+use substrate_engine::{
+  base, code, disasm, engine, experiment, logging, preprocess, render, runtime, stdlib, testing,
+};
 
+use crate::base::atom::Atom;
+use crate::base::ecc;
+use crate::code::Compiler;
+use crate::logging::Logger;
+use crate::render::{gif, png, Frame, Renderer};
+use crate::runtime::crash::CrashRecorder;
+use crate::runtime::energy::EnergyModel;
+use crate::runtime::grid::{Boundary, Grid, LAYERS};
+use crate::runtime::invariant::{Conserved, Violation};
 use crate::runtime::mfm::EventWindow;
-use crate::runtime::Runtime;
+use crate::runtime::observer::EventObserver;
+use crate::runtime::profile::{ElementProfile, Profiler};
+use crate::runtime::registry::ElementRegistry;
+use crate::runtime::scenario::{ParameterOverride, Scenario};
+use crate::runtime::schedule::Scheduling;
+use crate::runtime::stats::{csv_field, Metrics, Report};
+use crate::runtime::timeline::Timeline;
+use crate::runtime::trace::{TraceFormat, Tracer};
+use crate::runtime::watch::Watch;
+use crate::runtime::{
+  bench, mfs, snapshot, AssertPolicy, BudgetPolicy, Error, Runtime, BUNDLE_MAGIC_NUMBER,
+  MAGIC_NUMBER,
+};
+use crate::experiment::{Run, Sweep};
+use crate::testing::{GoldenTest, Outcome};
 use clap::arg_enum;
-use std::fs::File;
-use std::io::BufReader;
-use std::path::Path;
+use log::LevelFilter;
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, exit, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use structopt::StructOpt;
 
 arg_enum! {
@@ -39,14 +72,46 @@ arg_enum! {
     }
 }
 
+arg_enum! {
+  #[derive(Debug)]
+    enum ProfileFormat {
+      Annotated,
+      Folded,
+    }
+}
+
 #[derive(Debug, StructOpt)]
 #[structopt(
   name = "ewar",
-  about = "Execute event window assembly (EWAL) and collect detailed statistics."
+  about = "Execute event window assembly (EWAL) and collect detailed statistics.",
+  setting = structopt::clap::AppSettings::SubcommandsNegateReqs
 )]
 struct Cli {
-  #[structopt(name = "INPUT", required = true)]
-  input: String,
+  #[structopt(subcommand)]
+  command: Option<Command>,
+
+  #[structopt(
+    name = "INPUT",
+    required = true,
+    help = "Compiled element files, bundles linked by ewac link or EWAL sources, or names of standard elements such as DReg. The first element is run, or placed at the center of a new grid."
+  )]
+  input: Vec<String>,
+
+  #[structopt(
+    long = "log-level",
+    help = "Log compiling, loading, events and scheduling to stderr up to this level: off, error, warn, info, debug or trace. Records logged during an event carry its site and type.",
+    default_value = "warn"
+  )]
+  log_level: LevelFilter,
+
+  #[structopt(long = "log-json", help = "Log one JSON object per line instead of text.")]
+  log_json: bool,
+
+  #[structopt(
+    long = "types",
+    help = "Number element types from this file, adding new types to it, so saved grids stay loadable as elements are added."
+  )]
+  types: Option<String>,
 
   #[structopt(
     long = "random-seed",
@@ -104,23 +169,1773 @@ struct Cli {
     default_value = "color",
   )]
   color: ColorMode,
+
+  #[structopt(
+    long = "verify-ecc",
+    help = "Check the ECC bits of every site read, correcting single bit errors."
+  )]
+  verify_ecc: bool,
+
+  #[structopt(
+    long = "strict",
+    help = "Fail events which write a site further from the center than their element's .radius, or read a register before setting it."
+  )]
+  strict: bool,
+
+  #[structopt(
+    long = "events",
+    help = "Run this many events at random sites of a grid instead of a single event window. The input element starts at the center of a new grid."
+  )]
+  events: Option<u64>,
+
+  #[structopt(
+    long = "paint",
+    help = "Show each site's paint as its background color."
+  )]
+  paint: bool,
+
+  #[structopt(
+    long = "budget",
+    help = "The most instructions one event may run, or 0 for no limit.",
+    default_value = "1000000"
+  )]
+  budget: u64,
+
+  #[structopt(
+    long = "budget-policy",
+    help = "What to do with an event which exceeds its budget: discard its writes, commit them, or fail.",
+    possible_values = &["discard", "commit", "fail"],
+    default_value = "discard"
+  )]
+  budget_policy: BudgetPolicy,
+
+  #[structopt(
+    long = "assert-policy",
+    help = "What to do when an assert fails: log it, paint the site red, abort the event, or halt.",
+    possible_values = &["log", "paint", "abort", "halt"],
+    default_value = "halt"
+  )]
+  assert_policy: AssertPolicy,
+
+  #[structopt(
+    long = "max-stack-depth",
+    help = "The most values the operand stack may hold.",
+    default_value = "1024"
+  )]
+  max_stack_depth: usize,
+
+  #[structopt(
+    long = "max-call-depth",
+    help = "The most calls which may be nested.",
+    default_value = "256"
+  )]
+  max_call_depth: usize,
+
+  #[structopt(
+    long = "energy",
+    help = "Make events give off heat at their sites, which leaks away and throttles hot sites. Tuned by the options below."
+  )]
+  energy: bool,
+
+  #[structopt(
+    long = "heat-layer",
+    help = "The layer holding each site's heat under --energy. Defaults to the last."
+  )]
+  heat_layer: Option<usize>,
+
+  #[structopt(
+    long = "event-heat",
+    help = "The heat each event of an atom gives off under --energy. Defaults to 16."
+  )]
+  event_heat: Option<u32>,
+
+  #[structopt(
+    long = "instruction-heat",
+    help = "The heat each instruction gives off under --energy. Defaults to 1."
+  )]
+  instruction_heat: Option<u32>,
+
+  #[structopt(
+    long = "cooling",
+    help = "How fast heat leaks from each event's window under --energy, as the shift of decaylayer. Defaults to 4."
+  )]
+  cooling: Option<u32>,
+
+  #[structopt(
+    long = "throttle",
+    help = "The heat at which a site runs half its events under --energy; 0 never throttles. Defaults to 1024."
+  )]
+  throttle: Option<u32>,
+
+  #[structopt(
+    long = "watch",
+    number_of_values = 1,
+    help = "Stop when an event stores an atom meeting this watchpoint, such as \"site 3,4\" for a change of type there or \"Res.hp > 10\". Repeatable."
+  )]
+  watches: Vec<String>,
+
+  #[structopt(
+    long = "conserve",
+    number_of_values = 1,
+    help = "Check during grid runs that the number of atoms of this element never changes. Repeatable."
+  )]
+  conserve: Vec<String>,
+
+  #[structopt(
+    long = "param",
+    number_of_values = 1,
+    help = "Override a parameter of a loaded element, as ELEMENT.NAME=VALUE, without recompiling it. Repeatable."
+  )]
+  params: Vec<ParameterOverride>,
+
+  #[structopt(
+    long = "invariant-every",
+    help = "The events between checks of the --conserve invariants.",
+    default_value = "1000"
+  )]
+  invariant_every: u64,
+
+  #[structopt(
+    long = "fail-on-violation",
+    help = "Test mode: exit with status 1 after a grid run in which any invariant was violated."
+  )]
+  fail_on_violation: bool,
+
+  #[structopt(
+    long = "width",
+    help = "The width in sites of a new grid.",
+    default_value = "64"
+  )]
+  width: usize,
+
+  #[structopt(
+    long = "height",
+    help = "The height in sites of a new grid.",
+    default_value = "64"
+  )]
+  height: usize,
+
+  #[structopt(
+    long = "sparse",
+    help = "Allocate the sites of a new grid only as atoms reach them, for huge, mostly empty worlds."
+  )]
+  sparse: bool,
+
+  #[structopt(
+    long = "scheduling",
+    help = "How event sites are picked: uniform, active to skip events at empty sites, verify to check the active site set against uniform picks, sweep to visit every site in turn, poisson for a random clock per site, or prioritized to favor sites near recent changes.",
+    default_value = "uniform"
+  )]
+  scheduling: Scheduling,
+
+  #[structopt(
+    long = "boundary",
+    help = "What events see past the edges of the grid: void, where sites are empty and writes are dropped, wall, where sites are Wall atoms, or torus, where the grid wraps around. Wall loads the standard Wall unless an element named Wall is loaded.",
+    default_value = "void"
+  )]
+  boundary: Boundary,
+
+  #[structopt(
+    long = "tui",
+    help = "Show the grid live in the terminal, with pause, step and speed controls. Runs until quit, or for --events."
+  )]
+  tui: bool,
+
+  #[structopt(
+    long = "reload",
+    help = "Reload input files when they change during a grid run, compiling EWAL sources again, so atoms on the grid run the new code. Elements keep their type numbers."
+  )]
+  reload: bool,
+
+  #[structopt(
+    long = "stats",
+    help = "Print the element census, event rates and instruction counts after running."
+  )]
+  stats: bool,
+
+  #[structopt(
+    long = "stats-every",
+    help = "Also print statistics every N events while running."
+  )]
+  stats_every: Option<u64>,
+
+  #[structopt(
+    long = "metrics-out",
+    help = "Write the census, event rates, instruction counts and the counters of count to this CSV file after running, and every --metrics-every events while running."
+  )]
+  metrics_out: Option<String>,
+
+  #[structopt(
+    long = "metrics-every",
+    help = "Write a row of metrics every N events, for --metrics-out."
+  )]
+  metrics_every: Option<u64>,
+
+  #[structopt(
+    long = "metrics-addr",
+    help = "Serve the census, event rates, error counts, counters and utilization of a grid run at /metrics on this address, such as 127.0.0.1:9100, for Prometheus to scrape. Needs ewar built with the metrics-http feature."
+  )]
+  metrics_addr: Option<String>,
+
+  #[structopt(
+    long = "print-digest-every",
+    help = "Print a digest of the grid, a hash of every site, to stderr every N events and after running, for checking that runs with a fixed seed still leave the same grids."
+  )]
+  print_digest_every: Option<u64>,
+
+  #[structopt(
+    long = "save",
+    help = "Save a snapshot of the grid and random state to this file after running."
+  )]
+  save: Option<String>,
+
+  #[structopt(
+    long = "resume",
+    help = "Continue from a snapshot saved with --save instead of a new grid. Overrides --random-seed."
+  )]
+  resume: Option<String>,
+
+  #[structopt(
+    long = "import-mfs",
+    help = "Start from a grid in the reference simulator's .mfs format instead of a new grid."
+  )]
+  import_mfs: Option<String>,
+
+  #[structopt(
+    long = "scenario",
+    help = "Set up a new grid as this scenario file describes, placing loaded elements instead of the first at the center. Its size and seed override --width, --height and --random-seed."
+  )]
+  scenario: Option<String>,
+
+  #[structopt(
+    long = "export-mfs",
+    help = "Write the grid in the reference simulator's .mfs format to this file after running."
+  )]
+  export_mfs: Option<String>,
+
+  #[structopt(
+    long = "render-every",
+    help = "Render a frame of the grid before running and every N events, for --frames, --gif and --apng. Without it only the grid after running is rendered."
+  )]
+  render_every: Option<u64>,
+
+  #[structopt(
+    long = "frames",
+    help = "Write each rendered frame to this directory as a numbered PNG image."
+  )]
+  frames: Option<String>,
+
+  #[structopt(
+    long = "gif",
+    help = "Write the rendered frames to this file as an animated GIF."
+  )]
+  gif: Option<String>,
+
+  #[structopt(
+    long = "apng",
+    help = "Write the rendered frames to this file as an animated PNG."
+  )]
+  apng: Option<String>,
+
+  #[structopt(
+    long = "scale",
+    help = "The width and height in pixels of each site in rendered frames.",
+    default_value = "4"
+  )]
+  scale: usize,
+
+  #[structopt(
+    long = "overlay",
+    help = "Draw this layer on the empty sites of rendered frames, brighter for larger values."
+  )]
+  overlay: Option<usize>,
+
+  #[structopt(
+    long = "frame-delay",
+    help = "How long each frame of an animation is shown, in milliseconds.",
+    default_value = "100"
+  )]
+  frame_delay: u16,
+
+  #[structopt(
+    long = "trace",
+    help = "Log every instruction events run, with its stack effects and the sites it writes, to this file."
+  )]
+  trace: Option<String>,
+
+  #[structopt(
+    long = "trace-format",
+    help = "How --trace is written: json, one object per line, or binary.",
+    possible_values = &["json", "binary"],
+    default_value = "json"
+  )]
+  trace_format: TraceFormat,
+
+  #[structopt(
+    long = "trace-element",
+    number_of_values = 1,
+    help = "Trace only events run by this element. Repeatable."
+  )]
+  trace_elements: Vec<String>,
+
+  #[structopt(
+    long = "trace-site",
+    number_of_values = 1,
+    parse(try_from_str = parse_site),
+    help = "Trace only events at this site of the grid, as X,Y. Repeatable."
+  )]
+  trace_sites: Vec<(usize, usize)>,
+
+  #[structopt(
+    long = "trace-every",
+    help = "Trace only one in every N of the events otherwise traced.",
+    default_value = "1"
+  )]
+  trace_every: u64,
+
+  #[structopt(
+    long = "profile",
+    help = "Count the instructions each element runs and the time they take, and write a report to this file after running."
+  )]
+  profile: Option<String>,
+
+  #[structopt(
+    long = "profile-format",
+    help = "How --profile is written: annotated, each element's disassembly with counts and times, or folded, stacks for flame graph tools.",
+    possible_values = &ProfileFormat::variants(),
+    case_insensitive = true,
+    default_value = "annotated"
+  )]
+  profile_format: ProfileFormat,
+
+  #[structopt(
+    long = "crash-dump",
+    help = "If an event fails, stopping the run, write a report of it to this file: the element's disassembly with the failing instruction marked, the stack, the event window, the random number generator's state and the last instructions run."
+  )]
+  crash_dump: Option<String>,
+
+  #[structopt(
+    long = "crash-steps",
+    help = "The last instructions of the failed event --crash-dump reports.",
+    default_value = "32"
+  )]
+  crash_steps: usize,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+  #[structopt(
+    about = "Run events on a new grid without showing it, and report how quickly they ran."
+  )]
+  Bench(BenchArgs),
+  #[structopt(
+    about = "Run one event under an interactive debugger, stepping through its instructions."
+  )]
+  Debug(DebugArgs),
+  #[structopt(
+    about = "Compare two grids saved with --save or as .mfs files, and report the sites which differ grouped by element."
+  )]
+  Diff(DiffArgs),
+  #[structopt(
+    about = "Run every combination of the parameters and seeds a sweep file gives, each in a process of its own, and collect the metrics of every run in one directory."
+  )]
+  Experiment(ExperimentArgs),
+  #[structopt(
+    about = "Run the golden tests (*.test.mfm files) found under the given paths and compare their grids with the stored ones."
+  )]
+  Test(TestArgs),
+}
+
+#[derive(Debug, StructOpt)]
+struct BenchArgs {
+  #[structopt(
+    name = "INPUT",
+    required = true,
+    help = "Compiled element files, bundles linked by ewac link or EWAL sources, or names of standard elements such as DReg. The first element is placed at the center of the grid."
+  )]
+  input: Vec<String>,
+
+  #[structopt(
+    long = "events",
+    help = "The number of events to run.",
+    default_value = "1000000"
+  )]
+  events: u64,
+
+  #[structopt(
+    long = "random-seed",
+    help = "A 64 bit random seed used to initialize the random number generator.",
+    default_value = "1337"
+  )]
+  random_seed: u64,
+
+  #[structopt(
+    long = "width",
+    help = "The width in sites of the grid.",
+    default_value = "64"
+  )]
+  width: usize,
+
+  #[structopt(
+    long = "height",
+    help = "The height in sites of the grid.",
+    default_value = "64"
+  )]
+  height: usize,
+
+  #[structopt(
+    long = "sparse",
+    help = "Allocate the sites of the grid only as atoms reach them."
+  )]
+  sparse: bool,
+
+  #[structopt(
+    long = "scheduling",
+    help = "How event sites are picked: uniform, active, verify, sweep, poisson or prioritized.",
+    default_value = "uniform"
+  )]
+  scheduling: Scheduling,
+
+  #[structopt(
+    long = "boundary",
+    help = "What events see past the edges of the grid: void, wall or torus.",
+    default_value = "void"
+  )]
+  boundary: Boundary,
+
+  #[structopt(
+    long = "json",
+    help = "Print the results as a JSON object on one line, for tracking in CI."
+  )]
+  json: bool,
+}
+
+#[derive(Debug, StructOpt)]
+struct DebugArgs {
+  #[structopt(
+    name = "INPUT",
+    required = true,
+    help = "Compiled element files, bundles linked by ewac link or EWAL sources, or names of standard elements such as DReg. The first element is placed at the center of a new grid."
+  )]
+  input: Vec<String>,
+
+  #[structopt(
+    long = "site",
+    parse(try_from_str = parse_site),
+    help = "The site to run the event at, as X,Y. Defaults to the center of the grid."
+  )]
+  site: Option<(usize, usize)>,
+
+  #[structopt(
+    long = "event",
+    conflicts_with = "site",
+    help = "Run the grid with sites picked uniformly at random and debug its event numbered N, counting from 0, at the site the run picks. goto then moves to other events of the run, earlier or later."
+  )]
+  event: Option<u64>,
+
+  #[structopt(
+    long = "keyframe-every",
+    help = "With --event, keep a snapshot of the grid every N events, from which goto rebuilds earlier ones.",
+    default_value = "10000"
+  )]
+  keyframe_every: u64,
+
+  #[structopt(
+    long = "strict",
+    help = "Fail the event if it writes a site further from the center than its element's .radius, or reads a register before setting it."
+  )]
+  strict: bool,
+
+  #[structopt(
+    long = "break",
+    short = "b",
+    number_of_values = 1,
+    help = "Stop at this label or instruction number. Repeatable."
+  )]
+  breakpoints: Vec<String>,
+
+  #[structopt(
+    long = "run",
+    help = "Run to the first breakpoint instead of stopping before the first instruction."
+  )]
+  run: bool,
+
+  #[structopt(
+    long = "watch",
+    number_of_values = 1,
+    help = "Stop after the instruction which stores an atom meeting this watchpoint, such as \"site 3,4\" or \"Res.hp > 10\". Repeatable."
+  )]
+  watches: Vec<String>,
+
+  #[structopt(
+    long = "random-seed",
+    help = "A 64 bit random seed used to initialize the random number generator.",
+    default_value = "1337"
+  )]
+  random_seed: u64,
+
+  #[structopt(
+    long = "width",
+    help = "The width in sites of the grid.",
+    default_value = "9"
+  )]
+  width: usize,
+
+  #[structopt(
+    long = "height",
+    help = "The height in sites of the grid.",
+    default_value = "9"
+  )]
+  height: usize,
+
+  #[structopt(long = "resume", help = "Debug an event in a grid saved with --save.")]
+  resume: Option<String>,
+
+  #[structopt(
+    long = "import-mfs",
+    help = "Debug an event in a grid imported from a reference MFM simulator .mfs file."
+  )]
+  import_mfs: Option<String>,
+}
+
+#[derive(Debug, StructOpt)]
+struct DiffArgs {
+  #[structopt(name = "FIRST", help = "A grid saved with --save, or an .mfs file.")]
+  first: String,
+
+  #[structopt(
+    name = "SECOND",
+    help = "The grid to compare it with, saved with --save, or an .mfs file."
+  )]
+  second: String,
+
+  #[structopt(
+    long = "element",
+    short = "e",
+    number_of_values = 1,
+    help = "A compiled element file, EWAL source or standard element to name the types of the grids by, in the order they were loaded when the grids were run. Repeatable; needed to import .mfs files."
+  )]
+  elements: Vec<String>,
+
+  #[structopt(
+    long = "limit",
+    help = "The sites of each element to list.",
+    default_value = "10"
+  )]
+  limit: usize,
+
+  #[structopt(
+    long = "image",
+    help = "Write a PNG image of the second grid with the sites which differ in red, green or yellow."
+  )]
+  image: Option<String>,
+
+  #[structopt(
+    long = "scale",
+    help = "The width and height in pixels of each site in the image.",
+    default_value = "4"
+  )]
+  scale: usize,
+}
+
+#[derive(Debug, StructOpt)]
+struct ExperimentArgs {
+  #[structopt(name = "SWEEP", help = "The sweep file describing the runs.")]
+  sweep: String,
+
+  #[structopt(
+    long = "out",
+    short = "o",
+    help = "The directory to collect the results in, created if it doesn't exist.",
+    default_value = "experiment"
+  )]
+  out: String,
+
+  #[structopt(
+    long = "jobs",
+    short = "j",
+    help = "The runs to run at once.",
+    default_value = "1"
+  )]
+  jobs: usize,
+}
+
+#[derive(Debug, StructOpt)]
+struct TestArgs {
+  #[structopt(
+    name = "PATH",
+    help = "Test files, or directories searched for *.test.mfm files. Defaults to the current directory."
+  )]
+  paths: Vec<String>,
+
+  #[structopt(
+    long = "bless",
+    help = "Store the grid each test leaves as its golden grid instead of comparing them."
+  )]
+  bless: bool,
 }
 
+fn parse_site(s: &str) -> Result<(usize, usize), String> {
+  let bad = || format!("expected X,Y: {}", s);
+  let (x, y) = s.split_once(',').ok_or_else(bad)?;
+  Ok((
+    x.trim().parse().map_err(|_| bad())?,
+    y.trim().parse().map_err(|_| bad())?,
+  ))
+}
+
+/// Standard elements are compiled with the build tag of the files loaded
+/// before them, or this one, which is also `ewac`'s default.
+const STDLIB_BUILD_TAG: &str = "ephemeral";
+
 fn main() {
   let args = Cli::from_args();
-  ewar_main(&args);
+  Logger::init(args.log_level, args.log_json).expect("Failed to start logging");
+  match &args.command {
+    Some(Command::Bench(bench)) => bench_main(bench),
+    Some(Command::Debug(debug)) => debug_main(debug),
+    Some(Command::Diff(diff)) => diff_main(diff),
+    Some(Command::Experiment(experiment)) => experiment_main(experiment),
+    Some(Command::Test(test)) => test_main(test),
+    None => ewar_main(&args),
+  }
+}
+
+fn bench_main(args: &BenchArgs) {
+  let mut runtime = Runtime::new();
+  runtime.reseed(args.random_seed);
+  let atom = load_elements(&mut runtime, &args.input);
+  let mut grid = new_grid(args.width, args.height, args.sparse);
+  grid.set(args.width / 2, args.height / 2, ecc::encode(atom));
+  grid.set_scheduling(args.scheduling);
+  set_boundary(&mut grid, &mut runtime, args.boundary);
+  let result = bench::run(&grid, &mut runtime, args.events).unwrap_or_else(|e| {
+    eprintln!("Failed to execute: {}", e);
+    eprintln!("  {}", runtime.cursor());
+    exit(1)
+  });
+  if args.json {
+    result
+      .write_json(io::stdout())
+      .expect("Failed to write results");
+  } else {
+    print!("{}", result);
+  }
+}
+
+fn debug_main(args: &DebugArgs) {
+  let mut runtime = Runtime::new();
+  runtime.reseed(args.random_seed);
+  runtime.set_strict(args.strict);
+  let atom = load_elements(&mut runtime, &args.input);
+  let grid = match (&args.resume, &args.import_mfs) {
+    (Some(path), _) => {
+      let file = File::open(Path::new(path)).expect("Failed to open snapshot");
+      snapshot::load(BufReader::new(file), &mut runtime).expect("Failed to load snapshot")
+    }
+    (None, Some(path)) => {
+      let file = File::open(Path::new(path)).expect("Failed to open .mfs file");
+      mfs::import(BufReader::new(file), &runtime).expect("Failed to import .mfs file")
+    }
+    (None, None) => {
+      let mut grid = new_grid(args.width, args.height, false);
+      grid.set(args.width / 2, args.height / 2, ecc::encode(atom));
+      grid
+    }
+  };
+  add_watches(&mut runtime, &args.watches);
+  if let Some(event) = args.event {
+    let mut timeline =
+      Timeline::new(grid, &runtime, args.keyframe_every).expect("Failed to keep a keyframe");
+    let result = debug::travel(
+      &mut timeline,
+      &mut runtime,
+      event,
+      &args.breakpoints,
+      args.run,
+    );
+    if let Err(e) = result {
+      eprintln!("Failed to execute: {}", e);
+      exit(1)
+    }
+    return;
+  }
+  let (x, y) = args.site.unwrap_or((grid.width() / 2, grid.height() / 2));
+  if let Err(e) = debug::run(&grid, &mut runtime, x, y, &args.breakpoints, args.run) {
+    eprintln!("Failed to execute: {}", e);
+    exit(1)
+  }
+}
+
+/// The differences shown for each failed test.
+const SHOWN_DIFFERENCES: usize = 10;
+
+fn diff_main(args: &DiffArgs) {
+  let mut runtime = Runtime::new();
+  if !args.elements.is_empty() {
+    load_elements(&mut runtime, &args.elements);
+  }
+  let mut load = |path: &String| {
+    let file = File::open(Path::new(path)).unwrap_or_else(|e| {
+      eprintln!("Failed to open {}: {}", path, e);
+      exit(1)
+    });
+    if path.ends_with(".mfs") {
+      mfs::import(BufReader::new(file), &runtime)
+    } else {
+      snapshot::load(BufReader::new(file), &mut runtime)
+    }
+    .unwrap_or_else(|e| {
+      eprintln!("Failed to load {}: {}", path, e);
+      exit(1)
+    })
+  };
+  let (first, second) = (load(&args.first), load(&args.second));
+  let diff = runtime::diff::diff(&first, &second).unwrap_or_else(|e| {
+    eprintln!("Failed to compare the grids: {}", e);
+    exit(1)
+  });
+  diff
+    .write(&mut io::stdout(), &runtime, args.limit)
+    .expect("Failed to write the differences");
+  if let Some(path) = &args.image {
+    let frame = render::render_diff(&second, &diff, args.scale);
+    let file = File::create(Path::new(path)).expect("Failed to create image");
+    png::write(BufWriter::new(file), &render::DIFF_PALETTE, &frame).expect("Failed to write image");
+  }
+  if !diff.is_empty() {
+    exit(1);
+  }
+}
+
+fn test_main(args: &TestArgs) {
+  let paths = if args.paths.is_empty() {
+    vec![".".to_owned()]
+  } else {
+    args.paths.clone()
+  };
+  let mut tests = Vec::new();
+  for path in paths.iter() {
+    tests.extend(testing::discover(Path::new(path)).unwrap_or_else(|e| {
+      eprintln!("Failed to find tests in {}: {}", path, e);
+      exit(1)
+    }));
+  }
+  let mut failed = 0;
+  for path in tests.iter() {
+    let result = GoldenTest::load(path).and_then(|test| {
+      if args.bless {
+        test.bless().map(|_| Outcome::Passed)
+      } else {
+        test.check()
+      }
+    });
+    match result {
+      Ok(Outcome::Passed) if args.bless => println!("blessed {}", path.display()),
+      Ok(Outcome::Passed) => println!("pass {}", path.display()),
+      Ok(Outcome::NoGolden) => {
+        println!("FAIL {}: no golden grid; run with --bless", path.display());
+        failed += 1;
+      }
+      Ok(Outcome::Failed {
+        missing,
+        unexpected,
+      }) => {
+        println!("FAIL {}: the grid differs", path.display());
+        let lines = missing
+          .iter()
+          .map(|l| format!("  - {}", l))
+          .chain(unexpected.iter().map(|l| format!("  + {}", l)));
+        for line in lines.clone().take(SHOWN_DIFFERENCES) {
+          println!("{}", line);
+        }
+        let more = lines.count().saturating_sub(SHOWN_DIFFERENCES);
+        if more > 0 {
+          println!("  and {} more", more);
+        }
+        failed += 1;
+      }
+      Err(e) => {
+        println!("FAIL {}: {}", path.display(), e);
+        failed += 1;
+      }
+    }
+  }
+  println!("{} passed, {} failed", tests.len() - failed, failed);
+  if failed > 0 {
+    exit(1)
+  }
+}
+
+/// How a run of an experiment ended: its exit status, or why it couldn't be
+/// started, and how long it took.
+type Finished = (io::Result<ExitStatus>, Duration);
+
+fn experiment_main(args: &ExperimentArgs) {
+  let sweep = Sweep::load(Path::new(&args.sweep)).unwrap_or_else(|e| {
+    eprintln!("Failed to read sweep: {}", e);
+    exit(1)
+  });
+  let out = Path::new(&args.out);
+  fs::create_dir_all(out).expect("Failed to create results directory");
+  if let Some(name) = sweep.path().file_name() {
+    fs::copy(sweep.path(), out.join(name)).expect("Failed to copy sweep");
+  }
+  let exe = env::current_exe().expect("Failed to find ewar");
+  let runs = sweep.runs();
+  let queue = Mutex::new(runs.iter());
+  let finished: Mutex<Vec<(usize, Finished)>> = Mutex::new(Vec::new());
+  thread::scope(|s| {
+    for _ in 0..args.jobs.clamp(1, runs.len().max(1)) {
+      s.spawn(|| loop {
+        let run = match queue.lock().unwrap().next() {
+          Some(run) => run,
+          None => break,
+        };
+        let start = Instant::now();
+        let status = run_experiment(&exe, &sweep, run, &out.join(run.name()));
+        let result = (status, start.elapsed());
+        print_run(run, &result);
+        finished.lock().unwrap().push((run.number, result));
+      });
+    }
+  });
+  let mut finished = finished.into_inner().unwrap();
+  finished.sort_by_key(|(n, _)| *n);
+  let failed = finished
+    .iter()
+    .filter(|(_, (status, _))| !matches!(status, Ok(s) if s.success()))
+    .count();
+  let file = File::create(out.join("runs.csv")).expect("Failed to create runs.csv");
+  let mut w = BufWriter::new(file);
+  write_runs(&mut w, &sweep, &runs, &finished, out)
+    .and_then(|_| w.flush())
+    .expect("Failed to write runs.csv");
+  println!(
+    "{} runs, {} failed; results in {}",
+    runs.len(),
+    failed,
+    out.display()
+  );
+  if failed > 0 {
+    exit(1)
+  }
+}
+
+/// Runs `run` of `sweep` with the ewar at `exe`, writing its metrics and
+/// log into `dir`.
+fn run_experiment(exe: &Path, sweep: &Sweep, run: &Run, dir: &Path) -> io::Result<ExitStatus> {
+  fs::create_dir_all(dir)?;
+  let mut child = process::Command::new(exe);
+  child
+    .args(sweep.inputs())
+    .arg("--events")
+    .arg(sweep.events.to_string())
+    .arg("--random-seed")
+    .arg(run.seed.to_string())
+    .arg("--metrics-out")
+    .arg(dir.join("metrics.csv"));
+  if let Some(every) = sweep.metrics_every {
+    child.arg("--metrics-every").arg(every.to_string());
+  }
+  if let Some((width, height)) = sweep.size {
+    child
+      .arg("--width")
+      .arg(width.to_string())
+      .arg("--height")
+      .arg(height.to_string());
+  }
+  if let Some(path) = sweep.scenario_path() {
+    child.arg("--scenario").arg(path);
+  }
+  for (parameter, value) in run.parameters.iter() {
+    child.arg("--param").arg(format!("{}={}", parameter, value));
+  }
+  child
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
+    .stderr(File::create(dir.join("log.txt"))?)
+    .status()
+}
+
+/// Reports how `run` ended.
+fn print_run(run: &Run, (status, elapsed): &Finished) {
+  let mut about = vec![format!("seed {}", run.seed)];
+  about.extend(run.parameters.iter().map(|(p, v)| format!("{}={}", p, v)));
+  let how = match status {
+    Ok(s) if s.success() => "ok".to_owned(),
+    Ok(s) => format!("FAILED, {}", s),
+    Err(e) => format!("FAILED to start: {}", e),
+  };
+  println!(
+    "{} ({}): {} in {:.2?}",
+    run.name(),
+    about.join(", "),
+    how,
+    elapsed
+  );
+}
+
+/// Writes the table of `runs`: each run's number, seed, repetition and
+/// parameters, its exit status and time, and the last row of its metrics.
+fn write_runs<W: Write>(
+  w: &mut W,
+  sweep: &Sweep,
+  runs: &[Run],
+  finished: &[(usize, Finished)],
+  out: &Path,
+) -> io::Result<()> {
+  // The header and last row of each run's metrics, if it wrote any.
+  let metrics: Vec<Option<(String, String)>> = runs
+    .iter()
+    .map(|run| {
+      let table = fs::read_to_string(out.join(run.name()).join("metrics.csv")).ok()?;
+      let mut lines = table.lines().filter(|l| !l.is_empty());
+      let header = lines.next()?.to_owned();
+      Some((header, lines.next_back()?.to_owned()))
+    })
+    .collect();
+  let columns = metrics.iter().flatten().next().map(|(h, _)| h.clone());
+  let mut header = vec!["run".to_owned(), "seed".to_owned(), "repetition".to_owned()];
+  header.extend(sweep.axes.iter().map(|a| csv_field(&a.parameter)));
+  header.extend(vec!["exit".to_owned(), "seconds".to_owned()]);
+  header.extend(columns.clone());
+  writeln!(w, "{}", header.join(","))?;
+  for ((run, metrics), (_, (status, elapsed))) in runs.iter().zip(metrics).zip(finished) {
+    let mut row = vec![
+      run.number.to_string(),
+      run.seed.to_string(),
+      run.repetition.to_string(),
+    ];
+    row.extend(run.parameters.iter().map(|(_, v)| csv_field(v)));
+    row.push(match status {
+      Ok(s) => s.code().map_or(String::new(), |c| c.to_string()),
+      Err(_) => String::new(),
+    });
+    row.push(format!("{:.3}", elapsed.as_secs_f64()));
+    match (metrics, &columns) {
+      (Some((_, last)), _) => row.push(last),
+      // Leaves the metrics of a run which wrote none empty.
+      (None, Some(columns)) => row.push(",".repeat(columns.matches(',').count())),
+      (None, None) => {}
+    }
+    writeln!(w, "{}", row.join(","))?;
+  }
+  Ok(())
+}
+
+/// A new grid set up by the scenario at `path`.
+fn scenario_grid(args: &Cli, runtime: &mut Runtime, path: &str) -> Grid {
+  let scenario = Scenario::load(Path::new(path)).unwrap_or_else(|e| {
+    eprintln!("Failed to read scenario: {}", e);
+    exit(1)
+  });
+  if let Some(seed) = scenario.seed {
+    runtime.reseed(seed);
+  }
+  let (width, height) = scenario.size.unwrap_or((args.width, args.height));
+  let mut grid = new_grid(width, height, args.sparse);
+  if let Err(e) = scenario.populate(&mut grid, runtime) {
+    eprintln!("Failed to set up scenario: {}", e);
+    exit(1)
+  }
+  grid
+}
+
+fn new_grid(width: usize, height: usize, sparse: bool) -> Grid {
+  if sparse {
+    Grid::new_sparse(1, 1, width, height)
+  } else {
+    Grid::new(1, 1, width, height)
+  }
+}
+
+/// Sets the boundary of `grid`, loading the standard Wall for walls unless
+/// an element named Wall is loaded.
+fn set_boundary(grid: &mut Grid, runtime: &mut Runtime, boundary: Boundary) {
+  grid.set_boundary(boundary);
+  if boundary == Boundary::Wall {
+    let tag = runtime.build_tag().unwrap_or(STDLIB_BUILD_TAG).to_owned();
+    let wall = engine::wall_atom(runtime, &tag).unwrap_or_else(|e| {
+      eprintln!("Failed to load Wall: {}", e);
+      exit(1)
+    });
+    grid.set_wall(wall);
+  }
+}
+
+/// Loads each of `input`, a compiled element file or the name of a standard
+/// element, giving an atom of the first.
+fn load_elements(runtime: &mut Runtime, input: &[String]) -> Atom {
+  let mut atom = None;
+  for i in input {
+    let path = Path::new::<String>(i);
+    let a = if path.exists() {
+      read_input(runtime, path).and_then(|code| {
+        runtime
+          .load_from_reader(&mut code.as_slice())
+          .map_err(|e| e.to_string())
+      })
+    } else {
+      let tag = runtime.build_tag().unwrap_or(STDLIB_BUILD_TAG).to_owned();
+      let code = stdlib::compile(i, &tag)
+        .unwrap_or_else(|| {
+          eprintln!("No input file or standard element named {}", i);
+          exit(1)
+        })
+        .expect("Failed to compile standard element");
+      runtime
+        .load_from_reader(&mut code.as_slice())
+        .map_err(|e| e.to_string())
+    }
+    .unwrap_or_else(|e| {
+      eprintln!("Failed to process input file {}: {}", i, e.trim_end());
+      exit(1)
+    });
+    atom.get_or_insert(a);
+  }
+  for (n, name) in runtime.registry().unresolved() {
+    log::warn!(
+      target: "substrate::load",
+      "type {} ({}) is referred to but not loaded",
+      name,
+      n
+    );
+  }
+  atom.unwrap()
+}
+
+/// The compiled element or linked bundle in the input file at `path`,
+/// compiling it first if it is EWAL source.
+fn read_input(runtime: &Runtime, path: &Path) -> Result<Vec<u8>, String> {
+  let bytes = fs::read(path).map_err(|e| e.to_string())?;
+  if bytes.starts_with(&MAGIC_NUMBER.to_be_bytes())
+    || bytes.starts_with(&BUNDLE_MAGIC_NUMBER.to_be_bytes())
+  {
+    return Ok(bytes);
+  }
+  let name = path.display().to_string();
+  let src = preprocess::expand_file(path, &HashMap::new()).map_err(|e| e.to_string())?;
+  let mut compiler = Compiler::new(runtime.build_tag().unwrap_or(STDLIB_BUILD_TAG));
+  let mut code = Vec::new();
+  if let Err(e) = compiler.compile_to_writer(&mut code, &src.text) {
+    return Err(e.diagnostic(&src.text).render(&name, &src));
+  }
+  Ok(code)
+}
+
+/// How often `--reload` checks its files.
+const RELOAD_EVERY: Duration = Duration::from_millis(250);
+
+/// The input files of `--reload`, with when each was last modified.
+struct Reloader<'a> {
+  files: Vec<(PathBuf, Option<SystemTime>)>,
+  /// The `--param` overrides, set again on the elements reloaded.
+  params: &'a [ParameterOverride],
+  checked: Instant,
+}
+
+impl<'a> Reloader<'a> {
+  fn new(args: &'a Cli) -> Option<Self> {
+    if !args.reload {
+      return None;
+    }
+    let files = args
+      .input
+      .iter()
+      .map(PathBuf::from)
+      .filter(|path| path.exists())
+      .map(|path| {
+        let modified = modified(&path);
+        (path, modified)
+      })
+      .collect();
+    Some(Self {
+      files,
+      params: &args.params,
+      checked: Instant::now(),
+    })
+  }
+
+  /// Reloads the files changed since they were last checked, at most every
+  /// `RELOAD_EVERY`, giving a line telling how each went.
+  fn poll(&mut self, runtime: &mut Runtime) -> Vec<String> {
+    let mut notes = Vec::new();
+    if self.checked.elapsed() < RELOAD_EVERY {
+      return notes;
+    }
+    self.checked = Instant::now();
+    for (path, last) in self.files.iter_mut() {
+      let now = modified(path);
+      if now == *last {
+        continue;
+      }
+      *last = now;
+      let params = self.params;
+      let result = read_input(runtime, path).and_then(|code| {
+        runtime
+          .reload_from_reader(&mut code.as_slice())
+          .map_err(|e| e.to_string())?;
+        // A bundle reloads several elements; setting the others' parameters
+        // again changes nothing.
+        for p in params.iter() {
+          p.apply(runtime).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+      });
+      notes.push(match result {
+        Ok(()) => format!("Reloaded {}", path.display()),
+        Err(e) => format!("Failed to reload {}: {}", path.display(), e.trim_end()),
+      });
+    }
+    notes
+  }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+  fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Adds the watchpoints of `--watch` to `runtime`, once its elements are
+/// loaded.
+fn add_watches(runtime: &mut Runtime, watches: &[String]) {
+  for spec in watches {
+    let watch = Watch::parse(spec, runtime).unwrap_or_else(|e| {
+      eprintln!("Bad watchpoint: {}", e);
+      exit(1)
+    });
+    runtime.add_watch(watch);
+  }
+}
+
+/// The energy model of --energy and its knobs.
+fn energy_model(args: &Cli) -> EnergyModel {
+  let default = EnergyModel::default();
+  let model = EnergyModel {
+    layer: args.heat_layer.unwrap_or(default.layer),
+    event_heat: args.event_heat.unwrap_or(default.event_heat),
+    instruction_heat: args.instruction_heat.unwrap_or(default.instruction_heat),
+    cooling: args.cooling.unwrap_or(default.cooling),
+    throttle: args.throttle.unwrap_or(default.throttle),
+  };
+  if model.layer >= LAYERS {
+    eprintln!(
+      "Failed to keep heat in layer {}: layers are numbered 0 to {}",
+      model.layer,
+      LAYERS - 1
+    );
+    exit(1)
+  }
+  model
+}
+
+/// Reports the watchpoints the last event hit, with how they were written.
+fn report_watch_hits(runtime: &Runtime, watches: &[String]) {
+  for hit in runtime.cursor().watch_hits() {
+    eprintln!("Hit {} ({})", hit, watches[hit.watch]);
+  }
 }
 
 fn ewar_main(args: &Cli) {
   let mut runtime = Runtime::new();
+  runtime.reseed(args.random_seed);
+  runtime.set_verify_ecc(args.verify_ecc);
+  runtime.set_strict(args.strict);
+  runtime.set_instruction_budget(Some(args.budget).filter(|&n| n > 0));
+  runtime.set_budget_policy(args.budget_policy);
+  runtime.set_assert_policy(args.assert_policy);
+  runtime.set_max_stack_depth(args.max_stack_depth);
+  runtime.set_max_call_depth(args.max_call_depth);
+  if args.energy {
+    runtime.set_energy_model(Some(energy_model(args)));
+  }
+  runtime.on_corrupt_atom(Arc::new(|i, atom| {
+    log::warn!(target: "substrate::event", "Corrupt atom at site #{}: {}", i, atom)
+  }));
+
+  if let Some(path) = &args.types {
+    match File::open(Path::new(path)) {
+      Ok(file) => runtime.set_registry(ElementRegistry::load(BufReader::new(file)).unwrap_or_else(
+        |e| {
+          eprintln!("Failed to read type numbering: {}", e);
+          exit(1)
+        },
+      )),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+      Err(e) => {
+        eprintln!("Failed to open type numbering: {}", e);
+        exit(1)
+      }
+    }
+  }
+
+  let atom = load_elements(&mut runtime, &args.input);
+  for p in args.params.iter() {
+    if let Err(e) = p.apply(&mut runtime) {
+      eprintln!("Failed to set parameter: {}", e);
+      exit(1)
+    }
+  }
+  report_assertions(&mut runtime);
+  add_watches(&mut runtime, &args.watches);
+  let tracer = start_trace(args, &mut runtime);
+  let profiler = args.profile.as_ref().map(|_| Arc::new(Profiler::new()));
+  runtime.set_profiler(profiler.clone());
+  if args.crash_dump.is_some() {
+    runtime.set_crash_recorder(Some(Arc::new(CrashRecorder::new(args.crash_steps))));
+  }
+
+  if let Some(path) = &args.types {
+    let file = File::create(Path::new(path)).expect("Failed to create type numbering");
+    runtime
+      .registry()
+      .save(BufWriter::new(file))
+      .expect("Failed to write type numbering");
+  }
+
+  if args.events.is_some()
+    || args.save.is_some()
+    || args.resume.is_some()
+    || args.import_mfs.is_some()
+    || args.scenario.is_some()
+    || args.export_mfs.is_some()
+    || args.stats
+    || args.metrics_out.is_some()
+    || args.metrics_addr.is_some()
+    || args.tui
+    || args.frames.is_some()
+    || args.gif.is_some()
+    || args.apng.is_some()
+  {
+    let violations = run_grid(args, &mut runtime, atom);
+    finish_trace(tracer);
+    write_profile(args, profiler, &runtime);
+    if violations > 0 && args.fail_on_violation {
+      exit(1)
+    }
+    return;
+  }
+
+  let mut ew = EventWindow::new_with_atom(ecc::encode(atom));
+  match runtime.execute(&mut ew) {
+    Err(e @ Error::BudgetExceeded(_)) if args.budget_policy != BudgetPolicy::Fail => {
+      log::warn!(target: "substrate::event", "{}", e);
+      if args.budget_policy == BudgetPolicy::Discard {
+        ew = EventWindow::new_with_atom(ecc::encode(atom));
+      }
+    }
+    // The failure has already been reported.
+    Err(Error::AssertionFailed(_)) if args.assert_policy == AssertPolicy::Abort => {
+      ew = EventWindow::new_with_atom(ecc::encode(atom));
+    }
+    Err(e) => fail(args, &runtime, e),
+    Ok(()) => {}
+  }
+  report_watch_hits(&runtime, &args.watches);
+  if args.paint {
+    println!("{:#}", ew);
+  } else {
+    println!("{}", ew);
+  }
+  finish_trace(tracer);
+  write_profile(args, profiler, &runtime);
+}
+
+/// Reports each failed `assert` by the name of its element, once the elements
+/// are loaded.
+fn report_assertions(runtime: &mut Runtime) {
+  let names: HashMap<u16, String> = runtime
+    .elements()
+    .map(|(t, m)| (t, m.name.clone()))
+    .collect();
+  runtime.on_assert_failure(Arc::new(move |t, ip| match names.get(&t) {
+    Some(name) => log::warn!(
+      target: "substrate::event",
+      "Assertion failed in {} at instruction {}",
+      name,
+      ip
+    ),
+    None => log::warn!(
+      target: "substrate::event",
+      "Assertion failed in type {} at instruction {}",
+      t,
+      ip
+    ),
+  }));
+}
+
+/// Sets the tracer of `--trace` on `runtime`, once its elements are loaded.
+fn start_trace(args: &Cli, runtime: &mut Runtime) -> Option<Arc<Tracer>> {
+  let path = args.trace.as_ref()?;
+  let file = File::create(Path::new(path)).expect("Failed to create trace");
+  let mut tracer =
+    Tracer::new(Box::new(BufWriter::new(file)), args.trace_format).expect("Failed to write trace");
+  let types = args
+    .trace_elements
+    .iter()
+    .map(|name| {
+      runtime.registry().get(name).unwrap_or_else(|| {
+        eprintln!("No element {} to trace", name);
+        exit(1)
+      })
+    })
+    .collect();
+  tracer.set_elements(types);
+  tracer.set_sites(args.trace_sites.clone());
+  tracer.set_every(args.trace_every);
+  let tracer = Arc::new(tracer);
+  runtime.set_tracer(Some(tracer.clone()));
+  Some(tracer)
+}
+
+/// Writes the report of `--profile`.
+fn write_profile(args: &Cli, profiler: Option<Arc<Profiler>>, runtime: &Runtime) {
+  let (path, profiler) = match (&args.profile, profiler) {
+    (Some(path), Some(profiler)) => (path, profiler),
+    _ => return,
+  };
+  let file = File::create(Path::new(path)).expect("Failed to create profile");
+  let mut w = BufWriter::new(file);
+  match args.profile_format {
+    ProfileFormat::Folded => profiler.write_folded(&mut w, runtime),
+    ProfileFormat::Annotated => {
+      let mut profiles: Vec<(u16, ElementProfile)> = profiler
+        .profiles()
+        .into_iter()
+        .filter(|(t, _)| !runtime.code(*t).unwrap_or_default().is_empty())
+        .collect();
+      profiles.sort_by_key(|(t, p)| (std::cmp::Reverse(p.total().elapsed), *t));
+      profiles
+        .iter()
+        .try_for_each(|(t, p)| write_annotated(&mut w, runtime, *t, p))
+    }
+  }
+  .and_then(|_| w.flush())
+  .expect("Failed to write profile");
+}
+
+/// Writes the disassembly of element `t` with the instructions run at each
+/// instruction, their share of all the element ran, and the time they took.
+fn write_annotated<W: Write>(
+  w: &mut W,
+  runtime: &Runtime,
+  t: u16,
+  profile: &ElementProfile,
+) -> io::Result<()> {
+  let name = runtime.metadata(t).map_or("?", |m| m.name.as_str());
+  let labels = runtime.labels(t).unwrap_or_default();
+  let total = profile.total();
+  writeln!(
+    w,
+    "{}: {} events, {} instructions in {:?}",
+    name, profile.events, total.instructions, total.elapsed
+  )?;
+  let hits = profile.instructions();
+  for ip in 0..runtime.code(t).map_or(0, |c| c.len()) {
+    for (label, _) in labels.iter().filter(|(_, i)| *i as usize == ip) {
+      writeln!(w, "{:33}{}:", "", label)?;
+    }
+    let text = disasm::instruction(runtime, t, ip).unwrap_or_default();
+    match hits.get(ip).filter(|h| h.instructions > 0) {
+      Some(h) => writeln!(
+        w,
+        "{:>10} {:>6.2}% {:>12}    {}",
+        h.instructions,
+        100.0 * h.instructions as f64 / total.instructions as f64,
+        format!("{:?}", h.elapsed),
+        text
+      )?,
+      None => writeln!(w, "{:33}    {}", "", text)?,
+    }
+  }
+  writeln!(w)
+}
+
+/// Reports the event which failed with `e`, stopping the run, and writes the
+/// report of `--crash-dump`.
+fn fail(args: &Cli, runtime: &Runtime, e: Error) -> ! {
+  eprintln!("Failed to execute: {}", e);
+  eprintln!("  {}", runtime.cursor());
+  let crash = runtime.crash_recorder().and_then(|r| r.take());
+  if let (Some(path), Some(crash)) = (&args.crash_dump, crash) {
+    let file = File::create(Path::new(path)).expect("Failed to create crash dump");
+    let mut w = BufWriter::new(file);
+    crash
+      .write(&mut w, runtime)
+      .and_then(|_| w.flush())
+      .expect("Failed to write crash dump");
+    eprintln!("Wrote a report of the failed event to {}", path);
+  }
+  exit(1)
+}
+
+fn finish_trace(tracer: Option<Arc<Tracer>>) {
+  if let Some(tracer) = tracer {
+    if let Err(e) = tracer.flush() {
+      eprintln!("Failed to write trace: {}", e);
+      exit(1)
+    }
+  }
+}
+
+/// Reports invariant violations as they happen, counting them.
+#[derive(Default)]
+struct Violations(AtomicU64);
 
-  let mut file = File::open(Path::new::<String>(&args.input)).expect("Failed to open input file");
-  let mut r = BufReader::new(&mut file);
-  let atom = runtime
-    .load_from_reader(&mut r)
-    .expect("Failed to process input file");
+impl EventObserver for Violations {
+  fn invariant_violated(&self, violation: &Violation) {
+    log::warn!(target: "substrate::invariant", "{}", violation);
+    self.0.fetch_add(1, Ordering::Relaxed);
+  }
+}
+
+/// Adds the invariants of `--conserve` to `runtime`, with an observer counting
+/// their violations.
+fn add_invariants(args: &Cli, runtime: &mut Runtime) -> Arc<Violations> {
+  let violations = Arc::new(Violations::default());
+  if args.conserve.is_empty() {
+    return violations;
+  }
+  for name in args.conserve.iter() {
+    let t = runtime.registry().get(name).unwrap_or_else(|| {
+      eprintln!("No element {} to conserve", name);
+      exit(1)
+    });
+    runtime.add_invariant(Arc::new(Conserved::new(t, name)), args.invariant_every);
+  }
+  runtime.add_observer(violations.clone());
+  violations
+}
+
+/// Runs the grid, returning the number of invariant violations.
+fn run_grid(args: &Cli, runtime: &mut Runtime, atom: Atom) -> u64 {
+  let mut grid = match (&args.resume, &args.import_mfs) {
+    (Some(path), _) => {
+      let file = File::open(Path::new(path)).expect("Failed to open snapshot");
+      snapshot::load(BufReader::new(file), runtime).expect("Failed to load snapshot")
+    }
+    (None, Some(path)) => {
+      let file = File::open(Path::new(path)).expect("Failed to open .mfs file");
+      mfs::import(BufReader::new(file), runtime).expect("Failed to import .mfs file")
+    }
+    (None, None) => match &args.scenario {
+      Some(path) => scenario_grid(args, runtime, path),
+      None => {
+        let mut grid = new_grid(args.width, args.height, args.sparse);
+        grid.set(args.width / 2, args.height / 2, ecc::encode(atom));
+        grid
+      }
+    },
+  };
+  grid.set_scheduling(args.scheduling);
+  set_boundary(&mut grid, runtime, args.boundary);
+  let violations = add_invariants(args, runtime);
+  grid.check_invariants(runtime);
+
+  let mut metrics = start_metrics(args, runtime);
+  let mut endpoint = Endpoint::start(args);
+  let mut recording = Recording::new(args, runtime);
+  if args.render_every.is_some() {
+    recording.record(&grid);
+  }
+
+  let mut reloader = Reloader::new(args);
+  let mut digested = None;
+  let start = Instant::now();
+  let elapsed = if args.tui {
+    view(args, &mut grid, runtime, reloader.as_mut())
+  } else {
+    let events = args.events.unwrap_or(0);
+    let mut n = 0;
+    while n < events {
+      // Stop at every multiple of --stats-every and --render-every, however
+      // many events the scheduling skips.
+      let max = (events - n)
+        .min(to_next(n, args.stats_every))
+        .min(to_next(n, args.metrics_every))
+        .min(to_next(n, args.render_every))
+        .min(to_next(n, args.print_digest_every))
+        .min(endpoint.as_ref().map_or(u64::MAX, |_| Endpoint::EVENTS));
+      match grid.step_at_most(runtime, max) {
+        Ok(k) => n += k,
+        Err(e @ Error::Watchpoint { .. }) => {
+          eprintln!("Stopped after {} events: {}", grid.event_count(), e);
+          report_watch_hits(runtime, &args.watches);
+          break;
+        }
+        Err(e) => fail(args, runtime, e),
+      }
+      if let Some(reloader) = reloader.as_mut() {
+        for note in reloader.poll(runtime) {
+          eprintln!("{}", note);
+        }
+      }
+      if matches!(args.stats_every, Some(every) if every > 0 && n % every == 0) {
+        eprint!("{}", Report::new(&grid, runtime, start.elapsed()));
+      }
+      if matches!(args.metrics_every, Some(every) if every > 0 && n % every == 0) {
+        write_metrics(metrics.as_mut(), &Report::new(&grid, runtime, start.elapsed()));
+      }
+      if let Some(endpoint) = endpoint.as_mut() {
+        endpoint.publish(&grid, runtime, start.elapsed(), false);
+      }
+      if matches!(args.render_every, Some(every) if every > 0 && n % every == 0) {
+        recording.record(&grid);
+      }
+      if matches!(args.print_digest_every, Some(every) if every > 0 && n % every == 0) {
+        print_digest(&grid);
+        digested = Some(grid.event_count());
+      }
+    }
+    start.elapsed()
+  };
+  if !args.tui {
+    if args.paint {
+      print!("{:#}", grid);
+    } else {
+      print!("{}", grid);
+    }
+  }
+  let stats = grid.stats();
+  if stats.budget_exceeded > 0 {
+    eprintln!("Events over instruction budget: {}", stats.budget_exceeded);
+  }
+  if stats.assertions_failed > 0 {
+    eprintln!("Assertions failed: {}", stats.assertions_failed);
+  }
+  if stats.throttled > 0 {
+    eprintln!("Events throttled by heat: {}", stats.throttled);
+  }
+  let violations = violations.0.load(Ordering::Relaxed);
+  if violations > 0 {
+    eprintln!("Invariant violations: {}", violations);
+  }
+  if args.stats {
+    eprint!("{}", Report::new(&grid, runtime, elapsed));
+  }
+  if let Some(metrics) = metrics.as_mut() {
+    // The last row may already have been written.
+    let report = Report::new(&grid, runtime, elapsed);
+    if metrics.1 != Some(report.stats.events) {
+      write_metrics(Some(metrics), &report);
+    }
+  }
+  if let Some(endpoint) = endpoint.as_mut() {
+    endpoint.publish(&grid, runtime, elapsed, true);
+  }
+  // The digest may already have been printed after the last event.
+  if args.print_digest_every.is_some() && digested != Some(grid.event_count()) {
+    print_digest(&grid);
+  }
+
+  if let Some(path) = &args.save {
+    let file = File::create(Path::new(path)).expect("Failed to create snapshot");
+    snapshot::save(BufWriter::new(file), &grid, runtime).expect("Failed to save snapshot");
+  }
+  if let Some(path) = &args.export_mfs {
+    let file = File::create(Path::new(path)).expect("Failed to create .mfs file");
+    mfs::export(BufWriter::new(file), &grid, runtime).expect("Failed to export .mfs file");
+  }
+  recording.finish(&grid);
+  violations
+}
+
+fn print_digest(grid: &Grid) {
+  eprintln!(
+    "Digest after {} events: {:016x}",
+    grid.event_count(),
+    grid.digest()
+  );
+}
+
+/// The --metrics-out table and the events counted at its last row.
+type MetricsOut = (Metrics<BufWriter<File>>, Option<u64>);
+
+/// Starts the --metrics-out table, if it is set.
+fn start_metrics(args: &Cli, runtime: &Runtime) -> Option<MetricsOut> {
+  let path = args.metrics_out.as_ref()?;
+  if path.ends_with(".parquet") {
+    eprintln!("Failed to create metrics file: only CSV is supported, not Parquet");
+    exit(1)
+  }
+  let file = File::create(Path::new(path)).expect("Failed to create metrics file");
+  let metrics = Metrics::new(BufWriter::new(file), runtime).expect("Failed to write metrics");
+  Some((metrics, None))
+}
+
+fn write_metrics(metrics: Option<&mut MetricsOut>, report: &Report) {
+  if let Some((metrics, last)) = metrics {
+    metrics.write(report).expect("Failed to write metrics");
+    *last = Some(report.stats.events);
+  }
+}
+
+/// The --metrics-addr endpoint and when it was last published to.
+#[cfg(feature = "metrics-http")]
+struct Endpoint {
+  exporter: runtime::exporter::Exporter,
+  published: Option<Instant>,
+}
+
+#[cfg(feature = "metrics-http")]
+impl Endpoint {
+  /// The most events run between checks of whether to publish.
+  const EVENTS: u64 = 10_000;
+
+  /// How often a run publishes, since each report takes a census.
+  const EVERY: Duration = Duration::from_secs(1);
+
+  fn start(args: &Cli) -> Option<Self> {
+    let addr = args.metrics_addr.as_ref()?;
+    let exporter = runtime::exporter::Exporter::serve(addr).unwrap_or_else(|e| {
+      eprintln!("Failed to serve metrics on {}: {}", addr, e);
+      exit(1)
+    });
+    eprintln!("Serving metrics at http://{}/metrics", exporter.local_addr());
+    Some(Self {
+      exporter,
+      published: None,
+    })
+  }
+
+  /// Publishes the statistics of `grid` if a second has passed since the
+  /// last time, or if `force`.
+  fn publish(&mut self, grid: &Grid, runtime: &Runtime, elapsed: Duration, force: bool) {
+    if !force && matches!(self.published, Some(t) if t.elapsed() < Self::EVERY) {
+      return;
+    }
+    self.exporter.publish(&Report::new(grid, runtime, elapsed));
+    self.published = Some(Instant::now());
+  }
+}
+
+#[cfg(not(feature = "metrics-http"))]
+struct Endpoint;
+
+#[cfg(not(feature = "metrics-http"))]
+impl Endpoint {
+  const EVENTS: u64 = u64::MAX;
+
+  fn start(args: &Cli) -> Option<Self> {
+    if args.metrics_addr.is_some() {
+      eprintln!("Serving metrics needs ewar built with the metrics-http feature.");
+      exit(1)
+    }
+    None
+  }
+
+  fn publish(&mut self, _: &Grid, _: &Runtime, _: Duration, _: bool) {}
+}
+
+/// The events from `n` to the next multiple of `every`, if it is set.
+fn to_next(n: u64, every: Option<u64>) -> u64 {
+  match every {
+    Some(every) if every > 0 => every - n % every,
+    _ => u64::MAX,
+  }
+}
+
+/// The frames of a grid run rendered for --frames, --gif and --apng.
+struct Recording<'a> {
+  args: &'a Cli,
+  renderer: Renderer,
+  /// Frames kept for animations.
+  frames: Vec<Frame>,
+  count: usize,
+  /// The events run when the last frame was rendered.
+  last: Option<u64>,
+}
+
+impl<'a> Recording<'a> {
+  fn new(args: &'a Cli, runtime: &Runtime) -> Self {
+    if let Some(dir) = &args.frames {
+      fs::create_dir_all(dir).expect("Failed to create frame directory");
+    }
+    let mut renderer = Renderer::new(runtime, args.scale);
+    if let Some(l) = args.overlay {
+      if l >= LAYERS {
+        eprintln!(
+          "Failed to overlay layer {}: layers are numbered 0 to {}",
+          l,
+          LAYERS - 1
+        );
+        exit(1)
+      }
+      renderer.overlay(l);
+    }
+    Self {
+      args,
+      renderer,
+      frames: Vec::new(),
+      count: 0,
+      last: None,
+    }
+  }
+
+  fn enabled(&self) -> bool {
+    self.args.frames.is_some() || self.args.gif.is_some() || self.args.apng.is_some()
+  }
+
+  fn record(&mut self, grid: &Grid) {
+    if !self.enabled() {
+      return;
+    }
+    let frame = self.renderer.render(grid);
+    if let Some(dir) = &self.args.frames {
+      let path = Path::new(dir).join(format!("frame-{:06}.png", self.count));
+      let file = File::create(path).expect("Failed to create frame");
+      png::write(BufWriter::new(file), self.renderer.palette(), &frame)
+        .expect("Failed to write frame");
+    }
+    if self.args.gif.is_some() || self.args.apng.is_some() {
+      self.frames.push(frame);
+    }
+    self.count += 1;
+    self.last = Some(grid.event_count());
+  }
+
+  /// Renders the grid after running, unless it already was, and writes the
+  /// animations.
+  fn finish(mut self, grid: &Grid) {
+    if self.last != Some(grid.event_count()) {
+      self.record(grid);
+    }
+    let (palette, delay) = (self.renderer.palette(), self.args.frame_delay);
+    if let Some(path) = &self.args.gif {
+      let file = File::create(Path::new(path)).expect("Failed to create GIF");
+      gif::write(BufWriter::new(file), palette, &self.frames, delay).expect("Failed to write GIF");
+    }
+    if let Some(path) = &self.args.apng {
+      let file = File::create(Path::new(path)).expect("Failed to create APNG");
+      png::write_animated(BufWriter::new(file), palette, &self.frames, delay)
+        .expect("Failed to write APNG");
+    }
+  }
+}
+
+#[cfg(unix)]
+fn view(
+  args: &Cli,
+  grid: &mut Grid,
+  runtime: &mut Runtime,
+  mut reloader: Option<&mut Reloader>,
+) -> Duration {
+  let reload = |runtime: &mut Runtime| match reloader.as_mut() {
+    Some(reloader) => reloader.poll(runtime).pop(),
+    None => None,
+  };
+  match tui::run(grid, runtime, args.events, reload) {
+    Ok(elapsed) => elapsed,
+    Err(Error::IOError(e)) => {
+      eprintln!("Failed to run the terminal viewer: {}", e);
+      exit(1)
+    }
+    Err(e) => fail(args, runtime, e),
+  }
+}
 
-  let mut ew = EventWindow::new_with_const(atom);
-  runtime.execute(&mut ew).expect("Failed to execute");
-  println!("{}", ew);
+#[cfg(not(unix))]
+fn view(_: &Cli, _: &mut Grid, _: &mut Runtime, _: Option<&mut Reloader>) -> Duration {
+  eprintln!("The terminal viewer is only supported on Unix.");
+  exit(1)
 }
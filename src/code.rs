@@ -1,22 +1,33 @@
-use crate::ast::{Instruction, Metadata, Node};
-use crate::base;
-use crate::base::arith::Const;
+use crate::ast::{Arg, File, Instruction, Metadata, Node, Target};
+use crate::base::arith::{ArithMode, Const, ParseConstError};
+use crate::base::color::Color;
+use crate::base::field::{FieldError, FieldMap};
+use crate::base::FieldSelector;
+use crate::diag::{span_of, suggest, Diagnostic};
+use crate::ir::Ir;
+use crate::optimize;
+use crate::verify;
 use byteorder::BigEndian;
 use byteorder::WriteBytesExt;
 use lalrpop_util;
 use lalrpop_util::lalrpop_mod;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::io;
+use std::ops::Range;
+use std::str::FromStr;
 use thiserror;
 
-lalrpop_mod!(pub substrate); // syntesized by LALRPOP
+lalrpop_mod!(#[allow(clippy::all)] pub substrate); // syntesized by LALRPOP
 
 #[derive(thiserror::Error, Debug)]
 pub enum CompileError<'input> {
     #[error("IO error")]
     IOError(#[from] io::Error),
-    #[error("parse error")]
-    ParseError(lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'input>, &'input str>),
+    #[error("{}", describe_parse_error(.0))]
+    ParseError(
+        lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'input>, ExprError<'input>>,
+    ),
     #[error("internal error")]
     InternalError,
     #[error("unexpected node type")]
@@ -27,18 +38,449 @@ pub enum CompileError<'input> {
     InternalUnexpectedArgType,
     #[error("max code size reached: branches are unstable")]
     MaxCodeSize,
+    #[error("bad field declaration: {1}")]
+    FieldError(&'input str, FieldError),
+    #[error("unknown field: {0}")]
+    UnknownField(&'input str, Option<String>),
+    #[error("{} doesn't fit the {} bits of field {}", .1.to_decimal(), .2.length, .0)]
+    FieldRange(&'input str, Const, FieldSelector, ArithMode),
+    #[error("unknown parameter: {0}")]
+    UnknownParameter(&'input str, Option<String>),
+    #[error("unknown label: {0}")]
+    UnknownLabel(&'input str, Option<String>),
+    #[error("duplicate label: {0}")]
+    DuplicateLabel(&'input str, &'input str),
+    #[error("jump target {0} is outside the program's {1} instructions")]
+    BadTarget(&'input str, u16),
+    #[error("call target {0} is not an instruction")]
+    BadCallTarget(&'input str),
+    #[error("bad color: {0}")]
+    BadColor(&'input str),
+    #[error("element has no .name")]
+    MissingName,
+    #[error("max constant pool size reached")]
+    MaxConstPoolSize,
+    #[error("element {0} is linked twice")]
+    DuplicateElement(&'input str),
+    #[error("unresolved type: {0}")]
+    UnresolvedType(&'input str, Option<String>),
+    #[error("stack underflow: `{1}` takes {2} values but may find {3}")]
+    StackUnderflow(usize, &'static str, usize, usize),
+    #[error("`ret` may run outside any routine")]
+    RetWithoutCall(usize),
+    #[error("`{1}` takes a site number from 0 to 40, not {}", .2.to_decimal())]
+    BadSiteNumber(usize, &'static str, Const),
+    #[error("`{1}` divides by zero")]
+    DivisionByZero(usize, &'static str),
+    #[error("`{1}` may end the event with {} left on the stack", match .2 {
+        Some(1) => "1 value".to_owned(),
+        Some(n) => format!("{} values", n),
+        None => "values".to_owned(),
+    })]
+    Residue(usize, &'static str, Option<usize>),
+    #[error("`{1}` takes a field declared with `.bond`, not {2}")]
+    NotABond(usize, &'static str, &'input str),
 }
 
-impl<'input> From<lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'input>, &'input str>>
+impl<'input>
+    From<lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'input>, ExprError<'input>>>
     for CompileError<'input>
 {
     fn from(
-        x: lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'input>, &'input str>,
+        x: lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'input>, ExprError<'input>>,
     ) -> Self {
         CompileError::ParseError(x)
     }
 }
 
+fn describe_parse_error(
+    e: &lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'_>, ExprError<'_>>,
+) -> String {
+    use lalrpop_util::ParseError::*;
+    match e {
+        InvalidToken { .. } => "invalid token".to_owned(),
+        UnrecognizedEOF { .. } => "unexpected end of file".to_owned(),
+        UnrecognizedToken { token, .. } => format!("unexpected `{}`", (token.1).1),
+        ExtraToken { token } => format!("extra token `{}`", (token.1).1),
+        User { error } => error.to_string(),
+    }
+}
+
+/// The word ending at `end`, made of characters accepted by `f`.
+fn word_before(src: &str, end: usize, f: fn(char) -> bool) -> Range<usize> {
+    let start = src[..end].rfind(|c| !f(c)).map_or(0, |i| i + 1);
+    start..end
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// A readable name for a token the parser expected, as reported by LALRPOP:
+/// either a quoted literal or a raw string regex.
+fn describe_token(token: &str) -> Option<String> {
+    if let Some(regex) = token
+        .strip_prefix("r#\"")
+        .and_then(|t| t.strip_suffix("\"#"))
+    {
+        return match regex {
+            r"[_a-z][_a-zA-Z]*" => Some("identifier".to_owned()),
+            r"/\*.*\*/" => None,
+            r".radius [0-4]" => Some("`.radius`".to_owned()),
+            r#"\.name[ \t]+[^\s"]+"# => Some("`.name`".to_owned()),
+            r#"\.symbol[ \t]+[^\s"]+"# => Some("`.symbol`".to_owned()),
+            r"\.color[ \t]+#[0-9a-fA-F]+" => Some("`.color`".to_owned()),
+            r#""[^"]*""# => Some("string".to_owned()),
+            r"NONE|R000L|R090L|R180L|R270L|R000R|R090R|R180R|R270R|R000|R090|R180|R270|FLIPX|FLIPY|FLIPXY|ROTATIONS|ALL" => {
+                Some("symmetry".to_owned())
+            }
+            r"R1[0-4]|R[0-9]" => Some("register".to_owned()),
+            _ => Some("number".to_owned()),
+        };
+    }
+    let literal = token.trim_matches('"');
+    Some(format!("`{}`", literal))
+}
+
+fn did_you_mean(suggestion: &Option<String>) -> Option<String> {
+    suggestion
+        .as_ref()
+        .map(|s| format!("did you mean `{}`?", s))
+}
+
+impl CompileError<'_> {
+    /// Describes the error for reporting, pointing into `src`, the source
+    /// that was compiled.
+    pub fn diagnostic(&self, src: &str) -> Diagnostic {
+        use lalrpop_util::ParseError::*;
+        let span = |s: &str| span_of(src, s);
+        let mut d = Diagnostic::new(self.to_string(), None);
+        match self {
+            CompileError::ParseError(e) => match e {
+                InvalidToken { location } => {
+                    d.span = Some(*location..*location + 1);
+                    // Directives are the only tokens starting with `.`.
+                    if src[*location..].starts_with('.') {
+                        let word = word_before(
+                            src,
+                            src[*location + 1..]
+                                .find(|c| !is_word_char(c))
+                                .map_or(src.len(), |i| *location + 1 + i),
+                            is_word_char,
+                        );
+                        let word = &src[word.start - 1..word.end];
+                        d.span = span(word);
+                        // `.divzero` is lexed with its value.
+                        if Metadata::DIRECTIVES.contains(&word) {
+                            d.message = format!("bad value for `{}`", word);
+                        } else {
+                            d.message = format!("unknown directive `{}`", word);
+                            d.help =
+                                did_you_mean(&suggest(word, Metadata::DIRECTIVES.iter().copied()));
+                        }
+                    }
+                }
+                UnrecognizedEOF { location, expected } => {
+                    d.span = Some(*location..*location);
+                    self.explain_expected(src, *location, expected, &mut d);
+                }
+                UnrecognizedToken { token, expected } => {
+                    d.span = Some(token.0..token.2);
+                    self.explain_expected(src, token.0, expected, &mut d);
+                }
+                ExtraToken { token } => d.span = Some(token.0..token.2),
+                User { error } => {
+                    d.span = Some(error.offset()..error.offset() + 1);
+                    match error {
+                        ExprError::UnknownConstant(_, name, suggestion) => {
+                            d.span = span(name);
+                            d.help = did_you_mean(suggestion);
+                        }
+                        ExprError::OutOfRange(l, text, _) => d.span = Some(*l..*l + text.len()),
+                        _ => {}
+                    }
+                }
+            },
+            CompileError::FieldError(name, _) | CompileError::DuplicateElement(name) => {
+                d.span = span(name)
+            }
+            CompileError::DuplicateLabel(name, first) => {
+                d.span = span(name);
+                if let Some(o) = span(first) {
+                    let line = src[..o.start].matches('\n').count() + 1;
+                    d.notes.push(format!("first defined on line {}", line));
+                }
+            }
+            CompileError::BadTarget(text, _) => d.span = span(text),
+            CompileError::BadCallTarget(text) => {
+                d.span = span(text);
+                d.help = Some(
+                    "a routine must start with an instruction; use `jump` to end the event"
+                        .to_owned(),
+                );
+            }
+            CompileError::FieldRange(name, _, f, mode) => {
+                d.span = span(name);
+                d.help = Some(match mode {
+                    ArithMode::Checked => format!("it holds 0 to {}", f.max_value()),
+                    _ => format!(
+                        "it holds 0 to {}, or {} to -1 read signed",
+                        f.max_value(),
+                        f.min_signed()
+                    ),
+                });
+            }
+            CompileError::BadColor(text) => {
+                d.span = span(text);
+                d.help = Some("colors are written as #rgb, #rrggbb or #rrggbbaa".to_owned());
+            }
+            CompileError::UnknownField(name, suggestion)
+            | CompileError::UnknownParameter(name, suggestion)
+            | CompileError::UnknownLabel(name, suggestion) => {
+                d.span = span(name);
+                d.help = did_you_mean(suggestion);
+            }
+            CompileError::StackUnderflow(offset, mnemonic, _, _) => {
+                d.span = Some(*offset..*offset + mnemonic.len());
+                d.help = Some(if *mnemonic == "call" {
+                    "the routine pops values pushed before the call, and some path here pushes fewer"
+                        .to_owned()
+                } else {
+                    "some path here pushes fewer values than it pops".to_owned()
+                });
+            }
+            CompileError::RetWithoutCall(offset) => {
+                d.span = Some(*offset..*offset + "ret".len());
+                d.help = Some(
+                    "some path reaches it without a `call`; use `exit` to end the event".to_owned(),
+                );
+            }
+            CompileError::BadSiteNumber(offset, mnemonic, _)
+            | CompileError::DivisionByZero(offset, mnemonic) => {
+                d.span = Some(*offset..*offset + mnemonic.len())
+            }
+            CompileError::Residue(offset, mnemonic, _) => {
+                d.span = Some(*offset..*offset + mnemonic.len());
+                d.help = Some("pop what the event leaves before it ends".to_owned());
+            }
+            CompileError::NotABond(_, _, name) => {
+                d.span = span(name);
+                d.help = Some(format!("declare it with `.bond {}, POSITION`", name));
+            }
+            CompileError::UnresolvedType(name, suggestion) => {
+                d.span = span(name);
+                d.help = did_you_mean(suggestion).or_else(|| {
+                    Some(format!(
+                        "link {} too, or use --extern {} if it is loaded separately",
+                        name, name
+                    ))
+                });
+            }
+            _ => {}
+        }
+        d
+    }
+
+    /// Adds what the parser expected at `location` to `d`. A word alone at
+    /// the start of a line which is not followed by `:` was meant as an
+    /// instruction, so a misspelled mnemonic is reported as such rather than
+    /// as a malformed label.
+    fn explain_expected(
+        &self,
+        src: &str,
+        location: usize,
+        expected: &[String],
+        d: &mut Diagnostic,
+    ) {
+        let end = src[..location].trim_end().len();
+        let word = word_before(src, end, is_word_char);
+        let line_start = src[..word.start].rfind('\n').map_or(0, |i| i + 1);
+        let word = &src[word];
+        if expected.iter().any(|e| e == r#"":""#)
+            && !word.is_empty()
+            && src[line_start..end - word.len()].trim().is_empty()
+            && !Instruction::MNEMONICS.contains(&word)
+        {
+            d.message = format!("unknown instruction `{}`", word);
+            d.span = span_of(src, word);
+            d.help = did_you_mean(&suggest(word, Instruction::MNEMONICS.iter().copied()));
+            return;
+        }
+        let mut names: Vec<String> = Vec::new();
+        for e in expected.iter().filter_map(|e| describe_token(e)) {
+            if !names.contains(&e) {
+                names.push(e);
+            }
+        }
+        if !names.is_empty() && names.len() <= 8 {
+            d.notes
+                .push(format!("expected one of {}", names.join(", ")));
+        }
+    }
+}
+
+/// An error evaluating a constant expression. Each variant carries the byte
+/// offset of the offending name or operator.
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum ExprError<'input> {
+    #[error("unknown constant: {1}")]
+    UnknownConstant(usize, &'input str, Option<String>),
+    #[error("constant expression overflows")]
+    Overflow(usize),
+    #[error("division by zero in constant expression")]
+    DivisionByZero(usize),
+    #[error("bad shift amount: {1}")]
+    BadShift(usize, Const),
+    #[error("width {} is not between 1 and 96", .1.as_i128())]
+    BadWidth(usize, Const),
+    #[error("counter {} is not between 0 and 15", .1.as_i128())]
+    BadCounter(usize, Const),
+    #[error("assertion failed")]
+    AssertionFailed(usize),
+    #[error("bad number: {1}")]
+    BadNumber(usize, ParseConstError),
+    #[error("{2} out of range: {1}")]
+    OutOfRange(usize, &'input str, &'static str),
+}
+
+type ExprResult<'input> = Result<
+    Const,
+    lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'input>, ExprError<'input>>,
+>;
+
+impl<'input> ExprError<'input> {
+    pub fn offset(&self) -> usize {
+        match self {
+            ExprError::UnknownConstant(l, _, _) => *l,
+            ExprError::Overflow(l) => *l,
+            ExprError::DivisionByZero(l) => *l,
+            ExprError::BadShift(l, _) => *l,
+            ExprError::BadWidth(l, _) => *l,
+            ExprError::BadCounter(l, _) => *l,
+            ExprError::AssertionFailed(l) => *l,
+            ExprError::BadNumber(l, _) => *l,
+            ExprError::OutOfRange(l, _, _) => *l,
+        }
+    }
+
+    pub fn unknown(
+        l: usize,
+        name: &'input str,
+        params: &HashMap<&'input str, Const>,
+    ) -> lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'input>, Self> {
+        let suggestion = suggest(name, params.keys().copied());
+        ExprError::UnknownConstant(l, name, suggestion).user()
+    }
+
+    fn user(self) -> lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'input>, Self> {
+        lalrpop_util::ParseError::User { error: self }
+    }
+
+    /// Parses a numeric literal.
+    pub fn number(l: usize, s: &str) -> ExprResult<'input> {
+        s.parse().map_err(|e| ExprError::BadNumber(l, e).user())
+    }
+
+    /// Parses a decimal directive argument, `what`, which must fit a `T`.
+    pub fn integer<T: FromStr>(
+        l: usize,
+        s: &'input str,
+        what: &'static str,
+    ) -> Result<T, lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'input>, Self>> {
+        s.parse()
+            .map_err(|_| ExprError::OutOfRange(l, s, what).user())
+    }
+
+    /// Fails with `Overflow` if a checked operation had no result.
+    pub fn check(l: usize, c: Option<Const>) -> ExprResult<'input> {
+        c.ok_or_else(|| ExprError::Overflow(l).user())
+    }
+
+    /// Divides `a` by `b`, or takes the remainder if `rem`.
+    pub fn divide(l: usize, a: Const, b: Const, rem: bool) -> ExprResult<'input> {
+        if a.divides_by_zero(b, rem) {
+            return Err(ExprError::DivisionByZero(l).user());
+        }
+        let c = if rem {
+            a.checked_rem(b)
+        } else {
+            a.checked_div(b)
+        };
+        Self::check(l, c)
+    }
+
+    /// Adds a signed literal written directly after an operand. Negative
+    /// literals are subtracted, so unsigned operands stay unsigned.
+    pub fn add_signed(l: usize, a: Const, b: Const) -> ExprResult<'input> {
+        let magnitude = match b {
+            Const::Fixed { value, frac } => Const::Fixed {
+                value: value.abs(),
+                frac,
+            },
+            _ => Const::Unsigned(b.as_i128().unsigned_abs()),
+        };
+        if b.as_i128() < 0 {
+            Self::check(l, a.checked_sub(magnitude))
+        } else {
+            Self::check(l, a.checked_add(magnitude))
+        }
+    }
+
+    fn shift_amount(
+        l: usize,
+        b: Const,
+    ) -> Result<u8, lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'input>, Self>> {
+        if b.is_signed() && b.as_i128() < 0 || b.as_u128() >= Const::MAX_BITS as u128 {
+            return Err(ExprError::BadShift(l, b).user());
+        }
+        Ok(b.as_u128() as u8)
+    }
+
+    pub fn shift_left(l: usize, a: Const, b: Const) -> ExprResult<'input> {
+        let n = Self::shift_amount(l, b)?;
+        Self::check(l, a.checked_mul(Const::Unsigned(1 << n)))
+    }
+
+    pub fn shift_right(l: usize, a: Const, b: Const) -> ExprResult<'input> {
+        let n = Self::shift_amount(l, b)?;
+        Ok(a >> n)
+    }
+
+    /// Checks the bit width taken by `zext`, `sext` and `trunc`.
+    pub fn width(
+        l: usize,
+        w: Const,
+    ) -> Result<u8, lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'input>, Self>> {
+        if w.is_signed() && w.as_i128() < 0 || !(1..=Const::MAX_BITS as u128).contains(&w.as_u128())
+        {
+            return Err(ExprError::BadWidth(l, w).user());
+        }
+        Ok(w.as_u128() as u8)
+    }
+
+    /// Checks the counter taken by `count`.
+    pub fn counter(
+        l: usize,
+        c: Const,
+    ) -> Result<u8, lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'input>, Self>> {
+        if c.is_signed() && c.as_i128() < 0 || c.as_u128() >= Instruction::COUNTERS as u128 {
+            return Err(ExprError::BadCounter(l, c).user());
+        }
+        Ok(c.as_u128() as u8)
+    }
+
+    /// Checks the expression of a `.assert`, which holds unless it is zero.
+    pub fn assert(
+        l: usize,
+        c: Const,
+    ) -> Result<(), lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'input>, Self>> {
+        if c.is_zero() {
+            return Err(ExprError::AssertionFailed(l).user());
+        }
+        Ok(())
+    }
+}
+
 struct CodeEntry {
     args: Vec<u8>,
 }
@@ -49,57 +491,159 @@ impl CodeEntry {
     }
 }
 
+/// The distinct constants of a program, or of the elements linked into a
+/// bundle. Instructions and metadata refer to constants by their index in the
+/// pool.
+///
+/// Each parameter's default has a slot of its own, shared with nothing else,
+/// so the runtime can override one parameter without changing any other
+/// constant.
+struct ConstPool {
+    consts: Vec<Const>,
+    /// Whether each slot holds a parameter.
+    parameter: Vec<bool>,
+}
+
+impl ConstPool {
+    fn new() -> Self {
+        Self {
+            consts: Vec::new(),
+            parameter: Vec::new(),
+        }
+    }
+
+    fn push<'input>(&mut self, c: Const, parameter: bool) -> Result<u16, CompileError<'input>> {
+        if self.consts.len() >= u16::MAX as usize {
+            return Err(CompileError::MaxConstPoolSize);
+        }
+        self.consts.push(c);
+        self.parameter.push(parameter);
+        Ok((self.consts.len() - 1) as u16)
+    }
+
+    fn position(&self, c: Const) -> Option<usize> {
+        (0..self.consts.len()).find(|i| self.consts[*i] == c && !self.parameter[*i])
+    }
+
+    fn insert<'input>(&mut self, c: Const) -> Result<u16, CompileError<'input>> {
+        match self.position(c) {
+            Some(i) => Ok(i as u16),
+            None => self.push(c, false),
+        }
+    }
+
+    fn index(&self, c: Const) -> u16 {
+        self.position(c).expect("constant missing from pool") as u16
+    }
+}
+
+/// The element types a program names. The first is the element's own, which
+/// `Self` also names; in a bundle, the elements linked come first, in order.
+/// `gettype` refers to a type by its index in the table, and the runtime
+/// numbers them when the program is loaded.
+struct TypeTable<'input> {
+    names: Vec<&'input str>,
+}
+
+impl<'input> TypeTable<'input> {
+    fn new() -> Self {
+        Self { names: Vec::new() }
+    }
+
+    fn insert(&mut self, name: &'input str) {
+        if !self.names.contains(&name) {
+            self.names.push(name);
+        }
+    }
+
+    /// The index of type `name` in a program of element `own`.
+    fn index(&self, name: &str, own: &str) -> u16 {
+        let name = if name == "Self" { own } else { name };
+        self.names
+            .iter()
+            .position(|x| *x == name)
+            .expect("type missing from table") as u16
+    }
+}
+
+/// An element's source, parsed and indexed. Its constants and types go to a
+/// pool and table which it may share with the elements linked beside it.
+struct Program<'input> {
+    ast: File<'input>,
+    name: &'input str,
+    code_index: HashMap<u16, CodeEntry>,
+    label_map: HashMap<&'input str, u16>,
+    const_map: HashMap<&'input str, Const>,
+    /// The pool slot of each parameter.
+    parameters: HashMap<&'input str, u16>,
+    field_map: FieldMap,
+    code_lines: u16,
+    ir: Ir<'input>,
+}
+
 const MAGIC_NUMBER: u32 = 0x02030741;
+const BUNDLE_MAGIC_NUMBER: u32 = 0x02030742;
+
+/// The type every runtime numbers 0, which programs may name without loading.
+const EMPTY: &str = "Empty";
+
+/// Called with an element's name and its code before and after optimizing,
+/// as `Ir::dump` lists it.
+pub type IrHook = Box<dyn Fn(&str, &str, &str)>;
 
 pub struct Compiler {
     build_tag: String,
-    type_map: HashMap<String, u16>,
+    opt_level: u8,
+    ir_hook: Option<IrHook>,
 }
 
 impl Compiler {
-    const MINOR_VERSION: u16 = 1;
+    const MINOR_VERSION: u16 = 16;
     const MAJOR_VERSION: u16 = 0;
     const MAX_CODE_SIZE: usize = (u16::MAX - 1) as usize;
 
     pub fn new(build_tag: &str) -> Self {
         Self {
             build_tag: build_tag.to_owned(),
-            type_map: Self::new_type_map(),
+            opt_level: 0,
+            ir_hook: None,
         }
     }
 
-    fn new_type_map() -> HashMap<String, u16> {
-        let mut m = HashMap::new();
-        m.insert("Empty".to_owned(), 0);
-        m
+    /// Optimizes the code of the elements compiled from now on at `level`,
+    /// from 0, the default, for none to `optimize::MAX_LEVEL`; see
+    /// `optimize`.
+    pub fn set_opt_level(&mut self, level: u8) {
+        self.opt_level = level.min(optimize::MAX_LEVEL);
     }
 
-    fn new_field_map() -> HashMap<&'static str, base::FieldSelector> {
-        let mut m = HashMap::new();
-        m.insert("type", base::FieldSelector::TYPE);
-        m.insert("header", base::FieldSelector::HEADER);
-        m.insert("data", base::FieldSelector::DATA);
-        m
+    /// Calls `hook` with the code of each element compiled from now on,
+    /// before and after optimizing it.
+    pub fn set_ir_hook(&mut self, hook: IrHook) {
+        self.ir_hook = Some(hook);
     }
 
     fn index_metadata_node<'input>(
         n: Node<'input>,
-        type_map: &mut HashMap<String, u16>,
+        name: &mut Option<&'input str>,
         const_map: &mut HashMap<&'input str, Const>,
-        field_map: &mut HashMap<&'input str, base::FieldSelector>,
+        parameters: &mut HashMap<&'input str, u16>,
+        const_pool: &mut ConstPool,
+        field_map: &mut FieldMap,
     ) -> Result<(), CompileError<'input>> {
         match n {
             Node::Metadata(i) => match i {
                 Metadata::Name(i) => {
-                    let n = type_map.len();
-                    type_map.insert(i.to_owned(), n as u16);
-                    type_map.insert("Self".to_owned(), n as u16);
+                    name.get_or_insert(i);
                 }
                 Metadata::Parameter(i, c) => {
                     const_map.insert(i, c);
+                    parameters.insert(i, const_pool.push(c, true)?);
                 }
-                Metadata::Field(i, f) => {
-                    field_map.insert(i, f);
+                Metadata::Field(i, f) | Metadata::Bond(i, f) => {
+                    field_map
+                        .insert(i, f)
+                        .map_err(|e| CompileError::FieldError(i, e))?;
                 }
                 _ => {}
             },
@@ -113,18 +657,43 @@ impl Compiler {
         n: Node<'input>,
         _code_index: &mut HashMap<u16, CodeEntry>,
         label_map: &mut HashMap<&'input str, u16>,
+        types: &mut TypeTable<'input>,
+        const_map: &HashMap<&'input str, Const>,
     ) -> Result<(), CompileError<'input>> {
         match n {
             Node::Label(i) => {
+                if let Some((first, _)) = label_map.get_key_value(i) {
+                    return Err(CompileError::DuplicateLabel(i, first));
+                }
                 label_map.insert(i, *ln);
             }
-            Node::Instruction(_) => *ln += 1,
+            Node::Instruction(i) => {
+                match i {
+                    Instruction::GetType(x) if *x.ast() != "Self" => types.insert(x.ast()),
+                    Instruction::GetParameter(x) if !const_map.contains_key(x.ast()) => {
+                        let suggestion = suggest(x.ast(), const_map.keys().copied());
+                        return Err(CompileError::UnknownParameter(x.ast(), suggestion));
+                    }
+                    _ => {}
+                }
+                *ln += 1
+            }
             _ => return Err(CompileError::InternalUnexpectedNodeType),
         }
         Ok(())
     }
 
-    fn write_u96<W: WriteBytesExt>(w: &mut W, x: Const) -> Result<(), io::Error> {
+    /// Writes a pooled constant: a type byte with the sign in its top bit and
+    /// the width below it, or zero and the fraction bits for a fixed point
+    /// number, then the value as 96 bits.
+    fn write_const<W: WriteBytesExt>(w: &mut W, x: Const) -> Result<(), io::Error> {
+        if let Const::Fixed { frac, .. } = x {
+            w.write_u8(0)?;
+            w.write_u8(frac)?;
+        } else {
+            let sign = if x.is_signed() { 0x80 } else { 0 };
+            w.write_u8(sign | x.bits())?;
+        }
         let v = x.as_u128();
         w.write_u32::<BigEndian>((v >> 64) as u32)?;
         w.write_u64::<BigEndian>(v as u64)
@@ -143,8 +712,7 @@ impl Compiler {
     fn write_metadata<'input, W: WriteBytesExt>(
         w: &mut W,
         n: Node<'input>,
-        const_map: &HashMap<&'input str, Const>,
-        field_map: &HashMap<&'input str, base::FieldSelector>,
+        parameters: &HashMap<&'input str, u16>,
     ) -> Result<(), CompileError<'input>> {
         let m = match n {
             Node::Metadata(m) => m,
@@ -161,13 +729,23 @@ impl Compiler {
             Metadata::BgColor(x) => Self::write_string(w, x),
             Metadata::FgColor(x) => Self::write_string(w, x),
             Metadata::Symmetries(x) => w.write_u8(x.bits() as u8).map_err(|x| x.into()),
-            Metadata::Field(i, f) => {
+            Metadata::Field(i, f) | Metadata::Bond(i, f) => {
                 Self::write_string(w, i)?;
                 w.write_u16::<BigEndian>(f.as_u16()).map_err(|x| x.into())
             }
-            Metadata::Parameter(i, c) => {
+            Metadata::Parameter(i, _) => {
                 Self::write_string(w, i)?;
-                Self::write_u96(w, c).map_err(|x| x.into())
+                w.write_u16::<BigEndian>(parameters[i])
+                    .map_err(|x| x.into())
+            }
+            Metadata::Arithmetic(x) | Metadata::FieldWrites(x) => {
+                w.write_u8(x.as_u8()).map_err(|x| x.into())
+            }
+            Metadata::DivZero(x) => w.write_u8(x.as_u8()).map_err(|x| x.into()),
+            Metadata::MfmType(x) => w.write_u16::<BigEndian>(x).map_err(|x| x.into()),
+            Metadata::Color(x) => {
+                let c: Color = x.parse().map_err(|_| CompileError::BadColor(x))?;
+                w.write_u32::<BigEndian>(c.bits()).map_err(|x| x.into())
             }
         }
     }
@@ -179,32 +757,108 @@ impl Compiler {
         todo!()
     }
 
-    fn write_instruction<'input, W: WriteBytesExt>(
-        w: &mut W,
+    /// Resolves the target of a jump or call at instruction `ip` to an
+    /// instruction index.
+    fn resolve_target<'input>(
         n: Node<'input>,
-        type_map: &HashMap<String, u16>,
+        ip: u16,
+        code_lines: u16,
         label_map: &HashMap<&'input str, u16>,
-        const_map: &HashMap<&'input str, Const>,
-        field_map: &HashMap<&'input str, base::FieldSelector>,
-    ) -> Result<(), CompileError<'input>> {
+    ) -> Result<Node<'input>, CompileError<'input>> {
+        let target = |t: Target<'input>| -> Result<Arg<Target<'input>, u16>, CompileError<'input>> {
+            let dest = match t {
+                Target::Label(i) => {
+                    return label_map.get(i).map(|x| Arg::Runtime(*x)).ok_or_else(|| {
+                        let suggestion = suggest(i, label_map.keys().copied());
+                        CompileError::UnknownLabel(i, suggestion)
+                    })
+                }
+                Target::Absolute(x) => x.parse::<i64>().ok(),
+                Target::Relative(x) => x.parse::<i64>().ok().map(|d| ip as i64 + d),
+            };
+            // Jumping just past the last instruction ends the event, the same
+            // as a label at the end of the program.
+            dest.filter(|d| (0..=code_lines as i64).contains(d))
+                .map(|d| Arg::Runtime(d as u16))
+                .ok_or(CompileError::BadTarget(t.text(), code_lines))
+        };
         let i = match n {
-            Node::Label(_) => return Ok(()),
             Node::Instruction(i) => i,
-            _ => return Err(CompileError::InternalUnexpectedNodeType),
+            n => return Ok(n),
+        };
+        Ok(Node::Instruction(match i {
+            Instruction::Call(x) => match target(*x.ast())? {
+                // Calling past the last instruction would only end the
+                // event, which is almost certainly a mistake.
+                Arg::Runtime(d) if d == code_lines => {
+                    return Err(CompileError::BadCallTarget(x.ast().text()))
+                }
+                t => Instruction::Call(t),
+            },
+            Instruction::Jump(x) => Instruction::Jump(target(*x.ast())?),
+            Instruction::JumpZero(x) => Instruction::JumpZero(target(*x.ast())?),
+            Instruction::JumpNonZero(x) => Instruction::JumpNonZero(target(*x.ast())?),
+            Instruction::JumpIf(c, x) => Instruction::JumpIf(c, target(*x.ast())?),
+            i => i,
+        }))
+    }
+
+    fn write_instruction<'input, W: WriteBytesExt>(
+        w: &mut W,
+        i: Instruction<'input>,
+        types: &TypeTable<'input>,
+        const_pool: &ConstPool,
+        program: &Program<'input>,
+    ) -> Result<(), CompileError<'input>> {
+        let field_map = &program.field_map;
+        let field = |i: &'input str| {
+            field_map.get(i).ok_or_else(|| {
+                let suggestion = suggest(i, field_map.iter().map(|(name, _)| name));
+                CompileError::UnknownField(i, suggestion)
+            })
         };
         w.write_u8(i.as_u8())?;
         match i {
             Instruction::Nop => Ok(()),
             Instruction::Exit => Ok(()),
-            Instruction::SwapSites => Ok(()),
+            Instruction::SwapSites
+            | Instruction::MoveSite
+            | Instruction::Diffuse
+            | Instruction::CountOf
+            | Instruction::FindFirst
+            | Instruction::FindRandom
+            | Instruction::Rand
+            | Instruction::OddsOf
+            | Instruction::RandSite
+            | Instruction::Compare
+            | Instruction::Assert
+            | Instruction::PopCount
+            | Instruction::LeadingZeros
+            | Instruction::TrailingZeros
+            | Instruction::RotateLeft
+            | Instruction::RotateRight
+            | Instruction::Min
+            | Instruction::Max
+            | Instruction::Clamp
+            | Instruction::Abs
+            | Instruction::MulHigh => Ok(()),
+            Instruction::ZeroExtend(n) | Instruction::SignExtend(n) | Instruction::Truncate(n) => {
+                w.write_u8(n)
+            }
+            Instruction::GetRegister(n) | Instruction::SetRegister(n) | Instruction::Count(n) => {
+                w.write_u8(n)
+            }
             Instruction::SetSite => Ok(()),
-            Instruction::SetField(x) => w.write_u16::<BigEndian>(field_map[x.ast()].as_u16()),
-            Instruction::SetSiteField(x) => w.write_u16::<BigEndian>(field_map[x.ast()].as_u16()),
+            Instruction::SetField(x) => w.write_u16::<BigEndian>(field(x.ast())?.as_u16()),
+            Instruction::SetSiteField(x) => w.write_u16::<BigEndian>(field(x.ast())?.as_u16()),
             Instruction::GetSite => Ok(()),
-            Instruction::GetField(x) => w.write_u16::<BigEndian>(field_map[x.ast()].as_u16()),
-            Instruction::GetSiteField(x) => w.write_u16::<BigEndian>(field_map[x.ast()].as_u16()),
-            Instruction::GetType(x) => w.write_u16::<BigEndian>(type_map[x.ast().to_owned()]),
-            Instruction::GetParameter(x) => Self::write_u96(w, const_map[x.ast()]),
+            Instruction::GetField(x) => w.write_u16::<BigEndian>(field(x.ast())?.as_u16()),
+            Instruction::GetSiteField(x) => w.write_u16::<BigEndian>(field(x.ast())?.as_u16()),
+            Instruction::Bond(x) | Instruction::Unbond(x) | Instruction::FollowBond(x) => {
+                w.write_u16::<BigEndian>(field(x.ast())?.as_u16())
+            }
+            Instruction::GetType(x) => w.write_u16::<BigEndian>(types.index(x.ast(), program.name)),
+            Instruction::GetParameter(x) => w.write_u16::<BigEndian>(program.parameters[x.ast()]),
             Instruction::Scan => Ok(()),
             Instruction::SaveSymmetries => Ok(()),
             Instruction::UseSymmetries(x) => w.write_u8(x.bits() as u8),
@@ -250,10 +904,10 @@ impl Compiler {
             | Instruction::Push38
             | Instruction::Push39
             | Instruction::Push40 => Ok(()),
-            Instruction::Push(x) => Self::write_u96(w, x),
+            Instruction::Push(x) => w.write_u16::<BigEndian>(const_pool.index(x)),
             Instruction::Pop | Instruction::Dup | Instruction::Over | Instruction::Swap => Ok(()),
             Instruction::Rot => Ok(()),
-            Instruction::Call(x) => w.write_u16::<BigEndian>(label_map[x.ast()]),
+            Instruction::Call(x) => w.write_u16::<BigEndian>(*x.runtime()),
             Instruction::Ret => Ok(()),
             Instruction::Checksum => Ok(()),
             Instruction::Add
@@ -273,62 +927,287 @@ impl Compiler {
             | Instruction::BitScanReverse
             | Instruction::LShift
             | Instruction::RShift => Ok(()),
-            Instruction::Jump(x) => w.write_u16::<BigEndian>(label_map[x.ast()]),
+            Instruction::Jump(x) => w.write_u16::<BigEndian>(*x.runtime()),
             Instruction::JumpRelativeOffset => Ok(()),
-            Instruction::JumpZero(x) => w.write_u16::<BigEndian>(label_map[x.ast()]),
-            Instruction::JumpNonZero(x) => w.write_u16::<BigEndian>(label_map[x.ast()]),
-            Instruction::SetPaint | Instruction::GetPaint => Ok(()),
+            Instruction::JumpZero(x) => w.write_u16::<BigEndian>(*x.runtime()),
+            Instruction::JumpNonZero(x) => w.write_u16::<BigEndian>(*x.runtime()),
+            Instruction::JumpIf(_, x) => w.write_u16::<BigEndian>(*x.runtime()),
+            Instruction::SetPaint
+            | Instruction::GetPaint
+            | Instruction::GetLayer
+            | Instruction::SetLayer
+            | Instruction::DecayLayer
+            | Instruction::GetHeat
+            | Instruction::EmitHeat
+            | Instruction::AbsorbHeat
+            | Instruction::Hash
+            | Instruction::NewId => Ok(()),
         }
         .map_err(|x| x.into())
     }
 
-    pub fn compile_to_writer<'input, W: WriteBytesExt>(
-        &'input mut self,
-        w: &mut W,
+    /// Parses `src` and indexes its header, adding its parameters to
+    /// `const_pool`.
+    fn index_header<'input>(
         src: &'input str,
-    ) -> Result<(), CompileError<'input>> {
-        let ast = substrate::FileParser::new().parse(src)?;
+        const_pool: &mut ConstPool,
+    ) -> Result<Program<'input>, CompileError<'input>> {
+        let params = RefCell::new(HashMap::new());
+        let ast = substrate::FileParser::new().parse(&params, src)?;
 
         if ast.body.len() > Self::MAX_CODE_SIZE {
             return Err(CompileError::MaxCodeSize);
         }
 
-        let mut code_index: HashMap<u16, CodeEntry> = HashMap::new();
-        let mut label_map: HashMap<&'input str, u16> = HashMap::new();
+        let mut name = None;
         let mut const_map: HashMap<&'input str, Const> = HashMap::new();
-        let mut field_map: HashMap<&'input str, base::FieldSelector> = Self::new_field_map();
-
+        let mut parameters = HashMap::new();
+        let mut field_map = FieldMap::new();
         for n in ast.header.iter() {
-            Self::index_metadata_node(*n, &mut self.type_map, &mut const_map, &mut field_map)?;
+            Self::index_metadata_node(
+                *n,
+                &mut name,
+                &mut const_map,
+                &mut parameters,
+                const_pool,
+                &mut field_map,
+            )?;
         }
+        Ok(Program {
+            ast,
+            name: name.ok_or(CompileError::MissingName)?,
+            code_index: HashMap::new(),
+            label_map: HashMap::new(),
+            const_map,
+            parameters,
+            field_map,
+            code_lines: 0,
+            ir: Ir::default(),
+        })
+    }
 
-        let code_lines = {
-            let mut ln = 0u16;
-            for n in ast.body.iter() {
-                Self::index_code_node(&mut ln, *n, &mut code_index, &mut label_map)?;
+    /// Indexes the code of `program`, lowers it to its `Ir`, verifies it and
+    /// optimizes it, adding its constants to `const_pool` and the types it
+    /// names to `types`.
+    fn index_body<'input>(
+        &self,
+        program: &mut Program<'input>,
+        types: &mut TypeTable<'input>,
+        const_pool: &mut ConstPool,
+    ) -> Result<(), CompileError<'input>> {
+        let mut ln = 0u16;
+        for n in program.ast.body.iter() {
+            Self::index_code_node(
+                &mut ln,
+                *n,
+                &mut program.code_index,
+                &mut program.label_map,
+                types,
+                &program.const_map,
+            )?;
+        }
+        program.code_lines = ln;
+
+        let mut body = Vec::with_capacity(program.ast.body.len());
+        let mut ip = 0;
+        for n in program.ast.body.iter() {
+            body.push(Self::resolve_target(
+                *n,
+                ip,
+                program.code_lines,
+                &program.label_map,
+            )?);
+            if let Node::Instruction(_) = n {
+                ip += 1;
             }
-            ln
-        };
+        }
+        let mut ir = Ir::new(&body, &program.ast.offsets);
+        log::debug!(
+            target: "substrate::compile",
+            "{}: lowered {} instructions",
+            program.name,
+            ir.code.len()
+        );
+        verify::verify(&ir, &program.field_map, &program.ast.header)?;
+        log::debug!(target: "substrate::compile", "{}: verified", program.name);
+
+        if self.opt_level > 0 || self.ir_hook.is_some() {
+            ir = self.optimize(program, ir);
+            log::debug!(
+                target: "substrate::compile",
+                "{}: optimized at -O{} to {} instructions",
+                program.name,
+                self.opt_level,
+                ir.code.len()
+            );
+        }
+        for i in ir.code.iter() {
+            if let Instruction::Push(x) = i {
+                const_pool.insert(*x)?;
+            }
+        }
+        program.ir = ir;
+        Ok(())
+    }
 
-        w.write_u32::<BigEndian>(MAGIC_NUMBER)?;
+    /// Optimizes `ir`, the verified code of `program`, at the compiler's
+    /// level.
+    fn optimize<'input>(&self, program: &Program<'input>, ir: Ir<'input>) -> Ir<'input> {
+        let mode = program
+            .ast
+            .header
+            .iter()
+            .rev()
+            .find_map(|n| match n {
+                Node::Metadata(Metadata::Arithmetic(m)) => Some(*m),
+                _ => None,
+            })
+            .unwrap_or_default();
+        let optimized = optimize::optimize(&ir, mode, self.opt_level);
+        if let Some(hook) = self.ir_hook.as_ref() {
+            hook(program.name, &ir.dump(), &optimized.dump());
+        }
+        optimized
+    }
+
+    /// Writes everything before the elements: the version, the build tag, the
+    /// type table and the constant pool.
+    fn write_header<'input, W: WriteBytesExt>(
+        &'input self,
+        w: &mut W,
+        magic: u32,
+        types: &TypeTable<'input>,
+        const_pool: &ConstPool,
+    ) -> Result<(), CompileError<'input>> {
+        w.write_u32::<BigEndian>(magic)?;
         w.write_u16::<BigEndian>(Self::MINOR_VERSION)?;
         w.write_u16::<BigEndian>(Self::MAJOR_VERSION)?;
         Self::write_string(w, self.build_tag.as_str())?;
-        w.write_u16::<BigEndian>(self.type_map["Self"])?;
+        w.write_u16::<BigEndian>(types.names.len() as u16)?;
+        for name in types.names.iter() {
+            Self::write_string(w, name)?;
+        }
 
-        w.write_u8(ast.header.len() as u8)?;
-        for e in ast.header.iter() {
-            Self::write_metadata(w, *e, &const_map, &field_map)?;
+        w.write_u16::<BigEndian>(const_pool.consts.len() as u16)?;
+        for c in const_pool.consts.iter() {
+            Self::write_const(w, *c)?;
+        }
+        Ok(())
+    }
+
+    /// Writes an element: its metadata, code and labels.
+    fn write_program<'input, W: WriteBytesExt>(
+        w: &mut W,
+        program: &Program<'input>,
+        types: &TypeTable<'input>,
+        const_pool: &ConstPool,
+    ) -> Result<(), CompileError<'input>> {
+        w.write_u8(program.ast.header.len() as u8)?;
+        for e in program.ast.header.iter() {
+            Self::write_metadata(w, *e, &program.parameters)?;
         }
 
-        w.write_u16::<BigEndian>(code_index.len() as u16)?;
-        // Self::write_code_index(w, &code_index)?;
+        w.write_u16::<BigEndian>(program.code_index.len() as u16)?;
+        // Self::write_code_index(w, &program.code_index)?;
 
-        w.write_u16::<BigEndian>(code_lines)?;
-        for e in ast.body.iter() {
-            Self::write_instruction(w, *e, &self.type_map, &label_map, &const_map, &field_map)?;
+        let ir = &program.ir;
+        w.write_u16::<BigEndian>(ir.code.len() as u16)?;
+        for i in ir.code.iter() {
+            Self::write_instruction(w, *i, types, const_pool, program)?;
         }
 
+        let mut labels = ir.labels.clone();
+        labels.sort_by_key(|(i, ip)| (*ip, *i));
+        w.write_u16::<BigEndian>(labels.len() as u16)?;
+        for (i, ip) in labels {
+            Self::write_string(w, i)?;
+            w.write_u16::<BigEndian>(ip)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn compile_to_writer<'input, W: WriteBytesExt>(
+        &'input mut self,
+        w: &mut W,
+        src: &'input str,
+    ) -> Result<(), CompileError<'input>> {
+        let mut const_pool = ConstPool::new();
+        let mut types = TypeTable::new();
+        let mut program = Self::index_header(src, &mut const_pool)?;
+        types.insert(program.name);
+        self.index_body(&mut program, &mut types, &mut const_pool)?;
+
+        self.write_header(w, MAGIC_NUMBER, &types, &const_pool)?;
+        Self::write_program(w, &program, &types, &const_pool)?;
+        log::debug!(
+            target: "substrate::compile",
+            "{}: wrote {} constants and {} types",
+            program.name,
+            const_pool.consts.len(),
+            types.names.len()
+        );
+        Ok(())
+    }
+
+    /// Compiles the elements in `srcs` into one bundle, sharing a constant pool
+    /// and a type table, so a set of elements can be loaded from one file.
+    /// Every type the elements name must be one of them, Empty or one of
+    /// `externs`, such as a standard element loaded beside the bundle. Errors
+    /// come with the index of the source they are in.
+    pub fn link_to_writer<'input, W: WriteBytesExt>(
+        &'input self,
+        w: &mut W,
+        srcs: &[&'input str],
+        externs: &[&str],
+    ) -> Result<(), (usize, CompileError<'input>)> {
+        let mut const_pool = ConstPool::new();
+        let mut types = TypeTable::new();
+        let mut programs = Vec::new();
+        for (i, src) in srcs.iter().enumerate() {
+            let program = Self::index_header(src, &mut const_pool).map_err(|e| (i, e))?;
+            if types.names.contains(&program.name) {
+                return Err((i, CompileError::DuplicateElement(program.name)));
+            }
+            types.insert(program.name);
+            programs.push(program);
+        }
+        for (i, program) in programs.iter_mut().enumerate() {
+            self.index_body(program, &mut types, &mut const_pool)
+                .map_err(|e| (i, e))?;
+        }
+
+        let linked = &types.names[..programs.len()];
+        for (i, program) in programs.iter().enumerate() {
+            for n in program.ast.body.iter() {
+                let name = match n {
+                    Node::Instruction(Instruction::GetType(x)) => *x.ast(),
+                    _ => continue,
+                };
+                if name != "Self"
+                    && name != EMPTY
+                    && !linked.contains(&name)
+                    && !externs.contains(&name)
+                {
+                    let suggestion = suggest(name, linked.iter().copied());
+                    return Err((i, CompileError::UnresolvedType(name, suggestion)));
+                }
+            }
+        }
+
+        self.write_header(w, BUNDLE_MAGIC_NUMBER, &types, &const_pool)
+            .map_err(|e| (0, e))?;
+        w.write_u16::<BigEndian>(programs.len() as u16)
+            .map_err(|e| (0, e.into()))?;
+        for (i, program) in programs.iter().enumerate() {
+            Self::write_program(w, program, &types, &const_pool).map_err(|e| (i, e))?;
+        }
+        log::debug!(
+            target: "substrate::compile",
+            "linked {} elements sharing {} constants",
+            programs.len(),
+            const_pool.consts.len()
+        );
         Ok(())
     }
 }
@@ -0,0 +1,500 @@
+//! An interactive debugger which runs one event an instruction at a time.
+//!
+//! The event stops before its first instruction and at every breakpoint.
+//! Each time it stops, the debugger shows the event window, the operand stack
+//! and the next instruction, and reads commands from stdin:
+//!
+//! ```text
+//! step, s         run one instruction (also an empty line)
+//! next, n         run one instruction, running calls to their return
+//! finish, f       run until the current routine returns
+//! continue, c     run to the next breakpoint or the end of the event
+//! break, b [AT]   set a breakpoint at a label or instruction, or list them
+//! delete, d AT    remove a breakpoint
+//! list, l         show the instructions around the next one
+//! stack           show the operand stack, bottom first, and the calls
+//! registers, r    show the registers and flags
+//! window, w       show the event window
+//! site N          show the atom at site N and its fields
+//! goto, g N       debug event N of the run instead, with --event
+//! quit, q         stop the event where it is
+//! help, h         list the commands
+//! ```
+//!
+//! The window is drawn as the grid holds it, with each site showing the
+//! symbol of its element, while site numbers such as those of `site N` are
+//! mapped through the symmetry the event runs with, as the element sees them.
+//!
+//! The event also stops after any instruction which hits one of the runtime's
+//! watchpoints, showing what it stored.
+//!
+//! Debugging an event of a run, the debugger keeps the run in a `Timeline`,
+//! so `goto` can go back to an earlier event as well as on to a later one,
+//! rebuilding the grid as it was before that event. Once an event ends, a
+//! `step` or empty line goes on to the next one.
+
+use crate::base::{FieldSelector, SiteNumber};
+use crate::disasm;
+use crate::runtime::grid::Grid;
+use crate::runtime::mfm::EventWindow;
+use crate::runtime::timeline::Timeline;
+use crate::runtime::{Cursor, Error, Runtime};
+use atty::Stream;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, Write};
+use std::process::exit;
+
+/// The instructions shown by `list` before and after the next one.
+const LIST_CONTEXT: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    /// Stop before every instruction.
+    Step,
+    /// Stop once no more than this many calls are pending.
+    Next(usize),
+    /// Stop once fewer than this many calls are pending.
+    Finish(usize),
+    /// Stop only at breakpoints.
+    Continue,
+}
+
+/// How debugging an event ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stop {
+    /// The event ran to its end.
+    Ended,
+    Quit,
+    /// `goto` asked for this event of the run.
+    Goto(u64),
+}
+
+/// An event number, which may be written with separators, as in `1,234,567`.
+fn event_number(s: &str) -> Option<u64> {
+    s.replace([',', '_'], "").parse().ok()
+}
+
+/// The window sites of `mask` as a list, as in `0, 1, 5`.
+fn sites(mask: u64) -> String {
+    let sites: Vec<String> = (0..SiteNumber::COUNT)
+        .filter(|i| mask & (1 << i) != 0)
+        .map(|i| i.to_string())
+        .collect();
+    match sites.len() {
+        0 => "none".to_owned(),
+        _ => sites.join(", "),
+    }
+}
+
+/// What the debugger knows of an element.
+struct Element {
+    name: String,
+    symbol: char,
+    fields: Vec<(String, FieldSelector)>,
+}
+
+struct Debugger {
+    /// The event's instructions, disassembled.
+    listing: Vec<String>,
+    labels: Vec<(String, u16)>,
+    elements: HashMap<u16, Element>,
+    breakpoints: BTreeSet<usize>,
+    mode: Mode,
+    /// The watchpoint hits already shown.
+    hits: usize,
+    /// Whether the event is one of a run, which `goto` can leave.
+    timeline: bool,
+    stop: Stop,
+}
+
+impl Debugger {
+    /// The instruction a breakpoint names, by label or number.
+    fn resolve(&self, at: &str) -> Result<usize, String> {
+        if let Some((_, ip)) = self.labels.iter().find(|(name, _)| name == at) {
+            return Ok(*ip as usize);
+        }
+        match at.parse::<usize>() {
+            Ok(ip) if ip < self.listing.len() => Ok(ip),
+            Ok(ip) => Err(format!("no instruction {}", ip)),
+            Err(_) => Err(format!("no label {}", at)),
+        }
+    }
+
+    /// Instruction `ip` with its labels, as in `12 loop: dup`.
+    fn line(&self, ip: usize) -> String {
+        let mut s = format!("{:>4} ", ip);
+        for (name, _) in self.labels.iter().filter(|(_, i)| *i as usize == ip) {
+            s.push_str(name);
+            s.push_str(": ");
+        }
+        s.push_str(&self.listing[ip]);
+        s
+    }
+
+    fn element(&self, t: u16) -> Option<&Element> {
+        self.elements.get(&t)
+    }
+
+    fn window(&self, ew: &EventWindow) {
+        let mut rows = [[' '; 9]; 9];
+        for (i, atom) in ew.atoms().iter().enumerate() {
+            let (dx, dy) = SiteNumber(i as u8).coord().unwrap();
+            rows[(dy + 4) as usize][(dx + 4) as usize] = if atom.is_empty() {
+                '.'
+            } else {
+                self.element(atom.get_type()).map_or('?', |e| e.symbol)
+            };
+        }
+        println!("window, symmetry {}:", ew.symmetry());
+        for row in rows.iter() {
+            let row: Vec<String> = row.iter().map(char::to_string).collect();
+            println!("  {}", row.join(" ").trim_end());
+        }
+    }
+
+    fn stack(&self, cursor: &Cursor) {
+        let stack: Vec<String> = cursor.op_stack().iter().map(|c| c.to_string()).collect();
+        println!("stack [{}]", stack.join(", "));
+        if !cursor.call_stack().is_empty() {
+            let calls: Vec<String> = cursor.call_stack().iter().map(|c| c.to_string()).collect();
+            println!("calls from {}", calls.join(", "));
+        }
+    }
+
+    fn registers(&self, cursor: &Cursor) {
+        for (n, row) in cursor.registers().chunks(5).enumerate() {
+            let row: Vec<String> = row
+                .iter()
+                .enumerate()
+                .map(|(i, v)| format!("R{:<2} {:<10}", n * 5 + i, v.to_string()))
+                .collect();
+            println!("{}", row.join(" ").trim_end());
+        }
+        let f = cursor.flags();
+        println!(
+            "zero {} negative {} carry {} overflow {}",
+            f.zero as u8, f.negative as u8, f.carry as u8, f.overflow as u8
+        );
+    }
+
+    fn list(&self, ip: usize) {
+        let first = ip.saturating_sub(LIST_CONTEXT);
+        let last = (ip + LIST_CONTEXT).min(self.listing.len() - 1);
+        for i in first..=last {
+            let mark = match (i == ip, self.breakpoints.contains(&i)) {
+                (true, _) => "=>",
+                (false, true) => " *",
+                (false, false) => "  ",
+            };
+            println!("{}{}", mark, self.line(i));
+        }
+    }
+
+    fn site(&self, ew: &EventWindow, n: &str) {
+        let atom = match n.parse::<usize>().ok().and_then(|i| ew.get(i)) {
+            Some(atom) => *atom,
+            None => {
+                println!("sites are numbered 0 to {}", SiteNumber::COUNT - 1);
+                return;
+            }
+        };
+        let element = self.element(atom.get_type());
+        match element {
+            Some(e) => println!("{} {}", e.name, atom),
+            None => println!("type {} {}", atom.get_type(), atom),
+        }
+        if let Some(e) = element.filter(|_| !atom.is_empty()) {
+            for (name, f) in e.fields.iter() {
+                println!("  {:<16} {}", name, f.extract(atom.bits()));
+            }
+        }
+    }
+
+    /// Shows where the event stopped.
+    fn show(&self, cursor: &Cursor, ew: &EventWindow) {
+        self.window(ew);
+        self.stack(cursor);
+        println!("=>{}", self.line(cursor.ip()));
+    }
+
+    /// Called before each instruction. Returns false to stop the event.
+    fn step(&mut self, cursor: &Cursor, ew: &EventWindow) -> bool {
+        let depth = cursor.call_stack().len();
+        let stop = match self.mode {
+            Mode::Step => true,
+            Mode::Next(d) => depth <= d,
+            Mode::Finish(d) => depth < d,
+            Mode::Continue => false,
+        };
+        let hits = &cursor.watch_hits()[self.hits..];
+        for hit in hits {
+            println!("hit {}", hit);
+        }
+        self.hits += hits.len();
+        if !stop && hits.is_empty() && !self.breakpoints.contains(&cursor.ip()) {
+            return true;
+        }
+        self.show(cursor, ew);
+        loop {
+            if atty::is(Stream::Stdin) {
+                print!("(debug) ");
+                io::stdout().flush().ok();
+            }
+            let mut line = String::new();
+            if io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+                return false;
+            }
+            let mut words = line.split_whitespace();
+            let command = words.next().unwrap_or("step");
+            let arg = words.next();
+            match command {
+                "step" | "s" => self.mode = Mode::Step,
+                "next" | "n" => self.mode = Mode::Next(depth),
+                "finish" | "f" => self.mode = Mode::Finish(depth),
+                "continue" | "c" => self.mode = Mode::Continue,
+                "break" | "b" => {
+                    match arg.map(|at| self.resolve(at)) {
+                        Some(Ok(ip)) => {
+                            self.breakpoints.insert(ip);
+                        }
+                        Some(Err(e)) => println!("{}", e),
+                        None if self.breakpoints.is_empty() => println!("no breakpoints"),
+                        None => {
+                            for ip in self.breakpoints.iter() {
+                                println!(" *{}", self.line(*ip));
+                            }
+                        }
+                    }
+                    continue;
+                }
+                "delete" | "d" => {
+                    match arg.map(|at| self.resolve(at)) {
+                        Some(Ok(ip)) if self.breakpoints.remove(&ip) => {}
+                        Some(Ok(ip)) => println!("no breakpoint at {}", ip),
+                        Some(Err(e)) => println!("{}", e),
+                        None => println!("delete needs a label or instruction"),
+                    }
+                    continue;
+                }
+                "list" | "l" => {
+                    self.list(cursor.ip());
+                    continue;
+                }
+                "stack" => {
+                    self.stack(cursor);
+                    continue;
+                }
+                "registers" | "r" => {
+                    self.registers(cursor);
+                    continue;
+                }
+                "window" | "w" => {
+                    self.window(ew);
+                    continue;
+                }
+                "site" => {
+                    self.site(ew, arg.unwrap_or(""));
+                    continue;
+                }
+                "goto" | "g" if !self.timeline => {
+                    println!("goto needs a run; debug with --event");
+                    continue;
+                }
+                "goto" | "g" => match arg.and_then(event_number) {
+                    Some(event) => {
+                        self.stop = Stop::Goto(event);
+                        return false;
+                    }
+                    None => {
+                        println!("goto needs an event number");
+                        continue;
+                    }
+                },
+                "quit" | "q" => {
+                    self.stop = Stop::Quit;
+                    return false;
+                }
+                "help" | "h" => {
+                    println!("step, next, finish, continue, break [AT], delete AT, list, stack,");
+                    println!("registers, window, site N, goto N, quit");
+                    continue;
+                }
+                _ => {
+                    println!("unknown command {}; try help", command);
+                    continue;
+                }
+            }
+            return true;
+        }
+    }
+}
+
+/// Runs the event at site `(x, y)` of `grid` under the debugger, stopping at
+/// the instructions or labels in `breakpoints` and, unless `run` is set,
+/// before the first instruction.
+pub fn run(
+    grid: &Grid,
+    runtime: &mut Runtime,
+    x: usize,
+    y: usize,
+    breakpoints: &[String],
+    run: bool,
+) -> Result<(), Error> {
+    debug_event(grid, runtime, (x, y), breakpoints, run, false).0
+}
+
+/// Runs event number `event` of the run `timeline` keeps under the debugger,
+/// as `run` does, and then the events `goto` and `step` move to.
+pub fn travel(
+    timeline: &mut Timeline,
+    runtime: &mut Runtime,
+    mut event: u64,
+    breakpoints: &[String],
+    run: bool,
+) -> Result<(), Error> {
+    loop {
+        let grid = timeline.goto(runtime, event)?;
+        let (x, y) = grid.random_site(runtime.rng_mut());
+        println!("event {} at ({}, {})", event, x, y);
+        // A failed event is shown and may be gone back from.
+        let (_, stop) = debug_event(&grid, runtime, (x, y), breakpoints, run, true);
+        event = match stop {
+            Stop::Goto(event) => event,
+            Stop::Quit => return Ok(()),
+            Stop::Ended => match after_event(event) {
+                Some(event) => event,
+                None => return Ok(()),
+            },
+        };
+    }
+}
+
+/// Reads what to debug after event `event` ends: the event `goto` names, or
+/// the next one, or `None` to quit.
+fn after_event(event: u64) -> Option<u64> {
+    loop {
+        if atty::is(Stream::Stdin) {
+            print!("(debug) ");
+            io::stdout().flush().ok();
+        }
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+            return None;
+        }
+        let mut words = line.split_whitespace();
+        match words.next().unwrap_or("step") {
+            "step" | "s" => return Some(event + 1),
+            "goto" | "g" => match words.next().and_then(event_number) {
+                Some(event) => return Some(event),
+                None => println!("goto needs an event number"),
+            },
+            "quit" | "q" => return None,
+            _ => println!(
+                "event {} has ended; step to the next, goto N or quit",
+                event
+            ),
+        }
+    }
+}
+
+/// Runs the event at `site` of `grid` under the debugger, as `run` describes,
+/// returning how it ended. Breakpoints the element has no label or
+/// instruction for are an error unless the event is one of a run.
+fn debug_event(
+    grid: &Grid,
+    runtime: &mut Runtime,
+    (x, y): (usize, usize),
+    breakpoints: &[String],
+    run: bool,
+    timeline: bool,
+) -> (Result<(), Error>, Stop) {
+    let atom = match grid.get(x, y) {
+        Some(atom) => atom,
+        None => return (Err(Error::SiteOutOfBounds(x, y)), Stop::Ended),
+    };
+    let t = atom.get_type();
+    let code = match runtime.code(t) {
+        Some(code) => code,
+        None => return (Err(Error::UnknownElement(t)), Stop::Ended),
+    };
+    let elements = runtime
+        .elements()
+        .map(|(t, m)| {
+            let mut fields: Vec<(String, FieldSelector)> = m
+                .field_map
+                .user_fields()
+                .map(|(name, f)| (name.to_owned(), f))
+                .collect();
+            fields.sort_by_key(|(_, f)| f.offset);
+            let element = Element {
+                name: m.name.clone(),
+                symbol: m.symbol.chars().next().unwrap_or('?'),
+                fields,
+            };
+            (t, element)
+        })
+        .collect();
+    let mut debugger = Debugger {
+        listing: (0..code.len())
+            .map(|ip| disasm::instruction(runtime, t, ip).unwrap())
+            .collect(),
+        labels: runtime.labels(t).unwrap_or_default().to_vec(),
+        elements,
+        breakpoints: BTreeSet::new(),
+        mode: if run { Mode::Continue } else { Mode::Step },
+        hits: 0,
+        timeline,
+        stop: Stop::Ended,
+    };
+    if debugger.listing.is_empty() {
+        println!("{} has no instructions", debugger.elements[&t].name);
+        return (Ok(()), Stop::Ended);
+    }
+    for at in breakpoints {
+        match debugger.resolve(at) {
+            Ok(ip) => {
+                debugger.breakpoints.insert(ip);
+            }
+            Err(_) if timeline => {}
+            Err(e) => {
+                eprintln!("Bad breakpoint: {}", e);
+                exit(1)
+            }
+        }
+    }
+
+    let result = grid.event_at_stepping(runtime, x, y, &mut |cursor, ew| debugger.step(cursor, ew));
+    if let Stop::Goto(event) = debugger.stop {
+        return (Ok(()), Stop::Goto(event));
+    }
+    let cursor = runtime.cursor();
+    match &result {
+        Ok(()) | Err(Error::Watchpoint { .. }) => {
+            println!("event ended after {} instructions", cursor.executed())
+        }
+        Err(e) => println!("event failed: {}\n  {}", e, cursor),
+    }
+    for hit in &cursor.watch_hits()[debugger.hits..] {
+        println!("hit {}", hit);
+    }
+    println!(
+        "sites read: {}; written: {}",
+        sites(cursor.sites_read()),
+        sites(cursor.sites_written())
+    );
+    let mut window = EventWindow::new();
+    for (i, atom) in window.atoms_mut().iter_mut().enumerate() {
+        let (dx, dy) = SiteNumber(i as u8).coord().unwrap();
+        let (i, j) = (x as isize + dx as isize, y as isize + dy as isize);
+        if i >= 0 && j >= 0 {
+            *atom = grid.get(i as usize, j as usize).unwrap_or_default();
+        }
+    }
+    window.set_symmetry(runtime.event_symmetry());
+    debugger.window(&window);
+    let result = match result {
+        Err(Error::Watchpoint { .. }) => Ok(()),
+        result => result,
+    };
+    (result, debugger.stop)
+}
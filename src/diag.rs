@@ -0,0 +1,130 @@
+//! Annotated error reports for the assembler.
+//!
+//! Diagnostics are rendered in the style of rustc, pointing into the source
+//! line the error was found on:
+//!
+//! ```text
+//! error: unknown field: helth
+//!   --> elements/res.s:12:14
+//!    |
+//! 12 |     getfield helth
+//!    |              ^^^^^
+//!    |
+//!    = help: did you mean `health`?
+//! ```
+
+use crate::preprocess::Expanded;
+use std::fmt::Write;
+use std::ops::Range;
+
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub message: String,
+    /// The byte range of the expanded source the error points at.
+    pub span: Option<Range<usize>>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(message: String, span: Option<Range<usize>>) -> Self {
+        Self {
+            message,
+            span,
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    /// Renders the diagnostic against the source it was found in. `name` is
+    /// used when the error has no span.
+    pub fn render(&self, name: &str, src: &Expanded) -> String {
+        let mut out = String::new();
+        writeln!(out, "error: {}", self.message).unwrap();
+        let span = match &self.span {
+            Some(span) => span,
+            None => {
+                writeln!(out, "  --> {}", name).unwrap();
+                self.render_footer(&mut out, 0);
+                return out;
+            }
+        };
+
+        let text = src.text.as_str();
+        let start = span.start.min(text.len());
+        let line_start = text[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = text[start..].find('\n').map_or(text.len(), |i| start + i);
+        let line = &text[line_start..line_end];
+        let column = text[line_start..start].chars().count();
+        let width = text[start..span.end.clamp(start, line_end)]
+            .chars()
+            .count()
+            .max(1);
+
+        let origin = src.origin(start);
+        let number = origin.map_or(String::new(), |o| o.location.line.to_string());
+        let gutter = " ".repeat(number.len());
+        match origin {
+            Some(o) => writeln!(out, "{}--> {}:{}", gutter, o.location, column + 1),
+            None => writeln!(out, "{}--> {}", gutter, name),
+        }
+        .unwrap();
+        writeln!(out, "{} |", gutter).unwrap();
+        writeln!(out, "{} | {}", number, line).unwrap();
+        writeln!(
+            out,
+            "{} | {}{}",
+            gutter,
+            " ".repeat(column),
+            "^".repeat(width)
+        )
+        .unwrap();
+        let expansions = origin.map_or(0, |o| o.expansions.len());
+        if expansions + self.notes.len() > 0 || self.help.is_some() {
+            writeln!(out, "{} |", gutter).unwrap();
+        }
+        if let Some(o) = origin {
+            for (name, at) in o.expansions.iter().rev() {
+                writeln!(
+                    out,
+                    "{} = note: in macro `{}` expanded at {}",
+                    gutter, name, at
+                )
+                .unwrap();
+            }
+        }
+        self.render_footer(&mut out, number.len());
+        out
+    }
+
+    fn render_footer(&self, out: &mut String, indent: usize) {
+        let gutter = " ".repeat(indent);
+        for note in self.notes.iter() {
+            writeln!(out, "{} = note: {}", gutter, note).unwrap();
+        }
+        if let Some(help) = &self.help {
+            writeln!(out, "{} = help: {}", gutter, help).unwrap();
+        }
+    }
+}
+
+/// The candidate closest to `name`, if any is close enough to be a likely
+/// misspelling.
+pub fn suggest<'a, I: IntoIterator<Item = &'a str>>(name: &str, candidates: I) -> Option<String> {
+    let limit = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|c| (strsim::osa_distance(name, c), c))
+        .filter(|(d, _)| *d <= limit)
+        .min()
+        .map(|(_, c)| c.to_owned())
+}
+
+/// The byte range of `s` within `src`, if `s` was sliced out of it.
+pub fn span_of(src: &str, s: &str) -> Option<Range<usize>> {
+    let start = (s.as_ptr() as usize).checked_sub(src.as_ptr() as usize)?;
+    if start + s.len() > src.len() {
+        return None;
+    }
+    Some(start..start + s.len())
+}
@@ -0,0 +1,315 @@
+//! Converts loaded element programs back into assembly.
+//!
+//! Constants are printed as literals, field selectors by the name the element
+//! declares for them and jump and call targets by the labels of the source.
+//! Targets without a label get a generated one. Labels cannot contain digits,
+//! so the target's instruction index is spelled in letters (`_a` is 0, `_ba`
+//! is 26) and repeated in a comment. Parameters are recovered by
+//! value, so `getparameter` names the first parameter holding that value.
+//! Anything the bytecode no longer names is printed with a comment. Each
+//! routine, an instruction which is the target of a `call`, is set apart by a
+//! blank line and its label's comment lists the callers.
+//!
+//! Explained, each instruction is followed by a comment giving how many values
+//! the operand stack may hold before it, as `stack::analyze` finds: `depth 2`,
+//! or `depth 1..3` for the fewest and the most, or `depth 2..` if a loop may
+//! push without end. In a routine, depths count from the stack it was called
+//! with and name it, as `depth -1..+1 in two`. Instructions no path reaches
+//! are marked `unreachable`.
+
+use crate::ast::{Arg, Instruction};
+use crate::base::arith::{ArithMode, Const, DivZeroPolicy};
+use crate::base::FieldSelector;
+use crate::ir::Ir;
+use crate::preprocess::letters;
+use crate::runtime::mfm::Metadata;
+use crate::runtime::Runtime;
+use crate::stack::{self, Stack};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+fn field_name(m: &Metadata, f: FieldSelector) -> String {
+    let mut names: Vec<&str> = m
+        .field_map
+        .iter()
+        .filter(|(_, g)| *g == f)
+        .map(|(i, _)| i)
+        .collect();
+    names.sort_unstable();
+    match names.first() {
+        Some(i) => i.to_string(),
+        None => format!("_ /* offset {}, length {} */", f.offset, f.length),
+    }
+}
+
+fn generated_label(ip: u16) -> String {
+    format!("_{}", letters(ip as usize))
+}
+
+/// The labels of an element's source, by the instruction each marks.
+struct Labels<'a> {
+    names: BTreeMap<u16, Vec<&'a str>>,
+}
+
+impl<'a> Labels<'a> {
+    fn new(runtime: &'a Runtime, type_num: u16) -> Option<Self> {
+        let mut names: BTreeMap<u16, Vec<&str>> = BTreeMap::new();
+        for (name, ip) in runtime.labels(type_num)? {
+            names.entry(*ip).or_default().push(name);
+        }
+        Some(Self { names })
+    }
+
+    /// The first label of instruction `ip`, or a generated one.
+    fn name(&self, ip: u16) -> String {
+        match self.names.get(&ip) {
+            Some(names) => names[0].to_owned(),
+            None => generated_label(ip),
+        }
+    }
+}
+
+fn target<T>(x: &Arg<T, u16>) -> u16 {
+    match x {
+        Arg::Runtime(x) => *x,
+        Arg::Ast(_) => unreachable!("loaded code has resolved targets"),
+    }
+}
+
+/// The operand of `i`, an instruction of the element with metadata `m`, as
+/// written after its mnemonic.
+fn operand(runtime: &Runtime, m: &Metadata, i: &Instruction, labels: &Labels) -> String {
+    match i {
+        Instruction::SetField(Arg::Runtime(f))
+        | Instruction::SetSiteField(Arg::Runtime(f))
+        | Instruction::GetField(Arg::Runtime(f))
+        | Instruction::GetSiteField(Arg::Runtime(f))
+        | Instruction::Bond(Arg::Runtime(f))
+        | Instruction::Unbond(Arg::Runtime(f))
+        | Instruction::FollowBond(Arg::Runtime(f)) => format!(" {}", field_name(m, *f)),
+        Instruction::GetType(Arg::Runtime(t)) => match runtime.registry().name(*t) {
+            Some(name) => format!(" \"{}\"", name),
+            None => format!(" \"?\" /* type {} */", t),
+        },
+        Instruction::GetParameter(Arg::Runtime(c)) => {
+            let mut names: Vec<&String> = m
+                .parameter_map
+                .iter()
+                .filter(|(_, v)| *v == c)
+                .map(|(i, _)| i)
+                .collect();
+            names.sort();
+            match names.first() {
+                Some(i) => format!(" {}", i),
+                None => format!(" _ /* {} */", c.to_literal()),
+            }
+        }
+        Instruction::UseSymmetries(s) => format!(" {}", s),
+        Instruction::ZeroExtend(n)
+        | Instruction::SignExtend(n)
+        | Instruction::Truncate(n)
+        | Instruction::Count(n) => format!(" {}", n),
+        Instruction::GetRegister(n) | Instruction::SetRegister(n) => {
+            if *n == Instruction::REGISTERS {
+                " R?".to_owned()
+            } else {
+                format!(" R{}", n)
+            }
+        }
+        Instruction::Push(c) => format!(" {}", c.to_literal()),
+        Instruction::Call(x)
+        | Instruction::Jump(x)
+        | Instruction::JumpZero(x)
+        | Instruction::JumpNonZero(x)
+        | Instruction::JumpIf(_, x) => format!(" {}", labels.name(target(x))),
+        _ => String::new(),
+    }
+}
+
+fn write_metadata(out: &mut String, m: &Metadata) -> std::fmt::Result {
+    writeln!(out, ".name \"{}\"", m.name)?;
+    if !m.symbol.is_empty() {
+        writeln!(out, ".symbol \"{}\"", m.symbol)?;
+    }
+    for x in m.descs.iter() {
+        writeln!(out, ".desc \"{}\"", x)?;
+    }
+    for x in m.authors.iter() {
+        writeln!(out, ".author \"{}\"", x)?;
+    }
+    for x in m.licenses.iter() {
+        writeln!(out, ".license \"{}\"", x)?;
+    }
+    writeln!(out, ".radius {}", m.radius)?;
+    if !m.bg_color.is_empty() {
+        writeln!(out, ".bgcolor \"{}\"", m.bg_color)?;
+    }
+    if !m.fg_color.is_empty() {
+        writeln!(out, ".fgcolor \"{}\"", m.fg_color)?;
+    }
+    if let Some(c) = m.color {
+        writeln!(out, ".color {}", c)?;
+    }
+    writeln!(out, ".symmetries {}", m.symmetries)?;
+    let mut fields: Vec<(&str, FieldSelector)> = m.field_map.user_fields().collect();
+    fields.sort_by_key(|(_, f)| f.offset);
+    let (bonds, fields): (Vec<_>, Vec<_>) =
+        fields.into_iter().partition(|(_, f)| m.bonds.contains(f));
+    for (i, f) in fields {
+        writeln!(out, ".field {},{},{}", i, f.offset, f.length)?;
+    }
+    for (i, f) in bonds {
+        writeln!(out, ".bond {},{}", i, f.offset)?;
+    }
+    let mut params: Vec<(&String, &Const)> = m.parameter_map.iter().collect();
+    params.sort_by_key(|(i, _)| *i);
+    for (i, c) in params {
+        writeln!(out, ".parameter {}, {}", i, c.to_literal())?;
+    }
+    writeln!(out, ".arithmetic {}", m.arith_mode)?;
+    if m.field_writes != ArithMode::Wrapping {
+        writeln!(out, ".fieldwrites {}", m.field_writes)?;
+    }
+    if m.div_zero != DivZeroPolicy::Arithmetic {
+        writeln!(out, ".divzero {}", m.div_zero)?;
+    }
+    if let Some(t) = m.mfm_type {
+        writeln!(out, ".mfmtype {}", t)?;
+    }
+    Ok(())
+}
+
+/// The stack before `s`, as an explained disassembly prints it, within the
+/// routine `routine` if any.
+fn depth(s: &Stack, routine: Option<&str>) -> String {
+    let n = |d: i32| match routine {
+        Some(_) => format!("{:+}", d),
+        None => d.to_string(),
+    };
+    let mut out = match s.most {
+        Some(most) if most == s.depth => format!("depth {}", n(most)),
+        Some(most) => format!("depth {}..{}", n(s.depth), n(most)),
+        None => format!("depth {}..", n(s.depth)),
+    };
+    if let Some(r) = routine {
+        write!(out, " in {}", r).unwrap();
+    }
+    out
+}
+
+/// The stack before each instruction of `code`, as an explained disassembly
+/// prints it, or `None` if its paths can't be followed.
+fn depths(code: &[Instruction], labels: &Labels) -> Option<Vec<String>> {
+    let analysis = stack::analyze(&Ir::from_code(code))?;
+    let flows: Vec<(Option<String>, &stack::Flow)> = std::iter::once((None, &analysis.main))
+        .chain(
+            analysis
+                .routines
+                .iter()
+                .map(|(entry, f)| (Some(labels.name(*entry as u16)), f)),
+        )
+        .collect();
+    Some(
+        (0..code.len())
+            .map(|ip| {
+                let reached: Vec<String> = flows
+                    .iter()
+                    .filter_map(|(r, f)| Some(depth(f.states[ip].as_ref()?, r.as_deref())))
+                    .collect();
+                if reached.is_empty() {
+                    "unreachable".to_owned()
+                } else {
+                    reached.join("; ")
+                }
+            })
+            .collect(),
+    )
+}
+
+/// Disassembles the element with type number `type_num` loaded into
+/// `runtime`, or returns `None` if no such element is loaded. If `explain`,
+/// each instruction is followed by the depth of the stack before it.
+pub fn disassemble(runtime: &Runtime, type_num: u16, explain: bool) -> Option<String> {
+    let m = runtime.metadata(type_num)?;
+    let code = runtime.code(type_num)?;
+    let targets: BTreeSet<u16> = code
+        .iter()
+        .filter_map(|i| match i {
+            Instruction::Call(x)
+            | Instruction::Jump(x)
+            | Instruction::JumpZero(x)
+            | Instruction::JumpNonZero(x)
+            | Instruction::JumpIf(_, x) => Some(target(x)),
+            _ => None,
+        })
+        .collect();
+
+    let mut calls: BTreeMap<u16, Vec<usize>> = BTreeMap::new();
+    for (ip, i) in code.iter().enumerate() {
+        if let Instruction::Call(x) = i {
+            calls.entry(target(x)).or_default().push(ip);
+        }
+    }
+
+    let labels = Labels::new(runtime, type_num)?;
+
+    let mut out = String::new();
+    write_metadata(&mut out, m).unwrap();
+    writeln!(out).unwrap();
+    let depths = if explain {
+        let depths = depths(code, &labels);
+        if depths.is_none() {
+            writeln!(out, "/* the stack can't be followed through this code */").unwrap();
+        }
+        depths
+    } else {
+        None
+    };
+    for ip in 0..=code.len() as u16 {
+        let note = match calls.get(&ip) {
+            Some(callers) => {
+                if ip > 0 {
+                    writeln!(out).unwrap();
+                }
+                let callers: Vec<String> = callers.iter().map(|c| c.to_string()).collect();
+                format!("{}; routine called from {}", ip, callers.join(", "))
+            }
+            None => ip.to_string(),
+        };
+        match labels.names.get(&ip) {
+            Some(names) => {
+                for name in names {
+                    writeln!(out, "{}: /* {} */", name, note).unwrap();
+                }
+            }
+            None if targets.contains(&ip) => {
+                writeln!(out, "{}: /* {} */", generated_label(ip), note).unwrap();
+            }
+            None => {}
+        }
+        let i = match code.get(ip as usize) {
+            Some(i) => i,
+            None => break,
+        };
+        let text = format!("{}{}", i.mnemonic(), operand(runtime, m, i, &labels));
+        match &depths {
+            Some(depths) => writeln!(out, "    {:<24} /* {} */", text, depths[ip as usize]),
+            None => writeln!(out, "    {}", text),
+        }
+        .unwrap();
+    }
+    Some(out)
+}
+
+/// Disassembles instruction `ip` of the element with type number `type_num`
+/// as `disassemble` prints it, without indentation.
+pub fn instruction(runtime: &Runtime, type_num: u16, ip: usize) -> Option<String> {
+    let m = runtime.metadata(type_num)?;
+    let i = runtime.code(type_num)?.get(ip)?;
+    let labels = Labels::new(runtime, type_num)?;
+    Some(format!(
+        "{}{}",
+        i.mnemonic(),
+        operand(runtime, m, i, &labels)
+    ))
+}
@@ -0,0 +1,248 @@
+//! Reference documentation of the instruction set, the metadata directives
+//! and the builtins of the element language, printed by `ewac docs`.
+//!
+//! Everything listed is read from the tables the compiler itself uses: the
+//! opcodes, mnemonics, operands, stack effects and descriptions of
+//! `Instruction`, the flags each instruction sets or each conditional jump
+//! tests, `Metadata::DIRECTIVES` and the builtins `lang` lowers, so the
+//! reference can't drift from what `ewac` assembles and `ewar` runs.
+
+use crate::ast::{Condition, Instruction, Metadata};
+use crate::base::arith::Flags;
+use crate::lang;
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// How the reference is written.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Markdown,
+    Html,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "markdown" | "md" => Ok(Self::Markdown),
+            "html" => Ok(Self::Html),
+            _ => Err(format!("expected markdown or html, not {}", s)),
+        }
+    }
+}
+
+/// A section of the reference: a table with a paragraph above it. Text uses
+/// backticks for code, as Markdown does.
+struct Section {
+    heading: &'static str,
+    intro: &'static str,
+    columns: &'static [&'static str],
+    rows: Vec<Vec<String>>,
+}
+
+/// The flags `c` tests: those changing which way it jumps for some value of
+/// the others.
+fn tested(c: Condition) -> Vec<&'static str> {
+    let flags = |bits: u8| Flags {
+        zero: bits & 1 != 0,
+        negative: bits & 2 != 0,
+        carry: bits & 4 != 0,
+        overflow: bits & 8 != 0,
+    };
+    ["zero", "negative", "carry", "overflow"]
+        .iter()
+        .enumerate()
+        .filter(|(n, _)| (0..16u8).any(|b| c.holds(flags(b)) != c.holds(flags(b ^ 1 << n))))
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+/// How `i` is written, with its operand named in brackets.
+fn syntax(i: &Instruction) -> String {
+    match i.operand() {
+        Some(x) => format!("`{} [{}]`", i.mnemonic(), x),
+        None => format!("`{}`", i.mnemonic()),
+    }
+}
+
+fn instructions() -> Section {
+    let template = |op: u8| Instruction::template(op).unwrap();
+    // Consecutive opcodes which differ only by mnemonic, such as `push0` to
+    // `push40`, share a row.
+    let mut groups: Vec<(u8, u8, Vec<String>)> = Vec::new();
+    for op in 0..=Instruction::MAX {
+        let i = template(op);
+        let (pops, pushes) = i.stack_effect();
+        let flags = match i {
+            Instruction::JumpIf(c, _) => format!("tests {}", tested(c).join(", ")),
+            i if i.sets_flags() => "sets".to_owned(),
+            _ => String::new(),
+        };
+        let row = vec![
+            pops.to_string(),
+            pushes.to_string(),
+            flags,
+            i.description().to_owned(),
+        ];
+        match groups.last_mut() {
+            Some((_, last, r)) if *r == row => *last = op,
+            _ => groups.push((op, op, row)),
+        }
+    }
+    let rows = groups
+        .into_iter()
+        .map(|(first, last, row)| {
+            let mut cells = if first == last {
+                vec![first.to_string(), syntax(&template(first))]
+            } else {
+                vec![
+                    format!("{}-{}", first, last),
+                    format!(
+                        "{} to {}",
+                        syntax(&template(first)),
+                        syntax(&template(last))
+                    ),
+                ]
+            };
+            cells.extend(row);
+            cells
+        })
+        .collect();
+    Section {
+        heading: "Instructions",
+        intro: "`[0]` is the value on top of the operand stack, `[1]` the one below it and so on. \
+            Pops and Pushes count the values an instruction takes off the operand stack and \
+            puts back; `call` and `ret` only move the call stack. Arithmetic instructions and \
+            `cmp` set all four flags, which the conditional jumps test.",
+        columns: &[
+            "Opcode",
+            "Instruction",
+            "Pops",
+            "Pushes",
+            "Flags",
+            "Description",
+        ],
+        rows,
+    }
+}
+
+fn directives() -> Section {
+    let rows = Metadata::DIRECTIVES
+        .iter()
+        .zip(Metadata::USAGE.iter())
+        .map(|(d, (operands, description))| {
+            let operands: Vec<String> = operands.split(", ").map(|x| format!("[{}]", x)).collect();
+            vec![
+                format!("`{} {}`", d, operands.join(", ")),
+                (*description).to_owned(),
+            ]
+        })
+        .collect();
+    Section {
+        heading: "Metadata Directives",
+        intro: "Directives give an element's metadata, generally once each at the start of its \
+            source.",
+        columns: &["Directive", "Description"],
+        rows,
+    }
+}
+
+fn builtins() -> Section {
+    let rows = lang::BUILTINS
+        .iter()
+        .map(|(name, args, value, op)| {
+            vec![
+                format!("`{}`", name),
+                args.to_string(),
+                if *value { "yes" } else { "no" }.to_owned(),
+                format!("`{}`", op),
+            ]
+        })
+        .collect();
+    Section {
+        heading: "Builtins",
+        intro: "Builtins of the structured element language, compiled with `ewac --lang`. Each \
+            runs the instruction given, taking its arguments as the instruction's stack \
+            operands, deepest first, except that `setpaint`, `setlayer`, `emitheat` and \
+            `absorbheat` take the site first, `zext`, `sext` and `trunc` take the width, a \
+            constant, second and `count` takes its counter, a constant. `random` reads the register `R?` and `new` gives an atom of the \
+            type `type` names.",
+        columns: &["Builtin", "Arguments", "Gives a value", "Instruction"],
+        rows,
+    }
+}
+
+fn markdown(sections: &[Section]) -> String {
+    let mut out = String::new();
+    writeln!(out, "# EWAL Reference\n").unwrap();
+    writeln!(out, "Generated by `ewac docs`.").unwrap();
+    for s in sections.iter() {
+        writeln!(out, "\n## {}\n\n{}\n", s.heading, s.intro).unwrap();
+        writeln!(out, "|{}|", s.columns.join("|")).unwrap();
+        writeln!(out, "|{}|", vec!["---"; s.columns.len()].join("|")).unwrap();
+        for row in s.rows.iter() {
+            let cells: Vec<String> = row.iter().map(|c| c.replace('|', "\\|")).collect();
+            writeln!(out, "|{}|", cells.join("|")).unwrap();
+        }
+    }
+    out
+}
+
+/// `text` as HTML, with its backticked spans as code.
+fn html_text(text: &str) -> String {
+    let escaped = text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    let mut out = String::new();
+    for (n, part) in escaped.split('`').enumerate() {
+        if n % 2 == 1 {
+            write!(out, "<code>{}</code>", part).unwrap();
+        } else {
+            out.push_str(part);
+        }
+    }
+    out
+}
+
+fn html(sections: &[Section]) -> String {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>EWAL Reference</title>"
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "<style>table {{ border-collapse: collapse; }} th, td {{ border: 1px solid #ccc; padding: 2px 6px; text-align: left; }}</style>"
+    )
+    .unwrap();
+    writeln!(out, "</head>\n<body>\n<h1>EWAL Reference</h1>").unwrap();
+    writeln!(out, "<p>{}</p>", html_text("Generated by `ewac docs`.")).unwrap();
+    for s in sections.iter() {
+        writeln!(out, "<h2>{}</h2>\n<p>{}</p>", s.heading, html_text(s.intro)).unwrap();
+        writeln!(
+            out,
+            "<table>\n<tr><th>{}</th></tr>",
+            s.columns.join("</th><th>")
+        )
+        .unwrap();
+        for row in s.rows.iter() {
+            let cells: Vec<String> = row.iter().map(|c| html_text(c)).collect();
+            writeln!(out, "<tr><td>{}</td></tr>", cells.join("</td><td>")).unwrap();
+        }
+        writeln!(out, "</table>").unwrap();
+    }
+    writeln!(out, "</body>\n</html>").unwrap();
+    out
+}
+
+/// The reference documentation, written in `format`.
+pub fn reference(format: Format) -> String {
+    let sections = [instructions(), directives(), builtins()];
+    match format {
+        Format::Markdown => markdown(&sections),
+        Format::Html => html(&sections),
+    }
+}
@@ -0,0 +1,593 @@
+//! Embedding the engine.
+//!
+//! An `Engine` owns a runtime with its elements and a grid, and runs events
+//! on it without going through `ewac` and `ewar`:
+//!
+//! ```text
+//! let mut engine = Engine::builder()
+//!     .with_element(DREG_SRC)
+//!     .with_standard("Res")
+//!     .grid(64, 64)
+//!     .seed(42)
+//!     .build()?;
+//! let dreg = engine.new_atom("DReg").unwrap();
+//! engine.set(32, 32, dreg);
+//! engine.on_event(|grid, x, y| println!("event at ({}, {})", x, y));
+//! engine.run_events(10_000)?;
+//! ```
+//!
+//! Elements are loaded in the order they are given, so the first is numbered
+//! 1 unless the builder is given a registry.
+//!
+//! Analysis code can look for atoms without scanning the grid itself:
+//!
+//! ```text
+//! let old = engine.find("Res", |a| matches!(a.field("age"), Some(v) if v.as_i128() > 100));
+//! ```
+
+use crate::base::arith::Const;
+use crate::base::atom::Atom;
+use crate::base::color::Color;
+use crate::base::ecc;
+use crate::code::Compiler;
+use crate::preprocess;
+use crate::runtime::control::RunControl;
+use crate::runtime::energy::EnergyModel;
+use crate::runtime::grid::{Boundary, Grid, LAYERS};
+use crate::runtime::observer::EventObserver;
+use crate::runtime::registry::ElementRegistry;
+use crate::runtime::scenario::ParameterOverride;
+use crate::runtime::schedule::{Scheduler, Scheduling};
+use crate::runtime::stats::Report;
+use crate::runtime::{self, Runtime};
+use crate::stdlib;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror;
+
+#[derive(Debug, thiserror::Error)]
+pub enum EngineError {
+    #[error("failed to compile element {0}:\n{1}")]
+    Compile(usize, String),
+    #[error("no standard element named {0}")]
+    UnknownStandardElement(String),
+    #[error("bad grid size")]
+    BadGridSize,
+    #[error("no layer {0}")]
+    BadLayer(usize),
+    #[error(transparent)]
+    Runtime(#[from] runtime::Error),
+}
+
+/// An atom found by `Engine::find`, with the element's fields readable by
+/// name.
+pub struct Found<'a> {
+    pub x: usize,
+    pub y: usize,
+    pub atom: Atom,
+    pub type_id: u16,
+    runtime: &'a Runtime<'static>,
+}
+
+impl Found<'_> {
+    /// The value of the atom's field `name`, or `None` if its element has no
+    /// such field.
+    pub fn field(&self, name: &str) -> Option<Const> {
+        let f = self.runtime.get_field(self.type_id, name)?;
+        Some(self.atom.get_field(f))
+    }
+}
+
+/// Called after every event with the grid and the site the event ran at.
+pub type EventHook = Box<dyn FnMut(&Grid, usize, usize)>;
+
+enum ElementSource {
+    Source(String),
+    Bytecode(Vec<u8>),
+    Standard(String),
+}
+
+/// Configures an `Engine`. Made by `Engine::builder`.
+pub struct EngineBuilder {
+    elements: Vec<ElementSource>,
+    build_tag: String,
+    registry: Option<ElementRegistry>,
+    width: usize,
+    height: usize,
+    tiles: (usize, usize),
+    sparse: bool,
+    scheduling: Scheduling,
+    scheduler: Option<Box<dyn Scheduler>>,
+    boundary: Boundary,
+    seed: Option<u64>,
+    instruction_budget: Option<u64>,
+    strict: bool,
+    energy: Option<EnergyModel>,
+    parameters: Vec<ParameterOverride>,
+}
+
+impl EngineBuilder {
+    fn new() -> Self {
+        Self {
+            elements: Vec::new(),
+            build_tag: "ephemeral".to_owned(),
+            registry: None,
+            width: 64,
+            height: 64,
+            tiles: (1, 1),
+            sparse: false,
+            scheduling: Scheduling::Uniform,
+            scheduler: None,
+            boundary: Boundary::Void,
+            seed: None,
+            instruction_budget: Some(Runtime::DEFAULT_INSTRUCTION_BUDGET),
+            strict: false,
+            energy: None,
+            parameters: Vec::new(),
+        }
+    }
+
+    /// Adds an element from EWAL source. Includes are resolved relative to
+    /// the current directory.
+    pub fn with_element(mut self, src: &str) -> Self {
+        self.elements.push(ElementSource::Source(src.to_owned()));
+        self
+    }
+
+    /// Adds an element compiled by `ewac`, or the elements of a bundle linked
+    /// by `ewac link`.
+    pub fn with_bytecode(mut self, bytes: &[u8]) -> Self {
+        self.elements
+            .push(ElementSource::Bytecode(bytes.to_owned()));
+        self
+    }
+
+    /// Adds the standard element `name`, such as `"DReg"`.
+    pub fn with_standard(mut self, name: &str) -> Self {
+        self.elements.push(ElementSource::Standard(name.to_owned()));
+        self
+    }
+
+    /// The build tag elements given as source are compiled with. Defaults to
+    /// `ewac`'s, `"ephemeral"`.
+    pub fn build_tag(mut self, tag: &str) -> Self {
+        self.build_tag = tag.to_owned();
+        self
+    }
+
+    /// Numbers element types with `registry`, such as one read from a
+    /// numbering file.
+    pub fn registry(mut self, registry: ElementRegistry) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// The size of the grid in sites. Defaults to 64 by 64.
+    pub fn grid(mut self, width: usize, height: usize) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    /// Splits the grid into `tiles_x` by `tiles_y` equal tiles, which
+    /// `Engine::run_tiles` can run on separate threads. Fails to build unless
+    /// they divide the grid's width and height. Defaults to a single tile.
+    pub fn tiles(mut self, tiles_x: usize, tiles_y: usize) -> Self {
+        self.tiles = (tiles_x, tiles_y);
+        self
+    }
+
+    /// Allocates the grid's sites only as atoms reach them, for huge, mostly
+    /// empty worlds; see `Grid::new_sparse`.
+    pub fn sparse(mut self) -> Self {
+        self.sparse = true;
+        self
+    }
+
+    /// How event sites are picked; see `Scheduling`. Defaults to uniform.
+    pub fn scheduling(mut self, scheduling: Scheduling) -> Self {
+        self.scheduling = scheduling;
+        self.scheduler = None;
+        self
+    }
+
+    /// Picks event sites with a scheduler of one's own rather than a built-in
+    /// `Scheduling`.
+    pub fn scheduler(mut self, scheduler: Box<dyn Scheduler>) -> Self {
+        self.scheduler = Some(scheduler);
+        self
+    }
+
+    /// What events see past the edges of the grid; see `Boundary`. Defaults
+    /// to void. Under `Boundary::Wall` they see atoms of the element named
+    /// Wall, the standard one unless another is given.
+    pub fn boundary(mut self, boundary: Boundary) -> Self {
+        self.boundary = boundary;
+        self
+    }
+
+    /// Seeds the random number generator. Without a seed it is seeded from
+    /// entropy.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Limits the instructions run by each event; see
+    /// `Runtime::set_instruction_budget`.
+    pub fn instruction_budget(mut self, budget: Option<u64>) -> Self {
+        self.instruction_budget = budget;
+        self
+    }
+
+    /// Fails events which write outside their element's radius or read
+    /// registers they haven't set; see `Runtime::set_strict`.
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Makes events spend energy as heat; see `runtime::energy`. Fails to
+    /// build if the model's layer is not one of the grid's.
+    pub fn energy(mut self, model: EnergyModel) -> Self {
+        self.energy = Some(model);
+        self
+    }
+
+    /// Overrides parameter `name` of the element `element` once it is loaded;
+    /// see `Runtime::set_parameter`. Fails to build if there is no such
+    /// parameter.
+    pub fn parameter(mut self, element: &str, name: &str, value: Const) -> Self {
+        self.parameters.push(ParameterOverride {
+            element: element.to_owned(),
+            name: name.to_owned(),
+            value,
+        });
+        self
+    }
+
+    pub fn build(self) -> Result<Engine, EngineError> {
+        let (tiles_x, tiles_y) = self.tiles;
+        if self.width == 0 || self.height == 0 || tiles_x == 0 || tiles_y == 0 {
+            return Err(EngineError::BadGridSize);
+        }
+        let (tile_width, tile_height) = (self.width / tiles_x, self.height / tiles_y);
+        if tile_width * tiles_x != self.width || tile_height * tiles_y != self.height {
+            return Err(EngineError::BadGridSize);
+        }
+        let mut runtime = Runtime::new();
+        if let Some(registry) = self.registry {
+            runtime.set_registry(registry);
+        }
+        if let Some(seed) = self.seed {
+            runtime.reseed(seed);
+        }
+        runtime.set_instruction_budget(self.instruction_budget);
+        runtime.set_strict(self.strict);
+        if let Some(model) = self.energy {
+            if model.layer >= LAYERS {
+                return Err(EngineError::BadLayer(model.layer));
+            }
+            runtime.set_energy_model(Some(model));
+        }
+        for (i, e) in self.elements.into_iter().enumerate() {
+            let bytes = match e {
+                ElementSource::Source(src) => compile(&src, &self.build_tag, i)?,
+                ElementSource::Bytecode(bytes) => bytes,
+                ElementSource::Standard(name) => stdlib::compile(&name, &self.build_tag)
+                    .ok_or(EngineError::UnknownStandardElement(name))?
+                    .map_err(|e| EngineError::Compile(i, e))?,
+            };
+            runtime.load_from_reader(&mut bytes.as_slice())?;
+        }
+        for p in self.parameters.iter() {
+            p.apply(&mut runtime)?;
+        }
+        let mut grid = if self.sparse {
+            Grid::new_sparse(tiles_x, tiles_y, tile_width, tile_height)
+        } else {
+            Grid::new(tiles_x, tiles_y, tile_width, tile_height)
+        };
+        match self.scheduler {
+            Some(scheduler) => grid.set_scheduler(scheduler),
+            None => grid.set_scheduling(self.scheduling),
+        }
+        grid.set_boundary(self.boundary);
+        if self.boundary == Boundary::Wall {
+            grid.set_wall(wall_atom(&mut runtime, &self.build_tag)?);
+        }
+        Ok(Engine {
+            runtime,
+            grid,
+            hooks: Vec::new(),
+            elapsed: Duration::default(),
+            control: RunControl::new(),
+        })
+    }
+}
+
+/// An atom of the element named Wall, for `Boundary::Wall`, loading the
+/// standard one if no element of that name is loaded.
+pub fn wall_atom(runtime: &mut Runtime, build_tag: &str) -> Result<Atom, EngineError> {
+    let loaded = runtime
+        .registry()
+        .get("Wall")
+        .filter(|t| runtime.metadata(*t).is_some());
+    let t = match loaded {
+        Some(t) => t,
+        None => {
+            let bytes = stdlib::compile("Wall", build_tag)
+                .ok_or_else(|| EngineError::UnknownStandardElement("Wall".to_owned()))?
+                .map_err(|e| EngineError::Compile(runtime.elements().count(), e))?;
+            runtime.load_from_reader(&mut bytes.as_slice())?.get_type()
+        }
+    };
+    Ok(ecc::encode(Atom::new(t)))
+}
+
+/// Compiles the `i`th element given to a builder, rendering any error as
+/// `ewac` would.
+pub(crate) fn compile(src: &str, build_tag: &str, i: usize) -> Result<Vec<u8>, EngineError> {
+    let name = format!("<element {}>", i);
+    let src = preprocess::expand(&name, src, &HashMap::new())
+        .map_err(|e| EngineError::Compile(i, e.to_string()))?;
+    let mut v = Vec::new();
+    let mut compiler = Compiler::new(build_tag);
+    if let Err(e) = compiler.compile_to_writer(&mut v, &src.text) {
+        return Err(EngineError::Compile(
+            i,
+            e.diagnostic(&src.text).render(&name, &src),
+        ));
+    }
+    Ok(v)
+}
+
+/// A grid and the elements which run on it.
+pub struct Engine {
+    runtime: Runtime<'static>,
+    grid: Grid,
+    hooks: Vec<EventHook>,
+    elapsed: Duration,
+    control: RunControl,
+}
+
+impl Engine {
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::new()
+    }
+
+    /// Runs one event at a random site. With `Scheduling::Active` this may
+    /// instead count an event skipped at an empty site; see
+    /// `Grid::step_at_most`.
+    pub fn step(&mut self) -> Result<(), runtime::Error> {
+        self.advance(1).map(|_| ())
+    }
+
+    /// Runs `n` events at random sites, stopping at the first which fails.
+    /// Events skipped by the grid's scheduling count towards `n`.
+    ///
+    /// The run can be paused, stepped and paced from other threads through
+    /// `control`. Time spent waiting on it isn't counted in `stats`.
+    pub fn run_events(&mut self, n: u64) -> Result<(), runtime::Error> {
+        let sites = (self.width() * self.height()) as u64;
+        let mut run = 0;
+        while run < n {
+            let allowed = self.control.wait(self.grid.event_count(), sites);
+            let counted = self.advance((n - run).min(allowed))?;
+            self.control.counted(counted);
+            run += counted;
+        }
+        Ok(())
+    }
+
+    /// Runs `events` events in every tile of the grid, spreading the tiles
+    /// over `threads` threads; see `EngineBuilder::tiles` and
+    /// `Grid::run_tiles`. Sites are picked uniformly within each tile,
+    /// whatever the scheduling, and neither hooks nor `control` are used.
+    pub fn run_tiles(&mut self, events: usize, threads: usize) -> Result<(), runtime::Error> {
+        let start = Instant::now();
+        let result = self.grid.run_tiles(&mut self.runtime, events, threads);
+        self.elapsed += start.elapsed();
+        result
+    }
+
+    /// A handle to pause, resume, step and pace `run_events` from other
+    /// threads; see `runtime::control`. `step` ignores it.
+    pub fn control(&self) -> RunControl {
+        self.control.clone()
+    }
+
+    /// Advances the grid by at most `max` events, of which at most one is
+    /// run, giving the number counted.
+    fn advance(&mut self, max: u64) -> Result<u64, runtime::Error> {
+        let start = Instant::now();
+        let (skipped, site) = self.grid.next_site(&mut self.runtime, max)?;
+        let (x, y) = match site {
+            Some(site) => site,
+            None => {
+                self.elapsed += start.elapsed();
+                return Ok(skipped);
+            }
+        };
+        let result = self.grid.event_at(&mut self.runtime, x, y);
+        self.elapsed += start.elapsed();
+        result?;
+        for hook in self.hooks.iter_mut() {
+            hook(&self.grid, x, y);
+        }
+        Ok(skipped + 1)
+    }
+
+    /// Calls `hook` after every event run by `step` and `run_events`.
+    pub fn on_event<F: FnMut(&Grid, usize, usize) + 'static>(&mut self, hook: F) {
+        self.hooks.push(Box::new(hook));
+    }
+
+    /// Tells `observer` about every event; see `EventObserver`.
+    pub fn add_observer(&mut self, observer: Arc<dyn EventObserver>) {
+        self.runtime.add_observer(observer);
+    }
+
+    pub fn width(&self) -> usize {
+        self.grid.width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.grid.height()
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<Atom> {
+        self.grid.get(x, y)
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, atom: Atom) {
+        self.grid.set(x, y, atom)
+    }
+
+    pub fn get_paint(&self, x: usize, y: usize) -> Option<Color> {
+        self.grid.get_paint(x, y)
+    }
+
+    pub fn set_paint(&mut self, x: usize, y: usize, paint: Color) {
+        self.grid.set_paint(x, y, paint)
+    }
+
+    /// The value of layer `l` at site `(x, y)`; see `grid::LAYERS`.
+    pub fn get_layer(&self, l: usize, x: usize, y: usize) -> Option<u32> {
+        self.grid.get_layer(l, x, y)
+    }
+
+    pub fn set_layer(&mut self, l: usize, x: usize, y: usize, value: u32) {
+        self.grid.set_layer(l, x, y, value)
+    }
+
+    /// The type number of the element `name`.
+    pub fn type_of(&self, name: &str) -> Option<u16> {
+        self.runtime.registry().get(name)
+    }
+
+    /// A new atom of the element `name`, with its fields zero and its ECC bits
+    /// set.
+    pub fn new_atom(&self, name: &str) -> Option<Atom> {
+        Some(ecc::encode(Atom::new(self.type_of(name)?)))
+    }
+
+    /// The sites holding atoms of the element `name` for which `f` holds,
+    /// tile by tile, or none if there is no such element. Only tiles holding
+    /// atoms of the element are searched; see `Grid::find`.
+    pub fn find<F: FnMut(&Found) -> bool>(&self, name: &str, f: F) -> Vec<(usize, usize)> {
+        match self.type_of(name) {
+            Some(t) => self.find_where(Some(t), f),
+            None => Vec::new(),
+        }
+    }
+
+    /// The sites holding atoms of any element for which `f` holds.
+    pub fn find_all<F: FnMut(&Found) -> bool>(&self, f: F) -> Vec<(usize, usize)> {
+        self.find_where(None, f)
+    }
+
+    fn find_where<F: FnMut(&Found) -> bool>(
+        &self,
+        type_num: Option<u16>,
+        mut f: F,
+    ) -> Vec<(usize, usize)> {
+        self.grid.find(type_num, |x, y, atom| {
+            f(&Found {
+                x,
+                y,
+                atom,
+                type_id: atom.get_type(),
+                runtime: &self.runtime,
+            })
+        })
+    }
+
+    /// The number of events which have completed.
+    pub fn event_count(&self) -> u64 {
+        self.grid.event_count()
+    }
+
+    /// Statistics for the grid, timing the events run so far.
+    pub fn stats(&self) -> Report {
+        Report::new(&self.grid, &self.runtime, self.elapsed)
+    }
+
+    pub fn grid(&self) -> &Grid {
+        &self.grid
+    }
+
+    pub fn grid_mut(&mut self) -> &mut Grid {
+        &mut self.grid
+    }
+
+    pub fn runtime(&self) -> &Runtime<'static> {
+        &self.runtime
+    }
+
+    pub fn runtime_mut(&mut self) -> &mut Runtime<'static> {
+        &mut self.runtime
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 32 by 32 torus of four tiles with Res on every fifth site. Res only
+    /// moves, so the count of Res stays the same.
+    fn res_engine(seed: u64) -> Engine {
+        let mut engine = Engine::builder()
+            .with_standard("Res")
+            .grid(32, 32)
+            .tiles(2, 2)
+            .boundary(Boundary::Torus)
+            .seed(seed)
+            .build()
+            .unwrap();
+        let res = engine.new_atom("Res").unwrap();
+        for i in (0..32 * 32).step_by(5) {
+            engine.set(i % 32, i / 32, res);
+        }
+        engine
+    }
+
+    fn res_count(engine: &Engine) -> usize {
+        engine.find("Res", |_| true).len()
+    }
+
+    #[test]
+    fn tiles_must_divide_grid() {
+        for &(x, y) in [(0, 1), (3, 1), (1, 64)].iter() {
+            let built = Engine::builder().grid(32, 32).tiles(x, y).build();
+            assert!(matches!(built, Err(EngineError::BadGridSize)));
+        }
+    }
+
+    #[test]
+    fn run_tiles_single_thread_follows_seed() {
+        let mut digests = Vec::new();
+        for &seed in [1, 1, 2].iter() {
+            let mut engine = res_engine(seed);
+            engine.run_tiles(500, 1).unwrap();
+            assert_eq!(engine.event_count(), 500 * 4);
+            digests.push(engine.grid().digest());
+        }
+        assert_eq!(digests[0], digests[1]);
+        assert_ne!(digests[0], digests[2]);
+    }
+
+    #[test]
+    fn run_tiles_threads_conserve_atoms() {
+        for threads in 1..=4 {
+            let mut engine = res_engine(threads as u64);
+            let before = res_count(&engine);
+            for _ in 0..5 {
+                engine.run_tiles(100, threads).unwrap();
+                assert_eq!(res_count(&engine), before, "{} threads", threads);
+            }
+            assert_eq!(engine.event_count(), 5 * 100 * 4);
+        }
+    }
+}
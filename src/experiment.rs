@@ -0,0 +1,277 @@
+//! Sweeps of runs over parameters and seeds.
+//!
+//! A sweep file describes a batch of runs of the same elements: each
+//! combination of the values its parameters take, run with each of its
+//! seeds, as many times as it repeats. Like a golden test it is a list of
+//! statements written as function calls, one per line; blank lines and lines
+//! starting with `#` are ignored:
+//!
+//! ```text
+//! # How fast Sand settles, by how sticky it is and how often it moves.
+//! Element(sand.ewal)
+//! Element(Wall)
+//! Scenario(box.scenario)
+//! Events(100000)
+//! MetricsEvery(10000)
+//! Param(Sand.STICKY, 0, 5, 20)
+//! Range(Sand.MOVE_ODDS, 1, 9, 4)
+//! Seeds(1, 5)
+//! Repeat(2)
+//! ```
+//!
+//! - `Element(element)` loads an `.ewal` source or compiled file relative to
+//!   the sweep file, or a standard element. The first is placed at the
+//!   center of each new grid unless there is a scenario.
+//! - `Scenario(file)` sets up each grid as the scenario file, relative to the
+//!   sweep file, describes. A `Seed` in it would give every run the same
+//!   seed.
+//! - `Size(width, height)` gives the size of each grid.
+//! - `Events(count)` gives the events each run runs, and
+//!   `MetricsEvery(count)` how often its metrics are written as it goes, as
+//!   well as at its end.
+//! - `Param(element.NAME, value, ...)` sweeps a parameter over the values
+//!   listed, and `Range(element.NAME, from, to, step)` over the integers from
+//!   `from` to `to`, both included, `step` apart.
+//! - `Seed(seed, ...)` lists the seeds to run each combination with, and
+//!   `Seeds(from, to)` gives those from `from` to `to`, both included.
+//!   Without either, runs use seed 1337, as `ewar` does.
+//! - `Repeat(count)` runs each combination with each seed that many times.
+//!   The first repetition uses the seed itself and the others seeds drawn
+//!   in turn from a generator seeded with it, so repetitions differ and are
+//!   reproducible.
+//!
+//! Runs are numbered from 1, with the first parameter changing slowest and
+//! repetitions fastest.
+
+use crate::base::arith::Const;
+use crate::runtime::rng::Prng;
+use crate::runtime::scenario::ParameterOverride;
+use rand::RngCore;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The seed of runs of a sweep which gives none.
+pub const DEFAULT_SEED: u64 = 1337;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SweepError {
+    #[error("IO error: {0}")]
+    IOError(#[from] io::Error),
+    #[error("bad sweep statement on line {0}")]
+    BadLine(usize),
+    #[error("the sweep loads no elements")]
+    NoElements,
+    #[error("the sweep gives no events to run")]
+    NoEvents,
+}
+
+/// A parameter swept, written `element.NAME`, with the values it takes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Axis {
+    pub parameter: String,
+    /// The values as written, each of which parses as a constant.
+    pub values: Vec<String>,
+}
+
+/// One run of a sweep.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Run {
+    /// The run's number, counting from 1.
+    pub number: usize,
+    pub seed: u64,
+    /// The repetition of its combination and seed, counting from 0.
+    pub repetition: u64,
+    /// Each parameter swept, written `element.NAME`, with its value in the
+    /// run as written, in the order of the sweep's axes.
+    pub parameters: Vec<(String, String)>,
+}
+
+impl Run {
+    /// The name of the run's directory among the results of its sweep, such
+    /// as `run-0007`.
+    pub fn name(&self) -> String {
+        format!("run-{:04}", self.number)
+    }
+
+    /// The parameters to set for the run.
+    pub fn overrides(&self) -> Vec<ParameterOverride> {
+        // The values were checked as the sweep was read.
+        self.parameters
+            .iter()
+            .map(|(p, v)| format!("{}={}", p, v).parse().unwrap())
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Sweep {
+    path: PathBuf,
+    pub elements: Vec<String>,
+    pub scenario: Option<String>,
+    pub size: Option<(usize, usize)>,
+    pub events: u64,
+    pub metrics_every: Option<u64>,
+    pub axes: Vec<Axis>,
+    pub seeds: Vec<u64>,
+    pub repeat: u64,
+}
+
+impl Sweep {
+    /// Reads the sweep at `path`.
+    pub fn load(path: &Path) -> Result<Self, SweepError> {
+        let mut sweep = Self {
+            path: path.to_owned(),
+            elements: Vec::new(),
+            scenario: None,
+            size: None,
+            events: 0,
+            metrics_every: None,
+            axes: Vec::new(),
+            seeds: Vec::new(),
+            repeat: 1,
+        };
+        for (n, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad = || SweepError::BadLine(n + 1);
+            let open = line.find('(').ok_or_else(bad)?;
+            let args = line[open + 1..].strip_suffix(')').ok_or_else(bad)?;
+            let args: Vec<&str> = args.split(',').map(str::trim).collect();
+            let num = |i: usize| args[i].parse::<u64>().map_err(|_| bad());
+            match (line[..open].trim(), args.len()) {
+                ("Element", 1) if !args[0].is_empty() => sweep.elements.push(args[0].to_owned()),
+                ("Scenario", 1) if !args[0].is_empty() => sweep.scenario = Some(args[0].to_owned()),
+                ("Size", 2) if num(0)? > 0 && num(1)? > 0 => {
+                    sweep.size = Some((num(0)? as usize, num(1)? as usize))
+                }
+                ("Events", 1) => sweep.events = num(0)?,
+                ("MetricsEvery", 1) => sweep.metrics_every = Some(num(0)?),
+                ("Param", n) if n > 1 => {
+                    let values = &args[1..];
+                    if values.iter().any(|v| v.parse::<Const>().is_err()) {
+                        return Err(bad());
+                    }
+                    sweep
+                        .axes
+                        .push(Self::axis(args[0], values).ok_or_else(bad)?);
+                }
+                ("Range", 4) => {
+                    let int = |i: usize| args[i].parse::<i128>().map_err(|_| bad());
+                    let (from, to, step) = (int(1)?, int(2)?, int(3)?);
+                    if step <= 0 || from > to {
+                        return Err(bad());
+                    }
+                    let values: Vec<String> = (0..)
+                        .map(|i| from + i * step)
+                        .take_while(|v| *v <= to)
+                        .map(|v| v.to_string())
+                        .collect();
+                    let values: Vec<&str> = values.iter().map(String::as_str).collect();
+                    sweep
+                        .axes
+                        .push(Self::axis(args[0], &values).ok_or_else(bad)?);
+                }
+                ("Seed", _) => {
+                    for a in args.iter() {
+                        sweep.seeds.push(a.parse().map_err(|_| bad())?);
+                    }
+                }
+                ("Seeds", 2) if num(0)? <= num(1)? => sweep.seeds.extend(num(0)?..=num(1)?),
+                ("Repeat", 1) if num(0)? > 0 => sweep.repeat = num(0)?,
+                _ => return Err(bad()),
+            }
+        }
+        if sweep.elements.is_empty() {
+            return Err(SweepError::NoElements);
+        }
+        if sweep.events == 0 {
+            return Err(SweepError::NoEvents);
+        }
+        if sweep.seeds.is_empty() {
+            sweep.seeds.push(DEFAULT_SEED);
+        }
+        Ok(sweep)
+    }
+
+    /// The axis of the parameter `parameter`, written `element.NAME`, over
+    /// `values`.
+    fn axis(parameter: &str, values: &[&str]) -> Option<Axis> {
+        let (element, name) = parameter.split_once('.')?;
+        if element.is_empty() || name.is_empty() {
+            return None;
+        }
+        Some(Axis {
+            parameter: parameter.to_owned(),
+            values: values.iter().map(|v| v.to_string()).collect(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// `file`, as the sweep names it, relative to the sweep file.
+    fn resolve(&self, file: &str) -> PathBuf {
+        self.path
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join(file)
+    }
+
+    /// The elements to load, as files if the sweep's directory holds them
+    /// and otherwise as names of standard elements.
+    pub fn inputs(&self) -> Vec<String> {
+        self.elements
+            .iter()
+            .map(|e| match self.resolve(e) {
+                path if path.exists() => path.to_string_lossy().into_owned(),
+                _ => e.clone(),
+            })
+            .collect()
+    }
+
+    /// The scenario file, if any.
+    pub fn scenario_path(&self) -> Option<PathBuf> {
+        self.scenario.as_ref().map(|s| self.resolve(s))
+    }
+
+    /// Every run of the sweep, in order.
+    pub fn runs(&self) -> Vec<Run> {
+        let mut combinations: Vec<Vec<(String, String)>> = vec![Vec::new()];
+        for axis in self.axes.iter() {
+            combinations = combinations
+                .iter()
+                .flat_map(|c| {
+                    axis.values.iter().map(move |v| {
+                        let mut c = c.clone();
+                        c.push((axis.parameter.clone(), v.clone()));
+                        c
+                    })
+                })
+                .collect();
+        }
+        let mut runs = Vec::new();
+        for parameters in combinations.iter() {
+            for seed in self.seeds.iter() {
+                let mut rng = Prng::new(*seed);
+                for repetition in 0..self.repeat {
+                    let seed = if repetition == 0 {
+                        *seed
+                    } else {
+                        rng.next_u64()
+                    };
+                    runs.push(Run {
+                        number: runs.len() + 1,
+                        seed,
+                        repetition,
+                        parameters: parameters.clone(),
+                    });
+                }
+            }
+        }
+        runs
+    }
+}
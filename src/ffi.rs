@@ -0,0 +1,422 @@
+//! A C ABI for hosts written in other languages.
+//!
+//! With the `ffi` feature the library exports functions which C, C++ or any
+//! language with a C FFI can call on the library built as a `cdylib` or
+//! `staticlib`. `include/substrate_engine.h` declares them:
+//!
+//! ```text
+//! EwSession *s = ew_new(64, 64, 42);
+//! int32_t dreg = ew_load_standard(s, "DReg", 4);
+//! ew_set(s, 32, 32, dreg);
+//! if (ew_step(s, 100000) < 0)
+//!     fprintf(stderr, "%s\n", ew_error(s));
+//! ew_free(s);
+//! ```
+//!
+//! A session owns a runtime and a grid; sites are addressed by `(x, y)` and
+//! atoms by type number or by their raw bits. Calls which can fail return a
+//! negative number and leave a message for `ew_error`. The functions and
+//! their signatures are stable: new ones may be added, but existing ones
+//! won't change.
+
+use crate::base::atom::Atom;
+use crate::base::ecc;
+use crate::engine;
+use crate::render::Renderer;
+use crate::runtime::grid::Grid;
+use crate::runtime::stats::Report;
+use crate::runtime::Runtime;
+use crate::stdlib;
+use std::os::raw::c_char;
+use std::slice;
+use std::time::Duration;
+
+/// A runtime and the grid it runs on, owned by the host.
+pub struct EwSession {
+    runtime: Runtime<'static>,
+    grid: Grid,
+    /// Made when first rendering after elements are loaded.
+    renderer: Option<Renderer>,
+    rgba: Vec<u8>,
+    /// The last error, NUL-terminated.
+    error: Vec<u8>,
+}
+
+impl EwSession {
+    fn set(&mut self, x: u32, y: u32, atom: Atom) -> i32 {
+        if x as usize >= self.grid.width() || y as usize >= self.grid.height() {
+            return self.fail(format!("site ({}, {}) is outside the grid", x, y));
+        }
+        self.grid.set(x as usize, y as usize, atom);
+        0
+    }
+
+    fn fail<E: ToString>(&mut self, e: E) -> i32 {
+        self.error = e.to_string().into_bytes();
+        self.error.push(0);
+        -1
+    }
+
+    /// Loads compiled element `bytes`, giving its type number.
+    fn load(&mut self, bytes: Result<Vec<u8>, String>) -> i32 {
+        let bytes = match bytes {
+            Ok(bytes) => bytes,
+            Err(e) => return self.fail(e),
+        };
+        match self.runtime.load_from_reader(&mut bytes.as_slice()) {
+            Ok(atom) => {
+                self.renderer = None;
+                atom.get_type() as i32
+            }
+            Err(e) => self.fail(e),
+        }
+    }
+}
+
+/// Reads the UTF-8 string of `len` bytes at `ptr`.
+///
+/// # Safety
+///
+/// `ptr` must point to `len` readable bytes.
+unsafe fn string(ptr: *const c_char, len: usize) -> String {
+    String::from_utf8_lossy(bytes(ptr as *const u8, len)).into_owned()
+}
+
+/// # Safety
+///
+/// `ptr` must point to `len` writable values.
+unsafe fn bytes_mut<'a, T>(ptr: *mut T, len: usize) -> &'a mut [T] {
+    if len == 0 {
+        return &mut [];
+    }
+    slice::from_raw_parts_mut(ptr, len)
+}
+
+/// # Safety
+///
+/// `ptr` must point to `len` readable bytes.
+unsafe fn bytes<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        return &[];
+    }
+    slice::from_raw_parts(ptr, len)
+}
+
+/// Creates a session with an empty `width` by `height` grid and a generator
+/// seeded with `seed`. Gives null if the grid has no sites.
+#[no_mangle]
+pub extern "C" fn ew_new(width: u32, height: u32, seed: u64) -> *mut EwSession {
+    if width == 0 || height == 0 {
+        return std::ptr::null_mut();
+    }
+    let mut runtime = Runtime::new();
+    runtime.reseed(seed);
+    Box::into_raw(Box::new(EwSession {
+        runtime,
+        grid: Grid::new(1, 1, width as usize, height as usize),
+        renderer: None,
+        rgba: Vec::new(),
+        error: vec![0],
+    }))
+}
+
+/// # Safety
+///
+/// `s` must be from `ew_new` and not used again.
+#[no_mangle]
+pub unsafe extern "C" fn ew_free(s: *mut EwSession) {
+    drop(Box::from_raw(s));
+}
+
+/// Compiles and loads the EWAL source of `len` bytes at `ptr`, giving the
+/// element's type number.
+///
+/// # Safety
+///
+/// `s` must be from `ew_new` and `ptr` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ew_load_source(s: *mut EwSession, ptr: *const c_char, len: usize) -> i32 {
+    let s = &mut *s;
+    let i = s.runtime.elements().count();
+    let bytes = engine::compile(&string(ptr, len), "ephemeral", i).map_err(|e| e.to_string());
+    s.load(bytes)
+}
+
+/// Loads the `ewac` output of `len` bytes at `ptr`, giving the element's
+/// type number.
+///
+/// # Safety
+///
+/// `s` must be from `ew_new` and `ptr` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ew_load_bytecode(s: *mut EwSession, ptr: *const u8, len: usize) -> i32 {
+    let s = &mut *s;
+    s.load(Ok(bytes(ptr, len).to_owned()))
+}
+
+/// Loads the standard element named by the `len` bytes at `ptr`, giving its
+/// type number.
+///
+/// # Safety
+///
+/// `s` must be from `ew_new` and `ptr` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ew_load_standard(
+    s: *mut EwSession,
+    ptr: *const c_char,
+    len: usize,
+) -> i32 {
+    let s = &mut *s;
+    let name = string(ptr, len);
+    match stdlib::compile(&name, "ephemeral") {
+        Some(bytes) => s.load(bytes),
+        None => s.fail(format!("no standard element named {}", name)),
+    }
+}
+
+/// Gives the type number of the element `name`, of `len` bytes at `ptr`.
+///
+/// # Safety
+///
+/// `s` must be from `ew_new` and `ptr` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ew_type_of(s: *mut EwSession, ptr: *const c_char, len: usize) -> i32 {
+    let s = &mut *s;
+    let name = string(ptr, len);
+    match s.runtime.registry().get(&name) {
+        Some(t) => t as i32,
+        None => s.fail(format!("no element named {}", name)),
+    }
+}
+
+/// Places a new atom of type `t` at `(x, y)`, or empties the site if `t`
+/// is 0.
+///
+/// # Safety
+///
+/// `s` must be from `ew_new`.
+#[no_mangle]
+pub unsafe extern "C" fn ew_set(s: *mut EwSession, x: u32, y: u32, t: u32) -> i32 {
+    let s = &mut *s;
+    if t > u16::MAX as u32 || s.runtime.registry().name(t as u16).is_none() {
+        return s.fail(format!("unknown type {}", t));
+    }
+    let atom = match t {
+        0 => Atom::default(),
+        t => ecc::encode(Atom::new(t as u16)),
+    };
+    s.set(x, y, atom)
+}
+
+/// Gives the type of the atom at `(x, y)`, or -1 outside the grid.
+///
+/// # Safety
+///
+/// `s` must be from `ew_new`.
+#[no_mangle]
+pub unsafe extern "C" fn ew_get(s: *const EwSession, x: u32, y: u32) -> i32 {
+    let s = &*s;
+    match s.grid.get(x as usize, y as usize) {
+        Some(atom) => atom.get_type() as i32,
+        None => -1,
+    }
+}
+
+/// Writes the atom at `(x, y)` with the bits `hi << 64 | lo`, as stored;
+/// its ECC bits are not recomputed.
+///
+/// # Safety
+///
+/// `s` must be from `ew_new`.
+#[no_mangle]
+pub unsafe extern "C" fn ew_set_atom(s: *mut EwSession, x: u32, y: u32, lo: u64, hi: u64) -> i32 {
+    let s = &mut *s;
+    s.set(x, y, Atom::from_bits((hi as u128) << 64 | lo as u128))
+}
+
+/// Reads the bits of the atom at `(x, y)` into `lo` and `hi` as for
+/// `ew_set_atom`. Gives -1 outside the grid.
+///
+/// # Safety
+///
+/// `s` must be from `ew_new` and `lo` and `hi` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn ew_get_atom(
+    s: *mut EwSession,
+    x: u32,
+    y: u32,
+    lo: *mut u64,
+    hi: *mut u64,
+) -> i32 {
+    let s = &mut *s;
+    match s.grid.get(x as usize, y as usize) {
+        Some(atom) => {
+            *lo = atom.bits() as u64;
+            *hi = (atom.bits() >> 64) as u64;
+            0
+        }
+        None => s.fail(format!("site ({}, {}) is outside the grid", x, y)),
+    }
+}
+
+/// Writes the type of every site into the `len` values at `out`, in rows
+/// from the top. Fails if `len` is less than `width * height`.
+///
+/// # Safety
+///
+/// `s` must be from `ew_new` and `out` must point to `len` writable values.
+#[no_mangle]
+pub unsafe extern "C" fn ew_read_types(s: *mut EwSession, out: *mut u16, len: usize) -> i32 {
+    let s = &mut *s;
+    let (width, height) = (s.grid.width(), s.grid.height());
+    if len < width * height {
+        return s.fail(format!(
+            "{} values can't hold {} sites",
+            len,
+            width * height
+        ));
+    }
+    let out = slice::from_raw_parts_mut(out, width * height);
+    out.fill(0);
+    s.grid
+        .for_each_atom(|x, y, atom| out[y * width + x] = atom.get_type());
+    0
+}
+
+/// Counts the sites holding each type `t` below `len` into `out[t]`, empty
+/// sites as type 0, and gives one more than the highest type loaded or on
+/// the grid, the `len` needed to count them all.
+///
+/// # Safety
+///
+/// `s` must be from `ew_new` and `out` must point to `len` writable values.
+#[no_mangle]
+pub unsafe extern "C" fn ew_census(s: *const EwSession, out: *mut u64, len: usize) -> i32 {
+    let s = &*s;
+    let report = Report::new(&s.grid, &s.runtime, Duration::default());
+    let out = bytes_mut(out, len);
+    out.fill(0);
+    for (&t, &n) in report.census.iter() {
+        if let Some(count) = out.get_mut(t as usize) {
+            *count = n as u64;
+        }
+    }
+    let loaded = s.runtime.elements().map(|(t, _)| t).max();
+    let seen = report.census.keys().next_back().copied();
+    loaded.max(seen).map_or(1, |t| t as i32 + 1)
+}
+
+/// Gives the name of type `t` as `len` bytes of UTF-8, not NUL-terminated,
+/// or null if no element of that type is loaded.
+///
+/// # Safety
+///
+/// `s` must be from `ew_new` and `len` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn ew_type_name(
+    s: *const EwSession,
+    t: u32,
+    len: *mut usize,
+) -> *const c_char {
+    let s = &*s;
+    if t > u16::MAX as u32 {
+        return std::ptr::null();
+    }
+    match s.runtime.registry().name(t as u16) {
+        Some(name) => {
+            *len = name.len();
+            name.as_ptr() as *const c_char
+        }
+        None => std::ptr::null(),
+    }
+}
+
+/// Runs `events` events at random sites, stopping at the first which fails.
+///
+/// # Safety
+///
+/// `s` must be from `ew_new`.
+#[no_mangle]
+pub unsafe extern "C" fn ew_step(s: *mut EwSession, events: u32) -> i32 {
+    let s = &mut *s;
+    let mut run = 0;
+    while run < events as u64 {
+        match s.grid.step_at_most(&mut s.runtime, events as u64 - run) {
+            Ok(n) => run += n,
+            Err(e) => return s.fail(e),
+        }
+    }
+    0
+}
+
+/// The number of events which have completed.
+///
+/// # Safety
+///
+/// `s` must be from `ew_new`.
+#[no_mangle]
+pub unsafe extern "C" fn ew_event_count(s: *const EwSession) -> u64 {
+    let s = &*s;
+    s.grid.event_count()
+}
+
+/// # Safety
+///
+/// `s` must be from `ew_new`.
+#[no_mangle]
+pub unsafe extern "C" fn ew_width(s: *const EwSession) -> u32 {
+    let s = &*s;
+    s.grid.width() as u32
+}
+
+/// # Safety
+///
+/// `s` must be from `ew_new`.
+#[no_mangle]
+pub unsafe extern "C" fn ew_height(s: *const EwSession) -> u32 {
+    let s = &*s;
+    s.grid.height() as u32
+}
+
+/// Draws the grid, one pixel per site, as RGBA rows from the top, and gives
+/// a pointer to the `4 * width * height` bytes. They stay valid until the
+/// next call on the session.
+///
+/// # Safety
+///
+/// `s` must be from `ew_new`.
+#[no_mangle]
+pub unsafe extern "C" fn ew_render(s: *mut EwSession) -> *const u8 {
+    let s = &mut *s;
+    let runtime = &s.runtime;
+    let renderer = s.renderer.get_or_insert_with(|| Renderer::new(runtime, 1));
+    let frame = renderer.render(&s.grid);
+    let palette = renderer.palette();
+    s.rgba.clear();
+    for &i in frame.pixels.iter() {
+        let [r, g, b] = palette[i as usize];
+        s.rgba.extend_from_slice(&[r, g, b, 0xff]);
+    }
+    s.rgba.as_ptr()
+}
+
+/// The message left by the last call which failed, NUL-terminated UTF-8
+/// of `ew_error_len` bytes. Empty if none has failed.
+///
+/// # Safety
+///
+/// `s` must be from `ew_new`.
+#[no_mangle]
+pub unsafe extern "C" fn ew_error(s: *const EwSession) -> *const c_char {
+    let s = &*s;
+    s.error.as_ptr() as *const c_char
+}
+
+/// # Safety
+///
+/// `s` must be from `ew_new`.
+#[no_mangle]
+pub unsafe extern "C" fn ew_error_len(s: *const EwSession) -> usize {
+    let s = &*s;
+    s.error.len() - 1
+}
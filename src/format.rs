@@ -0,0 +1,431 @@
+//! Reprints EWAL sources in a canonical layout.
+//!
+//! Formatting works line by line on the source as written, so comments,
+//! macros, includes and conditionals are kept where they are. Each line is
+//! reprinted in one layout, close to the one `ewad` prints:
+//!
+//! ```text
+//! .name "Fork"
+//! .symbol "X"
+//! .symmetries ALL
+//!
+//!     push     3  // copies to make
+//! loop:
+//!     dup
+//!     jumpzero end
+//!     push1
+//!     push0
+//!     getsite
+//!     setsite     // #1 = #0
+//!     push1
+//!     sub
+//!     jump     loop
+//! end:
+//! ```
+//!
+//! Directives and labels start the line and instructions are indented by
+//! four spaces. A label sharing its line with an instruction is given a line
+//! of its own. Within a run of lines without a blank line between them, the
+//! operands of the instructions start in one column and so do the comments
+//! which follow code. Runs of blank lines are reduced to one and a blank line
+//! is put between the metadata and the code.
+//!
+//! Literals are normalized: hexadecimal digits and colors are lowercase,
+//! `.name` and `.symbol` are quoted, `.color` is not, and symmetries are
+//! joined by `|` without spaces. Consecutive metadata directives are sorted
+//! into the order `ewad` prints them in, each keeping the comment lines
+//! above it except those opening the source; repeated directives such as
+//! `.field` and `.bond` keep their order.
+
+/// Metadata directives in the order they are sorted into.
+const ORDER: [&str; 17] = [
+    ".name",
+    ".symbol",
+    ".desc",
+    ".author",
+    ".license",
+    ".radius",
+    ".bgcolor",
+    ".fgcolor",
+    ".color",
+    ".symmetries",
+    ".field",
+    ".bond",
+    ".parameter",
+    ".arithmetic",
+    ".fieldwrites",
+    ".divzero",
+    ".mfmtype",
+];
+
+/// The indentation of instructions.
+const INDENT: &str = "    ";
+
+#[derive(thiserror::Error, Debug)]
+pub enum FormatError {
+    #[error("line {0}: unterminated string")]
+    UnterminatedString(usize),
+    #[error("line {0}: unterminated comment")]
+    UnterminatedComment(usize),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Kind {
+    Blank,
+    Comment,
+    /// A metadata directive, with its place in `ORDER`.
+    Metadata(usize),
+    /// Any other directive, such as `.include` or `.if`.
+    Directive,
+    Label,
+    Instruction,
+}
+
+#[derive(Debug)]
+struct Line {
+    kind: Kind,
+    code: String,
+    comment: Option<String>,
+}
+
+impl Line {
+    fn new(kind: Kind, code: String) -> Self {
+        Self {
+            kind,
+            code,
+            comment: None,
+        }
+    }
+
+    fn indent(&self) -> &'static str {
+        if self.kind == Kind::Instruction {
+            INDENT
+        } else {
+            ""
+        }
+    }
+}
+
+/// Splits `line` into its code, with runs of whitespace outside strings
+/// reduced to one space, and its comment.
+fn split_comment(line: &str, n: usize) -> Result<(String, Option<String>), FormatError> {
+    let mut code = String::new();
+    let mut quoted = false;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if quoted {
+            quoted = c != '"';
+            code.push(c);
+            continue;
+        }
+        let rest = &line[i..];
+        if rest.starts_with("//") {
+            return Ok((code.trim().to_owned(), Some(rest.trim_end().to_owned())));
+        }
+        if rest.starts_with("/*") {
+            if !rest.contains("*/") {
+                return Err(FormatError::UnterminatedComment(n));
+            }
+            return Ok((code.trim().to_owned(), Some(rest.trim_end().to_owned())));
+        }
+        if c.is_whitespace() {
+            while matches!(chars.peek(), Some((_, c)) if c.is_whitespace()) {
+                chars.next();
+            }
+            code.push(' ');
+        } else {
+            quoted = c == '"';
+            code.push(c);
+        }
+    }
+    if quoted {
+        return Err(FormatError::UnterminatedString(n));
+    }
+    Ok((code.trim().to_owned(), None))
+}
+
+/// Applies `f` to the parts of `s` outside strings.
+fn unquoted(s: &str, f: impl Fn(&str) -> String) -> String {
+    s.split('"')
+        .enumerate()
+        .map(|(i, part)| if i % 2 == 0 { f(part) } else { part.to_owned() })
+        .collect::<Vec<_>>()
+        .join("\"")
+}
+
+/// Lowercases the digits of hexadecimal literals in `s`.
+fn lowercase_hex(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut word = String::new();
+    for c in s.chars().chain(std::iter::once(' ')) {
+        if c.is_ascii_alphanumeric() || c == '_' || c == '%' {
+            word.push(c);
+            continue;
+        }
+        if word.starts_with("0x") {
+            word.make_ascii_lowercase();
+        }
+        out.push_str(&word);
+        word.clear();
+        out.push(c);
+    }
+    out.pop();
+    out
+}
+
+/// Puts `sep` between the comma separated parts of `s`.
+fn join_commas(s: &str, sep: &str) -> String {
+    unquoted(s, |s| {
+        s.split(',').map(str::trim).collect::<Vec<_>>().join(sep)
+    })
+}
+
+fn quote(s: &str) -> String {
+    if s.starts_with('"') {
+        s.to_owned()
+    } else {
+        format!("\"{}\"", s)
+    }
+}
+
+fn is_color(s: &str) -> bool {
+    s.len() > 1 && s.starts_with('#') && s[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn directive(code: &str) -> Line {
+    let (name, args) = match code.find(' ') {
+        Some(i) => (&code[..i], &code[i + 1..]),
+        None => (code, ""),
+    };
+    let args = match name {
+        ".name" | ".symbol" => quote(args),
+        ".color" => {
+            let color = args.trim_matches('"');
+            if is_color(color) {
+                color.to_ascii_lowercase()
+            } else {
+                args.to_owned()
+            }
+        }
+        ".bgcolor" | ".fgcolor" if is_color(args.trim_matches('"')) => {
+            quote(&args.to_ascii_lowercase())
+        }
+        ".symmetries" => args.replace(' ', ""),
+        ".field" | ".bond" => join_commas(args, ","),
+        ".parameter" | ".macro" => lowercase_hex(&join_commas(args, ", ")),
+        _ => unquoted(args, lowercase_hex),
+    };
+    let kind = match ORDER.iter().position(|d| *d == name) {
+        Some(i) => Kind::Metadata(i),
+        None => Kind::Directive,
+    };
+    if args.is_empty() {
+        Line::new(kind, name.to_owned())
+    } else {
+        Line::new(kind, format!("{} {}", name, args))
+    }
+}
+
+/// The length of the label starting `code`, with its `:`, if it has one.
+fn label_len(code: &str) -> Option<usize> {
+    let end = code.find(':')?;
+    let name = &code[..end];
+    let valid = !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '%');
+    if valid {
+        Some(end + 1)
+    } else {
+        None
+    }
+}
+
+fn instruction(code: &str) -> Line {
+    let (mnemonic, args) = match code.find(' ') {
+        Some(i) => (&code[..i], &code[i + 1..]),
+        None => return Line::new(Kind::Instruction, code.to_owned()),
+    };
+    let args = match mnemonic {
+        "usesymmetries" => args.replace(' ', ""),
+        _ => join_commas(&unquoted(args, lowercase_hex), ", "),
+    };
+    Line::new(Kind::Instruction, format!("{} {}", mnemonic, args))
+}
+
+fn parse(src: &str) -> Result<Vec<Line>, FormatError> {
+    let mut lines = Vec::new();
+    for (n, text) in src.lines().enumerate() {
+        let (code, comment) = split_comment(text, n + 1)?;
+        let mut rest = code.as_str();
+        if rest.starts_with('.') {
+            lines.push(directive(rest));
+            rest = "";
+        } else if let Some(len) = label_len(rest) {
+            lines.push(Line::new(Kind::Label, rest[..len].to_owned()));
+            rest = rest[len..].trim_start();
+        }
+        if !rest.is_empty() {
+            lines.push(instruction(rest));
+        }
+        match lines.last_mut() {
+            Some(last) if !code.is_empty() => last.comment = comment,
+            _ => {
+                let kind = if comment.is_some() {
+                    Kind::Comment
+                } else {
+                    Kind::Blank
+                };
+                lines.push(Line {
+                    kind,
+                    code: String::new(),
+                    comment,
+                });
+            }
+        }
+    }
+    Ok(lines)
+}
+
+/// Sorts each run of metadata directives, moving comment lines along with
+/// the directive below them. Comment lines opening the source stay first.
+fn sort_metadata(lines: Vec<Line>) -> Vec<Line> {
+    let mut out = Vec::with_capacity(lines.len());
+    let mut run: Vec<(usize, Vec<Line>)> = Vec::new();
+    let mut pending: Vec<Line> = Vec::new();
+    let mut opening = true;
+    for line in lines {
+        opening &= matches!(line.kind, Kind::Comment | Kind::Blank);
+        match line.kind {
+            Kind::Comment if opening => out.push(line),
+            Kind::Comment => pending.push(line),
+            Kind::Metadata(i) => {
+                pending.push(line);
+                run.push((i, std::mem::take(&mut pending)));
+            }
+            _ => {
+                run.sort_by_key(|(i, _)| *i);
+                out.extend(run.drain(..).flat_map(|(_, lines)| lines));
+                out.append(&mut pending);
+                out.push(line);
+            }
+        }
+    }
+    run.sort_by_key(|(i, _)| *i);
+    out.extend(run.into_iter().flat_map(|(_, lines)| lines));
+    out.append(&mut pending);
+    out
+}
+
+/// Reduces blank lines to single ones between code, separating the metadata
+/// from the code after it.
+fn space(lines: Vec<Line>) -> Vec<Line> {
+    let mut out: Vec<Line> = Vec::with_capacity(lines.len());
+    let mut last = None;
+    for line in lines {
+        match line.kind {
+            Kind::Blank => {
+                if !matches!(
+                    out.last(),
+                    None | Some(Line {
+                        kind: Kind::Blank,
+                        ..
+                    })
+                ) {
+                    out.push(line);
+                }
+                continue;
+            }
+            Kind::Label | Kind::Instruction if matches!(last, Some(Kind::Metadata(_))) => {
+                let at = out
+                    .iter()
+                    .rposition(|l| l.kind != Kind::Comment)
+                    .map_or(0, |i| i + 1);
+                if out[at - 1].kind != Kind::Blank {
+                    out.insert(at, Line::new(Kind::Blank, String::new()));
+                }
+            }
+            _ => {}
+        }
+        if line.kind != Kind::Comment {
+            last = Some(line.kind);
+        }
+        out.push(line);
+    }
+    while matches!(
+        out.last(),
+        Some(Line {
+            kind: Kind::Blank,
+            ..
+        })
+    ) {
+        out.pop();
+    }
+    out
+}
+
+fn mnemonic_len(line: &Line) -> Option<usize> {
+    match line.kind {
+        Kind::Instruction => line.code.find(' '),
+        _ => None,
+    }
+}
+
+/// Writes a run of lines without blank lines between them, aligning their
+/// operands and comments.
+fn write_block(out: &mut String, block: &[Line]) {
+    let width = block.iter().filter_map(mnemonic_len).max().unwrap_or(0);
+    let codes: Vec<String> = block
+        .iter()
+        .map(|line| match mnemonic_len(line) {
+            Some(n) => format!(
+                "{}{:w$} {}",
+                INDENT,
+                &line.code[..n],
+                &line.code[n + 1..],
+                w = width
+            ),
+            None => format!("{}{}", line.indent(), line.code),
+        })
+        .collect();
+    let column = block
+        .iter()
+        .zip(&codes)
+        .filter(|(line, _)| line.kind != Kind::Comment && line.comment.is_some())
+        .map(|(_, code)| code.len() + 2)
+        .max()
+        .unwrap_or(0);
+    for (i, (line, code)) in block.iter().zip(&codes).enumerate() {
+        match (&line.comment, line.kind) {
+            (Some(comment), Kind::Comment) => {
+                let code = |l: &&Line| l.kind != Kind::Comment;
+                let indent = block[i..]
+                    .iter()
+                    .find(code)
+                    .or_else(|| block[..i].iter().rev().find(code))
+                    .map_or("", Line::indent);
+                out.push_str(indent);
+                out.push_str(comment);
+            }
+            (Some(comment), _) => {
+                out.push_str(&format!("{:w$}{}", code, comment, w = column));
+            }
+            (None, _) => out.push_str(code),
+        }
+        out.push('\n');
+    }
+}
+
+/// Reprints the EWAL source `src` in the canonical layout.
+pub fn format(src: &str) -> Result<String, FormatError> {
+    let lines = space(sort_metadata(parse(src)?));
+    let mut out = String::with_capacity(src.len());
+    for block in lines.split(|l| l.kind == Kind::Blank) {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        write_block(&mut out, block);
+    }
+    Ok(out)
+}
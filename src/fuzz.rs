@@ -0,0 +1,250 @@
+//! Entry points for fuzzing the interpreter and arithmetic.
+//!
+//! `run_single_event` runs one event of an element loaded with `load` in a
+//! window, the same way every time for a given seed. Whatever the window and
+//! the bytecode, it should only ever return an error; a panic, or two runs
+//! which differ (see `is_deterministic`), is a bug.
+//!
+//! Fuzzers hand over raw bytes, which `FuzzInput` turns into the values the
+//! engine works on through `FromFuzz`: constants of every width and
+//! signedness, field selectors, atoms which are empty, well formed or have
+//! bad ECC bits, and whole event windows. The targets under `fuzz/` run with
+//! `cargo fuzz`; one event of a standard element looks like:
+//!
+//! ```
+//! use substrate_engine::fuzz::{self, FuzzInput};
+//! use substrate_engine::runtime::mfm::EventWindow;
+//! use substrate_engine::stdlib;
+//!
+//! let program = stdlib::compile("Res", "fuzz").unwrap().unwrap();
+//! let (mut runtime, element) = fuzz::load(&program).unwrap();
+//!
+//! let data: &[u8] = b"bytes from the fuzzer";
+//! let mut input = FuzzInput::new(data);
+//! let mut window: EventWindow = input.value();
+//! let seed = input.u64();
+//! let _ = fuzz::run_single_event(&mut runtime, element, &mut window, seed);
+//! ```
+//!
+//! Property testing works the same way, from a strategy for `Vec<u8>`.
+
+use crate::base::arith::Const;
+use crate::base::atom::Atom;
+use crate::base::{ecc, FieldSelector};
+use crate::runtime::mfm::EventWindow;
+use crate::runtime::{Error, Runtime};
+
+/// The instructions an event may run under `run_single_event`, low enough
+/// that programs which loop forever don't slow fuzzing down.
+pub const INSTRUCTION_BUDGET: u64 = 10_000;
+
+/// Reads values from a fuzzer's bytes. Once they run out every value reads
+/// as zero, so any input gives some value.
+pub struct FuzzInput<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> FuzzInput<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    /// Reads `n` bytes as a big endian number, padded with zeros if fewer
+    /// are left.
+    fn uint(&mut self, n: usize) -> u128 {
+        let mut v = 0;
+        for _ in 0..n {
+            let b = match self.data.split_first() {
+                Some((b, rest)) => {
+                    self.data = rest;
+                    *b
+                }
+                None => 0,
+            };
+            v = v << 8 | b as u128;
+        }
+        v
+    }
+
+    pub fn u8(&mut self) -> u8 {
+        self.uint(1) as u8
+    }
+
+    pub fn bool(&mut self) -> bool {
+        self.u8() & 1 == 1
+    }
+
+    pub fn u16(&mut self) -> u16 {
+        self.uint(2) as u16
+    }
+
+    pub fn u32(&mut self) -> u32 {
+        self.uint(4) as u32
+    }
+
+    pub fn u64(&mut self) -> u64 {
+        self.uint(8) as u64
+    }
+
+    pub fn u128(&mut self) -> u128 {
+        self.uint(16)
+    }
+
+    /// The bytes not yet read.
+    pub fn rest(self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn value<T: FromFuzz>(&mut self) -> T {
+        T::from_fuzz(self)
+    }
+}
+
+/// Values which can be made from any bytes.
+pub trait FromFuzz: Sized {
+    fn from_fuzz(input: &mut FuzzInput) -> Self;
+}
+
+/// Unsigned constants of up to 96 bits, signed ones of any width from 1 to
+/// 96 bits and fixed point ones with any number of fraction bits.
+impl FromFuzz for Const {
+    fn from_fuzz(input: &mut FuzzInput) -> Self {
+        let kind = input.u8() % 3;
+        let bits = 1 + input.u8() % Const::MAX_BITS;
+        let value = input.u128();
+        match kind {
+            0 => Const::Unsigned(value & Const::MAX_UNSIGNED),
+            1 => Const::signed(value as i128, bits),
+            _ => Const::fixed(value as i128, 1 + bits % Const::MAX_FRAC),
+        }
+    }
+}
+
+/// Non-empty fields anywhere in the 96 bits of an atom.
+impl FromFuzz for FieldSelector {
+    fn from_fuzz(input: &mut FuzzInput) -> Self {
+        let offset = input.u8() % Atom::BITS;
+        let length = 1 + input.u8() % (Atom::BITS - offset);
+        FieldSelector { offset, length }
+    }
+}
+
+/// Empty atoms, atoms with correct ECC bits and atoms with whatever bits.
+impl FromFuzz for Atom {
+    fn from_fuzz(input: &mut FuzzInput) -> Self {
+        let kind = input.u8() % 3;
+        let atom = Atom::from_bits(input.u128() & Const::MAX_UNSIGNED);
+        match kind {
+            0 => Atom::default(),
+            1 => ecc::encode(atom),
+            _ => atom,
+        }
+    }
+}
+
+/// Windows with arbitrary atoms, paint and layers at every site.
+impl FromFuzz for EventWindow<'_> {
+    fn from_fuzz(input: &mut FuzzInput) -> Self {
+        let mut ew = EventWindow::new();
+        for atom in ew.atoms_mut().iter_mut() {
+            *atom = input.value();
+        }
+        for paint in ew.paints_mut().iter_mut() {
+            *paint = input.u32().into();
+        }
+        for v in ew.layers_mut().iter_mut().flatten() {
+            *v = input.u32();
+        }
+        ew
+    }
+}
+
+/// Loads `program`, the bytecode of a compiled element, into a runtime for
+/// `run_single_event`, giving the runtime and the element's type. Events may
+/// run at most `INSTRUCTION_BUDGET` instructions.
+pub fn load(program: &[u8]) -> Result<(Runtime<'static>, u16), Error> {
+    let mut runtime = Runtime::new();
+    runtime.set_instruction_budget(Some(INSTRUCTION_BUDGET));
+    let mut r = program;
+    let atom = runtime.load_from_reader(&mut r)?;
+    Ok((runtime, atom.get_type()))
+}
+
+/// Runs one event of `element`, loaded into `runtime` by `load`, in
+/// `window`, with the random number generator seeded with `seed` and ids
+/// handed out from 1. Site 0 is given the element's type, keeping its other
+/// bits.
+pub fn run_single_event(
+    runtime: &mut Runtime,
+    element: u16,
+    window: &mut EventWindow,
+    seed: u64,
+) -> Result<(), Error> {
+    runtime.reseed(seed);
+    runtime.set_next_id(1);
+    window.atoms_mut()[0].set_type(element);
+    runtime.execute(window)
+}
+
+/// Runs the event of `run_single_event` twice from copies of `window`,
+/// checking that both end the same way and leave the same window.
+pub fn is_deterministic(
+    runtime: &mut Runtime,
+    element: u16,
+    window: &EventWindow,
+    seed: u64,
+) -> bool {
+    let copy = || {
+        let mut ew = EventWindow::new();
+        *ew.atoms_mut() = *window.atoms();
+        *ew.paints_mut() = *window.paints();
+        *ew.layers_mut() = *window.layers();
+        ew
+    };
+    let (mut a, mut b) = (copy(), copy());
+    let mut run = |ew: &mut EventWindow| {
+        run_single_event(runtime, element, ew, seed).map_err(|e| e.to_string())
+    };
+    let (ra, rb) = (run(&mut a), run(&mut b));
+    let paints = |ew: &EventWindow| ew.paints().iter().map(|p| p.bits()).collect::<Vec<u32>>();
+    ra == rb
+        && a.atoms() == b.atoms()
+        && paints(&a) == paints(&b)
+        && a.layers() == b.layers()
+        && a.symmetry() == b.symmetry()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::rng::Prng;
+    use crate::stdlib;
+    use rand::RngCore;
+
+    #[test]
+    fn standard_elements_are_deterministic() {
+        for (name, _) in stdlib::ELEMENTS.iter() {
+            let program = stdlib::compile(name, "fuzz").unwrap().unwrap();
+            let (mut runtime, element) = load(&program).unwrap();
+            let mut rng = Prng::new(1);
+            for _ in 0..100 {
+                let mut data = vec![0; 2048];
+                rng.fill_bytes(&mut data);
+                let mut input = FuzzInput::new(&data);
+                let window: EventWindow = input.value();
+                let seed = input.u64();
+                assert!(
+                    is_deterministic(&mut runtime, element, &window, seed),
+                    "{}",
+                    name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn bad_bytecode_is_an_error() {
+        assert!(load(b"").is_err());
+        assert!(load(&[0x02, 0x03, 0x07, 0x41, 0xff]).is_err());
+    }
+}
@@ -0,0 +1,208 @@
+//! The intermediate representation of element code, between its parsed
+//! source and its bytecode.
+//!
+//! Each element's body is lowered to an `Ir`: its instructions in order, with
+//! every jump and call target resolved to an instruction index, and its
+//! labels beside them. `verify` checks it, `optimize` rewrites it and the
+//! bytecode is written from it.
+
+use crate::ast::{Arg, Instruction, Node};
+use std::fmt::Write;
+
+/// An element's instructions and labels, with its targets resolved.
+#[derive(Clone, Debug, Default)]
+pub struct Ir<'input> {
+    pub code: Vec<Instruction<'input>>,
+    /// Each label with the instruction it is at, in source order.
+    pub labels: Vec<(&'input str, u16)>,
+    /// The byte offset in the source of each instruction, or of the first
+    /// of those an instruction replaced, for errors to point at.
+    pub offsets: Vec<usize>,
+}
+
+/// What a pass replaces each instruction with, if anything. A sequence of
+/// instructions is replaced through its first, the others being replaced
+/// with nothing.
+pub type Edits<'input> = Vec<Option<Vec<Instruction<'input>>>>;
+
+/// The target of a jump or call.
+pub fn target(i: &Instruction) -> Option<usize> {
+    match i {
+        Instruction::Call(x)
+        | Instruction::Jump(x)
+        | Instruction::JumpZero(x)
+        | Instruction::JumpNonZero(x)
+        | Instruction::JumpIf(_, x) => Some(*x.runtime() as usize),
+        _ => None,
+    }
+}
+
+pub fn retarget(i: Instruction, t: usize) -> Instruction {
+    let t = Arg::Runtime(t as u16);
+    match i {
+        Instruction::Call(_) => Instruction::Call(t),
+        Instruction::Jump(_) => Instruction::Jump(t),
+        Instruction::JumpZero(_) => Instruction::JumpZero(t),
+        Instruction::JumpNonZero(_) => Instruction::JumpNonZero(t),
+        Instruction::JumpIf(c, _) => Instruction::JumpIf(c, t),
+        i => i,
+    }
+}
+
+impl<'input> Ir<'input> {
+    /// Lowers `body`, whose targets are resolved, found at `offsets` in the
+    /// source.
+    pub fn new(body: &[Node<'input>], offsets: &[usize]) -> Self {
+        let mut ir = Self::default();
+        for (n, o) in body.iter().zip(offsets.iter()) {
+            match n {
+                Node::Label(name) => ir.labels.push((*name, ir.code.len() as u16)),
+                Node::Instruction(i) => {
+                    ir.code.push(*i);
+                    ir.offsets.push(*o);
+                }
+                Node::Metadata(_) => {}
+            }
+        }
+        ir
+    }
+
+    /// Code loaded from bytecode, whose targets are resolved and which has no
+    /// source to point into.
+    pub fn from_code(code: &[Instruction<'input>]) -> Self {
+        Self {
+            code: code.to_vec(),
+            labels: Vec::new(),
+            offsets: vec![0; code.len()],
+        }
+    }
+
+    /// The instructions a `ret` may return to.
+    pub fn returns(&self) -> Vec<usize> {
+        (0..self.code.len())
+            .filter(|i| matches!(self.code[*i], Instruction::Call(_)))
+            .map(|i| i + 1)
+            .collect()
+    }
+
+    /// The instructions which may run after instruction `i`. The end of the
+    /// code is `code.len()`.
+    pub fn successors(&self, i: usize, returns: &[usize]) -> Vec<usize> {
+        match self.code[i] {
+            Instruction::Jump(x) | Instruction::Call(x) => vec![*x.runtime() as usize],
+            Instruction::JumpZero(x) | Instruction::JumpNonZero(x) | Instruction::JumpIf(_, x) => {
+                vec![i + 1, *x.runtime() as usize]
+            }
+            Instruction::Ret => returns.to_vec(),
+            Instruction::Exit | Instruction::JumpRelativeOffset => Vec::new(),
+            _ => vec![i + 1],
+        }
+    }
+
+    /// Whether each instruction may be jumped or returned to, so that no
+    /// sequence replaced as one may continue through it.
+    pub fn entries(&self) -> Vec<bool> {
+        let mut entries = vec![false; self.code.len() + 1];
+        for (ip, i) in self.code.iter().enumerate() {
+            if let Some(t) = target(i) {
+                entries[t] = true;
+            }
+            if let Instruction::Call(_) = i {
+                entries[ip + 1] = true;
+            }
+        }
+        entries
+    }
+
+    /// Whether the code jumps by `jumprelativeoffset`, to instructions which
+    /// are only known as it runs.
+    pub fn has_computed_jumps(&self) -> bool {
+        self.code
+            .iter()
+            .any(|i| matches!(i, Instruction::JumpRelativeOffset))
+    }
+
+    /// Applies `edits`, renumbering targets and labels.
+    pub fn edit(&self, edits: Edits<'input>) -> Self {
+        let mut index = Vec::with_capacity(self.code.len() + 1);
+        let mut n = 0;
+        for e in edits.iter() {
+            index.push(n);
+            n += e.as_ref().map_or(1, |e| e.len());
+        }
+        index.push(n);
+        let mut code = Vec::with_capacity(n);
+        let mut offsets = Vec::with_capacity(n);
+        for (i, e) in edits.into_iter().enumerate() {
+            for x in e.unwrap_or_else(|| vec![self.code[i]]) {
+                code.push(match target(&x) {
+                    Some(t) => retarget(x, index[t]),
+                    None => x,
+                });
+                offsets.push(self.offsets[i]);
+            }
+        }
+        let labels = self
+            .labels
+            .iter()
+            .map(|(name, ip)| (*name, index[*ip as usize] as u16))
+            .collect();
+        Self {
+            code,
+            labels,
+            offsets,
+        }
+    }
+
+    /// Lists the code one instruction or label per line, with instruction
+    /// numbers, to inspect what `optimize` does.
+    pub fn dump(&self) -> String {
+        let label = |t: usize| {
+            self.labels
+                .iter()
+                .find(|(_, ip)| *ip as usize == t)
+                .map_or_else(|| t.to_string(), |(name, _)| name.to_string())
+        };
+        let mut out = String::new();
+        let mut labels = self.labels.iter().peekable();
+        for (ip, i) in self.code.iter().enumerate() {
+            while let Some((name, _)) = labels.next_if(|(_, at)| *at as usize <= ip) {
+                writeln!(out, "{}:", name).unwrap();
+            }
+            let operand = match i {
+                Instruction::SetField(x)
+                | Instruction::SetSiteField(x)
+                | Instruction::GetField(x)
+                | Instruction::GetSiteField(x)
+                | Instruction::Bond(x)
+                | Instruction::Unbond(x)
+                | Instruction::FollowBond(x) => x.get_ast().map(|f| f.to_string()),
+                Instruction::GetType(x) => x.get_ast().map(|t| format!("\"{}\"", t)),
+                Instruction::GetParameter(x) => x.get_ast().map(|p| p.to_string()),
+                Instruction::UseSymmetries(s) => Some(s.to_string()),
+                Instruction::ZeroExtend(n)
+                | Instruction::SignExtend(n)
+                | Instruction::Truncate(n)
+                | Instruction::Count(n) => Some(n.to_string()),
+                Instruction::GetRegister(n) | Instruction::SetRegister(n) => {
+                    if *n == Instruction::REGISTERS {
+                        Some("R?".to_owned())
+                    } else {
+                        Some(format!("R{}", n))
+                    }
+                }
+                Instruction::Push(c) => Some(c.to_literal()),
+                i => target(i).map(label),
+            };
+            let text = match operand {
+                Some(x) => format!("{} {}", i.mnemonic(), x),
+                None => i.mnemonic().to_owned(),
+            };
+            writeln!(out, "    {:<24} /* {} */", text, ip).unwrap();
+        }
+        for (name, _) in labels {
+            writeln!(out, "{}:", name).unwrap();
+        }
+        out
+    }
+}
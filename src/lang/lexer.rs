@@ -0,0 +1,97 @@
+//! Splits source text into tokens.
+
+use super::LangError;
+use crate::base::arith::Const;
+use std::ops::Range;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token<'a> {
+    Ident(&'a str),
+    Number(u128),
+    Str(&'a str),
+    /// Punctuation and operators, such as `{` and `<=`.
+    Punct(&'static str),
+    Eof,
+}
+
+#[derive(Clone, Debug)]
+pub struct Spanned<'a> {
+    pub token: Token<'a>,
+    pub span: Range<usize>,
+}
+
+/// Operators and punctuation, longest first so that `<=` isn't read as `<`.
+const PUNCT: &[&str] = &[
+    "..=", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=", "*=", "/=", "%=", "..", "{",
+    "}", "(", ")", "[", "]", ";", ":", ",", ".", "=", "<", ">", "+", "-", "*", "/", "%", "&", "|",
+    "^", "!",
+];
+
+fn number(text: &str, span: Range<usize>) -> Result<u128, LangError> {
+    // Numbers start with a digit, so they are never signed.
+    match text.parse::<Const>() {
+        Ok(Const::Unsigned(n)) => Ok(n),
+        Ok(_) => Err(LangError::new(span, format!("bad number: {}", text))),
+        Err(e) => Err(LangError::new(span, format!("bad number {}: {}", text, e))),
+    }
+}
+
+pub fn tokenize(src: &str) -> Result<Vec<Spanned<'_>>, LangError> {
+    let bytes = src.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+        let token = if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        } else if src[i..].starts_with("//") {
+            i = src[i..].find('\n').map_or(src.len(), |n| i + n);
+            continue;
+        } else if src[i..].starts_with("/*") {
+            i = src[i + 2..]
+                .find("*/")
+                .map(|n| i + 2 + n + 2)
+                .ok_or_else(|| LangError::new(start..start + 2, "unterminated comment"))?;
+            continue;
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            Token::Ident(&src[start..i])
+        } else if c.is_ascii_digit() {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            Token::Number(number(&src[start..i], start..i)?)
+        } else if c == b'"' {
+            let end = src[i + 1..]
+                .find(['"', '\n'])
+                .map(|n| i + 1 + n)
+                .filter(|&n| bytes[n] == b'"')
+                .ok_or_else(|| LangError::new(start..start + 1, "unterminated string"))?;
+            i = end + 1;
+            Token::Str(&src[start + 1..end])
+        } else {
+            let p = PUNCT
+                .iter()
+                .find(|p| src[i..].starts_with(**p))
+                .ok_or_else(|| {
+                    let len = src[i..].chars().next().map_or(1, char::len_utf8);
+                    LangError::new(start..start + len, "unexpected character")
+                })?;
+            i += p.len();
+            Token::Punct(p)
+        };
+        tokens.push(Spanned {
+            token,
+            span: start..i,
+        });
+    }
+    tokens.push(Spanned {
+        token: Token::Eof,
+        span: src.len()..src.len(),
+    });
+    Ok(tokens)
+}
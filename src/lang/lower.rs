@@ -0,0 +1,1023 @@
+//! Lowers the syntax tree to EWAL.
+//!
+//! Expressions leave their value on the stack, and statements leave the
+//! stack as they found it, so loops and `return` never have values to clean
+//! up. Variables live in registers: each function has its own, placed after
+//! those of every function which may call it, so calls never need to save
+//! them.
+
+use super::parser::{
+    BinOp, Block, Expr, ExprKind, FieldType, Item, MetaValue, Stmt, StmtKind, UnOp,
+};
+use super::LangError;
+use crate::ast::Instruction;
+use crate::base::arith::{ArithMode, DivZeroPolicy};
+use crate::base::color::Color;
+use crate::base::{FieldSelector, Symmetries};
+use crate::diag::suggest;
+use crate::preprocess::letters;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::Write;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// Whether `s` can be used as an EWAL identifier.
+fn is_ewal_ident(s: &str) -> bool {
+    let mut c = s.chars();
+    matches!(c.next(), Some('a'..='z') | Some('_'))
+        && c.all(|c| c.is_ascii_alphabetic() || c == '_')
+}
+
+/// A line of output whose register operand is only known once every
+/// function's registers are placed.
+enum Line {
+    Text(String),
+    GetRegister(usize),
+    SetRegister(usize),
+}
+
+struct Field {
+    /// The name used in the EWAL source.
+    ewal: String,
+    ty: FieldType,
+}
+
+struct Function<'a> {
+    label: String,
+    params: usize,
+    span: Range<usize>,
+    body: &'a Block<'a>,
+}
+
+/// What every function can see: constants, fields and functions.
+struct Globals<'a> {
+    consts: HashMap<&'a str, i128>,
+    fields: HashMap<&'a str, Field>,
+    functions: HashMap<&'a str, Function<'a>>,
+}
+
+/// Builtins, with the number of arguments they take, whether they give a
+/// value and the instruction they run.
+pub const BUILTINS: &[(&str, usize, bool, &str)] = &[
+    ("type", 1, true, "gettype"),
+    ("new", 1, true, "gettype"),
+    ("odds", 2, true, "oddsof"),
+    ("rand", 1, true, "rand"),
+    ("random", 0, true, "getregister"),
+    ("randsite", 0, true, "randsite"),
+    ("scan", 1, true, "scan"),
+    ("countof", 2, true, "countof"),
+    ("findfirst", 1, true, "findfirst"),
+    ("findrandom", 1, true, "findrandom"),
+    ("bitcount", 1, true, "bitcount"),
+    ("bitscanforward", 1, true, "bitscanforward"),
+    ("bitscanreverse", 1, true, "bitscanreverse"),
+    ("popcnt", 1, true, "popcnt"),
+    ("clz", 1, true, "clz"),
+    ("ctz", 1, true, "ctz"),
+    ("rotl", 2, true, "rotl"),
+    ("rotr", 2, true, "rotr"),
+    ("min", 2, true, "min"),
+    ("max", 2, true, "max"),
+    ("clamp", 3, true, "clamp"),
+    ("abs", 1, true, "abs"),
+    ("mulh", 2, true, "mulh"),
+    ("checksum", 1, true, "checksum"),
+    ("paint", 1, true, "getpaint"),
+    ("layer", 2, true, "getlayer"),
+    ("heat", 1, true, "getheat"),
+    ("absorbheat", 2, true, "absorbheat"),
+    ("hash", 2, true, "hash"),
+    ("newid", 0, true, "newid"),
+    ("zext", 2, true, "zext"),
+    ("sext", 2, true, "sext"),
+    ("trunc", 2, true, "trunc"),
+    ("swap", 2, false, "swapsites"),
+    ("move", 2, false, "movesite"),
+    ("setpaint", 2, false, "setpaint"),
+    ("setlayer", 3, false, "setlayer"),
+    ("decaylayer", 2, false, "decaylayer"),
+    ("emitheat", 2, false, "emitheat"),
+    ("diffuse", 1, false, "diffuse"),
+    ("assert", 1, false, "assert"),
+    ("count", 1, false, "count"),
+];
+
+/// A function being lowered.
+struct Gen<'g, 'a> {
+    globals: &'g Globals<'a>,
+    lines: Vec<Line>,
+    scopes: Vec<Vec<(&'a str, usize)>>,
+    /// The number of registers in use, and the most ever in use.
+    live: usize,
+    frame: usize,
+    /// The labels `continue` and `break` jump to in each enclosing loop.
+    loops: Vec<(String, String)>,
+    calls: Vec<&'a str>,
+    in_function: bool,
+    next_label: &'g mut usize,
+}
+
+impl<'g, 'a> Gen<'g, 'a> {
+    fn new(globals: &'g Globals<'a>, in_function: bool, next_label: &'g mut usize) -> Self {
+        Self {
+            globals,
+            lines: Vec::new(),
+            scopes: vec![Vec::new()],
+            live: 0,
+            frame: 0,
+            loops: Vec::new(),
+            calls: Vec::new(),
+            in_function,
+            next_label,
+        }
+    }
+
+    fn op(&mut self, s: &str) {
+        self.lines.push(Line::Text(format!("  {}", s)));
+    }
+
+    fn push(&mut self, n: i128) {
+        self.op(&match n {
+            0..=40 => format!("push{}", n),
+            _ => format!("push {}", n),
+        });
+    }
+
+    fn label(&mut self, kind: &str) -> String {
+        let l = format!("_{}_{}", kind, letters(*self.next_label));
+        *self.next_label += 1;
+        l
+    }
+
+    fn place(&mut self, label: &str) {
+        self.lines.push(Line::Text(format!("{}:", label)));
+    }
+
+    fn jump(&mut self, op: &str, label: &str) {
+        self.op(&format!("{} {}", op, label));
+    }
+
+    /// Takes a register for a value which lasts until the enclosing scope
+    /// ends.
+    fn alloc(&mut self) -> usize {
+        self.live += 1;
+        self.frame = self.frame.max(self.live);
+        self.live - 1
+    }
+
+    fn bind(&mut self, name: &'a str) -> usize {
+        let r = self.alloc();
+        self.scopes.last_mut().unwrap().push((name, r));
+        r
+    }
+
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|s| s.iter().rev())
+            .find(|(n, _)| *n == name)
+            .map(|(_, r)| *r)
+    }
+
+    fn block(&mut self, block: &'a Block<'a>) -> Result<(), LangError> {
+        self.scopes.push(Vec::new());
+        for s in block {
+            self.stmt(s)?;
+        }
+        let scope = self.scopes.pop().unwrap();
+        self.live -= scope.len();
+        Ok(())
+    }
+
+    fn field(
+        &self,
+        name: &str,
+        span: &Range<usize>,
+    ) -> Result<(String, Option<FieldType>), LangError> {
+        if let Some(f) = self.globals.fields.get(name) {
+            return Ok((f.ewal.clone(), Some(f.ty)));
+        }
+        if ["type", "header", "data"].contains(&name) {
+            return Ok((name.to_owned(), None));
+        }
+        let known = self.globals.fields.keys().copied();
+        let mut e = LangError::new(span.clone(), format!("unknown field: {}", name));
+        e.help = suggest(
+            name,
+            known.chain(["type", "header", "data"].iter().copied()),
+        )
+        .map(|s| format!("did you mean `{}`?", s));
+        Err(e)
+    }
+
+    /// Reads field `f` of the atom on the stack, or of the site numbered on
+    /// the stack with `getsitefield`.
+    fn read_field(&mut self, op: &str, f: &str, span: &Range<usize>) -> Result<(), LangError> {
+        let (name, ty) = self.field(f, span)?;
+        self.op(&format!("{} {}", op, name));
+        if let Some(FieldType {
+            signed: true, bits, ..
+        }) = ty
+        {
+            self.op(&format!("sext {}", bits));
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self, e: &'a Expr<'a>) -> Result<(), LangError> {
+        match &e.kind {
+            ExprKind::Number(n) => self.push(*n as i128),
+            ExprKind::Str(_) => {
+                let mut err = LangError::new(e.span.clone(), "a string is not a value");
+                err.help = Some("element types are named with `type(\"Name\")`".to_owned());
+                return Err(err);
+            }
+            ExprKind::Var(name) => {
+                if let Some(r) = self.lookup(name) {
+                    self.lines.push(Line::GetRegister(r));
+                } else if let Some(c) = self.globals.consts.get(name) {
+                    self.push(*c);
+                } else {
+                    let mut err =
+                        LangError::new(e.span.clone(), format!("unknown variable: {}", name));
+                    let scoped = self.scopes.iter().flatten().map(|(n, _)| *n);
+                    err.help = suggest(name, scoped.chain(self.globals.consts.keys().copied()))
+                        .map(|s| format!("did you mean `{}`?", s));
+                    return Err(err);
+                }
+            }
+            ExprKind::Site(n) => {
+                self.expr(n)?;
+                self.op("getsite");
+            }
+            ExprKind::SelfAtom => {
+                self.push(0);
+                self.op("getsite");
+            }
+            ExprKind::Field(base, f) => match &base.kind {
+                ExprKind::Site(n) => {
+                    self.expr(n)?;
+                    self.read_field("getsitefield", f, &e.span)?;
+                }
+                ExprKind::SelfAtom => {
+                    self.push(0);
+                    self.read_field("getsitefield", f, &e.span)?;
+                }
+                _ => {
+                    self.expr(base)?;
+                    self.read_field("getfield", f, &e.span)?;
+                }
+            },
+            ExprKind::Call(name, args) => {
+                if !self.call(name, args, &e.span)? {
+                    return Err(LangError::new(
+                        e.span.clone(),
+                        format!("`{}` gives no value", name),
+                    ));
+                }
+            }
+            ExprKind::Unary(UnOp::Neg, a) => {
+                self.expr(a)?;
+                self.op("neg");
+            }
+            ExprKind::Unary(UnOp::Not, a) => {
+                self.expr(a)?;
+                self.push(0);
+                self.op("equal");
+            }
+            ExprKind::Binary(BinOp::And, ..) | ExprKind::Binary(BinOp::Or, ..) => {
+                let (no, end) = (self.label("false"), self.label("end"));
+                self.jump_unless(e, &no)?;
+                self.push(1);
+                self.jump("jump", &end);
+                self.place(&no);
+                self.push(0);
+                self.place(&end);
+            }
+            ExprKind::Binary(op, a, b) => {
+                self.expr(a)?;
+                self.expr(b)?;
+                let ops: &[&str] = match op {
+                    BinOp::Eq => &["equal"],
+                    BinOp::Ne => &["equal", "push0", "equal"],
+                    BinOp::Lt => &["less"],
+                    BinOp::Le => &["lessequal"],
+                    BinOp::Gt => &["lessequal", "push0", "equal"],
+                    BinOp::Ge => &["less", "push0", "equal"],
+                    BinOp::BitOr => &["or"],
+                    BinOp::BitXor => &["xor"],
+                    BinOp::BitAnd => &["and"],
+                    BinOp::Shl => &["lshift"],
+                    BinOp::Shr => &["rshift"],
+                    BinOp::Add => &["add"],
+                    BinOp::Sub => &["sub"],
+                    BinOp::Mul => &["mul"],
+                    BinOp::Div => &["div"],
+                    BinOp::Mod => &["mod"],
+                    BinOp::And | BinOp::Or => unreachable!(),
+                };
+                for o in ops {
+                    self.op(o);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Jumps to `label` if `e` is zero.
+    fn jump_unless(&mut self, e: &'a Expr<'a>, label: &str) -> Result<(), LangError> {
+        match &e.kind {
+            ExprKind::Binary(BinOp::And, a, b) => {
+                self.jump_unless(a, label)?;
+                self.jump_unless(b, label)
+            }
+            ExprKind::Binary(BinOp::Or, a, b) => {
+                let yes = self.label("true");
+                self.jump_if(a, &yes)?;
+                self.jump_unless(b, label)?;
+                self.place(&yes);
+                Ok(())
+            }
+            ExprKind::Unary(UnOp::Not, a) => self.jump_if(a, label),
+            _ => {
+                self.expr(e)?;
+                self.jump("jumpzero", label);
+                Ok(())
+            }
+        }
+    }
+
+    /// Jumps to `label` if `e` is not zero.
+    fn jump_if(&mut self, e: &'a Expr<'a>, label: &str) -> Result<(), LangError> {
+        match &e.kind {
+            ExprKind::Binary(BinOp::Or, a, b) => {
+                self.jump_if(a, label)?;
+                self.jump_if(b, label)
+            }
+            ExprKind::Binary(BinOp::And, a, b) => {
+                let no = self.label("false");
+                self.jump_unless(a, &no)?;
+                self.jump_if(b, label)?;
+                self.place(&no);
+                Ok(())
+            }
+            ExprKind::Unary(UnOp::Not, a) => self.jump_unless(a, label),
+            _ => {
+                self.expr(e)?;
+                self.jump("jumpnonzero", label);
+                Ok(())
+            }
+        }
+    }
+
+    /// Lowers a call, giving whether it leaves a value.
+    fn call(
+        &mut self,
+        name: &'a str,
+        args: &'a [Expr<'a>],
+        span: &Range<usize>,
+    ) -> Result<bool, LangError> {
+        let arity = |n: usize| {
+            if args.len() == n {
+                Ok(())
+            } else {
+                Err(LangError::new(
+                    span.clone(),
+                    format!(
+                        "`{}` takes {} argument{}, not {}",
+                        name,
+                        n,
+                        if n == 1 { "" } else { "s" },
+                        args.len()
+                    ),
+                ))
+            }
+        };
+        let globals = self.globals;
+        if let Some(f) = globals.functions.get(name) {
+            arity(f.params)?;
+            for a in args {
+                self.expr(a)?;
+            }
+            self.jump("call", &f.label);
+            self.calls.push(name);
+            return Ok(true);
+        }
+        let (_, n, value, op) = match BUILTINS.iter().find(|(b, _, _, _)| *b == name) {
+            Some(b) => *b,
+            None => {
+                let mut err = LangError::new(span.clone(), format!("unknown function: {}", name));
+                let names = BUILTINS.iter().map(|(b, _, _, _)| *b);
+                err.help = suggest(name, names.chain(self.globals.functions.keys().copied()))
+                    .map(|s| format!("did you mean `{}`?", s));
+                return Err(err);
+            }
+        };
+        arity(n)?;
+        match name {
+            "type" | "new" => {
+                let t = match &args[0].kind {
+                    ExprKind::Str(t) if !t.is_empty() => t,
+                    _ => {
+                        return Err(LangError::new(
+                            args[0].span.clone(),
+                            "expected an element name, as in \"Res\"",
+                        ))
+                    }
+                };
+                self.op(&format!("{} \"{}\"", op, t));
+                if name == "new" {
+                    self.push(0);
+                    self.op("setfield type");
+                }
+            }
+            "zext" | "sext" | "trunc" => {
+                self.expr(&args[0])?;
+                let w = const_eval(self.globals, &args[1])?;
+                if !(1..=96).contains(&w) {
+                    return Err(LangError::new(
+                        args[1].span.clone(),
+                        format!("width {} is not between 1 and 96", w),
+                    ));
+                }
+                self.op(&format!("{} {}", op, w));
+            }
+            "count" => {
+                let c = const_eval(self.globals, &args[0])?;
+                if !(0..16).contains(&c) {
+                    return Err(LangError::new(
+                        args[0].span.clone(),
+                        format!("counter {} is not between 0 and 15", c),
+                    ));
+                }
+                self.op(&format!("{} {}", op, c));
+            }
+            "random" => self.lines.push(Line::Text(format!("  {} R?", op))),
+            // Sites, paint and heat are written with the site on top.
+            "setpaint" | "emitheat" | "absorbheat" => {
+                self.expr(&args[1])?;
+                self.expr(&args[0])?;
+                self.op(op);
+            }
+            "setlayer" => {
+                self.expr(&args[2])?;
+                self.expr(&args[0])?;
+                self.expr(&args[1])?;
+                self.op(op);
+            }
+            _ => {
+                for a in args {
+                    self.expr(a)?;
+                }
+                self.op(op);
+            }
+        }
+        Ok(value)
+    }
+
+    fn stmt(&mut self, s: &'a Stmt<'a>) -> Result<(), LangError> {
+        match &s.kind {
+            StmtKind::Let(name, value) => {
+                self.expr(value)?;
+                let r = self.bind(name);
+                self.lines.push(Line::SetRegister(r));
+            }
+            StmtKind::Assign(target, op, value) => self.assign(target, *op, value, &s.span)?,
+            StmtKind::If(arms, otherwise) => {
+                let end = self.label("end");
+                for (i, (cond, body)) in arms.iter().enumerate() {
+                    let next = self.label("else");
+                    self.jump_unless(cond, &next)?;
+                    self.block(body)?;
+                    if i + 1 < arms.len() || otherwise.is_some() {
+                        self.jump("jump", &end);
+                    }
+                    self.place(&next);
+                }
+                if let Some(body) = otherwise {
+                    self.block(body)?;
+                }
+                self.place(&end);
+            }
+            StmtKind::While(cond, body) => {
+                let (top, end) = (self.label("while"), self.label("end"));
+                self.place(&top);
+                self.jump_unless(cond, &end)?;
+                self.loops.push((top.clone(), end.clone()));
+                self.block(body)?;
+                self.loops.pop();
+                self.jump("jump", &top);
+                self.place(&end);
+            }
+            StmtKind::For(name, from, to, inclusive, body) => {
+                let (top, step, end) = (self.label("for"), self.label("next"), self.label("end"));
+                self.scopes.push(Vec::new());
+                self.expr(from)?;
+                let i = self.bind(name);
+                self.lines.push(Line::SetRegister(i));
+                self.expr(to)?;
+                let limit = self.alloc();
+                self.scopes.last_mut().unwrap().push(("", limit));
+                self.lines.push(Line::SetRegister(limit));
+                self.place(&top);
+                self.lines.push(Line::GetRegister(i));
+                self.lines.push(Line::GetRegister(limit));
+                self.op(if *inclusive { "lessequal" } else { "less" });
+                self.jump("jumpzero", &end);
+                self.loops.push((step.clone(), end.clone()));
+                self.block(body)?;
+                self.loops.pop();
+                self.place(&step);
+                self.lines.push(Line::GetRegister(i));
+                self.push(1);
+                self.op("add");
+                self.lines.push(Line::SetRegister(i));
+                self.jump("jump", &top);
+                self.place(&end);
+                let scope = self.scopes.pop().unwrap();
+                self.live -= scope.len();
+            }
+            StmtKind::Break | StmtKind::Continue => {
+                let (next, end) = match self.loops.last() {
+                    Some(l) => l.clone(),
+                    None => {
+                        return Err(LangError::new(s.span.clone(), "not inside a loop"));
+                    }
+                };
+                let target = if let StmtKind::Break = s.kind {
+                    end
+                } else {
+                    next
+                };
+                self.jump("jump", &target);
+            }
+            StmtKind::Return(value) => {
+                if !self.in_function {
+                    if value.is_some() {
+                        return Err(LangError::new(
+                            s.span.clone(),
+                            "the event returns no value; use `return;` or `exit;`",
+                        ));
+                    }
+                    self.op("exit");
+                } else {
+                    match value {
+                        Some(v) => self.expr(v)?,
+                        None => self.push(0),
+                    }
+                    self.op("ret");
+                }
+            }
+            StmtKind::Exit => self.op("exit"),
+            StmtKind::Expr(e) => match &e.kind {
+                ExprKind::Call(name, args) => {
+                    if self.call(name, args, &e.span)? {
+                        self.op("pop");
+                    }
+                }
+                _ => {
+                    return Err(LangError::new(
+                        e.span.clone(),
+                        "only calls can stand alone as statements",
+                    ))
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn assign(
+        &mut self,
+        target: &'a Expr<'a>,
+        op: Option<BinOp>,
+        value: &'a Expr<'a>,
+        span: &Range<usize>,
+    ) -> Result<(), LangError> {
+        // The new value: `value`, or the target combined with it.
+        let new = |g: &mut Self, read: &dyn Fn(&mut Self) -> Result<(), LangError>| {
+            if let Some(op) = op {
+                read(g)?;
+                g.expr(value)?;
+                g.op(match op {
+                    BinOp::Add => "add",
+                    BinOp::Sub => "sub",
+                    BinOp::Mul => "mul",
+                    BinOp::Div => "div",
+                    _ => "mod",
+                });
+                Ok(())
+            } else {
+                g.expr(value)
+            }
+        };
+        match &target.kind {
+            ExprKind::Var(name) => {
+                let r = match self.lookup(name) {
+                    Some(r) => r,
+                    None if self.globals.consts.contains_key(name) => {
+                        return Err(LangError::new(
+                            target.span.clone(),
+                            format!("{} is a constant", name),
+                        ))
+                    }
+                    None => return self.expr(target),
+                };
+                new(self, &|g| {
+                    g.lines.push(Line::GetRegister(r));
+                    Ok(())
+                })?;
+                self.lines.push(Line::SetRegister(r));
+            }
+            ExprKind::Site(n) if op.is_none() => {
+                self.expr(n)?;
+                self.expr(value)?;
+                self.op("setsite");
+            }
+            ExprKind::Field(base, f) => {
+                let (name, ty) = self.field(f, &target.span)?;
+                if let (None, Some(ty), Ok(v)) = (op, ty, const_eval(self.globals, value)) {
+                    let (lo, hi) = ty.range();
+                    if v < lo || v > hi {
+                        let mut e = LangError::new(
+                            value.span.clone(),
+                            format!("{} doesn't fit field `{}`", v, f),
+                        );
+                        e.help = Some(format!("it holds {} to {}", lo, hi));
+                        return Err(e);
+                    }
+                }
+                match &base.kind {
+                    ExprKind::Site(_) | ExprKind::SelfAtom => {
+                        // The site number is kept in a register so that it
+                        // is only worked out once.
+                        let t = self.alloc();
+                        match &base.kind {
+                            ExprKind::Site(n) => self.expr(n)?,
+                            _ => self.push(0),
+                        }
+                        self.lines.push(Line::SetRegister(t));
+                        new(self, &|g| {
+                            g.lines.push(Line::GetRegister(t));
+                            g.read_field("getsitefield", f, &target.span)
+                        })?;
+                        self.lines.push(Line::GetRegister(t));
+                        self.op(&format!("setsitefield {}", name));
+                        self.live -= 1;
+                    }
+                    ExprKind::Var(v) if self.lookup(v).is_some() => {
+                        let r = self.lookup(v).unwrap();
+                        new(self, &|g| {
+                            g.lines.push(Line::GetRegister(r));
+                            g.read_field("getfield", f, &target.span)
+                        })?;
+                        self.lines.push(Line::GetRegister(r));
+                        self.op(&format!("setfield {}", name));
+                        self.lines.push(Line::SetRegister(r));
+                    }
+                    _ => {
+                        return Err(LangError::new(
+                            target.span.clone(),
+                            "only fields of sites, `self` and variables can be assigned",
+                        ))
+                    }
+                }
+            }
+            _ => {
+                let what = if let ExprKind::Site(_) = target.kind {
+                    "a site can only be assigned with `=`"
+                } else {
+                    "this can't be assigned"
+                };
+                return Err(LangError::new(span.clone(), what));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Evaluates a constant expression.
+fn const_eval(globals: &Globals, e: &Expr) -> Result<i128, LangError> {
+    let overflow = || LangError::new(e.span.clone(), "constant out of range");
+    let v = match &e.kind {
+        ExprKind::Number(n) => *n as i128,
+        ExprKind::Var(name) => *globals
+            .consts
+            .get(name)
+            .ok_or_else(|| LangError::new(e.span.clone(), format!("{} is not a constant", name)))?,
+        ExprKind::Unary(UnOp::Neg, a) => -const_eval(globals, a)?,
+        ExprKind::Unary(UnOp::Not, a) => (const_eval(globals, a)? == 0) as i128,
+        ExprKind::Binary(op, a, b) => {
+            let (a, b) = (const_eval(globals, a)?, const_eval(globals, b)?);
+            let zero = || LangError::new(e.span.clone(), "division by zero");
+            match op {
+                BinOp::Or => (a != 0 || b != 0) as i128,
+                BinOp::And => (a != 0 && b != 0) as i128,
+                BinOp::Eq => (a == b) as i128,
+                BinOp::Ne => (a != b) as i128,
+                BinOp::Lt => (a < b) as i128,
+                BinOp::Le => (a <= b) as i128,
+                BinOp::Gt => (a > b) as i128,
+                BinOp::Ge => (a >= b) as i128,
+                BinOp::BitOr => a | b,
+                BinOp::BitXor => a ^ b,
+                BinOp::BitAnd => a & b,
+                BinOp::Shl => u32::try_from(b)
+                    .ok()
+                    .and_then(|b| a.checked_shl(b))
+                    .ok_or_else(overflow)?,
+                BinOp::Shr => u32::try_from(b)
+                    .ok()
+                    .and_then(|b| a.checked_shr(b))
+                    .ok_or_else(overflow)?,
+                BinOp::Add => a.checked_add(b).ok_or_else(overflow)?,
+                BinOp::Sub => a.checked_sub(b).ok_or_else(overflow)?,
+                BinOp::Mul => a.checked_mul(b).ok_or_else(overflow)?,
+                BinOp::Div => a.checked_div(b).ok_or_else(zero)?,
+                BinOp::Mod => a.checked_rem(b).ok_or_else(zero)?,
+            }
+        }
+        _ => return Err(LangError::new(e.span.clone(), "expected a constant")),
+    };
+    if !(-(1 << 95)..1 << 96).contains(&v) {
+        return Err(overflow());
+    }
+    Ok(v)
+}
+
+/// Writes a metadata item as its EWAL line.
+fn meta(
+    out: &mut String,
+    key: &str,
+    value: &MetaValue,
+    span: &Range<usize>,
+) -> Result<(), LangError> {
+    let bad = |what: &str| {
+        Err(LangError::new(
+            span.clone(),
+            format!("`{}` takes {}", key, what),
+        ))
+    };
+    let text = match (key, value) {
+        ("name", MetaValue::Names(n)) | ("symbol", MetaValue::Names(n)) if n.len() == 1 => {
+            format!("\"{}\"", n[0])
+        }
+        ("radius", MetaValue::Number(n)) if *n <= 4 => n.to_string(),
+        ("radius", _) => return bad("a number from 0 to 4"),
+        ("mfmtype", MetaValue::Number(n)) if *n <= u16::MAX as u128 => n.to_string(),
+        ("mfmtype", _) => return bad("a type number"),
+        ("symmetries", MetaValue::Names(n)) => {
+            if let Some(s) = n.iter().find(|s| Symmetries::from_str(s).is_err()) {
+                return Err(LangError::new(
+                    span.clone(),
+                    format!("unknown symmetry: {}", s),
+                ));
+            }
+            n.join("|")
+        }
+        ("symmetries", _) => return bad("symmetries such as `R000L | FLIPX`"),
+        ("arithmetic", MetaValue::Names(n)) | ("fieldwrites", MetaValue::Names(n))
+            if n.len() == 1 && ArithMode::from_str(n[0]).is_ok() =>
+        {
+            n[0].to_owned()
+        }
+        ("arithmetic", _) | ("fieldwrites", _) => {
+            return bad("`saturating`, `wrapping` or `checked`")
+        }
+        ("divzero", MetaValue::Names(n))
+            if n.len() == 1 && DivZeroPolicy::from_str(n[0]).is_ok() =>
+        {
+            n[0].to_owned()
+        }
+        ("divzero", _) => return bad("`arithmetic`, `trap`, `ones` or `flag`"),
+        ("color", MetaValue::Str(c))
+        | ("bgcolor", MetaValue::Str(c))
+        | ("fgcolor", MetaValue::Str(c))
+            if Color::from_str(c).is_err() =>
+        {
+            return bad("a color such as \"#f80\"")
+        }
+        (_, MetaValue::Str(s)) => format!("\"{}\"", s),
+        _ => return bad("a string"),
+    };
+    writeln!(out, ".{} {}", key, text).unwrap();
+    Ok(())
+}
+
+/// Lowers a parsed program to EWAL source.
+pub fn lower<'a>(items: &'a [Item<'a>]) -> Result<String, LangError> {
+    let mut out = String::new();
+    let mut globals = Globals {
+        consts: HashMap::new(),
+        fields: HashMap::new(),
+        functions: HashMap::new(),
+    };
+    let duplicate = |span: &Range<usize>, name: &str| {
+        Err(LangError::new(
+            span.clone(),
+            format!("{} is already defined", name),
+        ))
+    };
+
+    // Declarations first, so that functions may be used before they are
+    // defined.
+    let mut used: Vec<(usize, usize, &str)> = Vec::new();
+    let mut next_field = 0;
+    for item in items {
+        match item {
+            Item::Meta(key, value, span) => meta(&mut out, key, value, span)?,
+            Item::Const(name, value, span) => {
+                if globals.consts.contains_key(name) {
+                    return duplicate(span, name);
+                }
+                let v = const_eval(&globals, value)?;
+                globals.consts.insert(name, v);
+            }
+            Item::Field { name, ty, at, span } => {
+                if globals.fields.contains_key(name) || ["type", "header", "data"].contains(name) {
+                    return duplicate(span, name);
+                }
+                let pos = match at {
+                    Some(at) => const_eval(&globals, at)?,
+                    None => next_field,
+                };
+                let end = pos + ty.bits as i128;
+                if pos < 0 || end > FieldSelector::DATA.length as i128 {
+                    return Err(LangError::new(
+                        span.clone(),
+                        format!(
+                            "bits {} to {} are outside the {} data bits",
+                            pos,
+                            end - 1,
+                            FieldSelector::DATA.length
+                        ),
+                    ));
+                }
+                let (pos, end) = (pos as usize, end as usize);
+                if let Some((_, _, other)) = used.iter().find(|(p, e, _)| pos < *e && *p < end) {
+                    return Err(LangError::new(
+                        span.clone(),
+                        format!("{} overlaps {}", name, other),
+                    ));
+                }
+                used.push((pos, end, name));
+                next_field = next_field.max(end as i128);
+                let ewal = if is_ewal_ident(name) && !Instruction::MNEMONICS.contains(name) {
+                    name.to_string()
+                } else {
+                    format!("_field_{}", letters(globals.fields.len()))
+                };
+                if ty.bond {
+                    writeln!(out, ".bond {},{}", ewal, pos).unwrap();
+                } else {
+                    writeln!(out, ".field {},{},{}", ewal, pos, ty.bits).unwrap();
+                }
+                globals.fields.insert(name, Field { ewal, ty: *ty });
+            }
+            Item::Fn {
+                name,
+                params,
+                body,
+                span,
+            } => {
+                if globals.functions.contains_key(name) {
+                    return duplicate(span, name);
+                }
+                if BUILTINS.iter().any(|(b, _, _, _)| b == name) {
+                    return Err(LangError::new(
+                        span.clone(),
+                        format!("{} is a builtin", name),
+                    ));
+                }
+                let label = if is_ewal_ident(name) {
+                    format!("fn_{}", name)
+                } else {
+                    format!("_fn_{}", letters(globals.functions.len()))
+                };
+                globals.functions.insert(
+                    name,
+                    Function {
+                        label,
+                        params: params.len(),
+                        span: span.clone(),
+                        body,
+                    },
+                );
+            }
+            Item::Stmt(_) => {}
+        }
+    }
+
+    // The event, then each function, lowered with registers numbered from 0.
+    let mut next_label = 0;
+    let mut main = Gen::new(&globals, false, &mut next_label);
+    for item in items {
+        if let Item::Stmt(s) = item {
+            main.stmt(s)?;
+        }
+    }
+    let (main_lines, main_frame, main_calls) = (main.lines, main.frame, main.calls);
+    let mut bodies: HashMap<&str, (Vec<Line>, usize, Vec<&str>)> = HashMap::new();
+    for item in items {
+        if let Item::Fn { name, params, .. } = item {
+            let f = &globals.functions[name];
+            let mut g = Gen::new(&globals, true, &mut next_label);
+            for p in params {
+                g.bind(p);
+            }
+            for r in (0..params.len()).rev() {
+                g.lines.push(Line::SetRegister(r));
+            }
+            g.block(f.body)?;
+            if !matches!(f.body.last().map(|s| &s.kind), Some(StmtKind::Return(_))) {
+                g.push(0);
+                g.op("ret");
+            }
+            bodies.insert(name, (g.lines, g.frame, g.calls));
+        }
+    }
+
+    // Each function's registers come after those of all its callers.
+    let mut base: HashMap<&str, usize> = HashMap::new();
+    fn visit<'a>(
+        name: &'a str,
+        from: usize,
+        path: &mut Vec<&'a str>,
+        bodies: &HashMap<&'a str, (Vec<Line>, usize, Vec<&'a str>)>,
+        globals: &Globals,
+        base: &mut HashMap<&'a str, usize>,
+    ) -> Result<(), LangError> {
+        if path.contains(&name) {
+            return Err(LangError::new(
+                globals.functions[name].span.clone(),
+                format!("{} calls itself, and recursion isn't supported", name),
+            ));
+        }
+        let (_, frame, calls) = &bodies[name];
+        if from + frame > Instruction::REGISTERS as usize {
+            return Err(LangError::new(
+                globals.functions[name].span.clone(),
+                format!(
+                    "{} needs more than the {} registers variables are kept in",
+                    name,
+                    Instruction::REGISTERS
+                ),
+            ));
+        }
+        let b = base.entry(name).or_insert(0);
+        *b = (*b).max(from);
+        path.push(name);
+        for callee in calls {
+            visit(callee, from + frame, path, bodies, globals, base)?;
+        }
+        path.pop();
+        Ok(())
+    }
+    if main_frame > Instruction::REGISTERS as usize {
+        return Err(LangError::new(
+            0..0,
+            format!(
+                "the event needs more than the {} registers variables are kept in",
+                Instruction::REGISTERS
+            ),
+        ));
+    }
+    for callee in &main_calls {
+        visit(
+            callee,
+            main_frame,
+            &mut Vec::new(),
+            &bodies,
+            &globals,
+            &mut base,
+        )?;
+    }
+
+    let mut render = |lines: &[Line], base: usize| {
+        for l in lines {
+            match l {
+                Line::Text(t) => writeln!(out, "{}", t),
+                Line::GetRegister(r) => writeln!(out, "  getregister R{}", base + r),
+                Line::SetRegister(r) => writeln!(out, "  setregister R{}", base + r),
+            }
+            .unwrap();
+        }
+    };
+    render(&main_lines, 0);
+    render(&[Line::Text("  exit".to_owned())], 0);
+    // Functions the event never calls are left out.
+    for item in items {
+        if let Item::Fn { name, .. } = item {
+            if let Some(b) = base.get(name) {
+                render(
+                    &[Line::Text(format!("{}:", globals.functions[name].label))],
+                    0,
+                );
+                render(&bodies[name].0, *b);
+            }
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,62 @@
+//! A small structured language for writing elements, lowered to EWAL.
+//!
+//! ```text
+//! name "Sand";
+//! symmetries R000L | FLIPX;
+//! field age: u8;
+//!
+//! if site[3].type == type("Empty") {
+//!     move(0, 3);
+//! } else if site[4].type == type("Empty") && site[8].type == type("Empty") {
+//!     move(0, 8);
+//! } else {
+//!     self.age += 1;
+//! }
+//! ```
+//!
+//! A source is a list of metadata, field, constant and function
+//! declarations and the statements of the event, which run in order. The
+//! lowering keeps variables in registers, so an event and the functions it
+//! calls have at most 15 between them, and functions may not call
+//! themselves.
+
+mod lexer;
+mod lower;
+mod parser;
+
+use crate::diag::Diagnostic;
+use std::ops::Range;
+
+pub use lower::BUILTINS;
+
+/// An error in a source, pointing at where it was found.
+#[derive(thiserror::Error, Debug)]
+#[error("{message}")]
+pub struct LangError {
+    pub span: Range<usize>,
+    pub message: String,
+    pub help: Option<String>,
+}
+
+impl LangError {
+    fn new<S: Into<String>>(span: Range<usize>, message: S) -> Self {
+        Self {
+            span,
+            message: message.into(),
+            help: None,
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let mut d = Diagnostic::new(self.message.clone(), Some(self.span.clone()));
+        d.help = self.help.clone();
+        d
+    }
+}
+
+/// Translates the source `src` to EWAL source.
+pub fn translate(src: &str) -> Result<String, LangError> {
+    let tokens = lexer::tokenize(src)?;
+    let items = parser::parse(&tokens)?;
+    lower::lower(&items)
+}
@@ -0,0 +1,622 @@
+//! The syntax tree and a recursive descent parser building it.
+
+use super::lexer::{Spanned, Token};
+use super::LangError;
+use crate::base::SiteNumber;
+use std::ops::Range;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug)]
+pub enum ExprKind<'a> {
+    Number(u128),
+    Str(&'a str),
+    Var(&'a str),
+    /// `site[n]`, the atom at a site of the event window.
+    Site(Box<Expr<'a>>),
+    /// `self`, the atom running the event.
+    SelfAtom,
+    Field(Box<Expr<'a>>, &'a str),
+    Call(&'a str, Vec<Expr<'a>>),
+    Unary(UnOp, Box<Expr<'a>>),
+    Binary(BinOp, Box<Expr<'a>>, Box<Expr<'a>>),
+}
+
+#[derive(Debug)]
+pub struct Expr<'a> {
+    pub kind: ExprKind<'a>,
+    pub span: Range<usize>,
+}
+
+pub type Block<'a> = Vec<Stmt<'a>>;
+
+#[derive(Debug)]
+pub enum StmtKind<'a> {
+    Let(&'a str, Expr<'a>),
+    /// An assignment, or a compound assignment such as `+=` with its
+    /// operator.
+    Assign(Expr<'a>, Option<BinOp>, Expr<'a>),
+    If(Vec<(Expr<'a>, Block<'a>)>, Option<Block<'a>>),
+    While(Expr<'a>, Block<'a>),
+    /// `for NAME in FROM..TO`, with whether the range includes `TO`.
+    For(&'a str, Expr<'a>, Expr<'a>, bool, Block<'a>),
+    Break,
+    Continue,
+    Return(Option<Expr<'a>>),
+    Exit,
+    Expr(Expr<'a>),
+}
+
+#[derive(Debug)]
+pub struct Stmt<'a> {
+    pub kind: StmtKind<'a>,
+    pub span: Range<usize>,
+}
+
+/// The type of a field: its width, whether it is read as signed and whether
+/// it holds a bond, as `.bond` declares.
+#[derive(Clone, Copy, Debug)]
+pub struct FieldType {
+    pub signed: bool,
+    pub bits: u8,
+    pub bond: bool,
+}
+
+impl FieldType {
+    /// The smallest and largest values a field of this type holds.
+    pub fn range(&self) -> (i128, i128) {
+        if self.signed {
+            (-(1 << (self.bits - 1)), (1 << (self.bits - 1)) - 1)
+        } else {
+            (0, (1 << self.bits) - 1)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MetaValue<'a> {
+    Str(&'a str),
+    Number(u128),
+    /// Names separated by `|`, as symmetries are.
+    Names(Vec<&'a str>),
+}
+
+#[derive(Debug)]
+pub enum Item<'a> {
+    Meta(&'a str, MetaValue<'a>, Range<usize>),
+    Field {
+        name: &'a str,
+        ty: FieldType,
+        at: Option<Expr<'a>>,
+        span: Range<usize>,
+    },
+    Const(&'a str, Expr<'a>, Range<usize>),
+    Fn {
+        name: &'a str,
+        params: Vec<&'a str>,
+        body: Block<'a>,
+        span: Range<usize>,
+    },
+    Stmt(Stmt<'a>),
+}
+
+/// Metadata which may be given at the top level, as in `name "Sand";`.
+pub const METADATA: &[&str] = &[
+    "name",
+    "symbol",
+    "color",
+    "desc",
+    "author",
+    "license",
+    "radius",
+    "bgcolor",
+    "fgcolor",
+    "symmetries",
+    "arithmetic",
+    "fieldwrites",
+    "divzero",
+    "mfmtype",
+];
+
+const KEYWORDS: &[&str] = &[
+    "let", "if", "else", "while", "for", "in", "break", "continue", "return", "exit", "fn",
+    "field", "const", "site", "self", "true", "false",
+];
+
+struct Parser<'s, 'a> {
+    tokens: &'s [Spanned<'a>],
+    pos: usize,
+}
+
+impl<'s, 'a> Parser<'s, 'a> {
+    fn peek(&self) -> &Token<'a> {
+        &self.tokens[self.pos].token
+    }
+
+    fn peek_at(&self, n: usize) -> &Token<'a> {
+        let i = (self.pos + n).min(self.tokens.len() - 1);
+        &self.tokens[i].token
+    }
+
+    fn span(&self) -> Range<usize> {
+        self.tokens[self.pos].span.clone()
+    }
+
+    /// The span from `start` to the end of the last token taken.
+    fn since(&self, start: usize) -> Range<usize> {
+        let end = self.tokens[self.pos.saturating_sub(1)].span.end;
+        start..end.max(start)
+    }
+
+    fn next(&mut self) -> Spanned<'a> {
+        let t = self.tokens[self.pos].clone();
+        if self.pos + 1 < self.tokens.len() {
+            self.pos += 1;
+        }
+        t
+    }
+
+    fn error<T>(&self, expected: &str) -> Result<T, LangError> {
+        let found = match self.peek() {
+            Token::Ident(s) => format!("`{}`", s),
+            Token::Number(n) => n.to_string(),
+            Token::Str(s) => format!("\"{}\"", s),
+            Token::Punct(p) => format!("`{}`", p),
+            Token::Eof => "the end of the file".to_owned(),
+        };
+        Err(LangError::new(
+            self.span(),
+            format!("expected {}, found {}", expected, found),
+        ))
+    }
+
+    fn is_punct(&self, p: &str) -> bool {
+        matches!(self.peek(), Token::Punct(q) if *q == p)
+    }
+
+    fn is_word(&self, w: &str) -> bool {
+        matches!(self.peek(), Token::Ident(s) if *s == w)
+    }
+
+    fn eat(&mut self, p: &str) -> bool {
+        let found = self.is_punct(p);
+        if found {
+            self.next();
+        }
+        found
+    }
+
+    fn expect(&mut self, p: &str) -> Result<(), LangError> {
+        if self.eat(p) {
+            Ok(())
+        } else {
+            self.error(&format!("`{}`", p))
+        }
+    }
+
+    fn keyword(&mut self, w: &str) -> Result<(), LangError> {
+        if self.is_word(w) {
+            self.next();
+            Ok(())
+        } else {
+            self.error(&format!("`{}`", w))
+        }
+    }
+
+    /// A name which isn't a keyword.
+    fn name(&mut self) -> Result<&'a str, LangError> {
+        match *self.peek() {
+            Token::Ident(s) if !KEYWORDS.contains(&s) => {
+                self.next();
+                Ok(s)
+            }
+            _ => self.error("a name"),
+        }
+    }
+
+    fn item(&mut self) -> Result<Item<'a>, LangError> {
+        let start = self.span().start;
+        match *self.peek() {
+            Token::Ident(m)
+                if METADATA.contains(&m)
+                    && matches!(
+                        self.peek_at(1),
+                        Token::Str(_) | Token::Number(_) | Token::Ident(_)
+                    ) =>
+            {
+                self.next();
+                let value = match self.next().token {
+                    Token::Str(s) => MetaValue::Str(s),
+                    Token::Number(n) => MetaValue::Number(n),
+                    Token::Ident(s) => {
+                        let mut names = vec![s];
+                        while self.eat("|") {
+                            match *self.peek() {
+                                Token::Ident(s) => {
+                                    self.next();
+                                    names.push(s);
+                                }
+                                _ => return self.error("a name"),
+                            }
+                        }
+                        MetaValue::Names(names)
+                    }
+                    _ => unreachable!(),
+                };
+                self.expect(";")?;
+                Ok(Item::Meta(m, value, self.since(start)))
+            }
+            Token::Ident("field") => {
+                self.next();
+                let name = self.name()?;
+                self.expect(":")?;
+                let ty = self.field_type()?;
+                let at = if self.is_word("at") {
+                    self.next();
+                    Some(self.expr()?)
+                } else {
+                    None
+                };
+                self.expect(";")?;
+                Ok(Item::Field {
+                    name,
+                    ty,
+                    at,
+                    span: self.since(start),
+                })
+            }
+            Token::Ident("const") => {
+                self.next();
+                let name = self.name()?;
+                self.expect("=")?;
+                let value = self.expr()?;
+                self.expect(";")?;
+                Ok(Item::Const(name, value, self.since(start)))
+            }
+            Token::Ident("fn") => {
+                self.next();
+                let name = self.name()?;
+                let name_end = self.since(start).end;
+                self.expect("(")?;
+                let mut params = Vec::new();
+                while !self.eat(")") {
+                    params.push(self.name()?);
+                    if !self.is_punct(")") {
+                        self.expect(",")?;
+                    }
+                }
+                let body = self.block()?;
+                Ok(Item::Fn {
+                    name,
+                    params,
+                    body,
+                    span: start..name_end,
+                })
+            }
+            _ => Ok(Item::Stmt(self.stmt()?)),
+        }
+    }
+
+    /// A field type: `bool`, `bond`, or `u` or `i` and a width such as `u8`.
+    fn field_type(&mut self) -> Result<FieldType, LangError> {
+        let t = match *self.peek() {
+            Token::Ident("bool") => Some(FieldType {
+                signed: false,
+                bits: 1,
+                bond: false,
+            }),
+            Token::Ident("bond") => Some(FieldType {
+                signed: false,
+                bits: SiteNumber::BITS,
+                bond: true,
+            }),
+            Token::Ident(t) => {
+                let signed = t.starts_with('i');
+                t.strip_prefix(|c| c == 'u' || c == 'i')
+                    .filter(|n| !n.starts_with('0'))
+                    .and_then(|n| n.parse().ok())
+                    .filter(|n| (1..=71).contains(n))
+                    .map(|bits| FieldType {
+                        signed,
+                        bits,
+                        bond: false,
+                    })
+            }
+            _ => None,
+        };
+        match t {
+            Some(t) => {
+                self.next();
+                Ok(t)
+            }
+            None => self.error("a field type such as `u8`, `i4`, `bool` or `bond`"),
+        }
+    }
+
+    fn block(&mut self) -> Result<Block<'a>, LangError> {
+        self.expect("{")?;
+        let mut stmts = Vec::new();
+        while !self.eat("}") {
+            if *self.peek() == Token::Eof {
+                return self.error("`}`");
+            }
+            stmts.push(self.stmt()?);
+        }
+        Ok(stmts)
+    }
+
+    fn stmt(&mut self) -> Result<Stmt<'a>, LangError> {
+        let start = self.span().start;
+        let kind = match *self.peek() {
+            Token::Ident("let") => {
+                self.next();
+                let name = self.name()?;
+                self.expect("=")?;
+                let value = self.expr()?;
+                self.expect(";")?;
+                StmtKind::Let(name, value)
+            }
+            Token::Ident("if") => {
+                let mut arms = Vec::new();
+                let mut otherwise = None;
+                self.next();
+                loop {
+                    let cond = self.expr()?;
+                    arms.push((cond, self.block()?));
+                    if !self.is_word("else") {
+                        break;
+                    }
+                    self.next();
+                    if self.is_word("if") {
+                        self.next();
+                    } else {
+                        otherwise = Some(self.block()?);
+                        break;
+                    }
+                }
+                StmtKind::If(arms, otherwise)
+            }
+            Token::Ident("while") => {
+                self.next();
+                let cond = self.expr()?;
+                StmtKind::While(cond, self.block()?)
+            }
+            Token::Ident("for") => {
+                self.next();
+                let name = self.name()?;
+                self.keyword("in")?;
+                let from = self.expr()?;
+                let inclusive = if self.eat("..=") {
+                    true
+                } else {
+                    self.expect("..")?;
+                    false
+                };
+                let to = self.expr()?;
+                StmtKind::For(name, from, to, inclusive, self.block()?)
+            }
+            Token::Ident(w @ "break") | Token::Ident(w @ "continue") | Token::Ident(w @ "exit") => {
+                self.next();
+                self.expect(";")?;
+                match w {
+                    "break" => StmtKind::Break,
+                    "continue" => StmtKind::Continue,
+                    _ => StmtKind::Exit,
+                }
+            }
+            Token::Ident("return") => {
+                self.next();
+                let value = if self.is_punct(";") {
+                    None
+                } else {
+                    Some(self.expr()?)
+                };
+                self.expect(";")?;
+                StmtKind::Return(value)
+            }
+            _ => {
+                let e = self.expr()?;
+                let op = match *self.peek() {
+                    Token::Punct("=") => Some(None),
+                    Token::Punct("+=") => Some(Some(BinOp::Add)),
+                    Token::Punct("-=") => Some(Some(BinOp::Sub)),
+                    Token::Punct("*=") => Some(Some(BinOp::Mul)),
+                    Token::Punct("/=") => Some(Some(BinOp::Div)),
+                    Token::Punct("%=") => Some(Some(BinOp::Mod)),
+                    _ => None,
+                };
+                let kind = match op {
+                    Some(op) => {
+                        self.next();
+                        StmtKind::Assign(e, op, self.expr()?)
+                    }
+                    None => StmtKind::Expr(e),
+                };
+                self.expect(";")?;
+                kind
+            }
+        };
+        Ok(Stmt {
+            kind,
+            span: self.since(start),
+        })
+    }
+
+    fn expr(&mut self) -> Result<Expr<'a>, LangError> {
+        self.binary(0)
+    }
+
+    /// Parses operators binding at least as tightly as `level`, from `||` at
+    /// level 0 up.
+    fn binary(&mut self, level: usize) -> Result<Expr<'a>, LangError> {
+        const LEVELS: &[&[(&str, BinOp)]] = &[
+            &[("||", BinOp::Or)],
+            &[("&&", BinOp::And)],
+            &[
+                ("==", BinOp::Eq),
+                ("!=", BinOp::Ne),
+                ("<", BinOp::Lt),
+                ("<=", BinOp::Le),
+                (">", BinOp::Gt),
+                (">=", BinOp::Ge),
+            ],
+            &[("|", BinOp::BitOr)],
+            &[("^", BinOp::BitXor)],
+            &[("&", BinOp::BitAnd)],
+            &[("<<", BinOp::Shl), (">>", BinOp::Shr)],
+            &[("+", BinOp::Add), ("-", BinOp::Sub)],
+            &[("*", BinOp::Mul), ("/", BinOp::Div), ("%", BinOp::Mod)],
+        ];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let start = self.span().start;
+        let mut lhs = self.binary(level + 1)?;
+        loop {
+            let op = match self.peek() {
+                Token::Punct(p) => LEVELS[level]
+                    .iter()
+                    .find(|(s, _)| s == p)
+                    .map(|(_, op)| *op),
+                _ => None,
+            };
+            let op = match op {
+                Some(op) => op,
+                None => return Ok(lhs),
+            };
+            self.next();
+            let rhs = self.binary(level + 1)?;
+            lhs = Expr {
+                kind: ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)),
+                span: self.since(start),
+            };
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr<'a>, LangError> {
+        let start = self.span().start;
+        let op = if self.eat("-") {
+            UnOp::Neg
+        } else if self.eat("!") {
+            UnOp::Not
+        } else {
+            return self.postfix();
+        };
+        let e = self.unary()?;
+        Ok(Expr {
+            kind: ExprKind::Unary(op, Box::new(e)),
+            span: self.since(start),
+        })
+    }
+
+    fn postfix(&mut self) -> Result<Expr<'a>, LangError> {
+        let start = self.span().start;
+        let mut e = self.primary()?;
+        while self.eat(".") {
+            let field = match *self.peek() {
+                Token::Ident(f) => f,
+                _ => return self.error("a field name"),
+            };
+            self.next();
+            e = Expr {
+                kind: ExprKind::Field(Box::new(e), field),
+                span: self.since(start),
+            };
+        }
+        Ok(e)
+    }
+
+    fn primary(&mut self) -> Result<Expr<'a>, LangError> {
+        let start = self.span().start;
+        let kind = match *self.peek() {
+            Token::Number(n) => {
+                self.next();
+                ExprKind::Number(n)
+            }
+            Token::Str(s) => {
+                self.next();
+                ExprKind::Str(s)
+            }
+            Token::Ident("true") => {
+                self.next();
+                ExprKind::Number(1)
+            }
+            Token::Ident("false") => {
+                self.next();
+                ExprKind::Number(0)
+            }
+            Token::Ident("self") => {
+                self.next();
+                ExprKind::SelfAtom
+            }
+            Token::Ident("site") => {
+                self.next();
+                self.expect("[")?;
+                let n = self.expr()?;
+                self.expect("]")?;
+                ExprKind::Site(Box::new(n))
+            }
+            Token::Punct("(") => {
+                self.next();
+                let e = self.expr()?;
+                self.expect(")")?;
+                return Ok(e);
+            }
+            Token::Ident(_) => {
+                let name = self.name()?;
+                if self.eat("(") {
+                    let mut args = Vec::new();
+                    while !self.eat(")") {
+                        args.push(self.expr()?);
+                        if !self.is_punct(")") {
+                            self.expect(",")?;
+                        }
+                    }
+                    ExprKind::Call(name, args)
+                } else {
+                    ExprKind::Var(name)
+                }
+            }
+            _ => return self.error("an expression"),
+        };
+        Ok(Expr {
+            kind,
+            span: self.since(start),
+        })
+    }
+}
+
+pub fn parse<'a>(tokens: &[Spanned<'a>]) -> Result<Vec<Item<'a>>, LangError> {
+    let mut p = Parser { tokens, pos: 0 };
+    let mut items = Vec::new();
+    while *p.peek() != Token::Eof {
+        items.push(p.item()?);
+    }
+    Ok(items)
+}
@@ -0,0 +1,30 @@
+//! A virtual machine for the movable feast machine (MFM).
+//!
+//! The `ewac`, `ewar` and `ewad` binaries compile, run and disassemble
+//! elements. Programs embedding the engine use `Engine` instead.
+
+pub mod ast;
+pub mod base;
+pub mod code;
+pub mod diag;
+pub mod disasm;
+pub mod engine;
+pub mod experiment;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod format;
+pub mod fuzz;
+mod ir;
+pub mod logging;
+pub mod optimize;
+pub mod preprocess;
+pub mod render;
+pub mod runtime;
+mod stack;
+pub mod stdlib;
+pub mod testing;
+mod verify;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use engine::{Engine, EngineBuilder, EngineError};
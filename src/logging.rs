@@ -0,0 +1,133 @@
+//! Logging from the compiler, events and schedulers.
+//!
+//! The engine logs through the `log` crate under the targets
+//! `substrate::compile`, `substrate::load`, `substrate::event`,
+//! `substrate::schedule` and `substrate::invariant`, and programs embedding it
+//! may install any logger.
+//! `ewac` and `ewar` install `Logger`, which writes to standard error at the
+//! level given by `--log-level`, as text or, with `--log-json`, as one JSON
+//! object per line.
+//!
+//! Records logged while a grid runs an event belong to the event's span: the
+//! site it runs at and the type of its atom, kept per thread so events run on
+//! other threads don't mix. `Logger` writes them as fields of the record:
+//!
+//! ```text
+//! WARN event{x=4 y=7 type=2}: substrate::event: Assertion failed in DReg at instruction 12
+//! {"level":"WARN","target":"substrate::event","thread":"main","x":4,"y":7,"type":2,"message":"Assertion failed in DReg at instruction 12"}
+//! ```
+
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use std::cell::Cell;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::thread;
+
+/// The event a thread is running.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EventSpan {
+    pub x: usize,
+    pub y: usize,
+    /// The type of the atom at the center of the event's window.
+    pub type_num: u16,
+}
+
+thread_local! {
+    static EVENT: Cell<Option<EventSpan>> = const { Cell::new(None) };
+}
+
+/// The event this thread is running, if any.
+pub fn current_event() -> Option<EventSpan> {
+    EVENT.with(Cell::get)
+}
+
+/// Leaves the span entered with `enter` when dropped.
+#[derive(Debug)]
+pub struct Entered {
+    outer: Option<EventSpan>,
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        EVENT.with(|e| e.set(self.outer));
+    }
+}
+
+/// Makes `span` this thread's event until the guard is dropped.
+pub fn enter(span: EventSpan) -> Entered {
+    Entered {
+        outer: EVENT.with(|e| e.replace(Some(span))),
+    }
+}
+
+/// `s` as a JSON string.
+fn json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Writes records to standard error, a line each.
+#[derive(Debug)]
+pub struct Logger {
+    json: bool,
+}
+
+impl Logger {
+    /// Installs a logger writing records up to `level`, as JSON if `json`.
+    /// Fails if a logger is already installed.
+    pub fn init(level: LevelFilter, json: bool) -> Result<(), SetLoggerError> {
+        log::set_boxed_logger(Box::new(Self { json }))?;
+        log::set_max_level(level);
+        Ok(())
+    }
+
+    fn format(&self, record: &Record) -> String {
+        let span = current_event();
+        let mut line = String::new();
+        if self.json {
+            write!(line, "{{\"level\":\"{}\",\"target\":", record.level()).unwrap();
+            json_string(&mut line, record.target());
+            line.push_str(",\"thread\":");
+            json_string(&mut line, thread::current().name().unwrap_or(""));
+            if let Some(s) = span {
+                write!(line, ",\"x\":{},\"y\":{},\"type\":{}", s.x, s.y, s.type_num).unwrap();
+            }
+            line.push_str(",\"message\":");
+            json_string(&mut line, &record.args().to_string());
+            line.push('}');
+        } else {
+            write!(line, "{:>5} ", record.level()).unwrap();
+            if let Some(s) = span {
+                write!(line, "event{{x={} y={} type={}}}: ", s.x, s.y, s.type_num).unwrap();
+            }
+            write!(line, "{}: {}", record.target(), record.args()).unwrap();
+        }
+        line
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            // Each record is one write, so lines from threads don't interleave.
+            let line = self.format(record) + "\n";
+            let _ = io::stderr().write_all(line.as_bytes());
+        }
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
@@ -0,0 +1,520 @@
+//! Optimizations of element code, run by `ewac -O`.
+//!
+//! Passes rewrite an element's `Ir`, once it has been verified, and run until
+//! none of them changes anything. At level 1:
+//!
+//! - Constant folding evaluates instructions whose operands were all pushed
+//!   just before them, as in `push 3 push 4 add`, the way the runtime would,
+//!   in the element's `.arithmetic` mode. Operations which would fail, such
+//!   as an overflow under `checked` or a division by zero, are left to fail
+//!   when they run.
+//! - Peephole cleanups drop `nop`s, constants and registers which are popped
+//!   straight away and assertions of nonzero constants, and turn `jumpzero`
+//!   and `jumpnonzero` on a constant into a `jump` or nothing.
+//! - Jump threading sends jumps and calls to a `jump` on to its target,
+//!   replaces a jump to `exit` or `ret` with the instruction itself and drops
+//!   jumps to the next instruction.
+//!
+//! Level 2 also runs:
+//!
+//! - Dead code elimination, which drops instructions no path from the start
+//!   of an event reaches.
+//! - Dead store elimination, which turns a `setregister` into a `pop` when no
+//!   `getregister` can read the value before the event ends or it is
+//!   overwritten.
+//!
+//! Arithmetic and `cmp` set the flags, which the conditional jumps read, so
+//! they are only folded away where no path reaches such a jump before the
+//! flags are set again. No sequence of instructions is folded across the
+//! target of a jump or the return from a `call`. Code using
+//! `jumprelativeoffset` jumps to computed positions, which moving any
+//! instruction would break, so it is left as written. Optimized code runs fewer
+//! instructions, so instruction counts, budgets and instruction numbers
+//! differ from those of the unoptimized code.
+
+use crate::ast::Instruction;
+use crate::base::arith::{ArithMode, Const};
+use crate::ir::{retarget, target, Edits, Ir};
+
+/// The highest level `optimize` takes.
+pub const MAX_LEVEL: u8 = 2;
+
+/// The most times the passes run over a program, should they keep finding
+/// something to change.
+const MAX_ROUNDS: usize = 16;
+
+/// The liveness bit of the flags, above those of the registers.
+const FLAGS: u16 = 1 << Instruction::REGISTERS;
+
+/// The registers and flags each instruction may leave to be read later,
+/// as bits of `1 << n` for register `n` and `FLAGS`.
+fn liveness(ir: &Ir) -> Vec<u16> {
+    let returns = ir.returns();
+    let successors: Vec<Vec<usize>> = (0..ir.code.len())
+        .map(|i| ir.successors(i, &returns))
+        .collect();
+    // Nothing is live once the event ends.
+    let mut live_in = vec![0u16; ir.code.len() + 1];
+    let mut live_out = vec![0u16; ir.code.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in (0..ir.code.len()).rev() {
+            let out = successors[i].iter().fold(0, |m, s| m | live_in[*s]);
+            let (read, written) = match ir.code[i] {
+                Instruction::GetRegister(n) if n < Instruction::REGISTERS => (1 << n, 0),
+                Instruction::SetRegister(n) if n < Instruction::REGISTERS => (0, 1 << n),
+                Instruction::JumpIf(..) => (FLAGS, 0),
+                i if i.sets_flags() => (0, FLAGS),
+                _ => (0, 0),
+            };
+            let live = read | (out & !written);
+            live_out[i] = out;
+            if live != live_in[i] {
+                live_in[i] = live;
+                changed = true;
+            }
+        }
+    }
+    live_out
+}
+
+/// Replaces `edits[i..i + n]` with `with`, unless an earlier edit already
+/// covers them.
+fn replace<'input>(edits: &mut Edits<'input>, i: usize, n: usize, with: Vec<Instruction<'input>>) {
+    if edits[i..i + n].iter().any(Option::is_some) {
+        return;
+    }
+    edits[i] = Some(with);
+    for e in edits[i + 1..i + n].iter_mut() {
+        *e = Some(Vec::new());
+    }
+}
+
+fn truth(b: bool) -> Const {
+    Const::from(if b { 1 } else { 0 })
+}
+
+/// What a binary instruction `op` makes of constants `a` and `b`, as the
+/// runtime would. Instructions setting the flags are only folded if
+/// `flags_dead`.
+fn binary<'input>(
+    op: &Instruction<'input>,
+    a: Const,
+    b: Const,
+    mode: ArithMode,
+    flags_dead: bool,
+) -> Option<Vec<Instruction<'input>>> {
+    let r = match op {
+        Instruction::Add if flags_dead => mode.add(a, b)?,
+        Instruction::Sub if flags_dead => mode.sub(a, b)?,
+        Instruction::Mul if flags_dead => mode.mul(a, b)?,
+        Instruction::Div if flags_dead && !a.divides_by_zero(b, false) => mode.div(a, b)?,
+        Instruction::Mod if flags_dead && !a.divides_by_zero(b, true) => mode.rem(a, b)?,
+        Instruction::Compare if flags_dead => return Some(Vec::new()),
+        Instruction::Or => a | b,
+        Instruction::And => a & b,
+        Instruction::Xor => a ^ b,
+        Instruction::Less => truth(a.cmp_value(b).is_lt()),
+        Instruction::LessEqual => truth(a.cmp_value(b).is_le()),
+        Instruction::Equal => truth(a.cmp_value(b).is_eq()),
+        Instruction::Min => a.min(b),
+        Instruction::Max => a.max(b),
+        Instruction::MulHigh => a.mul_high(b),
+        Instruction::RotateLeft | Instruction::RotateRight => {
+            let n = b.as_i128().rem_euclid(a.bits() as i128) as u32;
+            if matches!(op, Instruction::RotateLeft) {
+                a.rotate_left(n)
+            } else {
+                a.rotate_right(n)
+            }
+        }
+        Instruction::Swap => return Some(vec![Instruction::Push(b), Instruction::Push(a)]),
+        _ => return None,
+    };
+    Some(vec![Instruction::Push(r)])
+}
+
+/// What a unary instruction `op` makes of constant `a`, as `binary` does.
+fn unary<'input>(
+    op: &Instruction<'input>,
+    a: Const,
+    mode: ArithMode,
+    flags_dead: bool,
+) -> Option<Vec<Instruction<'input>>> {
+    let r = match op {
+        Instruction::Neg if flags_dead => mode.neg(a)?,
+        Instruction::Abs => mode.abs(a)?,
+        Instruction::PopCount => a.count_ones().into(),
+        Instruction::LeadingZeros => a.leading_zeros().into(),
+        Instruction::TrailingZeros => a.trailing_zeros().into(),
+        Instruction::BitCount => a.as_u128().count_ones().into(),
+        Instruction::ZeroExtend(n) => a.zero_extend(*n),
+        Instruction::SignExtend(n) => a.sign_extend(*n),
+        Instruction::Truncate(n) => a.with_bits(*n),
+        Instruction::Pop => return Some(Vec::new()),
+        Instruction::Assert if !a.is_zero() => return Some(Vec::new()),
+        Instruction::JumpZero(x) | Instruction::JumpNonZero(x) => {
+            let jumps = a.is_zero() == matches!(op, Instruction::JumpZero(_));
+            return Some(if jumps {
+                vec![Instruction::Jump(*x)]
+            } else {
+                Vec::new()
+            });
+        }
+        _ => return None,
+    };
+    Some(vec![Instruction::Push(r)])
+}
+
+/// Folds constants and cleans up short sequences of instructions.
+fn fold<'input>(c: &Ir<'input>, mode: ArithMode) -> Edits<'input> {
+    let live = liveness(c);
+    let entries = c.entries();
+    let flags_dead = |i: usize| live[i] & FLAGS == 0;
+    let mut edits: Edits = vec![None; c.code.len()];
+    for i in 0..c.code.len() {
+        // The instructions from `i` which run one after the other.
+        let end = (i + 1..c.code.len())
+            .find(|j| entries[*j])
+            .unwrap_or(c.code.len());
+        let window = &c.code[i..end];
+        let pushed = |k: usize| window.get(k).and_then(Instruction::pushed);
+        if let (Some(a), Some(b), Some(x), Some(Instruction::Clamp)) =
+            (pushed(0), pushed(1), pushed(2), window.get(3))
+        {
+            replace(&mut edits, i, 4, vec![Instruction::Push(a.clamp(b, x))]);
+            continue;
+        }
+        if let (Some(a), Some(b), Some(op)) = (pushed(0), pushed(1), window.get(2)) {
+            if let Some(r) = binary(op, a, b, mode, flags_dead(i + 2)) {
+                replace(&mut edits, i, 3, r);
+                continue;
+            }
+        }
+        if let (Some(a), Some(op)) = (pushed(0), window.get(1)) {
+            if let Some(r) = unary(op, a, mode, flags_dead(i + 1)) {
+                replace(&mut edits, i, 2, r);
+                continue;
+            }
+        }
+        match window {
+            [Instruction::Nop, ..] => replace(&mut edits, i, 1, Vec::new()),
+            [Instruction::GetRegister(n), Instruction::Pop, ..] if *n < Instruction::REGISTERS => {
+                replace(&mut edits, i, 2, Vec::new())
+            }
+            _ => {}
+        }
+    }
+    edits
+}
+
+/// Threads jumps through jumps and drops those which go nowhere.
+fn thread<'input>(c: &Ir<'input>, _: ArithMode) -> Edits<'input> {
+    let mut edits: Edits = vec![None; c.code.len()];
+    for (ip, i) in c.code.iter().enumerate() {
+        let mut t = match target(i) {
+            Some(t) => t,
+            None => continue,
+        };
+        // A loop of jumps is followed once around at most.
+        for _ in 0..c.code.len() {
+            match c.code.get(t) {
+                Some(Instruction::Jump(x)) if *x.runtime() as usize != t => {
+                    t = *x.runtime() as usize
+                }
+                _ => break,
+            }
+        }
+        let next = t == ip + 1;
+        edits[ip] = match i {
+            Instruction::Jump(_) if next => Some(Vec::new()),
+            Instruction::Jump(_) => match c.code.get(t) {
+                Some(x @ (Instruction::Exit | Instruction::Ret)) => Some(vec![*x]),
+                _ => None,
+            },
+            Instruction::JumpIf(..) if next => Some(Vec::new()),
+            Instruction::JumpZero(_) | Instruction::JumpNonZero(_) if next => {
+                Some(vec![Instruction::Pop])
+            }
+            _ => None,
+        }
+        .or_else(|| Some(vec![retarget(*i, t)]).filter(|_| target(i) != Some(t)));
+    }
+    edits
+}
+
+/// Drops the instructions no event reaches.
+fn unreachable<'input>(c: &Ir<'input>, _: ArithMode) -> Edits<'input> {
+    let returns = c.returns();
+    let mut reached = vec![false; c.code.len() + 1];
+    let mut todo = vec![0];
+    while let Some(i) = todo.pop() {
+        if reached[i] {
+            continue;
+        }
+        reached[i] = true;
+        if i < c.code.len() {
+            todo.extend(c.successors(i, &returns));
+        }
+    }
+    (0..c.code.len())
+        .map(|i| Some(Vec::new()).filter(|_| !reached[i]))
+        .collect()
+}
+
+/// Pops the values stored to registers which are never read.
+fn dead_stores<'input>(c: &Ir<'input>, _: ArithMode) -> Edits<'input> {
+    let live = liveness(c);
+    c.code
+        .iter()
+        .enumerate()
+        .map(|(ip, i)| match i {
+            Instruction::SetRegister(n)
+                if *n < Instruction::REGISTERS && live[ip] & 1 << n == 0 =>
+            {
+                Some(vec![Instruction::Pop])
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Optimizes `ir` at `level`: 0 for none, 1 or 2. `mode` is the element's
+/// arithmetic mode.
+pub fn optimize<'input>(ir: &Ir<'input>, mode: ArithMode, level: u8) -> Ir<'input> {
+    type Pass = for<'a> fn(&Ir<'a>, ArithMode) -> Edits<'a>;
+    let passes: &[Pass] = match level {
+        _ if level == 0 || ir.has_computed_jumps() => return ir.clone(),
+        1 => &[fold, thread],
+        _ => &[fold, thread, unreachable, dead_stores],
+    };
+    let mut ir = ir.clone();
+    for _ in 0..MAX_ROUNDS {
+        let mut changed = false;
+        for pass in passes.iter() {
+            let edits = pass(&ir, mode);
+            if edits.iter().any(Option::is_some) {
+                ir = ir.edit(edits);
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    ir
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Arg, Condition};
+    use crate::code::Compiler;
+    use crate::engine::Engine;
+    use crate::runtime::grid::Boundary;
+    use crate::stdlib;
+    use Instruction::*;
+
+    type Pass = for<'a> fn(&Ir<'a>, ArithMode) -> Edits<'a>;
+
+    fn push(n: u32) -> Instruction<'static> {
+        Push(n.into())
+    }
+
+    fn to(t: u16) -> Arg<crate::ast::Target<'static>, u16> {
+        Arg::Runtime(t)
+    }
+
+    /// The code as `Ir::dump` lists it, without instruction numbers.
+    fn listing(ir: &Ir) -> Vec<String> {
+        ir.dump()
+            .lines()
+            .map(|l| l.split("/*").next().unwrap().trim().to_owned())
+            .collect()
+    }
+
+    /// The code after one run of `pass`.
+    fn run(pass: Pass, code: &[Instruction<'static>]) -> Vec<String> {
+        let ir = Ir::from_code(code);
+        listing(&ir.edit(pass(&ir, ArithMode::default())))
+    }
+
+    #[test]
+    fn fold_constants() {
+        assert_eq!(
+            run(fold, &[push(3), push(4), Add, push(5), Mul, Exit]),
+            ["push 7", "push 5", "mul", "exit"]
+        );
+        assert_eq!(
+            run(fold, &[push(6), push(3), Swap, Pop, Nop, Exit]),
+            ["push 3", "push 6", "pop", "exit"]
+        );
+        assert_eq!(
+            run(fold, &[push(0), JumpZero(to(3)), push(1), Exit]),
+            ["jump 2", "push 1", "exit"]
+        );
+        assert_eq!(
+            run(fold, &[GetRegister(2), Pop, push(1), Assert, Exit]),
+            ["exit"]
+        );
+    }
+
+    #[test]
+    fn fold_keeps_live_flags() {
+        // The jump reads the flags the add sets.
+        let code = [
+            push(3),
+            push(4),
+            Add,
+            JumpIf(Condition::Equal, to(5)),
+            Exit,
+            Exit,
+        ];
+        assert_eq!(
+            run(fold, &code),
+            ["push 3", "push 4", "add", "jeq 5", "exit", "exit"]
+        );
+        // Flags set again before the jump leave these dead.
+        let code = [
+            push(3),
+            push(4),
+            Add,
+            push(1),
+            push(2),
+            Compare,
+            JumpIf(Condition::Equal, to(7)),
+            Exit,
+        ];
+        assert_eq!(run(fold, &code)[..3], ["push 7", "push 1", "push 2"]);
+        // Instructions which don't set the flags fold either way.
+        let code = [push(3), push(4), Or, JumpIf(Condition::Equal, to(4)), Exit];
+        assert_eq!(run(fold, &code)[0], "push 7");
+    }
+
+    #[test]
+    fn fold_stops_at_jump_targets() {
+        // The jump lands on the second push, which must still be there.
+        let code = [push(3), push(4), Add, Exit, Jump(to(1))];
+        assert_eq!(run(fold, &code), listing(&Ir::from_code(&code)));
+    }
+
+    #[test]
+    fn fold_stops_at_call_returns() {
+        // The return from the call lands on the push after it.
+        let code = [push(3), Call(to(5)), push(4), Add, Exit, Ret];
+        assert_eq!(run(fold, &code), listing(&Ir::from_code(&code)));
+        let code = [Call(to(4)), push(3), push(4), Add, Ret];
+        assert_eq!(run(fold, &code)[1], "push 7");
+    }
+
+    #[test]
+    fn thread_jumps() {
+        assert_eq!(
+            run(thread, &[Jump(to(2)), Nop, Jump(to(4)), Nop, Exit]),
+            ["exit", "nop", "exit", "nop", "exit"]
+        );
+        assert_eq!(
+            run(thread, &[Call(to(2)), Exit, Jump(to(4)), Nop, Ret]),
+            ["call 4", "exit", "ret", "nop", "ret"]
+        );
+        assert_eq!(
+            run(thread, &[push(1), JumpZero(to(2)), Exit]),
+            ["push 1", "pop", "exit"]
+        );
+        assert_eq!(
+            run(thread, &[push(1), JumpZero(to(2)), Jump(to(3)), Exit]),
+            ["push 1", "jumpzero 2", "exit"]
+        );
+    }
+
+    #[test]
+    fn drop_unreachable_code() {
+        assert_eq!(
+            run(unreachable, &[Call(to(4)), Exit, push(1), Pop, Ret]),
+            ["call 2", "exit", "ret"]
+        );
+        // Without the call, nothing returns to the exit after it.
+        assert_eq!(
+            run(unreachable, &[Jump(to(2)), Exit, Exit]),
+            ["jump 1", "exit"]
+        );
+    }
+
+    #[test]
+    fn drop_dead_stores() {
+        let code = [
+            push(1),
+            SetRegister(0),
+            push(2),
+            SetRegister(0),
+            push(3),
+            SetRegister(1),
+            GetRegister(0),
+            Exit,
+        ];
+        assert_eq!(
+            run(dead_stores, &code),
+            [
+                "push 1",
+                "pop",
+                "push 2",
+                "setregister R0",
+                "push 3",
+                "pop",
+                "getregister R0",
+                "exit"
+            ]
+        );
+        // A store read after a call returns is live.
+        let code = [
+            push(1),
+            SetRegister(0),
+            Call(to(4)),
+            Exit,
+            GetRegister(0),
+            Pop,
+            Ret,
+        ];
+        assert_eq!(run(dead_stores, &code)[1], "setregister R0");
+    }
+
+    /// The digest of a torus holding one atom of every standard element, whose
+    /// code is compiled at `level`, after 20000 events.
+    fn stdlib_digest(level: u8, seed: u64) -> u64 {
+        let mut builder = Engine::builder()
+            .grid(32, 32)
+            .boundary(Boundary::Torus)
+            .seed(seed);
+        for (_, src) in stdlib::ELEMENTS.iter() {
+            let mut compiler = Compiler::new("test");
+            compiler.set_opt_level(level);
+            let mut code = Vec::new();
+            compiler.compile_to_writer(&mut code, src).unwrap();
+            builder = builder.with_bytecode(&code);
+        }
+        let mut engine = builder.build().unwrap();
+        for (i, (name, _)) in stdlib::ELEMENTS.iter().enumerate() {
+            let atom = engine.new_atom(name).unwrap();
+            engine.set(4 + 5 * i, 4 + 5 * i, atom);
+        }
+        engine.run_events(20_000).unwrap();
+        engine.grid().digest()
+    }
+
+    #[test]
+    fn levels_run_stdlib_the_same() {
+        for seed in 1..=2 {
+            let want = stdlib_digest(0, seed);
+            for level in 1..=MAX_LEVEL {
+                assert_eq!(
+                    stdlib_digest(level, seed),
+                    want,
+                    "-O{} seed {}",
+                    level,
+                    seed
+                );
+            }
+        }
+    }
+}
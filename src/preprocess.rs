@@ -0,0 +1,660 @@
+//! Source expansion run before parsing.
+//!
+//! Macros are defined with `.macro NAME [PARAM, ...]` and `.endmacro` and are
+//! invoked like instructions, with comma separated arguments:
+//!
+//! ```text
+//! .macro copy from, to
+//!     push %to
+//!     push %from
+//!     getsite
+//!     setsite
+//! .endmacro
+//!
+//!     copy 0, 1
+//! ```
+//!
+//! Inside a body, `%PARAM` is replaced with the argument text and `%%` with a
+//! suffix unique to the expansion, for labels local to the macro. A macro must
+//! be defined before it is used. Macros may invoke other macros up to
+//! `MAX_DEPTH` expansions deep.
+//!
+//! Shared macros, fields and parameters can live in their own file, pulled
+//! in with `.include "PATH"`. Paths are relative to the including file. A file
+//! is only included once; including a file from itself, directly or through
+//! other files, is an error.
+//!
+//! Lines can be assembled conditionally, so one source can serve several
+//! configurations:
+//!
+//! ```text
+//! .ifdef debug
+//!     push 0xff0000
+//!     setpaint
+//! .endif
+//! .if radius == 4
+//! .radius 4
+//! .else
+//! .radius 2
+//! .endif
+//! ```
+//!
+//! `.ifdef NAME` and `.ifndef NAME` test whether a name is defined, `.if EXPR`
+//! whether a constant expression over the defined names is nonzero. Names are
+//! defined by the caller, such as with `ewac -D`, or with `.define NAME
+//! [EXPR]`, which defaults to 1. Conditionals may nest, and must be closed in
+//! the file or macro body they were opened in.
+//!
+//! Every line of the expanded text remembers the file and line it came from,
+//! so errors found by the parser can be reported against the original source.
+
+use crate::base::arith::Const;
+use crate::code::substrate::ConstExprParser;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The deepest nesting of macro expansions allowed.
+pub const MAX_DEPTH: usize = 32;
+
+#[derive(thiserror::Error, Debug)]
+pub enum PreprocessError {
+    #[error("{path}: {source}")]
+    IOError {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{0}: bad .include")]
+    BadInclude(Location),
+    #[error("{at}: cannot include {path}: {source}")]
+    MissingInclude {
+        at: Location,
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{at}: include cycle: {}", .cycle.join(" -> "))]
+    IncludeCycle { at: Location, cycle: Vec<String> },
+    #[error("{0}: .macro without .endmacro")]
+    UnterminatedMacro(Location),
+    #[error("{0}: .endmacro without .macro")]
+    UnexpectedEndMacro(Location),
+    #[error("{0}: nested .macro definition")]
+    NestedMacro(Location),
+    #[error("{0}: bad .macro definition")]
+    BadMacroDefinition(Location),
+    #[error("{at}: macro {name} is already defined")]
+    DuplicateMacro { name: String, at: Location },
+    #[error("{origin}: macro {name} takes {want} arguments but {got} were given")]
+    ArgumentCount {
+        name: String,
+        want: usize,
+        got: usize,
+        origin: Origin,
+    },
+    #[error("{origin}: unknown macro parameter %{param}")]
+    UnknownParameter { param: String, origin: Origin },
+    #[error("{origin}: macro {name} expands more than {} levels deep", MAX_DEPTH)]
+    RecursionLimit { name: String, origin: Origin },
+    #[error("{0}: conditional without .endif")]
+    UnterminatedIf(Location),
+    #[error("{0}: {1} without .if")]
+    UnexpectedConditional(Location, String),
+    #[error("{0}: .else after .else")]
+    DuplicateElse(Location),
+    #[error("{at}: bad condition: {message}")]
+    BadCondition { at: Location, message: String },
+    #[error("{0}: bad .define")]
+    BadDefine(Location),
+    #[error("{at}: {name} is already defined")]
+    DuplicateDefine { name: String, at: Location },
+}
+
+/// A line of a source file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// Where a line of expanded text came from: a line of the source and, if it
+/// was produced by a macro, the chain of invocations which produced it,
+/// outermost first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Origin {
+    pub location: Location,
+    pub expansions: Vec<(String, Location)>,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.location)?;
+        for (name, at) in self.expansions.iter().rev() {
+            write!(f, ", in macro {} expanded at {}", name, at)?;
+        }
+        Ok(())
+    }
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<(Location, String)>,
+}
+
+/// Expanded source text and the origin of each of its lines.
+#[derive(Debug)]
+pub struct Expanded {
+    pub text: String,
+    origins: Vec<Origin>,
+}
+
+impl Expanded {
+    /// `text` as read from `name`, with nothing expanded.
+    pub fn plain(name: &str, text: &str) -> Self {
+        let origins = (1..=text.lines().count().max(1))
+            .map(|line| Origin {
+                location: Location {
+                    file: name.to_owned(),
+                    line,
+                },
+                expansions: Vec::new(),
+            })
+            .collect();
+        Self {
+            text: text.to_owned(),
+            origins,
+        }
+    }
+
+    /// The origin of the line containing byte `offset` of the text.
+    pub fn origin(&self, offset: usize) -> Option<&Origin> {
+        let offset = offset.min(self.text.len());
+        let line = self.text[..offset].matches('\n').count();
+        self.origins.get(line).or_else(|| self.origins.last())
+    }
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Spells `n` in lowercase letters, since labels may not contain digits.
+pub fn letters(mut n: usize) -> String {
+    let mut s = Vec::new();
+    loop {
+        s.push(b'a' + (n % 26) as u8);
+        n /= 26;
+        if n == 0 {
+            break;
+        }
+    }
+    s.reverse();
+    String::from_utf8(s).unwrap()
+}
+
+/// `line` up to a `/* */` or `//` comment outside of quotes.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '/' if !quoted && matches!(line[i + 1..].chars().next(), Some('*') | Some('/')) => {
+                return &line[..i]
+            }
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Splits a line into its first word and the rest, ignoring a trailing
+/// comment.
+fn split_invocation(line: &str) -> (&str, &str) {
+    let line = strip_comment(line).trim();
+    match line.find(char::is_whitespace) {
+        Some(i) => (&line[..i], line[i..].trim()),
+        None => (line, ""),
+    }
+}
+
+/// An open `.if`, `.ifdef` or `.ifndef`.
+struct Conditional {
+    at: Location,
+    /// Whether the enclosing lines are assembled.
+    outer: bool,
+    /// Whether the current branch is assembled.
+    active: bool,
+    in_else: bool,
+}
+
+/// Evaluates a constant expression over `defines`.
+pub fn evaluate(expr: &str, defines: &HashMap<String, Const>) -> Result<Const, String> {
+    let names = RefCell::new(defines.iter().map(|(k, v)| (k.as_str(), *v)).collect());
+    ConstExprParser::new()
+        .parse(&names, expr)
+        .map_err(|e| match e {
+            lalrpop_util::ParseError::User { error } => error.to_string(),
+            e => format!("cannot parse `{}`: {}", expr, e),
+        })
+}
+
+struct Expander {
+    macros: HashMap<String, Macro>,
+    defines: HashMap<String, Const>,
+    conditionals: Vec<Conditional>,
+    expansions: usize,
+    /// The files being included, outermost first.
+    include_stack: Vec<PathBuf>,
+    included: HashSet<PathBuf>,
+    text: String,
+    origins: Vec<Origin>,
+}
+
+impl Expander {
+    fn new(defines: &HashMap<String, Const>) -> Self {
+        Self {
+            macros: HashMap::new(),
+            defines: defines.clone(),
+            conditionals: Vec::new(),
+            expansions: 0,
+            include_stack: Vec::new(),
+            included: HashSet::new(),
+            text: String::new(),
+            origins: Vec::new(),
+        }
+    }
+
+    fn read(path: &Path) -> Result<String, PreprocessError> {
+        fs::read_to_string(path).map_err(|source| PreprocessError::IOError {
+            path: path.display().to_string(),
+            source,
+        })
+    }
+
+    /// Expands the source of `file`, whose includes are resolved relative to
+    /// `dir`.
+    fn expand_source(&mut self, file: &str, dir: &Path, src: &str) -> Result<(), PreprocessError> {
+        let at = |line| Location {
+            file: file.to_owned(),
+            line,
+        };
+        let depth = self.conditionals.len();
+        let mut lines = src.lines().enumerate().map(|(i, l)| (at(i + 1), l));
+        while let Some((loc, text)) = lines.next() {
+            if self.conditional(&loc, text, depth)? || !self.active() {
+                continue;
+            }
+            match split_invocation(text) {
+                (".include", args) => self.include(loc, dir, args)?,
+                (".macro", args) => self.define(&mut lines, loc, args)?,
+                (".endmacro", _) => return Err(PreprocessError::UnexpectedEndMacro(loc)),
+                (".define", args) => self.define_name(loc, args)?,
+                _ => self.expand_line(loc, text, &[])?,
+            }
+        }
+        self.close_conditionals(depth)
+    }
+
+    /// Whether lines are currently assembled.
+    fn active(&self) -> bool {
+        self.conditionals.last().is_none_or(|c| c.active)
+    }
+
+    /// Handles `text` if it is a conditional directive. Conditionals opened
+    /// below `depth` belong to an enclosing file or macro and may not be
+    /// continued or closed here.
+    fn conditional(
+        &mut self,
+        loc: &Location,
+        text: &str,
+        depth: usize,
+    ) -> Result<bool, PreprocessError> {
+        let (directive, args) = split_invocation(text);
+        let outer = self.active();
+        match directive {
+            ".if" | ".ifdef" | ".ifndef" => {
+                // Conditions in skipped lines are not evaluated.
+                let active = outer && self.condition(loc, directive, args)?;
+                self.conditionals.push(Conditional {
+                    at: loc.clone(),
+                    outer,
+                    active,
+                    in_else: false,
+                });
+            }
+            ".else" | ".endif" => {
+                if self.conditionals.len() <= depth {
+                    return Err(PreprocessError::UnexpectedConditional(
+                        loc.clone(),
+                        directive.to_owned(),
+                    ));
+                }
+                if directive == ".endif" {
+                    self.conditionals.pop();
+                    return Ok(true);
+                }
+                let c = self.conditionals.last_mut().unwrap();
+                if c.in_else {
+                    return Err(PreprocessError::DuplicateElse(loc.clone()));
+                }
+                c.in_else = true;
+                c.active = c.outer && !c.active;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn condition(
+        &self,
+        loc: &Location,
+        directive: &str,
+        args: &str,
+    ) -> Result<bool, PreprocessError> {
+        let bad = |message: String| PreprocessError::BadCondition {
+            at: loc.clone(),
+            message,
+        };
+        if directive == ".if" {
+            return evaluate(args, &self.defines)
+                .map(|c| !c.is_zero())
+                .map_err(bad);
+        }
+        if args.is_empty() || !args.chars().all(is_ident_char) {
+            return Err(bad(format!("expected a name after {}", directive)));
+        }
+        Ok(self.defines.contains_key(args) == (directive == ".ifdef"))
+    }
+
+    /// Fails if conditionals opened at or above `depth` were left open.
+    fn close_conditionals(&mut self, depth: usize) -> Result<(), PreprocessError> {
+        if self.conditionals.len() > depth {
+            let at = self.conditionals[depth].at.clone();
+            self.conditionals.truncate(depth);
+            return Err(PreprocessError::UnterminatedIf(at));
+        }
+        Ok(())
+    }
+
+    fn define_name(&mut self, loc: Location, args: &str) -> Result<(), PreprocessError> {
+        let (name, expr) = match args.find(char::is_whitespace) {
+            Some(i) => (&args[..i], args[i..].trim()),
+            None => (args, ""),
+        };
+        if name.is_empty() || !name.chars().all(is_ident_char) {
+            return Err(PreprocessError::BadDefine(loc));
+        }
+        if self.defines.contains_key(name) {
+            return Err(PreprocessError::DuplicateDefine {
+                name: name.to_owned(),
+                at: loc,
+            });
+        }
+        let value = if expr.is_empty() {
+            Const::from(1u8)
+        } else {
+            evaluate(expr, &self.defines).map_err(|message| PreprocessError::BadCondition {
+                at: loc.clone(),
+                message,
+            })?
+        };
+        self.defines.insert(name.to_owned(), value);
+        Ok(())
+    }
+
+    fn include(&mut self, loc: Location, dir: &Path, args: &str) -> Result<(), PreprocessError> {
+        let path = args
+            .strip_prefix('"')
+            .and_then(|x| x.strip_suffix('"'))
+            .filter(|x| !x.is_empty())
+            .ok_or_else(|| PreprocessError::BadInclude(loc.clone()))?;
+        let path = dir.join(path);
+        let key = fs::canonicalize(&path).map_err(|source| PreprocessError::MissingInclude {
+            at: loc.clone(),
+            path: path.display().to_string(),
+            source,
+        })?;
+        if let Some(i) = self.include_stack.iter().position(|p| *p == key) {
+            let cycle = self.include_stack[i..]
+                .iter()
+                .chain(std::iter::once(&key))
+                .map(|p| p.display().to_string())
+                .collect();
+            return Err(PreprocessError::IncludeCycle { at: loc, cycle });
+        }
+        if !self.included.insert(key.clone()) {
+            return Ok(());
+        }
+        self.expand_file(&path, key)
+    }
+
+    fn expand_file(&mut self, path: &Path, key: PathBuf) -> Result<(), PreprocessError> {
+        let src = Self::read(path)?;
+        let dir = path.parent().unwrap_or_else(|| Path::new("")).to_owned();
+        self.include_stack.push(key);
+        self.expand_source(&path.display().to_string(), &dir, &src)?;
+        self.include_stack.pop();
+        Ok(())
+    }
+
+    fn define(
+        &mut self,
+        lines: &mut dyn Iterator<Item = (Location, &str)>,
+        line: Location,
+        args: &str,
+    ) -> Result<(), PreprocessError> {
+        let (name, params) = split_invocation(args);
+        if name.is_empty() || !name.chars().all(is_ident_char) {
+            return Err(PreprocessError::BadMacroDefinition(line));
+        }
+        let params: Vec<String> = if params.is_empty() {
+            Vec::new()
+        } else {
+            params.split(',').map(|p| p.trim().to_owned()).collect()
+        };
+        if params
+            .iter()
+            .any(|p| p.is_empty() || !p.chars().all(is_ident_char))
+        {
+            return Err(PreprocessError::BadMacroDefinition(line));
+        }
+        if self.macros.contains_key(name) {
+            return Err(PreprocessError::DuplicateMacro {
+                name: name.to_owned(),
+                at: line,
+            });
+        }
+
+        let mut body = Vec::new();
+        loop {
+            let (n, text) = lines
+                .next()
+                .ok_or_else(|| PreprocessError::UnterminatedMacro(line.clone()))?;
+            match split_invocation(text).0 {
+                ".endmacro" => break,
+                ".macro" => return Err(PreprocessError::NestedMacro(n)),
+                _ => body.push((n, text.to_owned())),
+            }
+        }
+        self.macros.insert(name.to_owned(), Macro { params, body });
+        Ok(())
+    }
+
+    fn substitute(
+        &self,
+        text: &str,
+        params: &HashMap<&str, &str>,
+        suffix: &str,
+        origin: &Origin,
+    ) -> Result<String, PreprocessError> {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(i) = rest.find('%') {
+            out.push_str(&rest[..i]);
+            rest = &rest[i + 1..];
+            if let Some(r) = rest.strip_prefix('%') {
+                out.push('_');
+                out.push_str(suffix);
+                rest = r;
+                continue;
+            }
+            let end = rest.find(|c| !is_ident_char(c)).unwrap_or(rest.len());
+            let param = &rest[..end];
+            if param.is_empty() {
+                // A lone `%` is the remainder operator.
+                out.push('%');
+                continue;
+            }
+            let arg = params
+                .get(param)
+                .ok_or_else(|| PreprocessError::UnknownParameter {
+                    param: param.to_owned(),
+                    origin: origin.clone(),
+                })?;
+            out.push_str(arg);
+            rest = &rest[end..];
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+
+    fn expand_line(
+        &mut self,
+        line: Location,
+        text: &str,
+        expansions: &[(String, Location)],
+    ) -> Result<(), PreprocessError> {
+        let origin = Origin {
+            location: line.clone(),
+            expansions: expansions.to_vec(),
+        };
+        let (name, args) = split_invocation(text);
+        let (param_names, body) = match self.macros.get(name) {
+            Some(m) => (m.params.clone(), m.body.clone()),
+            None => {
+                self.text.push_str(text);
+                self.text.push('\n');
+                self.origins.push(origin);
+                return Ok(());
+            }
+        };
+
+        if expansions.len() >= MAX_DEPTH {
+            return Err(PreprocessError::RecursionLimit {
+                name: name.to_owned(),
+                origin,
+            });
+        }
+        let args: Vec<&str> = if args.is_empty() {
+            Vec::new()
+        } else {
+            args.split(',').map(str::trim).collect()
+        };
+        if args.len() != param_names.len() {
+            return Err(PreprocessError::ArgumentCount {
+                name: name.to_owned(),
+                want: param_names.len(),
+                got: args.len(),
+                origin,
+            });
+        }
+        let params: HashMap<&str, &str> =
+            param_names.iter().map(String::as_str).zip(args).collect();
+        let suffix = letters(self.expansions);
+        self.expansions += 1;
+
+        let mut inner = expansions.to_vec();
+        inner.push((name.to_owned(), line));
+        let depth = self.conditionals.len();
+        for (n, text) in body {
+            let origin = Origin {
+                location: n.clone(),
+                expansions: inner.clone(),
+            };
+            let text = self.substitute(&text, &params, &suffix, &origin)?;
+            if self.conditional(&n, &text, depth)? || !self.active() {
+                continue;
+            }
+            self.expand_line(n, &text, &inner)?;
+        }
+        self.close_conditionals(depth)
+    }
+}
+
+/// Expands `src`, resolving includes relative to the current directory.
+/// Locations in errors are reported against `name`.
+pub fn expand(
+    name: &str,
+    src: &str,
+    defines: &HashMap<String, Const>,
+) -> Result<Expanded, PreprocessError> {
+    let mut e = Expander::new(defines);
+    e.expand_source(name, Path::new(""), src)?;
+    Ok(Expanded {
+        text: e.text,
+        origins: e.origins,
+    })
+}
+
+/// Reads and expands the file at `path`, with `defines` defined.
+pub fn expand_file(
+    path: &Path,
+    defines: &HashMap<String, Const>,
+) -> Result<Expanded, PreprocessError> {
+    let mut e = Expander::new(defines);
+    let key = fs::canonicalize(path).map_err(|source| PreprocessError::IOError {
+        path: path.display().to_string(),
+        source,
+    })?;
+    e.included.insert(key.clone());
+    e.expand_file(path, key)?;
+    Ok(Expanded {
+        text: e.text,
+        origins: e.origins,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_ignores_comments() {
+        assert_eq!(split_invocation("  wait // spin"), ("wait", ""));
+        assert_eq!(split_invocation("copy 0, 1 /* a */"), ("copy", "0, 1"));
+        assert_eq!(split_invocation(".if x == 1// odd"), (".if", "x == 1"));
+        assert_eq!(
+            split_invocation(".include \"a//b.s\" // c"),
+            (".include", "\"a//b.s\"")
+        );
+    }
+
+    #[test]
+    fn macro_invocation_with_line_comment() {
+        let src =
+            ".macro copy from, to // copy a site\n  push %to\n.endmacro\n  copy 0, 1 // copy\n";
+        let e = expand("test.s", src, &HashMap::new()).unwrap();
+        assert_eq!(e.text.trim(), "push 1");
+    }
+
+    #[test]
+    fn include_with_line_comment() {
+        let e = expand_file(
+            Path::new("tests/testdata/preprocess/main.s"),
+            &HashMap::new(),
+        )
+        .unwrap();
+        let lines: Vec<&str> = e.text.lines().map(str::trim).collect();
+        assert_eq!(lines, vec!["// Shared by main.s.", "nop"]);
+    }
+}
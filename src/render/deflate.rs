@@ -0,0 +1,174 @@
+//! A small zlib compressor for PNG image data.
+//!
+//! Data is compressed as one deflate block with the fixed Huffman codes,
+//! finding repeats with a hash chain. That is far from the best deflate can
+//! do, but grid images are mostly runs of a few colors and compress well
+//! regardless.
+
+/// The longest and furthest back a repeat may be.
+const MAX_LENGTH: usize = 258;
+const MAX_DISTANCE: usize = 32768;
+
+/// How many earlier positions are tried when looking for a repeat.
+const MAX_CHAIN: usize = 64;
+
+const HASH_BITS: u32 = 15;
+
+/// The shortest length of each length code, from 257, and its extra bits.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// The shortest distance of each distance code and its extra bits.
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Writes bits least significant first, as deflate reads them.
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u32,
+    n: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, n: u32) {
+        self.bits |= value << self.n;
+        self.n += n;
+        while self.n >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.n -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, which deflate packs most significant bit
+    /// first.
+    fn write_code(&mut self, code: u32, n: u32) {
+        self.write(code.reverse_bits() >> (32 - n), n);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.n > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+}
+
+/// Writes literal or length `v`, 0 to 287, in the fixed code.
+fn write_symbol(w: &mut BitWriter, v: u32) {
+    match v {
+        0..=143 => w.write_code(0x30 + v, 8),
+        144..=255 => w.write_code(0x190 + v - 144, 9),
+        256..=279 => w.write_code(v - 256, 7),
+        _ => w.write_code(0xc0 + v - 280, 8),
+    }
+}
+
+fn write_repeat(w: &mut BitWriter, length: usize, distance: usize) {
+    let i = LENGTH_BASE
+        .iter()
+        .rposition(|&b| b as usize <= length)
+        .unwrap();
+    write_symbol(w, 257 + i as u32);
+    w.write(
+        (length - LENGTH_BASE[i] as usize) as u32,
+        LENGTH_EXTRA[i] as u32,
+    );
+    let i = DISTANCE_BASE
+        .iter()
+        .rposition(|&b| b as usize <= distance)
+        .unwrap();
+    w.write_code(i as u32, 5);
+    w.write(
+        (distance - DISTANCE_BASE[i] as usize) as u32,
+        DISTANCE_EXTRA[i] as u32,
+    );
+}
+
+fn hash(data: &[u8]) -> usize {
+    let v = (data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32;
+    (v.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &x in chunk {
+            a += x as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+/// Compresses `data` in the zlib format.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter {
+        out: vec![0x78, 0x01],
+        bits: 0,
+        n: 0,
+    };
+    // A final block with fixed codes.
+    w.write(1, 1);
+    w.write(1, 2);
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; data.len()];
+    let mut i = 0;
+    while i < data.len() {
+        let (mut length, mut distance) = (0, 0);
+        if i + 3 <= data.len() {
+            let h = hash(&data[i..]);
+            let mut j = head[h];
+            let mut chain = 0;
+            let max = MAX_LENGTH.min(data.len() - i);
+            while j != usize::MAX && i - j <= MAX_DISTANCE && chain < MAX_CHAIN {
+                let n = data[j..]
+                    .iter()
+                    .zip(&data[i..i + max])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if n > length {
+                    length = n;
+                    distance = i - j;
+                    if n == max {
+                        break;
+                    }
+                }
+                j = prev[j];
+                chain += 1;
+            }
+        }
+        let n = if length >= 3 {
+            write_repeat(&mut w, length, distance);
+            length
+        } else {
+            write_symbol(&mut w, data[i] as u32);
+            1
+        };
+        for k in i..(i + n).min(data.len().saturating_sub(2)) {
+            let h = hash(&data[k..]);
+            prev[k] = head[h];
+            head[h] = k;
+        }
+        i += n;
+    }
+    write_symbol(&mut w, 256);
+
+    let mut out = w.finish();
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
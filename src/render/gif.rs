@@ -0,0 +1,124 @@
+//! Writing frames as animated GIFs.
+
+use crate::render::{Frame, Rgb};
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// Pixels are 8 bit palette indexes, so codes start at 9 bits.
+const MIN_CODE_SIZE: u32 = 8;
+const CLEAR: u16 = 1 << MIN_CODE_SIZE;
+const END: u16 = CLEAR + 1;
+const MAX_CODES: u16 = 4096;
+
+/// Packs codes least significant bit first into sub-blocks of at most 255
+/// bytes.
+struct CodeWriter {
+    bytes: Vec<u8>,
+    bits: u32,
+    n: u32,
+}
+
+impl CodeWriter {
+    fn write(&mut self, code: u16, size: u32) {
+        self.bits |= (code as u32) << self.n;
+        self.n += size;
+        while self.n >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.n -= 8;
+        }
+    }
+
+    fn finish<W: Write>(mut self, w: &mut W) -> io::Result<()> {
+        if self.n > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+        for block in self.bytes.chunks(255) {
+            w.write_all(&[block.len() as u8])?;
+            w.write_all(block)?;
+        }
+        w.write_all(&[0])
+    }
+}
+
+/// Writes `pixels` as GIF's variant of LZW: codes grow a bit as the table
+/// fills, up to 12 bits, and the table is cleared when full.
+fn write_lzw<W: Write>(w: &mut W, pixels: &[u8]) -> io::Result<()> {
+    w.write_all(&[MIN_CODE_SIZE as u8])?;
+    let mut out = CodeWriter {
+        bytes: Vec::new(),
+        bits: 0,
+        n: 0,
+    };
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = END + 1;
+    let mut size = MIN_CODE_SIZE + 1;
+    out.write(CLEAR, size);
+
+    let mut prefix = pixels[0] as u16;
+    for &p in &pixels[1..] {
+        if let Some(&code) = table.get(&(prefix, p)) {
+            prefix = code;
+            continue;
+        }
+        out.write(prefix, size);
+        // Decoders learn of the entry made here a code later, so the size
+        // grows one code after the table outgrows it.
+        if next >= 1 << size && size < 12 {
+            size += 1;
+        }
+        if next < MAX_CODES {
+            table.insert((prefix, p), next);
+            next += 1;
+        } else {
+            out.write(CLEAR, size);
+            table.clear();
+            next = END + 1;
+            size = MIN_CODE_SIZE + 1;
+        }
+        prefix = p as u16;
+    }
+    out.write(prefix, size);
+    if next >= 1 << size && size < 12 {
+        size += 1;
+    }
+    out.write(END, size);
+    out.finish(w)
+}
+
+/// Writes `frames`, which must all be the same size, as a GIF which shows
+/// each for `delay_ms`, to the nearest hundredth of a second, and loops
+/// forever. `palette` may have at most 256 colors, and frames at most 65535
+/// pixels a side.
+pub fn write<W: Write>(
+    mut w: W,
+    palette: &[Rgb],
+    frames: &[Frame],
+    delay_ms: u16,
+) -> io::Result<()> {
+    let first = match frames.first() {
+        Some(frame) => frame,
+        None => return Ok(()),
+    };
+    w.write_all(b"GIF89a")?;
+    w.write_all(&(first.width as u16).to_le_bytes())?;
+    w.write_all(&(first.height as u16).to_le_bytes())?;
+    // A global table of 256 colors, 8 bits per primary.
+    w.write_all(&[0xf7, 0, 0])?;
+    let mut table = palette.concat();
+    table.resize(256 * 3, 0);
+    w.write_all(&table)?;
+    // Loop forever.
+    w.write_all(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+
+    let delay = (delay_ms.saturating_add(5) / 10).to_le_bytes();
+    for frame in frames {
+        w.write_all(&[0x21, 0xf9, 4, 0, delay[0], delay[1], 0, 0])?;
+        w.write_all(&[0x2c, 0, 0, 0, 0])?;
+        w.write_all(&(frame.width as u16).to_le_bytes())?;
+        w.write_all(&(frame.height as u16).to_le_bytes())?;
+        w.write_all(&[0])?;
+        write_lzw(&mut w, &frame.pixels)?;
+    }
+    w.write_all(&[0x3b])
+}
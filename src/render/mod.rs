@@ -0,0 +1,204 @@
+//! Drawing grids as images, for sharing runs.
+//!
+//! Each site is drawn as a square of its element's `.color`, or `.fgcolor`
+//! if it has none. Empty sites are black and atoms of elements without a
+//! color, or of unknown types, are gray. Frames are indexed-color images, so
+//! every frame of a run shares one palette and can be written as PNG, APNG
+//! or GIF alike.
+//!
+//! A renderer may also overlay one of the grid's layers on its empty sites,
+//! in shades of cyan brightening with the number of bits each value needs, so
+//! a gradient spanning several orders of magnitude stays visible.
+//!
+//! `render_diff` draws how two grids differ (see `runtime::diff`) instead,
+//! with its own palette.
+
+mod deflate;
+pub mod gif;
+pub mod png;
+
+use crate::base::color::Color;
+use crate::runtime::diff::GridDiff;
+use crate::runtime::grid::{Grid, LAYERS};
+use crate::runtime::Runtime;
+use std::collections::HashMap;
+
+pub type Rgb = [u8; 3];
+
+const EMPTY: Rgb = [0, 0, 0];
+const UNCOLORED: Rgb = [0x80, 0x80, 0x80];
+/// The brightest shade of an overlaid layer.
+const OVERLAY: Rgb = [0x00, 0xe0, 0xff];
+/// The number of shades of an overlaid layer, two bits of value each.
+const SHADES: usize = 16;
+
+/// The palette of `render_diff` frames: empty sites, atoms the same in both
+/// grids, and atoms only in the first grid, only in the second and in both
+/// but different, in that order.
+pub const DIFF_PALETTE: [Rgb; 5] = [
+    EMPTY,
+    [0x40, 0x40, 0x40],
+    [0xff, 0x40, 0x40],
+    [0x40, 0xff, 0x40],
+    [0xff, 0xe0, 0x40],
+];
+
+/// An image of a grid, as indexes into a palette.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    /// Rows from the top, left to right.
+    pub pixels: Vec<u8>,
+}
+
+/// Draws grids with the colors of a runtime's elements.
+pub struct Renderer {
+    scale: usize,
+    palette: Vec<Rgb>,
+    colors: HashMap<u16, u8>,
+    /// The layer overlaid and the palette index of each of its shades.
+    overlay: Option<(usize, [u8; SHADES])>,
+}
+
+fn rgb(c: Color) -> Option<Rgb> {
+    let [r, g, b, a] = c.bits().to_be_bytes();
+    if a == 0 {
+        return None;
+    }
+    Some([r, g, b])
+}
+
+fn distance(a: Rgb, b: Rgb) -> u32 {
+    a.iter()
+        .zip(&b)
+        .map(|(&x, &y)| (x as i32 - y as i32).pow(2) as u32)
+        .sum()
+}
+
+impl Renderer {
+    /// A renderer drawing each site as a `scale` by `scale` square. The
+    /// palette holds the colors of the elements loaded now; past 256 colors,
+    /// elements get the nearest color already in it.
+    pub fn new(runtime: &Runtime, scale: usize) -> Self {
+        let mut renderer = Self {
+            scale: scale.max(1),
+            palette: vec![EMPTY, UNCOLORED],
+            colors: HashMap::new(),
+            overlay: None,
+        };
+        let mut elements: Vec<_> = runtime.elements().filter(|(t, _)| *t != 0).collect();
+        elements.sort_by_key(|(t, _)| *t);
+        for (t, m) in elements {
+            if let Some(c) = m.color.or_else(|| m.fg_color.parse().ok()).and_then(rgb) {
+                let i = renderer.index_of(c);
+                renderer.colors.insert(t, i);
+            }
+        }
+        renderer
+    }
+
+    /// Draws layer `l` on the empty sites of each frame. Its shades are added
+    /// to the palette after the elements' colors, or take the nearest colors
+    /// in it once it is full. Layers past `LAYERS` are ignored.
+    pub fn overlay(&mut self, l: usize) {
+        if l >= LAYERS {
+            return;
+        }
+        let mut shades = [0; SHADES];
+        for (k, shade) in shades.iter_mut().enumerate() {
+            let c = OVERLAY.map(|x| (x as usize * (k + 1) / SHADES) as u8);
+            *shade = self.index_of(c);
+        }
+        self.overlay = Some((l, shades));
+    }
+
+    /// The palette index of `c`, adding it if there is room.
+    fn index_of(&mut self, c: Rgb) -> u8 {
+        let i = match self.palette.iter().position(|&p| p == c) {
+            Some(i) => i,
+            None if self.palette.len() < 256 => {
+                self.palette.push(c);
+                self.palette.len() - 1
+            }
+            None => (0..self.palette.len())
+                .min_by_key(|&i| distance(self.palette[i], c))
+                .unwrap(),
+        };
+        i as u8
+    }
+
+    /// The colors frame pixels index, at most 256.
+    pub fn palette(&self) -> &[Rgb] {
+        &self.palette
+    }
+
+    pub fn render(&self, grid: &Grid) -> Frame {
+        let (width, height) = (grid.width() * self.scale, grid.height() * self.scale);
+        let mut pixels = Vec::with_capacity(width * height);
+        let mut row = Vec::with_capacity(width);
+        for y in 0..grid.height() {
+            row.clear();
+            for x in 0..grid.width() {
+                let atom = grid.get(x, y).unwrap();
+                let i = if atom.is_empty() {
+                    match self.overlay {
+                        Some((l, shades)) => match grid.get_layer(l, x, y).unwrap() {
+                            0 => 0,
+                            v => shades[(31 - v.leading_zeros() as usize) / 2],
+                        },
+                        None => 0,
+                    }
+                } else {
+                    self.colors.get(&atom.get_type()).copied().unwrap_or(1)
+                };
+                row.resize(row.len() + self.scale, i);
+            }
+            for _ in 0..self.scale {
+                pixels.extend_from_slice(&row);
+            }
+        }
+        Frame {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+/// Draws `diff` over `second`, the second grid compared, as a `scale` by
+/// `scale` square per site indexing `DIFF_PALETTE`: atoms the same in both
+/// grids are dark gray, and sites which differ red where only the first grid
+/// has an atom, green where only the second has one and yellow where both
+/// have different atoms.
+pub fn render_diff(second: &Grid, diff: &GridDiff, scale: usize) -> Frame {
+    let scale = scale.max(1);
+    let mut sites: Vec<u8> = (0..second.height())
+        .flat_map(|y| (0..second.width()).map(move |x| (x, y)))
+        .map(|(x, y)| u8::from(!second.get(x, y).unwrap().is_empty()))
+        .collect();
+    for s in diff.sites.iter() {
+        sites[s.y * second.width() + s.x] = match (s.before.is_empty(), s.after.is_empty()) {
+            (false, true) => 2,
+            (true, false) => 3,
+            _ => 4,
+        };
+    }
+    let (width, height) = (second.width() * scale, second.height() * scale);
+    let mut pixels = Vec::with_capacity(width * height);
+    let mut row = Vec::with_capacity(width);
+    for sites in sites.chunks(second.width()) {
+        row.clear();
+        for &i in sites {
+            row.resize(row.len() + scale, i);
+        }
+        for _ in 0..scale {
+            pixels.extend_from_slice(&row);
+        }
+    }
+    Frame {
+        width,
+        height,
+        pixels,
+    }
+}
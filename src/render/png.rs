@@ -0,0 +1,124 @@
+//! Writing frames as indexed-color PNG images, and as animated PNGs (APNG).
+
+use crate::render::{deflate, Frame, Rgb};
+use std::io::{self, Write};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |c, &b| {
+        CRC_TABLE[((c ^ b as u32) & 0xff) as usize] ^ (c >> 8)
+    })
+}
+
+fn chunk<W: Write>(w: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    let mut body = Vec::with_capacity(4 + data.len());
+    body.extend_from_slice(kind);
+    body.extend_from_slice(data);
+    w.write_all(&body)?;
+    w.write_all(&crc32(&body).to_be_bytes())
+}
+
+fn header<W: Write>(w: &mut W, frame: &Frame) -> io::Result<()> {
+    w.write_all(&SIGNATURE)?;
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(frame.width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(frame.height as u32).to_be_bytes());
+    // 8 bits per pixel, indexed color, no interlacing.
+    ihdr.extend_from_slice(&[8, 3, 0, 0, 0]);
+    chunk(w, b"IHDR", &ihdr)
+}
+
+fn palette_chunk<W: Write>(w: &mut W, palette: &[Rgb]) -> io::Result<()> {
+    chunk(w, b"PLTE", &palette.concat())
+}
+
+/// The compressed scanlines of `frame`, each unfiltered.
+fn image_data(frame: &Frame) -> Vec<u8> {
+    let mut raw = Vec::with_capacity((frame.width + 1) * frame.height);
+    for row in frame.pixels.chunks(frame.width) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    deflate::compress(&raw)
+}
+
+/// Writes `frame` as a PNG image.
+pub fn write<W: Write>(mut w: W, palette: &[Rgb], frame: &Frame) -> io::Result<()> {
+    header(&mut w, frame)?;
+    palette_chunk(&mut w, palette)?;
+    chunk(&mut w, b"IDAT", &image_data(frame))?;
+    chunk(&mut w, b"IEND", &[])
+}
+
+/// Writes `frames`, which must all be the same size, as an animated PNG
+/// which shows each for `delay_ms` and loops forever. Viewers without APNG
+/// support show the first frame.
+pub fn write_animated<W: Write>(
+    mut w: W,
+    palette: &[Rgb],
+    frames: &[Frame],
+    delay_ms: u16,
+) -> io::Result<()> {
+    let first = match frames.first() {
+        Some(frame) => frame,
+        None => return Ok(()),
+    };
+    header(&mut w, first)?;
+    let mut actl = Vec::with_capacity(8);
+    actl.extend_from_slice(&(frames.len() as u32).to_be_bytes());
+    actl.extend_from_slice(&0u32.to_be_bytes());
+    chunk(&mut w, b"acTL", &actl)?;
+    palette_chunk(&mut w, palette)?;
+
+    // Frame controls and frame data share one sequence.
+    let mut sequence = 0u32;
+    for (i, frame) in frames.iter().enumerate() {
+        let mut fctl = Vec::with_capacity(26);
+        fctl.extend_from_slice(&sequence.to_be_bytes());
+        fctl.extend_from_slice(&(frame.width as u32).to_be_bytes());
+        fctl.extend_from_slice(&(frame.height as u32).to_be_bytes());
+        fctl.extend_from_slice(&[0; 8]);
+        fctl.extend_from_slice(&delay_ms.to_be_bytes());
+        fctl.extend_from_slice(&1000u16.to_be_bytes());
+        // Replace the whole image, disposing of nothing.
+        fctl.extend_from_slice(&[0, 0]);
+        chunk(&mut w, b"fcTL", &fctl)?;
+        sequence += 1;
+
+        let data = image_data(frame);
+        if i == 0 {
+            chunk(&mut w, b"IDAT", &data)?;
+        } else {
+            let mut fdat = Vec::with_capacity(4 + data.len());
+            fdat.extend_from_slice(&sequence.to_be_bytes());
+            fdat.extend_from_slice(&data);
+            chunk(&mut w, b"fdAT", &fdat)?;
+            sequence += 1;
+        }
+    }
+    chunk(&mut w, b"IEND", &[])
+}
@@ -0,0 +1,266 @@
+//! How a grid stores its sites.
+//!
+//! Each tile of a `Grid` keeps its sites, and its cache of its neighbors'
+//! sites, in a `GridBackend`. `DenseBackend`, the default, allocates every
+//! site up front. `SparseBackend` allocates sites in chunks as atoms, paint or
+//! layer values are written to them, so a huge world holding a small colony
+//! costs memory only where the colony has been, and counting its atoms skips
+//! the rest. Both allocate a layer only once a value is written to it, and
+//! both count the atoms of each type they hold, so `Grid::find` can skip
+//! tiles holding none of the element it looks for.
+
+use crate::base::atom::Atom;
+use crate::base::color::Color;
+use crate::runtime::grid::LAYERS;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Storage for the sites of one tile, by the index `Grid` gives each site.
+/// Sites never written hold an empty atom, no paint and zero in every layer.
+pub trait GridBackend: fmt::Debug + Send {
+    fn atom(&self, i: usize) -> Atom;
+    fn paint(&self, i: usize) -> Color;
+    /// The value of layer `l`, less than `LAYERS`.
+    fn layer(&self, l: usize, i: usize) -> u32;
+    fn set_atom(&mut self, i: usize, atom: Atom);
+    fn set_paint(&mut self, i: usize, paint: Color);
+    fn set_layer(&mut self, l: usize, i: usize, value: u32);
+
+    /// Whether any layer may hold a nonzero value, so that grids can skip
+    /// copying layers of tiles which have none.
+    fn has_layers(&self) -> bool {
+        true
+    }
+
+    /// Calls `f` with the index of every site holding a non-empty atom, in
+    /// ascending order.
+    fn for_each_atom(&self, f: &mut dyn FnMut(usize, Atom));
+
+    /// The number of sites holding atoms of type `type_num`, cache included,
+    /// or `None` if the backend doesn't keep count.
+    fn count(&self, _type_num: u16) -> Option<usize> {
+        None
+    }
+}
+
+/// The number of atoms of each non-empty type a backend holds.
+#[derive(Clone, Debug, Default)]
+struct Census(HashMap<u16, usize>);
+
+impl Census {
+    /// Counts `new` replacing `old` at a site.
+    fn replace(&mut self, old: Atom, new: Atom) {
+        let (from, to) = (old.get_type(), new.get_type());
+        if from == to {
+            return;
+        }
+        if from != 0 {
+            if let Some(n) = self.0.get_mut(&from) {
+                *n -= 1;
+                if *n == 0 {
+                    self.0.remove(&from);
+                }
+            }
+        }
+        if to != 0 {
+            *self.0.entry(to).or_insert(0) += 1;
+        }
+    }
+
+    fn count(&self, type_num: u16) -> usize {
+        self.0.get(&type_num).copied().unwrap_or(0)
+    }
+}
+
+/// Every site stored in one allocation.
+#[derive(Clone, Debug)]
+pub struct DenseBackend {
+    sites: Vec<Atom>,
+    paint: Vec<Color>,
+    /// Empty until a value is written to the layer.
+    layers: Vec<Vec<u32>>,
+    census: Census,
+}
+
+impl DenseBackend {
+    /// Storage for `n` sites.
+    pub fn new(n: usize) -> Self {
+        Self {
+            sites: vec![Atom::default(); n],
+            paint: vec![Color::new(); n],
+            layers: vec![Vec::new(); LAYERS],
+            census: Census::default(),
+        }
+    }
+}
+
+impl GridBackend for DenseBackend {
+    fn atom(&self, i: usize) -> Atom {
+        self.sites[i]
+    }
+
+    fn paint(&self, i: usize) -> Color {
+        self.paint[i]
+    }
+
+    fn layer(&self, l: usize, i: usize) -> u32 {
+        self.layers[l].get(i).copied().unwrap_or(0)
+    }
+
+    fn set_atom(&mut self, i: usize, atom: Atom) {
+        self.census.replace(self.sites[i], atom);
+        self.sites[i] = atom;
+    }
+
+    fn set_paint(&mut self, i: usize, paint: Color) {
+        self.paint[i] = paint;
+    }
+
+    fn set_layer(&mut self, l: usize, i: usize, value: u32) {
+        let layer = &mut self.layers[l];
+        if layer.is_empty() {
+            if value == 0 {
+                return;
+            }
+            layer.resize(self.sites.len(), 0);
+        }
+        layer[i] = value;
+    }
+
+    fn has_layers(&self) -> bool {
+        self.layers.iter().any(|l| !l.is_empty())
+    }
+
+    fn for_each_atom(&self, f: &mut dyn FnMut(usize, Atom)) {
+        for (i, atom) in self.sites.iter().enumerate() {
+            if !atom.is_empty() {
+                f(i, *atom);
+            }
+        }
+    }
+
+    fn count(&self, type_num: u16) -> Option<usize> {
+        Some(self.census.count(type_num))
+    }
+}
+
+/// The number of consecutive sites allocated together by `SparseBackend`.
+const CHUNK: usize = 64;
+
+#[derive(Clone, Debug)]
+struct Chunk {
+    sites: [Atom; CHUNK],
+    paint: [Color; CHUNK],
+    /// Allocated when a value is first written to any layer of the chunk.
+    layers: Option<Box<[[u32; CHUNK]; LAYERS]>>,
+}
+
+/// Sites allocated a chunk at a time, when first written with a non-empty
+/// atom, any paint or a layer value. Chunks are never freed, so a colony which wanders
+/// across the whole world ends up costing a little more than a dense one.
+#[derive(Clone, Debug)]
+pub struct SparseBackend {
+    chunks: Vec<Option<Box<Chunk>>>,
+    /// Whether any chunk has allocated its layers.
+    layered: bool,
+    census: Census,
+}
+
+impl SparseBackend {
+    /// Storage for `n` sites, none of them allocated.
+    pub fn new(n: usize) -> Self {
+        Self {
+            chunks: vec![None; n.div_ceil(CHUNK)],
+            layered: false,
+            census: Census::default(),
+        }
+    }
+
+    /// The number of sites allocated.
+    pub fn allocated(&self) -> usize {
+        self.chunks.iter().filter(|c| c.is_some()).count() * CHUNK
+    }
+
+    fn chunk_mut(&mut self, i: usize) -> &mut Chunk {
+        self.chunks[i / CHUNK].get_or_insert_with(|| {
+            Box::new(Chunk {
+                sites: [Atom::default(); CHUNK],
+                paint: [Color::new(); CHUNK],
+                layers: None,
+            })
+        })
+    }
+}
+
+impl GridBackend for SparseBackend {
+    fn atom(&self, i: usize) -> Atom {
+        match &self.chunks[i / CHUNK] {
+            Some(chunk) => chunk.sites[i % CHUNK],
+            None => Atom::default(),
+        }
+    }
+
+    fn paint(&self, i: usize) -> Color {
+        match &self.chunks[i / CHUNK] {
+            Some(chunk) => chunk.paint[i % CHUNK],
+            None => Color::new(),
+        }
+    }
+
+    fn layer(&self, l: usize, i: usize) -> u32 {
+        match self.chunks[i / CHUNK]
+            .as_ref()
+            .and_then(|c| c.layers.as_ref())
+        {
+            Some(layers) => layers[l][i % CHUNK],
+            None => 0,
+        }
+    }
+
+    fn set_atom(&mut self, i: usize, atom: Atom) {
+        if self.chunks[i / CHUNK].is_none() && atom.bits() == Atom::default().bits() {
+            return;
+        }
+        self.census.replace(self.atom(i), atom);
+        self.chunk_mut(i).sites[i % CHUNK] = atom;
+    }
+
+    fn set_paint(&mut self, i: usize, paint: Color) {
+        if self.chunks[i / CHUNK].is_none() && paint.bits() == 0 {
+            return;
+        }
+        self.chunk_mut(i).paint[i % CHUNK] = paint;
+    }
+
+    fn set_layer(&mut self, l: usize, i: usize, value: u32) {
+        if value == 0 && self.layer(l, i) == 0 {
+            return;
+        }
+        self.layered = true;
+        let layers = self
+            .chunk_mut(i)
+            .layers
+            .get_or_insert_with(|| Box::new([[0; CHUNK]; LAYERS]));
+        layers[l][i % CHUNK] = value;
+    }
+
+    fn has_layers(&self) -> bool {
+        self.layered
+    }
+
+    fn for_each_atom(&self, f: &mut dyn FnMut(usize, Atom)) {
+        for (c, chunk) in self.chunks.iter().enumerate() {
+            if let Some(chunk) = chunk {
+                for (i, atom) in chunk.sites.iter().enumerate() {
+                    if !atom.is_empty() {
+                        f(c * CHUNK + i, *atom);
+                    }
+                }
+            }
+        }
+    }
+
+    fn count(&self, type_num: u16) -> Option<usize> {
+        Some(self.census.count(type_num))
+    }
+}
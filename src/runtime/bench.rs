@@ -0,0 +1,186 @@
+//! Measuring how quickly a grid runs.
+//!
+//! A benchmark runs events at random sites as `Grid::step` does, timing each
+//! and charging it to the element of the atom it ran on, so that slow
+//! elements stand out. Events at empty sites are charged to Empty, including
+//! those skipped by active scheduling, which take no time.
+
+use crate::runtime::grid::Grid;
+use crate::runtime::{Error, Runtime};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// The events one element ran in a benchmark and the time they took.
+#[derive(Clone, Debug, Default)]
+pub struct ElementTiming {
+    pub events: u64,
+    pub instructions: u64,
+    pub elapsed: Duration,
+}
+
+impl ElementTiming {
+    /// The mean time of an event, or zero if none ran.
+    pub fn per_event(&self) -> Duration {
+        match self.events {
+            0 => Duration::default(),
+            n => Duration::from_nanos((self.elapsed.as_nanos() / n as u128) as u64),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Benchmark {
+    pub events: u64,
+    pub instructions: u64,
+    /// The time the whole run took.
+    pub elapsed: Duration,
+    /// Timings by type number.
+    pub elements: BTreeMap<u16, ElementTiming>,
+    names: BTreeMap<u16, String>,
+}
+
+/// Runs `events` events on `grid`, stopping at the first which fails.
+pub fn run(grid: &Grid, runtime: &mut Runtime, events: u64) -> Result<Benchmark, Error> {
+    let mut elements: BTreeMap<u16, ElementTiming> = BTreeMap::new();
+    let start = Instant::now();
+    let mut run = 0;
+    while run < events {
+        let (skipped, site) = grid.next_site(runtime, events - run)?;
+        run += skipped;
+        if skipped > 0 {
+            elements.entry(0).or_default().events += skipped;
+        }
+        let (x, y) = match site {
+            Some(site) => site,
+            None => continue,
+        };
+        run += 1;
+        let t = grid.get(x, y).unwrap().get_type();
+        let event_start = Instant::now();
+        grid.event_at(runtime, x, y)?;
+        let timing = elements.entry(t).or_default();
+        timing.elapsed += event_start.elapsed();
+        timing.events += 1;
+        timing.instructions += runtime.cursor().executed();
+    }
+    let elapsed = start.elapsed();
+    let names = elements
+        .keys()
+        .filter_map(|t| Some((*t, runtime.registry().name(*t)?.to_owned())))
+        .collect();
+    Ok(Benchmark {
+        events,
+        instructions: elements.values().map(|e| e.instructions).sum(),
+        elapsed,
+        elements,
+        names,
+    })
+}
+
+/// Writes `s` as a JSON string.
+pub(crate) fn json_string<W: Write>(w: &mut W, s: &str) -> io::Result<()> {
+    w.write_all(b"\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => w.write_all(b"\\\"")?,
+            '\\' => w.write_all(b"\\\\")?,
+            c if (c as u32) < 0x20 => write!(w, "\\u{:04x}", c as u32)?,
+            c => write!(w, "{}", c)?,
+        }
+    }
+    w.write_all(b"\"")
+}
+
+impl Benchmark {
+    pub fn events_per_second(&self) -> f64 {
+        per_second(self.events, self.elapsed)
+    }
+
+    pub fn instructions_per_second(&self) -> f64 {
+        per_second(self.instructions, self.elapsed)
+    }
+
+    /// The name of type `t`, if its element is loaded.
+    pub fn name(&self, t: u16) -> Option<&str> {
+        self.names.get(&t).map(String::as_str)
+    }
+
+    /// Writes the benchmark as a JSON object on one line, for tracking in
+    /// CI:
+    ///
+    /// ```text
+    /// {"events":1000000,"instructions":52113,"seconds":0.84,
+    ///  "events_per_second":1190476.2,"instructions_per_second":62039.3,
+    ///  "elements":[{"type":1,"name":"DReg","events":412,"instructions":52113,
+    ///  "seconds":0.001,"nanos_per_event":2427},...]}
+    /// ```
+    pub fn write_json<W: Write>(&self, mut w: W) -> io::Result<()> {
+        write!(
+            w,
+            "{{\"events\":{},\"instructions\":{},\"seconds\":{},\"events_per_second\":{},\"instructions_per_second\":{},\"elements\":[",
+            self.events,
+            self.instructions,
+            self.elapsed.as_secs_f64(),
+            self.events_per_second(),
+            self.instructions_per_second()
+        )?;
+        for (i, (t, e)) in self.elements.iter().enumerate() {
+            if i > 0 {
+                w.write_all(b",")?;
+            }
+            write!(w, "{{\"type\":{},\"name\":", t)?;
+            match self.name(*t) {
+                Some(name) => json_string(&mut w, name)?,
+                None => w.write_all(b"null")?,
+            }
+            write!(
+                w,
+                ",\"events\":{},\"instructions\":{},\"seconds\":{},\"nanos_per_event\":{}}}",
+                e.events,
+                e.instructions,
+                e.elapsed.as_secs_f64(),
+                e.per_event().as_nanos()
+            )?;
+        }
+        writeln!(w, "]}}")
+    }
+}
+
+fn per_second(n: u64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 {
+        n as f64 / secs
+    } else {
+        0.0
+    }
+}
+
+impl fmt::Display for Benchmark {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} events in {:.3}s: {:.0} events/s, {:.0} instructions/s",
+            self.events,
+            self.elapsed.as_secs_f64(),
+            self.events_per_second(),
+            self.instructions_per_second()
+        )?;
+        for (t, e) in &self.elements {
+            let name = match self.name(*t) {
+                Some(name) => name.to_owned(),
+                None => format!("type {}", t),
+            };
+            writeln!(
+                f,
+                "  {:<12} {:>10} events {:>12} instructions {:>8} ns/event",
+                name,
+                e.events,
+                e.instructions,
+                e.per_event().as_nanos()
+            )?;
+        }
+        Ok(())
+    }
+}
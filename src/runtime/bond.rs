@@ -0,0 +1,88 @@
+//! Bonds between atoms.
+//!
+//! A bond field, declared with `.bond NAME, POSITION`, holds the 6-bit site
+//! number of the atom's partner as seen from the atom, in the grid's own
+//! orientation rather than that of any event's symmetry, or 0, the atom's own
+//! site, for no bond. A bond reaches at most 4 sites, as far as an event
+//! window does from its center.
+//!
+//! `bond`, `unbond` and `followbond` write and follow bond fields through the
+//! event's symmetry. When `swapsites`, `movesite` or `diffuse` moves atoms,
+//! the bonds leading to and from them within the window are pointed at where
+//! their partners now are, and a bond stretched past 4 sites is broken. Atoms
+//! outside the window keep their bonds as they were, and a bond to an atom
+//! which is overwritten or erased leads to whatever takes its place.
+
+use super::Element;
+use crate::base::atom::Atom;
+use crate::base::{ecc, SiteNumber};
+use std::collections::HashMap;
+
+/// The coordinates of window site `i`, numbered in grid orientation.
+fn coord(i: usize) -> (i8, i8) {
+    SiteNumber(i as u8).coord().unwrap_or((0, 0))
+}
+
+/// The bond from window site `from` to window site `to`, both numbered in
+/// grid orientation, or `None` if they are more than 4 sites apart.
+pub fn offset(from: usize, to: usize) -> Option<u8> {
+    let ((fx, fy), (tx, ty)) = (coord(from), coord(to));
+    SiteNumber::from_coord(tx - fx, ty - fy).map(|s| s.0)
+}
+
+/// The window site, numbered in grid orientation, which the bond `bond` of
+/// the atom at window site `from` leads to, or `None` if there is no bond or
+/// its partner is outside the window.
+pub fn partner(from: usize, bond: u128) -> Option<usize> {
+    let (dx, dy) = match bond {
+        1..=40 => coord(bond as usize),
+        _ => return None,
+    };
+    let (x, y) = coord(from);
+    SiteNumber::from_coord(x + dx, y + dy).map(|s| s.0 as usize)
+}
+
+/// Points the bonds of the atoms in `atoms`, a window in grid orientation, at
+/// where their partners are after `moves`, the atoms just moved from one
+/// site to another. Changed atoms are encoded again if `verify_ecc`.
+pub(super) fn rebond(
+    elements: &HashMap<u16, Element>,
+    atoms: &mut [Atom; 41],
+    moves: &[(usize, usize)],
+    verify_ecc: bool,
+) {
+    let moved_to = |i: usize| moves.iter().find(|(from, _)| *from == i).map(|(_, to)| *to);
+    for (i, site) in atoms.iter_mut().enumerate() {
+        let bonds = match elements.get(&site.get_type()) {
+            Some(e) if !e.metadata.bonds.is_empty() => &e.metadata.bonds,
+            _ => continue,
+        };
+        let was = moves
+            .iter()
+            .find(|(_, to)| *to == i)
+            .map_or(i, |(from, _)| *from);
+        let (wx, wy) = coord(was);
+        let (ix, iy) = coord(i);
+        let mut atom = *site;
+        for f in bonds.iter() {
+            let (dx, dy) = match atom.get_field(*f).as_u128() {
+                b @ 1..=40 => coord(b as usize),
+                _ => continue,
+            };
+            // Where the partner was, which may be outside the window, and
+            // where it is now.
+            let (px, py) = (wx + dx, wy + dy);
+            let moved = SiteNumber::from_coord(px, py).and_then(|p| moved_to(p.0 as usize));
+            let (nx, ny) = match moved {
+                Some(p) => coord(p),
+                None if was == i => continue,
+                None => (px, py),
+            };
+            let bond = SiteNumber::from_coord(nx - ix, ny - iy).map_or(0, |s| s.0);
+            atom.set_header_field(*f, bond.into());
+        }
+        if atom != *site {
+            *site = if verify_ecc { ecc::encode(atom) } else { atom };
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Controlling a running engine from other threads.
+//!
+//! `Engine::run_events` holds the engine for as long as it runs, so a viewer
+//! or an RPC server on another thread steers it through a `RunControl`
+//! instead, taken from `Engine::control` before the run starts:
+//!
+//! ```text
+//! let control = engine.control();
+//! thread::spawn(move || {
+//!     control.set_target_aer(2.0);
+//!     control.pause();
+//!     control.step_events(100);
+//!     control.resume();
+//! });
+//! engine.run_events(u64::MAX)?;
+//! ```
+//!
+//! A paused run, or one ahead of its target rate, sleeps until it may go on
+//! rather than spinning.
+
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+struct State {
+    paused: bool,
+    /// The events a paused run may still count.
+    steps: u64,
+    /// The target AER, or 0 for none.
+    target_aer: f64,
+    /// When the run last started keeping to the target, and the events
+    /// counted then.
+    epoch: Option<(Instant, u64)>,
+}
+
+/// A handle pausing, resuming and pacing the runs of an `Engine`. Clones
+/// control the same engine.
+#[derive(Clone, Debug, Default)]
+pub struct RunControl {
+    shared: Arc<(Mutex<State>, Condvar)>,
+}
+
+impl RunControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.shared.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Changes the state and wakes any run waiting on it.
+    fn update<F: FnOnce(&mut State)>(&self, f: F) {
+        let mut state = self.state();
+        f(&mut state);
+        state.epoch = None;
+        self.shared.1.notify_all();
+    }
+
+    /// Stops runs before their next event until `resume` or `step_events`.
+    pub fn pause(&self) {
+        self.update(|s| s.paused = true);
+    }
+
+    /// Lets paused runs go on, dropping any steps not yet run.
+    pub fn resume(&self) {
+        self.update(|s| {
+            s.paused = false;
+            s.steps = 0;
+        });
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state().paused
+    }
+
+    /// Paces runs to `aer` AEPS per second of wall-clock time, sleeping
+    /// between events as needed; 0, a negative or a non-finite rate runs
+    /// flat out, the default. The pace is kept from the last change made
+    /// through any handle on.
+    pub fn set_target_aer(&self, aer: f64) {
+        self.update(|s| {
+            s.target_aer = if aer.is_finite() && aer > 0.0 {
+                aer
+            } else {
+                0.0
+            };
+        });
+    }
+
+    /// The target AER, or 0 for none.
+    pub fn target_aer(&self) -> f64 {
+        self.state().target_aer
+    }
+
+    /// Pauses runs once they have counted `n` more events, adding to any
+    /// steps not yet run if already paused.
+    pub fn step_events(&self, n: u64) {
+        self.update(|s| {
+            s.steps = if s.paused {
+                s.steps.saturating_add(n)
+            } else {
+                n
+            };
+            s.paused = true;
+        });
+    }
+
+    /// Blocks until a run on a grid of `sites` sites which has counted
+    /// `events` events may go on, giving the most events it may count next.
+    pub(crate) fn wait(&self, events: u64, sites: u64) -> u64 {
+        let (_, wake) = &*self.shared;
+        let mut state = self.state();
+        loop {
+            if state.paused && state.steps == 0 {
+                state = wake.wait(state).unwrap_or_else(|e| e.into_inner());
+                continue;
+            }
+            if state.target_aer > 0.0 {
+                let now = Instant::now();
+                let (start, base) = *state.epoch.get_or_insert((now, events));
+                let aeps = events.saturating_sub(base) as f64 / sites as f64;
+                let due = Duration::try_from_secs_f64(aeps / state.target_aer)
+                    .ok()
+                    .and_then(|d| start.checked_add(d));
+                match due {
+                    Some(due) if due <= now => {}
+                    Some(due) => {
+                        state = wake
+                            .wait_timeout(state, due - now)
+                            .unwrap_or_else(|e| e.into_inner())
+                            .0;
+                        continue;
+                    }
+                    None => {
+                        state = wake.wait(state).unwrap_or_else(|e| e.into_inner());
+                        continue;
+                    }
+                }
+            }
+            return if state.paused { state.steps } else { u64::MAX };
+        }
+    }
+
+    /// Notes that a run counted `n` events.
+    pub(crate) fn counted(&self, n: u64) {
+        let mut state = self.state();
+        if state.paused {
+            state.steps = state.steps.saturating_sub(n);
+        }
+    }
+}
@@ -0,0 +1,294 @@
+//! Reports of events which fail.
+//!
+//! A `CrashRecorder` set on a runtime with `Runtime::set_crash_recorder` keeps
+//! the last few instructions of every event, and when an event fails with an
+//! error which stops the run, rather than one the budget or assert policy
+//! deals with, keeps a `Crash`: the error, the instruction it failed at, the
+//! stack, calls and registers, the event window as the event found it and as
+//! it left it, the state of the random number generator as the event started
+//! and the instructions it ran last. Only the first crash is kept, as the
+//! one which stopped the run.
+//!
+//! `Crash::write` writes it as a self-contained text report, for attaching to
+//! a bug report:
+//!
+//! ```text
+//! error: bad site number 42 at instruction 10
+//! element: Crash (type 1)
+//! site: (32, 32), symmetry R000L
+//! instructions run: 33
+//! random seed: 3, state at the start of the event: a0352d44760ffa12 ...
+//!
+//! code:
+//!        8 push 40
+//!        9 add
+//! =>    10 getsite
+//!       11 pop
+//! ...
+//! ```
+//!
+//! Sites are window indexes in unmapped site order, as for `EventObserver`,
+//! with their offsets from the center in grid orientation.
+
+use crate::base::arith::Const;
+use crate::base::atom::Atom;
+use crate::base::{SiteNumber, Symmetries};
+use crate::disasm;
+use crate::runtime::mfm::EventWindow;
+use crate::runtime::rng::Prng;
+use crate::runtime::{Cursor, Error, Runtime};
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+/// The values shown of the top of the stack before each instruction.
+const STACK_SHOWN: usize = 4;
+
+/// One instruction run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    pub ip: usize,
+    /// The depth of the operand stack before the instruction.
+    pub depth: usize,
+    /// The topmost values of the stack before the instruction, topmost last.
+    pub top: Vec<Const>,
+}
+
+/// An event which failed.
+#[derive(Clone, Debug)]
+pub struct Crash {
+    pub error: String,
+    pub type_num: u16,
+    /// The site of the grid the event ran at, or `None` outside a grid.
+    pub origin: Option<(usize, usize)>,
+    pub symmetry: Symmetries,
+    /// The instruction which failed, or would have run next.
+    pub ip: usize,
+    pub executed: u64,
+    pub stack: Vec<Const>,
+    pub calls: Vec<usize>,
+    pub registers: Vec<Const>,
+    /// The window as the event found it.
+    pub before: [Atom; 41],
+    /// The window as the event left it.
+    pub after: [Atom; 41],
+    pub seed: u64,
+    /// The generator's state as the event started, before it drew its
+    /// symmetry.
+    pub rng_state: [u64; 4],
+    /// The last instructions the event ran, oldest first, ending with the
+    /// one which failed.
+    pub steps: Vec<Step>,
+}
+
+/// Keeps the first event which fails.
+#[derive(Debug)]
+pub struct CrashRecorder {
+    steps: usize,
+    crash: Mutex<Option<Crash>>,
+}
+
+impl CrashRecorder {
+    /// Keeps the last `steps` instructions of each event.
+    pub fn new(steps: usize) -> Self {
+        Self {
+            steps,
+            crash: Mutex::new(None),
+        }
+    }
+
+    /// Takes the crash recorded, if any event has failed.
+    pub fn take(&self) -> Option<Crash> {
+        self.crash.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+
+    /// Starts recording the event about to run in `ew`.
+    pub(crate) fn begin(
+        &self,
+        ew: &EventWindow,
+        rng: &Prng,
+        origin: Option<(usize, usize)>,
+    ) -> EventRecord {
+        EventRecord {
+            limit: self.steps,
+            origin,
+            before: *ew.atoms(),
+            seed: rng.seed(),
+            rng_state: rng.state(),
+            steps: VecDeque::with_capacity(self.steps),
+        }
+    }
+
+    /// Keeps the event recorded in `record`, which failed with `error`,
+    /// unless an earlier one was kept.
+    pub(crate) fn finish(
+        &self,
+        record: EventRecord,
+        cursor: &Cursor,
+        ew: &EventWindow,
+        symmetry: Symmetries,
+        error: &Error,
+    ) {
+        let mut crash = self.crash.lock().unwrap_or_else(|e| e.into_inner());
+        if crash.is_some() {
+            return;
+        }
+        *crash = Some(Crash {
+            error: error.to_string(),
+            type_num: record.before[0].get_type(),
+            origin: record.origin,
+            symmetry,
+            ip: cursor.ip(),
+            executed: cursor.executed(),
+            stack: cursor.op_stack().to_vec(),
+            calls: cursor.call_stack().to_vec(),
+            registers: cursor.registers().to_vec(),
+            before: record.before,
+            after: *ew.atoms(),
+            seed: record.seed,
+            rng_state: record.rng_state,
+            steps: record.steps.into_iter().collect(),
+        });
+    }
+}
+
+/// What is recorded of one event as it runs.
+pub(crate) struct EventRecord {
+    limit: usize,
+    origin: Option<(usize, usize)>,
+    before: [Atom; 41],
+    seed: u64,
+    rng_state: [u64; 4],
+    steps: VecDeque<Step>,
+}
+
+impl EventRecord {
+    /// Called before each instruction.
+    pub(crate) fn instruction(&mut self, cursor: &Cursor) {
+        if self.limit == 0 {
+            return;
+        }
+        if self.steps.len() == self.limit {
+            self.steps.pop_front();
+        }
+        let stack = cursor.op_stack();
+        self.steps.push_back(Step {
+            ip: cursor.ip(),
+            depth: stack.len(),
+            top: stack[stack.len().saturating_sub(STACK_SHOWN)..].to_vec(),
+        });
+    }
+}
+
+/// `values` as a list, noting how many more are below them.
+fn list(values: &[Const], below: usize) -> String {
+    let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+    match below {
+        0 => format!("[{}]", values.join(", ")),
+        n => format!("[{} more, {}]", n, values.join(", ")),
+    }
+}
+
+impl Crash {
+    /// The name of the element with type number `t` in `runtime`.
+    fn name(runtime: &Runtime, t: u16) -> String {
+        match runtime.metadata(t) {
+            Some(m) => m.name.clone(),
+            None => format!("type {}", t),
+        }
+    }
+
+    /// Instruction `ip` of the element with its labels, as in `12 loop: dup`.
+    fn line(&self, runtime: &Runtime, ip: usize) -> String {
+        let mut s = format!("{:>6} ", ip);
+        let labels = runtime.labels(self.type_num).unwrap_or_default();
+        for (name, _) in labels.iter().filter(|(_, i)| *i as usize == ip) {
+            s.push_str(name);
+            s.push_str(": ");
+        }
+        match disasm::instruction(runtime, self.type_num, ip) {
+            Some(text) => s.push_str(&text),
+            None => s.push_str("(end of code)"),
+        }
+        s
+    }
+
+    /// Writes the report, naming elements and disassembling the failed
+    /// element's code as they are loaded into `runtime`.
+    pub fn write<W: Write>(&self, w: &mut W, runtime: &Runtime) -> io::Result<()> {
+        writeln!(w, "error: {}", self.error)?;
+        writeln!(
+            w,
+            "element: {} (type {})",
+            Self::name(runtime, self.type_num),
+            self.type_num
+        )?;
+        match self.origin {
+            Some((x, y)) => write!(w, "site: ({}, {})", x, y)?,
+            None => write!(w, "site: outside a grid")?,
+        }
+        writeln!(w, ", symmetry {}", self.symmetry)?;
+        writeln!(w, "instructions run: {}", self.executed)?;
+        let state: Vec<String> = self
+            .rng_state
+            .iter()
+            .map(|s| format!("{:016x}", s))
+            .collect();
+        writeln!(
+            w,
+            "random seed: {}, state at the start of the event: {}",
+            self.seed,
+            state.join(" ")
+        )?;
+        writeln!(w, "engine version: {}", env!("CARGO_PKG_VERSION"))?;
+
+        writeln!(w, "\ncode:")?;
+        let len = runtime.code(self.type_num).map_or(0, |c| c.len());
+        for ip in 0..len.max(self.ip + 1) {
+            let mark = if ip == self.ip { "=>" } else { "  " };
+            writeln!(w, "{}{}", mark, self.line(runtime, ip))?;
+        }
+
+        writeln!(w, "\nstack, bottom first: {}", list(&self.stack, 0))?;
+        if !self.calls.is_empty() {
+            let calls: Vec<String> = self.calls.iter().map(|c| c.to_string()).collect();
+            writeln!(w, "calls from: {}", calls.join(", "))?;
+        }
+        let registers: Vec<String> = self
+            .registers
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| !v.is_zero())
+            .map(|(i, v)| format!("R{} {}", i, v))
+            .collect();
+        if !registers.is_empty() {
+            writeln!(w, "registers not 0: {}", registers.join(", "))?;
+        }
+
+        writeln!(w, "\nwindow, as found and as left:")?;
+        for (i, (old, new)) in self.before.iter().zip(self.after.iter()).enumerate() {
+            if old.is_empty() && new.is_empty() {
+                continue;
+            }
+            let (dx, dy) = SiteNumber(i as u8).coord().unwrap_or((0, 0));
+            let atom = |a: &Atom| format!("{} {}", Self::name(runtime, a.get_type()), a);
+            write!(w, "  {:>2} ({:>2}, {:>2})  {}", i, dx, dy, atom(old))?;
+            if old != new {
+                write!(w, "  ->  {}", atom(new))?;
+            }
+            writeln!(w)?;
+        }
+
+        writeln!(
+            w,
+            "\nlast {} instructions, with the stack before each:",
+            self.steps.len()
+        )?;
+        for s in self.steps.iter() {
+            let line = self.line(runtime, s.ip);
+            let top = list(&s.top, s.depth - s.top.len());
+            writeln!(w, "  {:<40} {}", line, top)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+//! Comparing grids.
+//!
+//! `diff` compares two grids of the same size site by site, such as the
+//! grids two runs which should have gone the same way leave, and lists the
+//! sites whose atoms differ. Sites whose atoms are the same but whose paint
+//! or layers differ are only counted.
+//!
+//! `GridDiff::write` reports the differences grouped by element, each site
+//! under every element it holds in either grid:
+//!
+//! ```text
+//! 7 of 4096 sites differ, after 100000 and 100000 events
+//! Sand (type 2): 5 differing, 2 only in the first grid, 1 only in the second, 2 changed
+//!   (12, 40)  Sand %2(0x14)  ->  Empty %0(0x0)
+//!   (13, 40)  Empty %0(0x0)  ->  Sand %2(0x14)
+//! ...
+//! ```
+//!
+//! `render::render_diff` draws them as an image.
+
+use crate::base::atom::Atom;
+use crate::runtime::grid::{Grid, LAYERS};
+use crate::runtime::{Error, Runtime};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+/// A site whose atom differs between two grids.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SiteDiff {
+    pub x: usize,
+    pub y: usize,
+    /// The atom in the first grid.
+    pub before: Atom,
+    /// The atom in the second grid.
+    pub after: Atom,
+}
+
+impl SiteDiff {
+    /// The types the site holds in either grid, other than empty.
+    pub fn types(&self) -> Vec<u16> {
+        let mut types = Vec::new();
+        for a in [self.before, self.after].iter() {
+            if !a.is_empty() && !types.contains(&a.get_type()) {
+                types.push(a.get_type());
+            }
+        }
+        types
+    }
+}
+
+/// The sites of a diff holding one element in either grid.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ElementDiff {
+    pub type_num: u16,
+    /// Sites holding the element in the first grid but not the second.
+    pub removed: usize,
+    /// Sites holding the element in the second grid but not the first.
+    pub added: usize,
+    /// Sites holding the element in both grids, with other fields.
+    pub changed: usize,
+    /// The indexes of the sites in `GridDiff::sites`.
+    pub sites: Vec<usize>,
+}
+
+/// How two grids differ.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GridDiff {
+    pub width: usize,
+    pub height: usize,
+    /// The events each grid had run.
+    pub events: (u64, u64),
+    /// The sites whose atoms differ, row by row.
+    pub sites: Vec<SiteDiff>,
+    /// The number of sites whose atoms are the same but whose paint or
+    /// layers differ.
+    pub other: usize,
+}
+
+/// Compares `first` and `second` site by site.
+pub fn diff(first: &Grid, second: &Grid) -> Result<GridDiff, Error> {
+    if first.width() != second.width() || first.height() != second.height() {
+        return Err(Error::GridSizeMismatch(
+            first.width(),
+            first.height(),
+            second.width(),
+            second.height(),
+        ));
+    }
+    let mut sites = Vec::new();
+    let mut other = 0;
+    for y in 0..first.height() {
+        for x in 0..first.width() {
+            let (before, after) = (first.get(x, y).unwrap(), second.get(x, y).unwrap());
+            if before != after {
+                sites.push(SiteDiff {
+                    x,
+                    y,
+                    before,
+                    after,
+                });
+            } else if first.get_paint(x, y).map(|c| c.bits())
+                != second.get_paint(x, y).map(|c| c.bits())
+                || (0..LAYERS).any(|l| first.get_layer(l, x, y) != second.get_layer(l, x, y))
+            {
+                other += 1;
+            }
+        }
+    }
+    Ok(GridDiff {
+        width: first.width(),
+        height: first.height(),
+        events: (first.event_count(), second.event_count()),
+        sites,
+        other,
+    })
+}
+
+impl GridDiff {
+    /// Whether the grids are the same, paint and layers included.
+    pub fn is_empty(&self) -> bool {
+        self.sites.is_empty() && self.other == 0
+    }
+
+    /// The sites which differ grouped by element, those with the most sites
+    /// first.
+    pub fn by_element(&self) -> Vec<ElementDiff> {
+        let mut elements: BTreeMap<u16, ElementDiff> = BTreeMap::new();
+        for (i, site) in self.sites.iter().enumerate() {
+            for t in site.types() {
+                let e = elements.entry(t).or_insert_with(|| ElementDiff {
+                    type_num: t,
+                    removed: 0,
+                    added: 0,
+                    changed: 0,
+                    sites: Vec::new(),
+                });
+                let (was, is) = (
+                    !site.before.is_empty() && site.before.get_type() == t,
+                    !site.after.is_empty() && site.after.get_type() == t,
+                );
+                match (was, is) {
+                    (true, false) => e.removed += 1,
+                    (false, true) => e.added += 1,
+                    _ => e.changed += 1,
+                }
+                e.sites.push(i);
+            }
+        }
+        let mut elements: Vec<ElementDiff> = elements.into_values().collect();
+        elements.sort_by_key(|e| std::cmp::Reverse(e.sites.len()));
+        elements
+    }
+
+    /// Writes a report of the differences, naming elements as they are
+    /// loaded into `runtime` and listing at most `limit` sites of each.
+    pub fn write<W: Write>(&self, w: &mut W, runtime: &Runtime, limit: usize) -> io::Result<()> {
+        let element = |t: u16| match runtime.metadata(t) {
+            Some(m) => format!("{} (type {})", m.name, t),
+            None => format!("type {}", t),
+        };
+        let atom = |a: &Atom| match runtime.metadata(a.get_type()) {
+            Some(m) => format!("{} {}", m.name, a),
+            None => a.to_string(),
+        };
+        if self.is_empty() {
+            return writeln!(
+                w,
+                "the grids are the same, after {} and {} events",
+                self.events.0, self.events.1
+            );
+        }
+        writeln!(
+            w,
+            "{} of {} sites differ, after {} and {} events",
+            self.sites.len(),
+            self.width * self.height,
+            self.events.0,
+            self.events.1
+        )?;
+        for e in self.by_element() {
+            writeln!(
+                w,
+                "{}: {} differing, {} only in the first grid, {} only in the second, {} changed",
+                element(e.type_num),
+                e.sites.len(),
+                e.removed,
+                e.added,
+                e.changed
+            )?;
+            for s in e.sites.iter().take(limit).map(|&i| &self.sites[i]) {
+                writeln!(
+                    w,
+                    "  ({}, {})  {}  ->  {}",
+                    s.x,
+                    s.y,
+                    atom(&s.before),
+                    atom(&s.after)
+                )?;
+            }
+            if e.sites.len() > limit {
+                writeln!(w, "  and {} more", e.sites.len() - limit)?;
+            }
+        }
+        if self.other > 0 {
+            writeln!(
+                w,
+                "{} more sites differ only in paint or layers",
+                self.other
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+//! An optional model of the energy events spend.
+//!
+//! Computing is not free: under an `EnergyModel` each event of an atom gives
+//! off heat at its site, more for each instruction it runs. A site's heat is
+//! its temperature, kept in one of the grid's layers so it is saved with
+//! snapshots and can be drawn with `ewar --overlay`. Heat leaks away from the
+//! window of every event, and a hot site runs fewer of the events chosen
+//! there, so a busy region slows down until it has cooled. Elements can also
+//! read, emit and absorb heat themselves with `getheat`, `emitheat` and
+//! `absorbheat`.
+
+use crate::runtime::grid::LAYERS;
+use crate::runtime::mfm::EventWindow;
+use rand::Rng;
+
+/// The knobs of the energy model; see the module docs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EnergyModel {
+    /// The layer holding each site's heat, less than `LAYERS`.
+    pub layer: usize,
+    /// Heat given off at its site by each event of an atom.
+    pub event_heat: u32,
+    /// Heat given off by each instruction an event runs, on top of
+    /// `event_heat`.
+    pub instruction_heat: u32,
+    /// How fast heat leaks away: before each event, the heat of every site of
+    /// its window falls as `decaylayer` with this shift lowers it.
+    pub cooling: u32,
+    /// The heat at which a site runs half the events chosen there. A site
+    /// holding heat `h` runs them with a chance of `throttle / (throttle + h)`,
+    /// and 0 never throttles.
+    pub throttle: u32,
+}
+
+impl Default for EnergyModel {
+    fn default() -> Self {
+        Self {
+            layer: LAYERS - 1,
+            event_heat: 16,
+            instruction_heat: 1,
+            cooling: 4,
+            throttle: 1024,
+        }
+    }
+}
+
+impl EnergyModel {
+    /// Lets heat leak from every site of `ew`.
+    pub fn cool(&self, ew: &mut EventWindow) {
+        ew.decay_layer(self.layer, self.cooling);
+    }
+
+    /// Whether an event at a site holding `heat` runs, drawing from `rng`
+    /// only if the site is throttled at all.
+    pub fn runs<R: Rng + ?Sized>(&self, heat: u32, rng: &mut R) -> bool {
+        if self.throttle == 0 || heat == 0 {
+            return true;
+        }
+        let throttle = self.throttle as u64;
+        rng.gen_range(0..throttle + heat as u64) < throttle
+    }
+
+    /// The heat given off by an event which ran `instructions` instructions.
+    pub fn heat(&self, instructions: u64) -> u32 {
+        let heat = (self.instruction_heat as u64).saturating_mul(instructions);
+        heat.saturating_add(self.event_heat as u64)
+            .min(u32::MAX as u64) as u32
+    }
+}
@@ -0,0 +1,227 @@
+//! Serving statistics to Prometheus, under the `metrics-http` feature.
+//!
+//! An `Exporter` listens for HTTP requests on a thread of its own and answers
+//! `GET /metrics` with the last `Report` published to it, in Prometheus'
+//! text format:
+//!
+//! ```text
+//! let exporter = Exporter::serve("127.0.0.1:9100")?;
+//! loop {
+//!     engine.run_events(100_000)?;
+//!     exporter.publish(&engine.stats());
+//! }
+//! ```
+//!
+//! Publishing takes a census, so a long run publishes every so often rather
+//! than after every event; scrapes in between see the last report. It serves
+//! one request at a time, which suits a dashboard scraping every few
+//! seconds.
+
+use crate::runtime::stats::Report;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a connection may take to send its request.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `s` as the value of a label, escaped.
+fn label(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Writes a metric's help and type lines followed by its samples, each a set
+/// of labels and a value.
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(String, String)]) {
+    writeln!(out, "# HELP substrate_{} {}", name, help).unwrap();
+    writeln!(out, "# TYPE substrate_{} {}", name, kind).unwrap();
+    for (labels, value) in samples.iter() {
+        writeln!(out, "substrate_{}{} {}", name, labels, value).unwrap();
+    }
+}
+
+/// `report` in Prometheus' text format. `uptime` is how long the run has
+/// gone on, of which the events took `report.elapsed`.
+pub fn render(report: &Report, uptime: Duration) -> String {
+    let one = |v: String| vec![(String::new(), v)];
+    let mut out = String::new();
+    let stats = &report.stats;
+    metric(
+        &mut out,
+        "events_total",
+        "counter",
+        "Events run.",
+        &one(stats.events.to_string()),
+    );
+    metric(
+        &mut out,
+        "instructions_total",
+        "counter",
+        "Instructions run by every event.",
+        &one(stats.instructions.to_string()),
+    );
+    metric(
+        &mut out,
+        "aeps",
+        "gauge",
+        "Average events per site.",
+        &one(report.aeps().to_string()),
+    );
+    metric(
+        &mut out,
+        "aer",
+        "gauge",
+        "Average events per site per second of running time.",
+        &one(report.aer().to_string()),
+    );
+    metric(
+        &mut out,
+        "sites",
+        "gauge",
+        "Sites of the grid.",
+        &one(report.sites.to_string()),
+    );
+    let atoms: Vec<(String, String)> = report
+        .census
+        .iter()
+        .filter(|(t, _)| **t != 0)
+        .map(|(t, n)| {
+            let name = match report.name(*t) {
+                Some(name) => label(name),
+                None => format!("type {}", t),
+            };
+            (format!("{{element=\"{}\"}}", name), n.to_string())
+        })
+        .collect();
+    metric(&mut out, "atoms", "gauge", "Atoms of each element.", &atoms);
+    let errors = [
+        ("budget_exceeded", stats.budget_exceeded),
+        ("assertion_failed", stats.assertions_failed),
+        ("throttled", stats.throttled),
+    ];
+    let errors: Vec<(String, String)> = errors
+        .iter()
+        .map(|(kind, n)| (format!("{{kind=\"{}\"}}", kind), n.to_string()))
+        .collect();
+    metric(
+        &mut out,
+        "event_errors_total",
+        "counter",
+        "Events stopped for running out of budget or failing an assert, and events throttled by heat.",
+        &errors,
+    );
+    let counters: Vec<(String, String)> = report
+        .counters
+        .iter()
+        .enumerate()
+        .map(|(c, n)| (format!("{{counter=\"{}\"}}", c), n.to_string()))
+        .collect();
+    metric(
+        &mut out,
+        "counter_total",
+        "counter",
+        "Counters added to by the count instruction.",
+        &counters,
+    );
+    metric(
+        &mut out,
+        "busy_seconds_total",
+        "counter",
+        "Running time the events took.",
+        &one(report.elapsed.as_secs_f64().to_string()),
+    );
+    metric(
+        &mut out,
+        "uptime_seconds",
+        "gauge",
+        "Time since the run started.",
+        &one(uptime.as_secs_f64().to_string()),
+    );
+    let utilization = match uptime.as_secs_f64() {
+        secs if secs > 0.0 => (report.elapsed.as_secs_f64() / secs).min(1.0),
+        _ => 0.0,
+    };
+    metric(
+        &mut out,
+        "utilization",
+        "gauge",
+        "Share of the time since the run started spent running events.",
+        &one(utilization.to_string()),
+    );
+    out
+}
+
+/// Answers one request with `body` if it asks for `/metrics`.
+fn respond(stream: TcpStream, body: &str) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // Read the headers through, so the client sees its request taken.
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", body),
+        (Some("GET"), _) => ("404 Not Found", "not found\n"),
+        _ => ("405 Method Not Allowed", "only GET is served\n"),
+    };
+    let mut stream = reader.into_inner();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Serves the last report published to it at `/metrics`.
+#[derive(Debug)]
+pub struct Exporter {
+    addr: SocketAddr,
+    started: Instant,
+    latest: Arc<Mutex<String>>,
+}
+
+impl Exporter {
+    /// Listens on `addr` on a new thread, serving no metrics until the first
+    /// report is published. The run is taken to start now.
+    pub fn serve<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let latest = Arc::new(Mutex::new(String::new()));
+        let shared = latest.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let body = shared.lock().unwrap_or_else(|e| e.into_inner()).clone();
+                // A client which goes away only loses its own answer.
+                let _ = stream.and_then(|s| respond(s, &body));
+            }
+        });
+        Ok(Self {
+            addr,
+            started: Instant::now(),
+            latest,
+        })
+    }
+
+    /// The address listened on, with the port picked if it was given as 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Serves `report` from now on.
+    pub fn publish(&self, report: &Report) {
+        let text = render(report, self.started.elapsed());
+        *self.latest.lock().unwrap_or_else(|e| e.into_inner()) = text;
+    }
+}
@@ -0,0 +1,962 @@
+use crate::base::atom::Atom;
+use crate::base::color::Color;
+use crate::base::SiteNumber;
+use crate::logging::{self, EventSpan};
+use crate::runtime::backend::{DenseBackend, GridBackend, SparseBackend};
+use crate::runtime::invariant::Violation;
+use crate::runtime::mfm::EventWindow;
+use crate::runtime::rng;
+use crate::runtime::schedule::{ActiveSites, Scheduler, Scheduling};
+use crate::runtime::{AssertPolicy, BudgetPolicy, Error, Runtime, StepHook};
+use rand::Rng;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+
+/// The radius of an event window, and so the width of each tile's cache.
+pub const RADIUS: usize = 4;
+
+/// The number of layers of each site: 32-bit values kept apart from its atom
+/// and paint, such as a pheromone or a temperature, numbered from 0.
+pub const LAYERS: usize = 4;
+
+/// Sites owned by a tile plus its cache of the sites within `RADIUS` of its
+/// border, which are owned by neighboring tiles.
+type TileData = Box<dyn GridBackend>;
+
+#[derive(Debug)]
+struct Tile {
+    origin: (usize, usize),
+    data: Mutex<TileData>,
+}
+
+/// The tiles held by one event. Every tile owning or caching a site of the
+/// window is locked so that the event and its cache updates are atomic.
+struct WindowLock<'a> {
+    guards: Vec<(usize, MutexGuard<'a, TileData>)>,
+}
+
+impl WindowLock<'_> {
+    fn tile(&mut self, t: usize) -> &mut TileData {
+        let i = self.guards.iter().position(|(i, _)| *i == t).unwrap();
+        &mut self.guards[i].1
+    }
+
+    /// Whether any of the tiles may hold layer values.
+    fn has_layers(&self) -> bool {
+        self.guards.iter().any(|(_, data)| data.has_layers())
+    }
+}
+
+/// What events see past the edges of a grid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Boundary {
+    /// Sites outside the grid read as empty and writes to them are dropped.
+    /// The default.
+    Void,
+    /// Sites outside the grid read as the grid's wall atom, set with
+    /// `Grid::set_wall`, and writes to them are dropped.
+    Wall,
+    /// The grid wraps around at its edges, as a torus. A window wider than
+    /// the grid sees some sites twice; the copy written last is kept.
+    Torus,
+}
+
+impl std::str::FromStr for Boundary {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "void" => Ok(Self::Void),
+            "wall" => Ok(Self::Wall),
+            "torus" => Ok(Self::Torus),
+            _ => Err(format!("unknown boundary: {}", s)),
+        }
+    }
+}
+
+/// A world made of equally sized tiles. What events see past its edges is
+/// set by its `Boundary`.
+///
+/// Tiles are individually locked, so events in different tiles may run on
+/// different threads; see `run_tiles`. Each tile stores its sites in a
+/// `GridBackend`: dense by default, or sparse for huge, mostly empty worlds.
+#[derive(Debug)]
+pub struct Grid {
+    tiles_x: usize,
+    tiles_y: usize,
+    tile_width: usize,
+    tile_height: usize,
+    tiles: Vec<Tile>,
+    events: AtomicU64,
+    budget_exceeded: AtomicU64,
+    assertions_failed: AtomicU64,
+    throttled: AtomicU64,
+    instructions: AtomicU64,
+    /// The built-in scheduling last set with `set_scheduling`.
+    scheduling: Scheduling,
+    scheduler: Mutex<Box<dyn Scheduler>>,
+    /// Whether `active` is kept up to date, as the scheduler needs.
+    tracks_active: bool,
+    /// Whether the scheduler is told of the events run.
+    observes_events: bool,
+    active: Mutex<ActiveSites>,
+    boundary: Boundary,
+    wall: Atom,
+}
+
+/// Counters kept by a grid as it runs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub events: u64,
+    /// Events stopped for running out of instruction budget, whatever the
+    /// budget policy did with them.
+    pub budget_exceeded: u64,
+    /// `assert` instructions which failed, whatever the assert policy did
+    /// with their events.
+    pub assertions_failed: u64,
+    /// Events which didn't run for their site being too hot, under the
+    /// runtime's `EnergyModel`. They count as events.
+    pub throttled: u64,
+    /// Instructions run by every event, including those which failed or
+    /// were discarded.
+    pub instructions: u64,
+}
+
+impl Tile {
+    fn new(origin: (usize, usize), data: TileData) -> Self {
+        Self {
+            origin,
+            data: Mutex::new(data),
+        }
+    }
+
+    fn data(&self) -> MutexGuard<'_, TileData> {
+        self.data.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn data_mut(&mut self) -> &mut TileData {
+        self.data.get_mut().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Grid {
+    /// Creates an empty grid of `tiles_x` by `tiles_y` tiles, each owning
+    /// `tile_width` by `tile_height` sites.
+    pub fn new(tiles_x: usize, tiles_y: usize, tile_width: usize, tile_height: usize) -> Self {
+        Self::with_backend(tiles_x, tiles_y, tile_width, tile_height, |n| {
+            Box::new(DenseBackend::new(n))
+        })
+    }
+
+    /// Creates an empty grid like `new` whose sites are allocated only as
+    /// they are written; see `SparseBackend`.
+    pub fn new_sparse(
+        tiles_x: usize,
+        tiles_y: usize,
+        tile_width: usize,
+        tile_height: usize,
+    ) -> Self {
+        Self::with_backend(tiles_x, tiles_y, tile_width, tile_height, |n| {
+            Box::new(SparseBackend::new(n))
+        })
+    }
+
+    /// Creates an empty grid like `new`, storing each tile's sites in the
+    /// backend made by `backend` for the number of sites it holds.
+    pub fn with_backend<F: Fn(usize) -> Box<dyn GridBackend>>(
+        tiles_x: usize,
+        tiles_y: usize,
+        tile_width: usize,
+        tile_height: usize,
+        backend: F,
+    ) -> Self {
+        assert!(tiles_x > 0 && tiles_y > 0, "grid has no tiles");
+        assert!(tile_width > 0 && tile_height > 0, "tiles have no sites");
+        let n = (tile_width + 2 * RADIUS) * (tile_height + 2 * RADIUS);
+        let mut tiles = Vec::with_capacity(tiles_x * tiles_y);
+        for ty in 0..tiles_y {
+            for tx in 0..tiles_x {
+                let origin = (tx * tile_width, ty * tile_height);
+                tiles.push(Tile::new(origin, backend(n)));
+            }
+        }
+        Self {
+            tiles_x,
+            tiles_y,
+            tile_width,
+            tile_height,
+            tiles,
+            events: AtomicU64::new(0),
+            budget_exceeded: AtomicU64::new(0),
+            assertions_failed: AtomicU64::new(0),
+            throttled: AtomicU64::new(0),
+            instructions: AtomicU64::new(0),
+            scheduling: Scheduling::Uniform,
+            scheduler: Mutex::new(Scheduling::Uniform.scheduler()),
+            tracks_active: false,
+            observes_events: false,
+            active: Mutex::new(ActiveSites::default()),
+            boundary: Boundary::Void,
+            wall: Atom::default(),
+        }
+    }
+
+    pub fn tiles_x(&self) -> usize {
+        self.tiles_x
+    }
+
+    pub fn tiles_y(&self) -> usize {
+        self.tiles_y
+    }
+
+    pub fn tile_width(&self) -> usize {
+        self.tile_width
+    }
+
+    pub fn tile_height(&self) -> usize {
+        self.tile_height
+    }
+
+    pub fn width(&self) -> usize {
+        self.tiles_x * self.tile_width
+    }
+
+    pub fn height(&self) -> usize {
+        self.tiles_y * self.tile_height
+    }
+
+    pub fn tile_count(&self) -> usize {
+        self.tiles.len()
+    }
+
+    /// The number of events which have completed on this grid.
+    pub fn event_count(&self) -> u64 {
+        self.events.load(Ordering::Relaxed)
+    }
+
+    pub fn set_event_count(&mut self, n: u64) {
+        *self.events.get_mut() = n;
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            events: self.event_count(),
+            budget_exceeded: self.budget_exceeded.load(Ordering::Relaxed),
+            assertions_failed: self.assertions_failed.load(Ordering::Relaxed),
+            throttled: self.throttled.load(Ordering::Relaxed),
+            instructions: self.instructions.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn scheduling(&self) -> Scheduling {
+        self.scheduling
+    }
+
+    /// Sets how `step` and `step_at_most` pick sites to a built-in
+    /// scheduling.
+    pub fn set_scheduling(&mut self, scheduling: Scheduling) {
+        self.set_scheduler(scheduling.scheduler());
+        self.scheduling = scheduling;
+    }
+
+    /// Sets the scheduler `step` and `step_at_most` pick sites with,
+    /// gathering the sites holding atoms if it needs them.
+    pub fn set_scheduler(&mut self, scheduler: Box<dyn Scheduler>) {
+        self.tracks_active = scheduler.needs_active_sites();
+        self.observes_events = scheduler.observes_events();
+        let mut active = ActiveSites::default();
+        if self.tracks_active {
+            let width = self.width();
+            self.for_each_atom(|x, y, _| active.update(y * width + x, true));
+        }
+        log::debug!(
+            target: "substrate::schedule",
+            "scheduler set, tracking {} active sites",
+            active.len()
+        );
+        *self.active.get_mut().unwrap_or_else(|e| e.into_inner()) = active;
+        *self.scheduler.get_mut().unwrap_or_else(|e| e.into_inner()) = scheduler;
+    }
+
+    pub fn boundary(&self) -> Boundary {
+        self.boundary
+    }
+
+    pub fn set_boundary(&mut self, boundary: Boundary) {
+        self.boundary = boundary;
+    }
+
+    /// The atom sites outside the grid read as under `Boundary::Wall`.
+    pub fn wall(&self) -> Atom {
+        self.wall
+    }
+
+    pub fn set_wall(&mut self, wall: Atom) {
+        self.wall = wall;
+    }
+
+    /// The sites holding atoms, kept only while the scheduler needs them and
+    /// empty otherwise.
+    pub fn active_sites(&self) -> MutexGuard<'_, ActiveSites> {
+        self.active.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn in_bounds(&self, x: isize, y: isize) -> bool {
+        x >= 0 && y >= 0 && (x as usize) < self.width() && (y as usize) < self.height()
+    }
+
+    /// The site of the grid that window coordinates `(x, y)` stand for: the
+    /// same site if it is in the grid, the site they wrap around to on a
+    /// torus, and otherwise none.
+    fn resolve(&self, x: isize, y: isize) -> Option<(isize, isize)> {
+        if self.in_bounds(x, y) {
+            return Some((x, y));
+        }
+        match self.boundary {
+            Boundary::Torus => Some((
+                x.rem_euclid(self.width() as isize),
+                y.rem_euclid(self.height() as isize),
+            )),
+            Boundary::Void | Boundary::Wall => None,
+        }
+    }
+
+    /// The index of the tile owning site `(x, y)`.
+    pub fn tile_of(&self, x: usize, y: usize) -> usize {
+        (y / self.tile_height) * self.tiles_x + x / self.tile_width
+    }
+
+    /// The index of site `(x, y)` in tile `t`'s storage, if it is owned or
+    /// cached by that tile.
+    fn local_index(&self, t: usize, x: isize, y: isize) -> Option<usize> {
+        let (ox, oy) = self.tiles[t].origin;
+        let lx = x + RADIUS as isize - ox as isize;
+        let ly = y + RADIUS as isize - oy as isize;
+        let w = self.tile_width + 2 * RADIUS;
+        let h = self.tile_height + 2 * RADIUS;
+        if lx < 0 || ly < 0 || lx as usize >= w || ly as usize >= h {
+            return None;
+        }
+        Some(ly as usize * w + lx as usize)
+    }
+
+    /// The tiles which own or cache site `(x, y)`.
+    fn tiles_containing(&self, x: usize, y: usize) -> impl Iterator<Item = usize> + '_ {
+        let tx = x / self.tile_width;
+        let ty = y / self.tile_height;
+        let xs = tx.saturating_sub(1)..(tx + 2).min(self.tiles_x);
+        let ys = ty.saturating_sub(1)..(ty + 2).min(self.tiles_y);
+        ys.flat_map(move |j| xs.clone().map(move |i| j * self.tiles_x + i))
+            .filter(move |t| self.local_index(*t, x as isize, y as isize).is_some())
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<Atom> {
+        if !self.in_bounds(x as isize, y as isize) {
+            return None;
+        }
+        let t = self.tile_of(x, y);
+        let i = self.local_index(t, x as isize, y as isize)?;
+        Some(self.tiles[t].data().atom(i))
+    }
+
+    pub fn get_paint(&self, x: usize, y: usize) -> Option<Color> {
+        if !self.in_bounds(x as isize, y as isize) {
+            return None;
+        }
+        let t = self.tile_of(x, y);
+        let i = self.local_index(t, x as isize, y as isize)?;
+        Some(self.tiles[t].data().paint(i))
+    }
+
+    /// The value of layer `l` at site `(x, y)`, or `None` if there is no
+    /// such site or layer.
+    pub fn get_layer(&self, l: usize, x: usize, y: usize) -> Option<u32> {
+        if l >= LAYERS || !self.in_bounds(x as isize, y as isize) {
+            return None;
+        }
+        let t = self.tile_of(x, y);
+        let i = self.local_index(t, x as isize, y as isize)?;
+        Some(self.tiles[t].data().layer(l, i))
+    }
+
+    /// Calls `f` with every site owned by `tile` holding a non-empty atom.
+    fn for_each_atom_in<F: FnMut(usize, usize, Atom)>(
+        &self,
+        tile: &Tile,
+        data: &TileData,
+        f: &mut F,
+    ) {
+        let w = self.tile_width + 2 * RADIUS;
+        let (ox, oy) = tile.origin;
+        data.for_each_atom(&mut |i, atom| {
+            // Skip the cache; its sites are owned by other tiles.
+            let (lx, ly) = (i % w, i / w);
+            if (RADIUS..RADIUS + self.tile_width).contains(&lx)
+                && (RADIUS..RADIUS + self.tile_height).contains(&ly)
+            {
+                f(ox + lx - RADIUS, oy + ly - RADIUS, atom);
+            }
+        });
+    }
+
+    /// Calls `f` with every site holding a non-empty atom, tile by tile.
+    /// Regions a sparse grid never allocated are skipped.
+    pub fn for_each_atom<F: FnMut(usize, usize, Atom)>(&self, mut f: F) {
+        for tile in &self.tiles {
+            self.for_each_atom_in(tile, &tile.data(), &mut f);
+        }
+    }
+
+    /// The sites holding atoms of type `type_num`, or of any non-empty type
+    /// if `None`, for which `f` holds, tile by tile. Tiles whose backend
+    /// counts no atoms of the type are skipped without looking at their
+    /// sites, so finding a rare element in a large grid is cheap.
+    pub fn find<F: FnMut(usize, usize, Atom) -> bool>(
+        &self,
+        type_num: Option<u16>,
+        mut f: F,
+    ) -> Vec<(usize, usize)> {
+        let mut found = Vec::new();
+        for tile in &self.tiles {
+            let data = tile.data();
+            if matches!(type_num.and_then(|t| data.count(t)), Some(0)) {
+                continue;
+            }
+            self.for_each_atom_in(tile, &data, &mut |x, y, atom| {
+                if (type_num.is_none() || type_num == Some(atom.get_type())) && f(x, y, atom) {
+                    found.push((x, y));
+                }
+            });
+        }
+        found
+    }
+
+    /// A 64 bit digest of what the grid holds: its size and each site's
+    /// atom, paint and layers. Grids holding the same sites have the same
+    /// digest whatever their tiling, backend or events run, on every platform,
+    /// so a run with a fixed seed can be checked against a digest taken
+    /// before a change. Different grids may share a digest, though rarely.
+    pub fn digest(&self) -> u64 {
+        let w = self.tile_width + 2 * RADIUS;
+        let mut sum = 0u64;
+        for tile in &self.tiles {
+            let data = tile.data();
+            let (ox, oy) = tile.origin;
+            for ly in 0..self.tile_height {
+                for lx in 0..self.tile_width {
+                    let i = (ly + RADIUS) * w + lx + RADIUS;
+                    let (x, y) = ((ox + lx) as i64, (oy + ly) as i64);
+                    // Sites are summed so that the order tiles are visited
+                    // in doesn't matter.
+                    let mut h = rng::hash(data.atom(i).bits(), x, y);
+                    h = rng::hash((h as u128) << 32 | data.paint(i).bits() as u128, x, y);
+                    for l in 0..LAYERS {
+                        h = rng::hash((h as u128) << 32 | data.layer(l, i) as u128, x, y);
+                    }
+                    sum = sum.wrapping_add(h);
+                }
+            }
+        }
+        rng::hash(sum as u128, self.width() as i64, self.height() as i64)
+    }
+
+    /// Writes site `(x, y)` in its owning tile and in every tile caching it.
+    pub fn set(&mut self, x: usize, y: usize, atom: Atom) {
+        if self.tracks_active && self.in_bounds(x as isize, y as isize) {
+            let site = y * self.width() + x;
+            let active = self.active.get_mut().unwrap_or_else(|e| e.into_inner());
+            active.update(site, !atom.is_empty());
+        }
+        self.update(x, y, |data, i| data.set_atom(i, atom));
+    }
+
+    pub fn set_paint(&mut self, x: usize, y: usize, paint: Color) {
+        self.update(x, y, |data, i| data.set_paint(i, paint));
+    }
+
+    /// Sets layer `l` at site `(x, y)`. Writes to layers past `LAYERS` are
+    /// dropped, as are writes outside the grid.
+    pub fn set_layer(&mut self, l: usize, x: usize, y: usize, value: u32) {
+        if l < LAYERS {
+            self.update(x, y, |data, i| data.set_layer(l, i, value));
+        }
+    }
+
+    fn update<F: FnMut(&mut TileData, usize)>(&mut self, x: usize, y: usize, mut f: F) {
+        if !self.in_bounds(x as isize, y as isize) {
+            return;
+        }
+        let tiles: Vec<usize> = self.tiles_containing(x, y).collect();
+        for t in tiles {
+            let i = self.local_index(t, x as isize, y as isize).unwrap();
+            f(self.tiles[t].data_mut(), i);
+        }
+    }
+
+    /// Grid coordinates of each site of the window centered on `(x, y)`.
+    fn window_coords(x: usize, y: usize) -> impl Iterator<Item = (isize, isize)> {
+        (0..SiteNumber::COUNT).map(move |i| {
+            let (dx, dy) = SiteNumber(i as u8).coord().unwrap();
+            (x as isize + dx as isize, y as isize + dy as isize)
+        })
+    }
+
+    /// The tiles locked by an event centered on `(x, y)`: those owning or
+    /// caching any site of its window, in ascending order.
+    pub fn window_tiles(&self, x: usize, y: usize) -> Vec<usize> {
+        let mut tiles: Vec<usize> = Self::window_coords(x, y)
+            .filter_map(|(i, j)| self.resolve(i, j))
+            .flat_map(|(i, j)| self.tiles_containing(i as usize, j as usize))
+            .collect();
+        tiles.sort_unstable();
+        tiles.dedup();
+        tiles
+    }
+
+    /// Locks the tiles of the window centered on `(x, y)` without blocking.
+    /// Returns `None` if any of them is held by another event.
+    fn try_lock_window(&self, x: usize, y: usize) -> Option<WindowLock<'_>> {
+        let mut guards = Vec::new();
+        for t in self.window_tiles(x, y) {
+            // Dropping `guards` on failure releases the tiles taken so far.
+            guards.push((t, self.tiles[t].data.try_lock().ok()?));
+        }
+        Some(WindowLock { guards })
+    }
+
+    fn load_window(&self, lock: &mut WindowLock, x: usize, y: usize, ew: &mut EventWindow) {
+        let t = self.tile_of(x, y);
+        let layered = lock.has_layers();
+        *ew.layers_mut() = [[0; 41]; LAYERS];
+        for (k, (i, j)) in Self::window_coords(x, y).enumerate() {
+            let site = match (self.local_index(t, i, j), self.resolve(i, j)) {
+                (Some(l), _) if self.in_bounds(i, j) => Some((t, l)),
+                // Wrapped around, so read from the tile owning the site.
+                (_, Some((i, j))) => {
+                    let owner = self.tile_of(i as usize, j as usize);
+                    Some((owner, self.local_index(owner, i, j).unwrap()))
+                }
+                _ => None,
+            };
+            let (atom, paint) = match site {
+                Some((t, l)) => {
+                    let data = lock.tile(t);
+                    if layered {
+                        for (n, layer) in ew.layers_mut().iter_mut().enumerate() {
+                            layer[k] = data.layer(n, l);
+                        }
+                    }
+                    (data.atom(l), data.paint(l))
+                }
+                None => match self.boundary {
+                    Boundary::Wall => (self.wall, Color::new()),
+                    _ => (Atom::default(), Color::new()),
+                },
+            };
+            ew.atoms_mut()[k] = atom;
+            ew.paints_mut()[k] = paint;
+        }
+    }
+
+    fn store_window(&self, lock: &mut WindowLock, x: usize, y: usize, ew: &EventWindow) {
+        let mut active = if self.tracks_active {
+            Some(self.active_sites())
+        } else {
+            None
+        };
+        // Writing zeros to tiles without layers changes nothing.
+        let layered = lock.has_layers() || ew.layers().iter().flatten().any(|v| *v != 0);
+        for (k, (i, j)) in Self::window_coords(x, y).enumerate() {
+            let (i, j) = match self.resolve(i, j) {
+                Some(site) => site,
+                None => continue,
+            };
+            if let Some(active) = active.as_mut() {
+                let site = j as usize * self.width() + i as usize;
+                active.update(site, !ew.atoms()[k].is_empty());
+            }
+            for t in self.tiles_containing(i as usize, j as usize) {
+                let l = self.local_index(t, i, j).unwrap();
+                let data = lock.tile(t);
+                data.set_atom(l, ew.atoms()[k]);
+                data.set_paint(l, ew.paints()[k]);
+                if layered {
+                    for (n, layer) in ew.layers().iter().enumerate() {
+                        data.set_layer(n, l, layer[k]);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs one event centered on site `(x, y)`. Fails with `WindowLocked` if
+    /// another thread holds any of the window's tiles.
+    ///
+    /// An event which runs out of instruction budget is handled according to
+    /// the runtime's `BudgetPolicy`; a discarded event doesn't count as
+    /// completed. So is an event stopped by a failed `assert`, according to
+    /// the runtime's `AssertPolicy`.
+    ///
+    /// An event which hits one of the runtime's watchpoints is completed and
+    /// then fails with `Watchpoint`, naming the first it hit.
+    ///
+    /// Under the runtime's `EnergyModel` the window cools before the event,
+    /// an event at a hot site may be throttled, counting without running, and
+    /// an event which runs gives off heat at its site.
+    pub fn event_at(&self, runtime: &mut Runtime, x: usize, y: usize) -> Result<(), Error> {
+        self.event_at_with(runtime, x, y, None)
+    }
+
+    /// Runs one event centered on site `(x, y)` as `event_at` does, calling
+    /// `step` before each instruction as `Runtime::execute_stepping` does.
+    pub fn event_at_stepping(
+        &self,
+        runtime: &mut Runtime,
+        x: usize,
+        y: usize,
+        step: &mut StepHook,
+    ) -> Result<(), Error> {
+        self.event_at_with(runtime, x, y, Some(step))
+    }
+
+    fn event_at_with(
+        &self,
+        runtime: &mut Runtime,
+        x: usize,
+        y: usize,
+        step: Option<&mut StepHook>,
+    ) -> Result<(), Error> {
+        if !self.in_bounds(x as isize, y as isize) {
+            return Err(Error::SiteOutOfBounds(x, y));
+        }
+        let mut lock = self
+            .try_lock_window(x, y)
+            .ok_or(Error::WindowLocked(x, y))?;
+        let mut ew = EventWindow::new();
+        self.load_window(&mut lock, x, y, &mut ew);
+        let _span = logging::enter(EventSpan {
+            x,
+            y,
+            type_num: ew.atoms()[0].get_type(),
+        });
+        let before = if self.observes_events {
+            Some(*ew.atoms())
+        } else {
+            None
+        };
+        let energy = runtime.energy_model();
+        let active = !ew.atoms()[0].is_empty();
+        if let Some(model) = energy {
+            model.cool(&mut ew);
+            let heat = ew.layers()[model.layer][0];
+            if active && !model.runs(heat, runtime.rng_mut()) {
+                log::trace!(target: "substrate::event", "throttled at heat {}", heat);
+                self.store_window(&mut lock, x, y, &ew);
+                self.events.fetch_add(1, Ordering::Relaxed);
+                self.throttled.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+        }
+        runtime.set_event_origin(Some((x, y)));
+        let result = match step {
+            Some(step) => runtime.execute_stepping(&mut ew, step),
+            None => runtime.execute(&mut ew),
+        };
+        self.instructions
+            .fetch_add(runtime.cursor().executed(), Ordering::Relaxed);
+        self.assertions_failed
+            .fetch_add(runtime.cursor().assertions_failed(), Ordering::Relaxed);
+        log::trace!(
+            target: "substrate::event",
+            "ran {} instructions",
+            runtime.cursor().executed()
+        );
+        match result {
+            Err(Error::BudgetExceeded(n)) => {
+                self.budget_exceeded.fetch_add(1, Ordering::Relaxed);
+                log::debug!(
+                    target: "substrate::event",
+                    "over budget at instruction {}, {:?}",
+                    n,
+                    runtime.budget_policy()
+                );
+                match runtime.budget_policy() {
+                    BudgetPolicy::Discard => return Ok(()),
+                    BudgetPolicy::Commit => {}
+                    BudgetPolicy::Fail => return Err(Error::BudgetExceeded(n)),
+                }
+            }
+            Err(Error::AssertionFailed(_)) if runtime.assert_policy() == AssertPolicy::Abort => {
+                return Ok(())
+            }
+            result => result?,
+        }
+        if let (Some(model), true) = (energy, active) {
+            let heat = &mut ew.layers_mut()[model.layer][0];
+            *heat = heat.saturating_add(model.heat(runtime.cursor().executed()));
+        }
+        self.store_window(&mut lock, x, y, &ew);
+        self.events.fetch_add(1, Ordering::Relaxed);
+        if let Some(before) = before {
+            let mut scheduler = self.scheduler.lock().unwrap_or_else(|e| e.into_inner());
+            scheduler.event_ran(y * self.width() + x, before != *ew.atoms());
+        }
+        match runtime.cursor().watch_hits().first() {
+            Some(hit) => Err(Error::Watchpoint {
+                watch: hit.watch,
+                x,
+                y,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn random_site_in<R: Rng + ?Sized>(&self, t: usize, rng: &mut R) -> (usize, usize) {
+        let (ox, oy) = self.tiles[t].origin;
+        let x = ox + rng.gen_range(0..self.tile_width);
+        let y = oy + rng.gen_range(0..self.tile_height);
+        (x, y)
+    }
+
+    /// Picks a site uniformly at random: first a tile, then a site owned by
+    /// that tile.
+    pub fn random_site<R: Rng + ?Sized>(&self, rng: &mut R) -> (usize, usize) {
+        let t = rng.gen_range(0..self.tiles.len());
+        self.random_site_in(t, rng)
+    }
+
+    /// Runs one event at a random site drawn from `runtime`'s generator.
+    ///
+    /// With `Scheduling::Active` this may instead count an event skipped at
+    /// an empty site; see `step_at_most`.
+    pub fn step(&self, runtime: &mut Runtime) -> Result<(), Error> {
+        self.step_at_most(runtime, 1).map(|_| ())
+    }
+
+    /// Advances the grid by between 1 and `max` events, picking sites with its
+    /// scheduler, and returns how many were counted. All built-in scheduling
+    /// but active always runs exactly one event.
+    ///
+    /// Active scheduling draws how many events uniform scheduling would have
+    /// run at empty sites before reaching an atom, and counts those without
+    /// running them. If that reaches `max`, or the grid holds no atoms, it
+    /// counts `max` events and runs none; otherwise it runs one event at a
+    /// random atom after the skipped ones.
+    ///
+    /// The runtime's invariants due in the events counted are checked
+    /// afterwards, even if the event fails.
+    pub fn step_at_most(&self, runtime: &mut Runtime, max: u64) -> Result<u64, Error> {
+        let before = self.event_count();
+        let result = match self.next_site(runtime, max)? {
+            (skipped, Some((x, y))) => self.event_at(runtime, x, y).map(|_| skipped + 1),
+            (skipped, None) => Ok(skipped),
+        };
+        self.check_invariants_since(runtime, before);
+        result
+    }
+
+    /// Checks every invariant of `runtime` now, telling its observers about
+    /// each which fails, and returns how many failed.
+    pub fn check_invariants(&self, runtime: &Runtime) -> usize {
+        self.check_invariants_where(runtime, |_| true)
+    }
+
+    /// Checks the invariants of `runtime` due since the grid had counted
+    /// `before` events.
+    fn check_invariants_since(&self, runtime: &Runtime, before: u64) {
+        let after = self.event_count();
+        self.check_invariants_where(runtime, |every| after / every > before / every);
+    }
+
+    fn check_invariants_where<F: Fn(u64) -> bool>(&self, runtime: &Runtime, due: F) -> usize {
+        let mut failed = 0;
+        for (invariant, every) in runtime.invariants() {
+            if !due(*every) {
+                continue;
+            }
+            if let Err(message) = invariant.check(self) {
+                let violation = Violation {
+                    invariant: invariant.name(),
+                    events: self.event_count(),
+                    message,
+                };
+                for o in runtime.observers() {
+                    o.invariant_violated(&violation);
+                }
+                failed += 1;
+            }
+        }
+        failed
+    }
+
+    /// Picks the site of the next event as `step_at_most` does, counting the
+    /// events skipped before it. Gives the number skipped and the site, or no
+    /// site if `max` events were skipped.
+    pub fn next_site(
+        &self,
+        runtime: &mut Runtime,
+        max: u64,
+    ) -> Result<(u64, Option<(usize, usize)>), Error> {
+        if max == 0 {
+            return Ok((0, None));
+        }
+        let mut scheduler = self.scheduler.lock().unwrap_or_else(|e| e.into_inner());
+        let (skipped, site) = scheduler.next_site(self, runtime.rng_mut(), max)?;
+        if skipped > 0 {
+            log::trace!(target: "substrate::schedule", "skipped {} events", skipped);
+        }
+        self.events.fetch_add(skipped, Ordering::Relaxed);
+        Ok((skipped, site))
+    }
+
+    /// Runs `events` events in every tile.
+    ///
+    /// With `threads > 1` tiles are spread over that many threads, each with
+    /// its own clone of `runtime` and a generator forked from it. An event
+    /// whose window is held by another thread waits for it, retrying at the
+    /// same site so that busy sites near tile edges aren't picked less often,
+    /// but the order in which neighboring threads' events run still depends
+    /// on scheduling. A thread which panics fails the run with
+    /// `EventThreadPanicked` once the others are done. With `threads <= 1`
+    /// tiles take turns in order on the calling thread, which is
+    /// deterministic for a given seed.
+    ///
+    /// The runtime's invariants are checked after each round of tiles with
+    /// one thread, and once all threads are done with more.
+    ///
+    /// Sites are always picked uniformly within each tile, whatever the
+    /// grid's scheduler.
+    pub fn run_tiles(
+        &self,
+        runtime: &mut Runtime,
+        events: usize,
+        threads: usize,
+    ) -> Result<(), Error> {
+        if threads <= 1 {
+            for _ in 0..events {
+                let before = self.event_count();
+                for t in 0..self.tiles.len() {
+                    let (x, y) = self.random_site_in(t, runtime.rng_mut());
+                    self.event_at(runtime, x, y)?;
+                }
+                self.check_invariants_since(runtime, before);
+            }
+            return Ok(());
+        }
+
+        let before = self.event_count();
+        let threads = threads.min(self.tiles.len());
+        log::debug!(
+            target: "substrate::schedule",
+            "running {} events in each of {} tiles on {} threads",
+            events,
+            self.tiles.len(),
+            threads
+        );
+        let workers: Vec<Runtime> = (0..threads)
+            .map(|_| {
+                let mut r = runtime.clone();
+                r.set_rng(runtime.rng_mut().fork());
+                r
+            })
+            .collect();
+        thread::scope(|s| {
+            let handles: Vec<_> = workers
+                .into_iter()
+                .enumerate()
+                .map(|(w, mut runtime)| {
+                    s.spawn(move || -> Result<(), Error> {
+                        let tiles: Vec<usize> = (w..self.tiles.len()).step_by(threads).collect();
+                        log::debug!(target: "substrate::schedule", "thread {} runs tiles {:?}", w, tiles);
+                        for _ in 0..events {
+                            for t in tiles.iter() {
+                                let (x, y) = self.random_site_in(*t, runtime.rng_mut());
+                                loop {
+                                    match self.event_at(&mut runtime, x, y) {
+                                        Err(Error::WindowLocked(_, _)) => thread::yield_now(),
+                                        r => break r?,
+                                    }
+                                }
+                            }
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            // Join every thread before failing, so none is left running.
+            let results: Vec<_> = handles.into_iter().map(|h| h.join()).collect();
+            results
+                .into_iter()
+                .try_for_each(|r| r.map_err(|_| Error::EventThreadPanicked)?)
+        })?;
+        self.check_invariants_since(runtime, before);
+        Ok(())
+    }
+}
+
+/// The alternate form, `{:#}`, shows each site's paint as its background.
+impl fmt::Display for Grid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                let c = if self.get(x, y).unwrap().is_empty() {
+                    '.'
+                } else {
+                    'x'
+                };
+                if f.alternate() {
+                    self.get_paint(x, y).unwrap().write_background(f, c)?;
+                } else {
+                    write!(f, "{}", c)?;
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::rng::Prng;
+    use crate::stdlib;
+
+    /// The digest of a 32 by 32 torus tiled `tiles_x` by `tiles_y`, with DReg
+    /// on every ninth site, after `events` events at sites drawn from their own
+    /// generator, so that every tiling runs the same events.
+    fn dreg_digest(tiles_x: usize, tiles_y: usize, events: usize) -> u64 {
+        let mut runtime = Runtime::new();
+        runtime.reseed(7);
+        let mut load = |name| {
+            let code = stdlib::compile(name, "test").unwrap().unwrap();
+            runtime.load_from_reader(&mut &code[..]).unwrap()
+        };
+        load("Res");
+        let dreg = load("DReg");
+        let mut grid = Grid::new(tiles_x, tiles_y, 32 / tiles_x, 32 / tiles_y);
+        grid.set_boundary(Boundary::Torus);
+        for i in (0..32 * 32).step_by(9) {
+            grid.set(i % 32, i / 32, dreg);
+        }
+        let mut sites = Prng::new(11);
+        for _ in 0..events {
+            let (x, y) = (sites.gen_range(0..32), sites.gen_range(0..32));
+            grid.event_at(&mut runtime, x, y).unwrap();
+        }
+        grid.digest()
+    }
+
+    #[test]
+    fn known_digest_whatever_the_tiling() {
+        // A change to what events compute, or to how sites are digested,
+        // changes this.
+        const DIGEST: u64 = 0xf652_829e_9128_1d5d;
+        for &(x, y) in [(1, 1), (2, 2), (4, 1)].iter() {
+            assert_eq!(dreg_digest(x, y, 5000), DIGEST, "{} by {} tiles", x, y);
+        }
+        assert_ne!(dreg_digest(1, 1, 0), DIGEST);
+    }
+}
@@ -0,0 +1,86 @@
+//! Invariants checked over a whole grid as it runs.
+//!
+//! A `GridInvariant` added to a runtime with `Runtime::add_invariant` is
+//! checked by the grid every so many events, such as that the number of Data
+//! atoms never changes. Each check which fails is reported to the runtime's
+//! observers as a `Violation`; the run carries on.
+//!
+//! Invariants are checked between events by `Grid::step_at_most` and, when a
+//! single thread runs them, between rounds of `Grid::run_tiles`. Threaded
+//! runs are checked once all their threads are done, since other threads
+//! would change the grid under a check.
+
+use crate::runtime::grid::Grid;
+use std::fmt;
+use std::sync::Mutex;
+
+/// A condition the whole grid should meet between events.
+pub trait GridInvariant: Send + Sync {
+    /// What the invariant checks, for reports, such as `Data conserved`.
+    fn name(&self) -> String;
+
+    /// Checks `grid`, saying what is wrong if the invariant doesn't hold.
+    fn check(&self, grid: &Grid) -> Result<(), String>;
+}
+
+/// An invariant which failed a check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    /// The name of the invariant.
+    pub invariant: String,
+    /// The grid's event count when it was checked.
+    pub events: u64,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invariant {} violated after {} events: {}",
+            self.invariant, self.events, self.message
+        )
+    }
+}
+
+/// The number of atoms of one type stays the same as at the first check.
+pub struct Conserved {
+    type_num: u16,
+    name: String,
+    count: Mutex<Option<usize>>,
+}
+
+impl Conserved {
+    /// Conserves atoms of type `type_num`, the element `name`.
+    pub fn new(type_num: u16, name: &str) -> Self {
+        Self {
+            type_num,
+            name: name.to_owned(),
+            count: Mutex::new(None),
+        }
+    }
+}
+
+impl GridInvariant for Conserved {
+    fn name(&self) -> String {
+        format!("{} conserved", self.name)
+    }
+
+    fn check(&self, grid: &Grid) -> Result<(), String> {
+        let mut n = 0;
+        grid.for_each_atom(|_, _, atom| {
+            if !atom.is_empty() && atom.get_type() == self.type_num {
+                n += 1;
+            }
+        });
+        let mut count = self.count.lock().unwrap();
+        match *count {
+            Some(m) if m != n => Err(format!("{} {} atoms, expected {}", n, self.name, m)),
+            Some(_) => Ok(()),
+            None => {
+                *count = Some(n);
+                Ok(())
+            }
+        }
+    }
+}
@@ -1,11 +1,19 @@
 use crate::base;
-use crate::base::arith::Const;
+use crate::base::arith::{ArithMode, Const, DivZeroPolicy};
+use crate::base::atom::Atom;
 use crate::base::color::Color;
+use crate::base::field::FieldMap;
+use crate::base::{FieldSelector, SiteNumber, Symmetries};
+use crate::runtime::grid::LAYERS;
 use lazy_static::lazy_static;
+use rand::Rng;
 use std::collections::HashMap;
 use std::fmt;
 
+/// What an element declares about itself in its header: how it is shown,
+/// who wrote it and how its code runs.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Metadata {
     pub name: String,
     pub symbol: String,
@@ -15,9 +23,19 @@ pub struct Metadata {
     pub radius: u8,
     pub bg_color: String,
     pub fg_color: String,
+    pub color: Option<Color>,
     pub symmetries: base::Symmetries,
-    pub field_map: HashMap<String, base::FieldSelector>,
+    pub field_map: FieldMap,
+    /// The fields of `field_map` declared with `.bond`; see `bond`.
+    pub bonds: Vec<FieldSelector>,
     pub parameter_map: HashMap<String, Const>,
+    pub arith_mode: ArithMode,
+    /// What `setfield` and `setsitefield` do with values too wide for the
+    /// field.
+    pub field_writes: ArithMode,
+    /// What `div` and `mod` do when dividing by zero.
+    pub div_zero: DivZeroPolicy,
+    pub mfm_type: Option<u16>,
 }
 
 impl Metadata {
@@ -31,22 +49,34 @@ impl Metadata {
             radius: 0,
             bg_color: "".to_string(),
             fg_color: "".to_string(),
+            color: None,
             symmetries: base::Symmetries::R000L,
-            field_map: HashMap::new(),
+            field_map: FieldMap::new(),
+            bonds: Vec::new(),
             parameter_map: HashMap::new(),
+            arith_mode: ArithMode::default(),
+            field_writes: ArithMode::Wrapping,
+            div_zero: DivZeroPolicy::default(),
+            mfm_type: None,
         }
     }
 }
 
+/// The 41 sites within Manhattan distance 4 of an event's center.
+///
+/// Site numbers passed to the accessors are mapped through the current
+/// symmetry, so element code sees the same window regardless of orientation.
 #[derive(Debug)]
 pub struct EventWindow<'a> {
-    data: [Const; 41],
+    data: [Atom; 41],
     paint: [Color; 41],
+    layers: [[u32; 41]; LAYERS],
+    symmetry: Symmetries,
     type_data: Option<&'a HashMap<u16, Metadata>>,
 }
 
 impl<'a> EventWindow<'a> {
-    pub fn new_with_const(x: Const) -> Self {
+    pub fn new_with_atom(x: Atom) -> Self {
         let mut ew = Self::new();
         ew.data[0] = x;
         ew
@@ -54,33 +84,107 @@ impl<'a> EventWindow<'a> {
 
     pub fn new() -> Self {
         Self {
-            data: [0u128.into(); 41],
+            data: [Atom::default(); 41],
             paint: [0.into(); 41],
+            layers: [[0; 41]; LAYERS],
+            symmetry: Symmetries::R000L,
             type_data: None,
         }
     }
 
-    pub fn get(&self, i: usize) -> Option<&Const> {
-        self.data.get(i)
+    pub fn symmetry(&self) -> Symmetries {
+        self.symmetry
     }
 
-    pub fn get_mut(&mut self, i: usize) -> Option<&mut Const> {
+    /// Sets the symmetry used to map site numbers. Only the first symmetry in
+    /// `s` is used.
+    pub fn set_symmetry(&mut self, s: Symmetries) {
+        self.symmetry = s;
+    }
+
+    /// Selects one of the symmetries in `s` at random.
+    pub fn use_symmetries<R: Rng + ?Sized>(&mut self, s: Symmetries, rng: &mut R) {
+        self.symmetry = s.choose(rng);
+    }
+
+    /// Maps a site number through the current symmetry to its index in the
+    /// window, or `None` if out of bounds.
+    pub fn map(&self, i: usize) -> Option<usize> {
+        if i >= SiteNumber::COUNT {
+            return None;
+        }
+        SiteNumber(i as u8).map(self.symmetry).map(|x| x.0 as usize)
+    }
+
+    pub fn get(&self, i: usize) -> Option<&Atom> {
+        self.data.get(self.map(i)?)
+    }
+
+    pub fn get_mut(&mut self, i: usize) -> Option<&mut Atom> {
+        let i = self.map(i)?;
         self.data.get_mut(i)
     }
 
     pub fn get_paint(&self, i: usize) -> Option<&Color> {
-        self.paint.get(i)
+        self.paint.get(self.map(i)?)
     }
 
     pub fn get_paint_mut(&mut self, i: usize) -> Option<&mut Color> {
+        let i = self.map(i)?;
         self.paint.get_mut(i)
     }
+
+    /// The value of layer `l` at site `i`, or `None` if there is no such
+    /// layer or site.
+    pub fn get_layer(&self, l: usize, i: usize) -> Option<&u32> {
+        self.layers.get(l)?.get(self.map(i)?)
+    }
+
+    pub fn get_layer_mut(&mut self, l: usize, i: usize) -> Option<&mut u32> {
+        let i = self.map(i)?;
+        self.layers.get_mut(l)?.get_mut(i)
+    }
+
+    /// The window contents in unmapped site order.
+    pub fn atoms(&self) -> &[Atom; 41] {
+        &self.data
+    }
+
+    pub fn atoms_mut(&mut self) -> &mut [Atom; 41] {
+        &mut self.data
+    }
+
+    pub fn paints(&self) -> &[Color; 41] {
+        &self.paint
+    }
+
+    pub fn paints_mut(&mut self) -> &mut [Color; 41] {
+        &mut self.paint
+    }
+
+    /// Lowers every nonzero value `v` of layer `l` by `v >> shift`, or by 1
+    /// if that is less, so a layer left alone falls to zero.
+    pub fn decay_layer(&mut self, l: usize, shift: u32) {
+        for v in self.layers[l].iter_mut().filter(|v| **v > 0) {
+            *v -= v.checked_shr(shift).unwrap_or(0).max(1);
+        }
+    }
+
+    /// Each layer's values in unmapped site order.
+    pub fn layers(&self) -> &[[u32; 41]; LAYERS] {
+        &self.layers
+    }
+
+    pub fn layers_mut(&mut self) -> &mut [[u32; 41]; LAYERS] {
+        &mut self.layers
+    }
 }
 
 const VOID: char = ' ';
 const EMPTY: char = '.';
 const OCCUPIED: char = 'x';
 
+/// The alternate form, `{:#}`, shows each site's paint as its background.
 impl fmt::Display for EventWindow<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         lazy_static! {
@@ -100,10 +204,11 @@ impl fmt::Display for EventWindow<'_> {
                 }
                 for _ in 0..2 * $cols + 1 {
                     if let Some(x) = self.data.get(INDICES[idx]) {
-                        if x.as_u128() == 0u128 {
-                            s.push(EMPTY);
+                        let c = if x.bits() == 0u128 { EMPTY } else { OCCUPIED };
+                        if f.alternate() {
+                            self.paint[INDICES[idx]].write_background(&mut s, c)?;
                         } else {
-                            s.push(OCCUPIED);
+                            s.push(c);
                         }
                     }
                     idx += 1;
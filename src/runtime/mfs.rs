@@ -0,0 +1,120 @@
+//! Exchanging grids with the reference MFM simulator through `.mfs` files.
+//!
+//! An `.mfs` file is a list of statements, one per line, written as function
+//! calls; blank lines and lines starting with `#` are ignored. Two statements
+//! describe a grid:
+//!
+//! ```text
+//! Grid(tiles_x, tiles_y, tile_width, tile_height)
+//! GA(type, x, y, data)
+//! ```
+//!
+//! `GA` places an atom of the given type with the given data bits (hex with a
+//! `0x` prefix, or decimal; optional) at site `(x, y)`. Empty sites are not
+//! listed. Other statements, such as the reference simulator's element
+//! registrations and panel settings, are skipped on import.
+//!
+//! Type numbers in the file are the reference simulator's. Elements declaring
+//! `.mfmtype` are translated to and from that number; all others keep their
+//! own type number. Paint, ECC bits and random state are not exchanged.
+
+use crate::base::atom::Atom;
+use crate::base::{ecc, FieldSelector};
+use crate::runtime::grid::Grid;
+use crate::runtime::{Error, Runtime};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Write};
+
+/// Writes `grid` as `.mfs` statements.
+pub fn export<W: Write>(mut w: W, grid: &Grid, runtime: &Runtime) -> io::Result<()> {
+    let to_mfm: HashMap<u16, u16> = runtime
+        .elements()
+        .filter_map(|(t, m)| Some((t, m.mfm_type?)))
+        .collect();
+    writeln!(
+        w,
+        "Grid({}, {}, {}, {})",
+        grid.tiles_x(),
+        grid.tiles_y(),
+        grid.tile_width(),
+        grid.tile_height()
+    )?;
+    for y in 0..grid.height() {
+        for x in 0..grid.width() {
+            let atom = grid.get(x, y).unwrap();
+            if atom.is_empty() {
+                continue;
+            }
+            let t = atom.get_type();
+            let t = to_mfm.get(&t).copied().unwrap_or(t);
+            writeln!(w, "GA({}, {}, {}, {:#x})", t, x, y, atom.payload())?;
+        }
+    }
+    w.flush()
+}
+
+fn parse_statement(line: &str) -> Option<(&str, Vec<&str>)> {
+    let open = line.find('(')?;
+    let args = line[open + 1..].strip_suffix(')')?;
+    let args = if args.trim().is_empty() {
+        Vec::new()
+    } else {
+        args.split(',').map(str::trim).collect()
+    };
+    Some((line[..open].trim(), args))
+}
+
+fn parse_num(s: &str) -> Option<u128> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u128::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Reads a grid from `.mfs` statements.
+pub fn import<R: BufRead>(r: R, runtime: &Runtime) -> Result<Grid, Error> {
+    let from_mfm: HashMap<u16, u16> = runtime
+        .elements()
+        .filter_map(|(t, m)| Some((m.mfm_type?, t)))
+        .collect();
+    let mut grid: Option<Grid> = None;
+    for (n, line) in r.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let bad = || Error::BadMfsLine(n + 1);
+        let (name, args) = parse_statement(line).ok_or_else(bad)?;
+        let nums = args
+            .iter()
+            .map(|a| parse_num(a))
+            .collect::<Option<Vec<u128>>>();
+        match (name, nums) {
+            ("Grid", Some(nums)) if nums.len() == 4 => {
+                if grid.is_some() || nums.iter().any(|n| *n == 0 || *n > u16::MAX as u128) {
+                    return Err(bad());
+                }
+                let n: Vec<usize> = nums.iter().map(|n| *n as usize).collect();
+                grid = Some(Grid::new(n[0], n[1], n[2], n[3]));
+            }
+            ("GA", Some(nums)) if nums.len() == 3 || nums.len() == 4 => {
+                let grid = grid.as_mut().ok_or_else(bad)?;
+                let t = u16::try_from(nums[0]).map_err(|_| bad())?;
+                let x = usize::try_from(nums[1]).map_err(|_| bad())?;
+                let y = usize::try_from(nums[2]).map_err(|_| bad())?;
+                let data = nums.get(3).copied().unwrap_or(0);
+                if x >= grid.width() || y >= grid.height() || data > FieldSelector::DATA.mask() {
+                    return Err(bad());
+                }
+                let t = from_mfm.get(&t).copied().unwrap_or(t);
+                let atom = Atom::from_bits(FieldSelector::DATA.insert(Atom::new(t).bits(), data));
+                grid.set(x, y, ecc::encode(atom));
+            }
+            ("Grid", _) | ("GA", _) => return Err(bad()),
+            _ => {}
+        }
+    }
+    grid.ok_or(Error::BadGridSize)
+}
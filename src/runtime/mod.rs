@@ -1,13 +1,54 @@
+pub mod backend;
+pub mod bench;
+pub mod bond;
+pub mod control;
+pub mod crash;
+pub mod diff;
+pub mod energy;
+#[cfg(feature = "metrics-http")]
+pub mod exporter;
+pub mod grid;
+pub mod invariant;
 pub mod mfm;
+pub mod mfs;
+pub mod observer;
+pub mod profile;
+pub mod registry;
+pub mod replay;
+pub mod rng;
+pub mod scenario;
+pub mod schedule;
+pub mod snapshot;
+pub mod stats;
+pub mod timeline;
+pub mod trace;
+pub mod watch;
 
-use crate::ast::{Arg, Instruction};
-use crate::base::arith::Const;
-use crate::base::{FieldSelector, Symmetries};
+use crate::ast::{Arg, Condition, Instruction};
+use crate::base::arith::{ArithMode, Const, DivZeroPolicy, Flags};
+use crate::base::atom::{Atom, AtomError};
+use crate::base::ecc;
+use crate::base::field::FieldError;
+use crate::base::{FieldSelector, SiteNumber, Symmetries};
 use byteorder::BigEndian;
 use byteorder::ReadBytesExt;
+use crash::{CrashRecorder, EventRecord};
+use energy::EnergyModel;
+use grid::LAYERS;
+use invariant::GridInvariant;
+use observer::{Change, EventObserver};
+use profile::{EventProfile, Profiler};
+use rand::Rng;
+use registry::ElementRegistry;
+use rng::Prng;
 use std::collections::HashMap;
+use std::fmt;
 use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use thiserror;
+use trace::{EventTrace, Tracer};
+use watch::{Armed, Watch, WatchHit};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -21,18 +62,197 @@ pub enum Error {
   BadMinorVersion(u16),
   #[error("wrong major version")]
   BadMajorVersion(u16),
+  #[error("version {major}.{minor} is newer than supported; upgrade to load it")]
+  NewerVersion { major: u16, minor: u16 },
   #[error("build tag mismatch: {got:?} but expected: {want:?}")]
   BuildTagMismatch { want: String, got: String },
   #[error("bad metadata op code: {0}")]
   BadMetadataOpCode(u8),
   #[error("bad instruction op code: {0}")]
   BadInstructionOpCode(u8),
+  #[error("bad constant type: {0:#04x}")]
+  BadConstType(u8),
+  #[error("bad width: {0}")]
+  BadWidth(u8),
+  #[error("bad register: {0}")]
+  BadRegister(u8),
+  #[error("bad counter: {0}")]
+  BadCounter(u8),
+  #[error("bad constant index: {0}")]
+  BadConstIndex(u16),
+  #[error("bad type index: {0}")]
+  BadTypeIndex(u16),
+  #[error("element {0} is already loaded")]
+  DuplicateElement(String),
+  #[error("type number {number} is given to both {first} and {second}")]
+  TypeCollision {
+    number: u16,
+    first: String,
+    second: String,
+  },
+  #[error("type {0} is numbered twice")]
+  TypeNumberedTwice(String),
+  #[error("too many element types")]
+  TooManyTypes,
+  #[error("bad type numbering on line {0}")]
+  BadNumberingLine(usize),
   #[error("no element")]
   NoElement,
   #[error("running unknown element: {0}")]
   UnknownElement(u16),
-  #[error("stack underflow")]
-  StackUnderflow, // TODO: add context
+  #[error("no element named {0} is loaded")]
+  UnknownElementName(String),
+  #[error("element {0} has no parameter {1}")]
+  UnknownParameter(String, String),
+  #[error("bad field")]
+  FieldError(#[from] FieldError),
+  #[error("bad atom write")]
+  AtomError(#[from] AtomError),
+  #[error("bad arithmetic mode: {0}")]
+  BadArithMode(u8),
+  #[error("bad division by zero policy: {0}")]
+  BadDivZeroPolicy(u8),
+  #[error("arithmetic overflow")]
+  ArithmeticOverflow,
+  #[error("site out of bounds: ({0}, {1})")]
+  SiteOutOfBounds(usize, usize),
+  #[error("event window at ({0}, {1}) is locked")]
+  WindowLocked(usize, usize),
+  #[error("a thread running events panicked")]
+  EventThreadPanicked,
+  #[error("site ({0}, {1}) disagrees with the active site set")]
+  ActiveSetMismatch(usize, usize),
+  #[error("bad grid size")]
+  BadGridSize,
+  #[error("grids of different sizes: {0}x{1} and {2}x{3}")]
+  GridSizeMismatch(usize, usize, usize, usize),
+  #[error("bad .mfs statement on line {0}")]
+  BadMfsLine(usize),
+  #[error("replay diverged at event {0}")]
+  ReplayDiverged(u64),
+  #[error("value too wide for its field at instruction {0}")]
+  FieldOverflow(usize),
+  #[error("division by zero")]
+  DivisionByZero,
+  #[error("stack underflow at instruction {0}")]
+  StackUnderflow(usize),
+  #[error("stack overflow at instruction {0}")]
+  StackOverflow(usize),
+  #[error("call stack overflow at instruction {0}")]
+  CallStackOverflow(usize),
+  #[error("ret without call at instruction {0}")]
+  CallStackUnderflow(usize),
+  #[error("restoresymmetries without savesymmetries at instruction {0}")]
+  SymmetriesStackUnderflow(usize),
+  #[error("bad site number {site} at instruction {ip}")]
+  BadSiteNumber { site: u128, ip: usize },
+  #[error("bad layer {layer} at instruction {ip}")]
+  BadLayer { layer: u128, ip: usize },
+  #[error("bond longer than 4 sites at instruction {0}")]
+  BondTooLong(usize),
+  #[error("unsupported instruction {0}")]
+  UnsupportedInstruction(&'static str),
+  #[error("event exceeded the instruction budget of {0}")]
+  BudgetExceeded(u64),
+  #[error("watchpoint {watch} hit by the event at ({x}, {y})")]
+  Watchpoint { watch: usize, x: usize, y: usize },
+  #[error("assertion failed at instruction {0}")]
+  AssertionFailed(usize),
+  #[error("write to site {site} outside radius {radius} at instruction {ip}")]
+  WriteOutsideRadius { site: usize, radius: u8, ip: usize },
+  #[error("R{register} read before it was written at instruction {ip}")]
+  UninitializedRegister { register: u8, ip: usize },
+}
+
+/// What a grid does with an event which runs out of instruction budget.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BudgetPolicy {
+  /// Drop the event, leaving its window untouched.
+  Discard,
+  /// Keep whatever the event wrote before it was stopped.
+  Commit,
+  /// Fail the event with `Error::BudgetExceeded`.
+  Fail,
+}
+
+impl std::str::FromStr for BudgetPolicy {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "discard" => Ok(Self::Discard),
+      "commit" => Ok(Self::Commit),
+      "fail" => Ok(Self::Fail),
+      _ => Err(format!("unknown budget policy: {}", s)),
+    }
+  }
+}
+
+/// What an `assert` which fails does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AssertPolicy {
+  /// Carry on with the event.
+  Log,
+  /// Paint the event's own site red and carry on with the event.
+  Paint,
+  /// Drop the event, leaving its window untouched.
+  Abort,
+  /// Fail the event with `Error::AssertionFailed`, stopping the simulation.
+  Halt,
+}
+
+impl std::str::FromStr for AssertPolicy {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "log" => Ok(Self::Log),
+      "paint" => Ok(Self::Paint),
+      "abort" => Ok(Self::Abort),
+      "halt" => Ok(Self::Halt),
+      _ => Err(format!("unknown assert policy: {}", s)),
+    }
+  }
+}
+
+/// The paint `AssertPolicy::Paint` gives a site, opaque red.
+const ASSERT_PAINT: u32 = 0xff0000ff;
+
+/// Fits `v` to the width of field `f` as `mode` says: wrapping keeps its low
+/// bits, saturating clamps it to the field's range and checked gives `None`.
+fn fit_field(v: Const, f: FieldSelector, mode: ArithMode) -> Option<Const> {
+  let max = f.max_value();
+  let negative = v.is_negative();
+  if !negative && v.as_u128() <= max {
+    return Some(v);
+  }
+  match mode {
+    ArithMode::Wrapping => Some(v),
+    ArithMode::Saturating if negative => Some(Const::Unsigned(0)),
+    ArithMode::Saturating => Some(Const::Unsigned(max)),
+    ArithMode::Checked => None,
+  }
+}
+
+/// The diffusability at which `diffuse` always moves when it can.
+const FULL_DIFFUSABILITY: u128 = 100;
+
+/// Divides `a` by `b`, or gives the remainder if `rem`, dividing by zero as
+/// `policy` says.
+fn divide(
+  a: Const,
+  b: Const,
+  rem: bool,
+  mode: ArithMode,
+  policy: DivZeroPolicy,
+) -> Result<Const, Error> {
+  if a.divides_by_zero(b, rem) {
+    return policy
+      .divide_by_zero(a, rem, mode)
+      .ok_or(Error::DivisionByZero);
+  }
+  let r = if rem { mode.rem(a, b) } else { mode.div(a, b) };
+  r.ok_or(Error::ArithmeticOverflow)
 }
 
 pub fn load_from_bytes<'input>(bytes: &'input mut &[u8]) -> Result<Runtime<'input>, Error> {
@@ -41,12 +261,23 @@ pub fn load_from_bytes<'input>(bytes: &'input mut &[u8]) -> Result<Runtime<'inpu
   Ok(r)
 }
 
-const MAGIC_NUMBER: u32 = 0x02030741;
+/// The first four bytes of every compiled element, big endian.
+pub const MAGIC_NUMBER: u32 = 0x02030741;
+/// The first four bytes of every bundle of elements linked by `ewac link`.
+pub const BUNDLE_MAGIC_NUMBER: u32 = 0x02030742;
 
 #[derive(Clone, Debug)]
 struct Element<'input> {
   metadata: mfm::Metadata,
+  /// The element's bytecode, decoded once when it is loaded. Jump targets,
+  /// field selectors, type numbers and pooled constants are resolved then, so
+  /// events run these directly and never read bytecode.
   code: Vec<Instruction<'input>>,
+  labels: Vec<(String, u16)>,
+  /// The pool slot of each parameter's default.
+  parameter_slots: HashMap<u16, String>,
+  /// The `getparameter` instructions reading each parameter.
+  parameter_uses: HashMap<String, Vec<usize>>,
 }
 
 impl Element<'_> {
@@ -54,28 +285,179 @@ impl Element<'_> {
     Self {
       metadata: mfm::Metadata::new(),
       code: Vec::new(),
+      labels: Vec::new(),
+      parameter_slots: HashMap::new(),
+      parameter_uses: HashMap::new(),
     }
   }
 }
 
-#[derive(Debug)]
-struct Cursor {
+/// The interpreter state of an event: its instruction pointer and stacks.
+#[derive(Clone, Debug)]
+pub struct Cursor {
   ip: usize,
-  symmetries: Symmetries,
   symmetries_stack: Vec<Symmetries>,
   call_stack: Vec<usize>,
   op_stack: Vec<Const>,
+  registers: [Const; Instruction::REGISTERS as usize],
+  flags: Flags,
+  executed: u64,
+  assertions_failed: u64,
+  watch_hits: Vec<WatchHit>,
+  /// The window sites the event has read and written, one bit per site.
+  sites_read: u64,
+  sites_written: u64,
+  /// The registers the event has set, one bit per register.
+  registers_written: u16,
+  max_op_depth: usize,
+  max_call_depth: usize,
 }
 
 impl Cursor {
+  pub const DEFAULT_MAX_STACK_DEPTH: usize = 1024;
+  pub const DEFAULT_MAX_CALL_DEPTH: usize = 256;
+
   fn new() -> Self {
     Self {
       ip: 0,
-      symmetries: Symmetries::R000L,
       symmetries_stack: Vec::new(),
       call_stack: Vec::new(),
       op_stack: Vec::new(),
+      registers: [Const::Unsigned(0); Instruction::REGISTERS as usize],
+      flags: Flags::default(),
+      executed: 0,
+      assertions_failed: 0,
+      watch_hits: Vec::new(),
+      sites_read: 0,
+      sites_written: 0,
+      registers_written: 0,
+      max_op_depth: Self::DEFAULT_MAX_STACK_DEPTH,
+      max_call_depth: Self::DEFAULT_MAX_CALL_DEPTH,
+    }
+  }
+
+  /// The instruction being run, or the one which failed.
+  pub fn ip(&self) -> usize {
+    self.ip
+  }
+
+  /// The operand stack, bottom first.
+  pub fn op_stack(&self) -> &[Const] {
+    &self.op_stack
+  }
+
+  /// The instruction numbers of the pending calls, outermost first.
+  pub fn call_stack(&self) -> &[usize] {
+    &self.call_stack
+  }
+
+  /// The registers `R0` to `R14`.
+  pub fn registers(&self) -> &[Const] {
+    &self.registers
+  }
+
+  pub fn flags(&self) -> Flags {
+    self.flags
+  }
+
+  pub fn symmetries_stack(&self) -> &[Symmetries] {
+    &self.symmetries_stack
+  }
+
+  /// The number of instructions the event has run.
+  pub fn executed(&self) -> u64 {
+    self.executed
+  }
+
+  /// The number of `assert` instructions which have failed in the event.
+  pub fn assertions_failed(&self) -> u64 {
+    self.assertions_failed
+  }
+
+  /// The watchpoints the event has hit, in the order it hit them.
+  pub fn watch_hits(&self) -> &[WatchHit] {
+    &self.watch_hits
+  }
+
+  /// The window sites the event has read, as a mask with bit `N` set if it
+  /// read site `N` as it numbers them: its atom, paint, layers or heat.
+  pub fn sites_read(&self) -> u64 {
+    self.sites_read
+  }
+
+  /// The window sites the event has written, as a mask like `sites_read`,
+  /// whether or not the values written differ from those there.
+  pub fn sites_written(&self) -> u64 {
+    self.sites_written
+  }
+
+  /// Notes that the event reads site `i`.
+  fn read(&mut self, i: usize) {
+    self.sites_read |= 1 << i;
+  }
+
+  /// Notes that the event writes site `i`, failing with
+  /// `WriteOutsideRadius` if `radius` is given and the site is further from
+  /// the center.
+  fn write(&mut self, i: usize, radius: Option<u8>) -> Result<(), Error> {
+    self.sites_written |= 1 << i;
+    match (radius, SiteNumber(i as u8).radius()) {
+      (Some(radius), Some(r)) if r > radius => Err(Error::WriteOutsideRadius {
+        site: i,
+        radius,
+        ip: self.ip,
+      }),
+      _ => Ok(()),
+    }
+  }
+
+  fn push(&mut self, c: Const) -> Result<(), Error> {
+    if self.op_stack.len() >= self.max_op_depth {
+      return Err(Error::StackOverflow(self.ip));
+    }
+    self.op_stack.push(c);
+    Ok(())
+  }
+
+  fn call(&mut self) -> Result<(), Error> {
+    if self.call_stack.len() >= self.max_call_depth {
+      return Err(Error::CallStackOverflow(self.ip));
+    }
+    self.call_stack.push(self.ip);
+    Ok(())
+  }
+
+  fn pop(&mut self) -> Result<Const, Error> {
+    self.op_stack.pop().ok_or(Error::StackUnderflow(self.ip))
+  }
+
+  /// Pops a site number.
+  fn pop_site(&mut self) -> Result<usize, Error> {
+    let i = self.pop()?.as_u128();
+    if i >= SiteNumber::COUNT as u128 {
+      return Err(self.bad_site(i));
     }
+    Ok(i as usize)
+  }
+
+  fn bad_site(&self, site: u128) -> Error {
+    Error::BadSiteNumber { site, ip: self.ip }
+  }
+
+  fn pop_layer(&mut self) -> Result<usize, Error> {
+    let layer = self.pop()?.as_u128();
+    if layer >= LAYERS as u128 {
+      return Err(Error::BadLayer { layer, ip: self.ip });
+    }
+    Ok(layer as usize)
+  }
+
+  /// Fails unless at least `n` values are on the stack.
+  fn require(&self, n: usize) -> Result<(), Error> {
+    if self.op_stack.len() < n {
+      return Err(Error::StackUnderflow(self.ip));
+    }
+    Ok(())
   }
 
   fn reset(&mut self) {
@@ -83,35 +465,402 @@ impl Cursor {
     self.symmetries_stack.clear();
     self.call_stack.clear();
     self.op_stack.clear();
+    self.registers = [Const::Unsigned(0); Instruction::REGISTERS as usize];
+    self.flags = Flags::default();
+    self.executed = 0;
+    self.assertions_failed = 0;
+    self.watch_hits.clear();
+    self.sites_read = 0;
+    self.sites_written = 0;
+    self.registers_written = 0;
   }
 }
 
+impl fmt::Display for Cursor {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    // Only the top of a deep stack is shown.
+    const SHOWN: usize = 8;
+    write!(f, "at instruction {}, stack [", self.ip)?;
+    let hidden = self.op_stack.len().saturating_sub(SHOWN);
+    if hidden > 0 {
+      write!(f, "{} more, ", hidden)?;
+    }
+    for (i, c) in self.op_stack[hidden..].iter().enumerate() {
+      write!(f, "{}{}", if i > 0 { ", " } else { "" }, c)?;
+    }
+    write!(f, "], calls {:?}", self.call_stack)
+  }
+}
+
+/// Called with the site number and contents of an atom which failed its ECC
+/// check and could not be corrected.
+pub type CorruptAtomHook = Arc<dyn Fn(usize, Atom) + Send + Sync>;
+
+/// Called with the type number of the element and the instruction number of
+/// each `assert` which fails, whatever the assert policy.
+pub type AssertHook = Arc<dyn Fn(u16, usize) + Send + Sync>;
+
+/// Called before each instruction of an event run with
+/// `Runtime::execute_stepping`, with the interpreter state and the window as
+/// the event has left it so far. Returning false stops the event as `exit`
+/// would.
+pub type StepHook<'a> = dyn FnMut(&Cursor, &mfm::EventWindow) -> bool + 'a;
+
+#[derive(Clone)]
 pub struct Runtime<'input> {
   tag: Option<String>,
+  registry: ElementRegistry,
   element_map: HashMap<u16, Element<'input>>,
+  verify_ecc: bool,
+  strict: bool,
+  corrupt_atom_hook: Option<CorruptAtomHook>,
+  assert_hook: Option<AssertHook>,
+  observers: Vec<Arc<dyn EventObserver>>,
+  invariants: Vec<(Arc<dyn GridInvariant>, u64)>,
+  tracer: Option<Arc<Tracer>>,
+  profiler: Option<Arc<Profiler>>,
+  crash_recorder: Option<Arc<CrashRecorder>>,
+  rng: Prng,
+  ids: Arc<AtomicU64>,
+  counters: Arc<[AtomicU64; Instruction::COUNTERS as usize]>,
+  event_symmetry: Symmetries,
+  instruction_budget: Option<u64>,
+  budget_policy: BudgetPolicy,
+  assert_policy: AssertPolicy,
+  watches: Vec<Watch>,
+  event_origin: Option<(usize, usize)>,
+  energy: Option<EnergyModel>,
+  cursor: Cursor,
 }
 
 impl<'input> Runtime<'input> {
-  const MINOR_VERSION: u16 = 1;
+  const MINOR_VERSION: u16 = 16;
   const MAJOR_VERSION: u16 = 0;
 
+  /// The number of instructions an event may run before it is stopped.
+  pub const DEFAULT_INSTRUCTION_BUDGET: u64 = 1_000_000;
+
   pub fn new() -> Self {
     Self {
       tag: None,
+      registry: ElementRegistry::new(),
       element_map: Self::new_element_map(),
+      verify_ecc: false,
+      strict: false,
+      corrupt_atom_hook: None,
+      assert_hook: None,
+      observers: Vec::new(),
+      invariants: Vec::new(),
+      tracer: None,
+      profiler: None,
+      crash_recorder: None,
+      rng: Prng::from_entropy(),
+      ids: Arc::new(AtomicU64::new(1)),
+      counters: Arc::new(Default::default()),
+      event_symmetry: Symmetries::R000L,
+      instruction_budget: Some(Self::DEFAULT_INSTRUCTION_BUDGET),
+      budget_policy: BudgetPolicy::Discard,
+      assert_policy: AssertPolicy::Halt,
+      watches: Vec::new(),
+      event_origin: None,
+      energy: None,
+      cursor: Cursor::new(),
+    }
+  }
+
+  /// Enables ECC checking of every site read. Single bit errors are corrected
+  /// in place and atoms written by programs get their ECC bits recomputed.
+  pub fn set_verify_ecc(&mut self, verify: bool) {
+    self.verify_ecc = verify;
+  }
+
+  /// Has events fail with `WriteOutsideRadius` when they write a site further
+  /// from the center than their element's `.radius`, and with
+  /// `UninitializedRegister` when they read a register they haven't set,
+  /// rather than the 0 it holds.
+  pub fn set_strict(&mut self, strict: bool) {
+    self.strict = strict;
+  }
+
+  pub fn strict(&self) -> bool {
+    self.strict
+  }
+
+  pub fn on_corrupt_atom(&mut self, hook: CorruptAtomHook) {
+    self.corrupt_atom_hook = Some(hook);
+  }
+
+  pub fn on_assert_failure(&mut self, hook: AssertHook) {
+    self.assert_hook = Some(hook);
+  }
+
+  /// Tells `observer` about every event executed from now on. Clones of the
+  /// runtime, such as those running tiles on other threads, share it.
+  pub fn add_observer(&mut self, observer: Arc<dyn EventObserver>) {
+    self.observers.push(observer);
+  }
+
+  pub(crate) fn observers(&self) -> &[Arc<dyn EventObserver>] {
+    &self.observers
+  }
+
+  /// Has grids run by this runtime check `invariant` every `every` events,
+  /// telling the observers about each violation.
+  pub fn add_invariant(&mut self, invariant: Arc<dyn GridInvariant>, every: u64) {
+    self.invariants.push((invariant, every.max(1)));
+  }
+
+  /// The invariants, with how many events apart each is checked.
+  pub fn invariants(&self) -> &[(Arc<dyn GridInvariant>, u64)] {
+    &self.invariants
+  }
+
+  /// Traces the instructions of events from now on with `tracer`. Clones of
+  /// the runtime share it.
+  pub fn set_tracer(&mut self, tracer: Option<Arc<Tracer>>) {
+    self.tracer = tracer;
+  }
+
+  /// Counts the instructions of events from now on with `profiler`. Clones
+  /// of the runtime share it.
+  pub fn set_profiler(&mut self, profiler: Option<Arc<Profiler>>) {
+    self.profiler = profiler;
+  }
+
+  /// Keeps the first event from now on which fails with an error stopping
+  /// the run in `recorder`. Clones of the runtime share it.
+  pub fn set_crash_recorder(&mut self, recorder: Option<Arc<CrashRecorder>>) {
+    self.crash_recorder = recorder;
+  }
+
+  pub fn crash_recorder(&self) -> Option<&Arc<CrashRecorder>> {
+    self.crash_recorder.as_ref()
+  }
+
+  /// Limits the number of instructions run by each event, so an element
+  /// which loops forever can't hang the simulation. `None` removes the limit.
+  pub fn set_instruction_budget(&mut self, budget: Option<u64>) {
+    self.instruction_budget = budget;
+  }
+
+  pub fn instruction_budget(&self) -> Option<u64> {
+    self.instruction_budget
+  }
+
+  pub fn set_budget_policy(&mut self, policy: BudgetPolicy) {
+    self.budget_policy = policy;
+  }
+
+  pub fn budget_policy(&self) -> BudgetPolicy {
+    self.budget_policy
+  }
+
+  pub fn set_assert_policy(&mut self, policy: AssertPolicy) {
+    self.assert_policy = policy;
+  }
+
+  pub fn assert_policy(&self) -> AssertPolicy {
+    self.assert_policy
+  }
+
+  /// Has grids run by this runtime follow `model`, whose layer must be less
+  /// than `LAYERS`, or no energy model. Without one, `getheat` and
+  /// `absorbheat` give 0 and `emitheat` does nothing.
+  pub fn set_energy_model(&mut self, model: Option<EnergyModel>) {
+    self.energy = model;
+  }
+
+  pub fn energy_model(&self) -> Option<EnergyModel> {
+    self.energy
+  }
+
+  /// Checks `watch` at every atom events store from now on, numbering it
+  /// after the watchpoints already added.
+  pub fn add_watch(&mut self, watch: Watch) {
+    self.watches.push(watch);
+  }
+
+  pub fn watches(&self) -> &[Watch] {
+    &self.watches
+  }
+
+  /// The grid site the next event is centered on, which site watchpoints
+  /// need. Set by the grid before each event.
+  pub(crate) fn set_event_origin(&mut self, origin: Option<(usize, usize)>) {
+    self.event_origin = origin;
+  }
+
+  /// Limits the depth of the operand stack. Pushing beyond it fails the
+  /// event with `StackOverflow`.
+  pub fn set_max_stack_depth(&mut self, depth: usize) {
+    self.cursor.max_op_depth = depth;
+  }
+
+  /// Limits the number of nested calls. Calling beyond it fails the event
+  /// with `CallStackOverflow`.
+  pub fn set_max_call_depth(&mut self, depth: usize) {
+    self.cursor.max_call_depth = depth;
+  }
+
+  /// The interpreter state at the end of the most recent event. After a
+  /// failed event it shows the instruction and stacks where it stopped.
+  pub fn cursor(&self) -> &Cursor {
+    &self.cursor
+  }
+
+  /// Restarts the random number generator from `seed`. Two runtimes with
+  /// the same seed and elements make the same choices.
+  pub fn reseed(&mut self, seed: u64) {
+    self.rng = Prng::new(seed);
+  }
+
+  pub fn seed(&self) -> u64 {
+    self.rng.seed()
+  }
+
+  pub fn rng(&self) -> &Prng {
+    &self.rng
+  }
+
+  pub fn rng_mut(&mut self) -> &mut Prng {
+    &mut self.rng
+  }
+
+  pub fn set_rng(&mut self, rng: Prng) {
+    self.rng = rng;
+  }
+
+  /// The id `newid` hands out next. Ids start at 1, so 0 can mark an atom
+  /// which hasn't been given one, and each is handed out once: clones of a
+  /// runtime, such as those `Grid::run_tiles` runs threads with, share the
+  /// counter.
+  pub fn next_id(&self) -> u64 {
+    self.ids.load(Ordering::Relaxed)
+  }
+
+  /// Makes `newid` continue from `id`, as when resuming a snapshot.
+  pub fn set_next_id(&mut self, id: u64) {
+    self.ids.store(id, Ordering::Relaxed);
+  }
+
+  /// The counters `count` adds to, by number. Like the ids of `newid`, they
+  /// are shared by clones of the runtime.
+  pub fn counters(&self) -> [u64; Instruction::COUNTERS as usize] {
+    let mut counters = [0; Instruction::COUNTERS as usize];
+    for (n, c) in counters.iter_mut().zip(self.counters.iter()) {
+      *n = c.load(Ordering::Relaxed);
     }
+    counters
+  }
+
+  /// The symmetry picked at the start of the most recent event.
+  pub fn event_symmetry(&self) -> Symmetries {
+    self.event_symmetry
+  }
+
+  /// The build tag of the elements loaded so far.
+  pub fn build_tag(&self) -> Option<&str> {
+    self.tag.as_deref()
+  }
+
+  /// Numbers element types with `registry`, such as one read from a
+  /// numbering file. Must be called before any element is loaded.
+  pub fn set_registry(&mut self, registry: ElementRegistry) {
+    self.registry = registry;
+  }
+
+  pub fn registry(&self) -> &ElementRegistry {
+    &self.registry
   }
 
   fn new_element_map() -> HashMap<u16, Element<'input>> {
     let mut m = HashMap::new();
     let mut empty = Element::new();
-    empty.metadata.name = "Empty".to_owned();
+    empty.metadata.name = ElementRegistry::EMPTY.to_owned();
     m.insert(0, empty);
     m
   }
 
-  fn read_const<R: ReadBytesExt>(r: &mut R) -> Result<Const, Error> {
-    todo!()
+  /// The metadata of every loaded element, by type number.
+  pub fn elements(&self) -> impl Iterator<Item = (u16, &mfm::Metadata)> {
+    self.element_map.iter().map(|(t, e)| (*t, &e.metadata))
+  }
+
+  pub fn metadata(&self, type_num: u16) -> Option<&mfm::Metadata> {
+    Some(&self.element_map.get(&type_num)?.metadata)
+  }
+
+  /// Overrides parameter `name` of the loaded element `element`, so that its
+  /// `getparameter` reads `value` instead of the default it was compiled with.
+  /// Constant expressions which used the parameter keep the default.
+  pub fn set_parameter(&mut self, element: &str, name: &str, value: Const) -> Result<(), Error> {
+    let elem = self
+      .registry
+      .get(element)
+      .and_then(|t| self.element_map.get_mut(&t))
+      .ok_or_else(|| Error::UnknownElementName(element.to_owned()))?;
+    let param = elem
+      .metadata
+      .parameter_map
+      .get_mut(name)
+      .ok_or_else(|| Error::UnknownParameter(element.to_owned(), name.to_owned()))?;
+    *param = value;
+    for ip in elem.parameter_uses.get(name).into_iter().flatten() {
+      elem.code[*ip] = Instruction::GetParameter(Arg::Runtime(value));
+    }
+    Ok(())
+  }
+
+  pub fn code(&self, type_num: u16) -> Option<&[Instruction<'input>]> {
+    Some(&self.element_map.get(&type_num)?.code)
+  }
+
+  /// The labels of an element's source and the instruction each marks,
+  /// ordered by instruction.
+  pub fn labels(&self, type_num: u16) -> Option<&[(String, u16)]> {
+    Some(&self.element_map.get(&type_num)?.labels)
+  }
+
+  pub fn get_field(&self, type_num: u16, name: &str) -> Option<FieldSelector> {
+    self
+      .element_map
+      .get(&type_num)?
+      .metadata
+      .field_map
+      .get(name)
+  }
+
+  fn read_pool_const<R: ReadBytesExt>(r: &mut R) -> Result<Const, Error> {
+    let t = r.read_u8()?;
+    let bits = t & 0x7f;
+    // A zero type is a fixed point number, followed by its fraction bits.
+    let frac = match t {
+      0 => Some(r.read_u8()?),
+      _ => None,
+    };
+    if matches!(frac, Some(f) if f == 0 || f > Const::MAX_FRAC) {
+      return Err(Error::BadConstType(t));
+    }
+    if frac.is_none() && (bits == 0 || bits > Const::MAX_BITS) {
+      return Err(Error::BadConstType(t));
+    }
+    let hi = r.read_u32::<BigEndian>()? as u128;
+    let lo = r.read_u64::<BigEndian>()? as u128;
+    let v = hi << 64 | lo;
+    if let Some(frac) = frac {
+      Ok(Const::fixed(v as i128, frac))
+    } else if t & 0x80 != 0 {
+      Ok(Const::signed(v as i128, bits))
+    } else if bits == Const::MAX_BITS {
+      Ok(Const::Unsigned(v))
+    } else {
+      Err(Error::BadConstType(t))
+    }
+  }
+
+  fn read_const<R: ReadBytesExt>(r: &mut R, pool: &[Const]) -> Result<Const, Error> {
+    let i = r.read_u16::<BigEndian>()?;
+    pool.get(i as usize).copied().ok_or(Error::BadConstIndex(i))
   }
 
   fn read_string<R: ReadBytesExt>(r: &mut R) -> Result<String, Error> {
@@ -121,7 +870,11 @@ impl<'input> Runtime<'input> {
     Ok(String::from_utf8(b)?)
   }
 
-  fn read_metadata<R: ReadBytesExt>(r: &mut R, elem: &mut Element) -> Result<(), Error> {
+  fn read_metadata<R: ReadBytesExt>(
+    r: &mut R,
+    elem: &mut Element,
+    pool: &[Const],
+  ) -> Result<(), Error> {
     let op = r.read_u8()?;
     match op {
       0 => elem.metadata.name = Self::read_string(r)?, // Name
@@ -137,131 +890,252 @@ impl<'input> Runtime<'input> {
         // Field
         let i = Self::read_string(r)?;
         let f: FieldSelector = r.read_u16::<BigEndian>()?.into();
-        elem.metadata.field_map.insert(i, f);
+        elem.metadata.field_map.insert(&i, f)?;
       }
       10 => {
         // Parameter
         let i = Self::read_string(r)?;
-        let c = Self::read_const(r)?;
+        let slot = r.read_u16::<BigEndian>()?;
+        let c = pool.get(slot as usize).copied().ok_or(Error::BadConstIndex(slot))?;
+        elem.parameter_slots.insert(slot, i.clone());
         elem.metadata.parameter_map.insert(i, c);
       }
+      11 => {
+        // Arithmetic
+        let x = r.read_u8()?;
+        elem.metadata.arith_mode = ArithMode::from_u8(x).ok_or(Error::BadArithMode(x))?;
+      }
+      12 => elem.metadata.mfm_type = Some(r.read_u16::<BigEndian>()?), // MfmType
+      13 => elem.metadata.color = Some(r.read_u32::<BigEndian>()?.into()), // Color
+      14 => {
+        // FieldWrites
+        let x = r.read_u8()?;
+        elem.metadata.field_writes = ArithMode::from_u8(x).ok_or(Error::BadArithMode(x))?;
+      }
+      15 => {
+        // DivZero
+        let x = r.read_u8()?;
+        elem.metadata.div_zero = DivZeroPolicy::from_u8(x).ok_or(Error::BadDivZeroPolicy(x))?;
+      }
+      16 => {
+        // Bond
+        let i = Self::read_string(r)?;
+        let f: FieldSelector = r.read_u16::<BigEndian>()?.into();
+        elem.metadata.field_map.insert(&i, f)?;
+        elem.metadata.bonds.push(f);
+      }
       i => return Err(Error::BadMetadataOpCode(i)),
     }
     Ok(())
   }
 
-  fn read_instruction<R: ReadBytesExt>(r: &mut R, elem: &mut Element) -> Result<(), Error> {
+  fn read_instruction<R: ReadBytesExt>(
+    r: &mut R,
+    elem: &mut Element,
+    pool: &[Const],
+    types: &[u16],
+  ) -> Result<(), Error> {
     let op = r.read_u8()?;
+    let mut width = || match r.read_u8()? {
+      n @ 1..=Const::MAX_BITS => Ok(n),
+      n => Err(Error::BadWidth(n)),
+    };
     let instr = match op {
       0 => Instruction::Nop,       // Nop
       1 => Instruction::Exit,      // Exit
       2 => Instruction::SwapSites, // SwapSites
       3 => Instruction::SetSite,   // SetSite
-      4 => Instruction::SetSite,   // SetField
+      4 => Instruction::SetField(Arg::Runtime(r.read_u16::<BigEndian>()?.into())), // SetField
       5 => Instruction::SetSiteField(Arg::Runtime(r.read_u16::<BigEndian>()?.into())), // SetSiteField
       6 => Instruction::GetSite,                                                       // GetSite
       7 => Instruction::GetField(Arg::Runtime(r.read_u16::<BigEndian>()?.into())),     // GetField
       8 => Instruction::GetSiteField(Arg::Runtime(r.read_u16::<BigEndian>()?.into())), // GetSiteField
-      9 => Instruction::GetType(Arg::Runtime(r.read_u16::<BigEndian>()?)),             // GetType
-      10 => Instruction::GetParameter(Arg::Runtime(Self::read_const(r)?)), // GetParamter
-      11 => Instruction::Scan,                                             // Scan
-      12 => Instruction::SaveSymmetries,                                   // SaveSymmetries
-      13 => Instruction::UseSymmetries(r.read_u8()?.into()),               // UseSymmetries
-      14 => Instruction::RestoreSymmetries,                                // RestoreSymmetries
-      15 => Instruction::Push0,                                            // Push0
-      16 => Instruction::Push1,                                            // Push1
-      17 => Instruction::Push2,                                            // Push2
-      18 => Instruction::Push3,                                            // Push3
-      19 => Instruction::Push4,                                            // Push4
-      20 => Instruction::Push5,                                            // Push5
-      21 => Instruction::Push6,                                            // Push6
-      22 => Instruction::Push7,                                            // Push7
-      23 => Instruction::Push8,                                            // Push8
-      24 => Instruction::Push9,                                            // Push9
-      25 => Instruction::Push10,                                           // Push10
-      26 => Instruction::Push11,                                           // Push11
-      27 => Instruction::Push12,                                           // Push12
-      28 => Instruction::Push13,                                           // Push13
-      29 => Instruction::Push14,                                           // Push14
-      30 => Instruction::Push15,                                           // Push15
-      31 => Instruction::Push16,                                           // Push16
-      32 => Instruction::Push17,                                           // Push17
-      33 => Instruction::Push18,                                           // Push18
-      34 => Instruction::Push19,                                           // Push19
-      35 => Instruction::Push20,                                           // Push20
-      36 => Instruction::Push21,                                           // Push21
-      37 => Instruction::Push22,                                           // Push22
-      38 => Instruction::Push23,                                           // Push23
-      39 => Instruction::Push24,                                           // Push24
-      40 => Instruction::Push25,                                           // Push25
-      41 => Instruction::Push26,                                           // Push26
-      42 => Instruction::Push27,                                           // Push27
-      43 => Instruction::Push28,                                           // Push28
-      44 => Instruction::Push29,                                           // Push29
-      45 => Instruction::Push30,                                           // Push30
-      46 => Instruction::Push31,                                           // Push31
-      47 => Instruction::Push32,                                           // Push32
-      48 => Instruction::Push33,                                           // Push33
-      49 => Instruction::Push34,                                           // Push34
-      50 => Instruction::Push35,                                           // Push35
-      51 => Instruction::Push36,                                           // Push36
-      52 => Instruction::Push37,                                           // Push37
-      53 => Instruction::Push38,                                           // Push38
-      54 => Instruction::Push39,                                           // Push39
-      55 => Instruction::Push40,                                           // Push40
-      56 => Instruction::Push(Self::read_const(r)?),                       // Push
-      57 => Instruction::Pop,                                              // Pop
-      58 => Instruction::Dup,                                              // Dup
-      59 => Instruction::Over,                                             // Over
-      60 => Instruction::Swap,                                             // Swap
-      61 => Instruction::Rot,                                              // Rot
-      62 => Instruction::Call(Arg::Runtime(r.read_u16::<BigEndian>()?)),   // Call
-      63 => Instruction::Ret,                                              // Ret
-      64 => Instruction::Checksum,                                         // Checksum
-      65 => Instruction::Add,                                              // Add
-      66 => Instruction::Sub,                                              // Sub
-      67 => Instruction::Neg,                                              // Neg
-      68 => Instruction::Mod,                                              // Mod
-      69 => Instruction::Mul,                                              // Mul
-      70 => Instruction::Div,                                              // Div
-      71 => Instruction::Less,                                             // Less
-      72 => Instruction::LessEqual,                                        // LessEqual
-      73 => Instruction::Or,                                               // Or
-      74 => Instruction::And,                                              // And
-      75 => Instruction::Xor,                                              // Xor
-      76 => Instruction::Equal,                                            // Equal
-      77 => Instruction::BitCount,                                         // BitCount
-      78 => Instruction::BitScanForward,                                   // BitScanForward
-      79 => Instruction::BitScanReverse,                                   // BitScanReverse
-      80 => Instruction::LShift,                                           // LShift
-      81 => Instruction::RShift,                                           // RShift
-      82 => Instruction::Jump(Arg::Runtime(r.read_u16::<BigEndian>()?)),   // Jump
-      83 => Instruction::JumpRelativeOffset,                               // JumpRelativeOffset
+      9 => {
+        // GetType
+        let i = r.read_u16::<BigEndian>()?;
+        Instruction::GetType(Arg::Runtime(
+          *types.get(i as usize).ok_or(Error::BadTypeIndex(i))?,
+        ))
+      }
+      10 => {
+        // GetParameter
+        let slot = r.read_u16::<BigEndian>()?;
+        let c = pool.get(slot as usize).copied().ok_or(Error::BadConstIndex(slot))?;
+        if let Some(name) = elem.parameter_slots.get(&slot) {
+          let uses = elem.parameter_uses.entry(name.clone()).or_default();
+          uses.push(elem.code.len());
+        }
+        Instruction::GetParameter(Arg::Runtime(c))
+      }
+      11 => Instruction::Scan,                                                   // Scan
+      12 => Instruction::SaveSymmetries,                                         // SaveSymmetries
+      13 => Instruction::UseSymmetries(r.read_u8()?.into()),                     // UseSymmetries
+      14 => Instruction::RestoreSymmetries, // RestoreSymmetries
+      15 => Instruction::Push0,             // Push0
+      16 => Instruction::Push1,             // Push1
+      17 => Instruction::Push2,             // Push2
+      18 => Instruction::Push3,             // Push3
+      19 => Instruction::Push4,             // Push4
+      20 => Instruction::Push5,             // Push5
+      21 => Instruction::Push6,             // Push6
+      22 => Instruction::Push7,             // Push7
+      23 => Instruction::Push8,             // Push8
+      24 => Instruction::Push9,             // Push9
+      25 => Instruction::Push10,            // Push10
+      26 => Instruction::Push11,            // Push11
+      27 => Instruction::Push12,            // Push12
+      28 => Instruction::Push13,            // Push13
+      29 => Instruction::Push14,            // Push14
+      30 => Instruction::Push15,            // Push15
+      31 => Instruction::Push16,            // Push16
+      32 => Instruction::Push17,            // Push17
+      33 => Instruction::Push18,            // Push18
+      34 => Instruction::Push19,            // Push19
+      35 => Instruction::Push20,            // Push20
+      36 => Instruction::Push21,            // Push21
+      37 => Instruction::Push22,            // Push22
+      38 => Instruction::Push23,            // Push23
+      39 => Instruction::Push24,            // Push24
+      40 => Instruction::Push25,            // Push25
+      41 => Instruction::Push26,            // Push26
+      42 => Instruction::Push27,            // Push27
+      43 => Instruction::Push28,            // Push28
+      44 => Instruction::Push29,            // Push29
+      45 => Instruction::Push30,            // Push30
+      46 => Instruction::Push31,            // Push31
+      47 => Instruction::Push32,            // Push32
+      48 => Instruction::Push33,            // Push33
+      49 => Instruction::Push34,            // Push34
+      50 => Instruction::Push35,            // Push35
+      51 => Instruction::Push36,            // Push36
+      52 => Instruction::Push37,            // Push37
+      53 => Instruction::Push38,            // Push38
+      54 => Instruction::Push39,            // Push39
+      55 => Instruction::Push40,            // Push40
+      56 => Instruction::Push(Self::read_const(r, pool)?), // Push
+      57 => Instruction::Pop,               // Pop
+      58 => Instruction::Dup,               // Dup
+      59 => Instruction::Over,              // Over
+      60 => Instruction::Swap,              // Swap
+      61 => Instruction::Rot,               // Rot
+      62 => Instruction::Call(Arg::Runtime(r.read_u16::<BigEndian>()?)), // Call
+      63 => Instruction::Ret,               // Ret
+      64 => Instruction::Checksum,          // Checksum
+      65 => Instruction::Add,               // Add
+      66 => Instruction::Sub,               // Sub
+      67 => Instruction::Neg,               // Neg
+      68 => Instruction::Mod,               // Mod
+      69 => Instruction::Mul,               // Mul
+      70 => Instruction::Div,               // Div
+      71 => Instruction::Less,              // Less
+      72 => Instruction::LessEqual,         // LessEqual
+      73 => Instruction::Or,                // Or
+      74 => Instruction::And,               // And
+      75 => Instruction::Xor,               // Xor
+      76 => Instruction::Equal,             // Equal
+      77 => Instruction::BitCount,          // BitCount
+      78 => Instruction::BitScanForward,    // BitScanForward
+      79 => Instruction::BitScanReverse,    // BitScanReverse
+      80 => Instruction::LShift,            // LShift
+      81 => Instruction::RShift,            // RShift
+      82 => Instruction::Jump(Arg::Runtime(r.read_u16::<BigEndian>()?)), // Jump
+      83 => Instruction::JumpRelativeOffset, // JumpRelativeOffset
       84 => Instruction::JumpZero(Arg::Runtime(r.read_u16::<BigEndian>()?)), // JumpZero
       85 => Instruction::JumpNonZero(Arg::Runtime(r.read_u16::<BigEndian>()?)), // JumpNonZero
       86 => Instruction::SetPaint,
       87 => Instruction::GetPaint,
+      88 => Instruction::MoveSite,
+      89 => Instruction::Diffuse,
+      90 => Instruction::CountOf,
+      91 => Instruction::FindFirst,
+      92 => Instruction::FindRandom,
+      93 => Instruction::Rand,
+      94 => Instruction::OddsOf,
+      95 => Instruction::RandSite,
+      96 => Instruction::Compare,
+      107 => Instruction::ZeroExtend(width()?),
+      108 => Instruction::SignExtend(width()?),
+      109 => Instruction::Truncate(width()?),
+      110 => match r.read_u8()? {
+        n @ 0..=Instruction::REGISTERS => Instruction::GetRegister(n),
+        n => return Err(Error::BadRegister(n)),
+      },
+      111 => match r.read_u8()? {
+        n if n < Instruction::REGISTERS => Instruction::SetRegister(n),
+        n => return Err(Error::BadRegister(n)),
+      },
+      112 => Instruction::Assert,
+      113 => Instruction::PopCount,
+      114 => Instruction::LeadingZeros,
+      115 => Instruction::TrailingZeros,
+      116 => Instruction::RotateLeft,
+      117 => Instruction::RotateRight,
+      118 => Instruction::Min,
+      119 => Instruction::Max,
+      120 => Instruction::Clamp,
+      121 => Instruction::Abs,
+      122 => Instruction::MulHigh,
+      123 => Instruction::GetLayer,
+      124 => Instruction::SetLayer,
+      125 => Instruction::DecayLayer,
+      126 => Instruction::GetHeat,
+      127 => Instruction::EmitHeat,
+      128 => Instruction::AbsorbHeat,
+      129 => Instruction::Hash,
+      130 => Instruction::NewId,
+      131 => Instruction::Bond(Arg::Runtime(r.read_u16::<BigEndian>()?.into())),
+      132 => Instruction::Unbond(Arg::Runtime(r.read_u16::<BigEndian>()?.into())),
+      133 => Instruction::FollowBond(Arg::Runtime(r.read_u16::<BigEndian>()?.into())),
+      134 => match r.read_u8()? {
+        n if n < Instruction::COUNTERS => Instruction::Count(n),
+        n => return Err(Error::BadCounter(n)),
+      },
+      i @ 97..=106 => Instruction::JumpIf(
+        Condition::ALL[(i - 97) as usize],
+        Arg::Runtime(r.read_u16::<BigEndian>()?),
+      ),
       i => return Err(Error::BadInstructionOpCode(i)),
     };
     elem.code.push(instr);
     Ok(())
   }
 
-  pub fn load_from_reader<R: ReadBytesExt>(&mut self, r: &mut R) -> Result<Const, Error> {
-    if let v = r.read_u32::<BigEndian>()? {
-      if v != MAGIC_NUMBER {
-        return Err(Error::BadMagicNumber(v));
-      }
+  /// Loads a compiled element, or every element of a linked bundle, giving
+  /// an atom of the element, or of the first in the bundle.
+  pub fn load_from_reader<R: ReadBytesExt>(&mut self, r: &mut R) -> Result<Atom, Error> {
+    Ok(self.load_code(r, false)?[0])
+  }
+
+  /// Loads a compiled element or a linked bundle as `load_from_reader` does,
+  /// giving an atom of each element in it.
+  pub fn load_all_from_reader<R: ReadBytesExt>(&mut self, r: &mut R) -> Result<Vec<Atom>, Error> {
+    self.load_code(r, false)
+  }
+
+  /// Loads an element as `load_from_reader` does, but replaces a loaded
+  /// element of the same name instead of failing. The element keeps its type
+  /// number, so atoms of it on a grid run the new code from their next event.
+  pub fn reload_from_reader<R: ReadBytesExt>(&mut self, r: &mut R) -> Result<Atom, Error> {
+    Ok(self.load_code(r, true)?[0])
+  }
+
+  fn load_code<R: ReadBytesExt>(&mut self, r: &mut R, replace: bool) -> Result<Vec<Atom>, Error> {
+    let magic = r.read_u32::<BigEndian>()?;
+    let bundle = match magic {
+      MAGIC_NUMBER => false,
+      BUNDLE_MAGIC_NUMBER => true,
+      _ => return Err(Error::BadMagicNumber(magic)),
+    };
+    let minor = r.read_u16::<BigEndian>()?;
+    let major = r.read_u16::<BigEndian>()?;
+    if (major, minor) > (Self::MAJOR_VERSION, Self::MINOR_VERSION) {
+      return Err(Error::NewerVersion { major, minor });
     }
-    if let v = r.read_u16::<BigEndian>()? {
-      if v != Self::MINOR_VERSION {
-        return Err(Error::BadMinorVersion(v));
-      }
+    if major != Self::MAJOR_VERSION {
+      return Err(Error::BadMajorVersion(major));
     }
-    if let v = r.read_u16::<BigEndian>()? {
-      if v != Self::MAJOR_VERSION {
-        return Err(Error::BadMajorVersion(v));
-      }
+    if minor != Self::MINOR_VERSION {
+      return Err(Error::BadMinorVersion(minor));
     }
     let tag = Self::read_string(r)?;
     if let Some(self_tag) = self.tag.as_ref() {
@@ -275,224 +1149,874 @@ impl<'input> Runtime<'input> {
       self.tag = Some(tag);
     }
 
-    let type_num = r.read_u16::<BigEndian>()?;
-    let mut elem = Element::new();
-
-    for _ in 0..r.read_u8()? {
-      Self::read_metadata(r, &mut elem)?;
+    let names = (0..r.read_u16::<BigEndian>()?)
+      .map(|_| Self::read_string(r))
+      .collect::<Result<Vec<_>, _>>()?;
+    let pool = (0..r.read_u16::<BigEndian>()?)
+      .map(|_| Self::read_pool_const(r))
+      .collect::<Result<Vec<_>, _>>()?;
+    // A bundle's elements are its first types, an element file's its first
+    // alone; the rest are those they refer to.
+    let count = if bundle {
+      r.read_u16::<BigEndian>()? as usize
+    } else {
+      1
+    };
+    if count == 0 || names.len() < count {
+      return Err(Error::NoElement);
+    }
+    let mut types = Vec::new();
+    for (i, name) in names.iter().enumerate() {
+      types.push(if i < count {
+        match self.registry.get(name) {
+          Some(t) if replace && self.element_map.contains_key(&t) => t,
+          _ => self.registry.define(name)?,
+        }
+      } else {
+        self.registry.number(name)?
+      });
     }
 
-    r.read_u16::<BigEndian>()?; // Code index stub
+    let mut elems = Vec::new();
+    for _ in 0..count {
+      let mut elem = Element::new();
+      for _ in 0..r.read_u8()? {
+        Self::read_metadata(r, &mut elem, &pool)?;
+      }
+
+      r.read_u16::<BigEndian>()?; // Code index stub
+
+      for _ in 0..r.read_u16::<BigEndian>()? {
+        Self::read_instruction(r, &mut elem, &pool, &types)?;
+      }
 
-    for _ in 0..r.read_u16::<BigEndian>()? {
-      Self::read_instruction(r, &mut elem)?;
+      for _ in 0..r.read_u16::<BigEndian>()? {
+        let name = Self::read_string(r)?;
+        elem.labels.push((name, r.read_u16::<BigEndian>()?));
+      }
+      elems.push(elem);
     }
 
-    self.element_map.insert(type_num, elem);
-    Ok(((type_num as u128) << 80).into())
+    let mut atoms = Vec::new();
+    for (t, elem) in types.into_iter().zip(elems) {
+      log::debug!(
+        target: "substrate::load",
+        "{} {} as type {}, {} instructions",
+        if replace { "reloaded" } else { "loaded" },
+        elem.metadata.name,
+        t,
+        elem.code.len()
+      );
+      self.element_map.insert(t, elem);
+      atoms.push(Atom::new(t));
+    }
+    Ok(atoms)
   }
 
+  /// Runs one event in `ew`. Fails with `BudgetExceeded` once the
+  /// instruction budget is spent, leaving in `ew` whatever the event wrote.
   pub fn execute(&mut self, ew: &mut mfm::EventWindow) -> Result<(), Error> {
-    let my_atom = ew.get(0).ok_or(Error::NoElement)?;
-    let my_type = my_atom.apply(FieldSelector::TYPE).as_u128() as u16;
+    self.execute_with(ew, None)
+  }
+
+  /// Runs one event in `ew` as `execute` does, calling `step` before each
+  /// instruction, such as to debug it one instruction at a time.
+  pub fn execute_stepping(
+    &mut self,
+    ew: &mut mfm::EventWindow,
+    step: &mut StepHook,
+  ) -> Result<(), Error> {
+    self.execute_with(ew, Some(step))
+  }
+
+  fn execute_with(
+    &mut self,
+    ew: &mut mfm::EventWindow,
+    step: Option<&mut StepHook>,
+  ) -> Result<(), Error> {
+    let tracer = self.tracer.clone();
+    let mut trace = tracer.as_ref().and_then(|t| t.begin(ew, self.event_origin));
+    let profiler = self.profiler.clone();
+    let mut profile = profiler.as_ref().map(|p| {
+      let t = ew.atoms()[0].get_type();
+      p.begin(t, self.code(t).map_or(0, |c| c.len()))
+    });
+    let recorder = self.crash_recorder.clone();
+    let mut record = recorder
+      .as_ref()
+      .map(|r| r.begin(ew, &self.rng, self.event_origin));
+    let result = self.observe(ew, step, trace.as_mut(), profile.as_mut(), record.as_mut());
+    if let (Some(profiler), Some(profile)) = (profiler, profile) {
+      profiler.finish(profile);
+    }
+    if let (Some(recorder), Some(record), Err(e)) = (recorder, record, &result) {
+      if self.stops(e) {
+        recorder.finish(record, &self.cursor, ew, self.event_symmetry, e);
+      }
+    }
+    if let (Some(tracer), Some(trace)) = (tracer, trace) {
+      tracer.finish(
+        trace,
+        &self.cursor,
+        ew,
+        self.event_symmetry,
+        result.as_ref().map(|_| ()),
+      );
+    }
+    result
+  }
+
+  /// Whether an event failing with `e` stops the run, rather than being
+  /// dealt with by the budget or assert policy.
+  fn stops(&self, e: &Error) -> bool {
+    match e {
+      Error::BudgetExceeded(_) => self.budget_policy == BudgetPolicy::Fail,
+      Error::AssertionFailed(_) => self.assert_policy != AssertPolicy::Abort,
+      _ => true,
+    }
+  }
+
+  /// Runs one event in `ew`, telling the observers about it.
+  fn observe(
+    &mut self,
+    ew: &mut mfm::EventWindow,
+    step: Option<&mut StepHook>,
+    trace: Option<&mut EventTrace>,
+    profile: Option<&mut EventProfile>,
+    record: Option<&mut EventRecord>,
+  ) -> Result<(), Error> {
+    if self.observers.is_empty() {
+      return self.run(ew, None, step, trace, profile, record);
+    }
+    for o in self.observers.iter() {
+      o.event_start(ew);
+    }
+    let mut changes = Vec::new();
+    let result = self.run(ew, Some(&mut changes), step, trace, profile, record);
+    let kept = match &result {
+      Ok(()) => true,
+      Err(Error::BudgetExceeded(_)) => self.budget_policy == BudgetPolicy::Commit,
+      Err(_) => false,
+    };
+    for o in self.observers.iter() {
+      if kept {
+        for c in changes.iter() {
+          c.notify(o.as_ref());
+        }
+      }
+      o.event_end(ew, result.as_ref().map(|_| ()));
+    }
+    result
+  }
+
+  /// Runs one event in `ew`, recording in `changes` the atoms it creates,
+  /// destroys and transmutes, calling `step` before each instruction and
+  /// adding each to `trace`, `profile` and `record`.
+  fn run(
+    &mut self,
+    ew: &mut mfm::EventWindow,
+    mut changes: Option<&mut Vec<Change>>,
+    mut step: Option<&mut StepHook>,
+    mut trace: Option<&mut EventTrace>,
+    mut profile: Option<&mut EventProfile>,
+    mut record: Option<&mut EventRecord>,
+  ) -> Result<(), Error> {
+    let mut note = |change: Option<Change>| {
+      if let (Some(changes), Some(c)) = (changes.as_mut(), change) {
+        changes.push(c);
+      }
+    };
+    self.cursor.reset();
+    let verify_ecc = self.verify_ecc;
+    let hook = &self.corrupt_atom_hook;
+    let read_site =
+      |cursor: &mut Cursor, ew: &mut mfm::EventWindow, i: usize| -> Option<Atom> {
+        let site = ew.get_mut(i)?;
+        cursor.read(i);
+        if verify_ecc {
+          match ecc::check(*site) {
+            ecc::Check::Valid => {}
+            ecc::Check::Corrected { atom, .. } => *site = atom,
+            ecc::Check::Uncorrectable => {
+              if let Some(hook) = hook {
+                hook(i, *site);
+              }
+            }
+          }
+        }
+        Some(*site)
+      };
+    let write_site = |atom: Atom| if verify_ecc { ecc::encode(atom) } else { atom };
+
+    let my_atom = read_site(&mut self.cursor, ew, 0).ok_or(Error::NoElement)?;
+    let my_type = my_atom.get_type();
     let my_elem = self
       .element_map
       .get(&my_type)
       .ok_or(Error::UnknownElement(my_type))?;
-    let mut cursor = Cursor::new();
+    let mode = my_elem.metadata.arith_mode;
+    let field_writes = my_elem.metadata.field_writes;
+    let div_zero = my_elem.metadata.div_zero;
+    // The radius writes are checked against.
+    let radius = Some(my_elem.metadata.radius).filter(|_| self.strict);
+    let strict = self.strict;
+    ew.use_symmetries(my_elem.metadata.symmetries, &mut self.rng);
+    self.event_symmetry = ew.symmetry();
+    let budget = self.instruction_budget.unwrap_or(u64::MAX);
+    let armed = Armed::new(&self.watches, self.event_origin);
+    let watch = |cursor: &mut Cursor, ew: &mfm::EventWindow, i: usize, old: Atom, new: Atom| {
+      if armed.is_empty() {
+        return;
+      }
+      if let Some(i) = ew.map(i) {
+        armed.check(&mut cursor.watch_hits, cursor.ip, i, old, new);
+      }
+    };
+    let assert_hook = &self.assert_hook;
+    let assert_policy = self.assert_policy;
+    let heat_layer = self.energy.map(|m| m.layer);
+    let elements = &self.element_map;
+    let bonded = elements.values().any(|e| !e.metadata.bonds.is_empty());
+    // Keeps bonds to and from atoms which have moved between sites.
+    let rebond = |ew: &mut mfm::EventWindow, moves: &[(usize, usize)]| {
+      if bonded {
+        let moves: Vec<(usize, usize)> =
+          moves.iter().filter_map(|(a, b)| Some((ew.map(*a)?, ew.map(*b)?))).collect();
+        bond::rebond(elements, ew.atoms_mut(), &moves, verify_ecc);
+      }
+    };
+    let cursor = &mut self.cursor;
     while (cursor.ip as usize) < my_elem.code.len() {
-      match my_elem.code[cursor.ip as usize] {
+      if let Some(step) = step.as_mut() {
+        if !step(cursor, ew) {
+          break;
+        }
+      }
+      if cursor.executed == budget {
+        return Err(Error::BudgetExceeded(budget));
+      }
+      cursor.executed += 1;
+      let instruction = my_elem.code[cursor.ip];
+      if let Some(trace) = trace.as_mut() {
+        trace.instruction(cursor, ew, instruction.mnemonic());
+      }
+      if let Some(profile) = profile.as_mut() {
+        profile.instruction(cursor);
+      }
+      if let Some(record) = record.as_mut() {
+        record.instruction(cursor);
+      }
+      match instruction {
         Instruction::Nop => {}
         Instruction::Exit => break,
-        Instruction::SwapSites => todo!(),
+        Instruction::SwapSites => {
+          let i = cursor.pop_site()?;
+          let j = cursor.pop_site()?;
+          cursor.write(i, radius)?;
+          cursor.write(j, radius)?;
+          let a = read_site(cursor, ew, i).ok_or_else(|| cursor.bad_site(i as u128))?;
+          let b = read_site(cursor, ew, j).ok_or_else(|| cursor.bad_site(j as u128))?;
+          *ew.get_mut(i).ok_or_else(|| cursor.bad_site(i as u128))? = write_site(b);
+          *ew.get_mut(j).ok_or_else(|| cursor.bad_site(j as u128))? = write_site(a);
+          rebond(ew, &[(i, j), (j, i)]);
+          watch(cursor, ew, i, a, b);
+          watch(cursor, ew, j, b, a);
+        }
+        Instruction::MoveSite => {
+          let dst = cursor.pop_site()?;
+          let src = cursor.pop_site()?;
+          let a = read_site(cursor, ew, src).ok_or_else(|| cursor.bad_site(src as u128))?;
+          // Moving a site onto itself leaves it in place.
+          if src != dst {
+            cursor.write(src, radius)?;
+            cursor.write(dst, radius)?;
+            let old = *ew.get(dst).ok_or_else(|| cursor.bad_site(dst as u128))?;
+            if !old.is_empty() {
+              note(ew.map(dst).map(|i| Change::Destroyed(i, old)));
+            }
+            *ew
+              .get_mut(src)
+              .ok_or_else(|| cursor.bad_site(src as u128))? = write_site(Atom::default());
+            *ew
+              .get_mut(dst)
+              .ok_or_else(|| cursor.bad_site(dst as u128))? = write_site(a);
+            rebond(ew, &[(src, dst)]);
+            watch(cursor, ew, src, a, Atom::default());
+            watch(cursor, ew, dst, old, a);
+          }
+        }
+        Instruction::Diffuse => {
+          let d = cursor.pop()?.as_u128().min(FULL_DIFFUSABILITY);
+          if self.rng.gen_range(0..FULL_DIFFUSABILITY) < d {
+            let mut empty = Vec::new();
+            for i in 1..=4 {
+              let site = read_site(cursor, ew, i).ok_or_else(|| cursor.bad_site(i as u128))?;
+              if site.get_type() == 0 {
+                empty.push(i);
+              }
+            }
+            if !empty.is_empty() {
+              let i = empty[self.rng.gen_range(0..empty.len())];
+              cursor.write(0, radius)?;
+              cursor.write(i, radius)?;
+              let me = read_site(cursor, ew, 0).ok_or_else(|| cursor.bad_site(0))?;
+              let old = *ew.get(i).ok_or_else(|| cursor.bad_site(i as u128))?;
+              *ew.get_mut(0).ok_or_else(|| cursor.bad_site(0))? = write_site(Atom::default());
+              *ew.get_mut(i).ok_or_else(|| cursor.bad_site(i as u128))? = write_site(me);
+              rebond(ew, &[(0, i)]);
+              watch(cursor, ew, 0, me, Atom::default());
+              watch(cursor, ew, i, old, me);
+            }
+          }
+        }
+        Instruction::Rand => {
+          let bits = cursor.pop()?.as_u128().min(96) as u32;
+          let mask = u128::MAX.checked_shr(128 - bits).unwrap_or(0);
+          cursor.push((self.rng.gen::<u128>() & mask).into())?;
+        }
+        Instruction::OddsOf => {
+          let d = cursor.pop()?;
+          let n = cursor.pop()?.as_u128();
+          if d.is_zero() {
+            return Err(Error::DivisionByZero);
+          }
+          let hit = self.rng.gen_range(0..d.as_u128()) < n;
+          cursor.push(if hit { 1 } else { 0 }.into())?;
+        }
+        Instruction::RandSite => {
+          let i = self.rng.gen_range(0..SiteNumber::COUNT as u32);
+          cursor.push(i.into())?;
+        }
         Instruction::SetSite => {
-          let c = cursor.op_stack.pop().unwrap();
-          let i = cursor.op_stack.pop().unwrap().as_u128() as usize;
-          *ew.get_mut(i).unwrap() = c;
+          let c = cursor.pop()?;
+          let i = cursor.pop_site()?;
+          cursor.write(i, radius)?;
+          let site = ew.get_mut(i).ok_or_else(|| cursor.bad_site(i as u128))?;
+          let old = *site;
+          *site = write_site(c.into());
+          let new = *site;
+          note(ew.map(i).and_then(|i| Change::of(i, old, new)));
+          watch(cursor, ew, i, old, new);
+        }
+        Instruction::SetField(f) => {
+          let a = cursor.pop()?;
+          let v = cursor.pop()?;
+          let v =
+            fit_field(v, *f.runtime(), field_writes).ok_or(Error::FieldOverflow(cursor.ip))?;
+          cursor.push(a.with_field(*f.runtime(), v))?;
+        }
+        Instruction::SetSiteField(f) => {
+          let i = cursor.pop_site()?;
+          let v = cursor.pop()?;
+          let v =
+            fit_field(v, *f.runtime(), field_writes).ok_or(Error::FieldOverflow(cursor.ip))?;
+          cursor.write(i, radius)?;
+          let mut site = read_site(cursor, ew, i).ok_or_else(|| cursor.bad_site(i as u128))?;
+          let old = site;
+          if *f.runtime() == FieldSelector::TYPE {
+            site.set_type(v.as_u128() as u16);
+          } else {
+            site.set_field(*f.runtime(), v)?;
+          }
+          *ew.get_mut(i).ok_or_else(|| cursor.bad_site(i as u128))? = write_site(site);
+          note(ew.map(i).and_then(|i| Change::of(i, old, site)));
+          watch(cursor, ew, i, old, site);
         }
-        Instruction::SetField(_) => todo!(),
-        Instruction::SetSiteField(_) => todo!(),
         Instruction::GetSite => {
-          let v = *ew
-            .get(cursor.op_stack.pop().unwrap().as_u128() as usize)
-            .unwrap();
-          cursor.op_stack.push(v);
-        }
-        Instruction::GetField(_) => todo!(),
-        Instruction::GetSiteField(_) => todo!(),
-        Instruction::GetType(_) => todo!(),
-        Instruction::GetParameter(_) => todo!(),
-        Instruction::Scan => todo!(),
-        Instruction::SaveSymmetries => cursor.symmetries_stack.push(cursor.symmetries),
-        Instruction::UseSymmetries(x) => cursor.symmetries = x,
+          let i = cursor.pop_site()?;
+          let v = read_site(cursor, ew, i).ok_or_else(|| cursor.bad_site(i as u128))?;
+          cursor.push(v.into())?;
+        }
+        Instruction::GetField(f) => {
+          let a = cursor.pop()?;
+          cursor.push(a.apply(*f.runtime()))?;
+        }
+        Instruction::GetSiteField(f) => {
+          let i = cursor.pop_site()?;
+          let v = read_site(cursor, ew, i)
+            .ok_or_else(|| cursor.bad_site(i as u128))?
+            .get_field(*f.runtime());
+          cursor.push(v)?;
+        }
+        Instruction::GetType(t) => cursor.push((*t.runtime()).into())?,
+        Instruction::GetParameter(c) => cursor.push(*c.runtime())?,
+        Instruction::Scan => {
+          let t = cursor.pop()?.as_u128();
+          let mut mask = 0u128;
+          for i in 0..SiteNumber::COUNT {
+            let site = read_site(cursor, ew, i).ok_or_else(|| cursor.bad_site(i as u128))?;
+            if site.get_type() as u128 == t {
+              mask |= 1 << i;
+            }
+          }
+          cursor.push(mask.into())?;
+        }
+        Instruction::CountOf => {
+          let r = cursor.pop()?.as_u128();
+          let t = cursor.pop()?.as_u128();
+          let mut count = 0u32;
+          for i in 1..SiteNumber::COUNT {
+            if !matches!(SiteNumber(i as u8).radius(), Some(x) if x as u128 <= r) {
+              continue;
+            }
+            let site = read_site(cursor, ew, i).ok_or_else(|| cursor.bad_site(i as u128))?;
+            if site.get_type() as u128 == t {
+              count += 1;
+            }
+          }
+          cursor.push(count.into())?;
+        }
+        Instruction::FindFirst | Instruction::FindRandom => {
+          let t = cursor.pop()?.as_u128();
+          let mut found = Vec::new();
+          for i in 1..SiteNumber::COUNT {
+            let site = read_site(cursor, ew, i).ok_or_else(|| cursor.bad_site(i as u128))?;
+            if site.get_type() as u128 == t {
+              found.push(i as u32);
+            }
+          }
+          // Site 0 is never searched, so it doubles as "not found".
+          let i = match instruction {
+            Instruction::FindFirst => found.first().copied(),
+            _ if found.is_empty() => None,
+            _ => Some(found[self.rng.gen_range(0..found.len())]),
+          };
+          cursor.push(i.unwrap_or(0).into())?;
+        }
+        Instruction::SaveSymmetries => cursor.symmetries_stack.push(ew.symmetry()),
+        Instruction::UseSymmetries(x) => ew.use_symmetries(x, &mut self.rng),
         Instruction::RestoreSymmetries => {
-          cursor.symmetries = cursor.symmetries_stack.pop().unwrap()
-        }
-        Instruction::Push0 => cursor.op_stack.push(0.into()),
-        Instruction::Push1 => cursor.op_stack.push(1.into()),
-        Instruction::Push2 => cursor.op_stack.push(2.into()),
-        Instruction::Push3 => cursor.op_stack.push(3.into()),
-        Instruction::Push4 => cursor.op_stack.push(4.into()),
-        Instruction::Push5 => cursor.op_stack.push(5.into()),
-        Instruction::Push6 => cursor.op_stack.push(6.into()),
-        Instruction::Push7 => cursor.op_stack.push(7.into()),
-        Instruction::Push8 => cursor.op_stack.push(8.into()),
-        Instruction::Push9 => cursor.op_stack.push(9.into()),
-        Instruction::Push10 => cursor.op_stack.push(10.into()),
-        Instruction::Push11 => cursor.op_stack.push(11.into()),
-        Instruction::Push12 => cursor.op_stack.push(12.into()),
-        Instruction::Push13 => cursor.op_stack.push(13.into()),
-        Instruction::Push14 => cursor.op_stack.push(14.into()),
-        Instruction::Push15 => cursor.op_stack.push(15.into()),
-        Instruction::Push16 => cursor.op_stack.push(16.into()),
-        Instruction::Push17 => cursor.op_stack.push(17.into()),
-        Instruction::Push18 => cursor.op_stack.push(18.into()),
-        Instruction::Push19 => cursor.op_stack.push(19.into()),
-        Instruction::Push20 => cursor.op_stack.push(20.into()),
-        Instruction::Push21 => cursor.op_stack.push(21.into()),
-        Instruction::Push22 => cursor.op_stack.push(22.into()),
-        Instruction::Push23 => cursor.op_stack.push(23.into()),
-        Instruction::Push24 => cursor.op_stack.push(24.into()),
-        Instruction::Push25 => cursor.op_stack.push(25.into()),
-        Instruction::Push26 => cursor.op_stack.push(26.into()),
-        Instruction::Push27 => cursor.op_stack.push(27.into()),
-        Instruction::Push28 => cursor.op_stack.push(28.into()),
-        Instruction::Push29 => cursor.op_stack.push(29.into()),
-        Instruction::Push30 => cursor.op_stack.push(30.into()),
-        Instruction::Push31 => cursor.op_stack.push(31.into()),
-        Instruction::Push32 => cursor.op_stack.push(32.into()),
-        Instruction::Push33 => cursor.op_stack.push(33.into()),
-        Instruction::Push34 => cursor.op_stack.push(34.into()),
-        Instruction::Push35 => cursor.op_stack.push(35.into()),
-        Instruction::Push36 => cursor.op_stack.push(36.into()),
-        Instruction::Push37 => cursor.op_stack.push(37.into()),
-        Instruction::Push38 => cursor.op_stack.push(38.into()),
-        Instruction::Push39 => cursor.op_stack.push(39.into()),
-        Instruction::Push40 => cursor.op_stack.push(40.into()),
-        Instruction::Push(c) => cursor.op_stack.push(c),
+          let s = cursor.symmetries_stack.pop();
+          ew.set_symmetry(s.ok_or(Error::SymmetriesStackUnderflow(cursor.ip))?);
+        }
+        Instruction::Push0 => cursor.push(0.into())?,
+        Instruction::Push1 => cursor.push(1.into())?,
+        Instruction::Push2 => cursor.push(2.into())?,
+        Instruction::Push3 => cursor.push(3.into())?,
+        Instruction::Push4 => cursor.push(4.into())?,
+        Instruction::Push5 => cursor.push(5.into())?,
+        Instruction::Push6 => cursor.push(6.into())?,
+        Instruction::Push7 => cursor.push(7.into())?,
+        Instruction::Push8 => cursor.push(8.into())?,
+        Instruction::Push9 => cursor.push(9.into())?,
+        Instruction::Push10 => cursor.push(10.into())?,
+        Instruction::Push11 => cursor.push(11.into())?,
+        Instruction::Push12 => cursor.push(12.into())?,
+        Instruction::Push13 => cursor.push(13.into())?,
+        Instruction::Push14 => cursor.push(14.into())?,
+        Instruction::Push15 => cursor.push(15.into())?,
+        Instruction::Push16 => cursor.push(16.into())?,
+        Instruction::Push17 => cursor.push(17.into())?,
+        Instruction::Push18 => cursor.push(18.into())?,
+        Instruction::Push19 => cursor.push(19.into())?,
+        Instruction::Push20 => cursor.push(20.into())?,
+        Instruction::Push21 => cursor.push(21.into())?,
+        Instruction::Push22 => cursor.push(22.into())?,
+        Instruction::Push23 => cursor.push(23.into())?,
+        Instruction::Push24 => cursor.push(24.into())?,
+        Instruction::Push25 => cursor.push(25.into())?,
+        Instruction::Push26 => cursor.push(26.into())?,
+        Instruction::Push27 => cursor.push(27.into())?,
+        Instruction::Push28 => cursor.push(28.into())?,
+        Instruction::Push29 => cursor.push(29.into())?,
+        Instruction::Push30 => cursor.push(30.into())?,
+        Instruction::Push31 => cursor.push(31.into())?,
+        Instruction::Push32 => cursor.push(32.into())?,
+        Instruction::Push33 => cursor.push(33.into())?,
+        Instruction::Push34 => cursor.push(34.into())?,
+        Instruction::Push35 => cursor.push(35.into())?,
+        Instruction::Push36 => cursor.push(36.into())?,
+        Instruction::Push37 => cursor.push(37.into())?,
+        Instruction::Push38 => cursor.push(38.into())?,
+        Instruction::Push39 => cursor.push(39.into())?,
+        Instruction::Push40 => cursor.push(40.into())?,
+        Instruction::Push(c) => cursor.push(c)?,
         Instruction::Pop => {
-          cursor.op_stack.pop().expect("stack underflow");
+          cursor.pop()?;
         }
         Instruction::Dup => {
-          let t = cursor.op_stack.pop().unwrap();
-          cursor.op_stack.push(t);
-          cursor.op_stack.push(t);
+          let t = cursor.pop()?;
+          cursor.push(t)?;
+          cursor.push(t)?;
         }
         Instruction::Over => {
-          let ignore = cursor.op_stack.pop().unwrap();
-          let t = cursor.op_stack.pop().unwrap();
-          cursor.op_stack.push(t);
-          cursor.op_stack.push(ignore);
-          cursor.op_stack.push(t);
+          let ignore = cursor.pop()?;
+          let t = cursor.pop()?;
+          cursor.push(t)?;
+          cursor.push(ignore)?;
+          cursor.push(t)?;
         }
         Instruction::Swap => {
+          cursor.require(2)?;
           let n = cursor.op_stack.len();
           cursor.op_stack.swap(n - 2, n - 1);
         }
         Instruction::Rot => {
+          cursor.require(3)?;
           let n = cursor.op_stack.len();
           cursor.op_stack.swap(n - 2, n - 1);
           cursor.op_stack.swap(n - 3, n - 2);
         }
         Instruction::Call(x) => {
-          cursor.call_stack.push(cursor.ip);
+          cursor.call()?;
           cursor.ip = *x.runtime() as usize;
+          continue;
+        }
+        Instruction::Ret => {
+          cursor.ip = cursor
+            .call_stack
+            .pop()
+            .ok_or(Error::CallStackUnderflow(cursor.ip))?
+        }
+        Instruction::Checksum => {
+          let a = cursor.pop()?;
+          let valid = ecc::check(a.into()) == ecc::Check::Valid;
+          cursor.push(if valid { 0 } else { 1 }.into())?;
         }
-        Instruction::Ret => cursor.ip = cursor.call_stack.pop().unwrap(),
-        Instruction::Checksum => todo!(),
         Instruction::Add => {
-          let b = cursor.op_stack.pop().unwrap();
-          let a = cursor.op_stack.pop().unwrap();
-          cursor.op_stack.push(a + b);
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          cursor.flags = Flags::add(a, b);
+          cursor.push(mode.add(a, b).ok_or(Error::ArithmeticOverflow)?)?;
         }
         Instruction::Sub => {
-          let b = cursor.op_stack.pop().unwrap();
-          let a = cursor.op_stack.pop().unwrap();
-          cursor.op_stack.push(a - b);
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          cursor.flags = Flags::sub(a, b);
+          cursor.push(mode.sub(a, b).ok_or(Error::ArithmeticOverflow)?)?;
         }
         Instruction::Neg => {
-          let a = cursor.op_stack.pop().unwrap();
-          cursor.op_stack.push(-a);
+          let a = cursor.pop()?;
+          cursor.flags = Flags::neg(a);
+          cursor.push(mode.neg(a).ok_or(Error::ArithmeticOverflow)?)?;
         }
         Instruction::Mod => {
-          let b = cursor.op_stack.pop().unwrap();
-          let a = cursor.op_stack.pop().unwrap();
-          cursor.op_stack.push(a % b);
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          cursor.flags = Flags::rem(a, b);
+          cursor.push(divide(a, b, true, mode, div_zero)?)?;
         }
         Instruction::Mul => {
-          let b = cursor.op_stack.pop().unwrap();
-          let a = cursor.op_stack.pop().unwrap();
-          cursor.op_stack.push(a * b);
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          cursor.flags = Flags::mul(a, b);
+          cursor.push(mode.mul(a, b).ok_or(Error::ArithmeticOverflow)?)?;
         }
         Instruction::Div => {
-          let b = cursor.op_stack.pop().unwrap();
-          let a = cursor.op_stack.pop().unwrap();
-          cursor.op_stack.push(a / b);
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          cursor.flags = Flags::div(a, b);
+          cursor.push(divide(a, b, false, mode, div_zero)?)?;
+        }
+        Instruction::Compare => {
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          cursor.flags = Flags::compare(a, b);
+        }
+        Instruction::Assert => {
+          if cursor.pop()?.is_zero() {
+            cursor.assertions_failed += 1;
+            if let Some(hook) = assert_hook {
+              hook(my_type, cursor.ip);
+            }
+            match assert_policy {
+              AssertPolicy::Log => {}
+              AssertPolicy::Paint => {
+                *ew.get_paint_mut(0).ok_or_else(|| cursor.bad_site(0))? = ASSERT_PAINT.into()
+              }
+              AssertPolicy::Abort | AssertPolicy::Halt => {
+                return Err(Error::AssertionFailed(cursor.ip))
+              }
+            }
+          }
+        }
+        Instruction::ZeroExtend(n) => {
+          let a = cursor.pop()?;
+          cursor.push(a.zero_extend(n))?;
+        }
+        Instruction::PopCount => {
+          let a = cursor.pop()?;
+          cursor.push(a.count_ones().into())?;
+        }
+        Instruction::LeadingZeros => {
+          let a = cursor.pop()?;
+          cursor.push(a.leading_zeros().into())?;
+        }
+        Instruction::TrailingZeros => {
+          let a = cursor.pop()?;
+          cursor.push(a.trailing_zeros().into())?;
+        }
+        Instruction::RotateLeft | Instruction::RotateRight => {
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          // Negative counts rotate the other way.
+          let n = b.as_i128().rem_euclid(a.bits() as i128) as u32;
+          cursor.push(if matches!(instruction, Instruction::RotateLeft) {
+            a.rotate_left(n)
+          } else {
+            a.rotate_right(n)
+          })?;
+        }
+        Instruction::Min => {
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          cursor.push(a.min(b))?;
+        }
+        Instruction::Max => {
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          cursor.push(a.max(b))?;
+        }
+        Instruction::Clamp => {
+          let c = cursor.pop()?;
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          cursor.push(a.clamp(b, c))?;
+        }
+        Instruction::Abs => {
+          let a = cursor.pop()?;
+          cursor.push(mode.abs(a).ok_or(Error::ArithmeticOverflow)?)?;
+        }
+        Instruction::MulHigh => {
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          cursor.push(a.mul_high(b))?;
+        }
+        Instruction::SignExtend(n) => {
+          let a = cursor.pop()?;
+          cursor.push(a.sign_extend(n))?;
+        }
+        Instruction::Truncate(n) => {
+          let a = cursor.pop()?;
+          cursor.push(a.with_bits(n))?;
+        }
+        Instruction::GetRegister(n) => {
+          let v = match cursor.registers.get(n as usize) {
+            Some(_) if strict && cursor.registers_written & (1 << n) == 0 => {
+              return Err(Error::UninitializedRegister {
+                register: n,
+                ip: cursor.ip,
+              })
+            }
+            Some(v) => *v,
+            // R? reads 96 random bits.
+            None => (self.rng.gen::<u128>() & (u128::MAX >> 32)).into(),
+          };
+          cursor.push(v)?;
+        }
+        Instruction::SetRegister(n) => {
+          let v = cursor.pop()?;
+          *cursor
+            .registers
+            .get_mut(n as usize)
+            .ok_or(Error::BadRegister(n))? = v;
+          cursor.registers_written |= 1 << n;
         }
         Instruction::Less => {
-          let b = cursor.op_stack.pop().unwrap();
-          let a = cursor.op_stack.pop().unwrap();
-          cursor.op_stack.push(if a < b { 1 } else { 0 }.into());
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          cursor.push(if a.cmp_value(b).is_lt() { 1 } else { 0 }.into())?;
         }
         Instruction::LessEqual => {
-          let b = cursor.op_stack.pop().unwrap();
-          let a = cursor.op_stack.pop().unwrap();
-          cursor.op_stack.push(if a <= b { 1 } else { 0 }.into());
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          cursor.push(if a.cmp_value(b).is_le() { 1 } else { 0 }.into())?;
         }
         Instruction::Or => {
-          let b = cursor.op_stack.pop().unwrap();
-          let a = cursor.op_stack.pop().unwrap();
-          cursor.op_stack.push(a | b);
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          cursor.push(a | b)?;
         }
         Instruction::And => {
-          let b = cursor.op_stack.pop().unwrap();
-          let a = cursor.op_stack.pop().unwrap();
-          cursor.op_stack.push(a & b);
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          cursor.push(a & b)?;
         }
         Instruction::Xor => {
-          let b = cursor.op_stack.pop().unwrap();
-          let a = cursor.op_stack.pop().unwrap();
-          cursor.op_stack.push(a ^ b);
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          cursor.push(a ^ b)?;
         }
         Instruction::Equal => {
-          let b = cursor.op_stack.pop().unwrap();
-          let a = cursor.op_stack.pop().unwrap();
-          cursor.op_stack.push(if a == b { 1 } else { 0 }.into())
+          let b = cursor.pop()?;
+          let a = cursor.pop()?;
+          cursor.push(if a.cmp_value(b).is_eq() { 1 } else { 0 }.into())?
         }
         Instruction::BitCount => {
-          let a = cursor.op_stack.pop().unwrap();
-          cursor.op_stack.push(a.as_u128().count_ones().into());
+          let a = cursor.pop()?;
+          cursor.push(a.as_u128().count_ones().into())?;
+        }
+        Instruction::BitScanForward => {
+          return Err(Error::UnsupportedInstruction(instruction.mnemonic()))
+        }
+        Instruction::BitScanReverse => {
+          return Err(Error::UnsupportedInstruction(instruction.mnemonic()))
         }
-        Instruction::BitScanForward => todo!(),
-        Instruction::BitScanReverse => todo!(),
-        Instruction::LShift => todo!(),
-        Instruction::RShift => todo!(),
+        Instruction::LShift => return Err(Error::UnsupportedInstruction(instruction.mnemonic())),
+        Instruction::RShift => return Err(Error::UnsupportedInstruction(instruction.mnemonic())),
         Instruction::Jump(x) => {
           cursor.ip = *x.runtime() as usize;
           continue;
         }
-        Instruction::JumpRelativeOffset => todo!(),
+        Instruction::JumpRelativeOffset => {
+          return Err(Error::UnsupportedInstruction(instruction.mnemonic()))
+        }
         Instruction::JumpZero(x) => {
-          if cursor.op_stack.pop().unwrap().is_zero() {
+          if cursor.pop()?.is_zero() {
             cursor.ip = *x.runtime() as usize;
             continue;
           }
         }
         Instruction::JumpNonZero(x) => {
-          if !cursor.op_stack.pop().unwrap().is_zero() {
+          if !cursor.pop()?.is_zero() {
+            cursor.ip = *x.runtime() as usize;
+            continue;
+          }
+        }
+        Instruction::JumpIf(c, x) => {
+          if c.holds(cursor.flags) {
             cursor.ip = *x.runtime() as usize;
             continue;
           }
         }
         Instruction::SetPaint => {
-          let i = cursor.op_stack.pop().unwrap().as_u128() as usize;
-          let v = cursor.op_stack.pop().unwrap().as_u128() as u32;
-          *ew.get_paint_mut(i).unwrap() = v.into();
+          let i = cursor.pop_site()?;
+          let v = cursor.pop()?.as_u128() as u32;
+          cursor.write(i, radius)?;
+          *ew
+            .get_paint_mut(i)
+            .ok_or_else(|| cursor.bad_site(i as u128))? = v.into();
         }
         Instruction::GetPaint => {
-          let i = cursor.op_stack.pop().unwrap().as_u128() as usize;
-          let v = ew.get_paint(i).unwrap();
-          cursor.op_stack.push(v.bits().into());
+          let i = cursor.pop_site()?;
+          let v = ew.get_paint(i).ok_or_else(|| cursor.bad_site(i as u128))?;
+          cursor.read(i);
+          cursor.push(v.bits().into())?;
+        }
+        Instruction::GetLayer => {
+          let i = cursor.pop_site()?;
+          let l = cursor.pop_layer()?;
+          let v = *ew
+            .get_layer(l, i)
+            .ok_or_else(|| cursor.bad_site(i as u128))?;
+          cursor.read(i);
+          cursor.push(v.into())?;
+        }
+        Instruction::SetLayer => {
+          let i = cursor.pop_site()?;
+          let l = cursor.pop_layer()?;
+          let v = cursor.pop()?.as_u128() as u32;
+          cursor.write(i, radius)?;
+          *ew
+            .get_layer_mut(l, i)
+            .ok_or_else(|| cursor.bad_site(i as u128))? = v;
+        }
+        Instruction::DecayLayer => {
+          let shift = cursor.pop()?.as_u128().min(32) as u32;
+          let l = cursor.pop_layer()?;
+          // Only the sites holding a value are written.
+          for i in 0..SiteNumber::COUNT {
+            if matches!(ew.get_layer(l, i), Some(v) if *v > 0) {
+              cursor.write(i, radius)?;
+            }
+          }
+          ew.decay_layer(l, shift);
+        }
+        Instruction::GetHeat => {
+          let i = cursor.pop_site()?;
+          cursor.read(i);
+          let v = match heat_layer {
+            Some(l) => *ew
+              .get_layer(l, i)
+              .ok_or_else(|| cursor.bad_site(i as u128))?,
+            None => 0,
+          };
+          cursor.push(v.into())?;
+        }
+        Instruction::EmitHeat => {
+          let i = cursor.pop_site()?;
+          let v = cursor.pop()?.as_u128().min(u32::MAX as u128) as u32;
+          if let Some(l) = heat_layer {
+            cursor.write(i, radius)?;
+            let heat = ew
+              .get_layer_mut(l, i)
+              .ok_or_else(|| cursor.bad_site(i as u128))?;
+            *heat = heat.saturating_add(v);
+          }
+        }
+        Instruction::AbsorbHeat => {
+          let i = cursor.pop_site()?;
+          let v = cursor.pop()?.as_u128().min(u32::MAX as u128) as u32;
+          let taken = match heat_layer {
+            Some(l) => {
+              cursor.read(i);
+              cursor.write(i, radius)?;
+              let heat = ew
+                .get_layer_mut(l, i)
+                .ok_or_else(|| cursor.bad_site(i as u128))?;
+              let taken = v.min(*heat);
+              *heat -= taken;
+              taken
+            }
+            None => 0,
+          };
+          cursor.push(taken.into())?;
+        }
+        Instruction::Hash => {
+          let i = cursor.pop_site()?;
+          let v = cursor.pop()?.pattern();
+          let (dx, dy) = ew
+            .map(i)
+            .and_then(|i| SiteNumber(i as u8).coord())
+            .ok_or_else(|| cursor.bad_site(i as u128))?;
+          let (ox, oy) = self.event_origin.unwrap_or((0, 0));
+          let h = rng::hash(v, ox as i64 + dx as i64, oy as i64 + dy as i64);
+          cursor.push(h.into())?;
+        }
+        Instruction::Bond(f) => {
+          let i = cursor.pop_site()?;
+          let j = cursor.pop_site()?;
+          let (from, to) = (ew.map(i), ew.map(j));
+          let bond = from
+            .zip(to)
+            .and_then(|(from, to)| bond::offset(from, to))
+            .ok_or(Error::BondTooLong(cursor.ip))?;
+          cursor.write(i, radius)?;
+          let mut site = read_site(cursor, ew, i).ok_or_else(|| cursor.bad_site(i as u128))?;
+          let old = site;
+          site.set_field(*f.runtime(), bond.into())?;
+          *ew.get_mut(i).ok_or_else(|| cursor.bad_site(i as u128))? = write_site(site);
+          watch(cursor, ew, i, old, site);
+        }
+        Instruction::Unbond(f) => {
+          let i = cursor.pop_site()?;
+          cursor.write(i, radius)?;
+          let mut site = read_site(cursor, ew, i).ok_or_else(|| cursor.bad_site(i as u128))?;
+          let old = site;
+          site.set_field(*f.runtime(), 0.into())?;
+          *ew.get_mut(i).ok_or_else(|| cursor.bad_site(i as u128))? = write_site(site);
+          watch(cursor, ew, i, old, site);
+        }
+        Instruction::FollowBond(f) => {
+          let i = cursor.pop_site()?;
+          let bond = read_site(cursor, ew, i)
+            .ok_or_else(|| cursor.bad_site(i as u128))?
+            .get_field(*f.runtime());
+          // The partner's site in grid orientation, numbered as the event
+          // sees it.
+          let to = ew
+            .map(i)
+            .and_then(|from| bond::partner(from, bond.as_u128()))
+            .and_then(|to| (0..SiteNumber::COUNT).find(|j| ew.map(*j) == Some(to)))
+            .unwrap_or(SiteNumber::COUNT);
+          cursor.push((to as u8).into())?;
+        }
+        Instruction::NewId => {
+          let id = self.ids.fetch_add(1, Ordering::Relaxed);
+          cursor.push(id.into())?;
+        }
+        Instruction::Count(n) => {
+          self.counters[n as usize].fetch_add(1, Ordering::Relaxed);
         }
       }
       cursor.ip += 1;
@@ -500,3 +2024,136 @@ impl<'input> Runtime<'input> {
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::code::Compiler;
+
+  fn compile(src: &str) -> Vec<u8> {
+    let mut code = Vec::new();
+    Compiler::new("test").compile_to_writer(&mut code, src).unwrap();
+    code
+  }
+
+  /// `code` with its header claiming version `major.minor`.
+  fn with_version(mut code: Vec<u8>, major: u16, minor: u16) -> Vec<u8> {
+    code[4..6].copy_from_slice(&minor.to_be_bytes());
+    code[6..8].copy_from_slice(&major.to_be_bytes());
+    code
+  }
+
+  fn load(code: &[u8]) -> Result<Atom, Error> {
+    Runtime::new().load_from_reader(&mut &code[..])
+  }
+
+  const RES: &str = ".name Res\n  push 100\n  diffuse\n";
+
+  #[test]
+  fn refuse_newer_versions() {
+    let code = compile(RES);
+    let (major, minor) = (Runtime::MAJOR_VERSION, Runtime::MINOR_VERSION);
+    assert!(load(&code).is_ok());
+    // There is no major version before 0, so any other major version is newer.
+    for &(major, minor) in [(major, minor + 1), (major + 1, 0), (major + 1, minor)].iter() {
+      let newer = with_version(code.clone(), major, minor);
+      assert!(matches!(
+        load(&newer),
+        Err(Error::NewerVersion { major: a, minor: b }) if (a, b) == (major, minor)
+      ));
+    }
+    let older = with_version(code, major, minor - 1);
+    assert!(matches!(load(&older), Err(Error::BadMinorVersion(m)) if m == minor - 1));
+  }
+
+  #[test]
+  fn refuse_newer_bond_files() {
+    // .bond came in with version 0.15, so runtimes from before it see a newer
+    // version before they reach its metadata opcode.
+    let code = compile(".name Link\n.bond next, 0\n  push0\n  unbond next\n");
+    assert!(u16::from_be_bytes([code[4], code[5]]) >= 15);
+    assert!(load(&code).is_ok());
+    let newer = with_version(code, Runtime::MAJOR_VERSION, Runtime::MINOR_VERSION + 1);
+    assert!(matches!(load(&newer), Err(Error::NewerVersion { .. })));
+  }
+
+  #[test]
+  fn call_runs_routine_from_its_first_instruction() {
+    // `call` used to step past the routine's first instruction, so `square`
+    // skipped its `dup`.
+    let code = compile(
+      ".name Square
+.field out, 0, 16
+  push 3
+  call square
+  push 4
+  call square
+  add
+  push0
+  setsitefield out
+  exit
+square:
+  dup
+  mul
+  ret
+",
+    );
+    let mut runtime = Runtime::new();
+    let square = runtime.load_from_reader(&mut &code[..]).unwrap();
+    let mut grid = grid::Grid::new(1, 1, 16, 16);
+    grid.set(8, 8, square);
+    grid.event_at(&mut runtime, 8, 8).unwrap();
+    let out = FieldSelector { offset: 0, length: 16 };
+    assert_eq!(out.extract(grid.get(8, 8).unwrap().bits()), 25);
+  }
+
+  #[test]
+  fn refuse_calls_past_the_end() {
+    let mut code = Vec::new();
+    let err = Compiler::new("test")
+      .compile_to_writer(&mut code, ".name Bad\n  call end\nend:\n")
+      .map_err(|e| format!("{:?}", e));
+    assert!(matches!(err, Err(e) if e.starts_with("BadCallTarget")));
+  }
+
+  /// Runs one event of the element compiled from `src` at the center of a
+  /// grid, strictly or not, returning its result and the atom it left there.
+  fn run_event(src: &str, strict: bool) -> (Result<(), Error>, Atom) {
+    let code = compile(src);
+    let mut runtime = Runtime::new();
+    runtime.set_strict(strict);
+    let atom = runtime.load_from_reader(&mut &code[..]).unwrap();
+    let mut grid = grid::Grid::new(1, 1, 16, 16);
+    grid.set(8, 8, atom);
+    (grid.event_at(&mut runtime, 8, 8), grid.get(8, 8).unwrap())
+  }
+
+  #[test]
+  fn strict_registers() {
+    let unset = ".name Unset\n.field out, 0, 8\n  getregister R3\n  push0\n  setsitefield out\n";
+    let (result, atom) = run_event(unset, false);
+    assert!(result.is_ok());
+    assert_eq!(FieldSelector { offset: 0, length: 8 }.extract(atom.bits()), 0);
+    let (result, _) = run_event(unset, true);
+    assert!(matches!(
+      result,
+      Err(Error::UninitializedRegister { register: 3, ip: 0 })
+    ));
+    let set = ".name Set\n  push 5\n  setregister R3\n  getregister R3\n  setregister R4\n";
+    assert!(run_event(set, true).0.is_ok());
+  }
+
+  #[test]
+  fn strict_radius() {
+    // Copies itself to site `n`, which is 1 away for 1 to 4 and 2 for 5 to 12.
+    let copy = |n| format!(".name Copy\n.radius 1\n  push{}\n  push0\n  getsite\n  setsite\n", n);
+    for &strict in [false, true].iter() {
+      assert!(run_event(&copy(4), strict).0.is_ok());
+    }
+    assert!(run_event(&copy(5), false).0.is_ok());
+    assert!(matches!(
+      run_event(&copy(5), true).0,
+      Err(Error::WriteOutsideRadius { site: 5, radius: 1, ip: 3 })
+    ));
+  }
+}
@@ -0,0 +1,70 @@
+//! Watching events as they run.
+//!
+//! Observers are registered on a runtime with `Runtime::add_observer` and
+//! told about every event it executes, so statistics, visualization and
+//! invariant checks need not patch the interpreter.
+//!
+//! Sites are reported as indexes into the window in unmapped site order, the
+//! same as `EventWindow::atoms`, whatever symmetry the event ran with.
+
+use crate::base::atom::Atom;
+use crate::runtime::invariant::Violation;
+use crate::runtime::mfm::EventWindow;
+use crate::runtime::Error;
+
+/// Callbacks made as a runtime executes events. Every method does nothing by
+/// default.
+///
+/// Atom changes are reported after the event ends, and only if what it wrote
+/// is kept: they are dropped for a failed event or one discarded for running
+/// out of budget. Moving and swapping atoms create or destroy nothing.
+pub trait EventObserver: Send + Sync {
+    /// Called before an event runs, with its window as loaded.
+    fn event_start(&self, _ew: &EventWindow) {}
+
+    /// Called after an event, with its window as the event left it.
+    fn event_end(&self, _ew: &EventWindow, _result: Result<(), &Error>) {}
+
+    /// An atom was written over an empty site.
+    fn atom_created(&self, _site: usize, _atom: Atom) {}
+
+    /// An atom was erased or overwritten.
+    fn atom_destroyed(&self, _site: usize, _atom: Atom) {}
+
+    /// An atom's type was changed in place, from `from` to `to`.
+    fn type_transmuted(&self, _site: usize, _from: Atom, _to: Atom) {}
+
+    /// A grid invariant failed a check between events.
+    fn invariant_violated(&self, _violation: &Violation) {}
+}
+
+/// A change to the atoms of a window made by an event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+    Created(usize, Atom),
+    Destroyed(usize, Atom),
+    Transmuted(usize, Atom, Atom),
+}
+
+impl Change {
+    /// The change made by writing `new` over `old` at `site`, if any. Writes
+    /// which keep the type, such as to fields, are not changes.
+    pub fn of(site: usize, old: Atom, new: Atom) -> Option<Self> {
+        match (old.is_empty(), new.is_empty()) {
+            (true, false) => Some(Change::Created(site, new)),
+            (false, true) => Some(Change::Destroyed(site, old)),
+            (false, false) if old.get_type() != new.get_type() => {
+                Some(Change::Transmuted(site, old, new))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn notify(&self, o: &dyn EventObserver) {
+        match *self {
+            Change::Created(site, atom) => o.atom_created(site, atom),
+            Change::Destroyed(site, atom) => o.atom_destroyed(site, atom),
+            Change::Transmuted(site, from, to) => o.type_transmuted(site, from, to),
+        }
+    }
+}
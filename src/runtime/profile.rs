@@ -0,0 +1,216 @@
+//! Profiling the instructions elements run.
+//!
+//! A `Profiler` set on a runtime with `Runtime::set_profiler` counts every
+//! instruction events run, and the time until the next one starts, by
+//! element, instruction number and the calls pending when it ran. Timing each
+//! instruction slows events down several times over, so times are best
+//! compared with each other rather than with unprofiled runs.
+//!
+//! `Profiler::write_folded` writes the counts as folded stacks, one line per
+//! element, routine and label, which flame graph tools such as `inferno` and
+//! `flamegraph.pl` draw directly:
+//!
+//! ```text
+//! Sand;fn_open_sides;_loop 1200
+//! ```
+
+use crate::ast::Instruction;
+use crate::runtime::{Cursor, Runtime};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// What ran at one instruction of an element.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Hits {
+    pub instructions: u64,
+    pub elapsed: Duration,
+}
+
+impl Hits {
+    fn add(&mut self, other: Hits) {
+        self.instructions += other.instructions;
+        self.elapsed += other.elapsed;
+    }
+}
+
+/// The profile of one element.
+#[derive(Clone, Debug, Default)]
+pub struct ElementProfile {
+    pub events: u64,
+    /// Hits by instruction, for each list of pending calls, given by the
+    /// instructions which made them, outermost first.
+    pub paths: HashMap<Vec<usize>, Vec<Hits>>,
+}
+
+impl ElementProfile {
+    /// Hits by instruction, whatever calls were pending.
+    pub fn instructions(&self) -> Vec<Hits> {
+        let mut total: Vec<Hits> = Vec::new();
+        for hits in self.paths.values() {
+            if total.len() < hits.len() {
+                total.resize(hits.len(), Hits::default());
+            }
+            for (t, h) in total.iter_mut().zip(hits) {
+                t.add(*h);
+            }
+        }
+        total
+    }
+
+    pub fn total(&self) -> Hits {
+        let mut total = Hits::default();
+        for h in self.paths.values().flatten() {
+            total.add(*h);
+        }
+        total
+    }
+
+    fn merge(&mut self, paths: HashMap<Vec<usize>, Vec<Hits>>) {
+        for (path, hits) in paths {
+            let mine = self.paths.entry(path).or_default();
+            if mine.len() < hits.len() {
+                mine.resize(hits.len(), Hits::default());
+            }
+            for (m, h) in mine.iter_mut().zip(hits) {
+                m.add(h);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Profiler {
+    profiles: Mutex<BTreeMap<u16, ElementProfile>>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The profile of each element which has run, by type number.
+    pub fn profiles(&self) -> BTreeMap<u16, ElementProfile> {
+        self.profiles.lock().unwrap().clone()
+    }
+
+    pub(crate) fn begin(&self, type_num: u16, len: usize) -> EventProfile {
+        EventProfile {
+            type_num,
+            len,
+            path: Vec::new(),
+            hits: vec![Hits::default(); len],
+            last: None,
+            paths: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn finish(&self, mut profile: EventProfile) {
+        profile.flush(Instant::now());
+        profile.paths.insert(profile.path, profile.hits);
+        let mut profiles = self.profiles.lock().unwrap();
+        let element = profiles.entry(profile.type_num).or_default();
+        element.events += 1;
+        element.merge(profile.paths);
+    }
+
+    /// Writes the instruction counts as folded stacks: the element's name,
+    /// the label of each routine called, and the label the instruction
+    /// follows, if it isn't the routine's own.
+    pub fn write_folded<W: Write>(&self, mut w: W, runtime: &Runtime) -> io::Result<()> {
+        let mut lines: BTreeMap<String, u64> = BTreeMap::new();
+        for (t, profile) in self.profiles() {
+            let name = match runtime.metadata(t) {
+                Some(m) => m.name.clone(),
+                None => format!("type {}", t),
+            };
+            let code = runtime.code(t).unwrap_or_default();
+            let labels = runtime.labels(t).unwrap_or_default();
+            let label = |ip: usize| {
+                labels
+                    .iter()
+                    .find(|(_, i)| *i as usize == ip)
+                    .map_or_else(|| format!("@{}", ip), |(name, _)| name.clone())
+            };
+            for (path, hits) in profile.paths.iter() {
+                let mut frames = vec![name.clone()];
+                let mut routine = 0;
+                for site in path {
+                    if let Some(Instruction::Call(x)) = code.get(*site) {
+                        routine = *x.runtime() as usize;
+                        frames.push(label(routine));
+                    }
+                }
+                for (ip, h) in hits.iter().enumerate().filter(|(_, h)| h.instructions > 0) {
+                    let mut stack = frames.join(";");
+                    // The first label of the last labeled instruction at or
+                    // before this one.
+                    let at = labels
+                        .iter()
+                        .map(|(_, i)| *i as usize)
+                        .filter(|i| *i <= ip)
+                        .max()
+                        .filter(|i| *i > routine || path.is_empty());
+                    let block = labels.iter().find(|(_, i)| Some(*i as usize) == at);
+                    if let Some((block, _)) = block {
+                        stack.push(';');
+                        stack.push_str(block);
+                    }
+                    *lines.entry(stack).or_default() += h.instructions;
+                }
+            }
+        }
+        for (stack, n) in lines {
+            writeln!(w, "{} {}", stack, n)?;
+        }
+        Ok(())
+    }
+}
+
+/// The profile of one event as it runs.
+pub(crate) struct EventProfile {
+    type_num: u16,
+    /// The length of the element's code.
+    len: usize,
+    /// The pending calls of the last instruction, and the hits under them.
+    path: Vec<usize>,
+    hits: Vec<Hits>,
+    /// The last instruction and when it started.
+    last: Option<(usize, Instant)>,
+    /// The hits under other pending calls.
+    paths: HashMap<Vec<usize>, Vec<Hits>>,
+}
+
+impl EventProfile {
+    /// Called before each instruction.
+    pub(crate) fn instruction(&mut self, cursor: &Cursor) {
+        let now = Instant::now();
+        self.flush(now);
+        // Calls and returns change the depth, so the same depth means the same
+        // calls.
+        if cursor.call_stack().len() != self.path.len() {
+            let path = cursor.call_stack().to_vec();
+            let hits = self
+                .paths
+                .remove(&path)
+                .unwrap_or_else(|| vec![Hits::default(); self.len]);
+            let path = std::mem::replace(&mut self.path, path);
+            let hits = std::mem::replace(&mut self.hits, hits);
+            self.paths.insert(path, hits);
+        }
+        self.last = Some((cursor.ip(), now));
+    }
+
+    /// Charges the last instruction with the time until `now`.
+    fn flush(&mut self, now: Instant) {
+        let (ip, start) = match self.last.take() {
+            Some(last) => last,
+            None => return,
+        };
+        self.hits[ip].add(Hits {
+            instructions: 1,
+            elapsed: now - start,
+        });
+    }
+}
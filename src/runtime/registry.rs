@@ -0,0 +1,173 @@
+//! Numbering element types.
+//!
+//! Programs name the element types they refer to and are given numbers when
+//! they are loaded, in the order they are first seen. Empty is always 0.
+//!
+//! Numbers depend on which programs are loaded and in what order, so a grid
+//! saved by one run may not make sense to the next. A numbering file keeps
+//! them stable: one line per type, giving its number and name, with blank
+//! lines and lines starting with `#` ignored:
+//!
+//! ```text
+//! 1 Res
+//! 2 DReg
+//! ```
+//!
+//! Types in the file keep their numbers and new ones are numbered after the
+//! highest, so adding elements never renumbers those already saved.
+
+use crate::runtime::Error;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, BufRead, Write};
+
+#[derive(Clone, Debug)]
+pub struct ElementRegistry {
+    numbers: HashMap<String, u16>,
+    names: BTreeMap<u16, String>,
+    loaded: HashSet<u16>,
+    referenced: HashSet<u16>,
+    /// Whether a loaded element has replaced the built-in Empty.
+    empty_defined: bool,
+}
+
+impl ElementRegistry {
+    pub const EMPTY: &'static str = "Empty";
+
+    pub fn new() -> Self {
+        let mut r = Self {
+            numbers: HashMap::new(),
+            names: BTreeMap::new(),
+            loaded: HashSet::new(),
+            referenced: HashSet::new(),
+            empty_defined: false,
+        };
+        r.insert(Self::EMPTY, 0).unwrap();
+        r.loaded.insert(0);
+        r
+    }
+
+    /// Reads a numbering file. Fails with `TypeCollision` if two names share
+    /// a number and `TypeNumberedTwice` if a name is given two numbers.
+    pub fn load<R: BufRead>(r: R) -> Result<Self, Error> {
+        let mut reg = Self::new();
+        for (i, line) in r.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let (n, name) = match (words.next(), words.next(), words.next()) {
+                (Some(n), Some(name), None) => (n, name),
+                _ => return Err(Error::BadNumberingLine(i + 1)),
+            };
+            let n = n.parse().map_err(|_| Error::BadNumberingLine(i + 1))?;
+            if reg.numbers.get(name) == Some(&n) {
+                continue;
+            }
+            reg.insert(name, n)?;
+        }
+        Ok(reg)
+    }
+
+    /// Writes every numbered type in the format read by `load`.
+    pub fn save<W: Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(
+            w,
+            "# Element type numbers. Keep with grids saved using them."
+        )?;
+        for (n, name) in self.names.iter().filter(|(n, _)| **n != 0) {
+            writeln!(w, "{} {}", n, name)?;
+        }
+        w.flush()
+    }
+
+    fn insert(&mut self, name: &str, n: u16) -> Result<(), Error> {
+        if let Some(first) = self.names.get(&n) {
+            return Err(Error::TypeCollision {
+                number: n,
+                first: first.to_owned(),
+                second: name.to_owned(),
+            });
+        }
+        if self.numbers.contains_key(name) {
+            return Err(Error::TypeNumberedTwice(name.to_owned()));
+        }
+        self.numbers.insert(name.to_owned(), n);
+        self.names.insert(n, name.to_owned());
+        Ok(())
+    }
+
+    fn assign(&mut self, name: &str) -> Result<u16, Error> {
+        if let Some(n) = self.numbers.get(name) {
+            return Ok(*n);
+        }
+        let last = self.names.keys().next_back().copied().unwrap_or(0);
+        let n = last.checked_add(1).ok_or(Error::TooManyTypes)?;
+        self.insert(name, n)?;
+        Ok(n)
+    }
+
+    /// The number of the type `name` referred to by a program, numbering it
+    /// if it is new.
+    pub fn number(&mut self, name: &str) -> Result<u16, Error> {
+        let n = self.assign(name)?;
+        self.referenced.insert(n);
+        Ok(n)
+    }
+
+    /// Numbers the element `name` being loaded. Fails with `DuplicateElement`
+    /// if an element of that name is already loaded. The first element named
+    /// Empty replaces the built-in one as type 0.
+    pub fn define(&mut self, name: &str) -> Result<u16, Error> {
+        let n = self.assign(name)?;
+        if n == 0 && !self.empty_defined {
+            self.empty_defined = true;
+            return Ok(n);
+        }
+        if !self.loaded.insert(n) {
+            return Err(Error::DuplicateElement(name.to_owned()));
+        }
+        Ok(n)
+    }
+
+    pub fn get(&self, name: &str) -> Option<u16> {
+        self.numbers.get(name).copied()
+    }
+
+    pub fn name(&self, n: u16) -> Option<&str> {
+        self.names.get(&n).map(String::as_str)
+    }
+
+    /// Types referred to by loaded programs whose element has not been
+    /// loaded. `gettype` gives their number, but no atom will have it.
+    pub fn unresolved(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.names
+            .iter()
+            .filter(move |(n, _)| self.referenced.contains(n) && !self.loaded.contains(n))
+            .map(|(n, name)| (*n, name.as_str()))
+    }
+}
+
+impl Default for ElementRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn define_empty_replaces_built_in_once() {
+        let mut r = ElementRegistry::new();
+        assert_eq!(r.define(ElementRegistry::EMPTY).unwrap(), 0);
+        assert!(matches!(
+            r.define(ElementRegistry::EMPTY),
+            Err(Error::DuplicateElement(_))
+        ));
+        assert_eq!(r.define("Res").unwrap(), 1);
+        assert_eq!(r.unresolved().count(), 0);
+    }
+}
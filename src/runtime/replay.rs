@@ -0,0 +1,294 @@
+//! Recording and replaying grid runs.
+//!
+//! A recording starts with the generator's seed and state, followed by one
+//! record per event: the event's site, the symmetry picked at its start and
+//! every random value drawn while choosing the site and running the event.
+//! Replaying feeds the recorded draws back to the runtime, so the run is
+//! reproduced even if the way values are drawn has since changed, and stops
+//! with `ReplayDiverged` as soon as an event no longer matches.
+//!
+//! All integers are big endian:
+//!
+//! ```text
+//! magic u32, version u16, seed u64, state [u64; 4]
+//! { x u16, y u16, symmetry u8, count u32, draws [u64; count] }*
+//! ```
+//!
+//! Version 1 recordings, whose counts are u16, are still replayed.
+
+use crate::base::Symmetries;
+use crate::runtime::grid::Grid;
+use crate::runtime::rng::Prng;
+use crate::runtime::{Error, Runtime};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+const MAGIC_NUMBER: u32 = 0x45575250;
+const VERSION: u16 = 2;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordedEvent {
+    pub x: u16,
+    pub y: u16,
+    pub symmetry: Symmetries,
+    pub draws: Vec<u64>,
+}
+
+impl RecordedEvent {
+    /// Runs one event at a random site, as `Grid::step`, recording it.
+    /// Fails without running it if the grid is too large for its sites to
+    /// be recorded.
+    pub(crate) fn run(grid: &Grid, runtime: &mut Runtime) -> Result<Self, Error> {
+        for n in [grid.width(), grid.height()].iter() {
+            u16::try_from(n - 1).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, "grid too large to record")
+            })?;
+        }
+        runtime.rng_mut().start_recording();
+        let (x, y) = grid.random_site(runtime.rng_mut());
+        let result = grid.event_at(runtime, x, y);
+        let draws = runtime.rng_mut().stop_recording();
+        result?;
+        Ok(Self {
+            x: x as u16,
+            y: y as u16,
+            symmetry: runtime.event_symmetry(),
+            draws,
+        })
+    }
+
+    /// Runs the event again, as event number `n` of a replay, failing with
+    /// `ReplayDiverged` if it no longer matches.
+    pub(crate) fn replay(&self, grid: &Grid, runtime: &mut Runtime, n: u64) -> Result<(), Error> {
+        let rng = runtime.rng_mut();
+        rng.play(&self.draws);
+        let (x, y) = grid.random_site(rng);
+        let result = if (x, y) == (self.x as usize, self.y as usize) {
+            grid.event_at(runtime, x, y)
+        } else {
+            Err(Error::ReplayDiverged(n))
+        };
+        let exhausted = runtime.rng().tape_len() == 0;
+        runtime.rng_mut().clear_tape();
+        result?;
+        if !exhausted || runtime.event_symmetry() != self.symmetry {
+            return Err(Error::ReplayDiverged(n));
+        }
+        Ok(())
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_u16::<BigEndian>(self.x)?;
+        w.write_u16::<BigEndian>(self.y)?;
+        w.write_u8(self.symmetry.bits())?;
+        w.write_u32::<BigEndian>(self.draws.len() as u32)?;
+        for d in self.draws.iter() {
+            w.write_u64::<BigEndian>(*d)?;
+        }
+        Ok(())
+    }
+
+    fn read<R: Read>(r: &mut R, version: u16) -> Result<Option<Self>, Error> {
+        let x = match r.read_u16::<BigEndian>() {
+            Ok(x) => x,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let y = r.read_u16::<BigEndian>()?;
+        let symmetry = r.read_u8()?.into();
+        let count = match version {
+            1 => r.read_u16::<BigEndian>()? as u32,
+            _ => r.read_u32::<BigEndian>()?,
+        };
+        let draws = (0..count)
+            .map(|_| r.read_u64::<BigEndian>())
+            .collect::<io::Result<_>>()?;
+        Ok(Some(Self {
+            x,
+            y,
+            symmetry,
+            draws,
+        }))
+    }
+}
+
+pub struct Recorder<W: Write> {
+    w: W,
+    events: u64,
+}
+
+impl<W: Write> Recorder<W> {
+    /// Starts a recording from the current state of `runtime`'s generator.
+    pub fn new(mut w: W, runtime: &Runtime) -> io::Result<Self> {
+        let rng = runtime.rng();
+        w.write_u32::<BigEndian>(MAGIC_NUMBER)?;
+        w.write_u16::<BigEndian>(VERSION)?;
+        w.write_u64::<BigEndian>(rng.seed())?;
+        for s in rng.state().iter() {
+            w.write_u64::<BigEndian>(*s)?;
+        }
+        Ok(Self { w, events: 0 })
+    }
+
+    pub fn events(&self) -> u64 {
+        self.events
+    }
+
+    /// Runs one event at a random site, as `Grid::step`, and records it.
+    pub fn step(&mut self, grid: &Grid, runtime: &mut Runtime) -> Result<(), Error> {
+        RecordedEvent::run(grid, runtime)?.write(&mut self.w)?;
+        self.events += 1;
+        Ok(())
+    }
+
+    /// Flushes the recording and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+pub struct Replayer<R: Read> {
+    r: R,
+    version: u16,
+    rng: Prng,
+    events: u64,
+}
+
+impl<R: Read> Replayer<R> {
+    pub fn new(mut r: R) -> Result<Self, Error> {
+        let magic = r.read_u32::<BigEndian>()?;
+        if magic != MAGIC_NUMBER {
+            return Err(Error::BadMagicNumber(magic));
+        }
+        let version = r.read_u16::<BigEndian>()?;
+        if version == 0 || version > VERSION {
+            return Err(Error::BadMajorVersion(version));
+        }
+        let seed = r.read_u64::<BigEndian>()?;
+        let mut state = [0; 4];
+        for s in state.iter_mut() {
+            *s = r.read_u64::<BigEndian>()?;
+        }
+        Ok(Self {
+            r,
+            version,
+            rng: Prng::from_state(seed, state),
+            events: 0,
+        })
+    }
+
+    /// The generator as it was when recording started. Give it to the runtime
+    /// with `Runtime::set_rng` before replaying.
+    pub fn rng(&self) -> Prng {
+        self.rng.clone()
+    }
+
+    pub fn events(&self) -> u64 {
+        self.events
+    }
+
+    /// Replays the next recorded event. Returns `false` once the recording
+    /// is exhausted.
+    pub fn step(&mut self, grid: &Grid, runtime: &mut Runtime) -> Result<bool, Error> {
+        let record = match RecordedEvent::read(&mut self.r, self.version)? {
+            Some(record) => record,
+            None => return Ok(false),
+        };
+        record.replay(grid, runtime, self.events)?;
+        self.events += 1;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib;
+
+    #[test]
+    fn record_round_trip_over_u16_draws() {
+        let record = RecordedEvent {
+            x: 3,
+            y: 4,
+            symmetry: 1.into(),
+            draws: (0..70000).collect(),
+        };
+        let mut bytes = Vec::new();
+        record.write(&mut bytes).unwrap();
+        let read = RecordedEvent::read(&mut &bytes[..], VERSION).unwrap();
+        assert_eq!(read, Some(record));
+    }
+
+    #[test]
+    fn read_version_1_record() {
+        let bytes = [
+            0, 3, 0, 4, 1, 0, 2, 0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 0, 0, 0, 0, 8,
+        ];
+        let read = RecordedEvent::read(&mut &bytes[..], 1).unwrap().unwrap();
+        assert_eq!((read.x, read.y), (3, 4));
+        assert_eq!(read.draws, vec![7, 8]);
+        assert_eq!(RecordedEvent::read(&mut &[][..], 1).unwrap(), None);
+    }
+
+    /// A 16 by 16 grid with Res on every seventh site, and a runtime with Res
+    /// loaded seeded with `seed`.
+    fn res_grid(seed: u64) -> (Grid, Runtime<'static>) {
+        let mut runtime = Runtime::new();
+        runtime.reseed(seed);
+        let code = stdlib::compile("Res", "test").unwrap().unwrap();
+        let res = runtime.load_from_reader(&mut &code[..]).unwrap();
+        let mut grid = Grid::new(1, 1, 16, 16);
+        for i in (0..16 * 16).step_by(7) {
+            grid.set(i % 16, i / 16, res);
+        }
+        (grid, runtime)
+    }
+
+    fn record(events: u64) -> (Vec<u8>, u64) {
+        let (grid, mut runtime) = res_grid(5);
+        let mut recorder = Recorder::new(Vec::new(), &runtime).unwrap();
+        for _ in 0..events {
+            recorder.step(&grid, &mut runtime).unwrap();
+        }
+        assert_eq!(recorder.events(), events);
+        (recorder.finish().unwrap(), grid.digest())
+    }
+
+    #[test]
+    fn replay_reproduces_recording() {
+        let (bytes, digest) = record(2000);
+        // The replay takes its generator from the recording, not the seed.
+        let (grid, mut runtime) = res_grid(6);
+        let mut replayer = Replayer::new(&bytes[..]).unwrap();
+        runtime.set_rng(replayer.rng());
+        while replayer.step(&grid, &mut runtime).unwrap() {}
+        assert_eq!(replayer.events(), 2000);
+        assert_eq!(grid.digest(), digest);
+    }
+
+    #[test]
+    fn replay_diverges_on_another_grid() {
+        let (bytes, _) = record(200);
+        let (_, mut runtime) = res_grid(5);
+        let grid = Grid::new(1, 1, 16, 16);
+        let mut replayer = Replayer::new(&bytes[..]).unwrap();
+        runtime.set_rng(replayer.rng());
+        let result = (0..200).try_for_each(|_| replayer.step(&grid, &mut runtime).map(|_| ()));
+        assert!(matches!(result, Err(Error::ReplayDiverged(_))));
+    }
+
+    #[test]
+    fn refuse_grids_too_large_to_record() {
+        let mut runtime = Runtime::new();
+        let grid = Grid::new(1, 1, 1 << 16, 1);
+        assert!(RecordedEvent::run(&grid, &mut runtime).is_ok());
+        let grid = Grid::new(1, 1, (1 << 16) + 1, 1);
+        assert!(matches!(
+            RecordedEvent::run(&grid, &mut runtime),
+            Err(Error::IOError(_))
+        ));
+        assert_eq!(grid.event_count(), 0);
+    }
+}
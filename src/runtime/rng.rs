@@ -0,0 +1,162 @@
+//! The engine's random number generator.
+//!
+//! All randomness in a run (site selection, symmetry choice and random
+//! instructions) is drawn from a `Prng`, so runs with the same seed and
+//! programs are reproducible. The generator is xoshiro256** seeded through
+//! SplitMix64; unlike `rand::rngs::StdRng` its output is fixed across versions
+//! and platforms.
+//!
+//! For record and replay a generator can log the values it hands out and can
+//! be given a tape of values to hand out instead. The underlying state keeps
+//! advancing either way, so a replayed run ends in the same state as the
+//! recorded one.
+//!
+//! `hash` is the stateless counterpart the `hash` instruction uses: it mixes
+//! its inputs with wyhash's multiply and fold, draws nothing from any
+//! generator and gives the same result on every platform.
+
+use rand::{Error, RngCore};
+use std::collections::VecDeque;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Prng {
+    seed: u64,
+    state: [u64; 4],
+    log: Option<Vec<u64>>,
+    tape: VecDeque<u64>,
+}
+
+/// wyhash's mixing constants.
+const WY0: u64 = 0xa076_1d64_78bd_642f;
+const WY1: u64 = 0xe703_7ed1_a0b4_28db;
+
+/// The 128 bit product of `a` and `b`, its halves xored together.
+fn mum(a: u64, b: u64) -> u64 {
+    let r = a as u128 * b as u128;
+    r as u64 ^ (r >> 64) as u64
+}
+
+/// A 64 bit hash of `value` and the coordinates `x` and `y`.
+pub fn hash(value: u128, x: i64, y: i64) -> u64 {
+    let v = mum(value as u64 ^ WY0, (value >> 64) as u64 ^ WY1);
+    let site = mum(x as u64 ^ WY1, y as u64 ^ WY0);
+    mum(v ^ WY1, site ^ WY0)
+}
+
+fn splitmix64(x: &mut u64) -> u64 {
+    *x = x.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *x;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+impl Prng {
+    pub fn new(seed: u64) -> Self {
+        let mut x = seed;
+        let state = [
+            splitmix64(&mut x),
+            splitmix64(&mut x),
+            splitmix64(&mut x),
+            splitmix64(&mut x),
+        ];
+        Self::from_state(seed, state)
+    }
+
+    /// Seeds from the operating system.
+    pub fn from_entropy() -> Self {
+        Self::new(rand::random())
+    }
+
+    /// Restores a generator saved with `seed` and `state`.
+    pub fn from_state(seed: u64, state: [u64; 4]) -> Self {
+        Self {
+            seed,
+            state,
+            log: None,
+            tape: VecDeque::new(),
+        }
+    }
+
+    /// The seed this generator was created with.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The current state, for saving alongside the seed.
+    pub fn state(&self) -> [u64; 4] {
+        self.state
+    }
+
+    /// Derives a new generator, e.g. for a worker thread. The derived seed is
+    /// drawn from this generator, so forking is itself reproducible.
+    pub fn fork(&mut self) -> Self {
+        Self::new(self.next_u64())
+    }
+
+    /// Starts logging every value handed out, discarding any previous log.
+    pub fn start_recording(&mut self) {
+        self.log = Some(Vec::new());
+    }
+
+    /// Stops logging and returns the values handed out since
+    /// `start_recording`.
+    pub fn stop_recording(&mut self) -> Vec<u64> {
+        self.log.take().unwrap_or_default()
+    }
+
+    /// Queues values to be handed out in place of generated ones.
+    pub fn play(&mut self, draws: &[u64]) {
+        self.tape.extend(draws);
+    }
+
+    /// The number of queued values not yet handed out.
+    pub fn tape_len(&self) -> usize {
+        self.tape.len()
+    }
+
+    /// Drops any queued values.
+    pub fn clear_tape(&mut self) {
+        self.tape.clear();
+    }
+
+    fn advance(&mut self) -> u64 {
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+}
+
+impl RngCore for Prng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let generated = self.advance();
+        let x = self.tape.pop_front().unwrap_or(generated);
+        if let Some(log) = self.log.as_mut() {
+            log.push(x);
+        }
+        x
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let x = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&x[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
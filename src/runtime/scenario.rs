@@ -0,0 +1,236 @@
+//! Scenario files describing the initial conditions of an experiment.
+//!
+//! A scenario sets up a new grid by placing atoms of elements already loaded,
+//! so a run can be repeated from the file alone rather than code. Like an
+//! `.mfs` file it is a list of statements written as function calls, one per
+//! line; blank lines and lines starting with `#` are ignored:
+//!
+//! ```text
+//! # A DReg in a walled box, among Res.
+//! Size(64, 48)
+//! Seed(42)
+//! Fill(0, 0, 63, 0, Wall)
+//! Fill(0, 47, 63, 47, Wall)
+//! Scatter(50, Res)
+//! Place(10, 12, DReg)
+//! Center(DReg)
+//! ```
+//!
+//! `Size(width, height)` and `Seed(seed)` give the grid's size and the seed
+//! of the random number generator, in place of those given otherwise.
+//! Placements then run in order, each overwriting what is already there:
+//!
+//! - `Fill(x0, y0, x1, y1, element)` fills the rectangle between the two
+//!   corners, both included, with atoms of the element.
+//! - `Scatter(count, element)` places that many atoms at empty sites chosen
+//!   at random from the seeded generator.
+//! - `Place(x, y, element)` places one atom at a site.
+//! - `Center(element)` places one atom at the center of the grid.
+//!
+//! Elements are named as they are loaded; `Empty` clears sites.
+//! `Param(element.NAME, value)` overrides one of an element's parameters, as
+//! `ParameterOverride` does.
+
+use crate::base::arith::Const;
+use crate::base::atom::Atom;
+use crate::base::ecc;
+use crate::runtime::grid::Grid;
+use crate::runtime::registry::ElementRegistry;
+use crate::runtime::{Error, Runtime};
+use rand::seq::index;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScenarioError {
+    #[error("IO error: {0}")]
+    IOError(#[from] io::Error),
+    #[error("bad scenario statement on line {0}")]
+    BadLine(usize),
+    #[error("no element named {element} on line {line}")]
+    UnknownElement { element: String, line: usize },
+    #[error("placement off the grid on line {0}")]
+    OffGrid(usize),
+    #[error("too few empty sites to scatter on line {0}")]
+    NoRoom(usize),
+    #[error("{error} on line {line}")]
+    Parameter { error: Error, line: usize },
+}
+
+/// A new value for a parameter of an element, written `element.NAME=VALUE`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParameterOverride {
+    pub element: String,
+    pub name: String,
+    pub value: Const,
+}
+
+impl ParameterOverride {
+    /// Sets the parameter in `runtime`; see `Runtime::set_parameter`.
+    pub fn apply(&self, runtime: &mut Runtime) -> Result<(), Error> {
+        runtime.set_parameter(&self.element, &self.name, self.value)
+    }
+
+    fn parse(path: &str, value: &str) -> Option<Self> {
+        let (element, name) = path.trim().split_once('.')?;
+        if element.is_empty() || name.is_empty() {
+            return None;
+        }
+        Some(Self {
+            element: element.to_owned(),
+            name: name.to_owned(),
+            value: value.trim().parse().ok()?,
+        })
+    }
+}
+
+impl FromStr for ParameterOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split_once('=')
+            .and_then(|(path, value)| Self::parse(path, value))
+            .ok_or_else(|| format!("expected ELEMENT.NAME=VALUE, not {}", s))
+    }
+}
+
+/// Where a scenario places atoms of an element.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Placement {
+    Fill {
+        x0: usize,
+        y0: usize,
+        x1: usize,
+        y1: usize,
+    },
+    Scatter(usize),
+    Place(usize, usize),
+    Center,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Scenario {
+    pub size: Option<(usize, usize)>,
+    pub seed: Option<u64>,
+    /// The placements in order, with the element placed and the line each
+    /// came from.
+    pub placements: Vec<(Placement, String, usize)>,
+    /// The parameter overrides, with the line each came from.
+    pub parameters: Vec<(ParameterOverride, usize)>,
+}
+
+impl Scenario {
+    /// Reads the scenario at `path`.
+    pub fn load(path: &Path) -> Result<Self, ScenarioError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(src: &str) -> Result<Self, ScenarioError> {
+        let mut scenario = Self::default();
+        for (n, line) in src.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad = || ScenarioError::BadLine(n + 1);
+            let open = line.find('(').ok_or_else(bad)?;
+            let args = line[open + 1..].strip_suffix(')').ok_or_else(bad)?;
+            let args: Vec<&str> = args.split(',').map(str::trim).collect();
+            let num = |i: usize| args[i].parse::<usize>().map_err(|_| bad());
+            let element = || args.last().unwrap().to_string();
+            let placement = match (line[..open].trim(), args.len()) {
+                ("Size", 2) if num(0)? > 0 && num(1)? > 0 => {
+                    scenario.size = Some((num(0)?, num(1)?));
+                    continue;
+                }
+                ("Seed", 1) => {
+                    scenario.seed = Some(args[0].parse().map_err(|_| bad())?);
+                    continue;
+                }
+                ("Param", 2) => {
+                    let p = ParameterOverride::parse(args[0], args[1]).ok_or_else(bad)?;
+                    scenario.parameters.push((p, n + 1));
+                    continue;
+                }
+                ("Fill", 5) => Placement::Fill {
+                    x0: num(0)?,
+                    y0: num(1)?,
+                    x1: num(2)?,
+                    y1: num(3)?,
+                },
+                ("Scatter", 2) => Placement::Scatter(num(0)?),
+                ("Place", 3) => Placement::Place(num(0)?, num(1)?),
+                ("Center", 1) => Placement::Center,
+                _ => return Err(bad()),
+            };
+            if element().is_empty() {
+                return Err(bad());
+            }
+            scenario.placements.push((placement, element(), n + 1));
+        }
+        Ok(scenario)
+    }
+
+    /// Overrides the scenario's parameters in `runtime` and places its atoms
+    /// on `grid`, drawing random sites from `runtime`'s generator, which is not
+    /// reseeded.
+    pub fn populate(&self, grid: &mut Grid, runtime: &mut Runtime) -> Result<(), ScenarioError> {
+        for (p, line) in self.parameters.iter() {
+            p.apply(runtime)
+                .map_err(|error| ScenarioError::Parameter { error, line: *line })?;
+        }
+        let (width, height) = (grid.width(), grid.height());
+        for (placement, element, line) in self.placements.iter() {
+            let atom = match element.as_str() {
+                ElementRegistry::EMPTY => Atom::default(),
+                name => match runtime.registry().get(name) {
+                    Some(t) if runtime.metadata(t).is_some() => ecc::encode(Atom::new(t)),
+                    _ => {
+                        return Err(ScenarioError::UnknownElement {
+                            element: element.clone(),
+                            line: *line,
+                        })
+                    }
+                },
+            };
+            let off = || ScenarioError::OffGrid(*line);
+            match *placement {
+                Placement::Fill { x0, y0, x1, y1 } => {
+                    if x0.max(x1) >= width || y0.max(y1) >= height {
+                        return Err(off());
+                    }
+                    for y in y0.min(y1)..=y0.max(y1) {
+                        for x in x0.min(x1)..=x0.max(x1) {
+                            grid.set(x, y, atom);
+                        }
+                    }
+                }
+                Placement::Scatter(count) => {
+                    let mut empty = Vec::new();
+                    for y in 0..height {
+                        for x in 0..width {
+                            if matches!(grid.get(x, y), Some(a) if a.is_empty()) {
+                                empty.push((x, y));
+                            }
+                        }
+                    }
+                    if count > empty.len() {
+                        return Err(ScenarioError::NoRoom(*line));
+                    }
+                    for i in index::sample(runtime.rng_mut(), empty.len(), count) {
+                        let (x, y) = empty[i];
+                        grid.set(x, y, atom);
+                    }
+                }
+                Placement::Place(x, y) => match grid.get(x, y) {
+                    Some(_) => grid.set(x, y, atom),
+                    None => return Err(off()),
+                },
+                Placement::Center => grid.set(width / 2, height / 2, atom),
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,321 @@
+//! Choosing where events run.
+//!
+//! A grid picks the site of each event with a `Scheduler`. `Scheduling` names
+//! those built in, and other policies can be given to `Grid::set_scheduler`
+//! by implementing the trait, to study how the order of events shapes what
+//! elements do.
+//!
+//! Only atoms act: an event at an empty site runs no code and changes
+//! nothing. In a sparse world most uniformly chosen events are such no-ops,
+//! so a grid can instead keep the set of sites holding atoms and run events
+//! only there, counting the empty-site events it skips without running them.
+//! Each event then still lands on a given atom with the same probability per
+//! event counted as under uniform selection, so AEPS keeps its meaning,
+//! though the random choices made differ.
+
+use crate::runtime::grid::Grid;
+use crate::runtime::rng::Prng;
+use crate::runtime::Error;
+use rand::Rng;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
+
+/// How a grid picks the sites of its events.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scheduling {
+    /// Every site is equally likely. The default.
+    Uniform,
+    /// Events run only at sites holding atoms; see the module docs.
+    /// Observers are not told of the skipped events.
+    Active,
+    /// Sites are chosen as by `Uniform`, while the set `Active` would choose
+    /// from is kept and checked against each chosen site, failing with
+    /// `Error::ActiveSetMismatch` if they disagree.
+    Verify,
+    /// Every site in turn, row by row from the top left, so each gets
+    /// exactly one event per sweep.
+    Sweep,
+    /// Each site has its own clock ticking at random, with exponentially
+    /// distributed gaps averaging one, and the site whose clock ticks next
+    /// runs; see `PoissonScheduler`.
+    Poisson,
+    /// Half the events go to the neighborhoods of sites where recent events
+    /// changed something and half to sites picked uniformly; see
+    /// `PrioritizedScheduler`.
+    Prioritized,
+}
+
+impl std::str::FromStr for Scheduling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uniform" => Ok(Self::Uniform),
+            "active" => Ok(Self::Active),
+            "verify" => Ok(Self::Verify),
+            "sweep" => Ok(Self::Sweep),
+            "poisson" => Ok(Self::Poisson),
+            "prioritized" => Ok(Self::Prioritized),
+            _ => Err(format!("unknown scheduling: {}", s)),
+        }
+    }
+}
+
+impl Scheduling {
+    /// A new scheduler picking sites this way.
+    pub fn scheduler(self) -> Box<dyn Scheduler> {
+        match self {
+            Self::Uniform => Box::new(UniformScheduler),
+            Self::Active => Box::new(ActiveScheduler),
+            Self::Verify => Box::new(VerifyScheduler),
+            Self::Sweep => Box::new(SweepScheduler::default()),
+            Self::Poisson => Box::new(PoissonScheduler::default()),
+            Self::Prioritized => Box::new(PrioritizedScheduler::new(0.5)),
+        }
+    }
+}
+
+/// A site picked by a scheduler: the number of events counted without
+/// running before it, and the site, or none if the events counted used up
+/// all those asked for.
+pub type Pick = (u64, Option<(usize, usize)>);
+
+/// Picks the sites of a grid's events.
+pub trait Scheduler: fmt::Debug + Send {
+    /// Picks the site of the next event, given `max`, at least 1. Any events
+    /// counted without running must leave room for the event picked: fewer
+    /// than `max`, or exactly `max` with no site. The grid adds them to its
+    /// event count.
+    fn next_site(&mut self, grid: &Grid, rng: &mut Prng, max: u64) -> Result<Pick, Error>;
+
+    /// Whether the grid keeps the set of sites holding atoms,
+    /// `Grid::active_sites`, up to date for this scheduler.
+    fn needs_active_sites(&self) -> bool {
+        false
+    }
+
+    /// Whether the grid calls `event_ran`.
+    fn observes_events(&self) -> bool {
+        false
+    }
+
+    /// Told of each event the grid ran at `site`, numbered row by row, and
+    /// whether it changed any atom of its window.
+    fn event_ran(&mut self, _site: usize, _changed: bool) {}
+}
+
+/// `Scheduling::Uniform`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UniformScheduler;
+
+impl Scheduler for UniformScheduler {
+    fn next_site(&mut self, grid: &Grid, rng: &mut Prng, _max: u64) -> Result<Pick, Error> {
+        Ok((0, Some(grid.random_site(rng))))
+    }
+}
+
+/// `Scheduling::Active`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ActiveScheduler;
+
+impl Scheduler for ActiveScheduler {
+    fn next_site(&mut self, grid: &Grid, rng: &mut Prng, max: u64) -> Result<Pick, Error> {
+        let width = grid.width();
+        let active = grid.active_sites();
+        let skipped = skipped(rng, active.len(), width * grid.height());
+        match active.choose(rng) {
+            Some(site) if skipped < max => Ok((skipped, Some((site % width, site / width)))),
+            _ => Ok((max, None)),
+        }
+    }
+
+    fn needs_active_sites(&self) -> bool {
+        true
+    }
+}
+
+/// `Scheduling::Verify`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VerifyScheduler;
+
+impl Scheduler for VerifyScheduler {
+    fn next_site(&mut self, grid: &Grid, rng: &mut Prng, _max: u64) -> Result<Pick, Error> {
+        let (x, y) = grid.random_site(rng);
+        let empty = grid.get(x, y).unwrap().is_empty();
+        if empty == grid.active_sites().contains(y * grid.width() + x) {
+            return Err(Error::ActiveSetMismatch(x, y));
+        }
+        Ok((0, Some((x, y))))
+    }
+
+    fn needs_active_sites(&self) -> bool {
+        true
+    }
+}
+
+/// `Scheduling::Sweep`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SweepScheduler {
+    next: usize,
+}
+
+impl Scheduler for SweepScheduler {
+    fn next_site(&mut self, grid: &Grid, _rng: &mut Prng, _max: u64) -> Result<Pick, Error> {
+        let site = self.next % (grid.width() * grid.height());
+        self.next = site + 1;
+        Ok((0, Some((site % grid.width(), site / grid.width()))))
+    }
+}
+
+/// `Scheduling::Poisson`: every site's clock ticks as a Poisson process of
+/// rate one, so the time of the last tick, `time`, runs at the grid's AEPS
+/// on average. Sites are equally likely to run next, as under uniform
+/// scheduling, but each gets its own stream of ticks in continuous time. A
+/// clock is kept for every site, so this suits grids of moderate size.
+#[derive(Clone, Debug, Default)]
+pub struct PoissonScheduler {
+    /// The next tick of each site, as the bits of a nonnegative time, which
+    /// order as the times do.
+    clocks: BinaryHeap<Reverse<(u64, usize)>>,
+    time: f64,
+}
+
+impl PoissonScheduler {
+    /// The time of the last tick, in mean gaps between a site's ticks.
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    fn gap(rng: &mut Prng) -> f64 {
+        -(1.0 - rng.gen::<f64>()).ln()
+    }
+}
+
+impl Scheduler for PoissonScheduler {
+    fn next_site(&mut self, grid: &Grid, rng: &mut Prng, _max: u64) -> Result<Pick, Error> {
+        let sites = grid.width() * grid.height();
+        if self.clocks.len() != sites {
+            self.clocks = (0..sites)
+                .map(|site| Reverse(((self.time + Self::gap(rng)).to_bits(), site)))
+                .collect();
+        }
+        let Reverse((tick, site)) = self.clocks.pop().unwrap();
+        self.time = f64::from_bits(tick);
+        let next = self.time + Self::gap(rng);
+        self.clocks.push(Reverse((next.to_bits(), site)));
+        Ok((0, Some((site % grid.width(), site / grid.width()))))
+    }
+}
+
+/// `Scheduling::Prioritized`: sites where the last event changed an atom of
+/// its window are hot, until an event there changes nothing. With a chance
+/// of `bias` an event runs at a random site next to or at a hot site, and
+/// otherwise at a site picked uniformly, so quiet regions still run.
+#[derive(Clone, Debug)]
+pub struct PrioritizedScheduler {
+    bias: f64,
+    hot: ActiveSites,
+}
+
+impl PrioritizedScheduler {
+    /// A scheduler sending a `bias` share of events near hot sites.
+    pub fn new(bias: f64) -> Self {
+        Self {
+            bias: bias.clamp(0.0, 1.0),
+            hot: ActiveSites::default(),
+        }
+    }
+}
+
+impl Scheduler for PrioritizedScheduler {
+    fn next_site(&mut self, grid: &Grid, rng: &mut Prng, _max: u64) -> Result<Pick, Error> {
+        let (width, height) = (grid.width(), grid.height());
+        match self.hot.choose(rng) {
+            Some(site) if rng.gen_bool(self.bias) => {
+                let near = |c: usize, n: usize, d: usize| (c + d).clamp(1, n) - 1;
+                let x = near(site % width, width, rng.gen_range(0..3));
+                let y = near(site / width, height, rng.gen_range(0..3));
+                Ok((0, Some((x, y))))
+            }
+            _ => Ok((0, Some(grid.random_site(rng)))),
+        }
+    }
+
+    fn observes_events(&self) -> bool {
+        true
+    }
+
+    fn event_ran(&mut self, site: usize, changed: bool) {
+        self.hot.update(site, changed);
+    }
+}
+
+/// The sites holding atoms, by site index, in a form which can be sampled
+/// uniformly.
+#[derive(Clone, Debug, Default)]
+pub struct ActiveSites {
+    sites: Vec<usize>,
+    index: HashMap<usize, usize>,
+}
+
+impl ActiveSites {
+    pub fn len(&self) -> usize {
+        self.sites.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sites.is_empty()
+    }
+
+    pub fn contains(&self, site: usize) -> bool {
+        self.index.contains_key(&site)
+    }
+
+    /// Adds or removes `site` as it holds an atom or not.
+    pub fn update(&mut self, site: usize, active: bool) {
+        match (active, self.index.get(&site).copied()) {
+            (true, None) => {
+                self.index.insert(site, self.sites.len());
+                self.sites.push(site);
+            }
+            (false, Some(i)) => {
+                self.index.remove(&site);
+                self.sites.swap_remove(i);
+                if let Some(&moved) = self.sites.get(i) {
+                    self.index.insert(moved, i);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.sites.clear();
+        self.index.clear();
+    }
+
+    /// A site chosen uniformly, if any.
+    pub fn choose<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<usize> {
+        if self.sites.is_empty() {
+            return None;
+        }
+        Some(self.sites[rng.gen_range(0..self.sites.len())])
+    }
+}
+
+/// The number of uniformly chosen events which would miss the `active` of
+/// `sites` sites before one hit: a draw from the geometric distribution.
+pub fn skipped<R: Rng + ?Sized>(rng: &mut R, active: usize, sites: usize) -> u64 {
+    if active >= sites {
+        return 0;
+    }
+    let p = active as f64 / sites as f64;
+    let u = 1.0 - rng.gen::<f64>();
+    let k = (u.ln() / (1.0 - p).ln()).floor();
+    if k >= u64::MAX as f64 {
+        u64::MAX
+    } else {
+        k as u64
+    }
+}
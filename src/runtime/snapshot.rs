@@ -0,0 +1,246 @@
+//! Saving and restoring grids.
+//!
+//! A snapshot holds everything needed to continue a run: the grid's shape,
+//! every site's atom, paint and layers, the number of events run so far, the state
+//! of the runtime's generator and the next id `newid` hands out. Elements are not included; a snapshot must be
+//! resumed with the same programs loaded and numbered the same way, which a
+//! numbering file (see `registry`) keeps true as elements are added.
+//!
+//! All integers are big endian. Atoms are written as their high 32 and low 64
+//! bits, the same as 96 bit constants in bytecode:
+//!
+//! ```text
+//! magic u32, version u16,
+//! tiles_x u16, tiles_y u16, tile_width u16, tile_height u16,
+//! events u64, seed u64, state [u64; 4], next_id u64,
+//! { atom_hi u32, atom_lo u64, paint u32, layers [u32; LAYERS] }*  (row by row)
+//! ```
+//!
+//! Version 1 snapshots, from before layers, have no `layers` and still load,
+//! with every layer zero. Versions 1 and 2, from before `newid`, have no
+//! `next_id` and hand out ids from 1.
+
+use crate::base::atom::Atom;
+use crate::runtime::grid::{Grid, LAYERS};
+use crate::runtime::rng::Prng;
+use crate::runtime::{Error, Runtime};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+const MAGIC_NUMBER: u32 = 0x45575347;
+const VERSION: u16 = 3;
+
+/// Writes `grid`, the state of `runtime`'s generator and its next id to `w`.
+pub fn save<W: Write>(mut w: W, grid: &Grid, runtime: &Runtime) -> io::Result<()> {
+    w.write_u32::<BigEndian>(MAGIC_NUMBER)?;
+    w.write_u16::<BigEndian>(VERSION)?;
+    for n in [
+        grid.tiles_x(),
+        grid.tiles_y(),
+        grid.tile_width(),
+        grid.tile_height(),
+    ]
+    .iter()
+    {
+        let n = u16::try_from(*n)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "grid too large to save"))?;
+        w.write_u16::<BigEndian>(n)?;
+    }
+    w.write_u64::<BigEndian>(grid.event_count())?;
+    let rng = runtime.rng();
+    w.write_u64::<BigEndian>(rng.seed())?;
+    for s in rng.state().iter() {
+        w.write_u64::<BigEndian>(*s)?;
+    }
+    w.write_u64::<BigEndian>(runtime.next_id())?;
+    for y in 0..grid.height() {
+        for x in 0..grid.width() {
+            let bits = grid.get(x, y).unwrap().bits();
+            w.write_u32::<BigEndian>((bits >> 64) as u32)?;
+            w.write_u64::<BigEndian>(bits as u64)?;
+            w.write_u32::<BigEndian>(grid.get_paint(x, y).unwrap().bits())?;
+            for l in 0..LAYERS {
+                w.write_u32::<BigEndian>(grid.get_layer(l, x, y).unwrap())?;
+            }
+        }
+    }
+    w.flush()
+}
+
+/// Reads a grid saved with `save`, restoring `runtime`'s generator and next
+/// id to where they were when the snapshot was taken.
+pub fn load<R: Read>(mut r: R, runtime: &mut Runtime) -> Result<Grid, Error> {
+    let magic = r.read_u32::<BigEndian>()?;
+    if magic != MAGIC_NUMBER {
+        return Err(Error::BadMagicNumber(magic));
+    }
+    let version = r.read_u16::<BigEndian>()?;
+    if !(1..=VERSION).contains(&version) {
+        return Err(Error::BadMajorVersion(version));
+    }
+    let tiles_x = r.read_u16::<BigEndian>()? as usize;
+    let tiles_y = r.read_u16::<BigEndian>()? as usize;
+    let tile_width = r.read_u16::<BigEndian>()? as usize;
+    let tile_height = r.read_u16::<BigEndian>()? as usize;
+    if tiles_x == 0 || tiles_y == 0 || tile_width == 0 || tile_height == 0 {
+        return Err(Error::BadGridSize);
+    }
+    let events = r.read_u64::<BigEndian>()?;
+    let seed = r.read_u64::<BigEndian>()?;
+    let mut state = [0; 4];
+    for s in state.iter_mut() {
+        *s = r.read_u64::<BigEndian>()?;
+    }
+    let next_id = if version > 2 {
+        r.read_u64::<BigEndian>()?
+    } else {
+        1
+    };
+
+    let mut grid = Grid::new(tiles_x, tiles_y, tile_width, tile_height);
+    for y in 0..grid.height() {
+        for x in 0..grid.width() {
+            let hi = r.read_u32::<BigEndian>()? as u128;
+            let lo = r.read_u64::<BigEndian>()? as u128;
+            grid.set(x, y, Atom::from_bits(hi << 64 | lo));
+            grid.set_paint(x, y, r.read_u32::<BigEndian>()?.into());
+            if version > 1 {
+                for l in 0..LAYERS {
+                    grid.set_layer(l, x, y, r.read_u32::<BigEndian>()?);
+                }
+            }
+        }
+    }
+    grid.set_event_count(events);
+    runtime.set_rng(Prng::from_state(seed, state));
+    runtime.set_next_id(next_id);
+    Ok(grid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes before `next_id`: magic, version, shape, events, seed and state.
+    const HEADER: usize = 4 + 2 + 4 * 2 + 8 + 8 + 4 * 8;
+    const SITE: usize = 4 + 8 + 4 + LAYERS * 4;
+
+    /// Two tiles of 3 by 2 with different atoms, paint and layers on every
+    /// site, and a runtime part way through its generator and ids.
+    fn sample() -> (Grid, Runtime<'static>) {
+        let mut grid = Grid::new(2, 1, 3, 2);
+        for y in 0..grid.height() {
+            for x in 0..grid.width() {
+                let i = (y * grid.width() + x) as u128;
+                grid.set(x, y, Atom::from_bits((i << 90) | (i * 0x1_0000_0001)));
+                grid.set_paint(x, y, (0xff00_0000 | i as u32).into());
+                for l in 0..LAYERS {
+                    grid.set_layer(l, x, y, ((i as u32) << 8) | l as u32);
+                }
+            }
+        }
+        grid.set_event_count(12345);
+        let mut runtime = Runtime::new();
+        runtime.set_rng(Prng::from_state(7, [1, 2, 3, 4]));
+        runtime.set_next_id(42);
+        (grid, runtime)
+    }
+
+    /// Rewrites a current snapshot in the format of an older `version`.
+    fn downgrade(bytes: &[u8], version: u16) -> Vec<u8> {
+        let mut old = bytes[..HEADER].to_vec();
+        old[4..6].copy_from_slice(&version.to_be_bytes());
+        for site in bytes[HEADER + 8..].chunks(SITE) {
+            old.extend_from_slice(if version > 1 { site } else { &site[..16] });
+        }
+        old
+    }
+
+    fn assert_sites(grid: &Grid, loaded: &Grid, layers: bool) {
+        assert_eq!(
+            (loaded.tiles_x(), loaded.tiles_y()),
+            (grid.tiles_x(), grid.tiles_y())
+        );
+        assert_eq!(
+            (loaded.tile_width(), loaded.tile_height()),
+            (grid.tile_width(), grid.tile_height())
+        );
+        assert_eq!(loaded.event_count(), grid.event_count());
+        for y in 0..grid.height() {
+            for x in 0..grid.width() {
+                assert_eq!(
+                    loaded.get(x, y).unwrap().bits(),
+                    grid.get(x, y).unwrap().bits()
+                );
+                assert_eq!(
+                    loaded.get_paint(x, y).unwrap().bits(),
+                    grid.get_paint(x, y).unwrap().bits()
+                );
+                for l in 0..LAYERS {
+                    let expected = if layers {
+                        grid.get_layer(l, x, y)
+                    } else {
+                        Some(0)
+                    };
+                    assert_eq!(loaded.get_layer(l, x, y), expected);
+                }
+            }
+        }
+    }
+
+    fn save_sample() -> (Grid, Runtime<'static>, Vec<u8>) {
+        let (grid, runtime) = sample();
+        let mut bytes = Vec::new();
+        save(&mut bytes, &grid, &runtime).unwrap();
+        assert_eq!(
+            bytes.len(),
+            HEADER + 8 + grid.width() * grid.height() * SITE
+        );
+        (grid, runtime, bytes)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (grid, runtime, bytes) = save_sample();
+        let mut resumed = Runtime::new();
+        let loaded = load(&bytes[..], &mut resumed).unwrap();
+        assert_sites(&grid, &loaded, true);
+        assert_eq!(resumed.rng(), runtime.rng());
+        assert_eq!(resumed.next_id(), 42);
+    }
+
+    #[test]
+    fn load_older_versions() {
+        let (grid, runtime, bytes) = save_sample();
+        for &version in [1, 2].iter() {
+            let mut resumed = Runtime::new();
+            resumed.set_next_id(99);
+            let loaded = load(&downgrade(&bytes, version)[..], &mut resumed).unwrap();
+            assert_sites(&grid, &loaded, version > 1);
+            assert_eq!(resumed.rng(), runtime.rng());
+            assert_eq!(resumed.next_id(), 1, "version {}", version);
+        }
+    }
+
+    #[test]
+    fn reject_bad_headers() {
+        let (_, _, bytes) = save_sample();
+        let mut bad = bytes.clone();
+        bad[0] ^= 1;
+        assert!(matches!(
+            load(&bad[..], &mut Runtime::new()),
+            Err(Error::BadMagicNumber(_))
+        ));
+        for &version in [0, VERSION + 1].iter() {
+            let mut bad = bytes.clone();
+            bad[4..6].copy_from_slice(&version.to_be_bytes());
+            assert!(matches!(
+                load(&bad[..], &mut Runtime::new()),
+                Err(Error::BadMajorVersion(v)) if v == version
+            ));
+        }
+        let truncated = &bytes[..bytes.len() - 1];
+        assert!(load(truncated, &mut Runtime::new()).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+//! Statistics about a running grid: how many atoms of each element it holds,
+//! how many events it has run and how quickly.
+//!
+//! Simulated time is measured in AEPS, average events per site: the events
+//! run divided by the number of sites, so that grids of any size can be
+//! compared. AER, the average event rate, is AEPS per second of running time.
+//!
+//! `Metrics` writes reports as rows of a CSV table, for analysis once a run
+//! is over: the events, AEPS, AER and instructions, the census of each
+//! element loaded when the table was started and the counters of `count`.
+
+use crate::ast::Instruction;
+use crate::runtime::grid::{Grid, Stats};
+use crate::runtime::Runtime;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// The number of counters `count` adds to.
+const COUNTERS: usize = Instruction::COUNTERS as usize;
+
+/// The state of a grid at one moment.
+#[derive(Clone, Debug)]
+pub struct Report {
+    pub stats: Stats,
+    pub sites: usize,
+    /// The running time the events took.
+    pub elapsed: Duration,
+    /// The number of sites holding each type, by type number. Empty sites
+    /// are type 0.
+    pub census: BTreeMap<u16, usize>,
+    /// The counters `count` has added to, by number.
+    pub counters: [u64; COUNTERS],
+    names: BTreeMap<u16, String>,
+}
+
+impl Report {
+    /// Takes a census of `grid`, naming types by the elements of `runtime`.
+    pub fn new(grid: &Grid, runtime: &Runtime, elapsed: Duration) -> Self {
+        let sites = grid.width() * grid.height();
+        let mut census = BTreeMap::new();
+        grid.for_each_atom(|_, _, atom| *census.entry(atom.get_type()).or_insert(0) += 1);
+        let empty = sites - census.values().sum::<usize>();
+        if empty > 0 {
+            census.insert(0, empty);
+        }
+        let names = census
+            .keys()
+            .filter_map(|t| Some((*t, runtime.registry().name(*t)?.to_owned())))
+            .collect();
+        Self {
+            stats: grid.stats(),
+            sites,
+            elapsed,
+            census,
+            counters: runtime.counters(),
+            names,
+        }
+    }
+
+    /// Average events per site.
+    pub fn aeps(&self) -> f64 {
+        self.stats.events as f64 / self.sites as f64
+    }
+
+    /// Average events per site per second of running time, or 0 if no time
+    /// has passed.
+    pub fn aer(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.aeps() / secs
+        } else {
+            0.0
+        }
+    }
+
+    pub fn instructions_per_event(&self) -> f64 {
+        if self.stats.events > 0 {
+            self.stats.instructions as f64 / self.stats.events as f64
+        } else {
+            0.0
+        }
+    }
+
+    /// The name of type `t`, if its element is loaded.
+    pub fn name(&self, t: u16) -> Option<&str> {
+        self.names.get(&t).map(String::as_str)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "events {} (AEPS {:.3}, AER {:.3}/s), instructions {} ({:.2} per event)",
+            self.stats.events,
+            self.aeps(),
+            self.aer(),
+            self.stats.instructions,
+            self.instructions_per_event()
+        )?;
+        for (t, n) in self.census.iter().filter(|(t, _)| **t != 0) {
+            match self.name(*t) {
+                Some(name) => writeln!(f, "  {:<12} {}", name, n)?,
+                None => writeln!(f, "  {:<12} {}", format!("type {}", t), n)?,
+            }
+        }
+        for (c, n) in self.counters.iter().enumerate().filter(|(_, n)| **n != 0) {
+            writeln!(f, "  {:<12} {}", format!("count {}", c), n)?;
+        }
+        Ok(())
+    }
+}
+
+/// `s` as a CSV field, quoted if it needs to be.
+pub fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+/// A CSV table of reports, written a row at a time.
+pub struct Metrics<W: Write> {
+    w: W,
+    /// The types with a census column, in order.
+    types: Vec<u16>,
+}
+
+impl<W: Write> Metrics<W> {
+    /// Starts a table in `w`, writing its header, with a census column for
+    /// each element loaded in `runtime`, in type order.
+    pub fn new(mut w: W, runtime: &Runtime) -> io::Result<Self> {
+        let mut elements: Vec<(u16, &str)> = runtime
+            .elements()
+            .filter(|(t, _)| *t != 0)
+            .map(|(t, m)| (t, m.name.as_str()))
+            .collect();
+        elements.sort_unstable();
+        let mut header = vec![
+            "events".to_owned(),
+            "aeps".to_owned(),
+            "aer".to_owned(),
+            "instructions".to_owned(),
+        ];
+        header.extend(elements.iter().map(|(_, name)| csv_field(name)));
+        header.extend((0..COUNTERS).map(|c| format!("count{}", c)));
+        writeln!(w, "{}", header.join(","))?;
+        w.flush()?;
+        Ok(Self {
+            w,
+            types: elements.iter().map(|(t, _)| *t).collect(),
+        })
+    }
+
+    /// Writes `report` as a row, flushing it so the table can be read as the
+    /// run goes on.
+    pub fn write(&mut self, report: &Report) -> io::Result<()> {
+        let mut row = vec![
+            report.stats.events.to_string(),
+            format!("{:.6}", report.aeps()),
+            format!("{:.6}", report.aer()),
+            report.stats.instructions.to_string(),
+        ];
+        row.extend(
+            self.types
+                .iter()
+                .map(|t| report.census.get(t).copied().unwrap_or(0).to_string()),
+        );
+        row.extend(report.counters.iter().map(u64::to_string));
+        writeln!(self.w, "{}", row.join(","))?;
+        self.w.flush()
+    }
+}
@@ -0,0 +1,112 @@
+//! Going back to any event of a run.
+//!
+//! A `Timeline` runs a grid forward as `replay::Recorder` does, keeping every
+//! event's record in memory and a snapshot of the grid, a keyframe, every so
+//! many events. The grid as it was before any event is rebuilt on demand
+//! from the last keyframe before it, replaying the recorded events in
+//! between, so going back to event 1,234,567 of a run with keyframes every
+//! 10,000 events replays at most 9,999 events rather than the whole run:
+//!
+//! ```text
+//! let mut timeline = Timeline::new(grid, &runtime, 10_000)?;
+//! let grid = timeline.goto(&mut runtime, 1_234_567)?;
+//! ```
+//!
+//! Like a recording, the run picks sites uniformly at random, whatever the
+//! grid's scheduling. Keyframes hold what a snapshot does, so rebuilt grids
+//! are dense, with the grid's other settings at their defaults.
+
+use crate::runtime::grid::Grid;
+use crate::runtime::replay::RecordedEvent;
+use crate::runtime::rng::Prng;
+use crate::runtime::snapshot;
+use crate::runtime::{Error, Runtime};
+
+pub struct Timeline {
+    /// The events apart keyframes are taken.
+    every: u64,
+    /// Snapshots of the grid before events 0, `every`, twice `every` and so
+    /// on, as far as the run has gone.
+    keyframes: Vec<Vec<u8>>,
+    /// The record of each event run.
+    log: Vec<RecordedEvent>,
+    /// The grid after the last event run, with the generator and next id it
+    /// continues with.
+    head: Grid,
+    rng: Prng,
+    next_id: u64,
+}
+
+impl Timeline {
+    /// Starts a run of `grid` with `runtime`'s generator, keeping a keyframe
+    /// every `every` events.
+    pub fn new(grid: Grid, runtime: &Runtime, every: u64) -> Result<Self, Error> {
+        let mut timeline = Self {
+            every: every.max(1),
+            keyframes: Vec::new(),
+            log: Vec::new(),
+            head: grid,
+            rng: runtime.rng().clone(),
+            next_id: runtime.next_id(),
+        };
+        timeline.keyframe(runtime)?;
+        Ok(timeline)
+    }
+
+    /// The number of events run so far.
+    pub fn events(&self) -> u64 {
+        self.log.len() as u64
+    }
+
+    /// The event run as number `event`, if it has been.
+    pub fn event(&self, event: u64) -> Option<&RecordedEvent> {
+        self.log.get(event as usize)
+    }
+
+    fn keyframe(&mut self, runtime: &Runtime) -> Result<(), Error> {
+        let mut keyframe = Vec::new();
+        snapshot::save(&mut keyframe, &self.head, runtime)?;
+        self.keyframes.push(keyframe);
+        Ok(())
+    }
+
+    /// Runs the grid on until `events` events have run, leaving `runtime`'s
+    /// generator and next id as they were.
+    pub fn run_to(&mut self, runtime: &mut Runtime, events: u64) -> Result<(), Error> {
+        if self.events() >= events {
+            return Ok(());
+        }
+        let rng = runtime.rng().clone();
+        let next_id = runtime.next_id();
+        runtime.set_rng(self.rng.clone());
+        runtime.set_next_id(self.next_id);
+        let mut result = Ok(());
+        while self.events() < events {
+            result = RecordedEvent::run(&self.head, runtime).map(|record| self.log.push(record));
+            if result.is_ok() && self.events() == self.keyframes.len() as u64 * self.every {
+                result = self.keyframe(runtime);
+            }
+            if result.is_err() {
+                break;
+            }
+        }
+        self.rng = runtime.rng().clone();
+        self.next_id = runtime.next_id();
+        runtime.set_rng(rng);
+        runtime.set_next_id(next_id);
+        result
+    }
+
+    /// The grid as it was before event number `event`, running the grid on
+    /// to it first if it hasn't got that far. `runtime`'s generator and next
+    /// id are left as they were then, so stepping the grid runs the event.
+    pub fn goto(&mut self, runtime: &mut Runtime, event: u64) -> Result<Grid, Error> {
+        self.run_to(runtime, event)?;
+        let k = event / self.every;
+        let grid = snapshot::load(&self.keyframes[k as usize][..], runtime)?;
+        for n in k * self.every..event {
+            self.log[n as usize].replay(&grid, runtime, n)?;
+        }
+        Ok(grid)
+    }
+}
@@ -0,0 +1,375 @@
+//! Tracing the instructions events run.
+//!
+//! A `Tracer` set on a runtime with `Runtime::set_tracer` logs each
+//! instruction of the events it picks: its number, the values it took off
+//! the stack and those it left in their place, and the sites it wrote. Events
+//! can be picked by the element they run, by the site they run at, or one in
+//! every so many of those, to keep traces of long runs small enough to read.
+//! Each event is written whole once it ends, so events run on other threads
+//! don't interleave.
+//!
+//! Sites are window indexes in unmapped site order, as for `EventObserver`.
+//! The JSON format writes one object per line: one for the start of an event,
+//! one per instruction and one for its end.
+//!
+//! ```text
+//! {"event":0,"x":4,"y":4,"type":1,"symmetry":"R090L"}
+//! {"ip":0,"op":"push","pop":[],"push":[3],"writes":[]}
+//! {"ip":1,"op":"push0","pop":[],"push":[0],"writes":[]}
+//! {"ip":2,"op":"setsitefield","pop":[3,0],"push":[],"writes":[{"site":0,"old":..,"new":..}]}
+//! {"end":"ok","executed":3}
+//! ```
+//!
+//! The binary format holds the same records. All integers are big endian,
+//! values are written as in the constant pool of a compiled element and
+//! atoms as 96 bit integers; `x` and `y` are `0xffff` for events run outside
+//! a grid:
+//!
+//! ```text
+//! magic u32, version u16
+//! { event u64, x u16, y u16, type u16, symmetry u8,
+//!   { 1 u8, ip u16, pops u8, value*, pushes u8, value*,
+//!     writes u8, { site u8, old atom, new atom }* }*,
+//!   0 u8, failed u8, executed u64 }*
+//! ```
+
+use crate::base::arith::Const;
+use crate::base::atom::Atom;
+use crate::base::Symmetries;
+use crate::runtime::bench::json_string;
+use crate::runtime::mfm::EventWindow;
+use crate::runtime::{Cursor, Error};
+use byteorder::{BigEndian, WriteBytesExt};
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+const MAGIC_NUMBER: u32 = 0x45575452;
+const VERSION: u16 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// One JSON object per line.
+    Json,
+    /// Compact big endian records.
+    Binary,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "binary" => Ok(Self::Binary),
+            _ => Err(format!("unknown trace format: {}", s)),
+        }
+    }
+}
+
+pub struct Tracer {
+    format: TraceFormat,
+    elements: Vec<u16>,
+    sites: Vec<(usize, usize)>,
+    every: u64,
+    /// The events picked by element and site so far.
+    seen: AtomicU64,
+    /// The events traced so far.
+    traced: AtomicU64,
+    out: Mutex<Output>,
+}
+
+struct Output {
+    w: Box<dyn Write + Send>,
+    /// The first write which failed, after which nothing more is written.
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    /// Traces every event to `w`.
+    pub fn new(mut w: Box<dyn Write + Send>, format: TraceFormat) -> io::Result<Self> {
+        if format == TraceFormat::Binary {
+            w.write_u32::<BigEndian>(MAGIC_NUMBER)?;
+            w.write_u16::<BigEndian>(VERSION)?;
+        }
+        Ok(Self {
+            format,
+            elements: Vec::new(),
+            sites: Vec::new(),
+            every: 1,
+            seen: AtomicU64::new(0),
+            traced: AtomicU64::new(0),
+            out: Mutex::new(Output { w, error: None }),
+        })
+    }
+
+    /// Traces only events run by atoms of these types.
+    pub fn set_elements(&mut self, types: Vec<u16>) {
+        self.elements = types;
+    }
+
+    /// Traces only events at these sites of the grid.
+    pub fn set_sites(&mut self, sites: Vec<(usize, usize)>) {
+        self.sites = sites;
+    }
+
+    /// Traces only the first of every `every` events otherwise picked.
+    pub fn set_every(&mut self, every: u64) {
+        self.every = every.max(1);
+    }
+
+    /// The number of events traced so far.
+    pub fn traced(&self) -> u64 {
+        self.traced.load(Ordering::Relaxed)
+    }
+
+    /// Flushes the trace, failing if any of it couldn't be written.
+    pub fn flush(&self) -> io::Result<()> {
+        let mut out = self.out.lock().unwrap();
+        match out.error.take() {
+            Some(e) => Err(e),
+            None => out.w.flush(),
+        }
+    }
+
+    /// Starts tracing the event about to run in `ew`, centered on `origin` if
+    /// it runs in a grid, unless it isn't picked.
+    pub(crate) fn begin(
+        &self,
+        ew: &EventWindow,
+        origin: Option<(usize, usize)>,
+    ) -> Option<EventTrace> {
+        let type_num = ew.atoms()[0].get_type();
+        if !self.elements.is_empty() && !self.elements.contains(&type_num) {
+            return None;
+        }
+        if !self.sites.is_empty() && !matches!(origin, Some(o) if self.sites.contains(&o)) {
+            return None;
+        }
+        // Picks the first of every `every` events.
+        if self.seen.fetch_add(1, Ordering::Relaxed) % self.every >= 1 {
+            return None;
+        }
+        Some(EventTrace {
+            format: self.format,
+            type_num,
+            origin,
+            buf: Vec::new(),
+            pending: None,
+        })
+    }
+
+    /// Writes the trace of an event which ended with `result`.
+    pub(crate) fn finish(
+        &self,
+        mut trace: EventTrace,
+        cursor: &Cursor,
+        ew: &EventWindow,
+        symmetry: Symmetries,
+        result: Result<(), &Error>,
+    ) {
+        trace.flush(cursor, ew);
+        let mut out = self.out.lock().unwrap();
+        if out.error.is_some() {
+            return;
+        }
+        let event = self.traced.fetch_add(1, Ordering::Relaxed);
+        let written = trace
+            .header(&mut out.w, event, symmetry)
+            .and_then(|_| out.w.write_all(&trace.buf))
+            .and_then(|_| trace.end(&mut out.w, cursor, result))
+            // A failed event may end the run, so it is written out at once.
+            .and_then(|_| match result {
+                Ok(()) => Ok(()),
+                Err(_) => out.w.flush(),
+            });
+        if let Err(e) = written {
+            out.error = Some(e);
+        }
+    }
+}
+
+/// The trace of one event as it runs.
+pub(crate) struct EventTrace {
+    format: TraceFormat,
+    type_num: u16,
+    origin: Option<(usize, usize)>,
+    /// The instructions run so far.
+    buf: Vec<u8>,
+    /// The instruction running, with its mnemonic and the stack and atoms
+    /// before it.
+    pending: Option<(usize, &'static str, Vec<Const>, Vec<Atom>)>,
+}
+
+impl EventTrace {
+    /// Called before each instruction, with the state the previous one left.
+    pub(crate) fn instruction(&mut self, cursor: &Cursor, ew: &EventWindow, op: &'static str) {
+        self.flush(cursor, ew);
+        self.pending = Some((
+            cursor.ip(),
+            op,
+            cursor.op_stack().to_vec(),
+            ew.atoms().to_vec(),
+        ));
+    }
+
+    /// Records the pending instruction, if any.
+    fn flush(&mut self, cursor: &Cursor, ew: &EventWindow) {
+        let (ip, op, stack, atoms) = match self.pending.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+        let after = cursor.op_stack();
+        let kept = stack.iter().zip(after).take_while(|(a, b)| a == b).count();
+        let writes: Vec<(usize, Atom, Atom)> = atoms
+            .iter()
+            .zip(ew.atoms())
+            .enumerate()
+            .filter(|(_, (old, new))| old != new)
+            .map(|(i, (old, new))| (i, *old, *new))
+            .collect();
+        // Writing to a Vec can't fail.
+        let _ = match self.format {
+            TraceFormat::Json => json_instruction(
+                &mut self.buf,
+                ip,
+                op,
+                &stack[kept..],
+                &after[kept..],
+                &writes,
+            ),
+            TraceFormat::Binary => {
+                binary_instruction(&mut self.buf, ip, &stack[kept..], &after[kept..], &writes)
+            }
+        };
+    }
+
+    fn header<W: Write>(&self, w: &mut W, event: u64, symmetry: Symmetries) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Json => {
+                write!(w, "{{\"event\":{}", event)?;
+                if let Some((x, y)) = self.origin {
+                    write!(w, ",\"x\":{},\"y\":{}", x, y)?;
+                }
+                writeln!(
+                    w,
+                    ",\"type\":{},\"symmetry\":\"{}\"}}",
+                    self.type_num, symmetry
+                )
+            }
+            TraceFormat::Binary => {
+                let (x, y) = self.origin.unwrap_or((0xffff, 0xffff));
+                w.write_u64::<BigEndian>(event)?;
+                w.write_u16::<BigEndian>(x as u16)?;
+                w.write_u16::<BigEndian>(y as u16)?;
+                w.write_u16::<BigEndian>(self.type_num)?;
+                w.write_u8(symmetry.bits())
+            }
+        }
+    }
+
+    fn end<W: Write>(
+        &self,
+        w: &mut W,
+        cursor: &Cursor,
+        result: Result<(), &Error>,
+    ) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Json => {
+                match result {
+                    Ok(()) => write!(w, "{{\"end\":\"ok\"")?,
+                    Err(e) => {
+                        write!(w, "{{\"end\":\"failed\",\"error\":")?;
+                        json_string(w, &e.to_string())?;
+                    }
+                }
+                writeln!(w, ",\"executed\":{}}}", cursor.executed())
+            }
+            TraceFormat::Binary => {
+                w.write_u8(0)?;
+                w.write_u8(result.is_err() as u8)?;
+                w.write_u64::<BigEndian>(cursor.executed())
+            }
+        }
+    }
+}
+
+fn json_values<W: Write>(w: &mut W, values: &[Const]) -> io::Result<()> {
+    w.write_all(b"[")?;
+    for (i, v) in values.iter().enumerate() {
+        if i > 0 {
+            w.write_all(b",")?;
+        }
+        w.write_all(v.to_decimal().as_bytes())?;
+    }
+    w.write_all(b"]")
+}
+
+fn json_instruction<W: Write>(
+    w: &mut W,
+    ip: usize,
+    op: &str,
+    popped: &[Const],
+    pushed: &[Const],
+    writes: &[(usize, Atom, Atom)],
+) -> io::Result<()> {
+    write!(w, "{{\"ip\":{},\"op\":\"{}\",\"pop\":", ip, op)?;
+    json_values(w, popped)?;
+    w.write_all(b",\"push\":")?;
+    json_values(w, pushed)?;
+    w.write_all(b",\"writes\":[")?;
+    for (i, (site, old, new)) in writes.iter().enumerate() {
+        if i > 0 {
+            w.write_all(b",")?;
+        }
+        write!(
+            w,
+            "{{\"site\":{},\"old\":{},\"new\":{}}}",
+            site,
+            old.bits(),
+            new.bits()
+        )?;
+    }
+    writeln!(w, "]}}")
+}
+
+fn binary_value<W: Write>(w: &mut W, v: Const) -> io::Result<()> {
+    if let Const::Fixed { frac, .. } = v {
+        w.write_u8(0)?;
+        w.write_u8(frac)?;
+    } else {
+        let sign = if v.is_signed() { 0x80 } else { 0 };
+        w.write_u8(sign | v.bits())?;
+    }
+    binary_u96(w, v.as_u128())
+}
+
+fn binary_u96<W: Write>(w: &mut W, v: u128) -> io::Result<()> {
+    w.write_u32::<BigEndian>((v >> 64) as u32)?;
+    w.write_u64::<BigEndian>(v as u64)
+}
+
+fn binary_instruction<W: Write>(
+    w: &mut W,
+    ip: usize,
+    popped: &[Const],
+    pushed: &[Const],
+    writes: &[(usize, Atom, Atom)],
+) -> io::Result<()> {
+    w.write_u8(1)?;
+    w.write_u16::<BigEndian>(ip as u16)?;
+    for values in [popped, pushed].iter() {
+        w.write_u8(values.len().min(u8::MAX as usize) as u8)?;
+        for v in values.iter().take(u8::MAX as usize) {
+            binary_value(w, *v)?;
+        }
+    }
+    w.write_u8(writes.len() as u8)?;
+    for (site, old, new) in writes.iter() {
+        w.write_u8(*site as u8)?;
+        binary_u96(w, old.bits())?;
+        binary_u96(w, new.bits())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,210 @@
+//! Watchpoints: conditions on the atoms events store, such as a site changing
+//! type or a field passing a limit.
+//!
+//! Watchpoints are added to a runtime with `Runtime::add_watch` and checked
+//! by the interpreter each time an instruction stores an atom, so they cost
+//! nothing while none are set. Each one which holds is recorded in the
+//! event's `Cursor`, and a grid ends the event with `Error::Watchpoint` so
+//! that a run stops there. They are written as:
+//!
+//! ```text
+//! site 3,4          the atom at site (3, 4) of the grid changes type
+//! Res.hp > 10       an event stores a Res atom whose hp is above 10
+//! ```
+//!
+//! Fields compare with `>`, `>=`, `<`, `<=`, `==` and `!=` to a number. A
+//! field watchpoint holds when an atom meeting the condition is stored over
+//! one which didn't, so a Res atom already above the limit is reported again
+//! when it moves to another site but not when its other fields change.
+
+use crate::base::atom::Atom;
+use crate::base::{FieldSelector, SiteNumber};
+use crate::runtime::Runtime;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+/// How a field is compared with a number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Comparison {
+    /// Operators longest first, so that `>=` isn't read as `>`.
+    const OPERATORS: [(&'static str, Self); 6] = [
+        (">=", Self::GreaterEqual),
+        ("<=", Self::LessEqual),
+        ("==", Self::Equal),
+        ("!=", Self::NotEqual),
+        (">", Self::Greater),
+        ("<", Self::Less),
+    ];
+
+    pub fn holds(self, a: u128, b: u128) -> bool {
+        match self {
+            Self::Greater => a > b,
+            Self::GreaterEqual => a >= b,
+            Self::Less => a < b,
+            Self::LessEqual => a <= b,
+            Self::Equal => a == b,
+            Self::NotEqual => a != b,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Watch {
+    /// The atom at site `(x, y)` of the grid changes type.
+    SiteType { x: usize, y: usize },
+    /// An atom of type `type_num` whose `field` compares with `value` is
+    /// stored over one which isn't.
+    Field {
+        type_num: u16,
+        field: FieldSelector,
+        comparison: Comparison,
+        value: u128,
+    },
+}
+
+impl Watch {
+    /// Reads a watchpoint written as in the module documentation, finding
+    /// elements and fields among those loaded into `runtime`.
+    pub fn parse(s: &str, runtime: &Runtime) -> Result<Self, String> {
+        let s = s.trim();
+        if let Some(site) = s.strip_prefix("site") {
+            let (x, y) = site
+                .split_once(',')
+                .ok_or_else(|| format!("expected site X,Y: {}", s))?;
+            let coord = |c: &str| {
+                usize::from_str(c.trim()).map_err(|_| format!("bad coordinate: {}", c.trim()))
+            };
+            return Ok(Self::SiteType {
+                x: coord(x)?,
+                y: coord(y)?,
+            });
+        }
+        let (at, op, comparison) = Comparison::OPERATORS
+            .iter()
+            .find_map(|(op, c)| Some((s.find(op)?, op, *c)))
+            .ok_or_else(|| format!("expected site X,Y or ELEMENT.FIELD > N: {}", s))?;
+        let (element, name) = s[..at]
+            .trim()
+            .split_once('.')
+            .ok_or_else(|| format!("expected ELEMENT.FIELD: {}", s[..at].trim()))?;
+        let value = s[at + op.len()..].trim();
+        let value = u128::from_str(value).map_err(|_| format!("bad number: {}", value))?;
+        let type_num = runtime
+            .registry()
+            .get(element)
+            .filter(|t| runtime.metadata(*t).is_some())
+            .ok_or_else(|| format!("no element {}", element))?;
+        let field = runtime
+            .get_field(type_num, name)
+            .ok_or_else(|| format!("{} has no field {}", element, name))?;
+        Ok(Self::Field {
+            type_num,
+            field,
+            comparison,
+            value,
+        })
+    }
+}
+
+/// A watchpoint which held.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchHit {
+    /// The watchpoint's number, in the order they were added.
+    pub watch: usize,
+    /// The instruction which stored the atom.
+    pub ip: usize,
+    /// The window index of the site, in unmapped site order.
+    pub site: usize,
+    pub old: Atom,
+    pub new: Atom,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "watchpoint {} at instruction {}, site {}: {} became {}",
+            self.watch, self.ip, self.site, self.old, self.new
+        )
+    }
+}
+
+/// The watchpoints of one event, with grid sites turned into window indexes.
+pub(crate) struct Armed {
+    checks: Vec<(usize, Check)>,
+}
+
+enum Check {
+    Site(usize),
+    Field(u16, FieldSelector, Comparison, u128),
+}
+
+impl Armed {
+    /// Arms `watches` for an event centered on `origin`, the grid site of the
+    /// event, if known.
+    pub(crate) fn new(watches: &[Watch], origin: Option<(usize, usize)>) -> Self {
+        let checks = watches
+            .iter()
+            .enumerate()
+            .filter_map(|(n, w)| match *w {
+                Watch::SiteType { x, y } => {
+                    let (ox, oy) = origin?;
+                    let dx = i8::try_from(x as isize - ox as isize).ok()?;
+                    let dy = i8::try_from(y as isize - oy as isize).ok()?;
+                    Some((n, Check::Site(SiteNumber::from_coord(dx, dy)?.0 as usize)))
+                }
+                Watch::Field {
+                    type_num,
+                    field,
+                    comparison,
+                    value,
+                } => Some((n, Check::Field(type_num, field, comparison, value))),
+            })
+            .collect();
+        Self { checks }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.checks.is_empty()
+    }
+
+    /// Records in `hits` the watchpoints which hold for `new` stored over
+    /// `old` at window index `site` by instruction `ip`.
+    pub(crate) fn check(
+        &self,
+        hits: &mut Vec<WatchHit>,
+        ip: usize,
+        site: usize,
+        old: Atom,
+        new: Atom,
+    ) {
+        for (watch, check) in self.checks.iter() {
+            let holds = match *check {
+                Check::Site(i) => i == site && old.get_type() != new.get_type(),
+                Check::Field(t, f, c, v) => {
+                    let meets = |a: Atom| a.get_type() == t && c.holds(f.extract(a.bits()), v);
+                    meets(new) && !meets(old)
+                }
+            };
+            if holds {
+                hits.push(WatchHit {
+                    watch: *watch,
+                    ip,
+                    site,
+                    old,
+                    new,
+                });
+            }
+        }
+    }
+}
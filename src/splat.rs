@@ -0,0 +1,392 @@
+//! A front end for elements written as SPLAT-style spatial rules: pictures
+//! of a neighborhood to look for and what to make of it, drawn in ASCII.
+//!
+//! A rule is a block of consecutive diagram rows, one of which holds `->`
+//! between the pattern on its left and the result on its right:
+//!
+//! ```text
+//! given S = Sand
+//!
+//! @ -> _
+//! _    S
+//! ```
+//!
+//! In a pattern `@` is the atom running the event, `_` an empty site, `*`
+//! any atom but Empty, an uppercase letter an atom of a type it is `given`,
+//! and `.` or a space any site at all. In a result `@` is the atom running
+//! the event, a letter from the pattern is the atom it matched there, any
+//! other letter a new atom of its type, `_` an empty site, and `.` or a space
+//! leaves the site as it was. Results are lined up with patterns by their
+//! leftmost columns, and the two must be equally wide.
+//!
+//! Each event tries the rules in order and applies the first that matches,
+//! under the symmetry picked for the event. `chance N in M` on the line
+//! before a rule makes it apply only N times in M when it matches.
+//!
+//! Rules are translated to EWAL source; all other lines, such as metadata,
+//! preprocessor directives and `//` comments, are copied through as they are.
+
+use crate::base::SiteNumber;
+use std::collections::HashMap;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SplatError {
+    #[error("line {0}: expected `given LETTER = TYPE[ | TYPE...]` with an uppercase letter")]
+    BadGiven(usize),
+    #[error("line {0}: `{1}` is already given")]
+    Redefined(usize, char),
+    #[error("line {0}: expected `chance N in M` with M above 0")]
+    BadChance(usize),
+    #[error("line {0}: no rule follows the chance")]
+    DanglingChance(usize),
+    #[error("line {0}: a rule needs a row with `->`")]
+    MissingArrow(usize),
+    #[error("line {0}: a rule has only one row with `->`; separate rules with a blank line")]
+    ExtraArrow(usize),
+    #[error("line {0}: the pattern and the result differ in width")]
+    Shape(usize),
+    #[error("line {0}: a pattern has exactly one `@`")]
+    SelfCount(usize),
+    #[error("line {0}: unknown symbol `{1}`")]
+    BadSymbol(usize, char),
+    #[error("line {0}: `{1}` isn't given")]
+    Undefined(usize, char),
+    #[error("line {0}: `{1}` can't be part of a result")]
+    BadResult(usize, char),
+    #[error("line {0}: `{1}` matches more than one site, so which one it moves is ambiguous")]
+    AmbiguousMove(usize, char),
+    #[error("line {0}: `{1}` is given more than one type, so a new one can't be made")]
+    AmbiguousType(usize, char),
+    #[error("line {0}: a site lies outside the event window")]
+    OutsideWindow(usize),
+    #[error("no rules")]
+    NoRules,
+}
+
+/// What a pattern requires of a site.
+enum Test<'a> {
+    Empty,
+    Occupied,
+    Types(&'a [String]),
+}
+
+/// What a result does to a site.
+enum Effect<'a> {
+    Copy(u8, u8),
+    Empty(u8),
+    New(u8, &'a str),
+}
+
+struct Rule<'a> {
+    chance: Option<(u32, u32)>,
+    rows: Vec<(usize, &'a str)>,
+}
+
+/// The label ending rule `i`, spelled in letters as labels can't hold
+/// digits.
+fn label(mut i: usize) -> String {
+    let mut s = String::new();
+    loop {
+        s.insert(0, (b'a' + (i % 26) as u8) as char);
+        i /= 26;
+        if i == 0 {
+            break;
+        }
+        i -= 1;
+    }
+    format!("skip_{}", s)
+}
+
+fn given(line: usize, rest: &str) -> Result<(char, Vec<String>), SplatError> {
+    let (letter, types) = rest.split_once('=').ok_or(SplatError::BadGiven(line))?;
+    let mut letter = letter.trim().chars();
+    let c = match (letter.next(), letter.next()) {
+        (Some(c), None) if c.is_ascii_uppercase() => c,
+        _ => return Err(SplatError::BadGiven(line)),
+    };
+    let types: Vec<String> = types.split('|').map(|t| t.trim().to_owned()).collect();
+    if types.iter().any(|t| t.is_empty() || t.contains('"')) {
+        return Err(SplatError::BadGiven(line));
+    }
+    Ok((c, types))
+}
+
+fn chance(line: usize, rest: &str) -> Result<(u32, u32), SplatError> {
+    let words: Vec<&str> = rest.split_whitespace().collect();
+    match words.as_slice() {
+        [n, "in", m] => match (n.parse(), m.parse()) {
+            (Ok(n), Ok(m)) if m > 0 => Ok((n, m)),
+            _ => Err(SplatError::BadChance(line)),
+        },
+        _ => Err(SplatError::BadChance(line)),
+    }
+}
+
+/// Whether `line` is a row of a diagram rather than a line to copy through.
+fn is_row(line: &str) -> bool {
+    let t = line.trim_start();
+    let mut c = t.chars();
+    match c.next() {
+        Some('.') => !matches!(c.next(), Some(c) if c.is_ascii_lowercase()),
+        Some('-') => t.starts_with("->"),
+        Some(c) => c == '@' || c == '_' || c == '*' || c.is_ascii_uppercase(),
+        None => false,
+    }
+}
+
+/// Translates the rules in `src` to EWAL source.
+pub fn translate(src: &str) -> Result<String, SplatError> {
+    let mut out = String::new();
+    let mut givens: HashMap<char, Vec<String>> = HashMap::new();
+    let mut pending: Option<(usize, (u32, u32))> = None;
+    let mut rule: Option<Rule> = None;
+    let mut rules = 0;
+    for (i, text) in src.lines().enumerate() {
+        let line = i + 1;
+        if is_row(text) {
+            rule.get_or_insert_with(|| Rule {
+                chance: pending.take().map(|(_, c)| c),
+                rows: Vec::new(),
+            })
+            .rows
+            .push((line, text.trim_end()));
+            continue;
+        }
+        if let Some(r) = rule.take() {
+            emit(&mut out, &r, rules, &givens)?;
+            rules += 1;
+        }
+        let t = text.trim();
+        if let Some(rest) = t.strip_prefix("given ") {
+            let (c, types) = given(line, rest)?;
+            if givens.insert(c, types).is_some() {
+                return Err(SplatError::Redefined(line, c));
+            }
+        } else if let Some(rest) = t.strip_prefix("chance ") {
+            if let Some((line, _)) = pending {
+                return Err(SplatError::DanglingChance(line));
+            }
+            pending = Some((line, chance(line, rest)?));
+        } else {
+            if let Some((line, _)) = pending.filter(|_| !t.is_empty() && !t.starts_with("//")) {
+                return Err(SplatError::DanglingChance(line));
+            }
+            out.push_str(text);
+            out.push('\n');
+        }
+    }
+    if let Some(r) = rule.take() {
+        emit(&mut out, &r, rules, &givens)?;
+        rules += 1;
+    }
+    if let Some((line, _)) = pending {
+        return Err(SplatError::DanglingChance(line));
+    }
+    if rules == 0 {
+        return Err(SplatError::NoRules);
+    }
+    Ok(out)
+}
+
+/// Writes rule number `index` as EWAL which falls through to the next rule
+/// unless it matches.
+fn emit(
+    out: &mut String,
+    rule: &Rule,
+    index: usize,
+    givens: &HashMap<char, Vec<String>>,
+) -> Result<(), SplatError> {
+    let first = rule.rows[0].0;
+    let rows: Vec<Vec<char>> = rule.rows.iter().map(|(_, r)| r.chars().collect()).collect();
+    let arrows: Vec<usize> = rule
+        .rows
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, r))| r.contains("->"))
+        .map(|(i, _)| i)
+        .collect();
+    let arrow_row = match arrows.as_slice() {
+        [] => return Err(SplatError::MissingArrow(first)),
+        [a] => *a,
+        [_, a, ..] => return Err(SplatError::ExtraArrow(rule.rows[*a].0)),
+    };
+    let arrow = rows[arrow_row]
+        .windows(2)
+        .position(|w| w == ['-', '>'])
+        .unwrap_or_default();
+
+    // Cut every row at the arrow's column.
+    let mut lhs = Vec::new();
+    let mut rhs = Vec::new();
+    for (r, row) in rows.iter().enumerate() {
+        let line = rule.rows[r].0;
+        if r != arrow_row && row.iter().skip(arrow).take(2).any(|c| *c != ' ') {
+            return Err(SplatError::Shape(line));
+        }
+        lhs.push(&row[..arrow.min(row.len())]);
+        rhs.push(row.get(arrow + 2..).unwrap_or(&[]));
+    }
+    let extent = |side: &[&[char]]| {
+        let left = side
+            .iter()
+            .filter_map(|r| r.iter().position(|c| *c != ' '))
+            .min();
+        let right = side
+            .iter()
+            .filter_map(|r| r.iter().rposition(|c| *c != ' '))
+            .max();
+        left.zip(right)
+    };
+    let (l_left, l_right) = extent(&lhs).ok_or(SplatError::SelfCount(first))?;
+    let r_left = match extent(&rhs) {
+        Some((left, right)) if right - left == l_right - l_left => left,
+        Some(_) => return Err(SplatError::Shape(first)),
+        None => 0,
+    };
+    let cell = |side: &[&[char]], r: usize, left: usize, c: usize| {
+        side[r].get(left + c).copied().unwrap_or(' ')
+    };
+
+    let width = l_right - l_left + 1;
+    let mut centre = None;
+    for (r, (line, _)) in rule.rows.iter().enumerate() {
+        for c in 0..width {
+            if cell(&lhs, r, l_left, c) == '@' {
+                if centre.is_some() {
+                    return Err(SplatError::SelfCount(*line));
+                }
+                centre = Some((r, c));
+            }
+        }
+    }
+    let (cr, cc) = centre.ok_or(SplatError::SelfCount(first))?;
+    let site = |line: usize, r: usize, c: usize| {
+        let (dx, dy) = (c as isize - cc as isize, r as isize - cr as isize);
+        Some((dx, dy))
+            .filter(|(dx, dy)| dx.abs() <= 4 && dy.abs() <= 4)
+            .and_then(|(dx, dy)| SiteNumber::from_coord(dx as i8, dy as i8))
+            .map(|s| s.0)
+            .ok_or(SplatError::OutsideWindow(line))
+    };
+
+    // Where each letter of the pattern matched.
+    let mut matched: HashMap<char, Vec<u8>> = HashMap::new();
+    let mut tests = Vec::new();
+    for (r, (line, _)) in rule.rows.iter().enumerate() {
+        for c in 0..width {
+            let test = match cell(&lhs, r, l_left, c) {
+                ' ' | '.' | '@' => continue,
+                '_' => Test::Empty,
+                '*' => Test::Occupied,
+                l if l.is_ascii_uppercase() => {
+                    let types = givens.get(&l).ok_or(SplatError::Undefined(*line, l))?;
+                    matched.entry(l).or_default().push(site(*line, r, c)?);
+                    Test::Types(types)
+                }
+                s => return Err(SplatError::BadSymbol(*line, s)),
+            };
+            tests.push((site(*line, r, c)?, test));
+        }
+    }
+
+    let mut writes = Vec::new();
+    for (r, (line, _)) in rule.rows.iter().enumerate() {
+        for c in 0..width {
+            let was = cell(&lhs, r, l_left, c);
+            let write = match cell(&rhs, r, r_left, c) {
+                ' ' | '.' => continue,
+                s if s == was => continue,
+                '_' => Effect::Empty(site(*line, r, c)?),
+                '@' => Effect::Copy(site(*line, r, c)?, 0),
+                l if l.is_ascii_uppercase() => match matched.get(&l).map(Vec::as_slice) {
+                    Some([from]) => Effect::Copy(site(*line, r, c)?, *from),
+                    Some(_) => return Err(SplatError::AmbiguousMove(*line, l)),
+                    None => match givens.get(&l).map(Vec::as_slice) {
+                        Some([t]) => Effect::New(site(*line, r, c)?, t),
+                        Some(_) => return Err(SplatError::AmbiguousType(*line, l)),
+                        None => return Err(SplatError::Undefined(*line, l)),
+                    },
+                },
+                '*' => return Err(SplatError::BadResult(*line, '*')),
+                s => return Err(SplatError::BadSymbol(*line, s)),
+            };
+            writes.push(write);
+        }
+    }
+
+    let skip = label(index);
+    let mut w = |s: String| {
+        out.push_str(&s);
+        out.push('\n');
+    };
+    w(format!("// rule at line {}", first));
+    for (_, row) in &rule.rows {
+        w(format!("//   {}", row));
+    }
+    if let Some((n, m)) = rule.chance {
+        w(format!("  push {}", n));
+        w(format!("  push {}", m));
+        w("  oddsof".to_owned());
+        w(format!("  jumpzero {}", skip));
+    }
+    for (s, test) in &tests {
+        w(format!("  push{}", s));
+        w("  getsitefield type".to_owned());
+        match test {
+            Test::Empty => w(format!("  jumpnonzero {}", skip)),
+            Test::Occupied => w(format!("  jumpzero {}", skip)),
+            Test::Types(types) => {
+                let (last, rest) = match types.split_last() {
+                    Some(split) => split,
+                    None => continue,
+                };
+                for t in rest {
+                    w("  dup".to_owned());
+                    w(format!("  gettype \"{}\"", t));
+                    w("  equal".to_owned());
+                    w("  swap".to_owned());
+                }
+                w(format!("  gettype \"{}\"", last));
+                w("  equal".to_owned());
+                for _ in rest {
+                    w("  or".to_owned());
+                }
+                w(format!("  jumpzero {}", skip));
+            }
+        }
+    }
+    // Read every atom which moves before writing any site.
+    let mut copies = 0;
+    for write in &writes {
+        if let Effect::Copy(to, from) = write {
+            w(format!("  push{}", to));
+            w(format!("  push{}", from));
+            w("  getsite".to_owned());
+            copies += 1;
+        }
+    }
+    for _ in 0..copies {
+        w("  setsite".to_owned());
+    }
+    for write in &writes {
+        match write {
+            Effect::Copy(..) => {}
+            Effect::Empty(to) => {
+                w(format!("  push{}", to));
+                w("  push0".to_owned());
+                w("  setsite".to_owned());
+            }
+            Effect::New(to, t) => {
+                w(format!("  push{}", to));
+                w("  push0".to_owned());
+                w("  setsite".to_owned());
+                w(format!("  gettype \"{}\"", t));
+                w(format!("  push{}", to));
+                w("  setsitefield type".to_owned());
+            }
+        }
+    }
+    w("  exit".to_owned());
+    w(format!("{}:", skip));
+    Ok(())
+}
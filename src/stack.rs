@@ -0,0 +1,399 @@
+//! What the operand stack holds at each point of an element's code, found
+//! without running it.
+//!
+//! The analysis follows every path through an `Ir` from the start of an event,
+//! and from the start of each routine a `call` reaches, keeping the fewest and
+//! the most values the stack may hold before each instruction and which of its
+//! topmost values are constants every path agrees on. A `call` moves the
+//! stack as far as the routine it calls may, so each routine is summarized
+//! before the code calling it is followed. `verify` checks code with it and
+//! `ewad --explain` prints it beside the disassembly.
+
+use crate::ast::Instruction;
+use crate::base::arith::Const;
+use crate::ir::Ir;
+use std::collections::BTreeMap;
+
+/// The most times routines are followed over, should calls between them keep
+/// changing what they are found to do.
+const MAX_ROUNDS: usize = 32;
+
+/// What is known of the operand stack before an instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stack {
+    /// The fewest values it may hold, counted from the start of the routine:
+    /// below zero once the routine has popped values its caller pushed.
+    pub depth: i32,
+    /// The most values it may hold, counted the same way, or `None` if a
+    /// loop may push values without end.
+    pub most: Option<i32>,
+    /// Its topmost values, topmost last, each a constant if every path
+    /// pushes the same one.
+    pub top: Vec<Option<Const>>,
+}
+
+impl Stack {
+    fn new() -> Self {
+        Self {
+            depth: 0,
+            most: Some(0),
+            top: Vec::new(),
+        }
+    }
+
+    /// The `n`th value from the top, if it is a known constant.
+    pub fn arg(&self, n: usize) -> Option<Const> {
+        self.top.iter().rev().nth(n).copied().flatten()
+    }
+
+    /// What is known on both `self`'s and `other`'s paths.
+    fn join(&self, other: &Self) -> Self {
+        let n = self.top.len().min(other.top.len());
+        let top = self.top[self.top.len() - n..]
+            .iter()
+            .zip(other.top[other.top.len() - n..].iter())
+            .map(|(a, b)| if a == b { *a } else { None })
+            .collect();
+        Self {
+            depth: self.depth.min(other.depth),
+            most: self.most.zip(other.most).map(|(a, b)| a.max(b)),
+            top,
+        }
+    }
+
+    /// The stack after `i`, which isn't a `call`, no lower than `floor`.
+    fn apply(&self, i: &Instruction, floor: i32) -> Self {
+        let (pops, pushes) = i.stack_effect();
+        let mut popped: Vec<Option<Const>> = vec![None; pops.saturating_sub(self.top.len())];
+        let mut top = self.top.clone();
+        popped.extend(top.drain(top.len().saturating_sub(pops)..));
+        match (i, popped.as_slice()) {
+            (Instruction::Dup, [a]) => top.extend([*a, *a]),
+            (Instruction::Over, [a, b]) => top.extend([*a, *b, *a]),
+            (Instruction::Swap, [a, b]) => top.extend([*b, *a]),
+            (Instruction::Rot, [a, b, c]) => top.extend([*c, *a, *b]),
+            (i, _) => match i.pushed() {
+                Some(c) => top.push(Some(c)),
+                None => top.extend(vec![None; pushes]),
+            },
+        }
+        let moved = |n: i32| (n - pops as i32).max(floor) + pushes as i32;
+        Self {
+            depth: moved(self.depth),
+            most: self.most.map(moved),
+            top,
+        }
+    }
+
+    /// The stack after a `call` of a routine which may return with `r`.
+    fn call(&self, r: &Self, floor: i32) -> Self {
+        Self {
+            depth: (self.depth + r.depth).max(floor),
+            most: self.most.zip(r.most).map(|(a, b)| (a + b).max(floor)),
+            top: r.top.clone(),
+        }
+    }
+}
+
+/// What a routine does to its caller's stack.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Summary {
+    /// The most values it may pop which its caller pushed.
+    pub takes: i32,
+    /// The stack it may return with, counted from its start, or `None` if it
+    /// never returns.
+    pub returns: Option<Stack>,
+    /// The stack it may end the event with, counted the same way, or `None`
+    /// if it never does.
+    pub ends: Option<Stack>,
+}
+
+fn join(a: &mut Option<Stack>, b: Stack) {
+    *a = Some(match a {
+        Some(a) => a.join(&b),
+        None => b,
+    });
+}
+
+/// The paths through one routine, or through the event's own code.
+pub struct Flow {
+    /// The stack before each instruction, and at the end of the code, or
+    /// `None` where no path reaches.
+    pub states: Vec<Option<Stack>>,
+    /// Each instruction which may end the event, with the stack it may end
+    /// it with: an `exit`, one running off the end of the code or a `call`
+    /// of a routine which may end it.
+    pub ends: BTreeMap<usize, Stack>,
+    pub summary: Summary,
+}
+
+/// The paths through an element's code.
+pub struct Analysis {
+    /// From the start of an event, with nothing on the stack.
+    pub main: Flow,
+    /// From the start of each routine, by the instruction it starts at.
+    pub routines: BTreeMap<usize, Flow>,
+}
+
+/// Follows every path from `entry`. Stacks below `floor` count as `floor`.
+fn flow(ir: &Ir, entry: usize, floor: i32, routines: &BTreeMap<usize, Summary>) -> Flow {
+    let mut states: Vec<Option<Stack>> = vec![None; ir.code.len() + 1];
+    // How many times the most values before each instruction has grown. It
+    // can't grow more often than there are instructions unless a loop pushes
+    // more than it pops.
+    let mut grown = vec![0; ir.code.len() + 1];
+    let mut ends: BTreeMap<usize, Option<Stack>> = BTreeMap::new();
+    let mut summary = Summary::default();
+    let mut todo = vec![entry];
+    states[entry] = Some(Stack::new());
+    while let Some(ip) = todo.pop() {
+        let s = match (ir.code.get(ip), &states[ip]) {
+            (Some(_), Some(s)) => s.clone(),
+            _ => continue,
+        };
+        let i = &ir.code[ip];
+        let (pops, _) = i.stack_effect();
+        summary.takes = summary.takes.max(pops as i32 - s.depth);
+        let next = match i {
+            Instruction::Call(x) => {
+                let routine = &routines[&(*x.runtime() as usize)];
+                summary.takes = summary.takes.max(routine.takes - s.depth);
+                if let Some(e) = &routine.ends {
+                    join(ends.entry(ip).or_default(), s.call(e, floor));
+                }
+                match &routine.returns {
+                    Some(r) => vec![(ip + 1, s.call(r, floor))],
+                    None => Vec::new(),
+                }
+            }
+            Instruction::Ret => {
+                join(&mut summary.returns, s);
+                Vec::new()
+            }
+            Instruction::Exit => {
+                join(ends.entry(ip).or_default(), s);
+                Vec::new()
+            }
+            i => {
+                let after = s.apply(i, floor);
+                ir.successors(ip, &[])
+                    .into_iter()
+                    .map(|t| (t, after.clone()))
+                    .collect()
+            }
+        };
+        for (t, after) in next {
+            if t == ir.code.len() {
+                join(ends.entry(ip).or_default(), after.clone());
+            }
+            let mut joined = match &states[t] {
+                Some(s) => s.join(&after),
+                None => after,
+            };
+            if let Some(s) = &states[t] {
+                if joined.most > s.most && s.most.is_some() {
+                    grown[t] += 1;
+                    if grown[t] > ir.code.len() {
+                        joined.most = None;
+                    }
+                }
+            }
+            if states[t].as_ref() != Some(&joined) {
+                states[t] = Some(joined);
+                todo.push(t);
+            }
+        }
+    }
+    let ends: BTreeMap<usize, Stack> = ends
+        .into_iter()
+        .filter_map(|(ip, s)| Some((ip, s?)))
+        .collect();
+    summary.ends = ends.values().cloned().reduce(|a, b| a.join(&b));
+    Flow {
+        states,
+        ends,
+        summary,
+    }
+}
+
+/// Follows every path through `ir`, or returns `None` if its paths can't be
+/// followed: if it jumps by `jumprelativeoffset`, or if what its routines do
+/// never settles.
+pub fn analyze(ir: &Ir) -> Option<Analysis> {
+    if ir.has_computed_jumps() {
+        return None;
+    }
+    // What each routine does depends on the routines it calls, so all of
+    // them are followed until none changes.
+    let mut routines: BTreeMap<usize, Summary> = ir
+        .code
+        .iter()
+        .filter_map(|i| match i {
+            Instruction::Call(x) => Some((*x.runtime() as usize, Summary::default())),
+            _ => None,
+        })
+        .collect();
+    let floor = -(ir.code.len() as i32);
+    let mut settled = false;
+    for round in 0..MAX_ROUNDS {
+        settled = true;
+        for entry in routines.keys().copied().collect::<Vec<_>>() {
+            let mut summary = flow(ir, entry, floor, &routines).summary;
+            // A routine which still returns or ends the event with more
+            // values after half the rounds calls itself between pushes.
+            let old = &routines[&entry];
+            for (old, new) in [
+                (&old.returns, &mut summary.returns),
+                (&old.ends, &mut summary.ends),
+            ] {
+                if let (Some(old), Some(new)) = (old, new) {
+                    if round >= MAX_ROUNDS / 2 && new.most > old.most && old.most.is_some() {
+                        new.most = None;
+                    }
+                }
+            }
+            if routines[&entry] != summary {
+                routines.insert(entry, summary);
+                settled = false;
+            }
+        }
+        if settled {
+            break;
+        }
+    }
+    if !settled {
+        return None;
+    }
+    Some(Analysis {
+        main: flow(ir, 0, 0, &routines),
+        routines: routines
+            .keys()
+            .map(|entry| (*entry, flow(ir, *entry, floor, &routines)))
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Arg;
+    use crate::code::Compiler;
+    use Instruction::*;
+
+    fn push(n: u32) -> Instruction<'static> {
+        Push(n.into())
+    }
+
+    fn to(t: u16) -> Arg<crate::ast::Target<'static>, u16> {
+        Arg::Runtime(t)
+    }
+
+    fn analysis(code: &[Instruction<'static>]) -> Analysis {
+        analyze(&Ir::from_code(code)).unwrap()
+    }
+
+    /// The fewest and most values before each instruction from the start of
+    /// an event, or `None` where no path reaches.
+    fn depths(flow: &Flow) -> Vec<Option<(i32, Option<i32>)>> {
+        flow.states
+            .iter()
+            .map(|s| s.as_ref().map(|s| (s.depth, s.most)))
+            .collect()
+    }
+
+    /// The compile error `body` gives as the code of an element, as `Debug`
+    /// shows it.
+    fn error(body: &str) -> String {
+        let src = format!(".name A\n{}", body);
+        let mut compiler = Compiler::new("test");
+        match compiler.compile_to_writer(&mut Vec::new(), &src) {
+            Ok(()) => panic!("{:?} compiles", body),
+            Err(e) => format!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn underflow() {
+        let a = analysis(&[push(1), Add, Pop, Exit]);
+        assert_eq!(a.main.summary.takes, 1);
+        assert_eq!(
+            depths(&a.main),
+            [
+                Some((0, Some(0))),
+                Some((1, Some(1))),
+                Some((1, Some(1))),
+                Some((0, Some(0))),
+                None
+            ]
+        );
+        let e = error("  push1\n  add\n  pop\n");
+        assert!(
+            e.starts_with("StackUnderflow(") && e.ends_with("\"add\", 2, 1)"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn branches_join_unbalanced() {
+        // One way pushes a value the other doesn't before they meet.
+        let a = analysis(&[push(0), JumpZero(to(3)), push(1), Exit]);
+        assert_eq!(
+            a.main.states[3],
+            Some(Stack {
+                depth: 0,
+                most: Some(1),
+                top: Vec::new()
+            })
+        );
+        assert_eq!(a.main.ends[&3].most, Some(1));
+        assert!(error("  push0\n  jumpzero end\n  push1\nend:\n  exit\n").starts_with("Residue("));
+        // Pushing the same constant both ways keeps it known.
+        let a = analysis(&[
+            push(0),
+            JumpZero(to(4)),
+            push(7),
+            Jump(to(5)),
+            push(7),
+            Pop,
+            Exit,
+        ]);
+        assert_eq!(a.main.states[5].as_ref().unwrap().arg(0), Some(7u32.into()));
+        // A loop which pushes more than it pops has no most.
+        let a = analysis(&[push(1), push(0), JumpZero(to(0)), Exit]);
+        assert_eq!(a.main.states[0].as_ref().unwrap().most, None);
+    }
+
+    #[test]
+    fn depth_across_calls() {
+        // The routine at 5 adds its caller's two values, leaving one.
+        let code = [push(1), push(2), Call(to(5)), Pop, Exit, Add, Ret];
+        let a = analysis(&code);
+        let routine = &a.routines[&5];
+        assert_eq!(routine.summary.takes, 2);
+        assert_eq!(
+            routine.summary.returns.as_ref().map(|r| (r.depth, r.most)),
+            Some((-1, Some(-1)))
+        );
+        assert_eq!(
+            depths(&a.main)[..5],
+            [
+                Some((0, Some(0))),
+                Some((1, Some(1))),
+                Some((2, Some(2))),
+                Some((1, Some(1))),
+                Some((0, Some(0))),
+            ]
+        );
+        let e = error("  push1\n  call f\n  pop\n  exit\nf:\n  add\n  ret\n");
+        assert!(
+            e.starts_with("StackUnderflow(") && e.ends_with("\"call\", 2, 1)"),
+            "{}",
+            e
+        );
+        assert!(error("  push1\n  ret\n").starts_with("RetWithoutCall("));
+        // A routine which ends the event ends it for its caller too.
+        let a = analysis(&[push(1), Call(to(3)), Exit, Exit]);
+        assert_eq!(a.main.ends[&1].most, Some(1));
+    }
+}
@@ -0,0 +1,23 @@
+.name Data
+.symbol d
+.color #00f
+.desc "A datum carrying a random 8-bit value, picked on its first event. Data drifts at random until a Sorter moves it."
+.license "GPL-2.0-or-later"
+.symmetries ALL
+.radius 1
+.field value, 0, 8
+.field ready, 8, 1
+
+  push0
+  getsitefield ready
+  jumpnonzero drift
+  push8
+  rand
+  push0
+  setsitefield value      /* #0$value = random */
+  push1
+  push0
+  setsitefield ready
+drift:
+  push 50
+  diffuse
@@ -0,0 +1,57 @@
+.name DReg
+.symbol D
+.color #f00
+.desc "The dynamic regulator. DReg wanders, making Res and rarely more DReg in empty space and erasing what it meets, so the populations settle to a steady density."
+.author "Dave Ackley"
+.license "GPL-2.0-or-later"
+.symmetries ALL
+.radius 1
+
+  push1
+  getsitefield type       /* #1$type */
+  dup
+  gettype "Empty"
+  cmp
+  jeq empty
+  gettype "Self"
+  cmp
+  jeq dreg
+  push1                   /* erase anything else 1 in 100 */
+  push 100
+  oddsof
+  jumpzero end
+  jump erase
+dreg:
+  push1                   /* erase DReg 1 in 10 */
+  push 10
+  oddsof
+  jumpzero end
+erase:
+  push1
+  push0
+  setsite                 /* #1 = Empty */
+  exit
+empty:
+  pop
+  push1                   /* create DReg 1 in 1000 */
+  push 1000
+  oddsof
+  jumpnonzero create_dreg
+  push1                   /* create Res 1 in 200 */
+  push 200
+  oddsof
+  jumpnonzero create_res
+  push0
+  push1
+  swapsites               /* otherwise move */
+  exit
+create_dreg:
+  gettype "Self"
+  push1
+  setsitefield type       /* #1$type = DReg */
+  exit
+create_res:
+  gettype "Res"
+  push1
+  setsitefield type       /* #1$type = Res */
+end:
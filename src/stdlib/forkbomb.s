@@ -0,0 +1,12 @@
+.name ForkBomb
+.symbol F
+.color #f0f
+.desc "Copies itself into a random neighbor on every event until the grid is full."
+.license "GPL-2.0-or-later"
+.symmetries ALL
+.radius 1
+
+  push1
+  push0
+  getsite
+  setsite                 /* #1 = #0 */
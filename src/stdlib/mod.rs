@@ -0,0 +1,33 @@
+//! The standard library: canonical MFM elements written in EWAL and built
+//! into the binaries, so that they can be loaded by name.
+//!
+//! Empty is not listed; every runtime has it as type 0.
+
+use crate::code::Compiler;
+
+/// The name and source of every standard element.
+pub const ELEMENTS: &[(&str, &str)] = &[
+    ("Wall", include_str!("wall.s")),
+    ("Res", include_str!("res.s")),
+    ("DReg", include_str!("dreg.s")),
+    ("Sorter", include_str!("sorter.s")),
+    ("Data", include_str!("data.s")),
+    ("ForkBomb", include_str!("forkbomb.s")),
+];
+
+/// The source of the standard element `name`.
+pub fn source(name: &str) -> Option<&'static str> {
+    ELEMENTS.iter().find(|(n, _)| *n == name).map(|(_, s)| *s)
+}
+
+/// Compiles the standard element `name` with `build_tag`, giving `None` if
+/// there is no such element.
+pub fn compile(name: &str, build_tag: &str) -> Option<Result<Vec<u8>, String>> {
+    let src = source(name)?;
+    let mut v = Vec::new();
+    let mut compiler = Compiler::new(build_tag);
+    let result = compiler
+        .compile_to_writer(&mut v, src)
+        .map_err(|e| e.to_string());
+    Some(result.map(|_| v))
+}
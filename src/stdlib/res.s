@@ -0,0 +1,11 @@
+.name Res
+.symbol r
+.color #ff0
+.desc "A resource. Res drifts at random through empty space."
+.author "Dave Ackley"
+.license "GPL-2.0-or-later"
+.symmetries ALL
+.radius 1
+
+  push 100
+  diffuse
@@ -0,0 +1,42 @@
+.name Sorter
+.symbol S
+.color #0c0
+.desc "Sorts Data flowing west. Data from the east goes northwest if its value is above the Sorter's threshold, else southwest, and sets the threshold. A field of Sorters separates Data by value from north to south."
+.author "Dave Ackley"
+.license "GPL-2.0-or-later"
+.symmetries R000L
+.radius 1
+.field value, 0, 8
+
+  push4
+  getsitefield type       /* #4$type */
+  gettype "Data"
+  cmp
+  jne end
+  push4
+  getsitefield value
+  dup
+  push0
+  getsitefield value      /* the threshold, read as Data reads its value */
+  cmp                     /* #4$value > #0$value */
+  jgt high
+  push6
+  jump place
+high:
+  push5
+place:
+  dup
+  getsitefield type
+  gettype "Empty"
+  cmp
+  jne full
+  push4
+  swap
+  movesite                /* #4 -> #5 or #6 */
+  push0
+  setsitefield value      /* threshold = #4$value */
+  exit
+full:
+  pop
+  pop
+end:
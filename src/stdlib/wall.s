@@ -0,0 +1,7 @@
+.name Wall
+.symbol W
+.color #888
+.desc "An inert barrier. Wall never moves or changes, but other elements may erase it."
+.license "GPL-2.0-or-later"
+
+  exit
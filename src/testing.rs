@@ -0,0 +1,252 @@
+//! Golden tests of element programs.
+//!
+//! A golden test runs elements on a fixed grid with a fixed seed for a number
+//! of events, and compares the grid they leave with a golden copy stored
+//! beside the test, so that a change to an element library which changes
+//! what its elements do is caught.
+//!
+//! A test is a `.test.mfm` file: an `.mfs` grid (see `runtime::mfs`) with
+//! statements naming the elements to load, the seed, the number of events
+//! and optionally the grid's boundary, `void`, `wall` or `torus`. Elements
+//! are files relative to the test, compiled or, without a compiled file's
+//! magic number, source, or names of standard elements. The grid gives
+//! elements by their
+//! `.mfmtype`, if they have one, or else by their number, counting from 1 in
+//! the order they are given:
+//!
+//! ```text
+//! # Sand piles up on the wall.
+//! Element(sand.ewal)
+//! Element(Wall)
+//! Seed(42)
+//! Events(10000)
+//! Boundary(wall)
+//! Grid(1, 1, 16, 16)
+//! GA(1, 8, 0)
+//! GA(2, 8, 15)
+//! ```
+//!
+//! The golden grid is written as `.mfs` statements to the file named like
+//! the test with `.golden.mfs` in place of `.test.mfm`, by `GoldenTest::bless`
+//! or `ewar test --bless`.
+
+use crate::engine::{Engine, EngineError};
+use crate::preprocess::{self, PreprocessError};
+use crate::runtime::grid::Boundary;
+use crate::runtime::{self, mfs, BUNDLE_MAGIC_NUMBER, MAGIC_NUMBER};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+/// The suffix of golden test files.
+pub const TEST_SUFFIX: &str = ".test.mfm";
+
+/// The suffix which replaces `TEST_SUFFIX` in the name of a golden grid.
+pub const GOLDEN_SUFFIX: &str = ".golden.mfs";
+
+#[derive(Debug, thiserror::Error)]
+pub enum TestError {
+    #[error("IO error: {0}")]
+    IOError(#[from] io::Error),
+    #[error("bad test statement on line {0}")]
+    BadLine(usize),
+    #[error("the test loads no elements")]
+    NoElements,
+    #[error(transparent)]
+    Preprocess(#[from] PreprocessError),
+    #[error(transparent)]
+    Engine(#[from] EngineError),
+    #[error(transparent)]
+    Runtime(#[from] runtime::Error),
+}
+
+/// How a golden test went.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    /// The grid differs from the golden one: the statements of the golden
+    /// grid missing from it, and those in it but not in the golden grid.
+    Failed {
+        missing: Vec<String>,
+        unexpected: Vec<String>,
+    },
+    /// There is no golden grid yet.
+    NoGolden,
+}
+
+#[derive(Clone, Debug)]
+pub struct GoldenTest {
+    path: PathBuf,
+    elements: Vec<String>,
+    seed: u64,
+    events: u64,
+    boundary: Boundary,
+}
+
+impl GoldenTest {
+    /// Reads the test at `path`.
+    pub fn load(path: &Path) -> Result<Self, TestError> {
+        let mut test = Self {
+            path: path.to_owned(),
+            elements: Vec::new(),
+            seed: 0,
+            events: 0,
+            boundary: Boundary::Void,
+        };
+        for (n, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            let (name, arg) = match line.find('(') {
+                Some(open) if !line.starts_with('#') => (&line[..open], &line[open + 1..]),
+                _ => continue,
+            };
+            let bad = || TestError::BadLine(n + 1);
+            let arg = arg.strip_suffix(')').ok_or_else(bad)?.trim();
+            match name.trim() {
+                "Element" if !arg.is_empty() => test.elements.push(arg.to_owned()),
+                "Seed" => test.seed = arg.parse().map_err(|_| bad())?,
+                "Events" => test.events = arg.parse().map_err(|_| bad())?,
+                "Boundary" => test.boundary = arg.parse().map_err(|_| bad())?,
+                "Element" => return Err(bad()),
+                _ => {}
+            }
+        }
+        if test.elements.is_empty() {
+            return Err(TestError::NoElements);
+        }
+        Ok(test)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The file holding the golden grid.
+    pub fn golden_path(&self) -> PathBuf {
+        let name = self.path.to_string_lossy();
+        let stem = name.strip_suffix(TEST_SUFFIX).unwrap_or(&name);
+        PathBuf::from(format!("{}{}", stem, GOLDEN_SUFFIX))
+    }
+
+    /// Runs the test, giving the engine as the events left it.
+    pub fn run(&self) -> Result<Engine, TestError> {
+        let dir = self.path.parent().unwrap_or_else(|| Path::new("."));
+        let mut builder = Engine::builder().seed(self.seed).boundary(self.boundary);
+        for e in self.elements.iter() {
+            let path = dir.join(e);
+            if !path.exists() {
+                builder = builder.with_standard(e);
+                continue;
+            }
+            let bytes = fs::read(&path)?;
+            builder = if bytes.starts_with(&MAGIC_NUMBER.to_be_bytes())
+                || bytes.starts_with(&BUNDLE_MAGIC_NUMBER.to_be_bytes())
+            {
+                builder.with_bytecode(&bytes)
+            } else {
+                // Expanded here so that includes are found beside the source.
+                let src = preprocess::expand_file(&path, &HashMap::new())?;
+                builder.with_element(&src.text)
+            };
+        }
+        let mut engine = builder.build()?;
+        let file = fs::File::open(&self.path)?;
+        let mut grid = mfs::import(BufReader::new(file), engine.runtime())?;
+        grid.set_boundary(self.boundary);
+        grid.set_wall(engine.grid().wall());
+        *engine.grid_mut() = grid;
+        engine.run_events(self.events)?;
+        Ok(engine)
+    }
+
+    /// The grid the test leaves, as `.mfs` statements.
+    fn result(&self) -> Result<String, TestError> {
+        let engine = self.run()?;
+        let mut out = Vec::new();
+        mfs::export(&mut out, engine.grid(), engine.runtime())?;
+        Ok(String::from_utf8_lossy(&out).into_owned())
+    }
+
+    /// Runs the test and compares its grid with the golden one.
+    pub fn check(&self) -> Result<Outcome, TestError> {
+        let got = self.result()?;
+        let want = match fs::read_to_string(self.golden_path()) {
+            Ok(want) => want,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Outcome::NoGolden),
+            Err(e) => return Err(e.into()),
+        };
+        let got: Vec<&str> = got.lines().collect();
+        let want: Vec<&str> = want
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .collect();
+        if got == want {
+            return Ok(Outcome::Passed);
+        }
+        let (got_set, want_set): (HashSet<&str>, HashSet<&str>) = (
+            got.iter().copied().collect(),
+            want.iter().copied().collect(),
+        );
+        let missing = want
+            .iter()
+            .filter(|l| !got_set.contains(*l))
+            .map(|l| l.to_string())
+            .collect();
+        let unexpected = got
+            .iter()
+            .filter(|l| !want_set.contains(*l))
+            .map(|l| l.to_string())
+            .collect();
+        Ok(Outcome::Failed {
+            missing,
+            unexpected,
+        })
+    }
+
+    /// Runs the test and stores its grid as the golden one.
+    pub fn bless(&self) -> Result<(), TestError> {
+        let got = self.result()?;
+        fs::write(self.golden_path(), got)?;
+        Ok(())
+    }
+}
+
+/// Finds the golden tests under `path`, or `path` itself if it is one, in
+/// order of their paths.
+pub fn discover(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut tests = Vec::new();
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            tests.extend(discover(&entry?.path())?);
+        }
+    } else if path.to_string_lossy().ends_with(TEST_SUFFIX) {
+        tests.push(path.to_owned());
+    }
+    tests.sort();
+    Ok(tests)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GOLDEN: &str = "tests/testdata/golden";
+
+    #[test]
+    fn golden_tests_pass() {
+        let tests = discover(Path::new(GOLDEN)).unwrap();
+        assert_eq!(tests.len(), 5);
+        for path in tests {
+            let outcome = GoldenTest::load(&path).unwrap().check().unwrap();
+            assert_eq!(outcome, Outcome::Passed, "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn changed_grid_fails() {
+        let mut test = GoldenTest::load(&Path::new(GOLDEN).join("res.test.mfm")).unwrap();
+        test.seed += 1;
+        assert!(matches!(test.check().unwrap(), Outcome::Failed { .. }));
+    }
+}
@@ -0,0 +1,542 @@
+//! A live view of a running grid in the terminal.
+//!
+//! Sites are drawn with their element's symbol and color, beside a census of
+//! the grid and the fields of the atom under the cursor. Keys:
+//!
+//! ```text
+//! space   pause or resume
+//! .       run one frame while paused
+//! + -     double or halve the events run each frame
+//! arrows  move the cursor, scrolling grids larger than the terminal
+//! click   move the cursor to a site, using the tool there
+//! i       inspect: clicking only moves the cursor
+//! b       brush: paint atoms of the chosen element
+//! e       eraser: empty sites
+//! r       rectangle: fill the sites between two corners
+//! [ ]     choose the previous or next element
+//! enter   use the tool at the cursor
+//! q       quit
+//! ```
+//!
+//! The brush and eraser paint every site dragged over. Rectangles are drawn
+//! by dragging from one corner to the other, or by pressing enter at each.
+//!
+//! An event which hits one of the runtime's watchpoints pauses the view with
+//! the cursor on the event's site.
+//!
+//! Before each frame the viewer calls back, such as to reload elements whose
+//! files changed, and shows the note the callback gives under the run state.
+//!
+//! The terminal is put in raw mode on the alternate screen while the viewer
+//! runs and restored when it ends, however it ends.
+
+use crate::base::atom::Atom;
+use crate::base::color::Color;
+use crate::base::ecc;
+use crate::runtime::grid::Grid;
+use crate::runtime::stats::Report;
+use crate::runtime::{Error, Runtime};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+/// How long to wait for a key between frames.
+const FRAME: Duration = Duration::from_millis(50);
+
+/// The width of the census beside the grid, in columns.
+const SIDEBAR_WIDTH: usize = 28;
+
+/// Raw mode on the alternate screen, undone on drop.
+struct Terminal {
+    saved: libc::termios,
+}
+
+impl Terminal {
+    fn enter() -> io::Result<Self> {
+        let mut saved = unsafe { std::mem::zeroed::<libc::termios>() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = saved;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // The alternate screen, a hidden cursor and mouse presses, drags
+        // and releases reported as `CSI < button ; column ; row M` (or `m`
+        // for releases).
+        print!("\x1b[?1049h\x1b[?25l\x1b[?1002h\x1b[?1006h\x1b[2J");
+        io::stdout().flush()?;
+        Ok(Self { saved })
+    }
+
+    /// The terminal's size in columns and rows.
+    fn size(&self) -> (usize, usize) {
+        let mut ws = unsafe { std::mem::zeroed::<libc::winsize>() };
+        if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut ws) } != 0
+            || ws.ws_col == 0
+        {
+            return (80, 24);
+        }
+        (ws.ws_col as usize, ws.ws_row as usize)
+    }
+
+    /// Waits up to `timeout` for input, giving the keys read.
+    fn keys(&self, timeout: Duration) -> io::Result<Vec<Key>> {
+        let mut fd = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let n = unsafe { libc::poll(&mut fd, 1, timeout.as_millis() as libc::c_int) };
+        if n <= 0 {
+            return Ok(Vec::new());
+        }
+        let mut b = [0u8; 64];
+        let n = io::stdin().read(&mut b)?;
+        Ok(Key::parse(&b[..n]))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Key {
+    Char(u8),
+    Up,
+    Down,
+    Left,
+    Right,
+    /// The left button pressed, moved while pressed, or released at a
+    /// column and row, from 0.
+    Press(usize, usize),
+    Drag(usize, usize),
+    Release(usize, usize),
+}
+
+impl Key {
+    fn parse(mut b: &[u8]) -> Vec<Self> {
+        let mut keys = Vec::new();
+        loop {
+            let (key, n) = match b {
+                [0x1b, b'[', b'A', ..] => (Some(Key::Up), 3),
+                [0x1b, b'[', b'B', ..] => (Some(Key::Down), 3),
+                [0x1b, b'[', b'C', ..] => (Some(Key::Right), 3),
+                [0x1b, b'[', b'D', ..] => (Some(Key::Left), 3),
+                [0x1b, b'[', b'<', rest @ ..] => {
+                    match rest.iter().position(|&c| c == b'M' || c == b'm') {
+                        Some(end) => (Self::mouse(&rest[..end], rest[end]), 3 + end + 1),
+                        None => (None, b.len()),
+                    }
+                }
+                [c, ..] => (Some(Key::Char(*c)), 1),
+                [] => break,
+            };
+            keys.extend(key);
+            b = &b[n..];
+        }
+        keys
+    }
+
+    /// Parses the `button;column;row` of a mouse report ended by `end`.
+    fn mouse(report: &[u8], end: u8) -> Option<Self> {
+        let report = std::str::from_utf8(report).ok()?;
+        let v: Vec<usize> = report
+            .split(';')
+            .map(|s| s.parse().ok())
+            .collect::<Option<_>>()?;
+        let (button, col, row) = match *v.as_slice() {
+            [button, col, row] => (button, col.checked_sub(1)?, row.checked_sub(1)?),
+            _ => return None,
+        };
+        match (button, end) {
+            (0, b'M') => Some(Key::Press(col, row)),
+            (32, b'M') => Some(Key::Drag(col, row)),
+            (0, b'm') => Some(Key::Release(col, row)),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        print!("\x1b[0m\x1b[?1006l\x1b[?1002l\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved) };
+    }
+}
+
+/// How an element is drawn.
+struct Glyph {
+    symbol: char,
+    color: Option<Color>,
+}
+
+fn glyphs(runtime: &Runtime) -> HashMap<u16, Glyph> {
+    runtime
+        .elements()
+        .map(|(t, m)| {
+            let symbol = m.symbol.chars().next().unwrap_or('?');
+            let color = m.color.or_else(|| m.fg_color.parse().ok());
+            (t, Glyph { symbol, color })
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Tool {
+    Inspect,
+    Brush,
+    Eraser,
+    Rectangle,
+}
+
+/// The state of the view between frames.
+struct Viewer {
+    glyphs: HashMap<u16, Glyph>,
+    /// Events run each frame.
+    speed: u64,
+    paused: bool,
+    /// The watchpoint which paused the view, until it runs again.
+    hit: Option<usize>,
+    /// Events run so far, and the most to run.
+    run: u64,
+    events: Option<u64>,
+    elapsed: Duration,
+    /// The site under the cursor, and the site drawn at the top left.
+    cursor: (usize, usize),
+    origin: (usize, usize),
+    /// The size of the grid drawn in the last frame.
+    view: (usize, usize),
+    tool: Tool,
+    /// The types which can be painted, and the one chosen.
+    elements: Vec<u16>,
+    element: usize,
+    /// The first corner of a rectangle being drawn.
+    corner: Option<(usize, usize)>,
+    /// The last note given between frames.
+    note: Option<String>,
+}
+
+/// Runs `grid` in the terminal until `q` is pressed, stopping events once
+/// `events` have run if given, and calling `between_frames` before each
+/// frame. Gives the time spent running events.
+pub fn run<F>(
+    grid: &mut Grid,
+    runtime: &mut Runtime,
+    events: Option<u64>,
+    mut between_frames: F,
+) -> Result<Duration, Error>
+where
+    F: FnMut(&mut Runtime) -> Option<String>,
+{
+    let mut elements: Vec<u16> = runtime
+        .elements()
+        .map(|(t, _)| t)
+        .filter(|&t| t != 0)
+        .collect();
+    elements.sort_unstable();
+    let term = Terminal::enter()?;
+    let mut v = Viewer {
+        glyphs: glyphs(runtime),
+        speed: 1.max(grid.width() as u64 * grid.height() as u64 / 16),
+        paused: false,
+        hit: None,
+        run: 0,
+        events,
+        elapsed: Duration::default(),
+        cursor: (grid.width() / 2, grid.height() / 2),
+        origin: (0, 0),
+        view: (0, 0),
+        tool: Tool::Inspect,
+        elements,
+        element: 0,
+        corner: None,
+        note: None,
+    };
+    loop {
+        if let Some(note) = between_frames(runtime) {
+            v.glyphs = glyphs(runtime);
+            v.note = Some(note);
+        }
+        v.draw(&term, grid, runtime)?;
+        let mut step = false;
+        for key in term.keys(FRAME)? {
+            match key {
+                Key::Char(b'q') => return Ok(v.elapsed),
+                Key::Char(b' ') => v.paused = !v.paused,
+                Key::Char(b'.') => step = true,
+                Key::Char(b'+') | Key::Char(b'=') => v.speed = v.speed.saturating_mul(2),
+                Key::Char(b'-') => v.speed = 1.max(v.speed / 2),
+                key => v.edit(key, grid),
+            }
+        }
+        if v.done() || (v.paused && !step) {
+            continue;
+        }
+        let n = v.events.map_or(v.speed, |n| v.speed.min(n - v.run));
+        let start = Instant::now();
+        let mut run = 0;
+        v.hit = None;
+        while run < n {
+            match grid.step_at_most(runtime, n - run) {
+                Ok(k) => run += k,
+                // Pause on the site of the event which hit a watchpoint.
+                Err(Error::Watchpoint { watch, x, y }) => {
+                    run += 1;
+                    v.paused = true;
+                    v.hit = Some(watch);
+                    v.cursor = (x, y);
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        v.elapsed += start.elapsed();
+        v.run += run;
+    }
+}
+
+impl Viewer {
+    fn done(&self) -> bool {
+        matches!(self.events, Some(n) if self.run >= n)
+    }
+
+    /// Moves the cursor, or changes or uses the tool.
+    fn edit(&mut self, key: Key, grid: &mut Grid) {
+        match key {
+            Key::Up => self.cursor.1 = self.cursor.1.saturating_sub(1),
+            Key::Down => self.cursor.1 = (self.cursor.1 + 1).min(grid.height() - 1),
+            Key::Left => self.cursor.0 = self.cursor.0.saturating_sub(1),
+            Key::Right => self.cursor.0 = (self.cursor.0 + 1).min(grid.width() - 1),
+            Key::Char(b'i') => self.select(Tool::Inspect),
+            Key::Char(b'b') => self.select(Tool::Brush),
+            Key::Char(b'e') => self.select(Tool::Eraser),
+            Key::Char(b'r') => self.select(Tool::Rectangle),
+            Key::Char(b'[') if !self.elements.is_empty() => {
+                self.element = (self.element + self.elements.len() - 1) % self.elements.len()
+            }
+            Key::Char(b']') if !self.elements.is_empty() => {
+                self.element = (self.element + 1) % self.elements.len()
+            }
+            Key::Char(b'\r') | Key::Char(b'\n') => match (self.tool, self.corner.take()) {
+                (Tool::Rectangle, None) => self.corner = Some(self.cursor),
+                (Tool::Rectangle, Some(corner)) => self.fill(grid, corner, self.cursor),
+                _ => self.paint(grid, self.cursor),
+            },
+            Key::Press(col, row) | Key::Drag(col, row) | Key::Release(col, row) => {
+                let site = match self.site(col, row) {
+                    Some(site) => site,
+                    None => return,
+                };
+                self.cursor = site;
+                match (key, self.tool) {
+                    (Key::Press(..), Tool::Rectangle) => self.corner = Some(site),
+                    (Key::Release(..), Tool::Rectangle) => {
+                        if let Some(corner) = self.corner.take() {
+                            self.fill(grid, corner, site)
+                        }
+                    }
+                    (Key::Release(..), _) => {}
+                    _ => self.paint(grid, site),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn select(&mut self, tool: Tool) {
+        self.tool = tool;
+        self.corner = None;
+    }
+
+    /// The site drawn at a column and row of the terminal.
+    fn site(&self, col: usize, row: usize) -> Option<(usize, usize)> {
+        if col < self.view.0 && row < self.view.1 {
+            Some((self.origin.0 + col, self.origin.1 + row))
+        } else {
+            None
+        }
+    }
+
+    /// An atom of the chosen element.
+    fn brush(&self) -> Option<Atom> {
+        let t = *self.elements.get(self.element)?;
+        Some(ecc::encode(Atom::new(t)))
+    }
+
+    /// Uses the brush or eraser at `site`.
+    fn paint(&self, grid: &mut Grid, (x, y): (usize, usize)) {
+        let atom = match self.tool {
+            Tool::Brush => self.brush(),
+            Tool::Eraser => Some(Atom::default()),
+            _ => None,
+        };
+        if let Some(atom) = atom {
+            grid.set(x, y, atom);
+        }
+    }
+
+    /// Fills the rectangle with corners `a` and `b` with the chosen element.
+    fn fill(&self, grid: &mut Grid, a: (usize, usize), b: (usize, usize)) {
+        let atom = match self.brush() {
+            Some(atom) => atom,
+            None => return,
+        };
+        for y in a.1.min(b.1)..=a.1.max(b.1) {
+            for x in a.0.min(b.0)..=a.0.max(b.0) {
+                grid.set(x, y, atom);
+            }
+        }
+    }
+
+    /// Whether `(x, y)` is under the cursor or in the rectangle being drawn.
+    fn highlighted(&self, x: usize, y: usize) -> bool {
+        match self.corner {
+            Some(c) => {
+                (c.0.min(self.cursor.0)..=c.0.max(self.cursor.0)).contains(&x)
+                    && (c.1.min(self.cursor.1)..=c.1.max(self.cursor.1)).contains(&y)
+            }
+            None => (x, y) == self.cursor,
+        }
+    }
+
+    /// Describes the tool in use.
+    fn describe_tool(&self, runtime: &Runtime) -> String {
+        let element = self
+            .elements
+            .get(self.element)
+            .and_then(|&t| runtime.metadata(t))
+            .map_or("nothing", |m| m.name.as_str());
+        match self.tool {
+            Tool::Inspect => "inspect".to_owned(),
+            Tool::Brush => format!("brush {}", element),
+            Tool::Eraser => "eraser".to_owned(),
+            Tool::Rectangle => format!("rectangle {}", element),
+        }
+    }
+
+    /// Scrolls the view of a grid the size of `view` to show the cursor.
+    fn scroll(&mut self, view: (usize, usize)) {
+        fn follow(origin: usize, cursor: usize, size: usize) -> usize {
+            if cursor < origin {
+                cursor
+            } else if cursor >= origin + size {
+                cursor + 1 - size
+            } else {
+                origin
+            }
+        }
+        self.view = view;
+        self.origin = (
+            follow(self.origin.0, self.cursor.0, view.0),
+            follow(self.origin.1, self.cursor.1, view.1),
+        );
+    }
+
+    /// Describes the atom under the cursor and its fields.
+    fn inspect(&self, atom: Atom, runtime: &Runtime) -> Vec<String> {
+        let (x, y) = self.cursor;
+        let metadata = runtime.metadata(atom.get_type());
+        let name = match metadata {
+            Some(m) => m.name.clone(),
+            None => format!("type {}", atom.get_type()),
+        };
+        let mut lines = vec![format!("({}, {}) {}", x, y, name)];
+        if let Some(m) = metadata.filter(|_| !atom.is_empty()) {
+            let mut fields: Vec<_> = m.field_map.user_fields().collect();
+            fields.sort_by_key(|(_, f)| f.offset);
+            for (name, f) in fields {
+                lines.push(format!("  {:<16.16} {}", name, f.extract(atom.bits())));
+            }
+        }
+        lines
+    }
+
+    fn sidebar(&self, report: &Report, atom: Atom, runtime: &Runtime) -> Vec<String> {
+        let state = match self.hit {
+            _ if self.done() => "done".to_owned(),
+            Some(w) if self.paused => format!("watchpoint {}", w),
+            _ if self.paused => "paused".to_owned(),
+            _ => "running".to_owned(),
+        };
+        let mut lines = vec![
+            format!("events {}", report.stats.events),
+            format!("AEPS {:.3}", report.aeps()),
+            format!("AER {:.3}/s", report.aer()),
+            format!("{} events/frame", self.speed),
+            state,
+        ];
+        if let Some(note) = &self.note {
+            lines.extend(note.lines().next().map(str::to_owned));
+        }
+        lines.push(String::new());
+        for (t, n) in report.census.iter().filter(|(t, _)| **t != 0) {
+            let symbol = self.glyphs.get(t).map_or('?', |g| g.symbol);
+            let name = report
+                .name(*t)
+                .map_or_else(|| format!("type {}", t), str::to_owned);
+            lines.push(format!("{} {:<16.16} {}", symbol, name, n));
+        }
+        lines.push(String::new());
+        lines.extend(self.inspect(atom, runtime));
+        lines.push(String::new());
+        lines.push(self.describe_tool(runtime));
+        lines.push(String::new());
+        lines.push("space pause  . step".to_owned());
+        lines.push("+ - speed    q quit".to_owned());
+        lines.push("i inspect    b brush".to_owned());
+        lines.push("e eraser     r rectangle".to_owned());
+        lines.push("[ ] element  enter use".to_owned());
+        lines
+    }
+
+    fn draw(&mut self, term: &Terminal, grid: &Grid, runtime: &Runtime) -> io::Result<()> {
+        let (cols, rows) = term.size();
+        let width = grid.width().min(cols.saturating_sub(SIDEBAR_WIDTH + 1));
+        let height = grid.height().min(rows.saturating_sub(1));
+        self.scroll((width, height));
+        let atom = grid.get(self.cursor.0, self.cursor.1).unwrap();
+        let sidebar = self.sidebar(&Report::new(grid, runtime, self.elapsed), atom, runtime);
+
+        let mut out = String::from("\x1b[H");
+        for row in 0..height.max(sidebar.len()).min(rows.saturating_sub(1)) {
+            for col in 0..width {
+                let (x, y) = (self.origin.0 + col, self.origin.1 + row);
+                let atom = match grid.get(x, y) {
+                    Some(atom) if row < height => atom,
+                    _ => {
+                        out.push(' ');
+                        continue;
+                    }
+                };
+                let highlighted = self.highlighted(x, y);
+                if highlighted {
+                    out.push_str("\x1b[7m");
+                }
+                match self.glyphs.get(&atom.get_type()) {
+                    _ if atom.is_empty() => out.push('.'),
+                    Some(Glyph {
+                        symbol,
+                        color: Some(c),
+                    }) => c.write_foreground(&mut out, *symbol).unwrap(),
+                    Some(g) => out.push(g.symbol),
+                    None => out.push('?'),
+                }
+                if highlighted {
+                    out.push_str("\x1b[0m");
+                }
+            }
+            if let Some(line) = sidebar.get(row) {
+                let line: String = line.chars().take(SIDEBAR_WIDTH).collect();
+                write!(out, " {}", line).unwrap();
+            }
+            out.push_str("\x1b[K\r\n");
+        }
+        out.push_str("\x1b[J");
+        let mut stdout = io::stdout();
+        stdout.write_all(out.as_bytes())?;
+        stdout.flush()
+    }
+}
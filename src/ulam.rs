@@ -0,0 +1,588 @@
+//! Importing elements written in ulam, the language of the T2 tile and the
+//! reference MFM simulator.
+//!
+//! ulam elements are compiled to C++ and run natively, so their behavior
+//! can't be carried over. What the engine's element model can express is
+//! translated to EWAL source:
+//!
+//! - the element's name, and its `\symbol`, `\color`, `\author`, `\license`
+//!   and `\symmetries` doc comment tags, with the text before the first tag
+//!   as its description;
+//! - data members of the types `Unsigned`, `Int`, `Bool`, `Unary` and
+//!   `Bits`, as `.field`s placed as ulam packs them, from the top of the
+//!   data bits down in declaration order;
+//! - `constant`s and `parameter`s with literal values, as `.parameter`s.
+//!
+//! Everything else (methods, base classes, quarks, arrays, other types) is
+//! listed as not translated, and method bodies are copied into the output as
+//! comments to port by hand.
+
+use crate::base::FieldSelector;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(thiserror::Error, Debug)]
+pub enum UlamError {
+    #[error("line {0}: unterminated comment")]
+    UnterminatedComment(usize),
+    #[error("line {0}: unterminated string")]
+    UnterminatedString(usize),
+    #[error("line {0}: unbalanced braces")]
+    UnbalancedBraces(usize),
+    #[error("line {0}: expected the element's name")]
+    MissingName(usize),
+    #[error("no element declared")]
+    NoElement,
+}
+
+/// A construct the import couldn't translate.
+#[derive(Clone, Debug)]
+pub struct Unsupported {
+    pub line: usize,
+    pub what: String,
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.what)
+    }
+}
+
+/// One element translated to EWAL.
+#[derive(Clone, Debug)]
+pub struct Translation {
+    pub name: String,
+    pub source: String,
+    pub unsupported: Vec<Unsupported>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Doc(String),
+    Word(String),
+    Number(String),
+    Str,
+    Punct(char),
+}
+
+#[derive(Clone, Debug)]
+struct Spanned {
+    token: Token,
+    line: usize,
+    start: usize,
+    end: usize,
+}
+
+fn tokenize(src: &str) -> Result<Vec<Spanned>, UlamError> {
+    let bytes = src.as_bytes();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+        let start_line = line;
+        let token = if c == b'\n' {
+            line += 1;
+            i += 1;
+            continue;
+        } else if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        } else if src[i..].starts_with("//") {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                i += 1;
+            }
+            continue;
+        } else if src[i..].starts_with("/*") {
+            let end = src[i + 2..]
+                .find("*/")
+                .ok_or(UlamError::UnterminatedComment(line))?
+                + i
+                + 2;
+            let text = &src[i + 2..end];
+            line += text.matches('\n').count();
+            i = end + 2;
+            match text.strip_prefix('*') {
+                Some(doc) if !doc.starts_with('*') || doc.is_empty() => Token::Doc(doc.to_owned()),
+                _ => continue,
+            }
+        } else if c == b'"' || c == b'\'' {
+            i += 1;
+            while i < bytes.len() && bytes[i] != c {
+                if bytes[i] == b'\\' {
+                    i += 1;
+                }
+                if i < bytes.len() && bytes[i] == b'\n' {
+                    line += 1;
+                }
+                i += 1;
+            }
+            if i >= bytes.len() {
+                return Err(UlamError::UnterminatedString(start_line));
+            }
+            i += 1;
+            Token::Str
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            Token::Word(src[start..i].to_owned())
+        } else if c.is_ascii_digit() {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            Token::Number(src[start..i].to_owned())
+        } else {
+            // Skip the rest of a multibyte character.
+            i += src[i..].chars().next().map_or(1, char::len_utf8);
+            Token::Punct(c as char)
+        };
+        tokens.push(Spanned {
+            token,
+            line: start_line,
+            start,
+            end: i,
+        });
+    }
+    Ok(tokens)
+}
+
+/// Data member types and the width they have without one given.
+const DATA_TYPES: &[(&str, u32)] = &[
+    ("Unsigned", 32),
+    ("Int", 32),
+    ("Bool", 1),
+    ("Unary", 32),
+    ("Bits", 8),
+];
+
+/// ulam's names for the symmetries of a `\symmetries` tag, in the order of
+/// their numbers.
+const SYMMETRIES: &[&str] = &[
+    "R000L", "R090L", "R180L", "R270L", "R000R", "R090R", "R180R", "R270R",
+];
+
+const DIGITS: &[&str] = &[
+    "Zero", "One", "Two", "Three", "Four", "Five", "Six", "Seven", "Eight", "Nine",
+];
+
+/// An EWAL identifier for the ulam name `name`, which may begin with a
+/// capital or hold digits; EWAL identifiers may not.
+fn identifier(name: &str) -> String {
+    let mut s = String::new();
+    for (i, c) in name.chars().enumerate() {
+        match c {
+            '0'..='9' => s.push_str(DIGITS[c as usize - '0' as usize]),
+            c if i == 0 => s.push(c.to_ascii_lowercase()),
+            c => s.push(c),
+        }
+    }
+    s
+}
+
+/// Makes `s` safe to quote in EWAL, which has no escapes.
+fn quoted(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "'"))
+}
+
+/// The value of an integer literal, as EWAL reads it.
+fn literal(tokens: &[Token]) -> Option<String> {
+    let (negative, tokens) = match tokens {
+        [Token::Punct('-'), rest @ ..] => (true, rest),
+        _ => (false, tokens),
+    };
+    let value = match tokens {
+        [Token::Word(w)] if w == "true" => 1,
+        [Token::Word(w)] if w == "false" => 0,
+        [Token::Number(n)] => {
+            let n = n.trim_end_matches(['u', 'U']);
+            match n.strip_prefix("0x").or_else(|| n.strip_prefix("0X")) {
+                Some(hex) => i128::from_str_radix(hex, 16).ok()?,
+                None => n.parse().ok()?,
+            }
+        }
+        _ => return None,
+    };
+    Some(if negative { -value } else { value }.to_string())
+}
+
+struct Element {
+    name: String,
+    metadata: Vec<String>,
+    fields: Vec<String>,
+    parameters: Vec<String>,
+    methods: Vec<String>,
+    unsupported: Vec<Unsupported>,
+    /// Typedefs of data member types, by name, as the tokens they stand for.
+    typedefs: HashMap<String, Vec<Token>>,
+    /// Data bits used by the fields so far, from the top.
+    used: u32,
+}
+
+impl Element {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            metadata: vec![format!(".name {}", quoted(name))],
+            fields: Vec::new(),
+            parameters: Vec::new(),
+            methods: Vec::new(),
+            unsupported: Vec::new(),
+            typedefs: HashMap::new(),
+            used: 0,
+        }
+    }
+
+    fn unsupported(&mut self, line: usize, what: String) {
+        self.unsupported.push(Unsupported { line, what });
+    }
+
+    /// An identifier for `name`, noting if it had to change.
+    fn rename(&mut self, line: usize, name: &str) -> String {
+        let id = identifier(name);
+        if id != name {
+            self.unsupported.push(Unsupported {
+                line,
+                what: format!("{} renamed to {}", name, id),
+            });
+        }
+        id
+    }
+
+    fn doc(&mut self, line: usize, doc: &str) {
+        let text: Vec<&str> = doc
+            .lines()
+            .map(|l| l.trim().trim_start_matches('*').trim())
+            .collect();
+        let text = text.join("\n");
+        let mut parts = text.split('\\');
+        let desc = parts
+            .next()
+            .unwrap_or("")
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        if !desc.is_empty() {
+            self.metadata.push(format!(".desc {}", quoted(&desc)));
+        }
+        for tag in parts {
+            let (name, value) = match tag.find(char::is_whitespace) {
+                Some(i) => (&tag[..i], tag[i..].trim()),
+                None => (tag, ""),
+            };
+            // Values run to the end of the line; later lines continue the
+            // description, which EWAL doesn't take after tags.
+            let value = value.lines().next().unwrap_or("").trim();
+            match name {
+                "symbol" if !value.is_empty() => {
+                    self.metadata.push(format!(".symbol {}", quoted(value)))
+                }
+                "color" if value.starts_with('#') => {
+                    self.metadata.push(format!(".color {}", quoted(value)))
+                }
+                "author" => self.metadata.push(format!(".author {}", quoted(value))),
+                "license" => self.metadata.push(format!(".license {}", quoted(value))),
+                "symmetries" => match symmetries(value) {
+                    Some(s) => self.metadata.push(format!(".symmetries {}", s)),
+                    None => self.unsupported(line, format!("\\symmetries {}", value)),
+                },
+                // Whether the element shows in the simulator's palette.
+                "placeable" => {}
+                _ => self.unsupported(
+                    line,
+                    format!("doc tag \\{} {}", name, value)
+                        .trim_end()
+                        .to_owned(),
+                ),
+            }
+        }
+    }
+
+    /// Translates a declaration ending in `;`.
+    fn declaration(&mut self, line: usize, tokens: &[Token]) {
+        let expanded;
+        let tokens = match tokens.split_first() {
+            Some((Token::Word(w), rest)) if self.typedefs.contains_key(w) => {
+                expanded = [&self.typedefs[w][..], rest].concat();
+                &expanded[..]
+            }
+            _ => tokens,
+        };
+        let words: Vec<&str> = tokens
+            .iter()
+            .filter_map(|t| match t {
+                Token::Word(w) => Some(w.as_str()),
+                _ => None,
+            })
+            .collect();
+        match tokens.first() {
+            Some(Token::Word(w)) if w == "constant" || w == "parameter" => {
+                let kind = w.clone();
+                let eq = tokens.iter().position(|t| *t == Token::Punct('='));
+                let name = match eq.and_then(|i| tokens[..i].last()) {
+                    Some(Token::Word(name)) => name.clone(),
+                    _ => return self.unsupported(line, format!("{} without a value", kind)),
+                };
+                match literal(&tokens[eq.unwrap() + 1..]) {
+                    Some(value) => {
+                        let id = self.rename(line, &name);
+                        if kind == "parameter" {
+                            self.unsupported(
+                                line,
+                                format!("parameter {} is fixed at its default", name),
+                            );
+                        }
+                        self.parameters
+                            .push(format!(".parameter {}, {}", id, value));
+                    }
+                    None => self.unsupported(line, format!("{} {} is not a literal", kind, name)),
+                }
+            }
+            Some(Token::Word(w)) if DATA_TYPES.iter().any(|(t, _)| t == w) => {
+                let default = DATA_TYPES.iter().find(|(t, _)| t == w).unwrap().1;
+                let (width, rest) = match tokens {
+                    [_, Token::Punct('('), Token::Number(n), Token::Punct(')'), rest @ ..] => {
+                        match n.parse() {
+                            Ok(n) => (n, rest),
+                            Err(_) => {
+                                return self.unsupported(line, format!("{}({}) member", w, n))
+                            }
+                        }
+                    }
+                    [_, Token::Punct('('), ..] => {
+                        return self.unsupported(line, format!("{} member of computed width", w))
+                    }
+                    [_, rest @ ..] => (default, rest),
+                    [] => unreachable!(),
+                };
+                let kind = w.clone();
+                for member in rest.split(|t| *t == Token::Punct(',')) {
+                    match member {
+                        [Token::Word(name)] => self.field(line, name, width),
+                        [Token::Word(name), Token::Punct('['), ..] => {
+                            self.unsupported(line, format!("array member {}", name))
+                        }
+                        [Token::Word(name), Token::Punct('='), ..] => {
+                            self.unsupported(line, format!("initial value of {}", name));
+                            self.field(line, name, width);
+                        }
+                        _ => self.unsupported(line, format!("{} declaration", kind)),
+                    }
+                }
+            }
+            Some(Token::Word(w)) if w == "typedef" => match tokens {
+                [_, ty @ .., Token::Word(name)] if matches!(ty.first(), Some(Token::Word(t)) if DATA_TYPES.iter().any(|(d, _)| d == t)) =>
+                {
+                    self.typedefs.insert(name.clone(), ty.to_vec());
+                }
+                _ => self.unsupported(line, format!("typedef {}", words.last().unwrap_or(&""))),
+            },
+            Some(Token::Word(w)) => self.unsupported(
+                line,
+                format!("member {} of type {}", words.last().unwrap_or(&""), w),
+            ),
+            _ => {}
+        }
+    }
+
+    fn field(&mut self, line: usize, name: &str, width: u32) {
+        let data = FieldSelector::DATA.length as u32;
+        if width == 0 || self.used + width > data {
+            return self.unsupported(
+                line,
+                format!("{} does not fit in the {} data bits", name, data),
+            );
+        }
+        self.used += width;
+        let id = self.rename(line, name);
+        self.fields
+            .push(format!(".field {}, {}, {}", id, data - self.used, width));
+    }
+
+    fn source(&self, file: &str) -> String {
+        let mut s = format!("// Imported from {} by ewac --import-ulam.\n", file);
+        if !self.unsupported.is_empty() {
+            s.push_str("// Not translated:\n");
+            for u in &self.unsupported {
+                s.push_str(&format!("//   {}\n", u));
+            }
+        }
+        s.push('\n');
+        for line in self
+            .metadata
+            .iter()
+            .chain(&self.fields)
+            .chain(&self.parameters)
+        {
+            s.push_str(line);
+            s.push('\n');
+        }
+        for method in &self.methods {
+            s.push('\n');
+            for line in method.lines() {
+                s.push_str("// ");
+                s.push_str(line);
+                s.push('\n');
+            }
+        }
+        s
+    }
+}
+
+fn symmetries(value: &str) -> Option<String> {
+    match value {
+        "all" => return Some("ALL".to_owned()),
+        "normal" | "none" => return Some("R000L".to_owned()),
+        _ => {}
+    }
+    let names: Option<Vec<&str>> = value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            s.parse::<usize>()
+                .ok()
+                .and_then(|i| SYMMETRIES.get(i).copied())
+        })
+        .collect();
+    names.filter(|n| !n.is_empty()).map(|n| n.join("|"))
+}
+
+/// Finds the index of the `}` closing the `{` at `open`.
+fn closing(tokens: &[Spanned], open: usize) -> Result<usize, UlamError> {
+    let mut depth = 0;
+    for (i, t) in tokens.iter().enumerate().skip(open) {
+        match t.token {
+            Token::Punct('{') => depth += 1,
+            Token::Punct('}') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    Err(UlamError::UnbalancedBraces(tokens[open].line))
+}
+
+/// Translates the elements declared in the ulam source `src`, read from
+/// `file`. Constructs outside of any element which the import skipped are
+/// given alongside.
+pub fn translate(src: &str, file: &str) -> Result<(Vec<Translation>, Vec<Unsupported>), UlamError> {
+    let tokens = tokenize(src)?;
+    let mut elements = Vec::new();
+    let mut skipped = Vec::new();
+    let mut doc: Option<(usize, String)> = None;
+    let mut i = 0;
+    while i < tokens.len() {
+        let t = &tokens[i];
+        match &t.token {
+            Token::Doc(d) => {
+                doc = Some((t.line, d.clone()));
+                i += 1;
+                continue;
+            }
+            Token::Word(w) if w == "element" => {
+                let name = match tokens.get(i + 1).map(|t| &t.token) {
+                    Some(Token::Word(name)) => name.clone(),
+                    _ => return Err(UlamError::MissingName(t.line)),
+                };
+                let open = (i..tokens.len())
+                    .find(|&j| tokens[j].token == Token::Punct('{'))
+                    .ok_or(UlamError::UnbalancedBraces(t.line))?;
+                let close = closing(&tokens, open)?;
+                let mut e = Element::new(&name);
+                if let Some((line, d)) = doc.take() {
+                    e.doc(line, &d);
+                }
+                if open > i + 2 {
+                    let bases = &src[tokens[i + 2].start..tokens[open - 1].end];
+                    let bases = bases.trim_start_matches(':').trim();
+                    e.unsupported(t.line, format!("base classes {}", bases));
+                }
+                element_body(src, &tokens[open + 1..close], &mut e)?;
+                elements.push(Translation {
+                    name: e.name.clone(),
+                    source: e.source(file),
+                    unsupported: e.unsupported,
+                });
+                i = close + 1;
+            }
+            Token::Word(w) if w == "quark" || w == "transient" || w == "union" => {
+                let name = match tokens.get(i + 1).map(|t| &t.token) {
+                    Some(Token::Word(name)) => name.as_str(),
+                    _ => "",
+                };
+                skipped.push(Unsupported {
+                    line: t.line,
+                    what: format!("{} {}", w, name),
+                });
+                i = match (i..tokens.len()).find(|&j| tokens[j].token == Token::Punct('{')) {
+                    Some(open) => closing(&tokens, open)? + 1,
+                    None => tokens.len(),
+                };
+            }
+            Token::Word(w) if w == "use" || w == "load" || w == "local" => {
+                let end = (i..tokens.len())
+                    .find(|&j| tokens[j].token == Token::Punct(';'))
+                    .unwrap_or(tokens.len() - 1);
+                skipped.push(Unsupported {
+                    line: t.line,
+                    what: src[t.start..tokens[end].end].to_owned(),
+                });
+                i = end + 1;
+            }
+            _ => i += 1,
+        }
+        doc = None;
+    }
+    if elements.is_empty() {
+        return Err(UlamError::NoElement);
+    }
+    Ok((elements, skipped))
+}
+
+/// Translates the members of an element, the tokens between its braces.
+fn element_body(src: &str, tokens: &[Spanned], e: &mut Element) -> Result<(), UlamError> {
+    let mut start = 0;
+    let mut i = 0;
+    while i < tokens.len() {
+        match tokens[i].token {
+            Token::Doc(_) if i == start => {
+                start += 1;
+            }
+            Token::Punct(';') => {
+                let decl: Vec<Token> = tokens[start..i]
+                    .iter()
+                    .map(|t| t.token.clone())
+                    .filter(|t| !matches!(t, Token::Doc(_)))
+                    .collect();
+                e.declaration(tokens[start].line, &decl);
+                start = i + 1;
+            }
+            Token::Punct('{') => {
+                let close = closing(tokens, i)?;
+                let line = tokens[start].line;
+                let name = tokens[start..i]
+                    .iter()
+                    .position(|t| t.token == Token::Punct('('))
+                    .and_then(|p| tokens[start..start + p].last())
+                    .map_or("block", |t| &src[t.start..t.end]);
+                let what = if name == "behave" {
+                    "method behave, the element's behavior".to_owned()
+                } else {
+                    format!("method {}", name)
+                };
+                e.unsupported(line, what);
+                e.methods
+                    .push(src[tokens[start].start..tokens[close].end].to_owned());
+                start = close + 1;
+                i = close;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    Ok(())
+}
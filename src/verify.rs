@@ -0,0 +1,266 @@
+//! Checks of element code, run on its `Ir` before it is optimized and
+//! written, which turn what would fail as an event runs into compile errors.
+//!
+//! The checks follow every path through the code from the start of an event,
+//! and from the start of each routine a `call` reaches, with what
+//! `stack::analyze` finds of the operand stack before each instruction: the
+//! fewest and the most values it may hold and which of its topmost values are
+//! constants every path agrees on. Compiling fails when:
+//!
+//! - an instruction may find fewer values on the stack than it pops, such as
+//!   an `add` reached with one value pushed, or a `call` of a routine which
+//!   pops more values than its caller pushed;
+//! - a `ret` may run outside any routine;
+//! - a constant known to reach an instruction is wrong for it: a site number
+//!   past 40, a divisor of zero where dividing by zero stops the event, or a
+//!   value which doesn't fit the field `setfield` or `setsitefield` writes;
+//! - the event may end, by `exit` or by running off the end of the code, with
+//!   values left on the stack;
+//! - `bond`, `unbond` or `followbond` names a field not declared with `.bond`.
+//!
+//! The checks don't know which way a branch goes, so a path which can't run
+//! is checked too: a loop which pops more values than it pushes fails them,
+//! even if something pushed before it stops it in time, and so does one which
+//! pushes more than it pops and may then end the event. Jump targets are
+//! checked as code is lowered, and code using `jumprelativeoffset`, whose
+//! paths are only known as it runs, isn't checked.
+
+use crate::ast::{Instruction, Metadata, Node};
+use crate::base::arith::{ArithMode, DivZeroPolicy};
+use crate::base::field::FieldMap;
+use crate::base::SiteNumber;
+use crate::code::CompileError;
+use crate::ir::Ir;
+use crate::stack::{self, Stack};
+
+/// Checks the constants reaching instruction `i`, found at `offset`.
+fn check_operands<'input>(
+    i: &Instruction<'input>,
+    s: &Stack,
+    offset: usize,
+    field_map: &FieldMap,
+    modes: (ArithMode, ArithMode, DivZeroPolicy),
+) -> Result<(), CompileError<'input>> {
+    let (arith, field_writes, div_zero) = modes;
+    let sites: &[usize] = match i {
+        Instruction::SwapSites | Instruction::MoveSite | Instruction::Bond(_) => &[0, 1],
+        Instruction::SetSite => &[1],
+        Instruction::GetSite
+        | Instruction::GetSiteField(_)
+        | Instruction::SetSiteField(_)
+        | Instruction::SetPaint
+        | Instruction::GetPaint
+        | Instruction::GetLayer
+        | Instruction::SetLayer
+        | Instruction::GetHeat
+        | Instruction::EmitHeat
+        | Instruction::AbsorbHeat
+        | Instruction::Hash
+        | Instruction::Unbond(_)
+        | Instruction::FollowBond(_) => &[0],
+        _ => &[],
+    };
+    for n in sites.iter() {
+        match s.arg(*n) {
+            Some(c) if c.as_u128() >= SiteNumber::COUNT as u128 => {
+                return Err(CompileError::BadSiteNumber(offset, i.mnemonic(), c))
+            }
+            _ => {}
+        }
+    }
+    match i {
+        Instruction::SetField(x) | Instruction::SetSiteField(x) => {
+            let name = *x.ast();
+            if let (Some(value), Some(f)) = (s.arg(1), field_map.get(name)) {
+                let negative = field_writes == ArithMode::Checked && value.is_negative();
+                if negative || !f.fits(value) {
+                    return Err(CompileError::FieldRange(name, value, f, field_writes));
+                }
+            }
+        }
+        Instruction::OddsOf if matches!(s.arg(0), Some(d) if d.is_zero()) => {
+            return Err(CompileError::DivisionByZero(offset, i.mnemonic()));
+        }
+        Instruction::Div | Instruction::Mod => {
+            let b = match s.arg(0) {
+                Some(b) => b,
+                None => return Ok(()),
+            };
+            let a = s.arg(1).unwrap_or(b);
+            let rem = matches!(i, Instruction::Mod);
+            if a.divides_by_zero(b, rem) && div_zero.divide_by_zero(a, rem, arith).is_none() {
+                return Err(CompileError::DivisionByZero(offset, i.mnemonic()));
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Checks `ir`, an element's code, against its field declarations and its
+/// `.arithmetic`, `.fieldwrites` and `.divzero` modes, before it is
+/// optimized.
+pub fn verify<'input>(
+    ir: &Ir<'input>,
+    field_map: &FieldMap,
+    header: &[Node<'input>],
+) -> Result<(), CompileError<'input>> {
+    let bonds: Vec<&str> = header
+        .iter()
+        .filter_map(|n| match n {
+            Node::Metadata(Metadata::Bond(name, _)) => Some(*name),
+            _ => None,
+        })
+        .collect();
+    for (ip, i) in ir.code.iter().enumerate() {
+        if let Instruction::Bond(x) | Instruction::Unbond(x) | Instruction::FollowBond(x) = i {
+            // Unknown fields are reported as such when the code is written.
+            match x.get_ast() {
+                Some(name) if field_map.get(name).is_some() && !bonds.contains(name) => {
+                    return Err(CompileError::NotABond(ir.offsets[ip], i.mnemonic(), name))
+                }
+                _ => {}
+            }
+        }
+    }
+    if ir.has_computed_jumps() {
+        return Ok(());
+    }
+    let modes = header.iter().fold(
+        (
+            ArithMode::default(),
+            ArithMode::Wrapping,
+            DivZeroPolicy::default(),
+        ),
+        |(a, f, d), n| match n {
+            Node::Metadata(Metadata::Arithmetic(m)) => (*m, f, d),
+            Node::Metadata(Metadata::FieldWrites(m)) => (a, *m, d),
+            Node::Metadata(Metadata::DivZero(p)) => (a, f, *p),
+            _ => (a, f, d),
+        },
+    );
+
+    let analysis = match stack::analyze(ir) {
+        Some(analysis) => analysis,
+        None => return Ok(()),
+    };
+    let routines = &analysis.routines;
+
+    let main = &analysis.main;
+    for (ip, s) in main.states.iter().enumerate() {
+        let (i, s) = match (ir.code.get(ip), s) {
+            (Some(i), Some(s)) => (i, s),
+            _ => continue,
+        };
+        let offset = ir.offsets[ip];
+        let takes = match i {
+            Instruction::Call(x) => routines[&(*x.runtime() as usize)].summary.takes,
+            Instruction::Ret => return Err(CompileError::RetWithoutCall(offset)),
+            i => i.stack_effect().0 as i32,
+        };
+        if takes > s.depth {
+            return Err(CompileError::StackUnderflow(
+                offset,
+                i.mnemonic(),
+                takes as usize,
+                s.depth as usize,
+            ));
+        }
+    }
+    for f in std::iter::once(main).chain(routines.values()) {
+        for (ip, s) in f.states.iter().enumerate() {
+            if let (Some(i), Some(s)) = (ir.code.get(ip), s) {
+                check_operands(i, s, ir.offsets[ip], field_map, modes)?;
+            }
+        }
+    }
+    for (ip, s) in main.ends.iter() {
+        if s.most != Some(0) {
+            return Err(CompileError::Residue(
+                ir.offsets[*ip],
+                ir.code[*ip].mnemonic(),
+                s.most.map(|n| n as usize),
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::Instruction;
+    use crate::code::Compiler;
+    use crate::runtime::{self, Runtime};
+    use crate::stdlib;
+
+    /// The code compiled from `src`, or the compile error as `Debug` shows it.
+    fn compile(src: &str) -> Result<Vec<u8>, String> {
+        let mut code = Vec::new();
+        let mut compiler = Compiler::new("test");
+        match compiler.compile_to_writer(&mut code, src) {
+            Ok(()) => Ok(code),
+            Err(e) => Err(format!("{:?}", e)),
+        }
+    }
+
+    /// Compiles `body` as the code of an element.
+    fn check(body: &str) -> Result<Vec<u8>, String> {
+        compile(&format!(".name A\n.field f, 0, 2\n.bond b, 2\n{}", body))
+    }
+
+    /// Asserts that compiling `body` fails with an error starting `error`.
+    fn rejects(body: &str, error: &str) {
+        match check(body) {
+            Err(e) => assert!(e.starts_with(error), "{:?} gives {}", body, e),
+            Ok(_) => panic!("{:?} compiles", body),
+        }
+    }
+
+    /// `code` with the operand of its last `op` set to `operand`, and the
+    /// error loading it.
+    fn load_patched(mut code: Vec<u8>, op: Instruction, operand: &[u8]) -> runtime::Error {
+        let at = code.iter().rposition(|b| *b == op.as_u8()).unwrap() + 1;
+        code[at..at + operand.len()].copy_from_slice(operand);
+        Runtime::new().load_from_reader(&mut &code[..]).unwrap_err()
+    }
+
+    #[test]
+    fn reject_jump_out_of_range() {
+        rejects("  jump 5\n  exit\n", "BadTarget(\"5\"");
+    }
+
+    #[test]
+    fn reject_bad_register() {
+        rejects("  getregister R15\n  pop\n", "ParseError");
+        rejects("  push1\n  setregister R?\n", "ParseError");
+        let code = check("  getregister R3\n  pop\n").unwrap();
+        let e = load_patched(code, Instruction::GetRegister(0), &[16]);
+        assert!(matches!(e, runtime::Error::BadRegister(16)));
+    }
+
+    #[test]
+    fn reject_bad_field_or_constant() {
+        rejects("  push0\n  getsitefield g\n  pop\n", "UnknownField(\"g\"");
+        rejects("  getparameter p\n  pop\n", "UnknownParameter(\"p\"");
+        rejects("  push 9\n  push0\n  setsitefield f\n", "FieldRange(\"f\"");
+        rejects("  push0\n  unbond f\n", "NotABond(");
+        let code = check("  push 1000\n  pop\n").unwrap();
+        let e = load_patched(code, Instruction::Push(0u8.into()), &[0xff, 0xff]);
+        assert!(matches!(e, runtime::Error::BadConstIndex(0xffff)));
+    }
+
+    #[test]
+    fn reject_bad_operands() {
+        rejects("  push 41\n  getsite\n  pop\n", "BadSiteNumber(");
+        rejects(".divzero trap\n  push1\n  push0\n  div\n  pop\n", "DivisionByZero(");
+        rejects(".arithmetic checked\n  push1\n  push0\n  mod\n  pop\n", "DivisionByZero(");
+        assert!(check("  push1\n  push0\n  div\n  pop\n").is_ok());
+    }
+
+    #[test]
+    fn every_stdlib_element_passes() {
+        for (name, src) in stdlib::ELEMENTS.iter() {
+            assert!(compile(src).is_ok(), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,57 @@
+//! Running the engine in a browser.
+//!
+//! With the `wasm` feature the library built for `wasm32-unknown-unknown`
+//! exports the C ABI of `ffi`, which a page can call without any generated
+//! glue, plus `ew_alloc` and `ew_dealloc` for passing strings and buffers
+//! in. Entropy comes from the page through an `env.ew_random_fill` import:
+//!
+//! ```text
+//! let memory;
+//! const { instance } = await WebAssembly.instantiateStreaming(fetch("substrate_engine.wasm"), {
+//!   env: { ew_random_fill: (ptr, len) => crypto.getRandomValues(new Uint8Array(memory.buffer, ptr, len)) },
+//! });
+//! const ew = instance.exports;
+//! memory = ew.memory;
+//! const session = ew.ew_new(128, 128, 42n);
+//! const dreg = ew.ew_load_standard(session, namePtr, nameLen);
+//! ew.ew_set(session, 64, 64, dreg);
+//! ew.ew_step(session, 16384);
+//! const rgba = new Uint8ClampedArray(memory.buffer, ew.ew_render(session), 128 * 128 * 4);
+//! ```
+//!
+//! `examples/web` holds a complete page.
+
+#[cfg(target_arch = "wasm32")]
+extern "C" {
+    /// Fills `len` bytes at `ptr` with random bytes. Imported from the page,
+    /// which can use `crypto.getRandomValues`.
+    fn ew_random_fill(ptr: *mut u8, len: usize);
+}
+
+#[cfg(target_arch = "wasm32")]
+fn random_fill(dest: &mut [u8]) -> Result<(), getrandom::Error> {
+    unsafe { ew_random_fill(dest.as_mut_ptr(), dest.len()) };
+    Ok(())
+}
+
+#[cfg(target_arch = "wasm32")]
+getrandom::register_custom_getrandom!(random_fill);
+
+/// Allocates `len` bytes for passing strings and buffers in.
+#[no_mangle]
+pub extern "C" fn ew_alloc(len: usize) -> *mut u8 {
+    let mut v = Vec::<u8>::with_capacity(len);
+    let ptr = v.as_mut_ptr();
+    std::mem::forget(v);
+    ptr
+}
+
+/// Frees bytes allocated by `ew_alloc`.
+///
+/// # Safety
+///
+/// `ptr` and `len` must be from one call to `ew_alloc`.
+#[no_mangle]
+pub unsafe extern "C" fn ew_dealloc(ptr: *mut u8, len: usize) {
+    drop(Vec::from_raw_parts(ptr, 0, len));
+}
@@ -0,0 +1,9 @@
+// Drifts like Res, with its move from moves.s.
+.name Drift
+.symbol D
+.color #f0f
+.symmetries ALL
+.radius 1
+.include "moves.s"
+
+  drift
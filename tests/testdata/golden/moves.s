@@ -0,0 +1,5 @@
+// Moves to a random empty neighbour.
+.macro drift
+  push 100
+  diffuse
+.endmacro
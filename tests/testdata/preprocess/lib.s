@@ -0,0 +1,4 @@
+// Shared by main.s.
+.macro wait // spin once
+    nop
+.endmacro
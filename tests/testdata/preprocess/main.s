@@ -0,0 +1,3 @@
+.include "lib.s" // shared
+.include "lib.s" /* only once */
+    wait // spin